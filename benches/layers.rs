@@ -0,0 +1,100 @@
+//! Benchmarks of common operations on synthetic images. Run with `cargo bench`, optionally followed by a filter that
+//! the names of the benchmarks to run have to match.
+
+use std::cmp::Ordering;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{imageops, GrayImage, RgbaImage};
+
+use klex::{
+    layer::{
+        primitive::{Contours, Convert, InputFile, Threshold},
+        Layer, LayerOutput,
+    },
+    testing::TestPattern,
+};
+#[cfg(feature = "ui")]
+use klex::ui::ImageHandle;
+
+const SIZES: [(u32, u32); 2] = [(640, 480), (3000, 2000)];
+const SIGMAS: [f32; 3] = [1.0, 4.0, 16.0];
+
+fn compute(layer: &dyn Layer, input: &LayerOutput) -> LayerOutput {
+    let mut output = None;
+    layer.compute(&[input], &mut output).expect("Benchmarked layers don't fail");
+    output
+}
+
+/// Benchmarks `operation` on an input of each of the sizes, made by `input`
+fn at_sizes<I, O>(c: &mut Criterion, name: &str, input: impl Fn(u32, u32) -> I, operation: impl Fn(&I) -> O) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10); // The large images take a while
+    for (width, height) in SIZES {
+        let input = input(width, height);
+        let id = BenchmarkId::from_parameter(format!("{}x{}", width, height));
+        group.bench_with_input(id, &input, |b, input| b.iter(|| operation(input)));
+    }
+    group.finish();
+}
+
+fn open_png(c: &mut Criterion) {
+    let directory = std::env::temp_dir().join(format!("klex-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).expect("Can create a temporary directory");
+    let input_file = |width, height| {
+        let path = directory.join(format!("{}x{}.png", width, height));
+        TestPattern::Noise(1).rgba(width, height).save(&path).expect("Can save the generated image");
+        InputFile::<RgbaImage>::new(path)
+    };
+    at_sizes(c, "open png", input_file, |input_file| compute(input_file, &None));
+    std::fs::remove_dir_all(&directory).expect("Can remove the temporary directory");
+}
+
+fn convert_rgba_to_gray(c: &mut Criterion) {
+    let convert = Convert::<RgbaImage, GrayImage>::new();
+    let rgba = |width, height| -> LayerOutput { Some(Box::new(TestPattern::Noise(1).rgba(width, height))) };
+    at_sizes(c, "convert rgba to gray", rgba, |rgba| compute(&convert, rgba));
+}
+
+fn threshold(c: &mut Criterion) {
+    let threshold = Threshold::new(128, Ordering::Greater);
+    let gray = |width, height| -> LayerOutput { Some(Box::new(TestPattern::Gradient.gray(width, height))) };
+    at_sizes(c, "threshold", gray, |gray| compute(&threshold, gray));
+}
+
+fn gaussian_blur(c: &mut Criterion) {
+    let (width, height) = SIZES[0];
+    let gray = TestPattern::Noise(3).gray(width, height);
+    let mut group = c.benchmark_group(format!("gaussian blur {}x{}", width, height));
+    for sigma in SIGMAS {
+        group.bench_with_input(BenchmarkId::new("sigma", sigma), &sigma, |b, &sigma| {
+            b.iter(|| imageops::blur(&gray, sigma))
+        });
+    }
+    group.finish();
+}
+
+#[cfg(feature = "ui")]
+fn handle_rgba(c: &mut Criterion) {
+    at_sizes(c, "handle rgba", |width, height| TestPattern::Noise(1).rgba(width, height), |rgba| rgba.handle());
+}
+
+#[cfg(not(feature = "ui"))]
+fn handle_rgba(_c: &mut Criterion) {} // Display handles only exist with the user interface
+
+fn contours_of_noise(c: &mut Criterion) {
+    let threshold = Threshold::new(128, Ordering::Greater);
+    let mask = |width, height| compute(&threshold, &Some(Box::new(TestPattern::Noise(2).gray(width, height))));
+    let contours = Contours::new();
+    at_sizes(c, "contours of noise", mask, |mask| compute(&contours, mask));
+}
+
+criterion_group!(
+    benches,
+    open_png,
+    convert_rgba_to_gray,
+    threshold,
+    gaussian_blur,
+    handle_rgba,
+    contours_of_noise
+);
+criterion_main!(benches);
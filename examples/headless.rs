@@ -0,0 +1,43 @@
+//! Runs a threshold pipeline without any of the user interface, to show that the `core` feature is enough for that.
+//! Build and run it with `cargo run --example headless --no-default-features --features core`.
+
+use std::cmp::Ordering;
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    entity::BinaryImage,
+    error::{KlexError, Result},
+    layer::primitive::{Convert, InputFile, Threshold},
+    layer_graph::InteractiveLayerGraph,
+    parameter::ParamValue,
+    recipe::{self, ParamOverrides, Recipe},
+    testing::TestPattern,
+};
+
+fn main() -> Result<()> {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(128, Ordering::Greater)), vec![gray]);
+    let result = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers.rename(input, "input".to_string())?;
+    layers.rename(result, "result".to_string())?;
+    let recipe = Recipe::from_graph(&layers);
+
+    let path = std::env::temp_dir().join(format!("klex-headless-{}.png", std::process::id()));
+    TestPattern::Checkerboard(4).rgba(16, 16).save(&path)?;
+    let mut overrides = ParamOverrides::new();
+    overrides.set("input.path", ParamValue::Path(path.clone()));
+    let outputs = recipe::run(&recipe, &overrides);
+    std::fs::remove_file(&path).map_err(|source| KlexError::Io { path: path.clone(), source })?;
+
+    let outputs = outputs?;
+    let result = outputs["result"].value.downcast_ref::<GrayImage>().expect("A gray image");
+    let set = result.pixels().filter(|pixel| pixel.0[0] == 255).count();
+    if set != 128 {
+        return Err(format!("Half of the checkerboard is white, but {} pixels are set", set).into());
+    }
+    println!("{} of {} pixels are above the threshold", set, result.len());
+    Ok(())
+}
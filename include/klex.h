@@ -0,0 +1,93 @@
+#ifndef KLEX_H
+#define KLEX_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit it by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum KlexStatus {
+  KLEX_STATUS_OK = 0,
+  // See `klex_last_error`
+  KLEX_STATUS_ERROR = 1,
+  // E.g. a null pointer or a string that isn't UTF-8
+  KLEX_STATUS_INVALID_ARGUMENT = 2,
+  // A bug in Klex, the runner that was used shouldn't be used anymore
+  KLEX_STATUS_PANIC = 3,
+} KlexStatus;
+
+// A recipe that was built, together with the outputs of its last run. Opaque to C.
+typedef struct KlexRunner KlexRunner;
+
+// An image handed out by the library, with 4 bytes per pixel in the order red, green, blue, alpha. Rows follow each
+// other without padding. Free it with `klex_image_free`.
+typedef struct KlexImage {
+  uint32_t width;
+  uint32_t height;
+  uint8_t *data;
+  // Of `data` in bytes, `width * height * 4`
+  size_t length;
+} KlexImage;
+
+// Loads the recipe at `path` and builds it with the built-in layers. On success, `runner` is set to a runner that is
+// freed with `klex_runner_free`.
+//
+// # Safety
+//
+// `path` is a NUL terminated UTF-8 string and `runner` points to writable memory for a pointer
+KlexStatus klex_runner_new(const char *path, KlexRunner **runner);
+
+// # Safety
+//
+// `runner` is null or was created by `klex_runner_new` and isn't used afterwards
+void klex_runner_free(KlexRunner *runner);
+
+// Makes the node named `node` put out a copy of an image instead of computing its output, e.g. instead of reading
+// a file. `pixels` has 4 bytes per pixel in the order red, green, blue, alpha, and rows start `stride` bytes apart.
+//
+// # Safety
+//
+// `runner` was created by `klex_runner_new`, `node` is a NUL terminated UTF-8 string and `pixels` points to
+// `stride * height` readable bytes
+KlexStatus klex_runner_bind_rgba(KlexRunner *runner,
+                                 const char *node,
+                                 const uint8_t *pixels,
+                                 uint32_t width,
+                                 uint32_t height,
+                                 size_t stride);
+
+// Computes all nodes of the recipe. Only nodes whose inputs changed since the last run are computed again.
+//
+// # Safety
+//
+// `runner` was created by `klex_runner_new`
+KlexStatus klex_runner_run(KlexRunner *runner);
+
+// Copies the output of the node named `node`, usually one without children, from the last run. Images of other
+// kinds than RGBA are converted, e.g. gray values are repeated in the color channels. `image` is filled in and has to
+// be freed with `klex_image_free`.
+//
+// # Safety
+//
+// `runner` was created by `klex_runner_new`, `node` is a NUL terminated UTF-8 string and `image` points to writable
+// memory for a `KlexImage`
+KlexStatus klex_runner_output(const KlexRunner *runner, const char *node, KlexImage *image);
+
+// # Safety
+//
+// `image` is null or was filled in by `klex_runner_output` and isn't used afterwards
+void klex_image_free(KlexImage *image);
+
+// Copies the message of the last failure on the calling thread to `buffer`, which holds `size` bytes, as a NUL
+// terminated string. Longer messages are cut off. Returns the size the buffer needs for the whole message, or 0 if
+// the last call succeeded.
+//
+// # Safety
+//
+// `buffer` is null or points to `size` writable bytes
+size_t klex_last_error(char *buffer, size_t size);
+
+#endif /* KLEX_H */
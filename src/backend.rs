@@ -0,0 +1,1299 @@
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    thread,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use iced::image::Handle;
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    entity::{self, ExportJob, Geometry, Histogram, PixelValue},
+    error::{ensure, Context, KlexError, Result},
+    layer::{primitive::copy_to_clipboard, CancelToken, Layer, LayerCategory},
+    layer_graph::{GraphDelta, GraphSnapshot, InteractiveLayerGraph, LayerGraph},
+    logging::{Level, LogBuffer, LogRecord},
+    parameter::{ParamMap, ParamSpec},
+    recipe::{Bindings, Recipe},
+    registry::{LayerInfo, LayerRegistry},
+    ui::{self, Event, ImageHandle},
+    util::{Disconnected, ThreadChannel},
+};
+
+/// Messages from the backend to the user interface
+#[derive(Clone, Debug)]
+pub enum Data {
+    LayerAdded { node: NodeIndex, kind: String, name: String, inputs: Vec<NodeIndex> },
+    LayerRemoved(NodeIndex),
+    Connected { from: NodeIndex, output: usize, to: NodeIndex, port: usize }, // From an output port to an input port
+    Disconnected { to: NodeIndex, port: usize },
+    LayerMoved { node: NodeIndex, position: (f32, f32) },
+    LayerSelected(NodeIndex),
+    Parameters { node: NodeIndex, specs: Vec<ParamSpec>, values: ParamMap }, // After adding a layer or changing it
+    ComputeFinished { node: NodeIndex, duration: Duration },
+    ComputeFailed { node: NodeIndex, error: String },
+    // Output of a requested layer, along with its size at full resolution, which might be an estimate
+    Preview { node: NodeIndex, image: Handle, full_resolution: bool, full_size: (u32, u32) },
+    // Answer to `Event::QueryPixel`, which also repeats the position that was asked for
+    Pixel { node: NodeIndex, position: (f32, f32), x: u32, y: u32, value: PixelValue, exact: bool },
+    Histogram { node: NodeIndex, histogram: Histogram }, // Of the output of the selected layer, after its preview
+    // State of all layers, so that nothing can be missed in between. Sent first and when asked for with
+    // `Event::RequestGraph`, and otherwise followed by deltas. Each one is numbered one more than the one before.
+    Graph { sequence: u64, snapshot: GraphSnapshot },
+    GraphDelta { sequence: u64, delta: GraphDelta }, // Changes to the previously numbered state, whenever it changed
+    // Small picture of the output of a layer, sent whenever the output changed. Replaces the previous one.
+    Thumbnail { node: NodeIndex, image: Handle },
+    // Output of a requested layer that produces shapes instead of an image, along with the size of the image the shapes
+    // were found in, which is the input of the layer
+    Geometry { node: NodeIndex, geometry: Geometry, size: (u32, u32) },
+    ExportReady { node: NodeIndex, job: ExportJob }, // To be written by the user interface, off this thread
+    ExportFailed { node: NodeIndex, path: PathBuf, error: String },
+    Copied(NodeIndex), // The full resolution output of the layer was copied to the clipboard
+    CopyFailed { node: NodeIndex, error: String },
+    // Layer being computed, computations finished since the queue was last empty, and layers left to compute
+    QueueState { current: Option<NodeIndex>, done: usize, pending: usize },
+    MemoryUsage(usize),       // Bytes occupied by the stored outputs of the graph and its preview, whenever it changed
+    LogBatch(Vec<LogRecord>), // Log records since the previous batch
+    Error(String),            // An event couldn't be handled
+}
+
+/// How urgently the output of a layer is needed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Background, // Only needed eventually, e.g. full resolution results
+    Normal,
+    Preview, // The output of the selected layer, which is being looked at
+}
+
+/// Layers whose outputs were asked for, along with how urgently they are needed
+#[derive(Clone, Debug, Default)]
+pub struct JobQueue {
+    jobs: Vec<(NodeIndex, Priority)>, // In the order they were queued
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a layer. A layer that is queued already keeps the higher of both priorities.
+    pub fn push(&mut self, layer: NodeIndex, priority: Priority) {
+        match self.jobs.iter_mut().find(|(queued, _)| *queued == layer) {
+            Some((_, queued_priority)) => *queued_priority = priority.max(*queued_priority),
+            None => self.jobs.push((layer, priority)),
+        }
+    }
+
+    /// Queues the selected layer as a preview. Previews of layers that were selected before aren't as urgent anymore.
+    pub fn select(&mut self, layer: NodeIndex) {
+        for (_, priority) in &mut self.jobs {
+            if *priority == Priority::Preview {
+                *priority = Priority::Normal;
+            }
+        }
+        self.push(layer, Priority::Preview);
+    }
+
+    pub fn remove(&mut self, layer: NodeIndex) {
+        self.jobs.retain(|&(queued, _)| queued != layer);
+    }
+
+    pub fn highest_priority(&self) -> Option<Priority> {
+        self.jobs.iter().map(|&(_, priority)| priority).max()
+    }
+
+    /// Queued layers with the given priority, in the order they were queued
+    pub fn layers(&self, priority: Priority) -> Vec<NodeIndex> {
+        self.jobs
+            .iter()
+            .filter(|&&(_, queued_priority)| queued_priority == priority)
+            .map(|&(layer, _)| layer)
+            .collect()
+    }
+
+    pub fn all_layers(&self) -> Vec<NodeIndex> {
+        self.jobs.iter().map(|&(layer, _)| layer).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+/// The outcome of a computation, which is only passed on if the layer wasn't changed in the meantime
+enum ComputeResult {
+    Finished { node: NodeIndex, duration: Duration },
+    Failed { node: NodeIndex, error: String },
+    Preview { node: NodeIndex },
+}
+
+/// An output that is written to a file or copied to the clipboard once it is computed at full resolution
+struct Export {
+    node: NodeIndex,
+    destination: Destination,
+    queued: bool, // Whether the layer was queued to compute its output
+}
+
+enum Destination {
+    File { path: PathBuf, quality: u8 },
+    Clipboard, // Copied on this thread, which is quick compared to encoding a file
+}
+
+/// Which copy of the graph a layer is computed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Resolution {
+    Preview,
+    Full,
+}
+
+/// A copy of the graph that works on downscaled source images, so that the effect of a change can be seen long before
+/// the full resolution result is ready. Sources are never computed in here, they get a downscaled copy of their full
+/// resolution output instead.
+struct PreviewGraph {
+    graph: LayerGraph,
+    factor: f64,                      // Scale of the source images, which pixel parameters are scaled by as well
+    sources: HashMap<NodeIndex, u64>, // Generation of the full resolution source each downscaled output was made from
+}
+
+impl PreviewGraph {
+    fn new(graph: LayerGraph) -> Self {
+        Self {
+            graph,
+            factor: 1.0,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// A copy of a layer of the full resolution graph with its parameters scaled to the preview. Layers that can't be
+    /// duplicated are created anew from their parameters.
+    fn copy_layer(&self, full: &LayerGraph, registry: &LayerRegistry, node: NodeIndex) -> Result<Box<dyn Layer>> {
+        let layer = full.layer(node).context(format!("There is no layer {}", node.index()))?;
+        let mut copy = match layer.clone_boxed() {
+            Ok(copy) => copy,
+            Err(_) => registry.create(&layer.kind(), &layer.parameters())?,
+        };
+        if !full.inputs(node).is_empty() {
+            copy.scale_parameters(self.factor); // Sources keep their parameters, their output is downscaled instead
+        }
+        Ok(copy)
+    }
+
+    fn add_layer(&mut self, full: &LayerGraph, registry: &LayerRegistry, node: NodeIndex) -> Result<()> {
+        let inputs = full.inputs(node).into_iter().map(|(parent, _)| parent).collect();
+        let index = self.graph.add_layer(self.copy_layer(full, registry, node)?, inputs);
+        ensure!(index == node, "Preview graph got out of step with layer {}", node.index());
+        Ok(())
+    }
+
+    /// Brings a layer in line with the full resolution graph after its parameters or inputs changed
+    fn update_layer(&mut self, full: &LayerGraph, registry: &LayerRegistry, node: NodeIndex) -> Result<()> {
+        let copy = self.copy_layer(full, registry, node)?;
+        self.graph.replace_layer(node, copy)?;
+        Ok(())
+    }
+
+    fn set_factor(&mut self, full: &LayerGraph, registry: &LayerRegistry, factor: f64) -> Result<()> {
+        self.factor = factor;
+        self.sources.clear();
+        let layers: Vec<NodeIndex> = full.node_indices().filter(|&node| !full.inputs(node).is_empty()).collect();
+        for node in layers {
+            self.update_layer(full, registry, node)?;
+        }
+        Ok(())
+    }
+
+    /// Downscales the outputs of sources that changed since they were last downscaled
+    fn update_sources(&mut self, full: &LayerGraph, sources: &[NodeIndex]) -> Result<()> {
+        for &source in sources {
+            let generation = full.generation(source).context(format!("There is no layer {}", source.index()))?;
+            if self.sources.get(&source) == Some(&generation) {
+                continue;
+            }
+            let output = full
+                .output(source, 0)
+                .and_then(|output| entity::resize(output, self.factor))
+                .context(format!("Output of layer {} can't be downscaled", source.index()))?;
+            self.graph.set_output(source, Some(output))?;
+            self.sources.insert(source, generation);
+        }
+        Ok(())
+    }
+}
+
+/// Owns the layer graph and does all the heavy lifting, so that the user interface stays responsive. The user
+/// interface talks to it through a `ThreadChannel`.
+pub struct Backend {
+    channel: ThreadChannel<Data, Event>,
+    layers: InteractiveLayerGraph,
+    registry: LayerRegistry,
+    events: VecDeque<Event>, // Received, but not handled yet
+    queue: JobQueue,
+    queue_state: (Option<NodeIndex>, usize, usize), // As last sent to the user interface
+    computed: usize,                                // Since the queue was last empty
+    memory_usage: usize,                            // As last sent to the user interface
+    coalesce_interval: Duration,
+    hold_until: Option<Instant>, // Computing waits for further parameter changes until then
+    preview: Option<PreviewGraph>, // None if the graph can't be mirrored, then previews are computed at full resolution
+    preview_size: Option<u32>,
+    results: Vec<(ComputeResult, Resolution, Option<u64>)>, // Along with the generation of the layer at the time
+    panicked: HashMap<(NodeIndex, Resolution), Option<u64>>, // Along with the generation of the layer at the time
+    histograms: HashMap<NodeIndex, (Resolution, u64, Histogram)>, // Along with the generation they were computed at
+    handles: HashMap<NodeIndex, (Resolution, u64, Handle, (u32, u32))>, // Likewise, along with the size of the image
+    thumbnails: HashMap<NodeIndex, (Resolution, u64)>, // Output generation of the last thumbnail sent for each layer
+    graph_sent: GraphSnapshot, // As last sent to the user interface
+    graph_sequence: u64,       // Of the last snapshot or delta sent
+    graph_requested: bool,     // The user interface asked for a whole snapshot
+    exports: Vec<Export>,                 // Waiting for their layer to be computed
+    snapshot: Option<Arc<Mutex<Recipe>>>, // Kept up to date with the graph, for restarting after a crash
+    log: LogBuffer,
+}
+
+impl Backend {
+    /// How often to check for events while a layer is being computed
+    const POLL_INTERVAL: Duration = Duration::from_millis(1);
+    const DEFAULT_COALESCE_INTERVAL: Duration = Duration::from_millis(50);
+    const DEFAULT_PREVIEW_SIZE: u32 = 1024;
+    const LOG_CAPACITY: usize = 1000;
+    const THUMBNAIL_SIZE: u32 = 96;
+
+    pub fn new(channel: ThreadChannel<Data, Event>, registry: LayerRegistry) -> Self {
+        let mut backend = Self {
+            channel,
+            layers: InteractiveLayerGraph::new(),
+            registry,
+            events: VecDeque::new(),
+            queue: JobQueue::new(),
+            queue_state: (None, 0, 0),
+            computed: 0,
+            memory_usage: 0,
+            coalesce_interval: Self::DEFAULT_COALESCE_INTERVAL,
+            hold_until: None,
+            preview: None,
+            preview_size: Some(Self::DEFAULT_PREVIEW_SIZE),
+            results: Vec::new(),
+            panicked: HashMap::new(),
+            histograms: HashMap::new(),
+            handles: HashMap::new(),
+            thumbnails: HashMap::new(),
+            graph_sent: GraphSnapshot::default(),
+            graph_sequence: 0,
+            graph_requested: false,
+            exports: Vec::new(),
+            snapshot: None,
+            log: LogBuffer::new(Self::LOG_CAPACITY),
+        };
+        backend.replace_graph(InteractiveLayerGraph::new());
+        backend
+    }
+
+    /// A backend whose graph is built from a recipe
+    pub fn from_recipe(channel: ThreadChannel<Data, Event>, registry: LayerRegistry, recipe: &Recipe) -> Result<Self> {
+        let layers = recipe.build_graph(&registry, &Bindings::new())?;
+        let mut backend = Self::new(channel, registry);
+        backend.replace_graph(layers);
+        Ok(backend)
+    }
+
+    /// How long parameter changes are collected before computing starts. While a parameter is dragged around, this
+    /// limits how often the affected layers are recomputed.
+    pub fn set_coalesce_interval(&mut self, interval: Duration) {
+        self.coalesce_interval = interval;
+    }
+
+    /// Length of the long edge in pixels that source images are downscaled to for previews. Layers are then computed
+    /// at full resolution in the background. With `None`, layers are always computed at full resolution.
+    pub fn set_preview_size(&mut self, size: Option<u32>) {
+        self.preview_size = size;
+    }
+
+    /// Handles events until `Event::Exit` arrives. Fails if the user interface went away.
+    ///
+    /// Requested layers are computed one at a time, and events are handled in between. The selected layer is
+    /// computed first, then the other requested layers. Layers depending on large images are computed on downscaled
+    /// copies first, see `set_preview_size`. Events that invalidate the layer being computed, or that ask
+    /// for something more urgent, cancel the computation, which then starts over once the events are handled. Bursts
+    /// of parameter changes are coalesced, see `coalesce`.
+    pub fn run(mut self) -> Result<()> {
+        loop {
+            self.receive();
+            let events = coalesce(self.events.drain(..).collect());
+            let changed = !events.is_empty();
+            for event in events {
+                if let Event::Exit = event {
+                    return Ok(());
+                }
+                if let Err(e) = self.handle(event) {
+                    self.log.log(Level::Warn, format!("Failed to handle event: {:#}", e));
+                    self.send(Data::Error(format!("{:#}", e)))?;
+                }
+            }
+            if changed {
+                self.update_snapshot();
+            }
+            self.deliver_results()?;
+            self.send_graph_snapshot()?;
+            self.export()?;
+            let records = self.log.take_unsent();
+            if !records.is_empty() {
+                self.send(Data::LogBatch(records))?;
+            }
+
+            if self.hold_until.is_some_and(|hold_until| Instant::now() >= hold_until) {
+                self.hold_until = None;
+            }
+            self.send_memory_usage()?;
+            if self.hold_until.is_none() && !self.queue.is_empty() {
+                self.compute_step()?;
+                self.export()?; // Before events can outdate what was computed
+            } else {
+                if self.queue.is_empty() {
+                    self.computed = 0;
+                    self.send_queue_state(None)?;
+                }
+                self.wait_for_event()?;
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::AddLayer {
+                kind,
+                parameters,
+                inputs,
+            } => {
+                let layer = self.registry.create(&kind, &parameters)?;
+                self.add_layer(layer, kind, inputs)?;
+            }
+            Event::InsertLayer {
+                kind,
+                parameters,
+                connect_after,
+            } => {
+                let mut layer = self.registry.create_default(&kind)?;
+                for (name, value) in parameters {
+                    layer.set_parameter(&name, value)?;
+                }
+                let inputs = match layer.category() {
+                    LayerCategory::Input => Vec::new(),
+                    _ => connect_after.into_iter().collect(),
+                };
+                let node = self.add_layer(layer, kind, inputs)?;
+                self.handle(Event::SelectLayer(node))?;
+            }
+            Event::RemoveLayer(node) => {
+                let children = self.layers.graph().children(node);
+                let was_selected = self.layers.selected() == Some(node);
+                self.layers.remove_layer(node)?;
+                self.queue.remove(node);
+                self.histograms.remove(&node); // The index might be reused
+                self.handles.remove(&node);
+                self.thumbnails.remove(&node);
+                self.mirror(|preview, full, registry| {
+                    preview.graph.remove_layer(node)?;
+                    preview.sources.remove(&node);
+                    for child in children {
+                        preview.update_layer(full, registry, child)?; // Might be a source now
+                    }
+                    Ok(())
+                });
+                self.send(Data::LayerRemoved(node))?;
+                if let Some(selected) = self.layers.selected().filter(|_| was_selected) {
+                    self.queue.select(selected);
+                    self.send(Data::LayerSelected(selected))?;
+                }
+            }
+            Event::DuplicateLayer(node) => {
+                let copy = self.layers.duplicate_layer(node)?;
+                self.mirror(|preview, full, registry| preview.add_layer(full, registry, copy));
+                let graph = self.layers.graph();
+                let kind = graph.layer(copy).map(|layer| layer.kind()).unwrap_or_default();
+                let name = graph.name(copy).unwrap_or_default().to_string();
+                let position = self.layers.layout(copy).and_then(|layout| layout.position); // Next to the original
+                self.send(Data::LayerAdded { node: copy, kind, name, inputs: Vec::new() })?;
+                self.send_parameters(copy)?;
+                if let Some(position) = position {
+                    self.send(Data::LayerMoved { node: copy, position })?;
+                }
+                self.handle(Event::SelectLayer(copy))?;
+            }
+            Event::Connect { from, output, to, port } => {
+                self.layers.connect_output(from, output, to, port)?;
+                self.mirror(|preview, full, registry| {
+                    preview.graph.connect_output(from, output, to, port)?;
+                    preview.update_layer(full, registry, to) // Might have been a source before
+                });
+                self.send(Data::Connected { from, output, to, port })?;
+            }
+            Event::Disconnect { to, port } => {
+                self.layers.disconnect(to, port)?;
+                self.mirror(|preview, full, registry| {
+                    preview.graph.disconnect(to, port)?;
+                    preview.update_layer(full, registry, to)
+                });
+                self.send(Data::Disconnected { to, port })?;
+            }
+            Event::MoveLayer { node, position } => {
+                self.layers.move_layer(node, position)?;
+                self.send(Data::LayerMoved { node, position })?;
+            }
+            Event::SetLayout { node, layout } => {
+                self.layers.set_layout(node, layout)?; // Reaches the user interface with the next graph delta
+            }
+            Event::SetParameter { node, name, value } => {
+                self.layers.set_parameter(node, &name, value)?;
+                self.mirror(|preview, full, registry| preview.update_layer(full, registry, node));
+                self.send_parameters(node)?;
+                self.hold_until.get_or_insert_with(|| Instant::now() + self.coalesce_interval);
+            }
+            Event::SelectLayer(node) => {
+                self.layers.select(node)?;
+                self.queue.select(node);
+                self.send(Data::LayerSelected(node))?;
+            }
+            Event::RequestCompute(node) => self.queue.push(node, Priority::Normal),
+            Event::RequestGraph => self.graph_requested = true,
+            Event::Paint { node, stroke } => {
+                // Sources aren't computed in the preview graph, so it picks up the mask from the full resolution output
+                self.layers.paint(node, &stroke)?;
+                self.hold_until.get_or_insert_with(|| Instant::now() + self.coalesce_interval);
+            }
+            Event::EndStroke => self.layers.end_stroke(),
+            Event::Undo => self.step_history(InteractiveLayerGraph::undo)?,
+            Event::Redo => self.step_history(InteractiveLayerGraph::redo)?,
+            Event::QueryPixel { node, x, y } => {
+                if let Some(pixel) = self.pixel(node, x, y) {
+                    self.send(pixel)?;
+                }
+            }
+            Event::LoadRecipe { recipe, bindings } => {
+                let layers = recipe.build_graph(&self.registry, &bindings)?;
+                for node in self.layers.graph().node_indices().collect::<Vec<_>>() {
+                    self.send(Data::LayerRemoved(node))?;
+                }
+                for export in std::mem::take(&mut self.exports) {
+                    let error = "The graph was replaced before the layer was computed".to_string();
+                    self.send(match export.destination {
+                        Destination::File { path, .. } => Data::ExportFailed { node: export.node, path, error },
+                        Destination::Clipboard => Data::CopyFailed { node: export.node, error },
+                    })?;
+                }
+                self.replace_graph(layers);
+                self.send_graph()?;
+            }
+            Event::Export { node, path, quality } => {
+                ensure!(self.layers.graph().contains(node), KlexError::NoSuchLayer(node));
+                self.exports.push(Export {
+                    node,
+                    destination: Destination::File { path, quality },
+                    queued: false,
+                });
+            }
+            Event::CopyToClipboard(node) => {
+                ensure!(self.layers.graph().contains(node), KlexError::NoSuchLayer(node));
+                self.exports.push(Export {
+                    node,
+                    destination: Destination::Clipboard,
+                    queued: false,
+                });
+            }
+            Event::Exit => (),
+        }
+        Ok(())
+    }
+
+    /// Hands outputs that are up to date at full resolution to the user interface to export them, or copies them to
+    /// the clipboard. Layers that aren't are queued, and their exports fail if they can't be computed.
+    fn export(&mut self) -> Result<()> {
+        for mut export in std::mem::take(&mut self.exports) {
+            let (node, graph) = (export.node, self.layers.graph());
+            let queued = self.queue.all_layers().contains(&node);
+            let result = match graph.output(node, 0).filter(|_| !graph.is_dirty(node)) {
+                Some(output) => match &export.destination {
+                    Destination::File { path, quality } => ExportJob::new(output, path, *quality).map(Some),
+                    Destination::Clipboard => copy_to_clipboard(output).map(|()| None),
+                },
+                None if !graph.contains(node) => Err(KlexError::NoSuchLayer(node)),
+                None if export.queued && !queued && graph.is_dirty(node) => {
+                    Err(KlexError::msg(format!("Layer {} couldn't be computed", node.index())))
+                }
+                None => {
+                    // Also if the output was evicted since
+                    self.queue.push(node, Priority::Background);
+                    export.queued = true;
+                    self.exports.push(export);
+                    continue;
+                }
+            };
+            match (result, export.destination) {
+                (Ok(Some(job)), Destination::File { path, .. }) => {
+                    self.log.log(Level::Info, format!("Exporting layer {} to {:?}", node.index(), path));
+                    self.send(Data::ExportReady { node, job })?;
+                }
+                (Ok(_), _) => {
+                    self.log.log(Level::Info, format!("Copied layer {} to the clipboard", node.index()));
+                    self.send(Data::Copied(node))?;
+                }
+                (Err(e), destination) => {
+                    let error = format!("{:#}", e);
+                    self.log.log(Level::Error, format!("Failed to export layer {}: {}", node.index(), error));
+                    self.send(match destination {
+                        Destination::File { path, .. } => Data::ExportFailed { node, path, error },
+                        Destination::Clipboard => Data::CopyFailed { node, error },
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes or redoes an edit. Since that can change any part of the graph, the user interface is told about the
+    /// whole graph again, as if it was replaced.
+    fn step_history(&mut self, step: impl FnOnce(&mut InteractiveLayerGraph) -> Result<()>) -> Result<()> {
+        let before: Vec<_> = self.layers.graph().node_indices().collect();
+        step(&mut self.layers)?;
+        for node in before {
+            self.send(Data::LayerRemoved(node))?;
+        }
+        let layers = std::mem::take(&mut self.layers);
+        self.replace_graph(layers);
+        self.send_graph()
+    }
+
+    /// Swaps in another graph, along with a preview graph mirroring it. Whatever was queued or remembered about the
+    /// previous graph is dropped. Layers after an output that was computed again to the same content are left as they
+    /// are, so that e.g. thumbnails are only sent for outputs that changed.
+    fn replace_graph(&mut self, mut layers: InteractiveLayerGraph) {
+        layers.set_skip_unchanged(true);
+        self.preview = match layers.graph().try_clone() {
+            Ok(graph) => Some(PreviewGraph::new(graph)),
+            Err(e) => {
+                self.log.log(Level::Warn, format!("Previews are computed at full resolution: {:#}", e));
+                None
+            }
+        };
+        self.layers = layers;
+        self.queue = JobQueue::new();
+        self.results.clear();
+        self.panicked.clear();
+        self.histograms.clear();
+        self.handles.clear();
+        self.thumbnails.clear();
+    }
+
+    /// Tells the user interface about every layer of the graph and how they are connected, e.g. after the graph was
+    /// replaced
+    fn send_graph(&mut self) -> Result<()> {
+        let graph = self.layers.graph();
+        for node in graph.node_indices() {
+            let kind = graph.layer(node).map(|layer| layer.kind()).unwrap_or_default();
+            let name = graph.name(node).unwrap_or_default().to_string();
+            self.send(Data::LayerAdded { node, kind, name, inputs: Vec::new() })?;
+            self.send_parameters(node)?;
+            if let Some(position) = self.layers.layout(node).and_then(|layout| layout.position) {
+                self.send(Data::LayerMoved { node, position })?;
+            }
+        }
+        for (from, output, to, port) in graph.edges() {
+            self.send(Data::Connected { from, output, to, port })?;
+        }
+        if let Some(selected) = self.layers.selected() {
+            self.queue.select(selected);
+            self.send(Data::LayerSelected(selected))?;
+        }
+        Ok(())
+    }
+
+    /// Applies an edit of the graph to the preview graph as well. If that fails, previews are computed at full
+    /// resolution from then on.
+    fn mirror(&mut self, edit: impl FnOnce(&mut PreviewGraph, &LayerGraph, &LayerRegistry) -> Result<()>) {
+        if let Some(preview) = &mut self.preview {
+            if let Err(e) = edit(preview, self.layers.graph(), &self.registry) {
+                self.log.log(Level::Warn, format!("Previews are computed at full resolution: {:#}", e));
+                self.preview = None;
+            }
+        }
+    }
+
+    /// Computes the next layer needed by the most urgent jobs. Only fails if the user interface went away.
+    fn compute_step(&mut self) -> Result<()> {
+        let priority = match self.queue.highest_priority() {
+            Some(priority) => priority,
+            None => return Ok(()),
+        };
+
+        // Jobs of the same priority are worked on in the order they were queued. Jobs for layers that are up to date
+        // or gone are done.
+        let mut next = None;
+        for layer in self.queue.layers(priority) {
+            match self.next_computation(layer, priority) {
+                Ok((Some(node), resolution)) => {
+                    next = Some((layer, node, resolution));
+                    break;
+                }
+                Ok((None, resolution)) => {
+                    self.queue.remove(layer);
+                    let generation = self.graph(resolution).and_then(|graph| graph.generation(layer));
+                    self.results.push((ComputeResult::Preview { node: layer }, resolution, generation));
+                    if resolution == Resolution::Preview {
+                        self.queue.push(layer, Priority::Background);
+                    }
+                }
+                Err(e) => {
+                    self.queue.remove(layer);
+                    let error = format!("{:#}", e);
+                    self.send(Data::ComputeFailed { node: layer, error })?;
+                }
+            }
+        }
+        let (layer, node, resolution) = match next {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        self.send_queue_state(Some(node))?;
+        let name = self.layers.graph().name(node).unwrap_or_default();
+        let message = format!(
+            "Computing layer {} ({}) for layer {} at {:?} priority and {:?} resolution",
+            node.index(),
+            name,
+            layer.index(),
+            priority,
+            resolution
+        );
+        self.log.log(Level::Debug, message);
+
+        let stored_before: Vec<NodeIndex> = self.stored_outputs().collect();
+        let generation = self.graph(resolution).and_then(|graph| graph.generation(node));
+        let start = Instant::now();
+        let result = if self.panicked.get(&(node, resolution)) == Some(&generation) {
+            // Would only panic again
+            Err(KlexError::msg(format!("Layer {} panicked before and wasn't changed since", node.index())))
+        } else {
+            self.compute_interruptible(node, &self.dependencies(layer), priority, resolution)
+        };
+        match result {
+            Ok(()) => {
+                let duration = start.elapsed();
+                self.log.log(Level::Debug, format!("Computed layer {} in {:.1?}", node.index(), duration));
+                self.computed += 1;
+                self.results.push((ComputeResult::Finished { node, duration }, resolution, generation));
+            }
+            Err(e) if matches!(e.root(), KlexError::Cancelled) => {
+                // Starts over once the events that cancelled it are handled
+                self.log.log(Level::Info, format!("Cancelled computing layer {}", node.index()));
+            }
+            Err(e) => {
+                self.log.log(Level::Error, format!("Failed to compute layer {}: {:#}", node.index(), e));
+                if matches!(e.root(), KlexError::Panicked(_)) {
+                    self.panicked.insert((node, resolution), generation);
+                }
+                // Jobs that needed the layer can't be completed
+                for layer in self.queue.all_layers() {
+                    if self.dependencies(layer).contains(&node) {
+                        self.queue.remove(layer);
+                    }
+                }
+                let error = format!("{:#}", e);
+                self.results.push((ComputeResult::Failed { node, error }, resolution, generation));
+            }
+        }
+
+        for evicted in stored_before.into_iter().filter(|&layer| self.layers.graph().output(layer, 0).is_none()) {
+            self.log.log(Level::Debug, format!("Evicted output of layer {}", evicted.index()));
+        }
+        Ok(())
+    }
+
+    /// The next layer to compute for a job, or None if the job is done, along with the resolution the job is worked on
+    /// at. Sources are always computed at full resolution. Jobs that are more urgent than `Priority::Background` and
+    /// depend on sources larger than the preview size are worked on in the preview graph.
+    fn next_computation(&mut self, layer: NodeIndex, priority: Priority) -> Result<(Option<NodeIndex>, Resolution)> {
+        let order = self.layers.graph().compute_order(&[layer])?;
+        let sources: Vec<NodeIndex> = order.iter().copied().filter(|&node| self.is_source(node)).collect();
+        if let Some(&source) = sources.first() {
+            return Ok((Some(source), Resolution::Full));
+        }
+
+        let dependencies = self.dependencies(layer);
+        let sources: Vec<NodeIndex> = dependencies.into_iter().filter(|&node| self.is_source(node)).collect();
+        if let (Some(factor), true) = (self.preview_factor(&sources), priority > Priority::Background) {
+            self.mirror(|preview, full, registry| {
+                if preview.factor != factor {
+                    preview.set_factor(full, registry, factor)?;
+                }
+                preview.update_sources(full, &sources)
+            });
+            if let Some(preview) = &self.preview {
+                let node = preview.graph.compute_order(&[layer])?.first().copied();
+                return Ok((node, Resolution::Preview));
+            }
+        }
+        Ok((order.first().copied(), Resolution::Full))
+    }
+
+    /// The pixel at a position of the output of a layer, given as a fraction of its size. Once the full resolution
+    /// output is up to date, the pixel is read from it. Until then, it comes from the preview, but its coordinates
+    /// are those it will have at full resolution.
+    fn pixel(&self, node: NodeIndex, x: f32, y: f32) -> Option<Data> {
+        if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+            return None;
+        }
+        [Resolution::Full, Resolution::Preview].into_iter().find_map(|resolution| {
+            let graph = self.graph(resolution)?;
+            let output = graph.output(node, 0).filter(|_| !graph.is_dirty(node))?;
+            let (width, height) = entity::dimensions(output)?;
+            let value = entity::pixel(output, (x * width as f32) as u32, (y * height as f32) as u32)?;
+            let factor = match (resolution, &self.preview) {
+                (Resolution::Preview, Some(preview)) => preview.factor,
+                _ => 1.0,
+            };
+            let full_size = |size: u32| (f64::from(size) / factor).round() as f32;
+            Some(Data::Pixel {
+                node,
+                position: (x, y),
+                x: (x * full_size(width)) as u32,
+                y: (y * full_size(height)) as u32,
+                value,
+                exact: resolution == Resolution::Full,
+            })
+        })
+    }
+
+    fn is_source(&self, layer: NodeIndex) -> bool {
+        self.layers.graph().inputs(layer).is_empty()
+    }
+
+    /// The scale that makes the largest of `sources` fit the preview size, or None if they fit already or can't be
+    /// downscaled
+    fn preview_factor(&self, sources: &[NodeIndex]) -> Option<f64> {
+        let size = self.preview_size?;
+        self.preview.as_ref()?;
+        let mut factor = 1.0_f64;
+        for &source in sources {
+            let (width, height) = entity::dimensions(self.layers.graph().output(source, 0)?)?;
+            factor = factor.min(f64::from(size) / f64::from(width.max(height)));
+        }
+        (factor < 1.0).then_some(factor)
+    }
+
+    fn graph(&self, resolution: Resolution) -> Option<&LayerGraph> {
+        match resolution {
+            Resolution::Preview => self.preview.as_ref().map(|preview| &preview.graph),
+            Resolution::Full => Some(self.layers.graph()),
+        }
+    }
+
+    /// Computes a single layer on a worker thread while receiving events. The computation is cancelled if one of the
+    /// events changes any of `dependencies` or asks for something more urgent than `priority`. A panicking layer
+    /// results in `KlexError::Panicked`.
+    fn compute_interruptible(
+        &mut self,
+        node: NodeIndex,
+        dependencies: &HashSet<NodeIndex>,
+        priority: Priority,
+        resolution: Resolution,
+    ) -> Result<()> {
+        let name = self.layers.graph().name(node).unwrap_or_default().to_string();
+        #[cfg(feature = "tracing")]
+        let span = {
+            let kind = self.layers.graph().layer(node).map(|layer| layer.kind()).unwrap_or_default();
+            tracing::info_span!("compute", layer = node.index(), name = %name, kind = %kind)
+        };
+        let cancel = CancelToken::new();
+        let (channel, layers, preview, events) = (&self.channel, &mut self.layers, &mut self.preview, &mut self.events);
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                #[cfg(feature = "tracing")]
+                let _entered = span.enter();
+                match resolution {
+                    Resolution::Preview => preview
+                        .as_mut()
+                        .context("There is no preview graph")?
+                        .graph
+                        .compute_layer_cancellable(node, &cancel),
+                    Resolution::Full => layers.compute_layer_cancellable(node, &cancel),
+                }
+            });
+            while !worker.is_finished() {
+                let received: Vec<Event> = match channel.receive_timeout(Self::POLL_INTERVAL) {
+                    Ok(first) => first.into_iter().chain(channel.receive()).collect(),
+                    Err(Disconnected) => {
+                        cancel.cancel(); // Nobody is waiting for the result anymore
+                        thread::sleep(Self::POLL_INTERVAL);
+                        Vec::new()
+                    }
+                };
+                #[cfg(feature = "tracing")]
+                for event in &received {
+                    tracing::trace!(?event, "Received event");
+                }
+                if received
+                    .iter()
+                    .any(|event| invalidates(event, dependencies) || preempts(event, priority))
+                {
+                    cancel.cancel();
+                }
+                events.extend(received);
+            }
+            worker.join().unwrap_or_else(|payload| {
+                let message = format!("Layer {} ({}) panicked: {}", node.index(), name, panic_message(&*payload));
+                Err(KlexError::Panicked(message))
+            })
+        })
+    }
+
+    /// `layer` along with all layers its output depends on
+    fn dependencies(&self, layer: NodeIndex) -> HashSet<NodeIndex> {
+        let mut dependencies = HashSet::new();
+        let mut unvisited = vec![layer];
+        while let Some(layer) = unvisited.pop() {
+            if dependencies.insert(layer) {
+                unvisited.extend(self.layers.graph().inputs(layer).into_iter().map(|(parent, _)| parent));
+            }
+        }
+        dependencies
+    }
+
+    fn receive(&mut self) {
+        let received = self.channel.receive();
+        #[cfg(feature = "tracing")]
+        for event in &received {
+            tracing::trace!(?event, "Received event");
+        }
+        self.events.extend(received);
+    }
+
+    /// Blocks until an event arrives. While parameter changes are held back, it only waits until the hold ends.
+    fn wait_for_event(&mut self) -> Result<()> {
+        let event = match self.hold_until {
+            Some(hold_until) => self
+                .channel
+                .receive_timeout(hold_until.saturating_duration_since(Instant::now()))?,
+            None => Some(self.channel.receive_blocking()?),
+        };
+        #[cfg(feature = "tracing")]
+        if let Some(event) = &event {
+            tracing::trace!(?event, "Received event");
+        }
+        self.events.extend(event);
+        Ok(())
+    }
+
+    fn send(&self, data: Data) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(?data, "Sending data");
+        Ok(self.channel.send(data)?)
+    }
+
+    /// Layers that currently hold on to their output
+    fn stored_outputs(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        let graph = self.layers.graph();
+        graph.node_indices().filter(move |&layer| graph.output(layer, 0).is_some())
+    }
+
+    fn update_snapshot(&self) {
+        if let Some(snapshot) = &self.snapshot {
+            *snapshot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Recipe::from_graph(&self.layers);
+        }
+    }
+
+    fn add_layer(&mut self, layer: Box<dyn Layer>, kind: String, inputs: Vec<NodeIndex>) -> Result<NodeIndex> {
+        let node = self.layers.add_layer(layer, inputs.clone());
+        self.mirror(|preview, full, registry| preview.add_layer(full, registry, node));
+        let name = self.layers.graph().name(node).unwrap_or_default().to_string();
+        self.send(Data::LayerAdded { node, kind, name, inputs })?;
+        self.send_parameters(node)?;
+        Ok(node)
+    }
+
+    /// Tells the user interface which parameters a layer has and what they are set to. Parameters of layers that aren't
+    /// in the registry come without specs.
+    fn send_parameters(&self, node: NodeIndex) -> Result<()> {
+        let layer = self.layers.graph().layer(node).context(format!("There is no layer {}", node.index()))?;
+        let specs = self
+            .registry
+            .info(&layer.kind())
+            .map(|info| info.parameters.clone())
+            .unwrap_or_default();
+        let values = layer.parameters();
+        self.send(Data::Parameters { node, specs, values })
+    }
+
+    /// Tells the user interface what is being computed, unless it knows already
+    fn send_queue_state(&mut self, current: Option<NodeIndex>) -> Result<()> {
+        let pending = self
+            .layers
+            .graph()
+            .compute_order(&self.queue.all_layers())
+            .map_or(0, |order| order.len());
+        let done = self.computed;
+        if (current, done, pending) != self.queue_state {
+            self.queue_state = (current, done, pending);
+            self.send(Data::QueueState { current, done, pending })?;
+        }
+        Ok(())
+    }
+
+    /// Tells the user interface how the state of the graph changed, unless it didn't. The first time, or if asked to,
+    /// the whole state is sent instead.
+    fn send_graph_snapshot(&mut self) -> Result<()> {
+        let snapshot = self.layers.snapshot();
+        if snapshot == self.graph_sent && !self.graph_requested {
+            return Ok(());
+        }
+        self.graph_sequence += 1;
+        let sequence = self.graph_sequence;
+        let data = if self.graph_requested || sequence == 1 {
+            Data::Graph { sequence, snapshot: snapshot.clone() }
+        } else {
+            Data::GraphDelta { sequence, delta: GraphSnapshot::diff(&self.graph_sent, &snapshot) }
+        };
+        self.graph_requested = false;
+        self.graph_sent = snapshot;
+        self.send(data)
+    }
+
+    /// Tells the user interface how much memory the stored outputs occupy, unless it knows already
+    fn send_memory_usage(&mut self) -> Result<()> {
+        let preview = self.preview.as_ref().map_or(0, |preview| preview.graph.memory_usage());
+        let usage = self.layers.graph().memory_usage() + preview;
+        if usage != self.memory_usage {
+            self.memory_usage = usage;
+            self.send(Data::MemoryUsage(usage))?;
+        }
+        Ok(())
+    }
+
+    /// Sends the results of layers that weren't changed since they were computed and drops the others. Previews of the
+    /// selected layer are followed by its histogram, and computed layers by their thumbnail.
+    fn deliver_results(&mut self) -> Result<()> {
+        let mut histograms = Vec::new();
+        let mut thumbnails = Vec::new();
+        for (result, resolution, generation) in std::mem::take(&mut self.results) {
+            let graph = match self.graph(resolution) {
+                Some(graph) => graph,
+                None => continue,
+            };
+            let data = match result {
+                ComputeResult::Finished { node, .. }
+                | ComputeResult::Failed { node, .. }
+                | ComputeResult::Preview { node }
+                    if graph.generation(node) != generation =>
+                {
+                    self.log.log(Level::Debug, format!("Dropped outdated result of layer {}", node.index()));
+                    continue;
+                }
+                ComputeResult::Finished { node, duration } => {
+                    thumbnails.push((node, resolution));
+                    Data::ComputeFinished { node, duration }
+                }
+                ComputeResult::Failed { node, error } => Data::ComputeFailed { node, error },
+                ComputeResult::Preview { node } => match self.display_handle(node, resolution) {
+                    Some((image, size)) => {
+                        if self.layers.selected() == Some(node) {
+                            histograms.push((node, resolution));
+                        }
+                        Data::Preview {
+                            node,
+                            image,
+                            full_resolution: resolution == Resolution::Full,
+                            full_size: self.full_size(node, size, resolution),
+                        }
+                    }
+                    None => {
+                        let graph = match self.graph(resolution) {
+                            Some(graph) => graph,
+                            None => continue,
+                        };
+                        let input = graph.source(node, 0);
+                        let size = input.and_then(|(input, output)| entity::dimensions(graph.output(input, output)?));
+                        match graph.output(node, 0).and_then(|output| entity::geometry(output)).zip(size) {
+                            Some((geometry, size)) => Data::Geometry { node, geometry, size },
+                            None => continue,
+                        }
+                    }
+                },
+            };
+            self.send(data)?;
+        }
+        for (node, resolution) in histograms {
+            self.send_histogram(node, resolution)?;
+        }
+        for (node, resolution) in thumbnails {
+            self.send_thumbnail(node, resolution)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a thumbnail of the output of a layer, unless one was sent for the same output already
+    fn send_thumbnail(&mut self, node: NodeIndex, resolution: Resolution) -> Result<()> {
+        let graph = match self.graph(resolution) {
+            Some(graph) => graph,
+            None => return Ok(()),
+        };
+        let generation = match graph.output_generation(node) {
+            Some(generation) if self.thumbnails.get(&node) != Some(&(resolution, generation)) => generation,
+            _ => return Ok(()),
+        };
+        let thumbnail = graph.output(node, 0).and_then(|output| entity::thumbnail(output, Self::THUMBNAIL_SIZE));
+        let image = match thumbnail {
+            Some(thumbnail) => thumbnail.handle(),
+            None => return Ok(()), // Not an element that can be pictured
+        };
+        self.thumbnails.insert(node, (resolution, generation));
+        self.send(Data::Thumbnail { node, image })
+    }
+
+    /// The output of a layer converted for display along with its size, if it is an image. The conversion copies every
+    /// pixel, so it is only done again once the output changed.
+    fn display_handle(&mut self, node: NodeIndex, resolution: Resolution) -> Option<(Handle, (u32, u32))> {
+        let graph = self.graph(resolution)?;
+        let generation = graph.generation(node)?;
+        let cached = self.handles.get(&node).filter(|(cached_resolution, cached_generation, ..)| {
+            (*cached_resolution, *cached_generation) == (resolution, generation)
+        });
+        if let Some((_, _, handle, size)) = cached {
+            return Some((handle.clone(), *size));
+        }
+        let output = graph.output(node, 0)?;
+        let (handle, size) = ui::image_handle(output).zip(entity::dimensions(output))?;
+        self.handles.insert(node, (resolution, generation, handle.clone(), size));
+        Some((handle, size))
+    }
+
+    /// The size the output of a layer has at full resolution, given the size of its output at `resolution`. Until
+    /// the full resolution output is there, it is estimated from the preview.
+    fn full_size(&self, node: NodeIndex, size: (u32, u32), resolution: Resolution) -> (u32, u32) {
+        let full = self.layers.graph();
+        if let (Resolution::Preview, false) = (resolution, full.is_dirty(node)) {
+            if let Some(size) = full.output(node, 0).and_then(|output| entity::dimensions(output)) {
+                return size;
+            }
+        }
+        match (resolution, &self.preview) {
+            (Resolution::Preview, Some(preview)) => {
+                let scale = |size: u32| (f64::from(size) / preview.factor).round() as u32;
+                (scale(size.0), scale(size.1))
+            }
+            _ => size,
+        }
+    }
+
+    /// Sends the histogram of the output of a layer, if it has one. It is only computed again once the output changed,
+    /// or once the full resolution output replaces the preview.
+    fn send_histogram(&mut self, node: NodeIndex, resolution: Resolution) -> Result<()> {
+        let cached = self.histograms.get(&node).filter(|(cached_resolution, generation, _)| {
+            let current = self.graph(*cached_resolution).and_then(|graph| graph.generation(node));
+            let usable = *cached_resolution == Resolution::Full || resolution == Resolution::Preview;
+            usable && current == Some(*generation)
+        });
+        let histogram = match cached {
+            Some((_, _, histogram)) => histogram.clone(),
+            None => {
+                let graph = match self.graph(resolution) {
+                    Some(graph) => graph,
+                    None => return Ok(()),
+                };
+                let (generation, histogram) = match (graph.generation(node), graph.output(node, 0)) {
+                    (Some(generation), Some(output)) => match entity::histogram(output) {
+                        Some(histogram) => (generation, histogram),
+                        None => return Ok(()), // Not an image with a histogram
+                    },
+                    _ => return Ok(()),
+                };
+                self.histograms.insert(node, (resolution, generation, histogram.clone()));
+                histogram
+            }
+        };
+        self.send(Data::Histogram { node, histogram })
+    }
+}
+
+/// Runs a backend on its own thread and brings it back up with the last known state of the graph if it dies
+pub struct Supervisor {
+    channel: ThreadChannel<Event, Data>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+    registry: Box<dyn Fn() -> LayerRegistry + Send>,
+    snapshot: Arc<Mutex<Recipe>>,
+}
+
+impl Supervisor {
+    /// Previews that can be on their way to the user interface at once, before older ones are dropped
+    const PREVIEW_CAPACITY: usize = 4;
+
+    /// Starts a backend with an empty graph. `registry` provides the layers each time a backend is started.
+    pub fn spawn(registry: impl Fn() -> LayerRegistry + Send + 'static) -> Self {
+        let snapshot = Arc::new(Mutex::new(Recipe::from_graph(&InteractiveLayerGraph::new())));
+        let (channel, backend_channel) = Self::new_channel();
+        let mut backend = Backend::new(backend_channel, registry());
+        backend.snapshot = Some(snapshot.clone());
+        Self {
+            channel,
+            thread: Some(thread::spawn(move || backend.run())),
+            registry: Box::new(registry),
+            snapshot,
+        }
+    }
+
+    /// The channel to the current backend. It changes when the backend is restarted.
+    pub fn channel(&self) -> &ThreadChannel<Event, Data> {
+        &self.channel
+    }
+
+    /// All kinds of layers the backend can add, along with what is known about them
+    pub fn catalog(&self) -> Vec<(String, LayerInfo)> {
+        let registry = (self.registry)();
+        registry
+            .catalog()
+            .filter_map(|(kind, _)| Some((kind.to_string(), registry.info(kind)?.clone())))
+            .collect()
+    }
+
+    /// Whether the backend went away without being asked to
+    pub fn has_crashed(&self) -> bool {
+        self.channel.is_disconnected()
+    }
+
+    /// The graph as it was after the last events the backend handled
+    pub fn snapshot(&self) -> Recipe {
+        self.snapshot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Starts a new backend with the graph as it was after the last events the previous backend handled. Returns why
+    /// the previous backend stopped.
+    pub fn restart(&mut self) -> Result<String> {
+        let recipe = self.snapshot();
+        let (channel, backend_channel) = Self::new_channel();
+        let mut backend = Backend::from_recipe(backend_channel, (self.registry)(), &recipe)
+            .context("Failed to restore the graph for the restarted backend")?;
+        backend.snapshot = Some(self.snapshot.clone());
+
+        let previous = self.thread.replace(thread::spawn(move || backend.run()));
+        self.channel = channel;
+        let reason = match previous.map(|thread| thread.join()) {
+            Some(Ok(Ok(()))) | None => "Backend stopped".to_string(),
+            Some(Ok(Err(e))) => format!("Backend failed: {:#}", e),
+            Some(Err(payload)) => format!("Backend panicked: {}", panic_message(&*payload)),
+        };
+        Ok(reason)
+    }
+
+    /// A channel on which the backend replaces previews that the user interface didn't get to yet
+    fn new_channel() -> (ThreadChannel<Event, Data>, ThreadChannel<Data, Event>) {
+        let (channel, mut backend_channel) = ThreadChannel::new_pair();
+        backend_channel.set_coalescing(Self::PREVIEW_CAPACITY, |data| match data {
+            Data::Preview { node, .. } => Some(node.index()),
+            _ => None,
+        });
+        (channel, backend_channel)
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        let _ = self.channel.send(Event::Exit); // The backend might be gone already
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Unknown cause"
+    }
+}
+
+/// Drops parameter changes that are overridden by a later change of the same parameter, and pixel queries that are
+/// followed by another one. Changes are never moved past events that change the structure of the graph, and the order
+/// of the remaining events is kept.
+pub fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut coalesced: Vec<Event> = Vec::with_capacity(events.len());
+    let mut segment_start = 0; // Events before this can't be dropped, because a structural change follows them
+    for event in events {
+        match &event {
+            Event::SetParameter { node, name, .. } => {
+                let overridden = coalesced[segment_start..].iter().position(|earlier| {
+                    matches!(earlier, Event::SetParameter { node: earlier_node, name: earlier_name, .. }
+                        if earlier_node == node && earlier_name == name)
+                });
+                if let Some(i) = overridden {
+                    coalesced.remove(segment_start + i);
+                }
+                coalesced.push(event);
+            }
+            Event::QueryPixel { .. } => {
+                // Only the latest position of the cursor is of interest
+                let earlier = coalesced[segment_start..]
+                    .iter()
+                    .position(|earlier| matches!(earlier, Event::QueryPixel { .. }));
+                if let Some(i) = earlier {
+                    coalesced.remove(segment_start + i);
+                }
+                coalesced.push(event);
+            }
+            Event::AddLayer { .. }
+            | Event::InsertLayer { .. }
+            | Event::RemoveLayer(_)
+            | Event::DuplicateLayer(_)
+            | Event::Connect { .. }
+            | Event::Disconnect { .. }
+            | Event::Undo
+            | Event::Redo
+            | Event::LoadRecipe { .. }
+            | Event::Exit => {
+                coalesced.push(event);
+                segment_start = coalesced.len();
+            }
+            Event::MoveLayer { .. }
+            | Event::SetLayout { .. }
+            | Event::SelectLayer(_)
+            | Event::RequestCompute(_)
+            | Event::RequestGraph
+            | Event::Paint { .. }
+            | Event::EndStroke
+            | Event::Export { .. }
+            | Event::CopyToClipboard(_) => coalesced.push(event),
+        }
+    }
+    coalesced
+}
+
+/// Whether handling `event` would change the output of any of `layers`
+fn invalidates(event: &Event, layers: &HashSet<NodeIndex>) -> bool {
+    match event {
+        Event::SetParameter { node, .. } | Event::Paint { node, .. } => layers.contains(node),
+        Event::Connect { to, .. } | Event::Disconnect { to, .. } => layers.contains(to),
+        Event::RemoveLayer(node) => layers.contains(node),
+        Event::Undo | Event::Redo | Event::LoadRecipe { .. } | Event::Exit => true, // Not worth finishing
+        Event::AddLayer { .. }
+        | Event::InsertLayer { .. }
+        | Event::DuplicateLayer(_)
+        | Event::MoveLayer { .. }
+        | Event::SetLayout { .. }
+        | Event::SelectLayer(_)
+        | Event::RequestCompute(_)
+        | Event::RequestGraph
+        | Event::QueryPixel { .. }
+        | Event::EndStroke
+        | Event::Export { .. }
+        | Event::CopyToClipboard(_) => false,
+    }
+}
+
+/// Whether `event` asks for something more urgent than a job of the given priority
+fn preempts(event: &Event, priority: Priority) -> bool {
+    match event {
+        Event::SelectLayer(_) | Event::InsertLayer { .. } | Event::DuplicateLayer(_) => priority < Priority::Preview,
+        Event::RequestCompute(_) => priority < Priority::Normal,
+        _ => false,
+    }
+}
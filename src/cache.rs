@@ -0,0 +1,235 @@
+//! Outputs of layers kept on disk, so that running a pipeline again on the same input doesn't compute them again
+
+use std::{
+    any::Any,
+    fs,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use image::{GrayImage, RgbaImage};
+
+use crate::{
+    entity::{self, BinaryImage, Element, Gray16Image, GrayImageF32},
+    error::{KlexError, Result},
+    layer::{Layer, LayerOutput},
+    recipe::Recipe,
+    serialize::Npy,
+    util::StableHasher,
+};
+
+type Loaded = Box<dyn Any + Send + Sync>;
+type Read = fn(&Path) -> Result<Loaded>;
+
+/// The extension of the file each kind of element is stored in, and how to read it back
+pub(crate) const FILES: [(&str, Read); 5] = [
+    ("gray.npy", |path| Ok(Box::new(GrayImage::from_npy(path)?))),
+    ("gray16.npy", |path| Ok(Box::new(Gray16Image::from_npy(path)?))),
+    ("f32.npy", |path| Ok(Box::new(GrayImageF32::from_npy(path)?))),
+    ("binary.npy", |path| Ok(Box::new(BinaryImage::from_npy(path)?))),
+    ("rgba.png", |path| Ok(Box::new(image::open(path)?.into_rgba8()))),
+];
+
+/// Numbers the temporary files written by this process, so that threads storing the same output write to different ones
+static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A directory of outputs of layers, each stored under a hash of everything it was computed from: the layer's kind
+/// and parameters, the contents of its inputs and the versions of the recipe and cache formats. Outputs of layers
+/// without inputs aren't cached, since their key couldn't tell whether e.g. the file they read changed. Once the
+/// stored outputs take up more than the size limit, the ones used least recently are removed.
+pub struct OutputCache {
+    directory: PathBuf,
+    max_bytes: u64,
+}
+
+impl OutputCache {
+    pub const FORMAT: u32 = 1; // Increased whenever stored outputs can't be read the same way anymore
+    const STALE_TEMPORARY: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(directory: impl Into<PathBuf>, max_bytes: u64) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).map_err(|source| KlexError::Io { path: directory.clone(), source })?;
+        Ok(Self { directory, max_bytes })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The key of the output of `layer` for `input`, if it can be cached
+    pub fn key(layer: &dyn Layer, input: &[&LayerOutput]) -> Option<u64> {
+        if input.is_empty() {
+            return None;
+        }
+        let mut hasher = StableHasher::new();
+        hasher.write_u32(Self::FORMAT);
+        hasher.write_u32(Recipe::VERSION);
+        hasher.write(layer.cache_key()?.as_bytes());
+        for input in input {
+            hasher.write_u64(entity::content_hash(input.as_deref()?)?);
+        }
+        Some(hasher.finish())
+    }
+
+    /// The output stored for a layer of the given kind under `key`. Files that can't be read are removed.
+    pub fn load(&self, kind: &str, key: u64) -> Option<Loaded> {
+        for (extension, read) in FILES {
+            let path = self.path(kind, key, extension);
+            if !path.exists() {
+                continue;
+            }
+            match read(&path) {
+                Ok(output) => {
+                    // Marks the output as recently used
+                    let file = fs::File::options().append(true).open(&path);
+                    file.and_then(|file| file.set_modified(SystemTime::now())).ok();
+                    return Some(output);
+                }
+                Err(_) => {
+                    fs::remove_file(&path).ok();
+                }
+            }
+        }
+        None
+    }
+
+    /// Stores the output of a layer of the given kind under `key`. Returns whether the output is a kind of element
+    /// that can be stored.
+    pub fn store(&self, kind: &str, key: u64, output: &dyn Any) -> Result<bool> {
+        let extension = match entity::element_name(output).and_then(file_extension) {
+            Some(extension) => extension,
+            None => return Ok(false),
+        };
+        let path = self.path(kind, key, extension);
+        // Written next to its place first, so that a file at the place is always complete. Other threads and
+        // processes may be storing the same output at the same time, so the temporary file is unique.
+        let number = TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed);
+        let temporary = path.with_extension(format!("{}-{}.tmp", std::process::id(), number));
+        let stored = write_element(output, &temporary).and_then(|()| {
+            fs::rename(&temporary, &path).map_err(|source| KlexError::Io { path: path.clone(), source })?;
+            Ok(())
+        });
+        if stored.is_err() {
+            fs::remove_file(&temporary).ok(); // Not left behind, e.g. half written when the disk is full
+        }
+        stored?;
+        self.prune()?;
+        Ok(true)
+    }
+
+    /// Removes the stored outputs of all layers of a kind, e.g. after changing what the kind computes. Returns how many
+    /// were removed.
+    pub fn invalidate_kind(&self, kind: &str) -> Result<usize> {
+        let prefix = format!("{}-", Self::file_kind(kind));
+        let mut removed = 0;
+        for (path, _, _) in self.files()? {
+            if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix)) {
+                fs::remove_file(&path).map_err(|source| KlexError::Io { path, source })?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes all stored outputs
+    pub fn clear(&self) -> Result<()> {
+        for (path, _, _) in self.files()? {
+            fs::remove_file(&path).map_err(|source| KlexError::Io { path, source })?;
+        }
+        Ok(())
+    }
+
+    /// Bytes taken up by the stored outputs
+    pub fn size_bytes(&self) -> Result<u64> {
+        Ok(self.files()?.iter().map(|&(_, size, _)| size).sum())
+    }
+
+    fn prune(&self) -> Result<()> {
+        let mut files = self.files()?;
+        let mut size: u64 = files.iter().map(|&(_, size, _)| size).sum();
+        files.sort_by_key(|&(_, _, modified)| modified);
+        for (path, file_size, _) in files {
+            if size <= self.max_bytes {
+                break;
+            }
+            // Another thread may have removed it already
+            fs::remove_file(&path).ok();
+            size -= file_size;
+        }
+        self.remove_stale_temporaries()
+    }
+
+    /// Removes the temporary files of stores that never finished, e.g. because their process was killed. Files that
+    /// are still being written are left alone, since they changed recently.
+    fn remove_stale_temporaries(&self) -> Result<()> {
+        let io = |source| KlexError::Io { path: self.directory.clone(), source };
+        for entry in fs::read_dir(&self.directory).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            if path.extension().is_none_or(|extension| extension != "tmp") {
+                continue;
+            }
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+            if modified.is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > Self::STALE_TEMPORARY)) {
+                fs::remove_file(&path).ok(); // Another thread may have removed it already
+            }
+        }
+        Ok(())
+    }
+
+    /// The stored outputs with their size and when they were last used
+    fn files(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let io = |source| KlexError::Io { path: self.directory.clone(), source };
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.directory).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            let is_output = FILES.iter().any(|(extension, _)| path.to_string_lossy().ends_with(extension));
+            if !is_output {
+                continue;
+            }
+            // Files can disappear while they're listed, when several threads use the cache
+            if let Ok(metadata) = fs::metadata(&path) {
+                files.push((path, metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+            }
+        }
+        Ok(files)
+    }
+
+    fn path(&self, kind: &str, key: u64, extension: &str) -> PathBuf {
+        self.directory.join(format!("{}-{:016x}.{}", Self::file_kind(kind), key, extension))
+    }
+
+    /// The kind of a layer as part of a file name, e.g. "Threshold_Gray16Image_" for "Threshold<Gray16Image>"
+    fn file_kind(kind: &str) -> String {
+        kind.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+    }
+}
+
+/// The extension of the file an element of the given name is stored in, see `FILES`
+pub(crate) fn file_extension(element: &str) -> Option<&'static str> {
+    match element {
+        GrayImage::NAME => Some("gray.npy"),
+        Gray16Image::NAME => Some("gray16.npy"),
+        GrayImageF32::NAME => Some("f32.npy"),
+        BinaryImage::NAME => Some("binary.npy"),
+        RgbaImage::NAME => Some("rgba.png"),
+        _ => None,
+    }
+}
+
+/// Writes an element that has a file extension to `path`
+pub(crate) fn write_element(output: &dyn Any, path: &Path) -> Result<()> {
+    if let Some(image) = output.downcast_ref::<GrayImage>() {
+        image.to_npy(path)
+    } else if let Some(image) = output.downcast_ref::<Gray16Image>() {
+        image.to_npy(path)
+    } else if let Some(image) = output.downcast_ref::<GrayImageF32>() {
+        image.to_npy(path)
+    } else if let Some(image) = output.downcast_ref::<BinaryImage>() {
+        image.to_npy(path)
+    } else {
+        let image = output.downcast_ref::<RgbaImage>().expect("Only known elements are written");
+        Ok(image.save_with_format(path, image::ImageFormat::Png)?)
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, OnceLock},
+};
+
+use image::{GrayImage, Luma, Pixel, Rgba, RgbaImage};
+
+use crate::{
+    entity::{self, BinaryImage, Element, Gray16Image, GrayImageF32, LabelImage},
+    error::{Context, KlexError, Result},
+    layer::primitive::{Convert, FloatRange, FloatScale, Normalize, ToFloat},
+};
+
+/// Decodes an sRGB value from 0 to 1 into linear light
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes linear light from 0 to 1 as an sRGB value
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Relative luminance of a color in linear light, weighted for the primaries of sRGB
+pub fn luminance([red, green, blue]: [f32; 3]) -> f32 {
+    0.2126 * red + 0.7152 * green + 0.0722 * blue
+}
+
+/// Conversions between 8 bit sRGB values and linear light that look values up instead of computing powers, which
+/// matters when converting every pixel of an image
+pub struct SrgbTable {
+    linear: [f32; 256],
+    thresholds: [f32; 255], // Linear light halfway between neighboring values, above which the next value is closer
+}
+
+impl SrgbTable {
+    pub fn new() -> Self {
+        let mut table = Self {
+            linear: [0.0; 256],
+            thresholds: [0.0; 255],
+        };
+        for (value, linear) in table.linear.iter_mut().enumerate() {
+            *linear = srgb_to_linear(value as f32 / 255.0);
+        }
+        for (value, threshold) in table.thresholds.iter_mut().enumerate() {
+            *threshold = srgb_to_linear((value as f32 + 0.5) / 255.0);
+        }
+        table
+    }
+
+    pub fn to_linear(&self, value: u8) -> f32 {
+        self.linear[usize::from(value)]
+    }
+
+    /// The closest 8 bit sRGB value to linear light from 0 to 1
+    pub fn to_srgb(&self, linear: f32) -> u8 {
+        self.thresholds.partition_point(|&threshold| threshold <= linear) as u8
+    }
+}
+
+impl Default for SrgbTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts a value from 0 to 1 to 8 bits
+pub fn quantize(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Arithmetic on the 8 bit values of RGBA pixels as they are stored, without converting to linear light. Results
+// saturate at the ends of the range instead of wrapping around. Functions on colors keep the alpha of their first
+// argument, while compositing takes alpha into account, either straight or premultiplied.
+
+/// Adds the colors of two pixels
+pub fn saturating_add(a: Rgba<u8>, b: Rgba<u8>) -> Rgba<u8> {
+    let mut sum = a.map2(&b, u8::saturating_add);
+    sum[3] = a[3];
+    sum
+}
+
+/// Subtracts the color of `b` from that of `a`
+pub fn saturating_sub(a: Rgba<u8>, b: Rgba<u8>) -> Rgba<u8> {
+    let mut difference = a.map2(&b, u8::saturating_sub);
+    difference[3] = a[3];
+    difference
+}
+
+/// Multiplies the color of a pixel by `factor`, rounding the result
+pub fn scale(color: Rgba<u8>, factor: f32) -> Rgba<u8> {
+    color.map_without_alpha(|value| (f32::from(value) * factor).round().clamp(0.0, 255.0) as u8)
+}
+
+/// Interpolates all channels, including alpha, from `a` at a `t` of 0 to `b` at 1
+pub fn lerp(a: Rgba<u8>, b: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    a.map2(&b, |a, b| (f32::from(a) + (f32::from(b) - f32::from(a)) * t).round() as u8)
+}
+
+/// Scales the color by the alpha of a pixel with straight alpha
+pub fn premultiply(color: Rgba<u8>) -> Rgba<u8> {
+    let alpha = u16::from(color[3]);
+    color.map_without_alpha(|value| ((u16::from(value) * alpha + 127) / 255) as u8)
+}
+
+/// Undoes `premultiply`, as far as the precision of 8 bits allows. Fully transparent pixels become black.
+pub fn unpremultiply(color: Rgba<u8>) -> Rgba<u8> {
+    let alpha = u16::from(color[3]);
+    match alpha {
+        0 => Rgba([0; 4]),
+        _ => color.map_without_alpha(|value| ((u16::from(value) * 255 + alpha / 2) / alpha).min(255) as u8),
+    }
+}
+
+/// Composites `top` over `bottom`, both with premultiplied alpha
+pub fn over_premultiplied(top: Rgba<u8>, bottom: Rgba<u8>) -> Rgba<u8> {
+    let transparency = 255 - u16::from(top[3]);
+    top.map2(&bottom, |top, bottom| top.saturating_add(((u16::from(bottom) * transparency + 127) / 255) as u8))
+}
+
+/// Composites `top` over `bottom`, both with straight alpha
+pub fn over(top: Rgba<u8>, bottom: Rgba<u8>) -> Rgba<u8> {
+    unpremultiply(over_premultiplied(premultiply(top), premultiply(bottom)))
+}
+
+/// Converts an element of the type it was registered for, see `ConversionGraph::register`
+pub type Conversion = Arc<dyn Fn(&dyn Any) -> Result<Box<dyn Any + Send + Sync>> + Send + Sync>;
+
+/// The direct conversions between elements. Elements that aren't directly convertible may still be connected through
+/// a path of several conversions, e.g. 16 bit gray to RGBA through 8 bit gray.
+#[derive(Clone, Default)]
+pub struct ConversionGraph {
+    direct: BTreeMap<&'static str, BTreeMap<&'static str, Conversion>>,
+}
+
+impl ConversionGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The conversions between the elements of this crate
+    pub fn with_builtins() -> Self {
+        let mut graph = Self::new();
+        graph.register(Convert::<RgbaImage, GrayImage>::compute);
+        graph.register(Convert::<BinaryImage, GrayImage>::compute);
+        graph.register(|image: &GrayImage| BinaryImage::try_from(image)); // Fails unless all pixels are 0 or 255
+        graph.register(|image: &GrayImage| {
+            Ok(RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+                let Luma([value]) = *image.get_pixel(x, y);
+                Rgba([value, value, value, u8::MAX])
+            }))
+        });
+        graph.register(|image: &Gray16Image| Ok(entity::to_gray8(image)));
+        graph.register(|image: &GrayImage| {
+            Ok(Gray16Image::from_fn(image.width(), image.height(), |x, y| {
+                Luma([u16::from(image.get_pixel(x, y)[0]) * 257])
+            }))
+        });
+        graph.register(|image: &GrayImage| Ok(ToFloat::new(FloatScale::Normalized).compute(image)));
+        graph.register(|image: &GrayImageF32| Ok(Normalize::new(FloatRange::Fixed, 0.0, 1.0).compute(image)));
+        graph.register(|image: &LabelImage| Ok(entity::to_rgba(image).expect("Labels have colors")));
+        graph
+    }
+
+    /// The shared graph of builtin conversions
+    pub fn builtins() -> &'static Self {
+        static BUILTINS: OnceLock<ConversionGraph> = OnceLock::new();
+        BUILTINS.get_or_init(Self::with_builtins)
+    }
+
+    /// Makes `convert` the direct conversion from `A` to `B`, replacing a previous one
+    pub fn register<A: Element, B: Element>(&mut self, convert: fn(&A) -> Result<B>) {
+        let conversion: Conversion = Arc::new(move |element: &dyn Any| {
+            let found = entity::element_name(element).unwrap_or("an unknown type");
+            let element = element.downcast_ref::<A>().ok_or(KlexError::TypeMismatch { expected: A::NAME, found })?;
+            Ok(Box::new(convert(element)?))
+        });
+        self.direct.entry(A::NAME).or_default().insert(B::NAME, conversion);
+    }
+
+    /// Every element that can be converted from or to
+    pub fn elements(&self) -> Vec<&'static str> {
+        let reached = self.direct.iter().flat_map(|(&from, to)| to.keys().copied().chain([from]));
+        let mut elements: Vec<_> = reached.collect();
+        elements.sort_unstable();
+        elements.dedup();
+        elements
+    }
+
+    /// The shortest path of conversions from one element to another, including both. The path of an element to
+    /// itself is only that element.
+    pub fn path(&self, from: &str, to: &str) -> Option<Vec<&'static str>> {
+        let start = self.elements().into_iter().find(|&element| element == from)?;
+        let mut previous = BTreeMap::new(); // The element each reached one was converted from
+        let mut queue = VecDeque::from([start]);
+        while let Some(element) = queue.pop_front() {
+            if element == to {
+                let mut path = vec![element];
+                while let Some(&before) = previous.get(path.last().expect("path isn't empty")) {
+                    path.push(before);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &next in self.direct.get(element).into_iter().flat_map(BTreeMap::keys) {
+                if next != start && !previous.contains_key(next) {
+                    previous.insert(next, element);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether an element of type `from` can be turned into one of type `to`, e.g. to tell if the output of a layer
+    /// can feed another one
+    pub fn can_convert(&self, from: &str, to: &str) -> bool {
+        self.path(from, to).is_some()
+    }
+
+    /// The conversions along a path as returned by `path`
+    pub fn conversions(&self, path: &[&'static str]) -> Result<Vec<Conversion>> {
+        path.windows(2)
+            .map(|step| {
+                let conversion = self.direct.get(step[0]).and_then(|to| to.get(step[1]));
+                conversion.cloned().with_context(|| format!("No conversion from {} to {}", step[0], step[1]))
+            })
+            .collect()
+    }
+
+    /// Converts `element` to `to` along the shortest path. If a step fails, the error names the whole path.
+    pub fn convert(&self, element: &dyn Any, to: &str) -> Result<Box<dyn Any + Send + Sync>> {
+        let from = entity::element_name(element).context("Can only convert known elements")?;
+        let path = self.path(from, to).with_context(|| format!("No conversion from {} to {}", from, to))?;
+        convert_along(&path, &self.conversions(&path)?, element)
+    }
+}
+
+/// Applies the conversions along `path` one after another, naming the path if one of them fails
+pub fn convert_along(
+    path: &[&'static str],
+    conversions: &[Conversion],
+    element: &dyn Any,
+) -> Result<Box<dyn Any + Send + Sync>> {
+    let (first, rest) = conversions.split_first().context("Empty conversion path")?;
+    let failure = |step: usize| {
+        format!("Failed to convert {} to {} on the path {}", path[step], path[step + 1], path.join(" -> "))
+    };
+    let mut converted = first(element).with_context(|| failure(0))?;
+    for (step, conversion) in rest.iter().enumerate() {
+        converted = conversion(&*converted).with_context(|| failure(step + 1))?;
+    }
+    Ok(converted)
+}
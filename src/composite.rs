@@ -0,0 +1,169 @@
+use std::sync::Mutex;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    error::{bail, Context, KlexError, Result},
+    layer::{CancelToken, Layer, LayerOutput, OutputPort},
+    layer_graph::{ExternalInputs, LayerGraph},
+    parameter::{ParamMap, ParamValue},
+};
+
+/// A layer made up of a whole graph of layers, so that a chain of layers can be treated as a single reusable unit.
+/// Parameters of the inner layers are addressed as `"<layer name>.<parameter name>"`.
+pub struct CompositeLayer {
+    graph: Mutex<LayerGraph>, // Computing the inner graph stores outputs, which `Layer::compute` can't do through `&self`
+    inputs: Vec<Vec<(NodeIndex, usize)>>, // Inner layers and input ports that each input of the composite layer is fed to
+    output: NodeIndex,                    // Inner layer whose output becomes the output of the composite layer
+    outputs: Vec<OutputPort>,             // Those of the output layer, which can't be borrowed through the lock
+}
+
+impl CompositeLayer {
+    pub fn new(graph: LayerGraph, inputs: Vec<Vec<(NodeIndex, usize)>>, output: NodeIndex) -> Result<Self> {
+        for &(layer, _) in inputs.iter().flatten() {
+            if !graph.contains(layer) {
+                bail!("Input layer {} is not part of the composite graph", layer.index());
+            }
+        }
+        let outputs = match graph.layer(output) {
+            Some(layer) => layer.outputs().to_vec(),
+            None => bail!("Output layer {} is not part of the composite graph", output.index()),
+        };
+
+        Ok(Self {
+            graph: Mutex::new(graph),
+            inputs,
+            output,
+            outputs,
+        })
+    }
+
+    /// Runs `f` on the inner graph. Fails if a computation of the inner graph panicked.
+    pub fn with_graph<R>(&self, f: impl FnOnce(&LayerGraph) -> R) -> Result<R> {
+        let graph = self.graph.lock().map_err(|_| KlexError::Poisoned("the composite graph"))?;
+        Ok(f(&graph))
+    }
+
+    fn graph_mut(&mut self) -> Result<&mut LayerGraph> {
+        self.graph.get_mut().map_err(|_| KlexError::Poisoned("the composite graph"))
+    }
+
+    /// Splits `"<layer name>.<parameter name>"` and finds the inner layer it refers to
+    fn resolve(graph: &LayerGraph, path: &str) -> Result<(NodeIndex, String)> {
+        let (name, parameter) = path
+            .split_once('.')
+            .context(format!("Expected a parameter path like \"<layer>.<parameter>\", got {:?}", path))?;
+
+        let mut candidates = graph.node_indices().filter(|&layer| graph.name(layer) == Some(name));
+        match (candidates.next(), candidates.next()) {
+            (Some(layer), None) => Ok((layer, parameter.to_string())),
+            (None, _) => bail!(KlexError::UnknownName { name: name.to_string(), scope: "the composite layer" }),
+            (Some(_), Some(_)) => {
+                bail!(KlexError::AmbiguousName { name: name.to_string(), scope: "the composite layer" })
+            }
+        }
+    }
+}
+
+impl Layer for CompositeLayer {
+    fn kind(&self) -> String {
+        "Composite".to_string()
+    }
+
+    fn input_types(&self) -> Vec<&'static str> {
+        let graph = match self.graph.lock() {
+            Ok(graph) => graph,
+            Err(_) => return Vec::new(),
+        };
+
+        // Known only if every inner layer fed by an input agrees on its type
+        let mut types = Vec::new();
+        for targets in &self.inputs {
+            let mut target_types = targets.iter().map(|&(layer, port)| {
+                graph
+                    .layer(layer)
+                    .and_then(|layer| layer.input_types().get(port).copied())
+            });
+            match target_types.next().flatten() {
+                Some(first) if target_types.all(|other| other == Some(first)) => types.push(first),
+                _ => return Vec::new(),
+            }
+        }
+        types
+    }
+
+    fn outputs(&self) -> &[OutputPort] {
+        &self.outputs
+    }
+
+    fn output_type(&self) -> Option<&'static str> {
+        let graph = self.graph.lock().ok()?;
+        graph.layer(self.output)?.output_type()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        self.compute_cancellable(input, output, &CancelToken::new())
+    }
+
+    fn compute_cancellable(
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let mut graph = self.graph.lock().map_err(|_| KlexError::Poisoned("the composite graph"))?;
+
+        let mut external = ExternalInputs::new();
+        for (port, targets) in self.inputs.iter().enumerate() {
+            let input = *input.get(port).context(format!("Missing input {} of composite layer", port))?;
+            for &(layer, layer_port) in targets {
+                external.insert((layer, layer_port), input);
+                graph.mark_dirty(layer); // The input changed, otherwise this wouldn't be computed
+            }
+        }
+
+        graph.compute_all_with_inputs(&external, cancel)?;
+        *output = graph.take_output(self.output)?;
+        Ok(())
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        None // The parameters don't say how the inner layers are connected
+    }
+
+    fn parameters(&self) -> ParamMap {
+        let graph = match self.graph.lock() {
+            Ok(graph) => graph,
+            Err(_) => return ParamMap::new(),
+        };
+
+        let mut parameters = ParamMap::new();
+        for layer in graph.node_indices() {
+            let name = graph.name(layer).unwrap_or_default();
+            for (parameter, value) in graph.parameters(layer).unwrap_or_default() {
+                parameters.insert(format!("{}.{}", name, parameter), value);
+            }
+        }
+        parameters
+    }
+
+    fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        let graph = self.graph_mut()?;
+        let (layer, parameter) = Self::resolve(graph, name)?;
+        graph.set_parameter(layer, &parameter, value)
+    }
+
+    fn scale_parameters(&mut self, factor: f64) {
+        if let Ok(graph) = self.graph_mut() {
+            graph.scale_parameters(factor);
+        }
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        let graph = self.with_graph(LayerGraph::try_clone)??;
+        Ok(Box::new(Self::new(graph, self.inputs.clone(), self.output)?))
+    }
+}
+
+#[cfg(feature = "ui")]
+impl crate::layer::InteractiveLayer for CompositeLayer {}
@@ -0,0 +1,364 @@
+//! Drawing onto color images in software, e.g. for burning labels into exported images. Everything is clipped to the
+//! image, so drawing may start or reach outside of it. Shapes are turned into the same strokes as in the viewport
+//! overlay, so that they look alike when drawn into an image.
+
+use image::RgbaImage;
+
+use crate::entity::Geometry;
+
+/// Width and height of a glyph of the font, in pixels at a scale of 1
+pub const GLYPH_SIZE: (u32, u32) = (5, 7);
+
+/// From one glyph to the next and from one line to the next, in pixels at a scale of 1
+pub const ADVANCE: (u32, u32) = (6, 9);
+
+/// The printable ASCII characters from ' ' to '~', as a row of bits for each row of pixels from the top, with the
+/// leftmost pixel in the highest bit
+const GLYPHS: [[u8; 7]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // Space
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0a, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0a, 0x0a, 0x1f, 0x0a, 0x1f, 0x0a, 0x0a], // #
+    [0x04, 0x0f, 0x14, 0x0e, 0x05, 0x1e, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0c, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0d], // &
+    [0x04, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0e, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e], // 0
+    [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e], // 1
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f], // 2
+    [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e], // 3
+    [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02], // 4
+    [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e], // 5
+    [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e], // 6
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e], // 8
+    [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0e, 0x11, 0x01, 0x0d, 0x15, 0x15, 0x0e], // @
+    [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // A
+    [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e], // B
+    [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e], // C
+    [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c], // D
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f], // E
+    [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10], // F
+    [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f], // G
+    [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11], // H
+    [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f], // L
+    [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // O
+    [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10], // P
+    [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d], // Q
+    [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11], // R
+    [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e], // S
+    [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a], // W
+    [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04], // Y
+    [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f], // Z
+    [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+    [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e], // ]
+    [0x04, 0x0a, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f], // _
+    [0x08, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f], // a
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x1e], // b
+    [0x00, 0x00, 0x0e, 0x10, 0x10, 0x11, 0x0e], // c
+    [0x01, 0x01, 0x0d, 0x13, 0x11, 0x11, 0x0f], // d
+    [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e], // e
+    [0x06, 0x09, 0x08, 0x1c, 0x08, 0x08, 0x08], // f
+    [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e], // g
+    [0x10, 0x10, 0x16, 0x19, 0x11, 0x11, 0x11], // h
+    [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e], // i
+    [0x02, 0x00, 0x06, 0x02, 0x02, 0x12, 0x0c], // j
+    [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12], // k
+    [0x0c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e], // l
+    [0x00, 0x00, 0x1a, 0x15, 0x15, 0x11, 0x11], // m
+    [0x00, 0x00, 0x16, 0x19, 0x11, 0x11, 0x11], // n
+    [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e], // o
+    [0x00, 0x00, 0x1e, 0x11, 0x1e, 0x10, 0x10], // p
+    [0x00, 0x00, 0x0d, 0x13, 0x0f, 0x01, 0x01], // q
+    [0x00, 0x00, 0x16, 0x19, 0x10, 0x10, 0x10], // r
+    [0x00, 0x00, 0x0e, 0x10, 0x0e, 0x01, 0x1e], // s
+    [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06], // t
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x13, 0x0d], // u
+    [0x00, 0x00, 0x11, 0x11, 0x11, 0x0a, 0x04], // v
+    [0x00, 0x00, 0x11, 0x11, 0x15, 0x15, 0x0a], // w
+    [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11], // x
+    [0x00, 0x00, 0x11, 0x11, 0x0f, 0x01, 0x0e], // y
+    [0x00, 0x00, 0x1f, 0x02, 0x04, 0x08, 0x1f], // z
+    [0x02, 0x04, 0x04, 0x08, 0x04, 0x04, 0x02], // {
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // |
+    [0x08, 0x04, 0x04, 0x02, 0x04, 0x04, 0x08], // }
+    [0x00, 0x00, 0x08, 0x15, 0x02, 0x00, 0x00], // ~
+];
+
+/// Stands in for characters outside of the font
+const MISSING: [u8; 7] = [0x1f, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1f];
+
+fn glyph(character: char) -> &'static [u8; 7] {
+    match character {
+        ' '..='~' => &GLYPHS[character as usize - ' ' as usize],
+        _ => &MISSING,
+    }
+}
+
+/// Blends `color` over the pixel at `x`, `y` by its alpha, unless the pixel is outside of the image. Opaque pixels
+/// stay opaque, and transparent ones take on the color.
+pub fn blend(image: &mut RgbaImage, x: i64, y: i64, color: [u8; 4]) {
+    if !(0..i64::from(image.width())).contains(&x) || !(0..i64::from(image.height())).contains(&y) {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let max = f32::from(u8::MAX);
+    let alpha = f32::from(color[3]) / max;
+    let below = f32::from(pixel[3]) / max * (1.0 - alpha); // How much of the pixel still shows
+    let blended = alpha + below;
+    if blended <= 0.0 {
+        return;
+    }
+    for channel in 0..3 {
+        let value = (f32::from(color[channel]) * alpha + f32::from(pixel[channel]) * below) / blended;
+        pixel[channel] = value.round() as u8;
+    }
+    pixel[3] = (blended * max).round() as u8;
+}
+
+/// Blends `color` over the pixels from `left`, `top` up to but excluding `right`, `bottom`
+fn fill(image: &mut RgbaImage, (left, top): (i64, i64), (right, bottom): (i64, i64), color: [u8; 4]) {
+    let clamp = |value: i64, size: u32| value.clamp(0, i64::from(size));
+    let (left, right) = (clamp(left, image.width()), clamp(right, image.width()));
+    let (top, bottom) = (clamp(top, image.height()), clamp(bottom, image.height()));
+    for y in top..bottom {
+        for x in left..right {
+            blend(image, x, y, color);
+        }
+    }
+}
+
+/// Width and height of `text` drawn at `scale`, in pixels, which are 0 if there are no characters to draw. Lines
+/// are separated by `\n`.
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    let columns = text.split('\n').map(|line| line.chars().count()).max().unwrap_or(0);
+    if columns == 0 {
+        return (0, 0);
+    }
+    let rows = text.split('\n').count();
+    // There is no space after the last glyph of a line and below the last line
+    let size = |count: usize, advance: u32, glyph: u32| {
+        (count as u32 - 1).saturating_mul(advance).saturating_add(glyph).saturating_mul(scale)
+    };
+    (size(columns, ADVANCE.0, GLYPH_SIZE.0), size(rows, ADVANCE.1, GLYPH_SIZE.1))
+}
+
+/// Draws `text` with its top left corner at `x`, `y`, every pixel of the font becoming a square of `scale` pixels.
+/// Characters outside of ASCII are drawn as boxes.
+pub fn text(image: &mut RgbaImage, text: &str, (x, y): (i64, i64), scale: u32, color: [u8; 4]) {
+    let scale = i64::from(scale.max(1));
+    let (width, height) = (i64::from(image.width()), i64::from(image.height()));
+    for (row, line) in text.split('\n').enumerate() {
+        let top = y.saturating_add((row as i64).saturating_mul(i64::from(ADVANCE.1) * scale));
+        if top >= height {
+            break;
+        }
+        for (column, character) in line.chars().enumerate() {
+            let left = x.saturating_add((column as i64).saturating_mul(i64::from(ADVANCE.0) * scale));
+            if left >= width {
+                break;
+            }
+            for (glyph_y, bits) in (0..).zip(glyph(character)) {
+                for glyph_x in (0..GLYPH_SIZE.0).filter(|glyph_x| bits >> (GLYPH_SIZE.0 - 1 - glyph_x) & 1 == 1) {
+                    let corner = (left + i64::from(glyph_x) * scale, top + glyph_y * scale);
+                    fill(image, corner, (corner.0 + scale, corner.1 + scale), color);
+                }
+            }
+        }
+    }
+}
+
+/// A cross of two diagonals reaching `arm` pixels from `center` in each direction, like points are shown in the
+/// viewport
+pub fn marker(image: &mut RgbaImage, (x, y): (f32, f32), arm: u32, color: [u8; 4]) {
+    let (x, y) = (x.round() as i64, y.round() as i64);
+    // Only the offsets that reach columns of the image
+    let (first, last) = (x.saturating_neg(), i64::from(image.width()).saturating_sub(1).saturating_sub(x));
+    for offset in first.max(-i64::from(arm))..=last.min(i64::from(arm)) {
+        blend(image, x.saturating_add(offset), y.saturating_add(offset), color);
+        if offset != 0 {
+            blend(image, x.saturating_add(offset), y.saturating_sub(offset), color);
+        }
+    }
+}
+
+/// How far the diagonals of a point reach from its center, for shapes drawn `width` pixels wide
+pub fn arm(width: f32) -> f32 {
+    2.0 * width + 3.0
+}
+
+/// The shapes as polylines to stroke: every line, two diagonals reaching `arm` from every point in each direction, and
+/// the outline of every polygon back to its first point
+pub fn strokes(geometry: &Geometry, arm: f32) -> Vec<Vec<(f32, f32)>> {
+    let lines = geometry.lines.iter().map(|&(start, end)| vec![start, end]);
+    let points = geometry.points.iter().flat_map(|&(x, y)| {
+        [vec![(x - arm, y - arm), (x + arm, y + arm)], vec![(x + arm, y - arm), (x - arm, y + arm)]]
+    });
+    let polygons = geometry.polygons.iter().map(|polygon| {
+        let mut outline = polygon.clone();
+        outline.extend(polygon.first().copied());
+        outline
+    });
+    lines.chain(points).chain(polygons).collect()
+}
+
+/// How shapes are drawn into an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    pub color: [u8; 4], // RGBA, of strokes and fills alike
+    pub width: u32,     // Of strokes, in pixels, which are left out at 0
+    pub fill: bool,     // Whether the insides of polygons are drawn, by the even-odd rule
+}
+
+/// Draws the shapes, with positions in pixels of the image. Every pixel that is covered is blended with the color
+/// once, even where strokes and fills overlap.
+pub fn shapes(image: &mut RgbaImage, geometry: &Geometry, style: &Style) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut covered = vec![false; width * height];
+    if style.fill {
+        for polygon in &geometry.polygons {
+            fill_polygon(&mut covered, width, polygon);
+        }
+    }
+    if style.width > 0 {
+        let stamp = stamp(style.width);
+        // Pixels of a stroke outside of the image can still reach into it
+        let reach = f64::from(style.width) + 1.0;
+        let bounds = ((-reach, -reach), (width as f64 + reach, height as f64 + reach));
+        for stroke in strokes(geometry, arm(style.width as f32)) {
+            for (&start, &end) in stroke.iter().zip(stroke.iter().skip(1)) {
+                // In double precision, as the clipped ends of long segments are off by whole pixels otherwise
+                let (start, end) = ((f64::from(start.0), f64::from(start.1)), (f64::from(end.0), f64::from(end.1)));
+                if let Some((start, end)) = clip(start, end, bounds) {
+                    segment(start, end, |x, y| {
+                        for &(dx, dy) in &stamp {
+                            let (x, y) = (x + dx, y + dy);
+                            if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                                covered[y as usize * width + x as usize] = true;
+                            }
+                        }
+                    });
+                }
+            }
+        }
+    }
+    for (index, _) in covered.iter().enumerate().filter(|(_, &covered)| covered) {
+        blend(image, (index % width) as i64, (index / width) as i64, style.color);
+    }
+}
+
+/// The offsets of the pixels of a round pen `width` pixels wide
+fn stamp(width: u32) -> Vec<(i64, i64)> {
+    let width = i64::from(width.min(u32::from(u16::MAX)));
+    // The center lies between pixels for even widths
+    let center = if width % 2 == 0 { 0.5 } else { 0.0 };
+    let radius = width as f32 / 2.0;
+    let offsets = -(width - 1) / 2..=width / 2;
+    let pixels = offsets.clone().flat_map(|dy| offsets.clone().map(move |dx| (dx, dy)));
+    pixels.filter(|&(dx, dy)| (dx as f32 - center).hypot(dy as f32 - center) <= radius + 0.01).collect()
+}
+
+/// The part of the segment within `bounds`, if any, by Liang-Barsky. Segments that aren't finite are left out.
+fn clip(
+    (x1, y1): (f64, f64),
+    (x2, y2): (f64, f64),
+    ((left, top), (right, bottom)): ((f64, f64), (f64, f64)),
+) -> Option<((f64, f64), (f64, f64))> {
+    if ![x1, y1, x2, y2].iter().all(|value| value.is_finite()) {
+        return None;
+    }
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    let (mut enter, mut exit) = (0.0_f64, 1.0_f64);
+    for (p, q) in [(-dx, x1 - left), (dx, right - x1), (-dy, y1 - top), (dy, bottom - y1)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            enter = enter.max(q / p);
+        } else {
+            exit = exit.min(q / p);
+        }
+    }
+    match enter <= exit {
+        true => Some(((x1 + enter * dx, y1 + enter * dy), (x1 + exit * dx, y1 + exit * dy))),
+        false => None,
+    }
+}
+
+/// Calls `pixel` for each pixel of the segment by Bresenham, from the pixel that `start` is in to the one `end` is in
+fn segment(start: (f64, f64), end: (f64, f64), mut pixel: impl FnMut(i64, i64)) {
+    let (mut x, mut y) = (start.0.floor() as i64, start.1.floor() as i64);
+    let (x2, y2) = (end.0.floor() as i64, end.1.floor() as i64);
+    let (dx, dy) = ((x2 - x).abs(), -(y2 - y).abs());
+    let (step_x, step_y) = ((x2 - x).signum(), (y2 - y).signum());
+    let mut error = dx + dy;
+    loop {
+        pixel(x, y);
+        if (x, y) == (x2, y2) {
+            break;
+        }
+        if 2 * error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if 2 * error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// Covers the pixels whose centers are inside of the polygon by the even-odd rule, one row at a time
+fn fill_polygon(covered: &mut [bool], width: usize, polygon: &[(f32, f32)]) {
+    let height = covered.len().checked_div(width).unwrap_or(0);
+    if polygon.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+        return;
+    }
+    let (top, bottom) = polygon.iter().fold((f32::MAX, f32::MIN), |(top, bottom), &(_, y)| (top.min(y), bottom.max(y)));
+    let rows = (top - 0.5).ceil().max(0.0) as usize..((bottom - 0.5).ceil().max(0.0) as usize).min(height);
+    let edges: Vec<_> = polygon.iter().zip(polygon.iter().cycle().skip(1)).collect();
+    let mut crossings = Vec::new();
+    for y in rows {
+        let center = y as f32 + 0.5;
+        crossings.clear();
+        for (&(x1, y1), &(x2, y2)) in &edges {
+            if (y1 <= center) != (y2 <= center) {
+                crossings.push(x1 + (center - y1) / (y2 - y1) * (x2 - x1));
+            }
+        }
+        crossings.sort_by(f32::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            // The pixels whose centers are from the first crossing up to the second one
+            let column = |x: f32| ((x - 0.5).ceil().max(0.0) as usize).min(width);
+            covered[y * width..][column(pair[0])..column(pair[1])].iter_mut().for_each(|pixel| *pixel = true);
+        }
+    }
+}
@@ -1,7 +1,259 @@
-pub struct Line {}
+use serde::{Deserialize, Serialize};
 
-pub struct Point {}
+/// Data that is passed between layers
+pub trait Element: Send + Sync + 'static {
+    const NAME: &'static str; // Used to refer to the element in layer kinds and error messages
+}
+
+impl Element for image::RgbaImage {
+    const NAME: &'static str = "RgbaImage";
+}
+
+impl Element for image::GrayImage {
+    const NAME: &'static str = "GrayImage";
+}
+
+/// A gray image with 16 bits per pixel, like those of scanners and scientific cameras
+pub type Gray16Image = image::ImageBuffer<image::Luma<u16>, Vec<u16>>;
+
+impl Element for Gray16Image {
+    const NAME: &'static str = "Gray16Image";
+}
+
+/// A gray image with floating point pixels, which layers can pass on without rounding. Its values usually range from 0
+/// to 1, but aren't limited to that.
+pub type GrayImageF32 = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
+
+impl Element for GrayImageF32 {
+    const NAME: &'static str = "GrayImageF32";
+}
+
+impl Element for BinaryImage {
+    const NAME: &'static str = "BinaryImage";
+}
+
+/// The regions of a segmentation, with the number of the region each pixel belongs to. Regions are numbered from 1,
+/// while 0 is the background or the border between regions.
+pub type LabelImage = image::ImageBuffer<image::Luma<u32>, Vec<u32>>;
+
+impl Element for LabelImage {
+    const NAME: &'static str = "LabelImage";
+}
+
+/// A color for showing a label, which differs from those of the labels next to it. The background is black.
+pub fn label_color(label: u32) -> [u8; 4] {
+    if label == 0 {
+        return [0, 0, 0, u8::MAX];
+    }
+    // Hues a golden angle apart, so that consecutive labels are far apart around the color wheel
+    let hue = (label as f32 * 0.618_034).fract() * 6.0;
+    let channel = |offset: f32| {
+        let distance = ((hue + offset) % 6.0 - 3.0).abs(); // From the hue opposite of the channel
+        let value = (distance - 1.0).clamp(0.0, 1.0);
+        (60.0 + value * 180.0).round() as u8
+    };
+    [channel(0.0), channel(4.0), channel(2.0), u8::MAX]
+}
+
+/// Values of gray pixels, which layers for gray images are generic over
+pub trait Sample: image::Primitive + Ord + crate::parameter::Parameter + Send + Sync + 'static {
+    const MAX: Self; // White
+
+    /// The value scaled to 8 bits, e.g. for displaying it
+    fn to_u8(self) -> u8;
+}
+
+impl Sample for u8 {
+    const MAX: Self = u8::MAX;
+
+    fn to_u8(self) -> u8 {
+        self
+    }
+}
+
+impl Sample for u16 {
+    const MAX: Self = u16::MAX;
+
+    fn to_u8(self) -> u8 {
+        ((u32::from(self) + 128) / 257) as u8 // Rounded, 257 being u16::MAX / u8::MAX
+    }
+}
+
+/// A gray image scaled to 8 bits, e.g. for displaying a 16 bit image
+pub fn to_gray8<S: Sample>(image: &image::ImageBuffer<image::Luma<S>, Vec<S>>) -> image::GrayImage {
+    let data = image.as_raw().iter().map(|&value| value.to_u8()).collect();
+    image::GrayImage::from_raw(image.width(), image.height(), data).expect("Sizes match")
+}
+
+/// A rectangle of pixels, e.g. the part of an image that is cropped to. It reaches from `x` up to but excluding
+/// `x + width`, and likewise for `y`. Edges that would lie beyond `u32::MAX` saturate there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
 
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// The rectangle between the corners `(left, top)` and `(right, bottom)`, which is empty if they are reversed
+    fn between(left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        Self::new(left, top, right.saturating_sub(left), bottom.saturating_sub(top))
+    }
+
+    pub fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    pub fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Whether the pixel at `x`, `y` lies inside of the rectangle
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+    }
+
+    /// The pixels that lie in both rectangles, unless there are none. Rectangles that only touch don't intersect.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let (left, top) = (self.x.max(other.x), self.y.max(other.y));
+        let rect = Self::between(left, top, self.right().min(other.right()), self.bottom().min(other.bottom()));
+        (!rect.is_empty()).then_some(rect)
+    }
+
+    /// The smallest rectangle containing both rectangles. Empty rectangles don't contain anything, so they are ignored.
+    pub fn union(&self, other: &Self) -> Self {
+        match (self.is_empty(), other.is_empty()) {
+            (true, true) => Self::default(),
+            (false, true) => *self,
+            (true, false) => *other,
+            (false, false) => {
+                let (left, top) = (self.x.min(other.x), self.y.min(other.y));
+                Self::between(left, top, self.right().max(other.right()), self.bottom().max(other.bottom()))
+            }
+        }
+    }
+
+    /// The part of the rectangle inside of an image of the given size. It is empty if the rectangle lies outside of
+    /// the image, but starts inside of it.
+    pub fn clamped(&self, (width, height): (u32, u32)) -> Self {
+        let (left, top) = (self.x.min(width), self.y.min(height));
+        Self::between(left, top, self.right().min(width), self.bottom().min(height))
+    }
+}
+
+/// A straight line segment, in pixels of the image it was found in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Line {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+}
+
+/// A position, in pixels of the image it was found in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// The outline of a region as a closed polygon, in pixels of the image it was found in. The last point connects back
+/// to the first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contour {
+    pub points: Vec<(f32, f32)>,
+}
+
+impl Element for Vec<Line> {
+    const NAME: &'static str = "Lines";
+}
+
+impl Element for Vec<Point> {
+    const NAME: &'static str = "Points";
+}
+
+impl Element for Vec<Contour> {
+    const NAME: &'static str = "Contours";
+}
+
+impl Element for Vec<Rect> {
+    const NAME: &'static str = "Rects";
+}
+
+/// Shapes to draw over an image, which is how geometry elements are shown
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Geometry {
+    pub lines: Vec<((f32, f32), (f32, f32))>,
+    pub points: Vec<(f32, f32)>,
+    pub polygons: Vec<Vec<(f32, f32)>>, // Closed
+}
+
+impl Geometry {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.points.is_empty() && self.polygons.is_empty()
+    }
+
+    /// The same shapes with every position moved by `position`, e.g. onto the screen
+    pub fn map(&self, position: impl Fn((f32, f32)) -> (f32, f32)) -> Self {
+        Self {
+            lines: self.lines.iter().map(|&(start, end)| (position(start), position(end))).collect(),
+            points: self.points.iter().copied().map(&position).collect(),
+            polygons: self.polygons.iter().map(|polygon| polygon.iter().copied().map(&position).collect()).collect(),
+        }
+    }
+
+    /// Size of the smallest image at the origin that contains all shapes, at least a pixel
+    pub fn extent(&self) -> (f32, f32) {
+        let lines = self.lines.iter().flat_map(|&(start, end)| [start, end]);
+        let polygons = self.polygons.iter().flatten().copied();
+        let points = lines.chain(polygons).chain(self.points.iter().copied());
+        let (width, height) = points.fold((1.0_f32, 1.0_f32), |(width, height), (x, y)| (width.max(x), height.max(y)));
+        (width.ceil(), height.ceil())
+    }
+}
+
+/// Whether `name` is that of a geometry element, which is shown on top of the image it was found in
+pub fn is_geometry(name: &str) -> bool {
+    [Vec::<Line>::NAME, Vec::<Point>::NAME, Vec::<Contour>::NAME, Vec::<Rect>::NAME].contains(&name)
+}
+
+/// The shapes of a known geometry element, if `element` is one
+pub fn geometry(element: &dyn std::any::Any) -> Option<Geometry> {
+    let mut geometry = Geometry::default();
+    if let Some(lines) = element.downcast_ref::<Vec<Line>>() {
+        geometry.lines = lines.iter().map(|line| (line.start, line.end)).collect();
+    } else if let Some(points) = element.downcast_ref::<Vec<Point>>() {
+        geometry.points = points.iter().map(|point| (point.x, point.y)).collect();
+    } else if let Some(rects) = element.downcast_ref::<Vec<Rect>>() {
+        geometry.polygons = rects
+            .iter()
+            .map(|rect| {
+                let (left, top) = (rect.x as f32, rect.y as f32);
+                let (right, bottom) = (rect.right() as f32, rect.bottom() as f32);
+                vec![(left, top), (right, top), (right, bottom), (left, bottom)]
+            })
+            .collect();
+    } else {
+        let contours = element.downcast_ref::<Vec<Contour>>()?;
+        geometry.polygons = contours.iter().map(|contour| contour.points.clone()).collect();
+    }
+    Some(geometry)
+}
+
+/// An image whose pixels are either set or not. Pixels are stored row by row, and addressed by `(x, y)`, i.e. column
+/// first, like in `image`.
+#[derive(Clone, PartialEq, Eq)]
 pub struct BinaryImage {
     width: u32,
     height: u32,
@@ -22,4 +274,918 @@ impl BinaryImage {
     pub fn data(&self) -> &Vec<bool> {
         &self.data
     }
-}
\ No newline at end of file
+
+    /// Reads a binary PBM file (P4). PBM calls set bits black, but set pixels are shown in white, so they are read from
+    /// unset bits to keep them looking the same.
+    pub fn open_pbm(path: &std::path::Path) -> crate::error::Result<Self> {
+        use crate::error::Context;
+
+        let bytes = std::fs::read(path).map_err(|source| crate::error::KlexError::Io { path: path.into(), source })?;
+        Self::from_pbm(&bytes).context(format!("Failed to read PBM file {:?}", path))
+    }
+
+    fn from_pbm(bytes: &[u8]) -> crate::error::Result<Self> {
+        use crate::error::Context;
+
+        crate::error::ensure!(bytes.starts_with(b"P4"), "Only binary PBM files are supported");
+        // The header consists of whitespace separated fields, with comments running to the end of a line, and ends
+        // with a single whitespace after the height
+        let mut position = 2;
+        let mut field = || {
+            loop {
+                match bytes.get(position) {
+                    Some(b'#') => position += bytes[position..].iter().position(|&byte| byte == b'\n')?,
+                    Some(byte) if byte.is_ascii_whitespace() => position += 1,
+                    _ => break,
+                }
+            }
+            let digits = bytes.get(position..)?.iter().take_while(|byte| byte.is_ascii_digit()).count();
+            let value = std::str::from_utf8(&bytes[position..position + digits]).ok()?.parse::<u32>().ok();
+            position += digits + 1;
+            value
+        };
+        let (width, height) = field().zip(field()).context("PBM header is malformed")?;
+        let pixels = bytes.get(position.min(bytes.len())..).unwrap_or_default();
+        let row_bytes = (width as usize).div_ceil(8);
+        crate::error::ensure!(pixels.len() >= row_bytes * height as usize, "PBM file is missing pixels");
+        let data = (0..height as usize)
+            .flat_map(|y| (0..width as usize).map(move |x| (x, y)))
+            .map(|(x, y)| pixels[y * row_bytes + x / 8] & (0x80 >> (x % 8)) == 0)
+            .collect();
+        Ok(Self::new(width, height, data))
+    }
+
+    /// Writes a binary PBM file (P4), see `open_pbm`
+    pub fn save_pbm(&self, path: &std::path::Path) -> crate::error::Result<()> {
+        let mut bytes = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        for row in self.rows() {
+            bytes.extend(row.chunks(8).map(|pixels| {
+                pixels.iter().enumerate().filter(|(_, &set)| !set).fold(0_u8, |byte, (x, _)| byte | 0x80 >> x)
+            }));
+        }
+        std::fs::write(path, bytes).map_err(|source| crate::error::KlexError::Io { path: path.into(), source })
+    }
+
+    /// The pixels row by row, for editing them in place
+    pub fn data_mut(&mut self) -> &mut [bool] {
+        &mut self.data
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| y as usize * self.width as usize + x as usize)
+    }
+
+    /// The pixel in column `x` of row `y`, if that's inside of the image
+    pub fn get(&self, x: u32, y: u32) -> Option<&bool> {
+        self.index(x, y).map(|index| &self.data[index])
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32) -> Option<&mut bool> {
+        self.index(x, y).map(move |index| &mut self.data[index])
+    }
+
+    /// The rows of the image from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[bool]> {
+        let width = self.width as usize;
+        (0..self.height as usize).map(move |y| &self.data[y * width..(y + 1) * width])
+    }
+
+    /// The pixels together with their coordinates, row by row
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut bool)> {
+        let width = self.width.max(1);
+        self.data.iter_mut().enumerate().map(move |(index, pixel)| {
+            let index = index as u32;
+            (index % width, index / width, pixel)
+        })
+    }
+
+    /// Sets the pixels covered by a stroke, or clears them if it erases. Parts of the stroke outside of the image are
+    /// ignored.
+    pub fn paint(&mut self, stroke: &Stroke) {
+        let segments = stroke.points.windows(2).map(|pair| (pair[0], pair[1]));
+        let dots = stroke.points.first().filter(|_| stroke.points.len() == 1).map(|&point| (point, point));
+        for (start, end) in segments.chain(dots) {
+            let pixels = |from: f32, to: f32, extent: u32| {
+                let first = (from.min(to) - stroke.radius).floor().max(0.0) as u32;
+                let last = ((from.max(to) + stroke.radius).ceil().max(0.0) as u32).min(extent);
+                first..last
+            };
+            for y in pixels(start.1, end.1, self.height) {
+                for x in pixels(start.0, end.0, self.width) {
+                    // Pixels are covered if their center is close enough to the segment
+                    let center = (x as f32 + 0.5, y as f32 + 0.5);
+                    if distance_to_segment(center, start, end) <= stroke.radius {
+                        self[(x, y)] = !stroke.erase;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Summarizes the pixels instead of listing all of them, since images easily have millions
+impl std::fmt::Debug for BinaryImage {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("BinaryImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("set", &self.data.iter().filter(|&&pixel| pixel).count())
+            .finish()
+    }
+}
+
+/// Indexes by `(x, y)`, panicking outside of the image
+impl std::ops::Index<(u32, u32)> for BinaryImage {
+    type Output = bool;
+
+    fn index(&self, (x, y): (u32, u32)) -> &bool {
+        let (width, height) = (self.width, self.height);
+        self.get(x, y)
+            .unwrap_or_else(|| panic!("Pixel ({}, {}) is outside of the {}x{} image", x, y, width, height))
+    }
+}
+
+impl std::ops::IndexMut<(u32, u32)> for BinaryImage {
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut bool {
+        let (width, height) = (self.width, self.height);
+        self.get_mut(x, y)
+            .unwrap_or_else(|| panic!("Pixel ({}, {}) is outside of the {}x{} image", x, y, width, height))
+    }
+}
+
+/// A brush stroke, or a part of one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stroke {
+    pub points: Vec<(f32, f32)>, // In pixels. The brush moves along straight lines between them
+    pub radius: f32,
+    pub erase: bool,
+}
+
+fn distance_to_segment(point: (f32, f32), start: (f32, f32), end: (f32, f32)) -> f32 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = dx * dx + dy * dy;
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length).clamp(0.0, 1.0)
+    };
+    let closest = (start.0 + t * dx, start.1 + t * dy);
+    (point.0 - closest.0).hypot(point.1 - closest.1)
+}
+
+/// Number of bytes occupied by the pixel data of a known image element, or by the shapes of a geometry element, if
+/// `element` is one
+pub fn size_bytes(element: &dyn std::any::Any) -> Option<usize> {
+    use std::mem::size_of;
+
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(image.as_raw().len())
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(image.as_raw().len())
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(image.as_raw().len() * size_of::<u16>())
+    } else if let Some(image) = element.downcast_ref::<GrayImageF32>() {
+        Some(image.as_raw().len() * size_of::<f32>())
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        Some(image.as_raw().len() * size_of::<u32>())
+    } else if let Some(lines) = element.downcast_ref::<Vec<Line>>() {
+        Some(lines.len() * size_of::<Line>())
+    } else if let Some(points) = element.downcast_ref::<Vec<Point>>() {
+        Some(points.len() * size_of::<Point>())
+    } else if let Some(contours) = element.downcast_ref::<Vec<Contour>>() {
+        Some(contours.iter().map(|contour| contour.points.len() * size_of::<(f32, f32)>()).sum())
+    } else if let Some(rects) = element.downcast_ref::<Vec<Rect>>() {
+        Some(rects.len() * size_of::<Rect>())
+    } else {
+        element
+            .downcast_ref::<BinaryImage>()
+            .map(|image| image.data().len() * size_of::<bool>())
+    }
+}
+
+/// A hash of the kind, size and pixels of a known image element, if `element` is one. It stays the same across runs
+/// of the program, see `util::StableHasher`.
+pub fn content_hash(element: &dyn std::any::Any) -> Option<u64> {
+    use std::hash::Hasher;
+
+    let mut hasher = crate::util::StableHasher::new();
+    hasher.write(element_name(element)?.as_bytes());
+    let (width, height) = dimensions(element)?;
+    hasher.write_u32(width);
+    hasher.write_u32(height);
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        hasher.write(image.as_raw());
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        hasher.write(image.as_raw());
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        image.as_raw().iter().for_each(|&value| hasher.write_u16(value));
+    } else if let Some(image) = element.downcast_ref::<GrayImageF32>() {
+        image.as_raw().iter().for_each(|value| hasher.write_u32(value.to_bits()));
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        image.as_raw().iter().for_each(|&label| hasher.write_u32(label));
+    } else {
+        let image = element.downcast_ref::<BinaryImage>()?;
+        image.data().iter().for_each(|&pixel| hasher.write_u8(pixel.into()));
+    }
+    Some(hasher.finish())
+}
+
+/// Name of a known element, if `element` is one
+pub fn element_name(element: &dyn std::any::Any) -> Option<&'static str> {
+    if element.is::<image::RgbaImage>() {
+        Some(image::RgbaImage::NAME)
+    } else if element.is::<image::GrayImage>() {
+        Some(image::GrayImage::NAME)
+    } else if element.is::<Gray16Image>() {
+        Some(Gray16Image::NAME)
+    } else if element.is::<GrayImageF32>() {
+        Some(GrayImageF32::NAME)
+    } else if element.is::<LabelImage>() {
+        Some(LabelImage::NAME)
+    } else if element.is::<BinaryImage>() {
+        Some(BinaryImage::NAME)
+    } else if element.is::<Vec<Line>>() {
+        Some(Vec::<Line>::NAME)
+    } else if element.is::<Vec<Point>>() {
+        Some(Vec::<Point>::NAME)
+    } else if element.is::<Vec<Contour>>() {
+        Some(Vec::<Contour>::NAME)
+    } else if element.is::<Vec<Rect>>() {
+        Some(Vec::<Rect>::NAME)
+    } else if element.is::<Histogram>() {
+        Some(Histogram::NAME)
+    } else if element.is::<DataFile>() {
+        Some(DataFile::NAME)
+    } else {
+        #[cfg(feature = "svg")]
+        if element.is::<crate::svg::Svg>() {
+            return Some(crate::svg::Svg::NAME);
+        }
+        None
+    }
+}
+
+/// A known image element as an image of the `image` crate, which can encode it. Binary images become gray images.
+pub(crate) fn to_dynamic(element: &dyn std::any::Any) -> crate::error::Result<image::DynamicImage> {
+    use image::DynamicImage;
+
+    Ok(if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        DynamicImage::ImageRgba8(image.clone())
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        DynamicImage::ImageLuma8(image.clone())
+    } else if let Some(image) = element.downcast_ref::<image::GrayAlphaImage>() {
+        DynamicImage::ImageLumaA8(image.clone())
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        DynamicImage::ImageLuma16(image.clone())
+    } else if let Some(image) = element.downcast_ref::<BinaryImage>() {
+        DynamicImage::ImageLuma8(image.try_into()?)
+    } else {
+        crate::error::bail!("Output cannot be saved as an image");
+    })
+}
+
+/// The binary image to save natively, if `element` is one and `path` is that of a PBM file
+fn as_pbm<'a>(element: &'a dyn std::any::Any, path: &std::path::Path) -> Option<&'a BinaryImage> {
+    let extension = path.extension()?.to_str()?;
+    element.downcast_ref::<BinaryImage>().filter(|_| extension.eq_ignore_ascii_case("pbm"))
+}
+
+/// Writes a known image element to a file, in the format given by the file extension. Binary images are written to
+/// PBM files as they are, and as gray images otherwise.
+pub fn save(element: &dyn std::any::Any, path: &std::path::Path) -> crate::error::Result<()> {
+    if let Some(file) = element.downcast_ref::<DataFile>() {
+        return file.save(path);
+    }
+    #[cfg(feature = "svg")]
+    if let Some(svg) = element.downcast_ref::<crate::svg::Svg>() {
+        return svg.save(path);
+    }
+    match as_pbm(element, path) {
+        Some(image) => image.save_pbm(path),
+        None => Ok(to_dynamic(element)?.save(path)?),
+    }
+}
+
+/// Writes a known image element to a PNG, JPEG, BMP or TIFF file, depending on the file extension, or a binary image
+/// to a PBM file. Images are converted to a color type the format supports, e.g. JPEGs lose their alpha channel.
+/// `quality` ranges from 1 to 100 and only matters for JPEGs.
+pub fn export(element: &dyn std::any::Any, path: &std::path::Path, quality: u8) -> crate::error::Result<()> {
+    ExportJob::new(element, path, quality)?.write()
+}
+
+/// A copy of an image element to export, see `export`. Creating it checks the format, while encoding the image and
+/// writing it, which can take seconds for large PNGs, is left to `write`, e.g. on another thread.
+#[derive(Clone, Debug)]
+pub struct ExportJob {
+    image: Exportable,
+    path: std::path::PathBuf,
+    quality: u8,
+}
+
+#[derive(Clone, Debug)]
+enum Exportable {
+    Pbm(BinaryImage),
+    Image(image::DynamicImage, image::ImageFormat),
+}
+
+impl ExportJob {
+    pub fn new(element: &dyn std::any::Any, path: &std::path::Path, quality: u8) -> crate::error::Result<Self> {
+        use crate::error::Context;
+        use image::ImageFormat;
+
+        let image = match as_pbm(element, path) {
+            Some(image) => Exportable::Pbm(image.clone()),
+            None => {
+                let image = to_dynamic(element).context("Output cannot be exported as an image")?;
+                match ImageFormat::from_path(path)? {
+                    format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Bmp | ImageFormat::Tiff) => {
+                        Exportable::Image(image, format)
+                    }
+                    format => crate::error::bail!("Can't export to {:?}, only to PNG, JPEG, BMP and TIFF", format),
+                }
+            }
+        };
+        let path = path.to_path_buf();
+        Ok(Self { image, path, quality })
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn write(&self) -> crate::error::Result<()> {
+        use std::io::Write;
+
+        use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat};
+
+        let path = &self.path;
+        let (image, format) = match &self.image {
+            Exportable::Pbm(image) => return image.save_pbm(path),
+            Exportable::Image(image, format) => (image, *format),
+        };
+        match (format, image) {
+            (ImageFormat::Jpeg, image) => {
+                let converted = match image {
+                    DynamicImage::ImageLuma8(_) => None,
+                    DynamicImage::ImageLumaA8(_) => Some(DynamicImage::ImageLuma8(image.to_luma8())),
+                    DynamicImage::ImageLuma16(image) => Some(DynamicImage::ImageLuma8(to_gray8(image))),
+                    _ => Some(DynamicImage::ImageRgb8(image.to_rgb8())),
+                };
+                let io = |source| crate::error::KlexError::Io { path: path.into(), source };
+                let mut file = std::io::BufWriter::new(std::fs::File::create(path).map_err(io)?);
+                let mut encoder = JpegEncoder::new_with_quality(&mut file, self.quality.clamp(1, 100));
+                encoder.encode_image(converted.as_ref().unwrap_or(image))?;
+                file.flush().map_err(io)?;
+            }
+            // TIFF has no gray images with alpha channel
+            (ImageFormat::Tiff, image @ DynamicImage::ImageLumaA8(_)) => image.to_rgba8().save(path)?,
+            // BMP has no 16 bit images, while PNG and TIFF keep all of them
+            (ImageFormat::Bmp, DynamicImage::ImageLuma16(image)) => to_gray8(image).save(path)?,
+            (_, image) => image.save(path)?,
+        }
+        Ok(())
+    }
+}
+
+/// Set pixels become white and unset ones black. The pixels are copied, since they are stored as one `bool` each.
+impl TryFrom<&BinaryImage> for image::GrayImage {
+    type Error = crate::error::KlexError;
+
+    fn try_from(image: &BinaryImage) -> crate::error::Result<Self> {
+        use crate::error::Context;
+
+        let data = image.data().iter().map(|&pixel| if pixel { u8::MAX } else { u8::MIN }).collect();
+        Self::from_vec(image.width(), image.height(), data).context("Data cannot be converted to GrayImage")
+    }
+}
+
+/// Takes a gray image back that only consists of black and white pixels, like the ones binary images are converted to
+impl TryFrom<&image::GrayImage> for BinaryImage {
+    type Error = crate::error::KlexError;
+
+    fn try_from(image: &image::GrayImage) -> crate::error::Result<Self> {
+        let data = image.as_raw().iter().map(|&value| match value {
+            u8::MAX => Ok(true),
+            u8::MIN => Ok(false),
+            _ => Err(format!("Gray image has the value {}, which is neither black nor white", value).into()),
+        });
+        Ok(Self::new(image.width(), image.height(), data.collect::<crate::error::Result<_>>()?))
+    }
+}
+
+/// Turns an image of the `image` crate into an element. RGBA, gray, 16 bit gray and gray images with alpha channel keep
+/// their pixel buffer, without copying it. Other color types are converted to RGBA, which copies them.
+pub fn from_dynamic(image: image::DynamicImage) -> Box<dyn std::any::Any + Send + Sync> {
+    use image::DynamicImage;
+
+    match image {
+        DynamicImage::ImageLuma8(image) => Box::new(image),
+        DynamicImage::ImageLumaA8(image) => Box::new(image),
+        DynamicImage::ImageLuma16(image) => Box::new(image),
+        image => Box::new(image.into_rgba8()), // Without a copy if it is RGBA already
+    }
+}
+
+/// A single channel of an RGBA image, which borrows the pixels of the image instead of copying them. It can be
+/// processed like a gray image, e.g. with `image::imageops`.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane<'a> {
+    samples: &'a [u8], // Of all channels, starting with this one
+    width: u32,
+    height: u32,
+}
+
+impl<'a> Plane<'a> {
+    /// The values of the channel, row by row
+    pub fn values(&self) -> impl Iterator<Item = u8> + 'a {
+        self.samples.iter().step_by(4).copied()
+    }
+}
+
+impl image::GenericImageView for Plane<'_> {
+    type Pixel = image::Luma<u8>;
+    type InnerImageView = Self;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        (0, 0, self.width, self.height)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Self::Pixel {
+        assert!(self.in_bounds(x, y), "Pixel ({}, {}) is outside of the plane", x, y);
+        image::Luma([self.samples[(y as usize * self.width as usize + x as usize) * 4]])
+    }
+
+    fn inner(&self) -> &Self::InnerImageView {
+        self
+    }
+}
+
+/// The red, green, blue and alpha channels of an image
+pub fn planes(image: &image::RgbaImage) -> [Plane<'_>; 4] {
+    let (width, height) = image.dimensions();
+    [0, 1, 2, 3].map(|channel| Plane {
+        samples: image.as_raw().get(channel..).unwrap_or_default(),
+        width,
+        height,
+    })
+}
+
+/// Puts an image together from its red, green, blue and alpha channels, which all have to be of the same size
+pub fn from_planes<P>(planes: [&P; 4]) -> crate::error::Result<image::RgbaImage>
+where
+    P: image::GenericImageView<Pixel = image::Luma<u8>>,
+{
+    let size = planes[0].dimensions();
+    if let Some(plane) = planes.iter().find(|plane| plane.dimensions() != size) {
+        let found = plane.dimensions();
+        return Err(crate::error::KlexError::ShapeMismatch { expected: size, found });
+    }
+    Ok(image::RgbaImage::from_fn(size.0, size.1, |x, y| {
+        image::Rgba(planes.map(|plane| plane.get_pixel(x, y).0[0]))
+    }))
+}
+
+/// Converts a known image element to RGBA, e.g. for displaying it
+pub fn to_rgba(element: &dyn std::any::Any) -> Option<image::RgbaImage> {
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(image.clone())
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(image::DynamicImage::ImageLuma8(image.clone()).into_rgba8())
+    } else if let Some(image) = element.downcast_ref::<image::GrayAlphaImage>() {
+        Some(image::DynamicImage::ImageLumaA8(image.clone()).into_rgba8())
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(image::DynamicImage::ImageLuma8(to_gray8(image)).into_rgba8())
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        Some(image::RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+            image::Rgba(label_color(image.get_pixel(x, y)[0]))
+        }))
+    } else {
+        let image = element.downcast_ref::<BinaryImage>()?;
+        let data = image
+            .data()
+            .iter()
+            .flat_map(|&pixel| if pixel { [u8::MAX; 4] } else { [u8::MIN, u8::MIN, u8::MIN, u8::MAX] })
+            .collect();
+        image::RgbaImage::from_vec(image.width(), image.height(), data)
+    }
+}
+
+/// Width and height of a known image element, if `element` is one
+pub fn dimensions(element: &dyn std::any::Any) -> Option<(u32, u32)> {
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(image.dimensions())
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(image.dimensions())
+    } else if let Some(image) = element.downcast_ref::<image::GrayAlphaImage>() {
+        Some(image.dimensions())
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(image.dimensions())
+    } else if let Some(image) = element.downcast_ref::<GrayImageF32>() {
+        Some(image.dimensions())
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        Some(image.dimensions())
+    } else {
+        element
+            .downcast_ref::<BinaryImage>()
+            .map(|image| (image.width(), image.height()))
+    }
+}
+
+/// Copies a rectangle out of a known image element, if `element` is one. The rectangle is clamped to the image.
+pub fn crop(element: &dyn std::any::Any, rect: Rect) -> Option<Box<dyn std::any::Any + Send + Sync>> {
+    use image::imageops;
+
+    let rect = rect.clamped(dimensions(element)?);
+    let Rect { x, y, width, height } = rect;
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(Box::new(imageops::crop_imm(image, x, y, width, height).to_image()))
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(Box::new(imageops::crop_imm(image, x, y, width, height).to_image()))
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(Box::new(imageops::crop_imm(image, x, y, width, height).to_image()))
+    } else if let Some(image) = element.downcast_ref::<GrayImageF32>() {
+        Some(Box::new(imageops::crop_imm(image, x, y, width, height).to_image()))
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        Some(Box::new(imageops::crop_imm(image, x, y, width, height).to_image()))
+    } else {
+        let image = element.downcast_ref::<BinaryImage>()?;
+        let data = image.rows().skip(y as usize).take(height as usize);
+        let data = data.flat_map(|row| &row[x as usize..(x + width) as usize]).copied().collect();
+        Some(Box::new(BinaryImage::new(width, height, data)))
+    }
+}
+
+/// An image of the same kind of known image element as `like`, of the given size, with all pixels zero
+pub fn blank(like: &dyn std::any::Any, width: u32, height: u32) -> Option<Box<dyn std::any::Any + Send + Sync>> {
+    if like.is::<image::RgbaImage>() {
+        Some(Box::new(image::RgbaImage::new(width, height)))
+    } else if like.is::<image::GrayImage>() {
+        Some(Box::new(image::GrayImage::new(width, height)))
+    } else if like.is::<Gray16Image>() {
+        Some(Box::new(Gray16Image::new(width, height)))
+    } else if like.is::<GrayImageF32>() {
+        Some(Box::new(GrayImageF32::new(width, height)))
+    } else if like.is::<LabelImage>() {
+        Some(Box::new(LabelImage::new(width, height)))
+    } else if like.is::<BinaryImage>() {
+        Some(Box::new(BinaryImage::new(width, height, vec![false; width as usize * height as usize])))
+    } else {
+        None
+    }
+}
+
+/// Copies the pixels of `patch` onto `target` with its top left corner at `x`, `y`. Both have to be the same kind of
+/// known image element. Parts of `patch` that don't fit onto `target` are left out.
+pub fn paste(target: &mut dyn std::any::Any, patch: &dyn std::any::Any, x: u32, y: u32) -> crate::error::Result<()> {
+    use crate::error::Context;
+    use image::imageops;
+
+    let expected = element_name(target).context("Can only paste onto image elements")?;
+    let found = element_name(patch).unwrap_or("an unknown element");
+    let mismatch = || crate::error::KlexError::TypeMismatch { expected, found };
+    if let Some(target) = target.downcast_mut::<image::RgbaImage>() {
+        imageops::replace(target, patch.downcast_ref::<image::RgbaImage>().ok_or_else(mismatch)?, x, y);
+    } else if let Some(target) = target.downcast_mut::<image::GrayImage>() {
+        imageops::replace(target, patch.downcast_ref::<image::GrayImage>().ok_or_else(mismatch)?, x, y);
+    } else if let Some(target) = target.downcast_mut::<Gray16Image>() {
+        imageops::replace(target, patch.downcast_ref::<Gray16Image>().ok_or_else(mismatch)?, x, y);
+    } else if let Some(target) = target.downcast_mut::<GrayImageF32>() {
+        imageops::replace(target, patch.downcast_ref::<GrayImageF32>().ok_or_else(mismatch)?, x, y);
+    } else if let Some(target) = target.downcast_mut::<LabelImage>() {
+        imageops::replace(target, patch.downcast_ref::<LabelImage>().ok_or_else(mismatch)?, x, y);
+    } else if let Some(target) = target.downcast_mut::<BinaryImage>() {
+        let patch = patch.downcast_ref::<BinaryImage>().ok_or_else(mismatch)?;
+        for (row, pixels) in patch.rows().enumerate() {
+            for (column, &pixel) in pixels.iter().enumerate() {
+                if let Some(target) = target.get_mut(x.saturating_add(column as u32), y.saturating_add(row as u32)) {
+                    *target = pixel;
+                }
+            }
+        }
+    } else {
+        crate::error::bail!("Can't paste onto {}", expected);
+    }
+    Ok(())
+}
+
+/// Scales a known image element by `factor`, e.g. for computing a preview of a large image. Sizes are rounded, but
+/// never drop below one pixel.
+pub fn resize(element: &dyn std::any::Any, factor: f64) -> Option<Box<dyn std::any::Any + Send + Sync>> {
+    use image::imageops::{self, FilterType};
+
+    let (width, height) = dimensions(element)?;
+    let scale = |size: u32| ((f64::from(size) * factor).round() as u32).max(1);
+    let (new_width, new_height) = (scale(width), scale(height));
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(Box::new(imageops::resize(image, new_width, new_height, FilterType::Triangle)))
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(Box::new(imageops::resize(image, new_width, new_height, FilterType::Triangle)))
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(Box::new(imageops::resize(image, new_width, new_height, FilterType::Triangle)))
+    } else if let Some(image) = element.downcast_ref::<GrayImageF32>() {
+        Some(Box::new(imageops::resize(image, new_width, new_height, FilterType::Triangle)))
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        // Averaging labels would make up ones that aren't there
+        Some(Box::new(imageops::resize(image, new_width, new_height, FilterType::Nearest)))
+    } else {
+        // Nearest neighbour, since averaging doesn't make sense for binary pixels
+        let image = element.downcast_ref::<BinaryImage>()?;
+        let data = (0..new_height)
+            .flat_map(|y| (0..new_width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let source_x = (u64::from(x) * u64::from(width) / u64::from(new_width)) as u32;
+                let source_y = (u64::from(y) * u64::from(height) / u64::from(new_height)) as u32;
+                image[(source_x, source_y)]
+            })
+            .collect();
+        Some(Box::new(BinaryImage::new(new_width, new_height, data)))
+    }
+}
+
+/// A small RGBA copy of a known element that fits into a square of `size` pixels, e.g. to see what a layer produced
+/// at a glance. Images are scaled down first and then converted for display, while shapes are drawn as a miniature
+/// plot of their extent.
+pub fn thumbnail(element: &dyn std::any::Any, size: u32) -> Option<image::RgbaImage> {
+    use crate::color::ConversionGraph;
+
+    if let Some(geometry) = geometry(element) {
+        return Some(plot(&geometry, size));
+    }
+    let (width, height) = dimensions(element)?;
+    let factor = f64::from(size) / f64::from(width.max(height).max(1));
+    let resized = if factor < 1.0 { resize(element, factor) } else { None };
+    let image: &dyn std::any::Any = match &resized {
+        Some(resized) => &**resized,
+        None => element,
+    };
+    if let Some(image) = image.downcast_ref::<image::RgbaImage>() {
+        return Some(image.clone());
+    }
+    let converted = ConversionGraph::builtins().convert(image, image::RgbaImage::NAME).ok()?;
+    converted.downcast().ok().map(|image| *image)
+}
+
+/// Draws shapes into a square image of `size` pixels, scaled to fit the area they cover
+fn plot(geometry: &Geometry, size: u32) -> image::RgbaImage {
+    const MARGIN: f32 = 2.0;
+
+    let polygons = geometry.polygons.iter().map(|polygon| polygon.iter().chain(polygon.first()).copied().collect());
+    let lines = geometry.lines.iter().map(|&(start, end)| vec![start, end]);
+    let points = geometry.points.iter().map(|&point| vec![point]);
+    let shapes: Vec<Vec<_>> = polygons.chain(lines).chain(points).collect();
+    let coordinates = || shapes.iter().flatten();
+    let (min_x, max_x) = coordinates().fold((f32::MAX, f32::MIN), |(min, max), &(x, _)| (min.min(x), max.max(x)));
+    let (min_y, max_y) = coordinates().fold((f32::MAX, f32::MIN), |(min, max), &(_, y)| (min.min(y), max.max(y)));
+    let extent = (max_x - min_x).max(max_y - min_y).max(1.0);
+    let scale = (size as f32 - 2.0 * MARGIN).max(1.0) / extent;
+    let mut mask = BinaryImage::new(size, size, vec![false; size as usize * size as usize]);
+    for shape in shapes {
+        let points = shape.iter().map(|&(x, y)| (MARGIN + (x - min_x) * scale, MARGIN + (y - min_y) * scale));
+        let radius = if shape.len() == 1 { 1.5 } else { 0.75 }; // Points are drawn as dots
+        mask.paint(&Stroke { points: points.collect(), radius, erase: false });
+    }
+    let (ink, paper) = (image::Rgba([40, 90, 200, 255]), image::Rgba([245, 245, 245, 255]));
+    image::RgbaImage::from_fn(size, size, |x, y| if mask[(x, y)] { ink } else { paper })
+}
+
+/// The value of a single pixel of a known image element
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelValue {
+    Rgba([u8; 4]),
+    Gray(u8),
+    Gray16(u16),
+    Binary(bool),
+    Label(u32),
+}
+
+impl std::fmt::Display for PixelValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PixelValue::Rgba([red, green, blue, alpha]) => write!(f, "R {} G {} B {} A {}", red, green, blue, alpha),
+            PixelValue::Gray(value) => write!(f, "{}", value),
+            PixelValue::Gray16(value) => write!(f, "{}", value),
+            PixelValue::Binary(value) => write!(f, "{}", value),
+            PixelValue::Label(label) => write!(f, "Label {}", label),
+        }
+    }
+}
+
+impl PixelValue {
+    /// The value as an opaque color, unless it has an alpha channel
+    pub fn to_rgba(&self) -> [u8; 4] {
+        match *self {
+            PixelValue::Rgba(rgba) => rgba,
+            PixelValue::Gray(value) => [value, value, value, u8::MAX],
+            PixelValue::Gray16(value) => {
+                let value = value.to_u8();
+                [value, value, value, u8::MAX]
+            }
+            PixelValue::Binary(value) => {
+                let value = if value { u8::MAX } else { u8::MIN };
+                [value, value, value, u8::MAX]
+            }
+            PixelValue::Label(label) => label_color(label),
+        }
+    }
+}
+
+/// The pixel at `x`, `y` of a known image element, if `element` is one and the pixel is inside of it
+pub fn pixel(element: &dyn std::any::Any, x: u32, y: u32) -> Option<PixelValue> {
+    let (width, height) = dimensions(element)?;
+    if x >= width || y >= height {
+        return None;
+    }
+    if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        Some(PixelValue::Rgba(image.get_pixel(x, y).0))
+    } else if let Some(image) = element.downcast_ref::<image::GrayImage>() {
+        Some(PixelValue::Gray(image.get_pixel(x, y).0[0]))
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(PixelValue::Gray16(image.get_pixel(x, y).0[0]))
+    } else if let Some(image) = element.downcast_ref::<LabelImage>() {
+        Some(PixelValue::Label(image.get_pixel(x, y).0[0]))
+    } else {
+        let image = element.downcast_ref::<BinaryImage>()?;
+        Some(PixelValue::Binary(image[(x, y)]))
+    }
+}
+
+/// Number of pixels with each value, per channel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    pub channels: Vec<[u32; 256]>, // A single one for gray images, red, green and blue for RGBA images
+}
+
+impl Element for Histogram {
+    const NAME: &'static str = "Histogram";
+}
+
+impl Histogram {
+    /// The largest count of any value in any channel
+    pub fn max(&self) -> u32 {
+        self.channels.iter().flatten().copied().max().unwrap_or(0)
+    }
+}
+
+/// Counts the values of a gray image. Values with more than 8 bits are counted in 256 bins of equal width.
+fn gray_histogram<S: Sample>(image: &image::ImageBuffer<image::Luma<S>, Vec<S>>) -> [u32; 256] {
+    let mut channel = [0; 256];
+    for &value in image.as_raw() {
+        channel[usize::from(value.to_u8())] += 1;
+    }
+    channel
+}
+
+/// The histogram of a known gray or RGBA image element, if `element` is one
+pub fn histogram(element: &dyn std::any::Any) -> Option<Histogram> {
+    let channels = if let Some(image) = element.downcast_ref::<image::RgbaImage>() {
+        let mut channels = vec![[0; 256]; 3];
+        for pixel in image.pixels() {
+            for (channel, &value) in channels.iter_mut().zip(&pixel.0) {
+                channel[usize::from(value)] += 1;
+            }
+        }
+        channels
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        vec![gray_histogram(image)]
+    } else {
+        vec![gray_histogram(element.downcast_ref::<image::GrayImage>()?)]
+    };
+    Some(Histogram { channels })
+}
+
+/// A data element written out as rows of numbers with named columns, see `table`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl Table {
+    /// A header line with the column names, followed by a line for each row
+    pub fn to_csv(&self) -> String {
+        let mut csv = self.columns.join(",");
+        csv.push('\n');
+        for row in &self.rows {
+            let values: Vec<String> = row.iter().map(f64::to_string).collect();
+            csv.push_str(&values.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// An array with an object for each row, keyed by the column names. Values that aren't finite become `null`.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let fields: Vec<String> = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, value)| match value.is_finite() {
+                        true => format!("\"{}\": {}", column, value),
+                        false => format!("\"{}\": null", column),
+                    })
+                    .collect();
+                format!("  {{{}}}", fields.join(", "))
+            })
+            .collect();
+        format!("[\n{}\n]\n", rows.join(",\n"))
+    }
+}
+
+/// Names of the elements that `table` can write out
+pub const TABLE_ELEMENTS: [&str; 5] =
+    [Vec::<Line>::NAME, Vec::<Point>::NAME, Vec::<Contour>::NAME, Vec::<Rect>::NAME, Histogram::NAME];
+
+/// The rows of a known data element, if `element` is one: lines from `x1`, `y1` to `x2`, `y2`, points at `x`, `y`,
+/// every point of every contour along with the index of its contour, rectangles at `x`, `y` of `width` by `height`,
+/// and the count of each bin of a histogram, with a column for each channel
+pub fn table(element: &dyn std::any::Any) -> Option<Table> {
+    let point = |(x, y): (f32, f32)| [f64::from(x), f64::from(y)];
+    Some(if let Some(lines) = element.downcast_ref::<Vec<Line>>() {
+        Table {
+            columns: vec!["x1", "y1", "x2", "y2"],
+            rows: lines.iter().map(|line| [point(line.start), point(line.end)].concat()).collect(),
+        }
+    } else if let Some(points) = element.downcast_ref::<Vec<Point>>() {
+        Table {
+            columns: vec!["x", "y"],
+            rows: points.iter().map(|p| point((p.x, p.y)).to_vec()).collect(),
+        }
+    } else if let Some(contours) = element.downcast_ref::<Vec<Contour>>() {
+        let rows = contours.iter().enumerate().flat_map(|(index, contour)| {
+            contour.points.iter().map(move |&position| [&[index as f64][..], &point(position)].concat())
+        });
+        Table {
+            columns: vec!["contour", "x", "y"],
+            rows: rows.collect(),
+        }
+    } else if let Some(rects) = element.downcast_ref::<Vec<Rect>>() {
+        let row = |rect: &Rect| [rect.x, rect.y, rect.width, rect.height].map(f64::from).to_vec();
+        Table {
+            columns: vec!["x", "y", "width", "height"],
+            rows: rects.iter().map(row).collect(),
+        }
+    } else {
+        let histogram = element.downcast_ref::<Histogram>()?;
+        let columns = match histogram.channels.len() {
+            1 => vec!["bin", "count"],
+            _ => vec!["bin", "red", "green", "blue"],
+        };
+        let rows = (0..256).map(|bin| {
+            let counts = histogram.channels.iter().map(|channel| f64::from(channel[bin]));
+            std::iter::once(bin as f64).chain(counts).collect()
+        });
+        Table { columns, rows: rows.collect() }
+    })
+}
+
+/// A data element written out as text in some format, e.g. by the `ExportData` layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataFile {
+    pub format: DataFormat,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    Csv,
+    Json,
+}
+
+impl DataFormat {
+    pub const ALL: [Self; 2] = [Self::Csv, Self::Json];
+}
+
+impl Element for DataFile {
+    const NAME: &'static str = "DataFile";
+}
+
+impl DataFile {
+    /// Writes out a known data element, see `table`. Other elements fail with an error listing the known ones.
+    pub fn of(element: &dyn std::any::Any, format: DataFormat) -> crate::error::Result<Self> {
+        use crate::error::Context;
+
+        let table = table(element).with_context(|| {
+            let name = element_name(element).unwrap_or("this element");
+            format!("Can't export {} as data, only {}", name, TABLE_ELEMENTS.join(", "))
+        })?;
+        let text = match format {
+            DataFormat::Csv => table.to_csv(),
+            DataFormat::Json => table.to_json(),
+        };
+        Ok(Self { format, text })
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> crate::error::Result<()> {
+        crate::util::write_atomically(path, self.text.as_bytes())
+            .map_err(|source| crate::error::KlexError::Io { path: path.into(), source })
+    }
+}
@@ -0,0 +1,196 @@
+use std::{fmt, io, path::PathBuf};
+
+use petgraph::graph::NodeIndex;
+use thiserror::Error;
+
+use crate::util::Disconnected;
+
+/// The result of everything in the library that can fail
+pub type Result<T, E = KlexError> = std::result::Result<T, E>;
+
+/// Why something failed. Failures that callers may want to handle differently from others have variants of their own,
+/// and so do the errors of other libraries. Failures often get some context on top, so `root` is what to match on. The
+/// messages of errors wrapping others end with those of the wrapped ones.
+#[derive(Debug, Error)]
+pub enum KlexError {
+    #[error("Expected input of type {expected}, got {found}")]
+    TypeMismatch { expected: &'static str, found: &'static str },
+    #[error("Input {port} of layer {} is not connected", .node.index())]
+    MissingInput { node: NodeIndex, port: usize },
+    #[error("Layer {} has {found} inputs, but needs {expected}", .node.index())]
+    ExtraInputs { node: NodeIndex, found: usize, expected: usize },
+    #[error("Expected a size of {}x{}, got {}x{}", .expected.0, .expected.1, .found.0, .found.1)]
+    ShapeMismatch { expected: (u32, u32), found: (u32, u32) },
+    #[error("Failed to access {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Layer graph contains a cycle through layers {}", indices(.nodes))]
+    CycleDetected { nodes: Vec<NodeIndex> },
+    #[error("Unknown layer kind {0:?}")]
+    UnknownLayerKind(String),
+    #[error("Computation was cancelled")]
+    Cancelled, // A computation was stopped through its `CancelToken`
+    #[error("{0}")]
+    Panicked(String), // The message of a layer that panicked while computing its output
+    #[error("There is no layer {}", .0.index())]
+    NoSuchLayer(NodeIndex),
+    #[error("There is no node named {name:?} in {scope}")]
+    UnknownName { name: String, scope: &'static str },
+    #[error("The name {name:?} is ambiguous in {scope}")]
+    AmbiguousName { name: String, scope: &'static str },
+    #[error("Unknown parameter {0:?}")]
+    UnknownParameter(String),
+    #[error("Missing parameter {0:?}")]
+    MissingParameter(String),
+    #[error("Expected {expected}, got {found}")]
+    InvalidValue { expected: String, found: String },
+    #[error("{value} is out of range {min}..={max}")]
+    OutOfRange { value: f64, min: f64, max: f64 },
+    #[error("Path is empty")]
+    EmptyPath,
+    #[error("Placeholder {0:?} is not bound")]
+    UnboundPlaceholder(String),
+    #[error("Placeholder {0:?} is not declared in the recipe")]
+    UnknownPlaceholder(String),
+    #[error("A lock on {0} is poisoned")]
+    Poisoned(&'static str), // A thread panicked while holding it
+    #[error("Invalid argument {0:?}")]
+    InvalidArgument(&'static str), // Of the C interface, e.g. a null pointer
+    #[error("Event {position} of the log can't be replayed: {source}")]
+    ReplayFailed { position: usize, source: Box<KlexError> },
+    #[error("Failed to download an image from {url}: {source}")]
+    Download { url: String, source: Box<KlexError> },
+    #[error("The clipboard holds no image")]
+    NoClipboardImage,
+    #[error("The seed at ({x}, {y}) is outside of the image of {}x{} pixels", .size.0, .size.1)]
+    SeedOutOfBounds { x: f32, y: f32, size: (u32, u32) },
+    #[error("Input {index} is {found}, but the first one is {expected}")]
+    InputMismatch { index: usize, expected: String, found: String },
+    #[error(transparent)]
+    Disconnected(#[from] Disconnected),
+    #[error(transparent)]
+    Format(#[from] fmt::Error),
+    #[error(transparent)]
+    ParseInt(#[from] std::num::ParseIntError),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Ron(#[from] ron::Error),
+    #[error(transparent)]
+    Pattern(#[from] glob::PatternError),
+    #[error(transparent)]
+    Glob(#[from] glob::GlobError),
+    #[cfg(feature = "http")]
+    #[error(transparent)]
+    Http(Box<ureq::Error>), // Boxed, since it is large
+    #[cfg(feature = "clipboard")]
+    #[error(transparent)]
+    Clipboard(#[from] arboard::Error),
+    #[cfg(feature = "ui")]
+    #[error(transparent)]
+    Dialog(#[from] native_dialog::Error),
+    #[error("{0}")]
+    Failed(String), // Anything nobody is expected to tell apart, e.g. why a layer can't compute its output
+    #[error("{context}: {source}")]
+    Context { context: String, source: Box<KlexError> }, // What was being done when `source` happened
+}
+
+impl KlexError {
+    /// A failure that is only described by a message
+    pub fn msg(message: impl fmt::Display) -> Self {
+        Self::Failed(message.to_string())
+    }
+
+    /// A `found` value that isn't `expected`, e.g. `invalid_value("an integer", text)`
+    pub fn invalid_value(expected: impl fmt::Display, found: impl fmt::Debug) -> Self {
+        Self::InvalidValue { expected: expected.to_string(), found: format!("{:?}", found) }
+    }
+
+    /// The error below all the context that was added on top of it
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Adds what was being done when the error happened on top of it
+    pub fn context(self, context: impl fmt::Display) -> Self {
+        Self::Context { context: context.to_string(), source: Box::new(self) }
+    }
+}
+
+impl From<String> for KlexError {
+    fn from(message: String) -> Self {
+        Self::Failed(message)
+    }
+}
+
+impl From<&str> for KlexError {
+    fn from(message: &str) -> Self {
+        Self::Failed(message.to_string())
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<ureq::Error> for KlexError {
+    fn from(error: ureq::Error) -> Self {
+        Self::Http(Box::new(error))
+    }
+}
+
+/// Adds context to failures, like `KlexError::context`, and turns missing values into failures
+pub trait Context<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T>;
+
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<KlexError>> Context<T> for Result<T, E> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.map_err(|error| error.into().context(context))
+    }
+
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|error| error.into().context(context()))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.ok_or_else(|| KlexError::msg(context))
+    }
+
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| KlexError::msg(context()))
+    }
+}
+
+/// Returns a `KlexError` from the function, made from a message with format arguments or from anything that turns
+/// into one
+macro_rules! bail {
+    ($message:literal $(,)?) => {
+        return Err($crate::error::KlexError::msg(format!($message)))
+    };
+    ($error:expr $(,)?) => {
+        return Err($crate::error::KlexError::from($error))
+    };
+    ($format:expr, $($argument:tt)*) => {
+        return Err($crate::error::KlexError::msg(format!($format, $($argument)*)))
+    };
+}
+
+/// Returns a `KlexError` from the function unless the condition holds, see `bail`
+macro_rules! ensure {
+    ($condition:expr, $($error:tt)+) => {
+        if !$condition {
+            $crate::error::bail!($($error)+);
+        }
+    };
+}
+
+pub(crate) use {bail, ensure};
+
+fn indices(nodes: &[NodeIndex]) -> String {
+    let indices: Vec<_> = nodes.iter().map(|node| node.index().to_string()).collect();
+    indices.join(", ")
+}
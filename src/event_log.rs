@@ -0,0 +1,161 @@
+//! A record of the changes made to an `InteractiveLayerGraph`, e.g. to drive the graph from a script, to compare what
+//! a test harness did with what it expected or to recover a session after a crash
+
+use std::{fs, path::Path, time::SystemTime};
+
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity::{BinaryImage, Stroke},
+    error::{Context, KlexError, Result},
+    layer_graph::{InteractiveLayerGraph, LayerId, NodeLayout},
+    parameter::{ParamMap, ParamValue},
+    registry::LayerRegistry,
+    util,
+};
+
+/// A change of a graph. These are the edits that can be undone, with added layers described by their kind and
+/// parameters. Undoing or redoing an edit is logged as the changes it makes, so replaying a log doesn't depend on the
+/// history of the graph.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GraphEvent {
+    AddLayer {
+        layer: LayerId,
+        kind: String,
+        name: String,
+        parameters: ParamMap,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        canvas: Option<BinaryImage>, // Of layers that can be painted on
+    }, // Unconnected, the connections follow as events of their own
+    RemoveLayer {
+        layer: LayerId,
+    }, // Which isn't connected anymore
+    Connect {
+        from: LayerId,
+        output: usize,
+        to: LayerId,
+        port: usize,
+    },
+    Disconnect {
+        to: LayerId,
+        port: usize,
+    },
+    SetParameter {
+        layer: LayerId,
+        name: String,
+        value: ParamValue,
+    },
+    Rename {
+        layer: LayerId,
+        name: String,
+    },
+    Paint {
+        layer: LayerId,
+        stroke: Stroke,
+    },
+    SetCanvas {
+        layer: LayerId,
+        canvas: BinaryImage,
+    }, // Undoing or redoing a stroke
+    SetLayout {
+        layer: LayerId,
+        layout: NodeLayout,
+    },
+    Group {
+        layers: Vec<LayerId>,
+        composite: LayerId,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    pub time: SystemTime, // When the change was made
+    pub event: GraphEvent,
+}
+
+/// Changes of a graph in the order they were made, which `InteractiveLayerGraph::record_to` appends to. Replaying them
+/// onto an empty graph with the same layer registry gives the same graph, with the same layer ids.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventLog {
+    pub(crate) events: Vec<LoggedEvent>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> &[LoggedEvent] {
+        &self.events
+    }
+
+    /// Makes the changes of the log to `graph`, creating layers through `registry`. If an event can't be replayed,
+    /// e.g. because its layer kind isn't registered, the error is a `KlexError::ReplayFailed` with its position, and
+    /// the events before it stay applied.
+    pub fn replay_onto(&self, graph: &mut InteractiveLayerGraph, registry: &LayerRegistry) -> Result<()> {
+        for (position, logged) in self.events.iter().enumerate() {
+            apply(graph, &logged.event, registry)
+                .map_err(|e| KlexError::ReplayFailed { position, source: Box::new(e) })?;
+        }
+        Ok(())
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+            .context(format!("Failed to read event log {:?}", path))?;
+        Self::from_ron(&text).context(format!("Failed to parse event log {:?}", path))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        util::write_atomically(path, self.to_ron()?.as_bytes())
+            .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+            .context(format!("Failed to write event log {:?}", path))
+    }
+}
+
+fn apply(graph: &mut InteractiveLayerGraph, event: &GraphEvent, registry: &LayerRegistry) -> Result<()> {
+    match event {
+        GraphEvent::AddLayer { layer, kind, name, parameters, canvas } => {
+            let mut created = registry.create(kind, parameters)?;
+            if let Some(canvas) = canvas {
+                let painted = created.canvas_mut().context(format!("Layers of kind {:?} can't be painted on", kind))?;
+                *painted = canvas.clone();
+            }
+            graph.add_layer_with_id(created, *layer, name.clone())?;
+        }
+        GraphEvent::RemoveLayer { layer } => graph.remove_layer(find(graph, *layer)?)?,
+        GraphEvent::Connect { from, output, to, port } => {
+            let (from, to) = (find(graph, *from)?, find(graph, *to)?);
+            graph.connect_output(from, *output, to, *port)?;
+        }
+        GraphEvent::Disconnect { to, port } => {
+            graph.disconnect(find(graph, *to)?, *port)?;
+        }
+        GraphEvent::SetParameter { layer, name, value } => {
+            graph.set_parameter(find(graph, *layer)?, name, value.clone())?;
+        }
+        GraphEvent::Rename { layer, name } => graph.rename(find(graph, *layer)?, name.clone())?,
+        GraphEvent::Paint { layer, stroke } => graph.paint(find(graph, *layer)?, stroke)?,
+        GraphEvent::SetCanvas { layer, canvas } => graph.set_canvas(find(graph, *layer)?, canvas.clone())?,
+        GraphEvent::SetLayout { layer, layout } => graph.set_layout(find(graph, *layer)?, layout.clone())?,
+        GraphEvent::Group { layers, composite } => {
+            let nodes = layers.iter().map(|&layer| find(graph, layer)).collect::<Result<Vec<NodeIndex>>>()?;
+            graph.group_nodes_with_id(&nodes, *composite)?;
+        }
+    }
+    Ok(())
+}
+
+fn find(graph: &InteractiveLayerGraph, layer: LayerId) -> Result<NodeIndex> {
+    graph.find_by_id(layer).context(format!("There is no layer with id {}", layer))
+}
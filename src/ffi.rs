@@ -0,0 +1,270 @@
+//! A C interface for running recipes from programs in other languages, see `include/klex.h`
+//!
+//! Functions return a `KlexStatus` and never unwind into the caller. After a failure, `klex_last_error` tells what went
+//! wrong on the calling thread. Memory handed out by the library, i.e. runners and images, is freed with the matching
+//! `_free` function, while memory passed in, e.g. pixels and strings, stays the caller's and is copied where needed.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    ptr, slice,
+};
+
+use image::RgbaImage;
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    color::ConversionGraph,
+    entity::Element,
+    error::{bail, ensure, Context, KlexError, Result},
+    layer::{Layer, LayerCategory, LayerOutput},
+    layer_graph::LayerGraph,
+    recipe::{Bindings, Recipe},
+    registry::LayerRegistry,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KlexStatus {
+    Ok = 0,
+    Error = 1,           // See `klex_last_error`
+    InvalidArgument = 2, // E.g. a null pointer or a string that isn't UTF-8
+    Panic = 3,           // A bug in Klex, the runner that was used shouldn't be used anymore
+}
+
+/// A recipe that was built, together with the outputs of its last run. Opaque to C.
+pub struct KlexRunner {
+    graph: LayerGraph,
+}
+
+/// An image handed out by the library, with 4 bytes per pixel in the order red, green, blue, alpha. Rows follow each
+/// other without padding. Free it with `klex_image_free`.
+#[repr(C)]
+pub struct KlexImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: *mut u8,
+    pub length: usize, // Of `data` in bytes, `width * height * 4`
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, turning its errors and panics into a status and the last error of the thread
+fn guard(f: impl FnOnce() -> Result<()>) -> KlexStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (KlexStatus::Ok, None),
+        Ok(Err(error)) if matches!(error.root(), KlexError::InvalidArgument(_)) => {
+            (KlexStatus::InvalidArgument, Some(format!("{:#}", error)))
+        }
+        Ok(Err(error)) => (KlexStatus::Error, Some(format!("{:#}", error))),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            (KlexStatus::Panic, Some(format!("Klex panicked: {}", message)))
+        }
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+/// # Safety
+///
+/// `string` is null or points to a NUL terminated string
+unsafe fn string<'a>(string: *const c_char, name: &'static str) -> Result<&'a str> {
+    ensure!(!string.is_null(), KlexError::InvalidArgument(name));
+    CStr::from_ptr(string).to_str().map_err(|_| KlexError::InvalidArgument(name))
+}
+
+fn single(graph: &LayerGraph, name: &str) -> Result<NodeIndex> {
+    match graph.find_by_name(name)[..] {
+        [layer] => Ok(layer),
+        [] => bail!(KlexError::UnknownName { name: name.to_string(), scope: "the recipe" }),
+        _ => bail!(KlexError::AmbiguousName { name: name.to_string(), scope: "the recipe" }),
+    }
+}
+
+/// Loads the recipe at `path` and builds it with the built-in layers. On success, `runner` is set to a runner that is
+/// freed with `klex_runner_free`.
+///
+/// # Safety
+///
+/// `path` is a NUL terminated UTF-8 string and `runner` points to writable memory for a pointer
+#[no_mangle]
+pub unsafe extern "C" fn klex_runner_new(path: *const c_char, runner: *mut *mut KlexRunner) -> KlexStatus {
+    guard(|| {
+        ensure!(!runner.is_null(), KlexError::InvalidArgument("runner"));
+        let recipe = Recipe::load(Path::new(string(path, "path")?))?;
+        let graph = recipe.build_layer_graph(&LayerRegistry::with_builtins(), &Bindings::new())?;
+        *runner = Box::into_raw(Box::new(KlexRunner { graph }));
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `runner` is null or was created by `klex_runner_new` and isn't used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn klex_runner_free(runner: *mut KlexRunner) {
+    if !runner.is_null() {
+        guard(|| {
+            drop(Box::from_raw(runner));
+            Ok(())
+        });
+    }
+}
+
+/// Makes the node named `node` put out a copy of an image instead of computing its output, e.g. instead of reading
+/// a file. `pixels` has 4 bytes per pixel in the order red, green, blue, alpha, and rows start `stride` bytes apart.
+///
+/// # Safety
+///
+/// `runner` was created by `klex_runner_new`, `node` is a NUL terminated UTF-8 string and `pixels` points to
+/// `stride * height` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn klex_runner_bind_rgba(
+    runner: *mut KlexRunner,
+    node: *const c_char,
+    pixels: *const u8,
+    width: u32,
+    height: u32,
+    stride: usize,
+) -> KlexStatus {
+    guard(|| {
+        let runner = runner.as_mut().ok_or(KlexError::InvalidArgument("runner"))?;
+        let node = string(node, "node")?;
+        ensure!(!pixels.is_null(), KlexError::InvalidArgument("pixels"));
+        ensure!(width > 0 && height > 0, "Images need at least one pixel");
+        let row = width as usize * 4;
+        ensure!(stride >= row, "A stride of {} bytes is too small for rows of {} pixels", stride, width);
+
+        let pixels = slice::from_raw_parts(pixels, stride * height as usize);
+        let data = pixels.chunks_exact(stride).flat_map(|pixels| &pixels[..row]).copied().collect();
+        let image = RgbaImage::from_raw(width, height, data).expect("Rows have the size of the image");
+        let layer = single(&runner.graph, node)?;
+        runner.graph.replace_layer(layer, Box::new(Bound(image)))?;
+        Ok(())
+    })
+}
+
+/// Computes all nodes of the recipe. Only nodes whose inputs changed since the last run are computed again.
+///
+/// # Safety
+///
+/// `runner` was created by `klex_runner_new`
+#[no_mangle]
+pub unsafe extern "C" fn klex_runner_run(runner: *mut KlexRunner) -> KlexStatus {
+    guard(|| {
+        let runner = runner.as_mut().ok_or(KlexError::InvalidArgument("runner"))?;
+        runner.graph.compute_all()
+    })
+}
+
+/// Copies the output of the node named `node`, usually one without children, from the last run. Images of other
+/// kinds than RGBA are converted, e.g. gray values are repeated in the color channels. `image` is filled in and has to
+/// be freed with `klex_image_free`.
+///
+/// # Safety
+///
+/// `runner` was created by `klex_runner_new`, `node` is a NUL terminated UTF-8 string and `image` points to writable
+/// memory for a `KlexImage`
+#[no_mangle]
+pub unsafe extern "C" fn klex_runner_output(
+    runner: *const KlexRunner,
+    node: *const c_char,
+    image: *mut KlexImage,
+) -> KlexStatus {
+    guard(|| {
+        let runner = runner.as_ref().ok_or(KlexError::InvalidArgument("runner"))?;
+        let node = string(node, "node")?;
+        ensure!(!image.is_null(), KlexError::InvalidArgument("image"));
+
+        let layer = single(&runner.graph, node)?;
+        let output = runner
+            .graph
+            .output(layer, 0)
+            .context(format!("Node {:?} has no output, the recipe may not have run yet", node))?;
+        let converted = match output.downcast_ref::<RgbaImage>() {
+            Some(output) => output.clone(),
+            None => *ConversionGraph::builtins()
+                .convert(output, RgbaImage::NAME)?
+                .downcast::<RgbaImage>()
+                .expect("Converted to an RgbaImage"),
+        };
+        let (width, height) = converted.dimensions();
+        let data = converted.into_raw().into_boxed_slice();
+        let length = data.len();
+        *image = KlexImage { width, height, data: Box::into_raw(data) as *mut u8, length };
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `image` is null or was filled in by `klex_runner_output` and isn't used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn klex_image_free(image: *mut KlexImage) {
+    if let Some(image) = image.as_mut() {
+        if !image.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(image.data, image.length)));
+        }
+        image.data = ptr::null_mut();
+        image.length = 0;
+    }
+}
+
+/// Copies the message of the last failure on the calling thread to `buffer`, which holds `size` bytes, as a NUL
+/// terminated string. Longer messages are cut off. Returns the size the buffer needs for the whole message, or 0 if
+/// the last call succeeded.
+///
+/// # Safety
+///
+/// `buffer` is null or points to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn klex_last_error(buffer: *mut c_char, size: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let last = last.borrow();
+        let message = match last.as_deref() {
+            Some(message) => message.as_bytes(),
+            None => return 0,
+        };
+        if !buffer.is_null() && size > 0 {
+            let copied = message.len().min(size - 1);
+            ptr::copy_nonoverlapping(message.as_ptr(), buffer as *mut u8, copied);
+            *buffer.add(copied) = 0;
+        }
+        message.len() + 1
+    })
+}
+
+/// An image bound to a node in place of what it computed
+struct Bound(RgbaImage);
+
+impl Layer for Bound {
+    fn kind(&self) -> String {
+        "Bound<RgbaImage>".to_string()
+    }
+
+    fn category(&self) -> LayerCategory {
+        LayerCategory::Input
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(self.0.clone()));
+        Ok(())
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        None // Depends on the pixels
+    }
+
+    fn output_type(&self) -> Option<&'static str> {
+        Some(RgbaImage::NAME)
+    }
+}
@@ -0,0 +1,142 @@
+//! Fourier transforms of gray images, for layers that work on their spectrum, e.g. aligning two photos of the same
+//! scene by phase correlation. Images of any size are transformed as they are, without padding.
+
+use image::GrayImage;
+use rustfft::{num_complex::Complex, FftDirection, FftPlanner};
+
+use crate::{
+    entity::GrayImageF32,
+    error::{ensure, KlexError, Result},
+};
+
+/// The values of a gray image or its spectrum, row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<Complex<f32>>,
+}
+
+impl Grid {
+    pub fn from_gray(image: &GrayImage) -> Self {
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            values: image.iter().map(|&value| Complex::new(f32::from(value), 0.0)).collect(),
+        }
+    }
+
+    /// Transforms the rows and then the columns. The inverse transform is scaled so that it undoes the forward one.
+    pub fn transform(&mut self, direction: FftDirection) {
+        let mut planner = FftPlanner::new();
+        let (width, height) = (self.width, self.height);
+        if width == 0 || height == 0 {
+            return;
+        }
+        planner.plan_fft(width, direction).process(&mut self.values);
+        let mut column = vec![Complex::default(); height];
+        let columns = planner.plan_fft(height, direction);
+        for x in 0..width {
+            column.iter_mut().enumerate().for_each(|(y, value)| *value = self.values[y * width + x]);
+            columns.process(&mut column);
+            column.iter().enumerate().for_each(|(y, &value)| self.values[y * width + x] = value);
+        }
+        if direction == FftDirection::Inverse {
+            let scale = 1.0 / (width * height) as f32;
+            self.values.iter_mut().for_each(|value| *value *= scale);
+        }
+    }
+
+    /// The real parts of the values, e.g. of an image transformed back from its spectrum
+    pub fn real(&self) -> GrayImageF32 {
+        let values = self.values.iter().map(|value| value.re).collect();
+        GrayImageF32::from_raw(self.width as u32, self.height as u32, values).expect("Sizes match")
+    }
+
+    /// The logarithm of the magnitude of a spectrum, from 0 to 1 at the strongest frequency. The lowest frequencies
+    /// are moved to the center, so that periodic patterns show up as pairs of spots mirrored around it.
+    pub fn log_magnitude(&self) -> GrayImageF32 {
+        let (width, height) = (self.width, self.height);
+        let magnitudes: Vec<f32> = self.values.iter().map(|value| value.norm().ln_1p()).collect();
+        let max = magnitudes.iter().copied().fold(f32::EPSILON, f32::max);
+        GrayImageF32::from_fn(width as u32, height as u32, |x, y| {
+            let (x, y) = ((x as usize + width - width / 2) % width, (y as usize + height - height / 2) % height);
+            image::Luma([magnitudes[y * width + x] / max])
+        })
+    }
+
+    /// The frequency of a position of a spectrum along an axis of `size` values, in cycles per pixel from -0.5 to 0.5
+    pub fn frequency(position: usize, size: usize) -> f32 {
+        signed(position, size) / size as f32
+    }
+}
+
+/// How a second image is shifted against a first one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Correlation {
+    pub shift: (f32, f32), // Where the second image shows what the first one shows at the origin
+    pub confidence: f32,   // Height of the correlation peak, from 0 for unrelated images towards 1 for shifted copies
+}
+
+/// Finds the shift between two images of the same size by phase correlation. Shifts are whole pixels unless
+/// `subpixel` is set, in which case the peak is refined by how much of it spills over to its neighbors. Shifts
+/// of more than half of the size are taken to go the other way around. Both images are windowed, so that their edges
+/// don't correlate with each other.
+pub fn phase_correlation(first: &GrayImage, second: &GrayImage, subpixel: bool) -> Result<Correlation> {
+    if first.dimensions() != second.dimensions() {
+        return Err(KlexError::ShapeMismatch { expected: first.dimensions(), found: second.dimensions() });
+    }
+    ensure!(first.width() > 0 && first.height() > 0, "Can't correlate empty images");
+    let (mut first, mut second) = (windowed(first), windowed(second));
+    first.transform(FftDirection::Forward);
+    second.transform(FftDirection::Forward);
+    // The cross power spectrum keeps only the phase difference, whose inverse is a peak at the shift
+    for (first, second) in first.values.iter().zip(second.values.iter_mut()) {
+        let product = first.conj() * *second;
+        let magnitude = product.norm();
+        *second = if magnitude > f32::EPSILON { product / magnitude } else { Complex::default() };
+    }
+    second.transform(FftDirection::Inverse);
+
+    let (width, height) = (second.width, second.height);
+    let surface: Vec<f32> = second.values.iter().map(|value| value.re).collect();
+    let peak = (0..surface.len()).fold(0, |peak, index| if surface[index] > surface[peak] { index } else { peak });
+    let (x, y) = (peak % width, peak / width);
+    let at = |x: usize, y: usize| surface[(y % height) * width + x % width];
+    // The peak of a shift between pixels is spread over its neighbors like a sinc function, so the larger neighbor
+    // tells how far the shift is towards it
+    let refine = |before: f32, after: f32| match subpixel {
+        true if after >= before && after > 0.0 => after / (after + surface[peak]),
+        true if before > 0.0 => -before / (before + surface[peak]),
+        _ => 0.0,
+    };
+    let dx = refine(at(x + width - 1, y), at(x + 1, y));
+    let dy = refine(at(x, y + height - 1), at(x, y + 1));
+    Ok(Correlation {
+        shift: (signed(x, width) + dx, signed(y, height) + dy),
+        confidence: surface[peak].clamp(0.0, 1.0),
+    })
+}
+
+/// A position along an axis of `size` values, counted backwards from the end when it is past the middle
+fn signed(position: usize, size: usize) -> f32 {
+    match position > size / 2 {
+        true => position as f32 - size as f32,
+        false => position as f32,
+    }
+}
+
+/// The image without its mean, faded out towards the edges by a Hann window
+fn windowed(image: &GrayImage) -> Grid {
+    let mut grid = Grid::from_gray(image);
+    let mean = grid.values.iter().map(|value| value.re).sum::<f32>() / grid.values.len().max(1) as f32;
+    let hann = |position: usize, size: usize| {
+        let phase = std::f32::consts::PI * (position as f32 + 0.5) / size as f32;
+        phase.sin().powi(2)
+    };
+    for (index, value) in grid.values.iter_mut().enumerate() {
+        let (x, y) = (index % grid.width, index / grid.width);
+        *value = Complex::new((value.re - mean) * hann(x, grid.width) * hann(y, grid.height), 0.0);
+    }
+    grid
+}
@@ -0,0 +1,884 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::Hash,
+};
+
+use iced_graphics::{
+    triangle::{Mesh2D, Vertex2D},
+    Backend, Primitive, Renderer,
+};
+use iced_native::{
+    event, keyboard, layout, mouse, Background, Clipboard, Color, Element, Event, Font, Hasher, HorizontalAlignment,
+    Layout, Length, Point, Rectangle, Size, Vector, VerticalAlignment, Widget,
+};
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    layer::{InputCount, LayerCategory, OutputPort},
+    layer_graph::{ColorTag, NodeLayout},
+    registry::LayerInfo,
+    ui::GraphMirror,
+};
+
+/// A connection as (source layer, output port of the source, target layer, input port of the target)
+pub type EditorEdge = (NodeIndex, usize, NodeIndex, usize);
+
+/// A layer as the graph editor draws it
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditorNode {
+    pub node: NodeIndex,
+    pub name: String,
+    pub kind: String,
+    pub position: Point,                        // Top left corner in graph coordinates
+    pub input_types: Vec<Option<&'static str>>, // One for each input port, None if unknown
+    pub outputs: Vec<OutputPort>,               // At least one
+    pub layout: NodeLayout,                     // Its position is the one above, even if the layer was never placed
+}
+
+impl EditorNode {
+    const WIDTH: f32 = 160.0;
+    const HEADER_HEIGHT: f32 = 40.0;
+    const PORT_SPACING: f32 = 20.0;
+    const NOTE_HEIGHT: f32 = 20.0;
+    const PORT_RADIUS: f32 = 5.0;
+    const COLUMN_SPACING: f32 = 220.0; // Between layers that were never placed
+    const ROW_SPACING: f32 = 100.0;
+
+    /// The layers of a graph. Layers that were never placed are put in columns by their distance from the sources.
+    pub fn from_graph(graph: &GraphMirror, catalog: &BTreeMap<String, LayerInfo>) -> Vec<Self> {
+        let mut depths = HashMap::new();
+        let mut rows: HashMap<usize, usize> = HashMap::new();
+        graph
+            .layers()
+            .map(|(node, layer)| {
+                let info = catalog.get(&layer.kind);
+                // Layers of unknown kinds get a port, so that they can be connected anyway
+                let mut input_types: Vec<_> = match info {
+                    Some(info) if info.category == LayerCategory::Input => Vec::new(),
+                    Some(info) if !info.input_types.is_empty() => info.input_types.iter().copied().map(Some).collect(),
+                    _ => vec![None],
+                };
+                let connected = layer.inputs.iter().map(|&(_, _, port)| port + 1).max().unwrap_or(0);
+                // Layers taking any number of inputs get a free port after the connected ones
+                let free = matches!(info.map(|info| info.inputs), Some(InputCount::AtLeast(_)));
+                let ports = connected + usize::from(free);
+                if input_types.len() < ports {
+                    input_types.resize(ports, None);
+                }
+                let mut outputs: Vec<_> = match info {
+                    Some(info) if !info.outputs.is_empty() => info.outputs.clone(),
+                    _ => vec![OutputPort::SINGLE],
+                };
+                for (port, output) in outputs.iter_mut().enumerate() {
+                    output.element = info.and_then(|info| info.output_element(port));
+                }
+
+                let position = match layer.layout.position {
+                    Some((x, y)) => Point::new(x, y),
+                    None => {
+                        let depth = depth(graph, node, &mut depths);
+                        let row = rows.entry(depth).or_insert(0);
+                        *row += 1;
+                        Point::new(depth as f32 * Self::COLUMN_SPACING, (*row - 1) as f32 * Self::ROW_SPACING)
+                    }
+                };
+                Self {
+                    node,
+                    name: layer.name.clone(),
+                    kind: layer.kind.clone(),
+                    position,
+                    input_types,
+                    outputs,
+                    layout: NodeLayout {
+                        position: Some((position.x, position.y)),
+                        ..layer.layout.clone()
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Size in graph coordinates. Layers are at least as large as their ports and their note need, and collapsed
+    /// layers only show their header.
+    pub fn size(&self) -> Size {
+        if self.layout.collapsed {
+            return Size::new(Self::WIDTH, Self::HEADER_HEIGHT);
+        }
+        let ports = self.input_types.len().max(self.outputs.len()).max(1) as f32;
+        let note = if self.layout.note.is_empty() { 0.0 } else { Self::NOTE_HEIGHT };
+        let (width, height) = self.layout.size.unwrap_or_default();
+        Size::new(Self::WIDTH.max(width), (Self::HEADER_HEIGHT + ports * Self::PORT_SPACING + note).max(height))
+    }
+
+    /// Where an input port is while the layer is at `position`
+    pub fn input_port(&self, position: Point, port: usize) -> Point {
+        Point::new(position.x, position.y + self.port_offset(port))
+    }
+
+    pub fn output_port(&self, position: Point, port: usize) -> Point {
+        Point::new(position.x + self.size().width, position.y + self.port_offset(port))
+    }
+
+    /// Ports are in rows below the header, inputs on the left and outputs on the right. Those of collapsed layers are
+    /// all next to the header.
+    fn port_offset(&self, port: usize) -> f32 {
+        if self.layout.collapsed {
+            Self::HEADER_HEIGHT / 2.0
+        } else {
+            Self::HEADER_HEIGHT + (port as f32 + 0.5) * Self::PORT_SPACING
+        }
+    }
+}
+
+/// Longest distance of a layer from a source
+fn depth(graph: &GraphMirror, node: NodeIndex, depths: &mut HashMap<NodeIndex, usize>) -> usize {
+    if let Some(&depth) = depths.get(&node) {
+        return depth;
+    }
+    depths.insert(node, 0); // Stops at cycles, which the backend doesn't allow anyway
+    let parents: Vec<NodeIndex> = graph
+        .layer(node)
+        .map(|layer| layer.inputs.iter().map(|&(parent, _, _)| parent).collect())
+        .unwrap_or_default();
+    let depth = parents.into_iter().map(|parent| depth(graph, parent, depths) + 1).max().unwrap_or(0);
+    depths.insert(node, depth);
+    depth
+}
+
+/// All connections of the graph
+pub fn edges(graph: &GraphMirror) -> Vec<EditorEdge> {
+    graph
+        .layers()
+        .flat_map(|(node, layer)| layer.inputs.iter().map(move |&(parent, output, port)| (parent, output, node, port)))
+        .collect()
+}
+
+/// Why output `output` of `from` can't be fed into input `port` of `to`, if it can't
+pub fn connection_problem(
+    nodes: &[EditorNode],
+    edges: &[EditorEdge],
+    (from, output): (NodeIndex, usize),
+    to: NodeIndex,
+    port: usize,
+) -> Option<String> {
+    if from == to {
+        return Some("A layer can't be fed its own output".to_string());
+    }
+    let find = |node| nodes.iter().find(|candidate| candidate.node == node);
+    let produced = find(from).and_then(|node| node.outputs.get(output)?.element);
+    let expected = find(to).and_then(|node| node.input_types.get(port).copied().flatten());
+    if let (Some(produced), Some(expected)) = (produced, expected) {
+        if produced != expected {
+            return Some(format!("Expects {}, but gets {}", expected, produced));
+        }
+    }
+
+    // `to` must not feed into `from` already
+    let mut ancestors = vec![from];
+    let mut visited = Vec::new();
+    while let Some(node) = ancestors.pop() {
+        if node == to {
+            return Some("This would create a cycle".to_string());
+        }
+        if !visited.contains(&node) {
+            visited.push(node);
+            ancestors.extend(edges.iter().filter(|&&(_, _, child, _)| child == node).map(|&(parent, _, _, _)| parent));
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    Node(NodeIndex),
+    Edge { to: NodeIndex, port: usize },
+}
+
+/// What is under the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hit {
+    Output(NodeIndex, usize),
+    Input(NodeIndex, usize),
+    Node(NodeIndex),
+    Edge { to: NodeIndex, port: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Drag {
+    Pan { start: Point, offset: Vector }, // Cursor position and offset when dragging started
+    Node { node: NodeIndex, grab: Vector, start: Point, position: Point }, // In graph coordinates
+    Edge { from: NodeIndex, output: usize, cursor: Point },
+}
+
+/// How the graph is shown in a `GraphEditor`, along with what the user is doing with it. The application keeps it
+/// between frames and replaces it with the states the editor sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EditorState {
+    offset: Vector, // Where the origin of the graph is, relative to the top left corner of the editor
+    zoom: f32,      // Screen pixels per unit of the graph
+    drag: Option<Drag>,
+    selection: Option<Selection>,
+    dropped: Option<(NodeIndex, Point)>, // Shown until the backend confirms that the layer was moved
+}
+
+impl EditorState {
+    const MIN_ZOOM: f32 = 0.25;
+    const MAX_ZOOM: f32 = 4.0;
+    const ZOOM_STEP: f32 = 1.2; // Per line scrolled
+    const PIXELS_PER_LINE: f32 = 40.0;
+    const HIT_RADIUS: f32 = 8.0; // Around ports and edges, in screen pixels so that they can be hit at any zoom
+
+    pub fn new() -> Self {
+        Self {
+            offset: Vector::new(20.0, 20.0),
+            zoom: 1.0,
+            drag: None,
+            selection: None,
+            dropped: None,
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    /// Graph coordinates to coordinates relative to the top left corner of the editor
+    pub fn to_screen(&self, point: Point) -> Point {
+        Point::new(point.x * self.zoom + self.offset.x, point.y * self.zoom + self.offset.y)
+    }
+
+    pub fn to_graph(&self, point: Point) -> Point {
+        Point::new((point.x - self.offset.x) / self.zoom, (point.y - self.offset.y) / self.zoom)
+    }
+
+    /// Zooms in (`lines > 0`) or out while the point under `cursor` stays where it is
+    pub fn zoomed(&self, lines: f32, cursor: Point) -> Self {
+        let anchor = self.to_graph(cursor);
+        let zoom = (self.zoom * Self::ZOOM_STEP.powf(lines)).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        Self {
+            zoom,
+            offset: Vector::new(cursor.x - anchor.x * zoom, cursor.y - anchor.y * zoom),
+            ..*self
+        }
+    }
+
+    /// Forgets where a layer was dropped, once its position arrived from the backend
+    pub fn settle(&mut self, node: NodeIndex) {
+        if matches!(self.dropped, Some((dropped, _)) if dropped == node) {
+            self.dropped = None;
+        }
+    }
+
+    /// Where a layer is at the moment, which differs from its position while it is being dragged
+    pub fn position(&self, node: &EditorNode) -> Point {
+        match (self.drag, self.dropped) {
+            (Some(Drag::Node { node: dragged, position, .. }), _) if dragged == node.node => position,
+            (_, Some((dropped, position))) if dropped == node.node => position,
+            _ => node.position,
+        }
+    }
+
+    /// The topmost thing under `cursor`. Ports are preferred, since they are small.
+    pub fn hit(&self, nodes: &[EditorNode], edges: &[EditorEdge], cursor: Point) -> Option<Hit> {
+        // Zoomed out, neighbouring ports can all be in reach, so the closest one wins
+        let closest = |ports: usize, port_position: &dyn Fn(usize) -> Point| {
+            let distance = |port| distance(self.to_screen(port_position(port)), cursor);
+            let port = (0..ports).min_by(|&a, &b| distance(a).total_cmp(&distance(b)))?;
+            (distance(port) <= Self::HIT_RADIUS).then_some(port)
+        };
+        for node in nodes.iter().rev() {
+            let position = self.position(node);
+            if let Some(port) = closest(node.outputs.len(), &|port| node.output_port(position, port)) {
+                return Some(Hit::Output(node.node, port));
+            }
+            if let Some(port) = closest(node.input_types.len(), &|port| node.input_port(position, port)) {
+                return Some(Hit::Input(node.node, port));
+            }
+        }
+
+        let point = self.to_graph(cursor);
+        for node in nodes.iter().rev() {
+            if Rectangle::new(self.position(node), node.size()).contains(point) {
+                return Some(Hit::Node(node.node));
+            }
+        }
+
+        let placed: HashMap<NodeIndex, _> = nodes.iter().map(|node| (node.node, (node, self.position(node)))).collect();
+        edges.iter().find_map(|&(from, output, to, port)| {
+            let (&(from_node, from_position), &(to_node, to_position)) = (placed.get(&from)?, placed.get(&to)?);
+            let start = self.to_screen(from_node.output_port(from_position, output));
+            let end = self.to_screen(to_node.input_port(to_position, port));
+            let points = curve(start, end);
+            let is_near = points
+                .windows(2)
+                .any(|segment| segment_distance(segment[0], segment[1], cursor) <= Self::HIT_RADIUS / 2.0);
+            is_near.then_some(Hit::Edge { to, port })
+        })
+    }
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Background of layers marked with a color tag, light enough for the text on it
+fn tag_color(tag: ColorTag) -> Color {
+    match tag {
+        ColorTag::Red => Color::from_rgb(1.0, 0.85, 0.85),
+        ColorTag::Yellow => Color::from_rgb(1.0, 0.96, 0.75),
+        ColorTag::Green => Color::from_rgb(0.85, 0.96, 0.85),
+        ColorTag::Blue => Color::from_rgb(0.85, 0.9, 1.0),
+        ColorTag::Purple => Color::from_rgb(0.93, 0.87, 1.0),
+    }
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+fn segment_distance(start: Point, end: Point, point: Point) -> f32 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return distance(start, point);
+    }
+    let t = (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0);
+    distance(Point::new(start.x + t * dx, start.y + t * dy), point)
+}
+
+/// An edge from an output port to an input port, bending out of and into the ports horizontally
+fn curve(start: Point, end: Point) -> Vec<Point> {
+    const SEGMENTS: usize = 24;
+    let bend = ((end.x - start.x).abs() / 2.0).max(30.0);
+    let (first, second) = (Point::new(start.x + bend, start.y), Point::new(end.x - bend, end.y));
+    (0..=SEGMENTS)
+        .map(|i| {
+            let t = i as f32 / SEGMENTS as f32;
+            let u = 1.0 - t;
+            let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+            let points = [start, first, second, end];
+            let x = weights.iter().zip(&points).map(|(weight, point)| weight * point.x).sum();
+            let y = weights.iter().zip(&points).map(|(weight, point)| weight * point.y).sum();
+            Point::new(x, y)
+        })
+        .collect()
+}
+
+/// Triangles for drawing lines, since quads can only draw boxes
+#[derive(Default)]
+pub(crate) struct Lines {
+    vertices: Vec<Vertex2D>,
+    indices: Vec<u32>,
+}
+
+impl Lines {
+    pub(crate) fn push(&mut self, points: &[Point], width: f32, color: Color) {
+        let color = color.into_linear();
+        for segment in points.windows(2) {
+            let (start, end) = (segment[0], segment[1]);
+            let length = distance(start, end);
+            if length == 0.0 {
+                continue;
+            }
+            let normal = Vector::new(-(end.y - start.y), end.x - start.x) * (width / 2.0 / length);
+            let base = self.vertices.len() as u32;
+            for point in [start + normal, start - normal, end + normal, end - normal] {
+                self.vertices.push(Vertex2D {
+                    position: [point.x, point.y],
+                    color,
+                });
+            }
+            self.indices.extend([base, base + 1, base + 2, base + 1, base + 3, base + 2]);
+        }
+    }
+
+    pub(crate) fn into_primitive(self, size: Size) -> Primitive {
+        if self.indices.is_empty() {
+            return Primitive::None;
+        }
+        let buffers = Mesh2D {
+            vertices: self.vertices,
+            indices: self.indices,
+        };
+        Primitive::Mesh2D { buffers, size }
+    }
+}
+
+/// Changes the user made in a `GraphEditor`
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditorMessage {
+    State(EditorState),
+    Select(NodeIndex),
+    Move { node: NodeIndex, position: Point },
+    Connect { from: NodeIndex, output: usize, to: NodeIndex, port: usize },
+    Disconnect { to: NodeIndex, port: usize },
+    Remove(NodeIndex),
+    Layout { node: NodeIndex, layout: NodeLayout }, // Everything but moving it, which is `Move`
+}
+
+/// Shows the layers of a graph as boxes with their input ports on the left and their output ports on the right.
+/// Layers are moved by dragging them and connected by dragging from an output port to an input port. A click selects
+/// a layer or connection, which Delete removes, and a right click collapses or expands a layer. The view is panned by
+/// dragging the background and zoomed with the mouse wheel. Layers that failed to compute are highlighted.
+pub struct GraphEditor<Message> {
+    nodes: Vec<EditorNode>,
+    edges: Vec<EditorEdge>,
+    state: EditorState,
+    failed: BTreeSet<NodeIndex>,
+    on_message: Box<dyn Fn(EditorMessage) -> Message>,
+    width: Length,
+    height: Length,
+}
+
+impl<Message> GraphEditor<Message> {
+    const BACKGROUND: Color = Color::from_rgb(0.95, 0.95, 0.95);
+    const NODE: Color = Color::WHITE;
+    const BORDER: Color = Color::from_rgb(0.6, 0.6, 0.6);
+    const SELECTED: Color = Color::from_rgb(0.2, 0.45, 0.9);
+    const EDGE: Color = Color::from_rgb(0.45, 0.45, 0.45);
+    const PORT: Color = Color::from_rgb(0.55, 0.55, 0.55);
+    const COMPATIBLE: Color = Color::from_rgb(0.2, 0.7, 0.3);
+    const INCOMPATIBLE: Color = Color::from_rgb(0.85, 0.25, 0.2);
+    const FAILED: Color = Color::from_rgb(1.0, 0.88, 0.86);
+
+    pub fn new(
+        graph: &GraphMirror,
+        catalog: &BTreeMap<String, LayerInfo>,
+        state: EditorState,
+        on_message: impl Fn(EditorMessage) -> Message + 'static,
+    ) -> Self {
+        Self {
+            nodes: EditorNode::from_graph(graph, catalog),
+            edges: edges(graph),
+            state,
+            failed: BTreeSet::new(),
+            on_message: Box::new(on_message),
+            width: Length::Fill,
+            height: Length::Fill,
+        }
+    }
+
+    pub fn width(mut self, width: Length) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: Length) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Layers to highlight because their last computation failed
+    pub fn failed(mut self, failed: BTreeSet<NodeIndex>) -> Self {
+        self.failed = failed;
+        self
+    }
+
+    /// A layer along with where it is at the moment
+    fn placed(&self, node: NodeIndex) -> Option<(&EditorNode, Point)> {
+        self.nodes.iter().find(|candidate| candidate.node == node).map(|node| (node, self.state.position(node)))
+    }
+
+    fn position(&self, node: NodeIndex) -> Option<Point> {
+        self.placed(node).map(|(_, position)| position)
+    }
+
+    fn problem(&self, from: (NodeIndex, usize), to: NodeIndex, port: usize) -> Option<String> {
+        connection_problem(&self.nodes, &self.edges, from, to, port)
+    }
+
+    /// Rectangles and text of the layers, in coordinates relative to the editor
+    fn draw_nodes(&self) -> Vec<Primitive> {
+        let zoom = self.state.zoom;
+        let dragged_from = match self.state.drag {
+            Some(Drag::Edge { from, output, .. }) => Some((from, output)),
+            _ => None,
+        };
+
+        let mut primitives = Vec::new();
+        for node in &self.nodes {
+            let position = self.state.position(node);
+            let top_left = self.state.to_screen(position);
+            let size = node.size();
+            let selected = self.state.selection == Some(Selection::Node(node.node));
+            let failed = self.failed.contains(&node.node);
+            let background = match (failed, node.layout.color) {
+                (true, _) => Self::FAILED,
+                (false, Some(tag)) => tag_color(tag),
+                (false, None) => Self::NODE,
+            };
+            primitives.push(Primitive::Quad {
+                bounds: Rectangle::new(top_left, Size::new(size.width * zoom, size.height * zoom)),
+                background: Background::Color(background),
+                border_radius: 4.0 * zoom,
+                border_width: if selected || failed { 2.0 } else { 1.0 },
+                border_color: match (selected, failed) {
+                    (true, _) => Self::SELECTED,
+                    (false, true) => Self::INCOMPATIBLE,
+                    (false, false) => Self::BORDER,
+                },
+            });
+            let text = |content: String, offset: f32, text_size: f32, color: Color| Primitive::Text {
+                content,
+                bounds: Rectangle::new(
+                    Point::new(top_left.x + 8.0 * zoom, top_left.y + offset * zoom),
+                    Size::new((size.width - 16.0) * zoom, 20.0 * zoom),
+                ),
+                color,
+                size: text_size * zoom,
+                font: Font::Default,
+                horizontal_alignment: HorizontalAlignment::Left,
+                vertical_alignment: VerticalAlignment::Top,
+            };
+            primitives.push(text(node.name.clone(), 4.0, 16.0, Color::BLACK));
+            primitives.push(text(node.kind.clone(), 22.0, 12.0, Self::BORDER));
+            if !node.layout.collapsed && !node.layout.note.is_empty() {
+                let ports = node.input_types.len().max(node.outputs.len()).max(1) as f32;
+                let offset = EditorNode::HEADER_HEIGHT + ports * EditorNode::PORT_SPACING;
+                primitives.push(text(node.layout.note.clone(), offset, 12.0, Color::BLACK));
+            }
+
+            let mut port = |center: Point, color: Color| {
+                let radius = EditorNode::PORT_RADIUS * zoom;
+                let center = self.state.to_screen(center);
+                primitives.push(Primitive::Quad {
+                    bounds: Rectangle::new(center - Vector::new(radius, radius), Size::new(2.0 * radius, 2.0 * radius)),
+                    background: Background::Color(color),
+                    border_radius: radius,
+                    border_width: 0.0,
+                    border_color: Color::TRANSPARENT,
+                });
+            };
+            for output in 0..node.outputs.len() {
+                port(node.output_port(position, output), Self::PORT);
+            }
+            for input in 0..node.input_types.len() {
+                // While an edge is being dragged, inputs show whether it can be connected to them
+                let color = match dragged_from {
+                    Some(from) if self.problem(from, node.node, input).is_some() => Self::INCOMPATIBLE,
+                    Some(_) => Self::COMPATIBLE,
+                    None => Self::PORT,
+                };
+                port(node.input_port(position, input), color);
+            }
+            // Several outputs are told apart by their names, unless they are hidden
+            let named = if node.outputs.len() > 1 && !node.layout.collapsed { &node.outputs[..] } else { &[] };
+            for (output, OutputPort { name, .. }) in named.iter().enumerate() {
+                let center = self.state.to_screen(node.output_port(position, output));
+                primitives.push(Primitive::Text {
+                    content: name.to_string(),
+                    bounds: Rectangle::new(
+                        Point::new(center.x - (size.width / 2.0) * zoom, center.y),
+                        Size::new((size.width / 2.0 - 10.0) * zoom, 20.0 * zoom),
+                    ),
+                    color: Self::BORDER,
+                    size: 12.0 * zoom,
+                    font: Font::Default,
+                    horizontal_alignment: HorizontalAlignment::Right,
+                    vertical_alignment: VerticalAlignment::Center,
+                });
+            }
+        }
+        primitives
+    }
+
+    /// The edge being dragged out of an output port, along with why it can't be connected where it is, if it can't
+    fn draw_dragged_edge(&self, size: Size) -> Vec<Primitive> {
+        let (from, output, cursor) = match self.state.drag {
+            Some(Drag::Edge { from, output, cursor }) => (from, output, cursor),
+            _ => return Vec::new(),
+        };
+        let start = match self.placed(from) {
+            Some((node, position)) => self.state.to_screen(node.output_port(position, output)),
+            None => return Vec::new(),
+        };
+        let (end, problem) = match self.state.hit(&self.nodes, &self.edges, cursor) {
+            Some(Hit::Input(to, port)) => {
+                let end = self.placed(to).map(|(node, position)| node.input_port(position, port));
+                (end.map(|end| self.state.to_screen(end)), Some(self.problem((from, output), to, port)))
+            }
+            _ => (None, None),
+        };
+        let color = match &problem {
+            Some(Some(_)) => Self::INCOMPATIBLE,
+            Some(None) => Self::COMPATIBLE,
+            None => Self::EDGE,
+        };
+
+        let mut lines = Lines::default();
+        lines.push(&curve(start, end.unwrap_or(cursor)), 2.0, color);
+        let mut primitives = vec![lines.into_primitive(size)];
+        if let Some(Some(problem)) = problem {
+            primitives.push(Primitive::Text {
+                content: problem,
+                bounds: Rectangle::new(cursor + Vector::new(12.0, 12.0), Size::new(300.0, 20.0)),
+                color: Self::INCOMPATIBLE,
+                size: 14.0,
+                font: Font::Default,
+                horizontal_alignment: HorizontalAlignment::Left,
+                vertical_alignment: VerticalAlignment::Top,
+            });
+        }
+        primitives
+    }
+
+    /// Finishes a drag when the mouse button is released
+    fn drop(&self, drag: Drag, messages: &mut Vec<Message>) -> EditorState {
+        let mut state = EditorState { drag: None, ..self.state };
+        match drag {
+            Drag::Pan { .. } => (),
+            Drag::Node {
+                node, start, position, ..
+            } => {
+                if position != start {
+                    state.dropped = Some((node, position));
+                    messages.push((self.on_message)(EditorMessage::Move { node, position }));
+                }
+            }
+            Drag::Edge { from, output, cursor } => {
+                if let Some(Hit::Input(to, port)) = self.state.hit(&self.nodes, &self.edges, cursor) {
+                    if self.problem((from, output), to, port).is_none() {
+                        // An input takes a single connection, which is replaced
+                        if self.edges.iter().any(|&(_, _, child, child_port)| child == to && child_port == port) {
+                            messages.push((self.on_message)(EditorMessage::Disconnect { to, port }));
+                        }
+                        messages.push((self.on_message)(EditorMessage::Connect { from, output, to, port }));
+                    }
+                }
+            }
+        }
+        state
+    }
+}
+
+impl<Message, B: Backend> Widget<Message, Renderer<B>> for GraphEditor<Message> {
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, _renderer: &Renderer<B>, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(self.width).height(self.height).max())
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Renderer<B>,
+        _defaults: &iced_graphics::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> (Primitive, mouse::Interaction) {
+        let bounds = layout.bounds();
+        let origin = Vector::new(bounds.x, bounds.y);
+
+        let mut edges = Lines::default();
+        for &(from, output, to, port) in &self.edges {
+            let ((from_node, from_position), (to_node, to_position)) = match (self.placed(from), self.placed(to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => continue,
+            };
+            let start = self.state.to_screen(from_node.output_port(from_position, output));
+            let end = self.state.to_screen(to_node.input_port(to_position, port));
+            if self.state.selection == Some(Selection::Edge { to, port }) {
+                edges.push(&curve(start, end), 4.0, Self::SELECTED);
+            } else {
+                edges.push(&curve(start, end), 2.0, Self::EDGE);
+            }
+        }
+
+        // Each layer is clipped to the editor and drawn on top of the previous one
+        let clipped = |primitives: Vec<Primitive>| Primitive::Clip {
+            bounds,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Translate {
+                translation: origin,
+                content: Box::new(Primitive::Group { primitives }),
+            }),
+        };
+        let background = Primitive::Quad {
+            bounds,
+            background: Background::Color(Self::BACKGROUND),
+            border_radius: 0.0,
+            border_width: 0.0,
+            border_color: Color::TRANSPARENT,
+        };
+        let primitive = Primitive::Group {
+            primitives: vec![
+                background,
+                clipped(vec![edges.into_primitive(bounds.size())]),
+                clipped(self.draw_nodes()),
+                clipped(self.draw_dragged_edge(bounds.size())),
+            ],
+        };
+
+        let cursor = cursor_position - origin;
+        let interaction = match self.state.drag {
+            Some(Drag::Pan { .. } | Drag::Node { .. }) => mouse::Interaction::Grabbing,
+            Some(Drag::Edge { .. }) => mouse::Interaction::Crosshair,
+            None if !bounds.contains(cursor_position) => mouse::Interaction::Idle,
+            None => match self.state.hit(&self.nodes, &self.edges, cursor) {
+                Some(Hit::Output(..) | Hit::Input(..)) => mouse::Interaction::Crosshair,
+                Some(Hit::Node(_) | Hit::Edge { .. }) => mouse::Interaction::Pointer,
+                None => mouse::Interaction::Idle,
+            },
+        };
+        (primitive, interaction)
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+        self.width.hash(state);
+        self.height.hash(state);
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _renderer: &Renderer<B>,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let cursor = cursor_position - Vector::new(bounds.x, bounds.y);
+        let is_over = bounds.contains(cursor_position);
+        let state = match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if is_over => {
+                let lines = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / EditorState::PIXELS_PER_LINE,
+                };
+                self.state.zoomed(lines, cursor)
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Middle)) if is_over => {
+                let drag = Drag::Pan {
+                    start: cursor,
+                    offset: self.state.offset,
+                };
+                EditorState {
+                    drag: Some(drag),
+                    ..self.state
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Right)) if is_over => {
+                match self.state.hit(&self.nodes, &self.edges, cursor) {
+                    Some(Hit::Node(node)) => {
+                        let (editor_node, _) = self.placed(node).expect("Hit layers exist");
+                        let layout = NodeLayout {
+                            collapsed: !editor_node.layout.collapsed,
+                            ..editor_node.layout.clone()
+                        };
+                        messages.push((self.on_message)(EditorMessage::Layout { node, layout }));
+                        return event::Status::Captured;
+                    }
+                    _ => return event::Status::Ignored,
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) if is_over => {
+                let (drag, selection) = match self.state.hit(&self.nodes, &self.edges, cursor) {
+                    Some(Hit::Output(from, output)) => {
+                        (Some(Drag::Edge { from, output, cursor }), self.state.selection)
+                    }
+                    Some(Hit::Input(to, port)) => {
+                        let connected =
+                            self.edges.iter().any(|&(_, _, child, child_port)| (child, child_port) == (to, port));
+                        (None, connected.then_some(Selection::Edge { to, port }))
+                    }
+                    Some(Hit::Node(node)) => {
+                        messages.push((self.on_message)(EditorMessage::Select(node)));
+                        let start = self.position(node).unwrap_or_default();
+                        let grab = self.state.to_graph(cursor) - start;
+                        let drag = Drag::Node {
+                            node,
+                            grab,
+                            start,
+                            position: start,
+                        };
+                        (Some(drag), Some(Selection::Node(node)))
+                    }
+                    Some(Hit::Edge { to, port }) => (None, Some(Selection::Edge { to, port })),
+                    None => {
+                        let drag = Drag::Pan {
+                            start: cursor,
+                            offset: self.state.offset,
+                        };
+                        (Some(drag), None)
+                    }
+                };
+                EditorState {
+                    drag,
+                    selection,
+                    ..self.state
+                }
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) => {
+                let drag = match self.state.drag {
+                    Some(Drag::Pan { start, offset }) => Drag::Pan { start, offset },
+                    Some(Drag::Node { node, grab, start, .. }) => Drag::Node {
+                        node,
+                        grab,
+                        start,
+                        position: self.state.to_graph(cursor) - grab,
+                    },
+                    Some(Drag::Edge { from, output, .. }) => Drag::Edge { from, output, cursor },
+                    None => return event::Status::Ignored,
+                };
+                let offset = match drag {
+                    Drag::Pan { start, offset } => offset + (cursor - start),
+                    _ => self.state.offset,
+                };
+                EditorState {
+                    drag: Some(drag),
+                    offset,
+                    ..self.state
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left | mouse::Button::Middle)) => {
+                match self.state.drag {
+                    Some(drag) => self.drop(drag, messages),
+                    None => return event::Status::Ignored,
+                }
+            }
+            Event::Keyboard(keyboard::Event::KeyPressed {
+                key_code: keyboard::KeyCode::Delete | keyboard::KeyCode::Backspace,
+                ..
+            }) if is_over => {
+                let message = match self.state.selection {
+                    Some(Selection::Node(node)) => EditorMessage::Remove(node),
+                    Some(Selection::Edge { to, port }) => EditorMessage::Disconnect { to, port },
+                    None => return event::Status::Ignored,
+                };
+                messages.push((self.on_message)(message));
+                EditorState {
+                    selection: None,
+                    ..self.state
+                }
+            }
+            _ => return event::Status::Ignored,
+        };
+        self.state = state;
+        messages.push((self.on_message)(EditorMessage::State(state)));
+        event::Status::Captured
+    }
+}
+
+impl<'a, Message: 'a, B> From<GraphEditor<Message>> for Element<'a, Message, Renderer<B>>
+where
+    B: Backend + 'a,
+{
+    fn from(editor: GraphEditor<Message>) -> Self {
+        Element::new(editor)
+    }
+}
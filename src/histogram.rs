@@ -0,0 +1,232 @@
+use std::hash::Hash;
+
+use iced_graphics::{Backend, Primitive, Renderer};
+use iced_native::{
+    event, layout, mouse, Background, Clipboard, Color, Element, Event, Hasher, Layout, Length, Point, Rectangle, Size,
+    Widget,
+};
+
+use crate::entity::Histogram;
+
+/// Height of a bar as a fraction of the height of the chart. On a log scale, rare values stay visible next to common
+/// ones.
+pub fn bar_fraction(count: u32, max: u32, log_scale: bool) -> f32 {
+    if max == 0 {
+        return 0.0;
+    }
+    if log_scale {
+        (f64::from(count).ln_1p() / f64::from(max).ln_1p()) as f32
+    } else {
+        count as f32 / max as f32
+    }
+}
+
+/// The value whose bar is at `x` in a chart of the given width
+pub fn bin_at(x: f32, width: f32) -> u8 {
+    (x / width * 256.0).floor().clamp(0.0, 255.0) as u8
+}
+
+/// How a `HistogramChart` is being interacted with. The application keeps it between frames and replaces it with the
+/// states the chart sends.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChartState {
+    dragged: Option<u8>, // Where the threshold marker is being dragged to
+}
+
+impl ChartState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragged.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ChartMessage {
+    State(ChartState),
+    Threshold(u8), // The marker was dragged to a new value
+}
+
+/// A bar chart of a histogram, with the channels of color images drawn on top of each other. A threshold can be shown
+/// as a marker, which can be dragged to change it.
+pub struct HistogramChart<'a, Message> {
+    histogram: &'a Histogram,
+    log_scale: bool,
+    threshold: Option<u8>,
+    state: ChartState,
+    on_message: Box<dyn Fn(ChartMessage) -> Message>,
+    width: Length,
+    height: Length,
+}
+
+impl<'a, Message> HistogramChart<'a, Message> {
+    const MARKER_REACH: f32 = 5.0; // How close to the marker it can be grabbed, in pixels
+    const BACKGROUND: Color = Color::from_rgb(0.95, 0.95, 0.95);
+    const MARKER: Color = Color::from_rgb(0.95, 0.5, 0.1);
+
+    pub fn new(
+        histogram: &'a Histogram,
+        log_scale: bool,
+        state: ChartState,
+        on_message: impl Fn(ChartMessage) -> Message + 'static,
+    ) -> Self {
+        Self {
+            histogram,
+            log_scale,
+            threshold: None,
+            state,
+            on_message: Box::new(on_message),
+            width: Length::Fill,
+            height: Length::Units(100),
+        }
+    }
+
+    /// Shows a marker at `threshold`
+    pub fn threshold(mut self, threshold: u8) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn width(mut self, width: Length) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: Length) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// The marker is where it's being dragged to, until the new threshold arrives
+    fn marker(&self) -> Option<u8> {
+        self.state.dragged.or(self.threshold)
+    }
+
+    fn marker_x(value: u8, bounds: Rectangle) -> f32 {
+        bounds.x + (f32::from(value) + 0.5) * bounds.width / 256.0
+    }
+
+    fn colors(&self) -> Vec<Color> {
+        match self.histogram.channels.len() {
+            1 => vec![Color::from_rgb(0.3, 0.3, 0.3)],
+            _ => vec![
+                Color::from_rgba(0.9, 0.1, 0.1, 0.5),
+                Color::from_rgba(0.1, 0.7, 0.1, 0.5),
+                Color::from_rgba(0.1, 0.2, 0.9, 0.5),
+            ],
+        }
+    }
+}
+
+fn quad(bounds: Rectangle, color: Color) -> Primitive {
+    Primitive::Quad {
+        bounds,
+        background: Background::Color(color),
+        border_radius: 0.0,
+        border_width: 0.0,
+        border_color: Color::TRANSPARENT,
+    }
+}
+
+impl<'a, Message, B: Backend> Widget<Message, Renderer<B>> for HistogramChart<'a, Message> {
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, _renderer: &Renderer<B>, limits: &layout::Limits) -> layout::Node {
+        layout::Node::new(limits.width(self.width).height(self.height).max())
+    }
+
+    fn draw(
+        &self,
+        _renderer: &mut Renderer<B>,
+        _defaults: &iced_graphics::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> (Primitive, mouse::Interaction) {
+        let bounds = layout.bounds();
+        let max = self.histogram.max();
+        let bin_width = bounds.width / 256.0;
+        let mut primitives = vec![quad(bounds, Self::BACKGROUND)];
+        for (channel, color) in self.histogram.channels.iter().zip(self.colors()) {
+            for (value, &count) in channel.iter().enumerate() {
+                let height = bar_fraction(count, max, self.log_scale) * bounds.height;
+                if height <= 0.0 {
+                    continue;
+                }
+                let top_left = Point::new(bounds.x + value as f32 * bin_width, bounds.y + bounds.height - height);
+                primitives.push(quad(Rectangle::new(top_left, Size::new(bin_width, height)), color));
+            }
+        }
+        let mut interaction = mouse::Interaction::Idle;
+        if let Some(marker) = self.marker() {
+            let x = Self::marker_x(marker, bounds);
+            let line = Rectangle::new(Point::new(x - 1.0, bounds.y), Size::new(2.0, bounds.height));
+            primitives.push(quad(line, Self::MARKER));
+            let over_marker = bounds.contains(cursor_position) && (cursor_position.x - x).abs() <= Self::MARKER_REACH;
+            if self.state.is_dragging() || over_marker {
+                interaction = mouse::Interaction::ResizingHorizontally;
+            }
+        }
+        (Primitive::Group { primitives }, interaction)
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+        self.width.hash(state);
+        self.height.hash(state);
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _renderer: &Renderer<B>,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let marker = match self.threshold {
+            Some(_) => self.marker(),
+            None => return event::Status::Ignored, // Nothing to drag
+        };
+        let value = bin_at(cursor_position.x - bounds.x, bounds.width);
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) if bounds.contains(cursor_position) => {
+                let reach = |marker| (cursor_position.x - Self::marker_x(marker, bounds)).abs() <= Self::MARKER_REACH;
+                if !marker.is_some_and(reach) {
+                    return event::Status::Ignored;
+                }
+                self.state.dragged = marker;
+            }
+            Event::Mouse(mouse::Event::CursorMoved { .. }) if self.state.is_dragging() => {
+                if self.state.dragged == Some(value) {
+                    return event::Status::Captured;
+                }
+                self.state.dragged = Some(value);
+                messages.push((self.on_message)(ChartMessage::Threshold(value)));
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) if self.state.is_dragging() => {
+                self.state.dragged = None;
+            }
+            _ => return event::Status::Ignored,
+        }
+        messages.push((self.on_message)(ChartMessage::State(self.state)));
+        event::Status::Captured
+    }
+}
+
+impl<'a, Message: 'a, B: Backend + 'a> From<HistogramChart<'a, Message>> for Element<'a, Message, Renderer<B>> {
+    fn from(chart: HistogramChart<'a, Message>) -> Self {
+        Element::new(chart)
+    }
+}
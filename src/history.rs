@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    entity::BinaryImage,
+    error::{Context, Result},
+    layer_graph::{DetachedLayer, LayerGraph, LayerId},
+    parameter::ParamValue,
+};
+
+/// A single reversible change to a `LayerGraph`. Layers are referred to by their ids, which stay the same when a
+/// removed layer is restored.
+pub(crate) enum Edit {
+    AddLayer {
+        layer: LayerId,
+        detached: Option<DetachedLayer>, // Holds the layer while the edit is undone
+    },
+    RemoveLayer {
+        layer: LayerId,
+        detached: Option<DetachedLayer>, // Holds the layer while the edit is done
+    },
+    Connect {
+        from: LayerId,
+        output: usize,
+        to: LayerId,
+        port: usize,
+    },
+    Disconnect {
+        from: LayerId,
+        output: usize,
+        to: LayerId,
+        port: usize,
+    },
+    SetParameter {
+        layer: LayerId,
+        name: String,
+        old: ParamValue,
+        new: ParamValue,
+    },
+    Rename {
+        layer: LayerId,
+        old: String,
+        new: String,
+    },
+    Paint {
+        layer: LayerId,
+        canvas: BinaryImage, // What the canvas looks like on the other side of the edit
+    },
+}
+
+impl Edit {
+    fn apply(&mut self, graph: &mut LayerGraph) -> Result<()> {
+        match self {
+            Edit::AddLayer { detached, .. } => Self::attach(graph, detached),
+            Edit::RemoveLayer { layer, detached } => Self::detach(graph, *layer, detached),
+            Edit::Connect { from, output, to, port } => {
+                graph.restore_connection(index(graph, *from)?, *output, index(graph, *to)?, *port)
+            }
+            Edit::Disconnect { to, port, .. } => graph.disconnect(index(graph, *to)?, *port).map(|_| ()),
+            Edit::SetParameter { layer, name, new, .. } => {
+                graph.set_parameter(index(graph, *layer)?, name, new.clone())
+            }
+            Edit::Rename { layer, new, .. } => graph.rename(index(graph, *layer)?, new.clone()),
+            Edit::Paint { layer, canvas } => graph.swap_canvas(index(graph, *layer)?, canvas),
+        }
+    }
+
+    fn revert(&mut self, graph: &mut LayerGraph) -> Result<()> {
+        match self {
+            Edit::AddLayer { layer, detached } => Self::detach(graph, *layer, detached),
+            Edit::RemoveLayer { detached, .. } => Self::attach(graph, detached),
+            Edit::Connect { to, port, .. } => graph.disconnect(index(graph, *to)?, *port).map(|_| ()),
+            Edit::Disconnect { from, output, to, port } => {
+                graph.restore_connection(index(graph, *from)?, *output, index(graph, *to)?, *port)
+            }
+            Edit::SetParameter { layer, name, old, .. } => {
+                graph.set_parameter(index(graph, *layer)?, name, old.clone())
+            }
+            Edit::Rename { layer, old, .. } => graph.rename(index(graph, *layer)?, old.clone()),
+            Edit::Paint { layer, canvas } => graph.swap_canvas(index(graph, *layer)?, canvas),
+        }
+    }
+
+    fn attach(graph: &mut LayerGraph, detached: &mut Option<DetachedLayer>) -> Result<()> {
+        let layer = detached.take().context("Layer to restore is missing from the history")?;
+        graph.attach_layer(layer)?;
+        Ok(())
+    }
+
+    fn detach(graph: &mut LayerGraph, layer: LayerId, detached: &mut Option<DetachedLayer>) -> Result<()> {
+        *detached = Some(graph.detach_layer(index(graph, layer)?)?);
+        Ok(())
+    }
+}
+
+/// Where the layer with an id is in the graph
+fn index(graph: &LayerGraph, layer: LayerId) -> Result<NodeIndex> {
+    graph.find_by_id(layer).context(format!("There is no layer with id {}", layer))
+}
+
+/// The edits making up one user action, undone and redone as a whole
+pub(crate) struct Transaction {
+    pub edits: Vec<Edit>,
+    pub selected_layer: Option<LayerId>, // Selection at the time of the edits, which is restored along with them
+}
+
+impl Transaction {
+    /// Reverts the edits in reverse order
+    pub fn undo(&mut self, graph: &mut LayerGraph) -> Result<()> {
+        for edit in self.edits.iter_mut().rev() {
+            edit.revert(graph)?;
+        }
+        Ok(())
+    }
+
+    pub fn redo(&mut self, graph: &mut LayerGraph) -> Result<()> {
+        for edit in &mut self.edits {
+            edit.apply(graph)?;
+        }
+        Ok(())
+    }
+}
+
+/// Undo and redo stacks of transactions. Only the most recent `depth` transactions can be undone.
+pub(crate) struct History {
+    undo_stack: VecDeque<Transaction>,
+    redo_stack: Vec<Transaction>,
+    depth: usize,
+}
+
+impl History {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            depth,
+        }
+    }
+
+    /// Records a transaction that was just done. Anything that was undone before can't be redone anymore.
+    pub fn push(&mut self, transaction: Transaction) {
+        if transaction.edits.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+        self.undo_stack.push_back(transaction);
+        self.truncate();
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        self.undo_stack.pop_back()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        self.redo_stack.pop()
+    }
+
+    pub fn push_undone(&mut self, transaction: Transaction) {
+        self.redo_stack.push(transaction);
+    }
+
+    pub fn push_redone(&mut self, transaction: Transaction) {
+        self.undo_stack.push_back(transaction);
+        self.truncate();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.truncate();
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    fn truncate(&mut self) {
+        while self.undo_stack.len() > self.depth {
+            self.undo_stack.pop_front();
+        }
+    }
+}
@@ -1,15 +1,258 @@
-use std::any::{self, Any};
+use std::{
+    any::Any,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use anyhow::{Context, Result};
+use crate::{
+    entity::{BinaryImage, Element},
+    error::{bail, ensure, Context, KlexError, Result},
+    parameter::{ParamMap, ParamValue, Parameter},
+    util::BufferPool,
+};
+
+pub type LayerOutput = Option<Box<dyn Any + Send + Sync>>; // Outputs are shared between threads when independent layers are computed in parallel
+
+/// Lets a running computation know that its result isn't wanted anymore. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with `KlexError::Cancelled` once the token was cancelled. Meant to be called every now and then by long
+    /// computations.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(KlexError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// What a layer computes with besides its inputs
+#[derive(Clone, Copy)]
+pub struct ComputeContext<'a> {
+    pub cancel: &'a CancelToken,
+    pub pool: Option<&'a BufferPool>, // Where to take large temporary buffers from, if anywhere
+}
+
+/// The input of a layer that expects a single one, as the element it expects. Fails with a message saying what was
+/// there instead.
+pub fn single_input<'a, A: Element>(input: &[&'a LayerOutput]) -> Result<&'a A> {
+    let input = input.first().context("Missing input")?;
+    let input = input.as_deref().context("Empty input")?;
+    let found = || crate::entity::element_name(input).unwrap_or("an unknown type");
+    let mismatch = || KlexError::TypeMismatch { expected: A::NAME, found: found() };
+    input.downcast_ref::<A>().ok_or_else(mismatch)
+}
+
+/// Like `single_input`, for the element a layer computes in place
+pub fn element_mut<A: Element>(input: &mut dyn Any) -> Result<&mut A> {
+    let found = crate::entity::element_name(input).unwrap_or("an unknown type");
+    let mismatch = KlexError::TypeMismatch { expected: A::NAME, found };
+    input.downcast_mut::<A>().ok_or(mismatch)
+}
+
+/// One of the results of a layer. Layers have a single one unless they override `Layer::outputs`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OutputPort {
+    pub name: &'static str,            // Refers to the port in recipes
+    pub element: Option<&'static str>, // Name of the element produced at the port, if known
+}
+
+impl OutputPort {
+    pub const SINGLE: Self = Self::new("output", None);
+
+    pub const fn new(name: &'static str, element: Option<&'static str>) -> Self {
+        Self { name, element }
+    }
+}
+
+/// What layers with several output ports store as their output, one element for each port
+#[derive(Debug, Default)]
+pub struct Outputs(pub Vec<LayerOutput>);
+
+/// The output at `port` of a layer whose output is `output`. Outputs other than `Outputs` only have port 0.
+pub fn port_output(output: &LayerOutput, port: usize) -> &LayerOutput {
+    match output.as_deref().and_then(|output| output.downcast_ref::<Outputs>()) {
+        Some(Outputs(outputs)) => outputs.get(port).unwrap_or(&None),
+        None if port == 0 => output,
+        None => &None,
+    }
+}
+
+/// What a kind of layer is for, e.g. to group layers in a menu
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LayerCategory {
+    Input, // Brings data into the graph, e.g. from a file
+    #[default]
+    Filter,
+    Convert, // Turns one kind of element into another without changing its content
+    Analyze,
+    Output,
+}
+
+/// How many inputs a layer takes, see `Layer::expected_inputs`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InputCount {
+    #[default]
+    Unknown, // Not checked, e.g. for layers that tell their inputs apart by their elements
+    Exactly(usize), // One at each port of `Layer::input_types`
+    AtLeast(usize), // As many as are connected, at ports from 0 on without gaps, e.g. images to be stacked
+}
+
+impl InputCount {
+    /// How many inputs have to be connected
+    pub fn required(self) -> usize {
+        match self {
+            Self::Unknown => 0,
+            Self::Exactly(count) | Self::AtLeast(count) => count,
+        }
+    }
+}
+
+pub trait Layer: Send + Sync {
+    fn kind(&self) -> String; // Identifies the type of layer, e.g. for constructing it from a recipe
+
+    fn category(&self) -> LayerCategory {
+        LayerCategory::Filter
+    }
 
-pub trait Layer {
     fn compute(
-        &mut self,
-        input: &[&Option<Box<dyn Any>>],
-        output: &mut Option<Box<dyn Any>>,
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
     ) -> Result<()>; // Individual implementation necessary for every struct implementing this
+
+    fn compute_cancellable(
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        _cancel: &CancelToken,
+    ) -> Result<()> {
+        // Layers that take long to compute should override this and call `CancelToken::check` regularly
+        self.compute(input, output)
+    }
+
+    fn compute_in_context(
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        context: ComputeContext,
+    ) -> Result<()> {
+        // Layers that need large temporary buffers should override this and take them from the pool
+        self.compute_cancellable(input, output, context.cancel)
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        // Everything besides the inputs that the output depends on, for keeping outputs in an `OutputCache`. Layers
+        // depending on more than their kind and parameters have to add that, or return None to not be cached.
+        Some(format!("{} {:?}", self.kind(), self.parameters()))
+    }
+
+    fn computes_in_place(&self) -> bool {
+        false // Whether `compute_in_place` is supported
+    }
+
+    fn compute_in_place(&self, _input: &mut dyn Any) -> Result<()> {
+        // Point-wise layers with a single input can turn their input into their output instead of allocating a new
+        // one. The graph only hands over inputs that nothing else reads.
+        bail!("{} can't compute in place", self.kind())
+    }
+
+    fn halo(&self) -> Option<usize> {
+        // How many pixels around a pixel of the output the layer reads from its single image input, e.g. the radius of
+        // a blur, for layers that keep the size of the image. Such layers can be computed tile by tile, see
+        // `LayerGraph::compute_layer_tiled`.
+        None
+    }
+
+    fn input_types(&self) -> Vec<&'static str> {
+        Vec::new() // Names of the elements expected at each input port. Empty if unknown
+    }
+
+    fn expected_inputs(&self) -> InputCount {
+        // Layers taking a varying number of inputs override this, and leave `input_types` empty. Inputs bring data
+        // into the graph, so they take none.
+        match self.input_types().len() {
+            0 if self.category() == LayerCategory::Input => InputCount::Exactly(0),
+            0 => InputCount::Unknown,
+            count => InputCount::Exactly(count),
+        }
+    }
+
+    fn output_type(&self) -> Option<&'static str> {
+        None // Name of the element this layer produces at its first output port, if known
+    }
+
+    fn outputs(&self) -> &[OutputPort] {
+        // Layers with several results override this and store an `Outputs` with one element for each port
+        &[OutputPort::SINGLE]
+    }
+
+    fn output_size_bytes(&self, output: &(dyn Any + Send + Sync)) -> Option<usize> {
+        // Covers the image elements. Layers producing other kinds of output can report their size themselves
+        match output.downcast_ref::<Outputs>() {
+            Some(Outputs(outputs)) => {
+                outputs.iter().flatten().map(|output| self.output_size_bytes(output.as_ref())).sum()
+            }
+            None => crate::entity::size_bytes(output),
+        }
+    }
+
+    fn parameters(&self) -> ParamMap {
+        ParamMap::new() // Default implementation for layers without parameters
+    }
+
+    fn metadata(&self) -> ParamMap {
+        // What a layer found out while computing, e.g. the number of frames of an animation it reads, for showing it
+        // to the user. It can't be changed like the parameters.
+        ParamMap::new()
+    }
+
+    fn set_parameter(&mut self, name: &str, _value: ParamValue) -> Result<()> {
+        bail!(KlexError::UnknownParameter(name.to_string()))
+    }
+
+    fn has_changed(&self) -> Result<bool> {
+        // Whether what an input layer reads changed since it was computed, for sources that watch mode can't notice
+        // through the files of `"path"` parameters, e.g. remote images. Asked regularly while watching, so it has to be
+        // cheap or limit how often it does something expensive.
+        Ok(false)
+    }
+
+    fn scale_parameters(&mut self, _factor: f64) {
+        // Layers with parameters in pixels, e.g. a blur radius, scale them so that a downscaled preview of the input
+        // looks like the full resolution result
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        // Default implementation for layers that can't be duplicated, e.g. because they hold on to a resource
+        bail!("Layer cannot be duplicated")
+    }
+
+    fn canvas_mut(&mut self) -> Option<&mut BinaryImage> {
+        None // Pixels the user paints on, for layers that hold some, e.g. a mask
+    }
 }
 
+#[cfg(feature = "ui")]
 pub trait InteractiveLayer: Layer {
     fn interact(&self) {
         // Default implementation for layers that don't provide special user interation. Can be overwritten to allow for layer-specific user interaction
@@ -21,9 +264,12 @@ pub trait InteractiveLayer: Layer {
 pub mod primitive {
     use super::*;
 
-    use image::{GrayImage, RgbaImage};
+    use image::{GrayImage, Luma, Rgba, RgbaImage};
 
-    use crate::entity::{self, BinaryImage};
+    use crate::{
+        color, draw,
+        entity::{self, Element, Geometry, Gray16Image, GrayImageF32, LabelImage, Sample},
+    };
 
     pub struct Convert<A, B> {
         operation: fn(&A) -> Result<B>,
@@ -36,8 +282,16 @@ pub mod primitive {
             }
         }
 
+        /// Gray with the same luminance as the color of each pixel. Alpha is dropped.
         pub fn compute(input: &RgbaImage) -> Result<GrayImage> {
-            Ok(image::imageops::colorops::grayscale(input))
+            let table = color::SrgbTable::new();
+            let data = input
+                .pixels()
+                .map(|&Rgba([red, green, blue, _])| {
+                    table.to_srgb(color::luminance([red, green, blue].map(|value| table.to_linear(value))))
+                })
+                .collect();
+            GrayImage::from_vec(input.width(), input.height(), data).context("Data cannot be converted to GrayImage")
         }
     }
 
@@ -55,8 +309,7 @@ impl Default for Convert<RgbaImage, GrayImage> {
         }
 
         pub fn compute(input: &BinaryImage) -> Result<GrayImage> {
-            let data = input.data().iter().map(|&pixel| if pixel {u8::MAX} else {u8::MIN}).collect();
-            GrayImage::from_vec(input.width(), input.height(), data).context("Data cannot be converted to GrayImage")
+            GrayImage::try_from(input)
         }
     }
 
@@ -66,27 +319,104 @@ impl Default for Convert<BinaryImage, GrayImage> {
     }
 }
     
-    impl<A: 'static, B: 'static> Layer for Convert<A, B> {
+    impl<A: Element, B: Element> Layer for Convert<A, B> {
+        fn kind(&self) -> String {
+            format!("Convert<{}, {}>", A::NAME, B::NAME)
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![A::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(B::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            Some(0) // Every conversion looks at one pixel at a time
+        }
+
         fn compute(
-            &mut self,
-            input: &[&Option<Box<dyn Any>>],
-            output: &mut Option<Box<dyn Any>>,
+            &self,
+            input: &[&LayerOutput],
+            output: &mut LayerOutput,
         ) -> Result<()> {
-            let input = input[0]; // Convert only expects input from a single source layer
-            let input = input.as_ref().context("Empty input")?;
-            let input = input.downcast_ref::<A>().context(format!(
-                "Casting failed. Expected input of type {:#?}",
-                any::type_name::<A>()
-            ))?;
+            let input = single_input::<A>(input)?; // Convert only expects input from a single source layer
             *output = Some(Box::new((self.operation)(input)?));
             Ok(())
         }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                operation: self.operation,
+            }))
+        }
     }
     
-    impl<A: 'static, B: 'static> InteractiveLayer for Convert<A, B> {}
-    
+    #[cfg(feature = "ui")]
+    impl<A: Element, B: Element> InteractiveLayer for Convert<A, B> {}
+
+    /// Converts between any two elements that are connected in a `color::ConversionGraph`, possibly through other
+    /// elements. Elements with a direct conversion have their own layers, which should be preferred.
+    #[derive(Clone)]
+    pub struct ConvertAny {
+        path: Vec<&'static str>,
+        conversions: Vec<color::Conversion>,
+    }
 
-    pub struct Convolve {}
+    impl ConvertAny {
+        /// Converts along the shortest path of builtin conversions
+        pub fn between(from: &str, to: &str) -> Result<Self> {
+            Self::in_graph(color::ConversionGraph::builtins(), from, to)
+        }
+
+        pub fn in_graph(graph: &color::ConversionGraph, from: &str, to: &str) -> Result<Self> {
+            ensure!(from != to, "Can't convert {} to itself", from);
+            let path = graph.path(from, to).with_context(|| format!("No conversion from {} to {}", from, to))?;
+            let conversions = graph.conversions(&path)?;
+            Ok(Self { path, conversions })
+        }
+
+        /// The elements the input is converted to one after another, starting with the input element itself
+        pub fn path(&self) -> &[&'static str] {
+            &self.path
+        }
+    }
+
+    impl Layer for ConvertAny {
+        fn kind(&self) -> String {
+            format!("Convert<{}, {}>", self.path[0], self.path[self.path.len() - 1])
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![self.path[0]]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            self.path.last().copied()
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = input.first().context("Missing input")?.as_deref().context("Empty input")?;
+            *output = Some(color::convert_along(&self.path, &self.conversions, input)?);
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(self.clone()))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for ConvertAny {}
 
     pub struct InputFile<A> {
         file_path: std::path::PathBuf,
@@ -106,61 +436,3224 @@ impl Default for Convert<BinaryImage, GrayImage> {
         }
     }
 
-    impl<A: 'static> Layer for InputFile<A> {
+    /// Keeps all 16 bits of gray images. Images with fewer bits are scaled up, and colors are converted to gray.
+    impl InputFile<Gray16Image> {
+        pub fn new(file_path: std::path::PathBuf) -> Self {
+            Self {
+                file_path,
+                operation: Self::compute,
+            }
+        }
+
+        pub fn compute(&self) -> Result<Gray16Image> {
+            Ok(image::open(&self.file_path)?.into_luma16())
+        }
+    }
+
+    impl<A: Element> Layer for InputFile<A> {
+        fn kind(&self) -> String {
+            // RGBA images came first, and recipes refer to their layer without the element
+            match A::NAME {
+                RgbaImage::NAME => "InputFile".to_string(),
+                name => format!("InputFile<{}>", name),
+            }
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Input
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(A::NAME)
+        }
+
         fn compute(
-            &mut self,
-            _input: &[&Option<Box<dyn Any>>], // This layer does not depend on other layers
-            output: &mut Option<Box<dyn Any>>,
+            &self,
+            _input: &[&LayerOutput], // This layer does not depend on other layers
+            output: &mut LayerOutput,
         ) -> Result<()> {
             *output = Some(Box::new((self.operation)(self)?));
             Ok(())
         }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([("path".to_string(), self.file_path.to_value())])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "path" => self.file_path = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                file_path: self.file_path.clone(),
+                operation: self.operation,
+            }))
+        }
     }
 
-    impl<A: 'static> InteractiveLayer for InputFile<A> {}
+    #[cfg(feature = "ui")]
+    impl<A: Element> InteractiveLayer for InputFile<A> {}
+
+    #[cfg(feature = "http")]
+    pub use remote::InputUrl;
+
+    #[cfg(feature = "http")]
+    mod remote {
+        use std::{
+            io::Read,
+            sync::Mutex,
+            time::{Duration, Instant},
+        };
+
+        use super::*;
+
+        /// Downloads an image over HTTP(S). Downloads fail if they take longer than the timeout or are larger than the
+        /// maximum size. The ETag and Last-Modified headers of the last download are kept, so that `has_changed` can
+        /// ask the server whether the image changed without downloading it again.
+        pub struct InputUrl<A> {
+            url: String,
+            timeout: f64,  // In seconds, for connecting and for the whole download
+            max_bytes: u32, // Of the downloaded file
+            interval: f64, // Seconds to wait between asking the server in `has_changed`
+            validators: Mutex<Validators>,
+            operation: fn(image::DynamicImage) -> A,
+        }
+
+        /// What the server told about the last downloaded image
+        #[derive(Default)]
+        struct Validators {
+            etag: Option<String>,
+            last_modified: Option<String>,
+            checked: Option<Instant>, // When the server was last asked
+        }
+
+        impl Validators {
+            fn of(response: &ureq::Response) -> Self {
+                Self {
+                    etag: response.header("ETag").map(str::to_string),
+                    last_modified: response.header("Last-Modified").map(str::to_string),
+                    checked: Some(Instant::now()),
+                }
+            }
+        }
+
+        impl InputUrl<RgbaImage> {
+            pub fn new(url: String) -> Self {
+                Self {
+                    url,
+                    timeout: 10.0,
+                    max_bytes: 50 << 20,
+                    interval: 10.0,
+                    validators: Mutex::new(Validators::default()),
+                    operation: image::DynamicImage::into_rgba8,
+                }
+            }
+        }
+
+        impl<A> InputUrl<A> {
+            fn agent(&self) -> ureq::Agent {
+                ureq::AgentBuilder::new().timeout(Duration::from_secs_f64(self.timeout)).build()
+            }
+
+            fn validators(&self) -> Result<std::sync::MutexGuard<'_, Validators>> {
+                self.validators.lock().map_err(|_| KlexError::Poisoned("the validators"))
+            }
+
+            fn download(&self) -> Result<image::DynamicImage> {
+                let response = self.agent().get(&self.url).call()?;
+                let validators = Validators::of(&response);
+                let length = response.header("Content-Length").and_then(|length| length.parse::<u64>().ok());
+                let max_bytes = u64::from(self.max_bytes);
+                if let Some(length) = length.filter(|&length| length > max_bytes) {
+                    bail!("The image has {} bytes, more than the maximum of {}", length, max_bytes);
+                }
+                let mut bytes = Vec::new();
+                let received = response.into_reader().take(max_bytes + 1).read_to_end(&mut bytes);
+                received.map_err(|e| KlexError::msg(format!("Failed to receive the image: {}", e)))?;
+                ensure!(bytes.len() as u64 <= max_bytes, "The image has more than the maximum of {} bytes", max_bytes);
+                let image = image::load_from_memory(&bytes).context("Failed to decode the image")?;
+                *self.validators()? = validators;
+                Ok(image)
+            }
+
+            fn failed_download(&self, error: KlexError) -> KlexError {
+                KlexError::Download { url: self.url.clone(), source: Box::new(error) }
+            }
+        }
+
+        impl<A: Element> Layer for InputUrl<A> {
+            fn kind(&self) -> String {
+                match A::NAME {
+                    RgbaImage::NAME => "InputUrl".to_string(),
+                    name => format!("InputUrl<{}>", name),
+                }
+            }
+
+            fn category(&self) -> LayerCategory {
+                LayerCategory::Input
+            }
+
+            fn output_type(&self) -> Option<&'static str> {
+                Some(A::NAME)
+            }
+
+            fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+                let image = self.download().map_err(|e| self.failed_download(e))?;
+                *output = Some(Box::new((self.operation)(image)));
+                Ok(())
+            }
+
+            fn cache_key(&self) -> Option<String> {
+                None // Depends on what the server sends
+            }
+
+            fn has_changed(&self) -> Result<bool> {
+                // Asks the server at most once per interval. Servers that send neither an ETag nor a Last-Modified
+                // header can't tell, so their images count as changed every time.
+                let mut validators = self.validators()?;
+                let interval = Duration::from_secs_f64(self.interval);
+                if validators.checked.is_some_and(|checked| checked.elapsed() < interval) {
+                    return Ok(false);
+                }
+                validators.checked = Some(Instant::now());
+
+                let mut request = self.agent().head(&self.url);
+                if let Some(etag) = &validators.etag {
+                    request = request.set("If-None-Match", etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.set("If-Modified-Since", last_modified);
+                }
+                let response = request.call().map_err(|e| self.failed_download(e.into()))?;
+                if response.status() == 304 {
+                    return Ok(false); // Not modified
+                }
+                let current = Validators::of(&response);
+                Ok((current.etag.is_none() && current.last_modified.is_none())
+                    || current.etag != validators.etag
+                    || current.last_modified != validators.last_modified)
+            }
+
+            fn parameters(&self) -> ParamMap {
+                ParamMap::from([
+                    ("url".to_string(), self.url.to_value()),
+                    ("timeout".to_string(), self.timeout.to_value()),
+                    ("max_bytes".to_string(), self.max_bytes.to_value()),
+                    ("interval".to_string(), self.interval.to_value()),
+                ])
+            }
+
+            fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+                match name {
+                    "url" => {
+                        self.url = Parameter::from_value(&value)?;
+                        *self.validators.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+                            Validators::default();
+                    }
+                    "timeout" => self.timeout = Parameter::from_value(&value)?,
+                    "max_bytes" => self.max_bytes = Parameter::from_value(&value)?,
+                    "interval" => self.interval = Parameter::from_value(&value)?,
+                    _ => bail!(KlexError::UnknownParameter(name.to_string())),
+                }
+                Ok(())
+            }
+
+            fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+                Ok(Box::new(Self {
+                    url: self.url.clone(),
+                    timeout: self.timeout,
+                    max_bytes: self.max_bytes,
+                    interval: self.interval,
+                    validators: Mutex::new(Validators::default()),
+                    operation: self.operation,
+                }))
+            }
+        }
 
-    pub struct Threshold<A, B, T> {
-        threshold: T,
-        ordering: std::cmp::Ordering,
-        operation: fn(&Self, input: &A) -> B,
+        #[cfg(feature = "ui")]
+        impl<A: Element> InteractiveLayer for InputUrl<A> {}
     }
 
-impl Threshold<GrayImage, entity::BinaryImage, u8> {
-    pub fn new(threshold: u8, ordering: std::cmp::Ordering) -> Self {
-        Self {
-            threshold,
-            ordering,
-            operation: Self::compute,
+    #[cfg(feature = "clipboard")]
+    pub use clipboard::{copy_to_clipboard, ClipboardInput, ClipboardOutput};
+
+    #[cfg(feature = "clipboard")]
+    mod clipboard {
+        use std::sync::Mutex;
+
+        use super::*;
+
+        /// Kept for the whole program, since on some systems, e.g. X11, copied images are only available to other
+        /// programs while the clipboard that copied them is open
+        static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+
+        fn with_clipboard<T>(f: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>) -> Result<T> {
+            let mut clipboard = CLIPBOARD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let clipboard = match &mut *clipboard {
+                Some(clipboard) => clipboard,
+                empty => empty.insert(arboard::Clipboard::new().context("Failed to open the clipboard")?),
+            };
+            match f(clipboard) {
+                Err(arboard::Error::ContentNotAvailable | arboard::Error::ConversionFailure) => {
+                    Err(KlexError::NoClipboardImage)
+                }
+                result => Ok(result.context("Failed to access the clipboard")?),
+            }
+        }
+
+        /// Copies an image element to the system clipboard, as an RGBA image
+        pub fn copy_to_clipboard(element: &dyn Any) -> Result<()> {
+            let image = entity::to_dynamic(element)?.into_rgba8();
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            let data = arboard::ImageData { width, height, bytes: image.into_raw().into() };
+            with_clipboard(|clipboard| clipboard.set_image(data))
+        }
+
+        /// Puts out the image on the system clipboard, e.g. a screenshot. Fails if the clipboard holds anything else,
+        /// like text.
+        #[derive(Clone, Default)]
+        pub struct ClipboardInput {}
+
+        impl ClipboardInput {
+            pub fn new() -> Self {
+                Self {}
+            }
+        }
+
+        impl Layer for ClipboardInput {
+            fn kind(&self) -> String {
+                "ClipboardInput".to_string()
+            }
+
+            fn category(&self) -> LayerCategory {
+                LayerCategory::Input
+            }
+
+            fn output_type(&self) -> Option<&'static str> {
+                Some(RgbaImage::NAME)
+            }
+
+            fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+                let image = with_clipboard(|clipboard| clipboard.get_image())?;
+                let (width, height) = (image.width as u32, image.height as u32);
+                let image = RgbaImage::from_raw(width, height, image.bytes.into_owned())
+                    .context("The image on the clipboard has the wrong number of bytes")?;
+                *output = Some(Box::new(image));
+                Ok(())
+            }
+
+            fn cache_key(&self) -> Option<String> {
+                None // Depends on what was copied
+            }
+
+            fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+                Ok(Box::new(self.clone()))
+            }
+        }
+
+        #[cfg(feature = "ui")]
+        impl InteractiveLayer for ClipboardInput {}
+
+        /// Copies its input image to the system clipboard whenever it is computed, and puts it out as an RGBA image
+        #[derive(Clone, Default)]
+        pub struct ClipboardOutput {}
+
+        impl ClipboardOutput {
+            pub fn new() -> Self {
+                Self {}
+            }
+        }
+
+        impl Layer for ClipboardOutput {
+            fn kind(&self) -> String {
+                "ClipboardOutput".to_string()
+            }
+
+            fn category(&self) -> LayerCategory {
+                LayerCategory::Output
+            }
+
+            fn output_type(&self) -> Option<&'static str> {
+                Some(RgbaImage::NAME)
+            }
+
+            fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+                let input = input.first().context("Missing input")?.as_deref().context("Empty input")?;
+                copy_to_clipboard(input)?;
+                *output = Some(Box::new(entity::to_dynamic(input)?.into_rgba8()));
+                Ok(())
+            }
+
+            fn cache_key(&self) -> Option<String> {
+                None // Copying is what it is for
+            }
+
+            fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+                Ok(Box::new(self.clone()))
+            }
         }
 
+        #[cfg(feature = "ui")]
+        impl InteractiveLayer for ClipboardOutput {}
     }
 
-    pub fn compute(&self, input: &GrayImage) -> entity::BinaryImage {
-        let data = input.pixels().map(|pixel| pixel.0[0].cmp(&self.threshold) == self.ordering).collect();
-        entity::BinaryImage::new(input.width(), input.height(), data)
+    /// Reads one frame of an animation, which is either an animated GIF or a sequence of numbered files like
+    /// `frame_%04d.png`, numbered from 0 or 1. Other images count as a single frame. The frames are decoded when the
+    /// layer is first computed, so that choosing another frame only takes copying it.
+    pub struct InputFrames {
+        path: std::path::PathBuf,
+        frame: u32,
+        frames: std::sync::Mutex<Option<Frames>>, // Decoded from `path`
     }
-}
 
-    
+    enum Frames {
+        Decoded(Vec<(RgbaImage, std::time::Duration)>), // Along with how long each frame is shown
+        Files(Vec<std::path::PathBuf>),                 // Read when they are chosen
+    }
 
-    impl<A: 'static, B: 'static, T> Layer for Threshold<A, B, T> {
-        fn compute(&mut self, input: &[&Option<Box<dyn Any>>], output: &mut Option<Box<dyn Any>>) -> Result<()> {
-            let input = input[0]; // Threshold only expects input from a single source layer
-            let input = input.as_ref().context("Empty input")?;
-            let input = input.downcast_ref::<A>().context(format!(
-                "Casting failed. Expected input of type {:#?}",
-                any::type_name::<A>()
-            ))?;
-            *output = Some(Box::new((self.operation)(self, input)));
-            Ok(())
+    impl Frames {
+        fn open(path: &std::path::Path) -> Result<Self> {
+            use image::AnimationDecoder;
+
+            if let Some(files) = numbered_files(path) {
+                ensure!(!files.is_empty(), "There is no file numbered 0 or 1 for {:?}", path);
+                return Ok(Self::Files(files));
+            }
+            let read = || std::fs::File::open(path).map_err(|source| KlexError::Io { path: path.into(), source });
+            let is_gif = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+            if !is_gif {
+                return Ok(Self::Decoded(vec![(image::open(path)?.into_rgba8(), std::time::Duration::ZERO)]));
+            }
+            let decoder = image::codecs::gif::GifDecoder::new(std::io::BufReader::new(read()?))?;
+            let frames = decoder.into_frames().collect_frames()?;
+            ensure!(!frames.is_empty(), "{:?} has no frames", path);
+            Ok(Self::Decoded(
+                frames
+                    .into_iter()
+                    .map(|frame| {
+                        let (numerator, denominator) = frame.delay().numer_denom_ms();
+                        let delay = f64::from(numerator) / f64::from(denominator.max(1)) / 1000.0;
+                        (frame.into_buffer(), std::time::Duration::from_secs_f64(delay))
+                    })
+                    .collect(),
+            ))
+        }
+
+        fn count(&self) -> usize {
+            match self {
+                Self::Decoded(frames) => frames.len(),
+                Self::Files(files) => files.len(),
+            }
         }
     }
-    
-    impl<A: 'static, B: 'static, T> InteractiveLayer for Threshold<A, B, T> {}
 
+    /// The files of a sequence, if `path` has a placeholder like `%d` or `%04d` for the number of the frame
+    fn numbered_files(path: &std::path::Path) -> Option<Vec<std::path::PathBuf>> {
+        let text = path.to_str()?;
+        let start = text.find('%')?;
+        let digits = text[start + 1..].find('d')?;
+        let width = &text[start + 1..start + 1 + digits];
+        let width: usize = match width {
+            "" => 0,
+            width if width.starts_with('0') => width.parse().ok()?,
+            _ => return None,
+        };
+        let file = |number: usize| {
+            let name = format!("{}{:0width$}{}", &text[..start], number, &text[start + 2 + digits..], width = width);
+            Some(std::path::PathBuf::from(name)).filter(|file| file.is_file())
+        };
+        let first = usize::from(file(0).is_none());
+        Some((first..).map_while(file).collect())
+    }
+
+    impl InputFrames {
+        pub fn new(path: std::path::PathBuf, frame: u32) -> Self {
+            Self {
+                path,
+                frame,
+                frames: std::sync::Mutex::new(None),
+            }
+        }
 
+        /// Number of frames of the animation, once the layer was computed
+        pub fn frame_count(&self) -> Option<usize> {
+            Some(self.frames.lock().ok()?.as_ref()?.count())
+        }
 
-    pub struct TransformAffine<A> {
-        operation: fn(&A) -> Result<A>,
+        /// How long each frame of an animated GIF is shown, once the layer was computed. Frames of sequences of files
+        /// and of other images have no delay.
+        pub fn frame_delays(&self) -> Option<Vec<std::time::Duration>> {
+            let frames = self.frames.lock().ok()?;
+            Some(match frames.as_ref()? {
+                Frames::Decoded(frames) => frames.iter().map(|&(_, delay)| delay).collect(),
+                Frames::Files(files) => vec![std::time::Duration::ZERO; files.len()],
+            })
+        }
     }
+
+    impl Layer for InputFrames {
+        fn kind(&self) -> String {
+            "InputFrames".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Input
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(RgbaImage::NAME)
+        }
+
+        fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let mut frames = self.frames.lock().map_err(|_| crate::error::KlexError::Poisoned("the frames"))?;
+            if frames.is_none() {
+                *frames = Some(Frames::open(&self.path).context(format!("Failed to read frames of {:?}", self.path))?);
+            }
+            let frames = frames.as_ref().expect("Frames were just read");
+            let count = frames.count();
+            ensure!((self.frame as usize) < count, "There is no frame {}, there are {}", self.frame, count);
+            let image = match frames {
+                Frames::Decoded(frames) => frames[self.frame as usize].0.clone(),
+                Frames::Files(files) => image::open(&files[self.frame as usize])?.into_rgba8(),
+            };
+            *output = Some(Box::new(image));
+            Ok(())
+        }
+
+        fn metadata(&self) -> ParamMap {
+            let (count, delays) = match (self.frame_count(), self.frame_delays()) {
+                (Some(count), Some(delays)) => (count, delays),
+                _ => return ParamMap::new(),
+            };
+            let delay = delays.get(self.frame as usize).copied().unwrap_or_default();
+            ParamMap::from([
+                ("frame_count".to_string(), ParamValue::Int(count as i64)),
+                ("delay".to_string(), ParamValue::Float(delay.as_secs_f64())),
+            ])
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("path".to_string(), self.path.to_value()),
+                ("frame".to_string(), self.frame.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "path" => {
+                    self.path = Parameter::from_value(&value)?;
+                    *self.frames.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+                }
+                "frame" => self.frame = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.path.clone(), self.frame)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for InputFrames {}
+
+    /// Reads a choice between the variants of an enum, which are named like their `Debug` output
+    fn choice<T: fmt::Debug + Copy>(value: &ParamValue, variants: &[T], what: &str) -> Result<T> {
+        match value {
+            ParamValue::Choice(choice) | ParamValue::Text(choice) => variants
+                .iter()
+                .copied()
+                .find(|variant| format!("{:?}", variant) == *choice)
+                .with_context(|| format!("Unknown {} {:?}. Expected one of {:?}", what, choice, variants)),
+            _ => bail!(KlexError::invalid_value(format!("a {}", what), value)),
+        }
+    }
+
+    /// Which pixels a threshold selects. Pixels at the threshold are only selected by the modes that say so, e.g.
+    /// `GreaterEqual` but not `Greater`. At the ends of the range of values, some modes select all pixels or none,
+    /// e.g. `LessEqual` with a threshold of 255 or `Less` with a threshold of 0.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ThresholdMode {
+        Greater,
+        GreaterEqual,
+        Less,
+        LessEqual,
+        Equal,
+        NotEqual,
+    }
+
+    impl ThresholdMode {
+        pub const ALL: [Self; 6] = [
+            Self::Greater,
+            Self::GreaterEqual,
+            Self::Less,
+            Self::LessEqual,
+            Self::Equal,
+            Self::NotEqual,
+        ];
+
+        /// Values that can't be compared, like NaN, are only selected by `NotEqual`
+        pub fn selects<T: PartialOrd>(self, value: &T, threshold: &T) -> bool {
+            match self {
+                Self::Greater => value > threshold,
+                Self::GreaterEqual => value >= threshold,
+                Self::Less => value < threshold,
+                Self::LessEqual => value <= threshold,
+                Self::Equal => value == threshold,
+                Self::NotEqual => value != threshold,
+            }
+        }
+    }
+
+    /// The mode selecting the pixels that compare to the threshold like that
+    impl From<std::cmp::Ordering> for ThresholdMode {
+        fn from(ordering: std::cmp::Ordering) -> Self {
+            match ordering {
+                std::cmp::Ordering::Less => Self::Less,
+                std::cmp::Ordering::Equal => Self::Equal,
+                std::cmp::Ordering::Greater => Self::Greater,
+            }
+        }
+    }
+
+    impl Parameter for ThresholdMode {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "threshold mode")
+        }
+    }
+
+    /// Images that `Threshold` can binarize, pixel by pixel. This is how a layer is extended to new elements: the
+    /// layer is generic over a trait describing what it needs from its input, and each element implements that trait,
+    /// instead of the layer gaining a type parameter per element it reads or writes.
+    pub trait ThresholdableImage: Element {
+        /// The type of the pixel values, which the threshold shares
+        type Sample: Parameter + PartialOrd + Copy + Send + Sync + 'static;
+
+        fn samples(&self) -> &[Self::Sample];
+        fn size(&self) -> (u32, u32);
+
+        /// A binary image of the same size, from one value per pixel in row major order
+        fn binary(&self, data: Vec<bool>) -> entity::BinaryImage {
+            let (width, height) = self.size();
+            entity::BinaryImage::new(width, height, data)
+        }
+    }
+
+    /// `GrayImage`, `Gray16Image` and `GrayImageF32`
+    impl<S> ThresholdableImage for image::ImageBuffer<image::Luma<S>, Vec<S>>
+    where
+        S: image::Primitive + Parameter + Send + Sync + 'static,
+        Self: Element,
+    {
+        type Sample = S;
+
+        fn samples(&self) -> &[S] {
+            self.as_raw()
+        }
+
+        fn size(&self) -> (u32, u32) {
+            self.dimensions()
+        }
+    }
+
+    pub struct Threshold<I: ThresholdableImage> {
+        threshold: I::Sample,
+        mode: ThresholdMode,
+    }
+
+    impl Threshold<GrayImage> {
+        pub fn new(threshold: u8, mode: impl Into<ThresholdMode>) -> Self {
+            Self::with_threshold(threshold, mode)
+        }
+    }
+
+    impl<I: ThresholdableImage> Threshold<I> {
+        /// Like `new`, for any image that can be thresholded
+        pub fn with_threshold(threshold: I::Sample, mode: impl Into<ThresholdMode>) -> Self {
+            Self {
+                threshold,
+                mode: mode.into(),
+            }
+        }
+
+        pub fn compute(&self, input: &I) -> entity::BinaryImage {
+            input.binary(input.samples().iter().map(|value| self.mode.selects(value, &self.threshold)).collect())
+        }
+    }
+
+    impl<I: ThresholdableImage> Layer for Threshold<I> {
+        fn kind(&self) -> String {
+            // 8 bit images came first, and recipes refer to their layer without the element
+            match I::NAME {
+                GrayImage::NAME => "Threshold".to_string(),
+                name => format!("Threshold<{}>", name),
+            }
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![I::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(entity::BinaryImage::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            Some(0)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<I>(input)?; // Threshold only expects input from a single source layer
+            *output = Some(Box::new(Threshold::compute(self, input)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("threshold".to_string(), self.threshold.to_value()),
+                ("ordering".to_string(), self.mode.to_value()), // The name recipes already use
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "threshold" => self.threshold = Parameter::from_value(&value)?,
+                "ordering" => self.mode = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::with_threshold(self.threshold, self.mode)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl<I: ThresholdableImage> InteractiveLayer for Threshold<I> {}
+
+    /// Which range of 16 bit values `Window` spreads over the 8 bits of its output
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum WindowMode {
+        Full,       // All values, as if the image had been saved with 8 bits
+        MinMax,     // From the darkest to the brightest pixel
+        Percentile, // Ignoring the darkest and brightest pixels, see `Window::percentile`
+    }
+
+    impl WindowMode {
+        pub const ALL: [Self; 3] = [Self::Full, Self::MinMax, Self::Percentile];
+    }
+
+    impl Parameter for WindowMode {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "window mode")
+        }
+    }
+
+    /// Converts 16 bit gray images to 8 bits by mapping a window of values linearly to the full range of 8 bits.
+    /// Values outside of the window are clamped.
+    pub struct Window {
+        mode: WindowMode,
+        percentile: f64, // Percentage of pixels left out at either end with `WindowMode::Percentile`
+    }
+
+    impl Window {
+        pub fn new(mode: WindowMode, percentile: f64) -> Self {
+            Self { mode, percentile }
+        }
+
+        /// The lowest and highest value of the window
+        pub fn bounds(&self, input: &Gray16Image) -> (u16, u16) {
+            self.bounds_with(input, None)
+        }
+
+        fn bounds_with(&self, input: &Gray16Image, pool: Option<&BufferPool>) -> (u16, u16) {
+            let values = input.as_raw();
+            let clipped = match self.mode {
+                WindowMode::Full => return (u16::MIN, u16::MAX),
+                WindowMode::MinMax => 0,
+                WindowMode::Percentile => (self.percentile.clamp(0.0, 50.0) / 100.0 * values.len() as f64) as usize,
+            };
+            if values.is_empty() {
+                return (u16::MIN, u16::MAX);
+            }
+            let mut counts = BufferPool::take_from::<usize>(pool, usize::from(u16::MAX) + 1);
+            for &value in values {
+                counts[usize::from(value)] += 1;
+            }
+            // The first value with more than `clipped` pixels at or beyond it, from either end
+            let bound = |values: &mut dyn Iterator<Item = usize>| {
+                let mut seen = 0;
+                for value in values {
+                    seen += counts[value];
+                    if seen > clipped {
+                        return value as u16;
+                    }
+                }
+                unreachable!("Fewer pixels are clipped than there are")
+            };
+            let low = bound(&mut (0..counts.len()));
+            let high = bound(&mut (0..counts.len()).rev());
+            (low, high.max(low))
+        }
+
+        pub fn compute(&self, input: &Gray16Image) -> GrayImage {
+            self.compute_with(input, None)
+        }
+
+        fn compute_with(&self, input: &Gray16Image, pool: Option<&BufferPool>) -> GrayImage {
+            let (low, high) = self.bounds_with(input, pool);
+            let scale = f64::from(u8::MAX) / f64::from((high - low).max(1));
+            let data = input.as_raw().iter().map(|&value| {
+                (f64::from(value.clamp(low, high) - low) * scale).round() as u8
+            });
+            GrayImage::from_raw(input.width(), input.height(), data.collect()).expect("Sizes match")
+        }
+    }
+
+    impl Layer for Window {
+        fn kind(&self) -> String {
+            format!("Convert<{}, {}>", Gray16Image::NAME, GrayImage::NAME)
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![Gray16Image::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(GrayImage::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            // The other modes look at the values of the whole image
+            (self.mode == WindowMode::Full).then_some(0)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<Gray16Image>(input)?; // Window only expects input from a single source layer
+            *output = Some(Box::new(Window::compute(self, input)));
+            Ok(())
+        }
+
+        fn compute_in_context(
+            &self,
+            input: &[&LayerOutput],
+            output: &mut LayerOutput,
+            context: ComputeContext,
+        ) -> Result<()> {
+            let input = single_input::<Gray16Image>(input)?;
+            *output = Some(Box::new(self.compute_with(input, context.pool)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("window".to_string(), self.mode.to_value()),
+                ("percentile".to_string(), self.percentile.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "window" => self.mode = Parameter::from_value(&value)?,
+                "percentile" => self.percentile = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.mode, self.percentile)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Window {}
+
+    /// How `ToFloat` maps 8 bit values to floating point ones
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum FloatScale {
+        Normalized, // From 0 to 1
+        Raw,        // From 0 to 255
+    }
+
+    impl FloatScale {
+        pub const ALL: [Self; 2] = [Self::Normalized, Self::Raw];
+    }
+
+    impl Parameter for FloatScale {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "float scale")
+        }
+    }
+
+    /// Converts gray images to floating point ones, so that layers can work on them without rounding in between
+    pub struct ToFloat {
+        scale: FloatScale,
+    }
+
+    impl ToFloat {
+        pub fn new(scale: FloatScale) -> Self {
+            Self { scale }
+        }
+
+        pub fn compute(&self, input: &GrayImage) -> GrayImageF32 {
+            let divisor = match self.scale {
+                FloatScale::Normalized => f32::from(u8::MAX),
+                FloatScale::Raw => 1.0,
+            };
+            let data = input.as_raw().iter().map(|&value| f32::from(value) / divisor).collect();
+            GrayImageF32::from_raw(input.width(), input.height(), data).expect("Sizes match")
+        }
+    }
+
+    impl Layer for ToFloat {
+        fn kind(&self) -> String {
+            format!("Convert<{}, {}>", GrayImage::NAME, GrayImageF32::NAME)
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![GrayImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(GrayImageF32::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            Some(0)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<GrayImage>(input)?; // ToFloat only expects input from a single source layer
+            *output = Some(Box::new(ToFloat::compute(self, input)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([("scale".to_string(), self.scale.to_value())])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "scale" => self.scale = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.scale)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for ToFloat {}
+
+    /// Which range of values `Normalize` spreads over the 8 bits of its output
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum FloatRange {
+        MinMax, // From the lowest to the highest finite value in the image
+        Fixed,  // From `Normalize::min` to `Normalize::max`
+    }
+
+    impl FloatRange {
+        pub const ALL: [Self; 2] = [Self::MinMax, Self::Fixed];
+    }
+
+    impl Parameter for FloatRange {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "float range")
+        }
+    }
+
+    /// Converts floating point images to 8 bit gray ones by mapping a range of values linearly to 0 to 255. Values
+    /// outside of the range are clamped to it, as are infinities, while NaN becomes 0.
+    pub struct Normalize {
+        range: FloatRange,
+        min: f64,
+        max: f64,
+    }
+
+    impl Normalize {
+        pub fn new(range: FloatRange, min: f64, max: f64) -> Self {
+            Self { range, min, max }
+        }
+
+        /// The values mapped to 0 and 255. Images without finite values get the range from 0 to 1.
+        pub fn bounds(&self, input: &GrayImageF32) -> (f32, f32) {
+            match self.range {
+                FloatRange::Fixed => (self.min as f32, self.max as f32),
+                FloatRange::MinMax => {
+                    let finite = input.as_raw().iter().copied().filter(|value| value.is_finite());
+                    let bounds = finite.fold(None, |bounds, value| match bounds {
+                        None => Some((value, value)),
+                        Some((low, high)) => Some((value.min(low), value.max(high))),
+                    });
+                    bounds.unwrap_or((0.0, 1.0))
+                }
+            }
+        }
+
+        pub fn compute(&self, input: &GrayImageF32) -> GrayImage {
+            let (low, high) = self.bounds(input);
+            let span = (high - low).max(f32::MIN_POSITIVE); // A step at `low` for empty ranges
+            let data = input.as_raw().iter().map(|&value| match value.is_nan() {
+                true => u8::MIN,
+                false => (((value - low) / span).clamp(0.0, 1.0) * f32::from(u8::MAX)).round() as u8,
+            });
+            GrayImage::from_raw(input.width(), input.height(), data.collect()).expect("Sizes match")
+        }
+    }
+
+    impl Layer for Normalize {
+        fn kind(&self) -> String {
+            format!("Convert<{}, {}>", GrayImageF32::NAME, GrayImage::NAME)
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![GrayImageF32::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(GrayImage::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            (self.range == FloatRange::Fixed).then_some(0) // The range of a whole image isn't that of a tile
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<GrayImageF32>(input)?; // Normalize only expects input from a single source layer
+            *output = Some(Box::new(Normalize::compute(self, input)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("range".to_string(), self.range.to_value()),
+                ("min".to_string(), self.min.to_value()),
+                ("max".to_string(), self.max.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "range" => self.range = Parameter::from_value(&value)?,
+                "min" => self.min = Parameter::from_value(&value)?,
+                "max" => self.max = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.range, self.min, self.max)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Normalize {}
+
+    /// Inverts a gray image, so that black becomes white and vice versa
+    pub struct Invert<A> {
+        operation: fn(&mut A),
+    }
+
+    impl<S: Sample> Invert<image::ImageBuffer<image::Luma<S>, Vec<S>>> {
+        pub fn new() -> Self {
+            Self {
+                operation: Self::invert,
+            }
+        }
+
+        pub fn compute(
+            &self,
+            input: &image::ImageBuffer<image::Luma<S>, Vec<S>>,
+        ) -> image::ImageBuffer<image::Luma<S>, Vec<S>> {
+            let mut output = input.clone();
+            Self::invert(&mut output);
+            output
+        }
+
+        fn invert(image: &mut image::ImageBuffer<image::Luma<S>, Vec<S>>) {
+            image.iter_mut().for_each(|value| *value = S::MAX - *value);
+        }
+    }
+
+    impl<S: Sample> Default for Invert<image::ImageBuffer<image::Luma<S>, Vec<S>>> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<A: Element + Clone> Layer for Invert<A> {
+        fn kind(&self) -> String {
+            format!("Invert<{}>", A::NAME)
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![A::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(A::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            Some(0)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let mut image = single_input::<A>(input)?.clone();
+            (self.operation)(&mut image);
+            *output = Some(Box::new(image));
+            Ok(())
+        }
+
+        fn computes_in_place(&self) -> bool {
+            true
+        }
+
+        fn compute_in_place(&self, input: &mut dyn Any) -> Result<()> {
+            (self.operation)(element_mut::<A>(input)?);
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                operation: self.operation,
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl<A: Element + Clone> InteractiveLayer for Invert<A> {}
+
+    /// Cuts a rectangle out of an image. A width or height of 0 reaches to the edge of the image, and a rectangle that
+    /// doesn't fit is clamped to the image.
+    pub struct Crop<A> {
+        rect: entity::Rect, // Sizes of 0 reach to the edge of the image
+        operation: fn(&Self, &A) -> Result<A>,
+    }
+
+    impl<A> Crop<A> {
+        /// The part of an image of the given size that is cut out. At least one pixel is kept, so a crop that starts
+        /// outside of the image keeps the last row or column.
+        pub fn rectangle(&self, image_width: u32, image_height: u32) -> Result<entity::Rect> {
+            if image_width == 0 || image_height == 0 {
+                bail!("Can't crop an empty image");
+            }
+            let (x, y) = (self.rect.x.min(image_width - 1), self.rect.y.min(image_height - 1));
+            let reach = |size: u32| if size == 0 { u32::MAX } else { size };
+            let rect = entity::Rect::new(x, y, reach(self.rect.width), reach(self.rect.height));
+            Ok(rect.clamped((image_width, image_height)))
+        }
+    }
+
+    impl<P: image::Pixel + 'static> Crop<image::ImageBuffer<P, Vec<P::Subpixel>>> {
+        pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+            Self {
+                rect: entity::Rect::new(x, y, width, height),
+                operation: Self::compute,
+            }
+        }
+
+        pub fn compute(
+            &self,
+            input: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+        ) -> Result<image::ImageBuffer<P, Vec<P::Subpixel>>> {
+            let rect = self.rectangle(input.width(), input.height())?;
+            Ok(image::imageops::crop_imm(input, rect.x, rect.y, rect.width, rect.height).to_image())
+        }
+    }
+
+    impl<A: Element> Layer for Crop<A> {
+        fn kind(&self) -> String {
+            format!("Crop<{}>", A::NAME)
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![A::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(A::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<A>(input)?; // Crop only expects input from a single source layer
+            *output = Some(Box::new((self.operation)(self, input)?));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("x".to_string(), self.rect.x.to_value()),
+                ("y".to_string(), self.rect.y.to_value()),
+                ("width".to_string(), self.rect.width.to_value()),
+                ("height".to_string(), self.rect.height.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "x" => self.rect.x = Parameter::from_value(&value)?,
+                "y" => self.rect.y = Parameter::from_value(&value)?,
+                "width" => self.rect.width = Parameter::from_value(&value)?,
+                "height" => self.rect.height = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn scale_parameters(&mut self, factor: f64) {
+            let scale = |value: u32| (f64::from(value) * factor).round() as u32;
+            let rect = &mut self.rect;
+            (rect.x, rect.y) = (scale(rect.x), scale(rect.y));
+            // A crop that isn't empty at full resolution isn't empty in the preview either
+            let scale_size = |size: u32| if size == 0 { 0 } else { scale(size).max(1) };
+            (rect.width, rect.height) = (scale_size(rect.width), scale_size(rect.height));
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                rect: self.rect,
+                operation: self.operation,
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl<A: Element> InteractiveLayer for Crop<A> {}
+
+    /// A mask the user paints on. The painted pixels aren't parameters, so only the size of the mask ends up in
+    /// recipes.
+    pub struct PaintedMask {
+        mask: BinaryImage,
+    }
+
+    impl PaintedMask {
+        pub fn new(width: u32, height: u32) -> Self {
+            let data = vec![false; width as usize * height as usize];
+            Self {
+                mask: BinaryImage::new(width, height, data),
+            }
+        }
+
+        /// Changes the size of the mask, keeping what was painted where the old and new mask overlap
+        fn resize(&mut self, width: u32, height: u32) {
+            let old = &self.mask;
+            let painted = |x: u32, y: u32| old.get(x, y).copied().unwrap_or(false);
+            let data = (0..height).flat_map(|y| (0..width).map(move |x| painted(x, y))).collect();
+            self.mask = BinaryImage::new(width, height, data);
+        }
+    }
+
+    impl Layer for PaintedMask {
+        fn kind(&self) -> String {
+            "PaintedMask".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Input
+        }
+
+        fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            *output = Some(Box::new(self.mask.clone()));
+            Ok(())
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(BinaryImage::NAME)
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("width".to_string(), self.mask.width().to_value()),
+                ("height".to_string(), self.mask.height().to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            let (width, height) = match name {
+                "width" => (Parameter::from_value(&value)?, self.mask.height()),
+                "height" => (self.mask.width(), Parameter::from_value(&value)?),
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            };
+            self.resize(width, height);
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                mask: self.mask.clone(),
+            }))
+        }
+
+        fn canvas_mut(&mut self) -> Option<&mut BinaryImage> {
+            Some(&mut self.mask)
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for PaintedMask {}
+
+    /// Traces the outlines of the regions of set pixels in a binary image, along the edges of the pixels. Holes in a
+    /// region get outlines of their own.
+    pub struct Contours {}
+
+    impl Contours {
+        pub fn new() -> Self {
+            Self {}
+        }
+
+        pub fn compute(input: &BinaryImage) -> Vec<entity::Contour> {
+            Self::compute_with(input, None)
+        }
+
+        fn compute_with(input: &BinaryImage, pool: Option<&BufferPool>) -> Vec<entity::Contour> {
+            // Steps from one pixel corner to the next, clockwise starting upwards
+            const STEPS: [(i64, i64); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+            let (width, height) = (i64::from(input.width()), i64::from(input.height()));
+            let set = |x: i64, y: i64| {
+                (0..width).contains(&x) && (0..height).contains(&y) && input.data()[(y * width + x) as usize]
+            };
+            // Edges between set and unset pixels, directed so that the set pixel is on their right. Every corner then
+            // has as many edges leaving it as reaching it, so following the edges always leads back to the start.
+            // Each corner stores the directions its edges leave in as bits, so that there's no allocation per corner.
+            let index = |(x, y): (i64, i64)| (y * (width + 1) + x) as usize;
+            let mut edges = BufferPool::take_from::<u8>(pool, index((width + 1, height)));
+            for y in 0..height {
+                for x in 0..width {
+                    if !set(x, y) {
+                        continue;
+                    }
+                    let sides = [
+                        ((x, y - 1), (x, y), 1),
+                        ((x + 1, y), (x + 1, y), 2),
+                        ((x, y + 1), (x + 1, y + 1), 3),
+                        ((x - 1, y), (x, y + 1), 0),
+                    ];
+                    for ((neighbor_x, neighbor_y), start, direction) in sides {
+                        if !set(neighbor_x, neighbor_y) {
+                            edges[index(start)] |= 1 << direction;
+                        }
+                    }
+                }
+            }
+
+            let mut contours = Vec::new();
+            for start in 0..edges.len() {
+                let start_corner = (start as i64 % (width + 1), start as i64 / (width + 1));
+                while edges[start] != 0 {
+                    let mut direction = edges[start].trailing_zeros() as usize;
+                    let (mut corner, mut corners) = (start_corner, vec![start_corner]);
+                    loop {
+                        edges[index(corner)] &= !(1 << direction);
+                        corner = (corner.0 + STEPS[direction].0, corner.1 + STEPS[direction].1);
+                        if corner == start_corner {
+                            break;
+                        }
+                        corners.push(corner);
+                        // Turning right first keeps regions apart that only touch diagonally
+                        let leaves = |next: &usize| edges[index(corner)] & (1 << next) != 0;
+                        match [1, 0, 3].map(|turn| (direction + turn) % 4).into_iter().find(leaves) {
+                            Some(next) => direction = next,
+                            None => break,
+                        }
+                    }
+                    // Only the corners where the outline turns are kept
+                    let count = corners.len();
+                    let points = (0..count)
+                        .filter(|&i| {
+                            let (previous, current, next) =
+                                (corners[(i + count - 1) % count], corners[i], corners[(i + 1) % count]);
+                            let incoming = (current.0 - previous.0, current.1 - previous.1);
+                            let outgoing = (next.0 - current.0, next.1 - current.1);
+                            incoming.0 * outgoing.1 != incoming.1 * outgoing.0
+                        })
+                        .map(|i| (corners[i].0 as f32, corners[i].1 as f32))
+                        .collect();
+                    contours.push(entity::Contour { points });
+                }
+            }
+            contours
+        }
+    }
+
+    impl Default for Contours {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Layer for Contours {
+        fn kind(&self) -> String {
+            "Contours".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Analyze
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![BinaryImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(Vec::<entity::Contour>::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<BinaryImage>(input)?; // Contours only expects a single input
+            *output = Some(Box::new(Self::compute(input)));
+            Ok(())
+        }
+
+        fn compute_in_context(
+            &self,
+            input: &[&LayerOutput],
+            output: &mut LayerOutput,
+            context: ComputeContext,
+        ) -> Result<()> {
+            let input = single_input::<BinaryImage>(input)?;
+            *output = Some(Box::new(Self::compute_with(input, context.pool)));
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new()))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Contours {}
+
+    /// Splits a color image into a gray image for each of its channels
+    pub struct SplitChannels;
+
+    impl SplitChannels {
+        const OUTPUTS: [OutputPort; 4] = [
+            OutputPort::new("red", Some(GrayImage::NAME)),
+            OutputPort::new("green", Some(GrayImage::NAME)),
+            OutputPort::new("blue", Some(GrayImage::NAME)),
+            OutputPort::new("alpha", Some(GrayImage::NAME)),
+        ];
+
+        pub fn compute(input: &RgbaImage) -> [GrayImage; 4] {
+            [0, 1, 2, 3].map(|channel| {
+                GrayImage::from_fn(input.width(), input.height(), |x, y| Luma([input.get_pixel(x, y)[channel]]))
+            })
+        }
+    }
+
+    impl Layer for SplitChannels {
+        fn kind(&self) -> String {
+            "SplitChannels".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Convert
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![RgbaImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(GrayImage::NAME)
+        }
+
+        fn outputs(&self) -> &[OutputPort] {
+            &Self::OUTPUTS
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<RgbaImage>(input)?;
+            let channels = Self::compute(input).map(|channel| Some(Box::new(channel) as Box<dyn Any + Send + Sync>));
+            *output = Some(Box::new(Outputs(channels.into())));
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for SplitChannels {}
+
+    /// How `WhiteBalance` finds the gains of the color channels
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum WhiteBalanceMethod {
+        GrayWorld,  // The average color becomes gray
+        WhitePatch, // The brightest values of each channel become white, ignoring a percentile of outliers
+        Fixed,      // The gains are parameters, e.g. ones that were estimated from another image
+    }
+
+    impl WhiteBalanceMethod {
+        pub const ALL: [Self; 3] = [Self::GrayWorld, Self::WhitePatch, Self::Fixed];
+    }
+
+    impl Parameter for WhiteBalanceMethod {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "white balance method")
+        }
+    }
+
+    /// Multiplies the red, green and blue channels of a color image with gains, which are estimated from the image or
+    /// fixed. Values are clamped to 255 and alpha is kept. Fully transparent pixels don't count towards the estimate.
+    /// The gains of the last computation can be read with `gains`, and passed to `with_gains` to balance other images,
+    /// e.g. the rest of a batch, the same way.
+    pub struct WhiteBalance {
+        method: WhiteBalanceMethod,
+        percentile: f64,                           // Of the brightest values ignored with `WhitePatch`
+        fixed: [f32; 3],                           // Gains with `WhiteBalanceMethod::Fixed`
+        gains: std::sync::Mutex<Option<[f32; 3]>>, // Of the last computation
+    }
+
+    impl WhiteBalance {
+        /// Gains are limited to this, so that channels that are nearly black don't turn into noise
+        pub const MAX_GAIN: f32 = 16.0;
+
+        pub fn new(method: WhiteBalanceMethod, percentile: f64) -> Self {
+            Self {
+                method,
+                percentile,
+                fixed: [1.0; 3],
+                gains: std::sync::Mutex::new(None),
+            }
+        }
+
+        /// Applies the same gains to every image
+        pub fn with_gains(gains: [f32; 3]) -> Self {
+            Self {
+                fixed: gains,
+                ..Self::new(WhiteBalanceMethod::Fixed, 1.0)
+            }
+        }
+
+        /// The gains of red, green and blue that were applied by the last computation
+        pub fn gains(&self) -> Option<[f32; 3]> {
+            *self.gains.lock().ok()?
+        }
+
+        /// The gains of red, green and blue for an image. Channels without any light get a gain of 1, since nothing
+        /// can be said about them, as do all channels of images without opaque pixels.
+        pub fn estimate(&self, input: &RgbaImage) -> [f32; 3] {
+            if self.method == WhiteBalanceMethod::Fixed {
+                return self.fixed;
+            }
+            let mut counts = [[0_usize; 256]; 3];
+            for pixel in input.pixels().filter(|pixel| pixel[3] > 0) {
+                for channel in 0..3 {
+                    counts[channel][usize::from(pixel[channel])] += 1;
+                }
+            }
+            let pixels: usize = counts[0].iter().sum();
+            let (levels, target) = match self.method {
+                _ if pixels == 0 => return [1.0; 3],
+                WhiteBalanceMethod::GrayWorld => {
+                    let means = counts.map(|counts| {
+                        let sum: usize = counts.iter().enumerate().map(|(value, &count)| value * count).sum();
+                        sum as f32 / pixels as f32
+                    });
+                    (means, means.iter().sum::<f32>() / 3.0)
+                }
+                _ => {
+                    // White patch, as fixed gains were returned above. The brightest value of each channel is the
+                    // highest one with more than `ignored` values at or above it.
+                    let ignored = (self.percentile.clamp(0.0, 50.0) / 100.0 * pixels as f64) as usize;
+                    let brightest = counts.map(|counts| {
+                        let mut seen = 0;
+                        let value = (0..counts.len()).rev().find(|&value| {
+                            seen += counts[value];
+                            seen > ignored
+                        });
+                        value.unwrap_or(0) as f32
+                    });
+                    (brightest, f32::from(u8::MAX))
+                }
+            };
+            levels.map(|level| match level > 0.0 {
+                true => (target / level).min(Self::MAX_GAIN),
+                false => 1.0,
+            })
+        }
+
+        pub fn compute(&self, input: &RgbaImage) -> RgbaImage {
+            let mut output = input.clone();
+            self.balance(&mut output);
+            output
+        }
+
+        fn balance(&self, image: &mut RgbaImage) {
+            let gains = self.estimate(image);
+            let tables = gains.map(|gain| {
+                let mut table = [0_u8; 256];
+                for (value, balanced) in table.iter_mut().enumerate() {
+                    *balanced = (value as f32 * gain).round().clamp(0.0, f32::from(u8::MAX)) as u8;
+                }
+                table
+            });
+            for pixel in image.pixels_mut() {
+                for channel in 0..3 {
+                    pixel[channel] = tables[channel][usize::from(pixel[channel])];
+                }
+            }
+            if let Ok(mut last) = self.gains.lock() {
+                *last = Some(gains);
+            }
+        }
+    }
+
+    impl Layer for WhiteBalance {
+        fn kind(&self) -> String {
+            "WhiteBalance".to_string()
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![RgbaImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(RgbaImage::NAME)
+        }
+
+        fn halo(&self) -> Option<usize> {
+            // The estimates look at the colors of the whole image
+            (self.method == WhiteBalanceMethod::Fixed).then_some(0)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<RgbaImage>(input)?; // WhiteBalance only expects input from a single source layer
+            *output = Some(Box::new(WhiteBalance::compute(self, input)));
+            Ok(())
+        }
+
+        fn computes_in_place(&self) -> bool {
+            true
+        }
+
+        fn compute_in_place(&self, input: &mut dyn Any) -> Result<()> {
+            self.balance(element_mut::<RgbaImage>(input)?);
+            Ok(())
+        }
+
+        fn cache_key(&self) -> Option<String> {
+            None // The gains are only known by computing the output, not by loading it
+        }
+
+        fn parameters(&self) -> ParamMap {
+            let [red, green, blue] = self.fixed;
+            ParamMap::from([
+                ("method".to_string(), self.method.to_value()),
+                ("percentile".to_string(), self.percentile.to_value()),
+                ("red".to_string(), red.to_value()),
+                ("green".to_string(), green.to_value()),
+                ("blue".to_string(), blue.to_value()),
+            ])
+        }
+
+        fn metadata(&self) -> ParamMap {
+            let [red, green, blue] = match self.gains() {
+                Some(gains) => gains,
+                None => return ParamMap::new(),
+            };
+            ParamMap::from([
+                ("red_gain".to_string(), red.to_value()),
+                ("green_gain".to_string(), green.to_value()),
+                ("blue_gain".to_string(), blue.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "method" => self.method = Parameter::from_value(&value)?,
+                "percentile" => self.percentile = Parameter::from_value(&value)?,
+                "red" => self.fixed[0] = Parameter::from_value(&value)?,
+                "green" => self.fixed[1] = Parameter::from_value(&value)?,
+                "blue" => self.fixed[2] = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                fixed: self.fixed,
+                ..Self::new(self.method, self.percentile)
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for WhiteBalance {}
+
+    /// Darkens an image towards its edges like a lens does, or brightens the edges with a negative strength to remove
+    /// such a falloff, e.g. of the illumination of a microscope. The gain at a distance `d` from the center is
+    /// `1 - strength * (d / radius)²` up to the radius and stays at that of the radius beyond it, while a negative
+    /// strength divides by that instead, undoing the same positive strength. Only the color channels are changed, not
+    /// alpha.
+    pub struct Vignette<A> {
+        strength: f32,                                  // From -1 to 1, 0 leaves the image as it is
+        radius: f32,                                    // As a fraction of the diagonal of the image
+        center: [f32; 2],                               // Offset from the center, in widths and heights of the image
+        distances: std::sync::Mutex<Option<Distances>>, // Of the last size, see `Vignette::distances`
+        operation: fn(&Self, &mut A),
+    }
+
+    /// Squared distances of the pixels from the center of a vignette, as fractions of the diagonal
+    struct Distances {
+        size: (u32, u32),
+        center: [f32; 2],
+        squared: std::sync::Arc<Vec<f32>>,
+    }
+
+    impl<A> Vignette<A> {
+        /// Gains are limited to this when removing a vignette, so that black edges don't turn into noise
+        pub const MAX_GAIN: f32 = 16.0;
+
+        /// The squared distances of the pixels of an image of the given size from the center, as fractions of its
+        /// diagonal and row by row. They are kept until the size or the center changes, so that changing the strength
+        /// or the radius doesn't compute them again.
+        pub fn distances(&self, width: u32, height: u32) -> std::sync::Arc<Vec<f32>> {
+            let mut distances = self.distances.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(distances) = distances.as_ref() {
+                if distances.size == (width, height) && distances.center == self.center {
+                    return distances.squared.clone();
+                }
+            }
+            let (w, h) = (width as f32, height as f32);
+            let (center_x, center_y) = (w * (0.5 + self.center[0]), h * (0.5 + self.center[1]));
+            let diagonal = w.hypot(h).max(1.0);
+            let squared: Vec<f32> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x as f32 + 0.5, y as f32 + 0.5)))
+                .map(|(x, y)| ((x - center_x) / diagonal).powi(2) + ((y - center_y) / diagonal).powi(2))
+                .collect();
+            let squared = std::sync::Arc::new(squared);
+            *distances = Some(Distances { size: (width, height), center: self.center, squared: squared.clone() });
+            squared
+        }
+
+        /// The gain at a squared distance from the center, as a fraction of the diagonal
+        fn gain(&self, squared_distance: f32) -> f32 {
+            let falloff = (squared_distance / self.radius.max(f32::EPSILON).powi(2)).min(1.0);
+            let darkened = 1.0 - self.strength.abs().min(1.0) * falloff;
+            match self.strength < 0.0 {
+                true => 1.0 / darkened.max(1.0 / Self::MAX_GAIN),
+                false => darkened,
+            }
+        }
+    }
+
+    impl<P: image::Pixel<Subpixel = u8> + 'static> Vignette<image::ImageBuffer<P, Vec<u8>>> {
+        pub fn new(strength: f32, radius: f32, center: [f32; 2]) -> Self {
+            Self {
+                strength,
+                radius,
+                center,
+                distances: std::sync::Mutex::new(None),
+                operation: Self::apply,
+            }
+        }
+
+        pub fn compute(&self, input: &image::ImageBuffer<P, Vec<u8>>) -> image::ImageBuffer<P, Vec<u8>> {
+            let mut output = input.clone();
+            self.apply(&mut output);
+            output
+        }
+
+        fn apply(&self, image: &mut image::ImageBuffer<P, Vec<u8>>) {
+            if self.strength == 0.0 {
+                return;
+            }
+            let distances = self.distances(image.width(), image.height());
+            let colors = usize::from(P::CHANNEL_COUNT) - usize::from(P::COLOR_TYPE.has_alpha()); // Alpha comes last
+            for (pixel, &distance) in image.chunks_exact_mut(usize::from(P::CHANNEL_COUNT)).zip(distances.iter()) {
+                let gain = self.gain(distance);
+                for value in &mut pixel[..colors] {
+                    *value = (f32::from(*value) * gain).round().clamp(0.0, f32::from(u8::MAX)) as u8;
+                }
+            }
+        }
+    }
+
+    impl<A: Element + Clone> Layer for Vignette<A> {
+        fn kind(&self) -> String {
+            format!("Vignette<{}>", A::NAME)
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![A::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(A::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let mut image = single_input::<A>(input)?.clone(); // Vignette only expects input from a single source layer
+            (self.operation)(self, &mut image);
+            *output = Some(Box::new(image));
+            Ok(())
+        }
+
+        fn computes_in_place(&self) -> bool {
+            true
+        }
+
+        fn compute_in_place(&self, input: &mut dyn Any) -> Result<()> {
+            (self.operation)(self, element_mut::<A>(input)?);
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("strength".to_string(), self.strength.to_value()),
+                ("radius".to_string(), self.radius.to_value()),
+                ("center_x".to_string(), self.center[0].to_value()),
+                ("center_y".to_string(), self.center[1].to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "strength" => self.strength = Parameter::from_value(&value)?,
+                "radius" => self.radius = Parameter::from_value(&value)?,
+                "center_x" => self.center[0] = Parameter::from_value(&value)?,
+                "center_y" => self.center[1] = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                strength: self.strength,
+                radius: self.radius,
+                center: self.center,
+                distances: std::sync::Mutex::new(None),
+                operation: self.operation,
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl<A: Element + Clone> InteractiveLayer for Vignette<A> {}
+
+    /// How `BackgroundEstimate` finds the background
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BackgroundMethod {
+        Morphological, // Removes everything smaller than the radius, like rolling a square ball under the image
+        Blur,          // Averages with a Gaussian of the radius, faster but pulled towards large objects
+    }
+
+    impl BackgroundMethod {
+        pub const ALL: [Self; 2] = [Self::Morphological, Self::Blur];
+    }
+
+    impl Parameter for BackgroundMethod {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "background method")
+        }
+    }
+
+    /// What `BackgroundEstimate` puts out
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BackgroundOutput {
+        Background, // The estimated background itself
+        Subtracted, // The image without the background, stretched to the full range of values
+    }
+
+    impl BackgroundOutput {
+        pub const ALL: [Self; 2] = [Self::Background, Self::Subtracted];
+    }
+
+    impl Parameter for BackgroundOutput {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "background output")
+        }
+    }
+
+    /// Estimates the slowly varying background of a gray image, e.g. the uneven lighting of a scan, or removes it so
+    /// that a single threshold works across the whole image. Objects are either dark on a light background, like text
+    /// on paper, or light on a dark one, like cells under a fluorescence microscope. Large radii are handled on a
+    /// downscaled image, so that the time and memory taken don't grow with the radius.
+    pub struct BackgroundEstimate {
+        method: BackgroundMethod,
+        output: BackgroundOutput,
+        radius: u32,            // In pixels, larger than the objects that aren't part of the background
+        light_background: bool, // Whether objects are darker than the background
+    }
+
+    impl BackgroundEstimate {
+        /// Radii above this are handled on an image that is downscaled to bring the radius down to it
+        pub const FULL_RESOLUTION_RADIUS: u32 = 16;
+
+        pub fn new(method: BackgroundMethod, output: BackgroundOutput, radius: u32, light_background: bool) -> Self {
+            Self { method, output, radius, light_background }
+        }
+
+        /// The background of an image. With `BackgroundMethod::Morphological`, it is never darker than the image on
+        /// a light background and never brighter on a dark one.
+        pub fn background(&self, input: &GrayImage) -> GrayImage {
+            use image::imageops::FilterType::Triangle;
+
+            let (width, height) = input.dimensions();
+            if width == 0 || height == 0 {
+                return input.clone();
+            }
+            let radius = self.radius.max(1);
+            let factor = radius.div_ceil(Self::FULL_RESOLUTION_RADIUS);
+            let (small_width, small_height) = (width.div_ceil(factor), height.div_ceil(factor));
+            let small_radius = radius.div_ceil(factor);
+            let small = match self.method {
+                BackgroundMethod::Morphological => {
+                    // Keeping the background side of each block already removes objects smaller than a block
+                    let pooled = pool(input, factor, self.light_background);
+                    let (first, second) = (self.light_background, !self.light_background);
+                    square_filter(&square_filter(&pooled, small_radius, first), small_radius, second)
+                }
+                BackgroundMethod::Blur => {
+                    let small = image::imageops::resize(input, small_width, small_height, Triangle);
+                    image::imageops::blur(&small, small_radius as f32 / 2.0)
+                }
+            };
+            if factor == 1 {
+                return small;
+            }
+            let mut background = image::imageops::resize(&small, width, height, Triangle);
+            if self.method == BackgroundMethod::Morphological {
+                // Interpolation overshoots next to objects, while the background doesn't go beyond the image
+                for (background, &value) in background.iter_mut().zip(input.iter()) {
+                    *background = match self.light_background {
+                        true => (*background).max(value),
+                        false => (*background).min(value),
+                    };
+                }
+            }
+            background
+        }
+
+        /// The image with the background subtracted, stretched so that the background becomes white on a light
+        /// background and black on a dark one
+        pub fn subtracted(&self, input: &GrayImage) -> GrayImage {
+            let background = self.background(input);
+            let difference = |(&value, &background): (&u8, &u8)| i16::from(value) - i16::from(background);
+            let differences: Vec<i16> = input.iter().zip(background.iter()).map(difference).collect();
+            let low = differences.iter().copied().min().unwrap_or(0).min(0);
+            let high = differences.iter().copied().max().unwrap_or(0).max(0);
+            let scale = f32::from(u8::MAX) / f32::from((high - low).max(1));
+            let data = differences.iter().map(|&difference| match self.light_background {
+                true => (f32::from(u8::MAX) - f32::from(high - difference) * scale).round() as u8,
+                false => (f32::from(difference - low) * scale).round() as u8,
+            });
+            GrayImage::from_raw(input.width(), input.height(), data.collect()).expect("Sizes match")
+        }
+
+        pub fn compute(&self, input: &GrayImage) -> GrayImage {
+            match self.output {
+                BackgroundOutput::Background => self.background(input),
+                BackgroundOutput::Subtracted => self.subtracted(input),
+            }
+        }
+    }
+
+    /// Downscales by taking the brightest or darkest pixel of each block of `factor` by `factor` pixels
+    fn pool(input: &GrayImage, factor: u32, brightest: bool) -> GrayImage {
+        let (width, height) = (input.width().div_ceil(factor), input.height().div_ceil(factor));
+        GrayImage::from_fn(width, height, |x, y| {
+            let columns = x * factor..((x + 1) * factor).min(input.width());
+            let rows = y * factor..((y + 1) * factor).min(input.height());
+            let values = rows.flat_map(|y| columns.clone().map(move |x| input.get_pixel(x, y)[0]));
+            let value = match brightest {
+                true => values.max(),
+                false => values.min(),
+            };
+            Luma([value.expect("Blocks have at least one pixel")])
+        })
+    }
+
+    /// The brightest or darkest value in a square of pixels around each pixel, reaching `radius` pixels to each side.
+    /// Squares are cut off at the edges of the image.
+    fn square_filter(input: &GrayImage, radius: u32, brightest: bool) -> GrayImage {
+        let pick = |values: &mut dyn Iterator<Item = u8>| match brightest {
+            true => values.max().unwrap_or(0),
+            false => values.min().unwrap_or(0),
+        };
+        let (width, height) = input.dimensions();
+        let window = |center: u32, size: u32| center.saturating_sub(radius)..(center + radius + 1).min(size);
+        let rows = GrayImage::from_fn(width, height, |x, y| {
+            Luma([pick(&mut window(x, width).map(|x| input.get_pixel(x, y)[0]))])
+        });
+        GrayImage::from_fn(width, height, |x, y| Luma([pick(&mut window(y, height).map(|y| rows.get_pixel(x, y)[0]))]))
+    }
+
+    impl Layer for BackgroundEstimate {
+        fn kind(&self) -> String {
+            "BackgroundEstimate".to_string()
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![GrayImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(GrayImage::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = single_input::<GrayImage>(input)?; // Only expects input from a single source layer
+            *output = Some(Box::new(BackgroundEstimate::compute(self, input)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("method".to_string(), self.method.to_value()),
+                ("output".to_string(), self.output.to_value()),
+                ("radius".to_string(), self.radius.to_value()),
+                ("light_background".to_string(), self.light_background.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "method" => self.method = Parameter::from_value(&value)?,
+                "output" => self.output = Parameter::from_value(&value)?,
+                "radius" => self.radius = Parameter::from_value(&value)?,
+                "light_background" => self.light_background = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn scale_parameters(&mut self, factor: f64) {
+            self.radius = ((f64::from(self.radius) * factor).round() as u32).max(1);
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.method, self.output, self.radius, self.light_background)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for BackgroundEstimate {}
+
+    /// Which pixels count as neighbors of a pixel
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Connectivity {
+        Four,  // Those sharing an edge
+        Eight, // Those sharing an edge or a corner
+    }
+
+    impl Connectivity {
+        pub const ALL: [Self; 2] = [Self::Four, Self::Eight];
+
+        /// The neighbors of a pixel that are inside of an image of the given size
+        pub fn neighbors(self, (x, y): (u32, u32), (width, height): (u32, u32)) -> impl Iterator<Item = (u32, u32)> {
+            const OFFSETS: [(i64, i64); 8] = [(0, -1), (-1, 0), (1, 0), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)];
+            let count = match self {
+                Self::Four => 4,
+                Self::Eight => 8,
+            };
+            OFFSETS[..count].iter().filter_map(move |&(dx, dy)| {
+                let (x, y) = (i64::from(x) + dx, i64::from(y) + dy);
+                let inside = (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y);
+                inside.then_some((x as u32, y as u32))
+            })
+        }
+    }
+
+    impl Parameter for Connectivity {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "connectivity")
+        }
+    }
+
+    /// Grows a region of a gray image from each seed, adding the neighbors of its pixels whose values are within a
+    /// tolerance of the mean of the region so far. Regions grow at the same pace, and a pixel belongs to the region
+    /// that reaches it first. The regions are labeled in the order of their seeds, starting at 1. Seeds come from a
+    /// second input with points, e.g. placed by another layer, or from the `"seeds"` parameter if that isn't connected.
+    pub struct RegionGrow {
+        seeds: Vec<entity::Point>, // Used unless points are connected
+        tolerance: f32,            // Largest difference of a pixel to the mean of a region that it is added to
+        connectivity: Connectivity,
+        binary: bool, // Whether to put out a `BinaryImage` of all regions instead of labels, e.g. for a single seed
+    }
+
+    impl RegionGrow {
+        pub fn new(seeds: Vec<entity::Point>, tolerance: f32) -> Self {
+            Self {
+                seeds,
+                tolerance,
+                connectivity: Connectivity::Four,
+                binary: false,
+            }
+        }
+
+        /// Labels the regions grown from `seeds`. A seed on a pixel that an earlier seed already covers doesn't get a
+        /// region, but keeps its label.
+        pub fn compute(&self, input: &GrayImage, seeds: &[entity::Point]) -> Result<LabelImage> {
+            ensure!(!seeds.is_empty(), "Growing regions needs at least one seed");
+            let size = input.dimensions();
+            let mut labels = LabelImage::new(size.0, size.1);
+            let mut regions = Vec::with_capacity(seeds.len()); // Sum and number of the values of each region
+            let mut queue = std::collections::VecDeque::new();
+            for (label, seed) in (1..).zip(seeds) {
+                let inside = |position: f32, size: u32| position >= 0.0 && position < size as f32;
+                if !inside(seed.x, size.0) || !inside(seed.y, size.1) {
+                    bail!(KlexError::SeedOutOfBounds { x: seed.x, y: seed.y, size });
+                }
+                let (x, y) = (seed.x as u32, seed.y as u32);
+                let value = input.get_pixel(x, y)[0];
+                regions.push((f64::from(value), 1_u64));
+                if labels.get_pixel(x, y)[0] == 0 {
+                    labels.put_pixel(x, y, Luma([label]));
+                    queue.push_back((x, y));
+                }
+            }
+            while let Some((x, y)) = queue.pop_front() {
+                let label = labels.get_pixel(x, y)[0];
+                for (x, y) in self.connectivity.neighbors((x, y), size) {
+                    let (sum, count) = &mut regions[label as usize - 1];
+                    let value = input.get_pixel(x, y)[0];
+                    let mean = *sum / *count as f64;
+                    if labels.get_pixel(x, y)[0] == 0 && (f64::from(value) - mean).abs() <= f64::from(self.tolerance) {
+                        labels.put_pixel(x, y, Luma([label]));
+                        *sum += f64::from(value);
+                        *count += 1;
+                        queue.push_back((x, y));
+                    }
+                }
+            }
+            Ok(labels)
+        }
+    }
+
+    /// Points written as `x,y` and separated by spaces, like `"12,30 40.5,8"`
+    fn points_from_text(text: &str) -> Result<Vec<entity::Point>> {
+        let coordinate = |text: &str| text.parse::<f32>().map_err(|_| KlexError::invalid_value("a coordinate", text));
+        text.split_whitespace()
+            .map(|point| {
+                let expected = || format!("Expected a point like 12,30, not {:?}", point);
+                let (x, y) = point.split_once(',').with_context(expected)?;
+                Ok(entity::Point { x: coordinate(x)?, y: coordinate(y)? })
+            })
+            .collect()
+    }
+
+    fn points_to_text(points: &[entity::Point]) -> String {
+        let points: Vec<_> = points.iter().map(|point| format!("{},{}", point.x, point.y)).collect();
+        points.join(" ")
+    }
+
+    impl Layer for RegionGrow {
+        fn kind(&self) -> String {
+            "RegionGrow".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Analyze
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(if self.binary { BinaryImage::NAME } else { LabelImage::NAME })
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            // The inputs are told apart by their elements, since the points are optional
+            let input: Vec<&(dyn Any + Send + Sync)> = input.iter().filter_map(|input| input.as_deref()).collect();
+            let image = input.iter().find_map(|input| input.downcast_ref::<GrayImage>());
+            let image = image.context("Expected a GrayImage to grow regions in")?;
+            let points = input.iter().find_map(|input| input.downcast_ref::<Vec<entity::Point>>());
+            let labels = self.compute(image, points.unwrap_or(&self.seeds))?;
+            *output = Some(match self.binary {
+                true => {
+                    let data = labels.iter().map(|&label| label != 0).collect();
+                    Box::new(BinaryImage::new(labels.width(), labels.height(), data))
+                }
+                false => Box::new(labels),
+            });
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("seeds".to_string(), ParamValue::Text(points_to_text(&self.seeds))),
+                ("tolerance".to_string(), self.tolerance.to_value()),
+                ("connectivity".to_string(), self.connectivity.to_value()),
+                ("binary".to_string(), self.binary.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "seeds" => self.seeds = points_from_text(&String::from_value(&value)?)?,
+                "tolerance" => self.tolerance = Parameter::from_value(&value)?,
+                "connectivity" => self.connectivity = Parameter::from_value(&value)?,
+                "binary" => self.binary = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn scale_parameters(&mut self, factor: f64) {
+            for seed in &mut self.seeds {
+                (seed.x, seed.y) = (seed.x * factor as f32, seed.y * factor as f32);
+            }
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                seeds: self.seeds.clone(),
+                ..*self
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for RegionGrow {}
+
+    /// Splits a gray image into the basins around markers, by flooding it from the markers upwards like a landscape
+    /// filled with water, e.g. an inverted distance map of touching blobs. The first input is the image and the second
+    /// the markers, whose labels are kept. Pixels are flooded from the lowest value up and in the order of their index
+    /// for equal values, so the result doesn't depend on anything else. Where basins meet, pixels can be left as
+    /// watershed lines with the label 0. Pixels that can't be reached from any marker keep the label 0 as well.
+    pub struct Watershed {
+        connectivity: Connectivity,
+        lines: bool, // Whether to keep the pixels where basins meet at 0
+    }
+
+    impl Watershed {
+        pub fn new(connectivity: Connectivity, lines: bool) -> Self {
+            Self { connectivity, lines }
+        }
+
+        pub fn compute(&self, input: &GrayImage, markers: &LabelImage) -> Result<LabelImage> {
+            use std::{cmp::Reverse, collections::BinaryHeap};
+
+            let size = input.dimensions();
+            if markers.dimensions() != size {
+                bail!(KlexError::ShapeMismatch { expected: size, found: markers.dimensions() });
+            }
+            let width = size.0 as usize;
+            let index = |(x, y): (u32, u32)| y as usize * width + x as usize;
+            let position = |index: usize| ((index % width) as u32, (index / width) as u32);
+            let values = input.as_raw();
+            let mut labels = markers.as_raw().clone();
+            let mut queued = vec![0; labels.len()]; // The label of the basin a queued pixel was reached from
+            let mut queue = BinaryHeap::new();
+
+            // Queues the neighbors of a labeled pixel that aren't yet labeled or queued
+            let flood_from = |pixel: usize, labels: &[u32], queued: &mut [u32], queue: &mut BinaryHeap<_>| {
+                for neighbor in self.connectivity.neighbors(position(pixel), size).map(index) {
+                    if labels[neighbor] == 0 && queued[neighbor] == 0 {
+                        queued[neighbor] = labels[pixel];
+                        queue.push(Reverse((values[neighbor], neighbor)));
+                    }
+                }
+            };
+            for pixel in (0..labels.len()).filter(|&pixel| labels[pixel] != 0) {
+                flood_from(pixel, &labels, &mut queued, &mut queue);
+            }
+            while let Some(Reverse((_, pixel))) = queue.pop() {
+                let label = queued[pixel];
+                let meets_other = || {
+                    let mut neighbors = self.connectivity.neighbors(position(pixel), size).map(index);
+                    neighbors.any(|neighbor| labels[neighbor] != 0 && labels[neighbor] != label)
+                };
+                if self.lines && meets_other() {
+                    continue; // Stays 0, and isn't flooded from
+                }
+                labels[pixel] = label;
+                flood_from(pixel, &labels, &mut queued, &mut queue);
+            }
+            Ok(LabelImage::from_raw(size.0, size.1, labels).expect("Sizes match"))
+        }
+    }
+
+    impl Layer for Watershed {
+        fn kind(&self) -> String {
+            "Watershed".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Analyze
+        }
+
+        fn input_types(&self) -> Vec<&'static str> {
+            vec![GrayImage::NAME, LabelImage::NAME]
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(LabelImage::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let image = single_input::<GrayImage>(input)?;
+            let markers = single_input::<LabelImage>(input.get(1..).unwrap_or_default())?;
+            *output = Some(Box::new(self.compute(image, markers)?));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("connectivity".to_string(), self.connectivity.to_value()),
+                ("lines".to_string(), self.lines.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "connectivity" => self.connectivity = Parameter::from_value(&value)?,
+                "lines" => self.lines = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.connectivity, self.lines)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Watershed {}
+
+    /// How `Stack` combines the values of its images at each pixel
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum StackReduction {
+        Mean,   // Averages out noise that differs between exposures
+        Median, // Like the mean, but ignores what only a few of the images show, e.g. people walking by
+        Min,
+        Max,
+    }
+
+    impl StackReduction {
+        pub const ALL: [Self; 4] = [Self::Mean, Self::Median, Self::Min, Self::Max];
+
+        fn reduce(self, values: &mut [u8]) -> u8 {
+            let count = values.len();
+            match self {
+                Self::Mean => {
+                    let sum: usize = values.iter().map(|&value| usize::from(value)).sum();
+                    ((sum + count / 2) / count) as u8
+                }
+                Self::Median => {
+                    let (lower, &mut upper, _) = values.select_nth_unstable(count / 2);
+                    match lower.iter().max() {
+                        // Between the two values in the middle, rounded up
+                        Some(&lower) if count.is_multiple_of(2) => {
+                            (u16::from(lower) + u16::from(upper)).div_ceil(2) as u8
+                        }
+                        _ => upper,
+                    }
+                }
+                Self::Min => values.iter().copied().min().unwrap_or_default(),
+                Self::Max => values.iter().copied().max().unwrap_or_default(),
+            }
+        }
+    }
+
+    impl Parameter for StackReduction {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "stack reduction")
+        }
+    }
+
+    /// Combines any number of gray or color images of the same size pixel by pixel, e.g. several exposures of the
+    /// same scene into one with less noise. The channels of color images are combined separately, alpha included.
+    pub struct Stack {
+        reduction: StackReduction,
+    }
+
+    impl Stack {
+        pub fn new(reduction: StackReduction) -> Self {
+            Self { reduction }
+        }
+
+        /// Goes through the images row by row, so that besides the output only a row of each image is kept at a time
+        pub fn compute<P: image::Pixel<Subpixel = u8> + 'static>(
+            &self,
+            images: &[&image::ImageBuffer<P, Vec<u8>>],
+        ) -> Result<image::ImageBuffer<P, Vec<u8>>> {
+            let first = images.first().context("There are no images to stack")?;
+            if let Some(index) = images.iter().position(|image| image.dimensions() != first.dimensions()) {
+                bail!(KlexError::InputMismatch {
+                    index,
+                    expected: describe(*first),
+                    found: describe(images[index]),
+                });
+            }
+            let (width, height) = first.dimensions();
+            let (row, count) = (width as usize * usize::from(P::CHANNEL_COUNT), images.len());
+            let mut output = Vec::with_capacity(first.as_raw().len());
+            let mut values = vec![0; row * count]; // The values of a row of each image, one column after another
+            for y in 0..height as usize {
+                for (index, image) in images.iter().enumerate() {
+                    for (column, &value) in image.as_raw()[y * row..(y + 1) * row].iter().enumerate() {
+                        values[column * count + index] = value;
+                    }
+                }
+                output.extend(values.chunks_mut(count).map(|column| self.reduction.reduce(column)));
+            }
+            Ok(image::ImageBuffer::from_raw(width, height, output).expect("Sizes match"))
+        }
+    }
+
+    /// E.g. "a GrayImage of 4x3 pixels", for saying how an input differs from another one
+    fn describe(element: &dyn Any) -> String {
+        match (entity::element_name(element), entity::dimensions(element)) {
+            (Some(name), Some((width, height))) => format!("a {} of {}x{} pixels", name, width, height),
+            (Some(name), None) => format!("a {}", name),
+            (None, _) => "an element of an unknown type".to_string(),
+        }
+    }
+
+    /// The elements of all inputs, which have to be of the same type and size
+    fn same_inputs<'a, A: Element>(input: &[&'a LayerOutput]) -> Result<Vec<&'a A>> {
+        let first = single_input::<A>(input)?;
+        let mut elements = vec![first];
+        for (index, element) in input.iter().enumerate().skip(1) {
+            let element = element.as_deref().context("Empty input")?;
+            match element.downcast_ref::<A>() {
+                Some(same) if entity::dimensions(element) == entity::dimensions(first) => elements.push(same),
+                _ => bail!(KlexError::InputMismatch { index, expected: describe(first), found: describe(element) }),
+            }
+        }
+        Ok(elements)
+    }
+
+    impl Layer for Stack {
+        fn kind(&self) -> String {
+            "Stack".to_string()
+        }
+
+        fn expected_inputs(&self) -> InputCount {
+            InputCount::AtLeast(1)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let first = input.first().and_then(|first| first.as_deref()).context("Missing input")?;
+            if first.is::<RgbaImage>() {
+                *output = Some(Box::new(self.compute(&same_inputs::<RgbaImage>(input)?)?));
+            } else {
+                *output = Some(Box::new(self.compute(&same_inputs::<GrayImage>(input)?)?));
+            }
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([("reduction".to_string(), self.reduction.to_value())])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "reduction" => self.reduction = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.reduction)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Stack {}
+
+    /// Writes labels onto a copy of a color image, e.g. measurements of the contours found in it for reports of batch
+    /// runs. With lines, points or contours connected as well, there is a label next to each shape, and otherwise a
+    /// single one in the top left corner. Placeholders in braces are replaced by values of the shape, see
+    /// `Annotate::labels`, and `{{` and `}}` stand for the braces themselves. The inputs are told apart by their
+    /// elements, like for `ExportVector`.
+    pub struct Annotate {
+        format: String,
+        size: u32, // Height of capital letters in pixels, rounded down to a multiple of the height of the font
+        color: Rgba<u8>,
+        markers: bool, // Whether to mark where each shape is, like points are shown in the viewport
+    }
+
+    impl Annotate {
+        pub fn new(format: String) -> Self {
+            Self {
+                format,
+                size: 14,
+                color: Rgba([255, 255, 0, 255]),
+                markers: true,
+            }
+        }
+
+        fn scale(&self) -> u32 {
+            (self.size / draw::GLYPH_SIZE.1).max(1)
+        }
+
+        /// Where each label goes, with its text. Every shape has an `index`, counted from 0, and a position at `x`,
+        /// `y`, which is the middle of lines and the centroid of contours. Lines also have a `length`, and contours
+        /// an `area` and a `perimeter`. Without shapes, there is a single label with index 0 and the `width` and
+        /// `height` of the image.
+        pub fn labels(&self, image: &RgbaImage, shapes: Option<&Geometry>) -> Result<Vec<((f32, f32), String)>> {
+            let shapes = match shapes {
+                Some(shapes) => shapes,
+                None => {
+                    let (width, height) = (f64::from(image.width()), f64::from(image.height()));
+                    let values = [("index", 0.0), ("width", width), ("height", height)];
+                    return Ok(vec![((0.0, 0.0), substitute(&self.format, &values)?)]);
+                }
+            };
+            let mut labels = Vec::new();
+            let mut label = |position: (f32, f32), values: &[(&str, f64)]| -> Result<()> {
+                let index = [("index", labels.len() as f64)];
+                let position_values = [("x", f64::from(position.0)), ("y", f64::from(position.1))];
+                let values: Vec<_> = index.into_iter().chain(position_values).chain(values.iter().copied()).collect();
+                labels.push((position, substitute(&self.format, &values)?));
+                Ok(())
+            };
+            for &point in &shapes.points {
+                label(point, &[])?;
+            }
+            for &(start, end) in &shapes.lines {
+                let middle = ((start.0 + end.0) / 2.0, (start.1 + end.1) / 2.0);
+                label(middle, &[("length", f64::from((end.0 - start.0).hypot(end.1 - start.1)))])?;
+            }
+            for polygon in &shapes.polygons {
+                let (area, centroid, perimeter) = measure(polygon);
+                label(centroid, &[("area", area), ("perimeter", perimeter)])?;
+            }
+            Ok(labels)
+        }
+
+        pub fn compute(&self, image: &RgbaImage, shapes: Option<&Geometry>) -> Result<RgbaImage> {
+            let mut annotated = image.clone();
+            let scale = self.scale();
+            let arm = 2 * scale;
+            for ((x, y), text) in self.labels(image, shapes)? {
+                let corner = match shapes {
+                    Some(_) => {
+                        if self.markers {
+                            draw::marker(&mut annotated, (x, y), arm, self.color.0);
+                        }
+                        // To the right of the marker, centered on it
+                        let height = draw::text_size(&text, scale).1;
+                        (x.round() as i64 + i64::from(2 * arm), y.round() as i64 - i64::from(height / 2))
+                    }
+                    None => (i64::from(scale), i64::from(scale)),
+                };
+                draw::text(&mut annotated, &text, corner, scale, self.color.0);
+            }
+            Ok(annotated)
+        }
+    }
+
+    /// Area, centroid and perimeter of a closed polygon. Polygons without an area have the mean of their points as
+    /// their centroid.
+    fn measure(polygon: &[(f32, f32)]) -> (f64, (f32, f32), f64) {
+        let edges = polygon.iter().zip(polygon.iter().cycle().skip(1));
+        let (mut area, mut x, mut y, mut perimeter) = (0.0, 0.0, 0.0, 0.0);
+        for (&(x0, y0), &(x1, y1)) in edges {
+            let (x0, y0, x1, y1) = (f64::from(x0), f64::from(y0), f64::from(x1), f64::from(y1));
+            let cross = x0 * y1 - x1 * y0;
+            area += cross / 2.0;
+            x += (x0 + x1) * cross / 6.0;
+            y += (y0 + y1) * cross / 6.0;
+            perimeter += (x1 - x0).hypot(y1 - y0);
+        }
+        let centroid = match area.abs() > f64::EPSILON {
+            true => ((x / area) as f32, (y / area) as f32),
+            false => {
+                let count = polygon.len().max(1) as f32;
+                let sum = polygon.iter().fold((0.0, 0.0), |(x, y), &point| (x + point.0, y + point.1));
+                (sum.0 / count, sum.1 / count)
+            }
+        };
+        (area.abs(), centroid, perimeter)
+    }
+
+    /// Replaces the placeholders in `format` by their values, rounded to two decimals
+    fn substitute(format: &str, values: &[(&str, f64)]) -> Result<String> {
+        let mut text = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find(['{', '}']) {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("{{") {
+                text.push('{');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("}}") {
+                text.push('}');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').context(format!("Unterminated placeholder in {:?}", format))?;
+                let name = &after[..end];
+                let value = match values.iter().find(|&&(known, _)| known == name) {
+                    Some(&(_, value)) => value,
+                    None => {
+                        let known: Vec<_> = values.iter().map(|&(known, _)| known).collect();
+                        bail!("Unknown placeholder {:?}, expected one of {}", name, known.join(", "));
+                    }
+                };
+                text.push_str(&((value * 100.0).round() / 100.0 + 0.0).to_string());
+                rest = &after[end + 1..];
+            } else {
+                bail!("Unmatched }} in {:?}, which is written as }}}}", format);
+            }
+        }
+        text.push_str(rest);
+        Ok(text)
+    }
+
+    impl Layer for Annotate {
+        fn kind(&self) -> String {
+            "Annotate".to_string()
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(RgbaImage::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input: Vec<&(dyn Any + Send + Sync)> = input.iter().filter_map(|input| input.as_deref()).collect();
+            let mut images = input.iter().filter_map(|input| input.downcast_ref::<RgbaImage>());
+            let image = images.next().context("Expected a color image at one of the inputs")?;
+            ensure!(images.next().is_none(), "Expected a single color image");
+            let mut geometry = input.iter().filter_map(|&input| entity::geometry(input));
+            let shapes = geometry.next();
+            ensure!(geometry.next().is_none(), "Expected at most one input with lines, points or contours");
+            ensure!(input.len() == 1 + usize::from(shapes.is_some()), "Expected a color image and shapes to label");
+            *output = Some(Box::new(Annotate::compute(self, image, shapes.as_ref())?));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("format".to_string(), self.format.to_value()),
+                ("size".to_string(), self.size.to_value()),
+                ("color".to_string(), self.color.to_value()),
+                ("markers".to_string(), self.markers.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "format" => self.format = Parameter::from_value(&value)?,
+                "size" => self.size = Parameter::from_value(&value)?,
+                "color" => self.color = Parameter::from_value(&value)?,
+                "markers" => self.markers = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn scale_parameters(&mut self, factor: f64) {
+            self.size = (f64::from(self.size) * factor).round() as u32;
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self {
+                format: self.format.clone(),
+                ..*self
+            }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for Annotate {}
+
+    /// Draws lines, points, rectangles or contours into a color image, the way the viewport shows them over an image.
+    /// With a color image connected as well, the shapes are drawn onto a copy of it, and otherwise onto a transparent
+    /// image, e.g. to blend over another image later. The inputs are told apart by their elements, like for
+    /// `ExportVector`.
+    pub struct DrawGeometry {
+        width: u32,  // Of the transparent image, or 0 to reach to the shapes furthest to the right
+        height: u32, // Of the transparent image, or 0 to reach to the shapes furthest to the bottom
+        stroke: u32, // Width of lines and outlines in pixels, which are left out at 0
+        color: Rgba<u8>,
+        fill: bool, // Whether the insides of contours and rectangles are drawn as well
+    }
+
+    impl DrawGeometry {
+        pub fn new(width: u32, height: u32) -> Self {
+            Self {
+                width,
+                height,
+                stroke: 1,
+                color: Rgba([255, 0, 0, 255]),
+                fill: false,
+            }
+        }
+
+        pub fn compute(&self, shapes: &Geometry, image: Option<&RgbaImage>) -> RgbaImage {
+            let mut drawn = match image {
+                Some(image) => image.clone(),
+                None => {
+                    let (width, height) = shapes.extent();
+                    let size = |size: u32, extent: f32| if size == 0 { extent as u32 } else { size };
+                    RgbaImage::new(size(self.width, width), size(self.height, height))
+                }
+            };
+            let style = draw::Style { color: self.color.0, width: self.stroke, fill: self.fill };
+            draw::shapes(&mut drawn, shapes, &style);
+            drawn
+        }
+    }
+
+    impl Layer for DrawGeometry {
+        fn kind(&self) -> String {
+            "DrawGeometry".to_string()
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(RgbaImage::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input: Vec<&(dyn Any + Send + Sync)> = input.iter().filter_map(|input| input.as_deref()).collect();
+            let mut geometry = input.iter().filter_map(|&input| entity::geometry(input));
+            let shapes = geometry.next().context("Expected shapes to draw at one of the inputs")?;
+            ensure!(geometry.next().is_none(), "Expected a single input with shapes");
+            let mut images = input.iter().filter_map(|input| input.downcast_ref::<RgbaImage>());
+            let image = images.next();
+            ensure!(images.next().is_none(), "Expected at most one color image to draw onto");
+            ensure!(input.len() == 1 + usize::from(image.is_some()), "Expected shapes and a color image to draw onto");
+            *output = Some(Box::new(DrawGeometry::compute(self, &shapes, image)));
+            Ok(())
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("width".to_string(), self.width.to_value()),
+                ("height".to_string(), self.height.to_value()),
+                ("stroke".to_string(), self.stroke.to_value()),
+                ("color".to_string(), self.color.to_value()),
+                ("fill".to_string(), self.fill.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "width" => self.width = Parameter::from_value(&value)?,
+                "height" => self.height = Parameter::from_value(&value)?,
+                "stroke" => self.stroke = Parameter::from_value(&value)?,
+                "color" => self.color = Parameter::from_value(&value)?,
+                "fill" => self.fill = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn scale_parameters(&mut self, factor: f64) {
+            let scale = |value: u32| (f64::from(value) * factor).round() as u32;
+            self.width = scale(self.width);
+            self.height = scale(self.height);
+            // Strokes stay visible in small previews
+            self.stroke = scale(self.stroke).max(self.stroke.min(1));
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self { ..*self }))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for DrawGeometry {}
+
+    #[cfg(feature = "fft")]
+    pub use spectral::{AlignTranslate, FftFilter, FrequencyBand, SpectrumOutput};
+
+    #[cfg(feature = "fft")]
+    mod spectral {
+        use std::sync::Mutex;
+
+        use rustfft::FftDirection;
+
+        use super::*;
+        use crate::fft::{self, Correlation, Grid};
+
+        /// Finds how a second gray image is shifted against a first one, by phase correlation, and moves it back into
+        /// alignment with the first, e.g. for taking the difference of two photos of the same scene. Puts out the
+        /// aligned image, with black where the second image doesn't reach, and the shift as a single point. The shift
+        /// and the confidence of the last computation can be read afterwards, see `fft::Correlation`.
+        pub struct AlignTranslate {
+            subpixel: bool, // Whether to find shifts in fractions of pixels, and resample the image bilinearly
+            correlation: Mutex<Option<Correlation>>, // Of the last computation
+        }
+
+        impl AlignTranslate {
+            const OUTPUTS: [OutputPort; 2] = [
+                OutputPort::new("aligned", Some(GrayImage::NAME)),
+                OutputPort::new("shift", Some(Vec::<entity::Point>::NAME)),
+            ];
+
+            pub fn new(subpixel: bool) -> Self {
+                Self {
+                    subpixel,
+                    correlation: Mutex::new(None),
+                }
+            }
+
+            /// The shift and the confidence found by the last computation
+            pub fn correlation(&self) -> Option<Correlation> {
+                *self.correlation.lock().ok()?
+            }
+
+            /// The second image aligned with the first, along with the shift between them
+            pub fn compute(&self, first: &GrayImage, second: &GrayImage) -> Result<(GrayImage, Correlation)> {
+                let correlation = fft::phase_correlation(first, second, self.subpixel)?;
+                if let Ok(mut last) = self.correlation.lock() {
+                    *last = Some(correlation);
+                }
+                Ok((translate(second, correlation.shift), correlation))
+            }
+        }
+
+        /// Moves an image by `-shift`, interpolating bilinearly between pixels. Pixels that come from outside of the
+        /// image are black.
+        fn translate(image: &GrayImage, (shift_x, shift_y): (f32, f32)) -> GrayImage {
+            let (width, height) = image.dimensions();
+            let inside = |x: i64, y: i64| (0..i64::from(width)).contains(&x) && (0..i64::from(height)).contains(&y);
+            let value = |x: i64, y: i64| match inside(x, y) {
+                true => f32::from(image.get_pixel(x as u32, y as u32)[0]),
+                false => 0.0,
+            };
+            GrayImage::from_fn(width, height, |x, y| {
+                let (x, y) = (x as f32 + shift_x, y as f32 + shift_y);
+                let (left, top) = (x.floor(), y.floor());
+                let (right_weight, bottom_weight) = (x - left, y - top);
+                let (left, top) = (left as i64, top as i64);
+                let row = |y: i64| value(left, y) * (1.0 - right_weight) + value(left + 1, y) * right_weight;
+                let interpolated = row(top) * (1.0 - bottom_weight) + row(top + 1) * bottom_weight;
+                Luma([interpolated.round().clamp(0.0, f32::from(u8::MAX)) as u8])
+            })
+        }
+
+        impl Layer for AlignTranslate {
+            fn kind(&self) -> String {
+                "AlignTranslate".to_string()
+            }
+
+            fn input_types(&self) -> Vec<&'static str> {
+                vec![GrayImage::NAME, GrayImage::NAME]
+            }
+
+            fn output_type(&self) -> Option<&'static str> {
+                Some(GrayImage::NAME)
+            }
+
+            fn outputs(&self) -> &[OutputPort] {
+                &Self::OUTPUTS
+            }
+
+            fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+                let first = single_input::<GrayImage>(input)?;
+                let second = single_input::<GrayImage>(input.get(1..).unwrap_or_default())?;
+                let (aligned, correlation) = self.compute(first, second)?;
+                let (x, y) = correlation.shift;
+                let shift = vec![entity::Point { x, y }];
+                let outputs: [Box<dyn Any + Send + Sync>; 2] = [Box::new(aligned), Box::new(shift)];
+                *output = Some(Box::new(Outputs(outputs.map(Some).into())));
+                Ok(())
+            }
+
+            fn parameters(&self) -> ParamMap {
+                ParamMap::from([("subpixel".to_string(), self.subpixel.to_value())])
+            }
+
+            fn metadata(&self) -> ParamMap {
+                let correlation = match self.correlation() {
+                    Some(correlation) => correlation,
+                    None => return ParamMap::new(),
+                };
+                ParamMap::from([
+                    ("shift_x".to_string(), correlation.shift.0.to_value()),
+                    ("shift_y".to_string(), correlation.shift.1.to_value()),
+                    ("confidence".to_string(), correlation.confidence.to_value()),
+                ])
+            }
+
+            fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+                match name {
+                    "subpixel" => self.subpixel = Parameter::from_value(&value)?,
+                    _ => bail!(KlexError::UnknownParameter(name.to_string())),
+                }
+                Ok(())
+            }
+
+            fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+                Ok(Box::new(Self::new(self.subpixel)))
+            }
+        }
+
+        #[cfg(feature = "ui")]
+        impl InteractiveLayer for AlignTranslate {}
+
+        /// Which frequencies `FftFilter` keeps
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum FrequencyBand {
+            LowPass,  // Those below the cutoff, smoothing the image
+            HighPass, // Those above the cutoff, keeping edges and fine texture
+            BandPass, // Those between the cutoff and the upper cutoff
+        }
+
+        impl FrequencyBand {
+            pub const ALL: [Self; 3] = [Self::LowPass, Self::HighPass, Self::BandPass];
+        }
+
+        impl Parameter for FrequencyBand {
+            fn to_value(&self) -> ParamValue {
+                ParamValue::Choice(format!("{:?}", self))
+            }
+
+            fn from_value(value: &ParamValue) -> Result<Self> {
+                choice(value, &Self::ALL, "frequency band")
+            }
+        }
+
+        /// What `FftFilter` puts out
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum SpectrumOutput {
+            Filtered, // The image with only the frequencies of the band, from 0 to 1 like `FloatScale::Normalized`
+            Spectrum, // The log-magnitude spectrum of the image, see `fft::Grid::log_magnitude`
+        }
+
+        impl SpectrumOutput {
+            pub const ALL: [Self; 2] = [Self::Filtered, Self::Spectrum];
+        }
+
+        impl Parameter for SpectrumOutput {
+            fn to_value(&self) -> ParamValue {
+                ParamValue::Choice(format!("{:?}", self))
+            }
+
+            fn from_value(value: &ParamValue) -> Result<Self> {
+                choice(value, &Self::ALL, "spectrum output")
+            }
+        }
+
+        /// Filters a gray image in the frequency domain, e.g. for removing the raster pattern of a scanner. Cutoffs
+        /// are fractions of the Nyquist frequency of half a cycle per pixel, in any direction, and the gain rolls off
+        /// like a Gaussian, with half of it left at the cutoff. Looking at the spectrum first shows where periodic
+        /// noise is. High frequencies go below zero, which the display conversion shows as black, so a `Normalize`
+        /// layer over the full range shows them better.
+        pub struct FftFilter {
+            band: FrequencyBand,
+            output: SpectrumOutput,
+            cutoff: f32,       // Where low and high pass filters change over, and where band pass filters start
+            upper_cutoff: f32, // Where band pass filters end
+        }
+
+        impl FftFilter {
+            pub fn new(band: FrequencyBand, cutoff: f32, upper_cutoff: f32) -> Self {
+                Self {
+                    band,
+                    output: SpectrumOutput::Filtered,
+                    cutoff,
+                    upper_cutoff,
+                }
+            }
+
+            pub fn with_output(self, output: SpectrumOutput) -> Self {
+                Self { output, ..self }
+            }
+
+            /// How much of a frequency is kept, given as a fraction of the Nyquist frequency
+            pub fn gain(&self, frequency: f32) -> f32 {
+                let low_pass = |cutoff: f32| {
+                    let relative = frequency / cutoff.max(f32::EPSILON);
+                    (-std::f32::consts::LN_2 * relative * relative).exp()
+                };
+                match self.band {
+                    FrequencyBand::LowPass => low_pass(self.cutoff),
+                    FrequencyBand::HighPass => 1.0 - low_pass(self.cutoff),
+                    FrequencyBand::BandPass => (1.0 - low_pass(self.cutoff)) * low_pass(self.upper_cutoff),
+                }
+            }
+
+            pub fn compute(&self, input: &GrayImage) -> GrayImageF32 {
+                let mut grid = Grid::from_gray(input);
+                grid.transform(FftDirection::Forward);
+                if self.output == SpectrumOutput::Spectrum {
+                    return grid.log_magnitude();
+                }
+                let (width, height) = (grid.width, grid.height);
+                for (index, value) in grid.values.iter_mut().enumerate() {
+                    let frequency = Grid::frequency(index % width, width).hypot(Grid::frequency(index / width, height));
+                    *value *= self.gain(2.0 * frequency) / f32::from(u8::MAX);
+                }
+                grid.transform(FftDirection::Inverse);
+                grid.real()
+            }
+        }
+
+        impl Layer for FftFilter {
+            fn kind(&self) -> String {
+                "FftFilter".to_string()
+            }
+
+            fn input_types(&self) -> Vec<&'static str> {
+                vec![GrayImage::NAME]
+            }
+
+            fn output_type(&self) -> Option<&'static str> {
+                Some(GrayImageF32::NAME)
+            }
+
+            fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+                let input = single_input::<GrayImage>(input)?; // Only expects input from a single source layer
+                *output = Some(Box::new(FftFilter::compute(self, input)));
+                Ok(())
+            }
+
+            fn parameters(&self) -> ParamMap {
+                ParamMap::from([
+                    ("band".to_string(), self.band.to_value()),
+                    ("output".to_string(), self.output.to_value()),
+                    ("cutoff".to_string(), self.cutoff.to_value()),
+                    ("upper_cutoff".to_string(), self.upper_cutoff.to_value()),
+                ])
+            }
+
+            fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+                match name {
+                    "band" => self.band = Parameter::from_value(&value)?,
+                    "output" => self.output = Parameter::from_value(&value)?,
+                    "cutoff" => self.cutoff = Parameter::from_value(&value)?,
+                    "upper_cutoff" => self.upper_cutoff = Parameter::from_value(&value)?,
+                    _ => bail!(KlexError::UnknownParameter(name.to_string())),
+                }
+                Ok(())
+            }
+
+            fn scale_parameters(&mut self, factor: f64) {
+                // The same structures are closer to the Nyquist frequency of a smaller image
+                self.cutoff = (f64::from(self.cutoff) / factor) as f32;
+                self.upper_cutoff = (f64::from(self.upper_cutoff) / factor) as f32;
+            }
+
+            fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+                Ok(Box::new(Self::new(self.band, self.cutoff, self.upper_cutoff).with_output(self.output)))
+            }
+        }
+
+        #[cfg(feature = "ui")]
+        impl InteractiveLayer for FftFilter {}
+    }
+
+    impl Parameter for entity::DataFormat {
+        fn to_value(&self) -> ParamValue {
+            ParamValue::Choice(format!("{:?}", self))
+        }
+
+        fn from_value(value: &ParamValue) -> Result<Self> {
+            choice(value, &Self::ALL, "data format")
+        }
+    }
+
+    /// Writes data like lines, points or histograms to a CSV or JSON file, see `entity::table`, and puts out what it
+    /// wrote as a `DataFile`. Batch runs fill in `{stem}` and `{index}` in the path like in their output template, so
+    /// that each input gets a file of its own.
+    pub struct ExportData {
+        path: std::path::PathBuf,
+        format: entity::DataFormat,
+    }
+
+    impl ExportData {
+        pub fn new(path: std::path::PathBuf, format: entity::DataFormat) -> Self {
+            Self { path, format }
+        }
+    }
+
+    impl Layer for ExportData {
+        fn kind(&self) -> String {
+            "ExportData".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Output
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(entity::DataFile::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input = input.first().context("Missing input")?.as_deref().context("Empty input")?;
+            let file = entity::DataFile::of(input, self.format)?;
+            file.save(&self.path).context(format!("Failed to export data to {:?}", self.path))?;
+            *output = Some(Box::new(file));
+            Ok(())
+        }
+
+        fn cache_key(&self) -> Option<String> {
+            None // Writing the file is what it is for
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("path".to_string(), self.path.to_value()),
+                ("format".to_string(), self.format.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "path" => self.path = Parameter::from_value(&value)?,
+                "format" => self.format = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self::new(self.path.clone(), self.format)))
+        }
+    }
+
+    #[cfg(feature = "ui")]
+    impl InteractiveLayer for ExportData {}
+
+    /// Writes lines, points or contours to an SVG file, over the image they were found in if that is connected as
+    /// well. The inputs are told apart by their elements, so they can be connected in any order. Batch runs fill in
+    /// the path like for `ExportData`.
+    #[cfg(feature = "svg")]
+    pub struct ExportVector {
+        path: std::path::PathBuf,
+        color: Rgba<u8>,
+        width: f32, // Of the lines, in pixels of the SVG
+        scale: f32, // Pixels of the SVG per pixel of the image
+    }
+
+    #[cfg(feature = "svg")]
+    impl ExportVector {
+        pub fn new(path: std::path::PathBuf) -> Self {
+            Self {
+                path,
+                color: Rgba([255, 0, 0, 255]),
+                width: 2.0,
+                scale: 1.0,
+            }
+        }
+    }
+
+    #[cfg(feature = "svg")]
+    impl Layer for ExportVector {
+        fn kind(&self) -> String {
+            "ExportVector".to_string()
+        }
+
+        fn category(&self) -> LayerCategory {
+            LayerCategory::Output
+        }
+
+        fn output_type(&self) -> Option<&'static str> {
+            Some(crate::svg::Svg::NAME)
+        }
+
+        fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            let input: Vec<&(dyn Any + Send + Sync)> = input.iter().filter_map(|input| input.as_deref()).collect();
+            let mut geometry = input.iter().filter_map(|&input| entity::geometry(input));
+            let shapes = geometry.next().context("Expected lines, points or contours at one of the inputs")?;
+            ensure!(geometry.next().is_none(), "Expected a single input with lines, points or contours");
+            let mut images = input.iter().filter_map(|&input| entity::to_dynamic(input).ok());
+            let background = images.next();
+            ensure!(images.next().is_none(), "Expected at most one image to draw over");
+            ensure!(input.len() == 1 + usize::from(background.is_some()), "Expected shapes and an image to draw over");
+
+            let style = crate::svg::Style { color: self.color.0, width: self.width, scale: self.scale };
+            let svg = crate::svg::render(&shapes, background.as_ref(), &style)?;
+            svg.save(&self.path).context(format!("Failed to export shapes to {:?}", self.path))?;
+            *output = Some(Box::new(svg));
+            Ok(())
+        }
+
+        fn cache_key(&self) -> Option<String> {
+            None // Writing the file is what it is for
+        }
+
+        fn parameters(&self) -> ParamMap {
+            ParamMap::from([
+                ("path".to_string(), self.path.to_value()),
+                ("color".to_string(), self.color.to_value()),
+                ("width".to_string(), self.width.to_value()),
+                ("scale".to_string(), self.scale.to_value()),
+            ])
+        }
+
+        fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+            match name {
+                "path" => self.path = Parameter::from_value(&value)?,
+                "color" => self.color = Parameter::from_value(&value)?,
+                "width" => self.width = Parameter::from_value(&value)?,
+                "scale" => self.scale = Parameter::from_value(&value)?,
+                _ => bail!(KlexError::UnknownParameter(name.to_string())),
+            }
+            Ok(())
+        }
+
+        fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+            Ok(Box::new(Self { path: self.path.clone(), ..*self }))
+        }
+    }
+
+    #[cfg(all(feature = "svg", feature = "ui"))]
+    impl InteractiveLayer for ExportVector {}
 }
@@ -1,59 +1,1811 @@
-use std::any::{Any};
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, panic,
+    sync::{Arc, OnceLock},
+    thread,
+    time::SystemTime,
+};
 
-use anyhow::{Result};
-use petgraph::{graph::NodeIndex, Direction, Graph};
+use petgraph::{
+    algo,
+    graph::NodeIndex,
+    stable_graph::StableGraph,
+    visit::{Dfs, EdgeRef, IntoEdgeReferences},
+    Direction,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::layer::InteractiveLayer;
+use crate::{
+    cache::OutputCache,
+    composite::CompositeLayer,
+    entity::{self, BinaryImage, Rect, Stroke},
+    error::{bail, ensure, Context, KlexError, Result},
+    event_log::{EventLog, GraphEvent, LoggedEvent},
+    history::{Edit, History, Transaction},
+    layer::{self, CancelToken, ComputeContext, InputCount, Layer, LayerOutput},
+    parameter::{ParamMap, ParamValue},
+    util::BufferPool,
+};
 
-pub struct InteractiveLayerGraph {
-    pub layers: Graph<Box<dyn InteractiveLayer>, ()>, // Store layers together with their corresponding output
-    pub layer_output: Vec<Option<Box<dyn Any>>>,
-    selected_layer: NodeIndex,
+/// Inputs that are provided from outside of a graph, keyed by the receiving layer and input port
+pub(crate) type ExternalInputs<'a> = HashMap<(NodeIndex, usize), &'a LayerOutput>;
+
+struct LayerNode {
+    id: LayerId,
+    name: String,
+    layer: Box<dyn Layer>,
+    output: LayerOutput,
+    dirty: bool,                        // The output is outdated and has to be recomputed
+    output_size: usize,                 // Bytes occupied by the stored output
+    generation: u64,                    // Incremented whenever the output becomes outdated
+    modified: bool, // Dirty because of a change to the layer itself, not just to layers before it
+    output_generation: u64, // Incremented whenever a differing output is stored
+    output_hash: OnceLock<Option<u64>>, // Computed when first asked for
+    input_generations: Vec<(NodeIndex, u64)>, // Output generations of the inputs the output was computed from
+    error: Option<String>, // Why the layer failed to compute, until it is computed successfully
 }
 
-impl InteractiveLayerGraph {
+/// What an edge connects, besides the layers: an output port of its source and an input port of its target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Ports {
+    output: usize,
+    input: usize,
+}
+
+/// Identifies a layer for as long as it exists, unlike its `NodeIndex`, which is handed out again once the layer is
+/// removed. Ids are never reused within a graph and are kept in recipes, so they mean the same after loading one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LayerId(pub u64);
+
+impl fmt::Display for LayerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// An unconnected layer taken out of a graph, which can be put back at the index it had before
+pub(crate) struct DetachedLayer {
+    index: NodeIndex,
+    node: LayerNode,
+}
+
+/// Layers connected by edges that carry an output of one layer into an input port of another. This is all that's
+/// needed to compute a pipeline without any user interface.
+pub struct LayerGraph {
+    layers: StableGraph<LayerNode, Ports>,
+    ids: HashMap<LayerId, NodeIndex>, // The other way around, each layer knows its id
+    next_id: u64,
+    focus: Option<NodeIndex>,
+    memory_budget: Option<usize>,
+    skip_unchanged: bool,
+    buffer_pool: Arc<BufferPool>, // Temporary buffers of layers, reused between computations
+    output_cache: Option<Arc<OutputCache>>,
+}
+
+impl LayerGraph {
     pub fn new() -> Self {
         Self {
-            layers: Graph::new(),
-            layer_output: Vec::new(),
-            selected_layer: NodeIndex::new(0),
+            layers: StableGraph::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            focus: None,
+            memory_budget: None,
+            skip_unchanged: false,
+            buffer_pool: Arc::default(),
+            output_cache: None,
         }
     }
 
+    /// Adds a layer whose inputs are the outputs of `parent_nodes`, in order
+    pub fn add_layer(&mut self, layer: Box<dyn Layer>, parent_nodes: Vec<NodeIndex>) -> NodeIndex {
+        self.add_layer_with_children(layer, parent_nodes, vec![])
+    }
+
+    /// Adds a layer whose inputs are the first outputs of `parent_nodes`, in order. The first output of the new layer
+    /// is connected to the next free input port of each of `child_nodes`.
     pub fn add_layer_with_children(
         &mut self,
-        layer: Box<dyn InteractiveLayer>,
+        layer: Box<dyn Layer>,
         parent_nodes: Vec<NodeIndex>,
         child_nodes: Vec<NodeIndex>,
-    ) {
-        let new_node = self.layers.add_node(layer);
-        self.layer_output.push(None);
+    ) -> NodeIndex {
+        let name = layer.kind();
+        let id = self.new_id();
+        let new_node = self.add_node(LayerNode {
+            id,
+            name,
+            layer,
+            output: None,
+            dirty: true,
+            output_size: 0,
+            generation: 0,
+            modified: true,
+            output_generation: 0,
+            output_hash: OnceLock::new(),
+            input_generations: Vec::new(),
+            error: None,
+        });
 
-        for parent in parent_nodes {
-            self.layers.add_edge(parent, new_node, ());
+        for (port, parent) in parent_nodes.into_iter().enumerate() {
+            self.layers.add_edge(parent, new_node, Ports { output: 0, input: port });
         }
 
         for child in child_nodes {
-            self.layers.add_edge(new_node, child, ());
+            let port = self.next_free_port(child);
+            self.layers.add_edge(new_node, child, Ports { output: 0, input: port });
         }
+
+        self.mark_dirty(new_node);
+        new_node
     }
 
-    pub fn add_layer(&mut self, layer: Box<dyn InteractiveLayer>, parent_nodes: Vec<NodeIndex>) {
-        self.add_layer_with_children(layer, parent_nodes, vec![])
+    /// Removes a layer along with all of its connections
+    pub fn remove_layer(&mut self, layer: NodeIndex) -> Result<Box<dyn Layer>> {
+        self.node(layer)?;
+        for child in self.children(layer) {
+            self.mark_dirty(child);
+        }
+        if self.focus == Some(layer) {
+            self.focus = None;
+        }
+        Ok(self.remove_node(layer).expect("Layer exists").layer)
+    }
+
+    /// Takes an unconnected layer out of the graph. Unlike `remove_layer`, the index of the layer is handed out
+    /// again by `attach_layer` as long as the graph isn't changed in between other than by reverting later changes.
+    pub(crate) fn detach_layer(&mut self, layer: NodeIndex) -> Result<DetachedLayer> {
+        self.node(layer)?;
+        if self.layers.neighbors_undirected(layer).next().is_some() {
+            bail!("Layer {} is still connected and can't be detached", layer.index());
+        }
+        if self.focus == Some(layer) {
+            self.focus = None;
+        }
+        let node = self.remove_node(layer).expect("Layer exists");
+        Ok(DetachedLayer { index: layer, node })
+    }
+
+    pub(crate) fn attach_layer(&mut self, detached: DetachedLayer) -> Result<NodeIndex> {
+        let layer = self.add_node(detached.node);
+        if layer != detached.index {
+            self.remove_node(layer);
+            bail!(
+                "Layer {} can't be restored, because its index is taken",
+                detached.index.index()
+            );
+        }
+        Ok(layer)
+    }
+
+    /// Feeds the first output of `from` into input `port` of `to`
+    pub fn connect(&mut self, from: NodeIndex, to: NodeIndex, port: usize) -> Result<()> {
+        self.connect_output(from, 0, to, port)
+    }
+
+    /// Feeds output port `output` of `from` into input `port` of `to`
+    pub fn connect_output(&mut self, from: NodeIndex, output: usize, to: NodeIndex, port: usize) -> Result<()> {
+        let outputs = self.node(from)?.layer.outputs().len();
+        self.node(to)?;
+        ensure!(output < outputs, "Layer {} has no output {}", from.index(), output);
+        if let Some((source, _)) = self.inputs(to).into_iter().find(|&(_, input_port)| input_port == port) {
+            bail!(
+                "Input {} of layer {} is already connected to layer {}",
+                port,
+                to.index(),
+                source.index()
+            );
+        }
+        if from == to || algo::has_path_connecting(&self.layers, to, from, None) {
+            bail!(
+                "Connecting layer {} to layer {} would create a cycle",
+                from.index(),
+                to.index()
+            );
+        }
+
+        self.layers.add_edge(from, to, Ports { output, input: port });
+        self.mark_dirty(to);
+        Ok(())
+    }
+
+    /// Adds a connection without any checks, for putting back connections that existed before
+    pub(crate) fn restore_connection(
+        &mut self,
+        from: NodeIndex,
+        output: usize,
+        to: NodeIndex,
+        port: usize,
+    ) -> Result<()> {
+        self.node(from)?;
+        self.node(to)?;
+        self.layers.add_edge(from, to, Ports { output, input: port });
+        self.mark_dirty(to);
+        Ok(())
+    }
+
+    /// Removes the connection into input `port` of `to` and returns the layer it came from
+    pub fn disconnect(&mut self, to: NodeIndex, port: usize) -> Result<NodeIndex> {
+        let edge = self
+            .layers
+            .edges_directed(to, Direction::Incoming)
+            .find(|edge| edge.weight().input == port)
+            .ok_or(KlexError::MissingInput { node: to, port })?;
+        let (edge, source) = (edge.id(), edge.source());
+
+        self.layers.remove_edge(edge);
+        self.mark_dirty(to);
+        Ok(source)
+    }
+
+    pub fn contains(&self, layer: NodeIndex) -> bool {
+        self.layers.contains_node(layer)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.layers.node_count()
+    }
+
+    pub fn node_indices(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.layers.node_indices()
+    }
+
+    pub fn id(&self, layer: NodeIndex) -> Option<LayerId> {
+        self.layers.node_weight(layer).map(|node| node.id)
+    }
+
+    /// The index of the layer with an id, if it is part of the graph
+    pub fn find_by_id(&self, id: LayerId) -> Option<NodeIndex> {
+        self.ids.get(&id).copied()
+    }
+
+    /// All layers with a name, ordered by index. Names don't have to be unique.
+    pub fn find_by_name(&self, name: &str) -> Vec<NodeIndex> {
+        self.node_indices().filter(|&layer| self.layers[layer].name == name).collect()
+    }
+
+    /// Gives a layer the id it had before, e.g. in a recipe. Ids that are handed out later are all larger.
+    pub(crate) fn set_id(&mut self, layer: NodeIndex, id: LayerId) -> Result<()> {
+        if let Some(other) = self.find_by_id(id).filter(|&other| other != layer) {
+            bail!("Layers {} and {} can't both have id {}", other.index(), layer.index(), id);
+        }
+        let node = self.node_mut(layer)?;
+        let previous = std::mem::replace(&mut node.id, id);
+        self.ids.remove(&previous);
+        self.ids.insert(id, layer);
+        self.reserve_ids(id);
+        Ok(())
+    }
+
+    /// Makes sure that ids up to `last` aren't handed out to new layers, e.g. before giving layers their ids with
+    /// `set_id`
+    pub(crate) fn reserve_ids(&mut self, last: LayerId) {
+        self.next_id = self.next_id.max(last.0 + 1);
+    }
+
+    /// All connections of the graph as (source layer, output port of the source, target layer, input port of the
+    /// target)
+    pub fn edges(&self) -> impl Iterator<Item = (NodeIndex, usize, NodeIndex, usize)> + '_ {
+        (&self.layers).edge_references().map(|edge| {
+            let Ports { output, input } = *edge.weight();
+            (edge.source(), output, edge.target(), input)
+        })
+    }
+
+    /// The layers feeding into `layer` along with the input port they are connected to, ordered by port
+    pub fn inputs(&self, layer: NodeIndex) -> Vec<(NodeIndex, usize)> {
+        let mut inputs: Vec<(NodeIndex, usize)> = self
+            .layers
+            .edges_directed(layer, Direction::Incoming)
+            .map(|edge| (edge.source(), edge.weight().input))
+            .collect();
+        inputs.sort_by_key(|&(_, port)| port);
+        inputs
+    }
+
+    /// The layer and its output port that feed into input `port` of `to`, if it is connected
+    pub fn source(&self, to: NodeIndex, port: usize) -> Option<(NodeIndex, usize)> {
+        self.layers
+            .edges_directed(to, Direction::Incoming)
+            .find(|edge| edge.weight().input == port)
+            .map(|edge| (edge.source(), edge.weight().output))
+    }
+
+    pub fn children(&self, layer: NodeIndex) -> Vec<NodeIndex> {
+        self.layers.neighbors_directed(layer, Direction::Outgoing).collect()
+    }
+
+    pub fn layer(&self, layer: NodeIndex) -> Option<&dyn Layer> {
+        self.layers.node_weight(layer).map(|node| &*node.layer)
+    }
+
+    pub fn name(&self, layer: NodeIndex) -> Option<&str> {
+        self.layers.node_weight(layer).map(|node| node.name.as_str())
+    }
+
+    pub fn rename(&mut self, layer: NodeIndex, name: String) -> Result<()> {
+        self.node_mut(layer)?.name = name;
+        Ok(())
+    }
+
+    /// Why the layer failed the last time it was computed, unless it was computed successfully since
+    pub fn error(&self, layer: NodeIndex) -> Option<&str> {
+        self.layers.node_weight(layer).and_then(|node| node.error.as_deref())
+    }
+
+    /// The stored output of a layer at one of its output ports
+    pub fn output(&self, layer: NodeIndex, port: usize) -> Option<&(dyn Any + Send + Sync)> {
+        self.layers.node_weight(layer).and_then(|node| layer::port_output(&node.output, port).as_deref())
+    }
+
+    /// Adds an unconnected copy of a layer, with the same name and parameters but without its output
+    pub fn duplicate_layer(&mut self, layer: NodeIndex) -> Result<NodeIndex> {
+        let mut copy = self.node(layer)?.duplicate().context(format!("Failed to duplicate layer {}", layer.index()))?;
+        copy.id = self.new_id();
+        Ok(self.add_node(copy))
+    }
+
+    /// Copies the structure and parameters of the graph, without any outputs. Layer indices and ids stay the same.
+    pub fn try_clone(&self) -> Result<Self> {
+        let mut copies = HashMap::new();
+        for layer in self.node_indices() {
+            copies.insert(layer, self.layers[layer].duplicate()?);
+        }
+        Ok(Self {
+            layers: self.layers.map(
+                |layer, _| copies.remove(&layer).expect("Every layer was duplicated"),
+                |_, &ports| ports,
+            ),
+            ids: self.ids.clone(),
+            next_id: self.next_id,
+            focus: self.focus,
+            memory_budget: self.memory_budget,
+            skip_unchanged: self.skip_unchanged,
+            buffer_pool: self.buffer_pool.clone(), // Copies compute one after another, e.g. a preview and the original
+            output_cache: self.output_cache.clone(),
+        })
+    }
+
+    pub fn parameters(&self, layer: NodeIndex) -> Result<ParamMap> {
+        Ok(self.node(layer)?.layer.parameters())
+    }
+
+    pub fn set_parameter(&mut self, layer: NodeIndex, name: &str, value: ParamValue) -> Result<()> {
+        self.node_mut(layer)?
+            .layer
+            .set_parameter(name, value)
+            .context(format!("Failed to set parameter {:?} of layer {}", name, layer.index()))?;
+        self.mark_dirty(layer);
+        Ok(())
+    }
+
+    /// Paints on a layer that holds a canvas, see `Layer::canvas_mut`
+    pub fn paint(&mut self, layer: NodeIndex, stroke: &Stroke) -> Result<()> {
+        self.canvas_mut(layer)?.paint(stroke);
+        self.mark_dirty(layer);
+        Ok(())
+    }
+
+    /// Exchanges the canvas of a layer with another one of the same size
+    pub(crate) fn swap_canvas(&mut self, layer: NodeIndex, canvas: &mut BinaryImage) -> Result<()> {
+        let current = self.canvas_mut(layer)?;
+        let size = |canvas: &BinaryImage| (canvas.width(), canvas.height());
+        if size(current) != size(canvas) {
+            let mismatch = KlexError::ShapeMismatch { expected: size(current), found: size(canvas) };
+            return Err(mismatch).context(format!("Canvas of layer {} changed its size", layer.index()));
+        }
+        std::mem::swap(current, canvas);
+        self.mark_dirty(layer);
+        Ok(())
+    }
+
+    fn canvas_mut(&mut self, layer: NodeIndex) -> Result<&mut BinaryImage> {
+        self.node_mut(layer)?
+            .layer
+            .canvas_mut()
+            .context(format!("Layer {} can't be painted on", layer.index()))
+    }
+
+    /// Swaps the layer at an index for another one, keeping its name and connections. Returns the previous layer.
+    pub fn replace_layer(&mut self, layer: NodeIndex, replacement: Box<dyn Layer>) -> Result<Box<dyn Layer>> {
+        let previous = std::mem::replace(&mut self.node_mut(layer)?.layer, replacement);
+        self.mark_dirty(layer);
+        Ok(previous)
+    }
+
+    /// Stores an output for a layer that was computed elsewhere, e.g. a downscaled copy of the output of another
+    /// graph. Everything depending on the layer becomes outdated.
+    #[cfg(feature = "ui")]
+    pub(crate) fn set_output(&mut self, layer: NodeIndex, output: LayerOutput) -> Result<()> {
+        self.node(layer)?;
+        self.mark_dirty(layer);
+        self.store_output(layer, output);
+        Ok(())
+    }
+
+    /// Marks the output of a layer and of everything depending on it as outdated
+    pub fn mark_dirty(&mut self, layer: NodeIndex) {
+        let mut dfs = Dfs::new(&self.layers, layer);
+        while let Some(node) = dfs.next(&self.layers) {
+            self.layers[node].dirty = true;
+            self.layers[node].generation += 1;
+        }
+        self.layers[layer].modified = true;
+    }
+
+    /// Changes whenever a layer gets an output that differs from its previous one. With `set_skip_unchanged`, an
+    /// output that is recomputed to the same content keeps its generation.
+    pub fn output_generation(&self, layer: NodeIndex) -> Option<u64> {
+        self.layers.node_weight(layer).map(|node| node.output_generation)
+    }
+
+    /// A hash of the content of the output of a layer, see `entity::content_hash`. It's computed when it's first
+    /// asked for and then kept along with the output.
+    pub fn output_hash(&self, layer: NodeIndex) -> Option<u64> {
+        self.layers.node_weight(layer)?.hash()
+    }
+
+    /// Whether layers that are only dirty because of layers before them are left as they are if the outputs before
+    /// them are recomputed to the same content as before. Comparing outputs takes hashing them, which is why it has to
+    /// be asked for, e.g. when a threshold is dragged over a range of values that no pixel has.
+    pub fn set_skip_unchanged(&mut self, skip: bool) {
+        self.skip_unchanged = skip;
+    }
+
+    /// Changes whenever the output of a layer becomes outdated. A result computed for a layer is only valid as long as
+    /// the generation of the layer stays the same.
+    pub fn generation(&self, layer: NodeIndex) -> Option<u64> {
+        self.layers.node_weight(layer).map(|node| node.generation)
+    }
+
+    pub fn is_dirty(&self, layer: NodeIndex) -> bool {
+        self.layers.node_weight(layer).is_some_and(|node| node.dirty)
+    }
+
+    /// Scales the pixel parameters of all layers, see `Layer::scale_parameters`
+    pub fn scale_parameters(&mut self, factor: f64) {
+        let layers: Vec<NodeIndex> = self.node_indices().collect();
+        for layer in layers {
+            self.layers[layer].layer.scale_parameters(factor);
+            self.mark_dirty(layer);
+        }
+    }
+
+    /// The layers that have to be computed, one after another, to bring the outputs of `layers` up to date. Inputs
+    /// come before the layers that depend on them.
+    pub fn compute_order(&self, layers: &[NodeIndex]) -> Result<Vec<NodeIndex>> {
+        for &layer in layers {
+            self.node(layer)?;
+        }
+        let mut needed = HashSet::new();
+        let mut unvisited = layers.to_vec();
+        while let Some(layer) = unvisited.pop() {
+            if (self.is_dirty(layer) || self.layers[layer].output.is_none()) && needed.insert(layer) {
+                unvisited.extend(self.layers.neighbors_directed(layer, Direction::Incoming));
+            }
+        }
+
+        let order = algo::toposort(&self.layers, None).map_err(|cycle| self.cycle_error(cycle.node_id()))?;
+        Ok(order.into_iter().filter(|layer| needed.contains(layer)).collect())
+    }
+
+    /// The layer whose surroundings are most important to keep in memory, usually the one being looked at
+    pub fn set_focus(&mut self, layer: Option<NodeIndex>) {
+        self.focus = layer;
+    }
+
+    pub fn focus(&self) -> Option<NodeIndex> {
+        self.focus
+    }
+
+    /// Limits the number of bytes the stored outputs may occupy. When exceeded, outputs of layers far away from the
+    /// focused layer are dropped and recomputed once they are needed again. Source layers are never evicted.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+        self.evict_outputs(&HashSet::new());
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Keeps outputs on disk and uses them instead of computing layers whose kind, parameters and inputs match those
+    /// of a stored output, e.g. in a later run of the same pipeline
+    pub fn set_output_cache(&mut self, cache: Option<Arc<OutputCache>>) {
+        self.output_cache = cache;
+    }
+
+    pub fn output_cache(&self) -> Option<&OutputCache> {
+        self.output_cache.as_deref()
+    }
+
+    /// Where layers take temporary buffers from, e.g. to see how often they could be reused
+    pub fn buffer_pool(&self) -> &BufferPool {
+        &self.buffer_pool
+    }
+
+    /// Number of bytes currently occupied by the stored outputs
+    pub fn memory_usage(&self) -> usize {
+        self.layers.node_weights().map(|node| node.output_size).sum()
     }
 
     pub fn compute_layer(&mut self, layer: NodeIndex) -> Result<()> {
-        let input: Vec<&Option<Box<dyn Any>>> = self
+        self.compute_layer_cancellable(layer, &CancelToken::new())
+    }
+
+    /// Like `compute_layer`, but stops with `KlexError::Cancelled` once `cancel` is cancelled
+    pub fn compute_layer_cancellable(&mut self, layer: NodeIndex, cancel: &CancelToken) -> Result<()> {
+        self.node(layer)?;
+        self.restore_inputs(layer, cancel)?;
+        let output = self.compute_output(layer, &ExternalInputs::new(), cancel);
+        let output = self.record_failure(layer, output)?;
+        self.store_output(layer, output);
+
+        for child in self.children(layer) {
+            self.mark_dirty(child);
+        }
+
+        self.evict_outputs(&HashSet::from([layer])); // Don't throw away what was just asked for
+        Ok(())
+    }
+
+    /// Like `compute_layer`, but computes the layers leading up to `layer` that declare a halo tile by tile, so that
+    /// only the output of `layer` is ever held in full. Tiles are `tile_size` pixels square and overlap by the halos
+    /// of these layers, so the result is the same as computing the layers as a whole. The tiles are cut from the output
+    /// of the closest layer without a halo, or one whose output is read by other layers as well.
+    pub fn compute_layer_tiled(&mut self, layer: NodeIndex, tile_size: u32) -> Result<()> {
+        ensure!(tile_size > 0, "Tiles must not be empty");
+        let chain = self.tiled_chain(layer)?;
+        let first = match chain.first() {
+            Some(&first) => first,
+            None => return self.compute_layer(layer), // Nothing to tile
+        };
+        let cancel = CancelToken::new();
+        self.restore_inputs(first, &cancel)?;
+        let (source, output) = self.source(first, 0).context("Input of tiled layers is missing")?;
+        let input = self.output(source, output).context("Input of tiled layers is missing")?;
+        let (width, height) = entity::dimensions(input).context("Only images can be computed tile by tile")?;
+        ensure!(width > 0 && height > 0, "Can't compute an empty image tile by tile");
+        let halo: usize = chain.iter().filter_map(|&layer| self.layers[layer].layer.halo()).sum();
+        let halo = u32::try_from(halo).unwrap_or(u32::MAX);
+        let context = ComputeContext {
+            cancel: &cancel,
+            pool: Some(&self.buffer_pool),
+        };
+
+        let mut output: LayerOutput = None;
+        for y in (0..height).step_by(tile_size as usize) {
+            for x in (0..width).step_by(tile_size as usize) {
+                let tile = Rect::new(x, y, tile_size, tile_size).clamped((width, height));
+                let (left, top) = (x.saturating_sub(halo), y.saturating_sub(halo));
+                let (right, bottom) = (tile.right().saturating_add(halo), tile.bottom().saturating_add(halo));
+                let grown = Rect::new(left, top, right - left, bottom - top).clamped((width, height));
+
+                let mut tile_output = entity::crop(input, grown);
+                for &layer in &chain {
+                    let mut next = None;
+                    let computed = self.layers[layer].layer.compute_in_context(&[&tile_output], &mut next, context);
+                    computed.with_context(|| self.failure(layer))?;
+                    tile_output = next;
+                }
+                let tile_output = tile_output.context("Tiled layers produced no output")?;
+                ensure!(
+                    entity::dimensions(tile_output.as_ref()) == Some((grown.width, grown.height)),
+                    "Layers with a halo have to keep the size of the image"
+                );
+                let inner = Rect::new(x - grown.x, y - grown.y, tile.width, tile.height);
+                let inner = entity::crop(tile_output.as_ref(), inner).context("Can't cut out a tile")?;
+                if output.is_none() {
+                    output = entity::blank(inner.as_ref(), width, height);
+                }
+                let output = output.as_deref_mut().context("Can't stitch tiles of this element")?;
+                entity::paste(output, inner.as_ref(), x, y)?;
+            }
+        }
+
+        // The layers before `layer` end up like evicted ones, so they're computed again when they're needed
+        for &previous in &chain[..chain.len() - 1] {
+            self.store_output(previous, None);
+        }
+        self.store_output(layer, output);
+        for child in self.children(layer) {
+            self.mark_dirty(child);
+        }
+        self.evict_outputs(&HashSet::from([layer]));
+        Ok(())
+    }
+
+    /// The layers that `compute_layer_tiled` computes tile by tile, first to last
+    fn tiled_chain(&self, layer: NodeIndex) -> Result<Vec<NodeIndex>> {
+        self.node(layer)?;
+        let mut chain = Vec::new();
+        let mut current = layer;
+        loop {
+            let node = &self.layers[current];
+            let parent = match self.inputs(current)[..] {
+                [(parent, 0)] if node.layer.halo().is_some() && node.layer.input_types().len() <= 1 => parent,
+                _ => break,
+            };
+            if node.layer.outputs().len() != 1 {
+                break; // Only single outputs are stitched together from tiles
+            }
+            // Outputs before `layer` aren't kept, so nothing else may need them
+            if current != layer && (self.children(current).len() != 1 || self.focus == Some(current)) {
+                break;
+            }
+            chain.push(current);
+            current = parent;
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Computes every dirty layer of the graph, along with evicted outputs that these depend on. Layers that don't
+    /// depend on each other are computed concurrently, one wavefront at a time, so that all inputs of a wavefront
+    /// are available before it starts.
+    pub fn compute_all(&mut self) -> Result<()> {
+        self.compute_all_with_inputs(&ExternalInputs::new(), &CancelToken::new())
+    }
+
+    pub(crate) fn compute_all_with_inputs(&mut self, external: &ExternalInputs, cancel: &CancelToken) -> Result<()> {
+        let mut pending: HashSet<NodeIndex> = self.node_indices().filter(|&layer| self.is_dirty(layer)).collect();
+
+        // Evicted inputs of pending layers have to be restored as well
+        let mut unvisited: Vec<NodeIndex> = pending.iter().copied().collect();
+        while let Some(layer) = unvisited.pop() {
+            for parent in self.layers.neighbors_directed(layer, Direction::Incoming) {
+                if self.layers[parent].output.is_none() && pending.insert(parent) {
+                    unvisited.push(parent);
+                }
+            }
+        }
+
+        for mut wavefront in self.wavefronts(&pending)? {
+            wavefront.retain(|&layer| !(self.keep_unchanged(layer, external) && pending.remove(&layer)));
+            let mut reused: HashMap<NodeIndex, LayerOutput> = wavefront
+                .iter()
+                .filter_map(|&layer| Some((layer, self.take_reusable_input(layer, external)?)))
+                .collect();
+            let outputs: Vec<Result<LayerOutput>> = if let [layer] = wavefront[..] {
+                // No need to spin up a thread for a single layer
+                vec![self.compute_output_reusing(layer, reused.remove(&layer), external, cancel)]
+            } else {
+                let graph = &*self;
+                thread::scope(|scope| {
+                    let handles: Vec<_> = wavefront
+                        .iter()
+                        .map(|&layer| {
+                            let input = reused.remove(&layer);
+                            scope.spawn(move || graph.compute_output_reusing(layer, input, external, cancel))
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+                        .collect()
+                })
+            };
+
+            // Keep the results of the layers that succeeded, even if a sibling failed
+            let mut error = None;
+            for (&layer, output) in wavefront.iter().zip(outputs) {
+                match self.record_failure(layer, output) {
+                    Ok(output) => self.store_output(layer, output),
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+            if let Some(e) = error {
+                return Err(e);
+            }
+
+            for layer in &wavefront {
+                pending.remove(layer);
+            }
+            let still_needed = pending
+                .iter()
+                .flat_map(|&layer| self.layers.neighbors_directed(layer, Direction::Incoming))
+                .collect();
+            self.evict_outputs(&still_needed);
+        }
+        Ok(())
+    }
+
+    /// Moves the output out of the graph. The layer is dirty afterwards.
+    pub(crate) fn take_output(&mut self, layer: NodeIndex) -> Result<LayerOutput> {
+        let node = self.node_mut(layer)?;
+        node.dirty = true;
+        Ok(node.take_output())
+    }
+
+    /// Collapses a selection of layers into a single `CompositeLayer`. Connections from outside of the selection
+    /// become inputs of the composite layer. The selection must have exactly one layer that is connected to the rest
+    /// of the graph or, lacking such connections, exactly one layer without children. That layer's output becomes the
+    /// output of the composite layer.
+    pub fn group_nodes(&mut self, nodes: &[NodeIndex]) -> Result<NodeIndex> {
+        if nodes.is_empty() {
+            bail!("Cannot group an empty selection of layers");
+        }
+        let selection: HashSet<NodeIndex> = nodes.iter().copied().collect();
+        for &layer in &selection {
+            self.node(layer)?;
+        }
+
+        let mut incoming = Vec::new();
+        let mut outgoing = Vec::new();
+        let mut internal = Vec::new();
+        for (from, output, to, port) in self.edges() {
+            match (selection.contains(&from), selection.contains(&to)) {
+                (false, true) => incoming.push((from, output, to, port)),
+                (true, false) => outgoing.push((from, output, to, port)),
+                (true, true) => internal.push((from, output, to, port)),
+                (false, false) => (),
+            }
+        }
+        incoming.sort_by_key(|&(from, output, to, port)| (to, port, from, output));
+
+        let output_candidates: HashSet<NodeIndex> = if outgoing.is_empty() {
+            selection
+                .iter()
+                .copied()
+                .filter(|&layer| self.children(layer).iter().all(|child| !selection.contains(child)))
+                .collect()
+        } else {
+            outgoing.iter().map(|&(from, _, _, _)| from).collect()
+        };
+        let output = match output_candidates.into_iter().collect::<Vec<_>>()[..] {
+            [output] => output,
+            _ => bail!("The selected layers must have exactly one output to be grouped"),
+        };
+
+        // Move the selected layers into the graph of the composite layer
+        let mut inner = Self::new();
+        let mut moved = HashMap::new();
+        let mut ordered: Vec<NodeIndex> = selection.iter().copied().collect();
+        ordered.sort();
+        for layer in ordered {
+            let node = self.remove_node(layer).expect("Layer exists");
+            moved.insert(layer, inner.add_node(node));
+        }
+        for (from, output, to, port) in internal {
+            inner.layers.add_edge(moved[&from], moved[&to], Ports { output, input: port });
+        }
+
+        // Every external output feeding into the selection becomes one input of the composite layer
+        let mut sources: Vec<(NodeIndex, usize)> = Vec::new();
+        let mut inputs: Vec<Vec<(NodeIndex, usize)>> = Vec::new();
+        for (from, output, to, port) in incoming {
+            let composite_port = match sources.iter().position(|&source| source == (from, output)) {
+                Some(composite_port) => composite_port,
+                None => {
+                    sources.push((from, output));
+                    inputs.push(Vec::new());
+                    sources.len() - 1
+                }
+            };
+            inputs[composite_port].push((moved[&to], port));
+        }
+
+        // The composite layer has the outputs of the layer whose output it passes on
+        let composite = CompositeLayer::new(inner, inputs, moved[&output])?;
+        let composite = self.add_layer(Box::new(composite), vec![]);
+        for (port, (from, output)) in sources.into_iter().enumerate() {
+            self.layers.add_edge(from, composite, Ports { output, input: port });
+        }
+        for (_, output, to, port) in outgoing {
+            self.layers.add_edge(composite, to, Ports { output, input: port });
+            self.mark_dirty(to);
+        }
+        if self.focus.is_some_and(|focus| selection.contains(&focus)) {
+            self.focus = Some(composite);
+        }
+        Ok(composite)
+    }
+
+    fn add_node(&mut self, mut node: LayerNode) -> NodeIndex {
+        node.dirty = true;
+        let id = node.id;
+        let layer = self.layers.add_node(node);
+        self.ids.insert(id, layer);
+        layer
+    }
+
+    fn remove_node(&mut self, layer: NodeIndex) -> Option<LayerNode> {
+        let node = self.layers.remove_node(layer)?;
+        self.ids.remove(&node.id);
+        Some(node)
+    }
+
+    fn new_id(&mut self) -> LayerId {
+        self.next_id += 1;
+        LayerId(self.next_id - 1)
+    }
+
+    fn node(&self, layer: NodeIndex) -> Result<&LayerNode> {
+        self.layers
+            .node_weight(layer)
+            .context(format!("There is no layer {}", layer.index()))
+    }
+
+    fn node_mut(&mut self, layer: NodeIndex) -> Result<&mut LayerNode> {
+        self.layers
+            .node_weight_mut(layer)
+            .context(format!("There is no layer {}", layer.index()))
+    }
+
+    /// The error for a cycle through `layer`, naming every layer that is part of a cycle along with it
+    fn cycle_error(&self, layer: NodeIndex) -> KlexError {
+        let mut nodes = algo::tarjan_scc(&self.layers)
+            .into_iter()
+            .find(|component| component.contains(&layer))
+            .unwrap_or_else(|| vec![layer]);
+        nodes.sort();
+        KlexError::CycleDetected { nodes }
+    }
+
+    fn next_free_port(&self, layer: NodeIndex) -> usize {
+        self.inputs(layer).last().map_or(0, |&(_, port)| port + 1)
+    }
+
+    fn compute_output(&self, layer: NodeIndex, external: &ExternalInputs, cancel: &CancelToken) -> Result<LayerOutput> {
+        cancel.check()?;
+        let mut input: Vec<(usize, &LayerOutput)> = self
             .layers
-            .neighbors_directed(layer, Direction::Incoming)
-            .map(|neighbor| &self.layer_output[neighbor.index()])
+            .edges_directed(layer, Direction::Incoming)
+            .map(|edge| {
+                let Ports { output, input } = *edge.weight();
+                (input, layer::port_output(&self.layers[edge.source()].output, output))
+            })
             .collect();
+        input.extend(
+            external
+                .iter()
+                .filter(|((target, _), _)| *target == layer)
+                .map(|(&(_, port), &output)| (port, output)),
+        );
+        input.sort_by_key(|&(port, _)| port);
+        let node = &self.layers[layer];
+        let ports = match node.layer.expected_inputs() {
+            InputCount::Unknown => 0,
+            InputCount::Exactly(count) => count,
+            InputCount::AtLeast(count) => count.max(input.len()),
+        };
+        if let Some(port) = (0..ports).find(|&port| !input.iter().any(|&(connected, _)| connected == port)) {
+            bail!(KlexError::MissingInput { node: layer, port });
+        }
+        if let InputCount::Exactly(expected) = node.layer.expected_inputs() {
+            ensure!(input.len() == expected, KlexError::ExtraInputs { node: layer, found: input.len(), expected });
+        }
+        let input: Vec<&LayerOutput> = input.into_iter().map(|(_, output)| output).collect();
+
+        let cache = self.output_cache.as_deref();
+        let cached = cache.and_then(|cache| Some((cache, OutputCache::key(node.layer.as_ref(), &input)?)));
+        if let Some((cache, key)) = cached {
+            if let Some(output) = cache.load(&node.layer.kind(), key) {
+                return Ok(Some(output));
+            }
+        }
 
         let mut output = None;
-        self.layers[layer].compute(&input, &mut output)?;
-        self.layer_output[layer.index()] = output;
+        let context = ComputeContext {
+            cancel,
+            pool: Some(&self.buffer_pool),
+        };
+        node.layer.compute_in_context(&input, &mut output, context).with_context(|| self.failure(layer))?;
+        if let (Some((cache, key)), Some(output)) = (cached, output.as_deref()) {
+            // Failing to store an output, e.g. because the disk is full, only means computing it again next time
+            cache.store(&node.layer.kind(), key, output).ok();
+        }
+        Ok(output)
+    }
+
+    fn failure(&self, layer: NodeIndex) -> String {
+        let node = &self.layers[layer];
+        format!("Failed to compute layer {} {:?} ({})", layer.index(), node.name, node.layer.kind())
+    }
+
+    /// Computes a layer in place on its input if that was taken from its parent, and like `compute_output` otherwise
+    fn compute_output_reusing(
+        &self,
+        layer: NodeIndex,
+        input: Option<LayerOutput>,
+        external: &ExternalInputs,
+        cancel: &CancelToken,
+    ) -> Result<LayerOutput> {
+        let mut output = match input {
+            Some(input) => input,
+            None => return self.compute_output(layer, external, cancel),
+        };
+        cancel.check()?;
+        let node = &self.layers[layer];
+        let element = output.as_deref_mut().ok_or(KlexError::MissingInput { node: layer, port: 0 })?;
+        node.layer.compute_in_place(element).with_context(|| self.failure(layer))?;
+        Ok(output)
+    }
+
+    /// Moves the output of a layer's parent out of the graph, if the layer can compute in place on it and nothing
+    /// else reads it. The parent is left like an evicted one, so its output is restored when it's needed again.
+    fn take_reusable_input(&mut self, layer: NodeIndex, external: &ExternalInputs) -> Option<LayerOutput> {
+        let node = &self.layers[layer];
+        if !node.layer.computes_in_place() || external.keys().any(|&(target, _)| target == layer) {
+            return None;
+        }
+        let parent = match self.inputs(layer)[..] {
+            [(parent, 0)] if self.layers[parent].layer.outputs().len() == 1 => parent,
+            _ => return None,
+        };
+        // Outputs that can't be evicted, like those of layers without inputs, or that are shown aren't taken either
+        let shared = self.layers.edges_directed(parent, Direction::Outgoing).count() != 1;
+        let source = self.layers.neighbors_directed(parent, Direction::Incoming).next().is_none();
+        if shared || source || self.focus == Some(parent) || self.layers[parent].output.is_none() {
+            return None;
+        }
+        Some(self.layers[parent].take_output())
+    }
+
+    fn store_output(&mut self, layer: NodeIndex, output: LayerOutput) {
+        let input_generations = self.input_generations(layer);
+        let skip_unchanged = self.skip_unchanged;
+        let node = &mut self.layers[layer];
+        // Restored outputs are the ones from before, so only computed ones can be new
+        let mut hash = None;
+        if node.dirty {
+            if skip_unchanged && node.output.is_some() {
+                hash = output.as_deref().and_then(|output| entity::content_hash(output));
+            }
+            if hash.is_none() || hash != node.hash() {
+                node.output_generation += 1;
+            }
+        }
+        node.output_size = output
+            .as_deref()
+            .and_then(|output| node.layer.output_size_bytes(output))
+            .unwrap_or(0);
+        node.output = output;
+        node.output_hash = hash.map(|hash| OnceLock::from(Some(hash))).unwrap_or_default();
+        node.input_generations = input_generations;
+        node.dirty = false;
+        node.modified = false;
+        node.error = None;
+    }
+
+    /// Remembers why computing a layer failed, unless it was cancelled
+    fn record_failure(&mut self, layer: NodeIndex, output: Result<LayerOutput>) -> Result<LayerOutput> {
+        if let Err(e) = &output {
+            if !matches!(e.root(), KlexError::Cancelled) {
+                self.layers[layer].error = Some(format!("{:#}", e));
+            }
+        }
+        output
+    }
+
+    fn input_generations(&self, layer: NodeIndex) -> Vec<(NodeIndex, u64)> {
+        let mut inputs = self.inputs(layer);
+        inputs.sort_by_key(|&(_, port)| port);
+        inputs.into_iter().map(|(parent, _)| (parent, self.layers[parent].output_generation)).collect()
+    }
+
+    /// Marks a dirty layer clean without computing it, if unchanged outputs are skipped and the layer is only dirty
+    /// because of layers before it, whose outputs all turned out the same as when the layer was last computed
+    fn keep_unchanged(&mut self, layer: NodeIndex, external: &ExternalInputs) -> bool {
+        let node = &self.layers[layer];
+        let external = external.keys().any(|&(target, _)| target == layer);
+        if !self.skip_unchanged || !node.dirty || node.modified || node.output.is_none() || external {
+            return false;
+        }
+        if self.input_generations(layer) != node.input_generations {
+            return false;
+        }
+        self.layers[layer].dirty = false;
+        true
+    }
+
+    /// Recomputes inputs of a layer that were evicted. Restored outputs are identical to the evicted ones, so
+    /// nothing depending on them becomes dirty.
+    fn restore_inputs(&mut self, layer: NodeIndex, cancel: &CancelToken) -> Result<()> {
+        for (parent, _) in self.inputs(layer) {
+            if self.layers[parent].output.is_none() {
+                self.restore_inputs(parent, cancel)?;
+                let output = self.compute_output(parent, &ExternalInputs::new(), cancel);
+                let output = self.record_failure(parent, output)?;
+                self.store_output(parent, output);
+            }
+        }
         Ok(())
     }
+
+    /// Drops outputs until the memory budget is met, starting with the layers farthest away from the focused layer
+    fn evict_outputs(&mut self, protected: &HashSet<NodeIndex>) {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let mut usage = self.memory_usage();
+        if usage <= budget {
+            return;
+        }
+
+        let distance = self.focus.map(|focus| self.distances_from(focus)).unwrap_or_default();
+        let mut candidates: Vec<NodeIndex> = self
+            .node_indices()
+            .filter(|layer| {
+                Some(*layer) != self.focus
+                    && !protected.contains(layer)
+                    && self.layers[*layer].output.is_some()
+                    && self.layers.neighbors_directed(*layer, Direction::Incoming).next().is_some()
+            })
+            .collect();
+        candidates.sort_by_key(|layer| Reverse(distance.get(layer).copied().unwrap_or(usize::MAX)));
+
+        for layer in candidates {
+            if usage <= budget {
+                break;
+            }
+            let node = &mut self.layers[layer];
+            #[cfg(feature = "tracing")]
+            tracing::debug!(layer = layer.index(), name = %node.name, bytes = node.output_size, "Evicted output");
+            usage -= node.output_size;
+            node.take_output();
+        }
+    }
+
+    /// Number of edges between `origin` and every layer connected to it, regardless of edge direction
+    fn distances_from(&self, origin: NodeIndex) -> HashMap<NodeIndex, usize> {
+        let mut distance = HashMap::new();
+        if !self.contains(origin) {
+            return distance;
+        }
+
+        distance.insert(origin, 0);
+        let mut queue = VecDeque::from(vec![origin]);
+        while let Some(layer) = queue.pop_front() {
+            let next_distance = distance[&layer] + 1;
+            for neighbor in self.layers.neighbors_undirected(layer) {
+                distance.entry(neighbor).or_insert_with(|| {
+                    queue.push_back(neighbor);
+                    next_distance
+                });
+            }
+        }
+        distance
+    }
+
+    /// Groups the given layers by their depth in the graph. A layer only depends on layers of earlier wavefronts, so
+    /// the layers within a wavefront are independent of each other.
+    fn wavefronts(&self, layers: &HashSet<NodeIndex>) -> Result<Vec<Vec<NodeIndex>>> {
+        let order = algo::toposort(&self.layers, None).map_err(|cycle| self.cycle_error(cycle.node_id()))?;
+
+        let mut depth: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut wavefronts: Vec<Vec<NodeIndex>> = Vec::new();
+        for layer in order.into_iter().filter(|layer| layers.contains(layer)) {
+            let layer_depth = self
+                .layers
+                .neighbors_directed(layer, Direction::Incoming)
+                .filter_map(|parent| depth.get(&parent).map(|parent_depth| parent_depth + 1))
+                .max()
+                .unwrap_or(0);
+            depth.insert(layer, layer_depth);
+
+            if wavefronts.len() <= layer_depth {
+                wavefronts.resize_with(layer_depth + 1, Vec::new);
+            }
+            wavefronts[layer_depth].push(layer);
+        }
+        Ok(wavefronts)
+    }
+}
+
+impl Default for LayerGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LayerNode {
+    fn hash(&self) -> Option<u64> {
+        *self.output_hash.get_or_init(|| entity::content_hash(self.output.as_deref()?))
+    }
+
+    fn take_output(&mut self) -> LayerOutput {
+        self.output_size = 0;
+        self.output_hash = OnceLock::new();
+        self.output.take()
+    }
+
+    /// A copy with the same id, which only one of them may keep in the same graph
+    fn duplicate(&self) -> Result<Self> {
+        Ok(Self {
+            id: self.id,
+            name: self.name.clone(),
+            layer: self.layer.clone_boxed()?,
+            output: None,
+            dirty: true,
+            output_size: 0,
+            generation: 0,
+            modified: true,
+            output_generation: 0,
+            output_hash: OnceLock::new(),
+            input_generations: Vec::new(),
+            error: None,
+        })
+    }
+}
+
+/// The structure of a graph and the state of its layers, without their outputs, e.g. for a user interface that
+/// doesn't own the graph. Taking one is cheap enough to do after every edit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphSnapshot {
+    pub nodes: Vec<NodeSnapshot>,                         // Ordered by index
+    pub edges: Vec<(NodeIndex, usize, NodeIndex, usize)>, // See `LayerGraph::edges`, ordered by `edge_order`
+    pub selected: Option<NodeIndex>,
+}
+
+impl GraphSnapshot {
+    /// What changed between two snapshots of the same graph. Layers whose index was reused by another layer are told
+    /// apart by their ids. They count as removed and added, along with their connections.
+    pub fn diff(old: &GraphSnapshot, new: &GraphSnapshot) -> GraphDelta {
+        let old_nodes: HashMap<_, _> = old.nodes.iter().map(|node| (node.node, node)).collect();
+        let new_nodes: HashSet<_> = new.nodes.iter().map(|node| node.node).collect();
+        let mut delta = GraphDelta {
+            removed: old.nodes.iter().map(|node| node.node).filter(|node| !new_nodes.contains(node)).collect(),
+            selected: new.selected,
+            ..GraphDelta::default()
+        };
+        let mut replaced = HashSet::new();
+        for node in &new.nodes {
+            match old_nodes.get(&node.node) {
+                None => delta.added.push(node.clone()),
+                Some(&old_node) if old_node.id != node.id => {
+                    delta.removed.push(node.node);
+                    delta.added.push(node.clone());
+                    replaced.insert(node.node);
+                }
+                Some(&old_node) if old_node != node => delta.changed.push(node.clone()),
+                Some(_) => (),
+            }
+        }
+        delta.removed.sort();
+        let old_edges: HashSet<_> = old.edges.iter().collect();
+        let new_edges: HashSet<_> = new.edges.iter().collect();
+        let replaced = |&&(from, _, to, _): &&(NodeIndex, usize, NodeIndex, usize)| {
+            replaced.contains(&from) || replaced.contains(&to)
+        };
+        let removed_edges = old.edges.iter().filter(|edge| !new_edges.contains(edge) || replaced(edge));
+        delta.removed_edges = removed_edges.copied().collect();
+        let added_edges = new.edges.iter().filter(|edge| !old_edges.contains(edge) || replaced(edge));
+        delta.added_edges = added_edges.copied().collect();
+        delta
+    }
+}
+
+/// Order of the edges of a `GraphSnapshot`: by the layer they lead to, then by port
+pub fn edge_order(
+    &(from, output, to, port): &(NodeIndex, usize, NodeIndex, usize),
+) -> (NodeIndex, usize, NodeIndex, usize) {
+    (to, port, from, output)
+}
+
+/// The difference between two snapshots of a graph, see `GraphSnapshot::diff`. Applying it to the older snapshot
+/// gives the newer one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphDelta {
+    pub added: Vec<NodeSnapshot>,
+    pub removed: Vec<NodeIndex>,
+    pub changed: Vec<NodeSnapshot>, // The new state of layers that were there before
+    pub added_edges: Vec<(NodeIndex, usize, NodeIndex, usize)>,
+    pub removed_edges: Vec<(NodeIndex, usize, NodeIndex, usize)>,
+    pub selected: Option<NodeIndex>, // Whether or not it changed
+}
+
+impl GraphDelta {
+    /// Whether the delta changes nothing but possibly the selection
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+
+    pub fn apply(&self, snapshot: &mut GraphSnapshot) {
+        snapshot.nodes.retain(|node| !self.removed.contains(&node.node));
+        for node in self.changed.iter().chain(&self.added) {
+            match snapshot.nodes.binary_search_by_key(&node.node, |node| node.node) {
+                Ok(i) => snapshot.nodes[i] = node.clone(),
+                Err(i) => snapshot.nodes.insert(i, node.clone()),
+            }
+        }
+        snapshot.edges.retain(|edge| !self.removed_edges.contains(edge));
+        for edge in &self.added_edges {
+            if let Err(i) = snapshot.edges.binary_search_by_key(&edge_order(edge), edge_order) {
+                snapshot.edges.insert(i, *edge);
+            }
+        }
+        snapshot.selected = self.selected;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeSnapshot {
+    pub node: NodeIndex,
+    pub id: LayerId,
+    pub kind: String,
+    pub name: String,
+    pub dirty: bool,
+    pub error: Option<String>,                  // See `LayerGraph::error`
+    pub output_dimensions: Option<(u32, u32)>, // Of a stored image at the first output port
+    pub outputs: Vec<&'static str>,            // Names of the output ports
+    pub layout: NodeLayout,
+}
+
+/// Colors that layers can be marked with in a graph editor
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorTag {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorTag {
+    pub const ALL: [ColorTag; 5] = [Self::Red, Self::Yellow, Self::Green, Self::Blue, Self::Purple];
+}
+
+/// How a layer is shown in a graph editor. None of it affects what the layer computes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeLayout {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<(f32, f32)>, // If the layer was ever placed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<(f32, f32)>, // Otherwise it is as large as its ports need
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub collapsed: bool, // Only its header is shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorTag>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub note: String,
+}
+
+impl NodeLayout {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A `LayerGraph` together with the state needed to edit it interactively. Edits made through it can be undone.
+pub struct InteractiveLayerGraph {
+    graph: LayerGraph,
+    selected_layer: Option<NodeIndex>, // None only if the graph is empty
+    history: History,
+    stroke: Option<(NodeIndex, BinaryImage)>, // Layer being painted on, and its canvas from before the stroke
+    layout: HashMap<NodeIndex, NodeLayout>,   // Only of layers that have one other than the default
+    removed_layout: HashMap<NodeIndex, NodeLayout>, // Of removed layers, in case removing them is undone
+    events: Vec<LoggedEvent>,                 // Changes that weren't taken by `record_to` yet
+}
+
+impl InteractiveLayerGraph {
+    const DEFAULT_HISTORY_DEPTH: usize = 100;
+    const DUPLICATE_OFFSET: f32 = 30.0; // Copies are placed below and to the right of the original
+
+    pub fn new() -> Self {
+        Self {
+            graph: LayerGraph::new(),
+            selected_layer: None,
+            history: History::new(Self::DEFAULT_HISTORY_DEPTH),
+            stroke: None,
+            layout: HashMap::new(),
+            removed_layout: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn graph(&self) -> &LayerGraph {
+        &self.graph
+    }
+
+    /// The layer being looked at, which the graph computes first. The first layer that is added is selected, and
+    /// removing the selected layer selects another one.
+    pub fn selected(&self) -> Option<NodeIndex> {
+        self.selected_layer
+    }
+
+    pub fn select(&mut self, layer: NodeIndex) -> Result<()> {
+        if !self.graph.contains(layer) {
+            bail!(KlexError::NoSuchLayer(layer));
+        }
+        self.set_selection(Some(layer));
+        Ok(())
+    }
+
+    /// See `LayerGraph::find_by_id`
+    pub fn find_by_id(&self, id: LayerId) -> Option<NodeIndex> {
+        self.graph.find_by_id(id)
+    }
+
+    /// See `LayerGraph::find_by_name`
+    pub fn find_by_name(&self, name: &str) -> Vec<NodeIndex> {
+        self.graph.find_by_name(name)
+    }
+
+    /// The output of the selected layer, if it was computed
+    pub fn selected_output(&self) -> Option<&dyn Any> {
+        Some(self.graph.output(self.selected_layer?, 0)? as &dyn Any)
+    }
+
+    /// The state of every layer, ordered by index
+    pub fn nodes(&self) -> impl Iterator<Item = NodeSnapshot> + '_ {
+        let graph = &self.graph;
+        let outputs = |layer: &dyn Layer| layer.outputs().iter().map(|output| output.name).collect();
+        graph.node_indices().map(move |node| NodeSnapshot {
+            node,
+            id: graph.id(node).expect("Layer exists"),
+            kind: graph.layer(node).map(|layer| layer.kind()).unwrap_or_default(),
+            name: graph.name(node).unwrap_or_default().to_string(),
+            dirty: graph.is_dirty(node),
+            error: graph.error(node).map(str::to_string),
+            output_dimensions: graph.output(node, 0).and_then(|output| entity::dimensions(output)),
+            outputs: graph.layer(node).map_or_else(Vec::new, outputs),
+            layout: self.layout.get(&node).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// All connections, see `LayerGraph::edges`
+    pub fn edges(&self) -> impl Iterator<Item = (NodeIndex, usize, NodeIndex, usize)> + '_ {
+        self.graph.edges()
+    }
+
+    pub fn snapshot(&self) -> GraphSnapshot {
+        let mut edges: Vec<_> = self.edges().collect();
+        edges.sort_by_key(edge_order);
+        GraphSnapshot {
+            nodes: self.nodes().collect(),
+            edges,
+            selected: self.selected_layer,
+        }
+    }
+
+    pub fn add_layer_with_children(
+        &mut self,
+        layer: Box<dyn Layer>,
+        parent_nodes: Vec<NodeIndex>,
+        child_nodes: Vec<NodeIndex>,
+    ) -> NodeIndex {
+        let new_layer = self.graph.add_layer_with_children(layer, parent_nodes, child_nodes);
+        self.record_added(new_layer);
+        new_layer
+    }
+
+    /// Adds an unconnected layer that takes the id it had in another graph, e.g. when replaying an `EventLog`
+    pub(crate) fn add_layer_with_id(&mut self, layer: Box<dyn Layer>, id: LayerId, name: String) -> Result<NodeIndex> {
+        ensure!(self.graph.find_by_id(id).is_none(), "There already is a layer with id {}", id);
+        let new_layer = self.graph.add_layer(layer, vec![]);
+        self.graph.set_id(new_layer, id)?;
+        self.graph.rename(new_layer, name)?;
+        self.record_added(new_layer);
+        Ok(new_layer)
+    }
+
+    /// Records a layer that was just added, along with its connections
+    fn record_added(&mut self, new_layer: NodeIndex) {
+        self.removed_layout.remove(&new_layer); // Of a removed layer that had the same index
+        if self.selected_layer.is_none() {
+            self.set_selection(Some(new_layer));
+        }
+
+        let mut edits = vec![Edit::AddLayer {
+            layer: self.id(new_layer),
+            detached: None,
+        }];
+        edits.extend(
+            self.graph
+                .edges()
+                .filter(|&(from, _, to, _)| from == new_layer || to == new_layer)
+                .map(|(from, output, to, port)| Edit::Connect {
+                    from: self.id(from),
+                    output,
+                    to: self.id(to),
+                    port,
+                }),
+        );
+        self.record(edits);
+    }
+
+    pub fn add_layer(&mut self, layer: Box<dyn Layer>, parent_nodes: Vec<NodeIndex>) -> NodeIndex {
+        self.add_layer_with_children(layer, parent_nodes, vec![])
+    }
+
+    /// Removes a layer along with all of its connections. The layer is kept around, so that this can be undone. If it
+    /// was selected, its first input is selected instead, or any other layer if it has none.
+    pub fn remove_layer(&mut self, layer: NodeIndex) -> Result<()> {
+        self.graph.name(layer).context(format!("There is no layer {}", layer.index()))?;
+        self.end_stroke(); // It may have been painted on the layer
+        let selected = self.selected_id();
+        let parent = self.graph.inputs(layer).into_iter().find(|&(_, port)| port == 0).map(|(parent, _)| parent);
+
+        let connections: Vec<_> = self
+            .graph
+            .edges()
+            .filter(|&(from, _, to, _)| from == layer || to == layer)
+            .collect();
+        let mut edits = Vec::new();
+        for (from, output, to, port) in connections {
+            let (from_id, to_id) = (self.id(from), self.id(to));
+            self.graph.disconnect(to, port)?;
+            edits.push(Edit::Disconnect { from: from_id, output, to: to_id, port });
+        }
+        let id = self.id(layer);
+        let detached = self.graph.detach_layer(layer)?;
+        edits.push(Edit::RemoveLayer {
+            layer: id,
+            detached: Some(detached),
+        });
+        if let Some(layout) = self.layout.remove(&layer) {
+            self.removed_layout.insert(layer, layout);
+        }
+
+        self.record_selecting(edits, selected); // Undoing this selects the layer again
+        if self.selected_layer == Some(layer) {
+            self.set_selection(parent);
+            self.fix_selection();
+        }
+        Ok(())
+    }
+
+    pub fn connect(&mut self, from: NodeIndex, to: NodeIndex, port: usize) -> Result<()> {
+        self.connect_output(from, 0, to, port)
+    }
+
+    pub fn connect_output(&mut self, from: NodeIndex, output: usize, to: NodeIndex, port: usize) -> Result<()> {
+        self.graph.connect_output(from, output, to, port)?;
+        let (from, to) = (self.id(from), self.id(to));
+        self.record(vec![Edit::Connect { from, output, to, port }]);
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, to: NodeIndex, port: usize) -> Result<NodeIndex> {
+        let output = self.graph.source(to, port).map_or(0, |(_, output)| output);
+        let from = self.graph.disconnect(to, port)?;
+        let edit = Edit::Disconnect { from: self.id(from), output, to: self.id(to), port };
+        self.record(vec![edit]);
+        Ok(from)
+    }
+
+    pub fn rename(&mut self, layer: NodeIndex, name: String) -> Result<()> {
+        let old = self
+            .graph
+            .name(layer)
+            .context(format!("There is no layer {}", layer.index()))?
+            .to_string();
+        self.graph.rename(layer, name.clone())?;
+        self.record(vec![Edit::Rename { layer: self.id(layer), old, new: name }]);
+        Ok(())
+    }
+
+    /// How a layer is shown in a graph editor, if it was ever changed from the default
+    pub fn layout(&self, layer: NodeIndex) -> Option<&NodeLayout> {
+        self.layout.get(&layer)
+    }
+
+    /// Changes how a layer is shown in a graph editor. Since that doesn't change any results, it isn't recorded in
+    /// the history.
+    pub fn set_layout(&mut self, layer: NodeIndex, layout: NodeLayout) -> Result<()> {
+        ensure!(self.graph.contains(layer), KlexError::NoSuchLayer(layer));
+        if layout.is_default() {
+            self.layout.remove(&layer);
+        } else {
+            self.layout.insert(layer, layout.clone());
+        }
+        self.log(GraphEvent::SetLayout { layer: self.id(layer), layout });
+        Ok(())
+    }
+
+    /// Places a layer in the graph editor, see `set_layout`
+    pub fn move_layer(&mut self, layer: NodeIndex, position: (f32, f32)) -> Result<()> {
+        let mut layout = self.layout(layer).cloned().unwrap_or_default();
+        layout.position = Some(position);
+        self.set_layout(layer, layout)
+    }
+
+    /// Adds an unconnected copy of a layer. Its layout is copied as well, but it is placed next to the original
+    /// rather than on top of it.
+    pub fn duplicate_layer(&mut self, layer: NodeIndex) -> Result<NodeIndex> {
+        let copy = self.graph.duplicate_layer(layer)?;
+        self.removed_layout.remove(&copy);
+        self.record(vec![Edit::AddLayer {
+            layer: self.id(copy),
+            detached: None,
+        }]);
+        if let Some(layout) = self.layout(layer) {
+            let offset = Self::DUPLICATE_OFFSET;
+            let position = layout.position.map(|(x, y)| (x + offset, y + offset));
+            let layout = NodeLayout { position, ..layout.clone() };
+            self.set_layout(copy, layout)?;
+        }
+        Ok(copy)
+    }
+
+    pub fn set_parameter(&mut self, layer: NodeIndex, name: &str, value: ParamValue) -> Result<()> {
+        let old = self
+            .graph
+            .parameters(layer)?
+            .remove(name)
+            .context(format!("Layer {} has no parameter {:?}", layer.index(), name))?;
+        self.graph.set_parameter(layer, name, value.clone())?;
+        self.record(vec![Edit::SetParameter {
+            layer: self.id(layer),
+            name: name.to_string(),
+            old,
+            new: value,
+        }]);
+        Ok(())
+    }
+
+    /// Paints a part of a stroke on a layer, see `LayerGraph::paint`. Everything painted until `end_stroke` is undone
+    /// as a whole.
+    pub fn paint(&mut self, layer: NodeIndex, stroke: &Stroke) -> Result<()> {
+        if self.stroke.as_ref().is_some_and(|(painted, _)| *painted != layer) {
+            self.end_stroke();
+        }
+        if self.stroke.is_none() {
+            let canvas = self.graph.canvas_mut(layer)?.clone();
+            self.stroke = Some((layer, canvas));
+        }
+        self.graph.paint(layer, stroke)?;
+        self.log(GraphEvent::Paint { layer: self.id(layer), stroke: stroke.clone() });
+        Ok(())
+    }
+
+    /// Replaces the canvas of a layer that can be painted on, like painting all of it at once
+    pub(crate) fn set_canvas(&mut self, layer: NodeIndex, mut canvas: BinaryImage) -> Result<()> {
+        self.graph.swap_canvas(layer, &mut canvas)?;
+        self.record(vec![Edit::Paint { layer: self.id(layer), canvas }]);
+        Ok(())
+    }
+
+    /// Records the stroke that is being painted, if there is one
+    pub fn end_stroke(&mut self) {
+        if let Some((layer, canvas)) = self.stroke.take() {
+            let layer = self.id(layer); // Removing it ends the stroke first
+            self.history.push(Transaction {
+                edits: vec![Edit::Paint { layer, canvas }],
+                selected_layer: self.selected_id(),
+            });
+        }
+    }
+
+    pub fn mark_dirty(&mut self, layer: NodeIndex) {
+        self.graph.mark_dirty(layer)
+    }
+
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.graph.set_memory_budget(budget)
+    }
+
+    pub fn set_skip_unchanged(&mut self, skip: bool) {
+        self.graph.set_skip_unchanged(skip)
+    }
+
+    pub fn compute_layer(&mut self, layer: NodeIndex) -> Result<()> {
+        self.graph.compute_layer(layer)
+    }
+
+    pub fn compute_layer_cancellable(&mut self, layer: NodeIndex, cancel: &CancelToken) -> Result<()> {
+        self.graph.compute_layer_cancellable(layer, cancel)
+    }
+
+    pub fn compute_all(&mut self) -> Result<()> {
+        self.graph.compute_all()
+    }
+
+    /// Collapses the given layers into a single `CompositeLayer`, see `LayerGraph::group_nodes`. Grouping can't be
+    /// undone and clears the edit history.
+    pub fn group_nodes(&mut self, nodes: &[NodeIndex]) -> Result<NodeIndex> {
+        self.group(nodes, None)
+    }
+
+    /// Like `group_nodes`, with the id the composite layer had in another graph, e.g. when replaying an `EventLog`
+    pub(crate) fn group_nodes_with_id(&mut self, nodes: &[NodeIndex], id: LayerId) -> Result<NodeIndex> {
+        self.group(nodes, Some(id))
+    }
+
+    fn group(&mut self, nodes: &[NodeIndex], id: Option<LayerId>) -> Result<NodeIndex> {
+        let layers = nodes.iter().map(|&node| self.graph.id(node)).collect::<Option<Vec<_>>>();
+        let layers = layers.context("Can't group layers that don't exist")?;
+        let composite = self.graph.group_nodes(nodes)?;
+        if let Some(id) = id {
+            self.graph.set_id(composite, id)?;
+        }
+        self.log(GraphEvent::Group { layers, composite: self.id(composite) });
+        if self.selected_layer.is_some_and(|selected| nodes.contains(&selected)) {
+            self.set_selection(Some(composite));
+        }
+        self.history.clear();
+        self.stroke = None;
+        // The composite layer takes the place of the first grouped layer that was placed
+        let position = nodes.iter().find_map(|node| self.layout.get(node)?.position);
+        for node in nodes {
+            self.layout.remove(node);
+        }
+        self.removed_layout.clear();
+        if let Some(position) = position {
+            self.move_layer(composite, position)?;
+        }
+        Ok(composite)
+    }
+
+    /// Reverts the most recent edit. Outputs of affected layers are recomputed rather than restored.
+    pub fn undo(&mut self) -> Result<()> {
+        self.end_stroke();
+        let mut transaction = self.history.pop_undo().context("There is nothing to undo")?;
+        self.replay(|graph| transaction.undo(graph))?;
+        self.restore_layout();
+        for edit in transaction.edits.iter().rev() {
+            self.log_edit(edit, true);
+        }
+        self.restore_selection(transaction.selected_layer);
+        self.history.push_undone(transaction);
+        Ok(())
+    }
+
+    /// Applies the most recently undone edit again
+    pub fn redo(&mut self) -> Result<()> {
+        let mut transaction = self.history.pop_redo().context("There is nothing to redo")?;
+        self.replay(|graph| transaction.redo(graph))?;
+        self.restore_layout();
+        for edit in &transaction.edits {
+            self.log_edit(edit, false);
+        }
+        self.restore_selection(transaction.selected_layer);
+        self.history.push_redone(transaction);
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Limits how many edits can be undone. The oldest edits are forgotten first.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth)
+    }
+
+    /// Hands over the changes made to the graph since the last call, or since it was created, to the end of `log`
+    pub fn record_to(&mut self, log: &mut EventLog) {
+        log.events.append(&mut self.events);
+    }
+
+    fn log(&mut self, event: GraphEvent) {
+        self.events.push(LoggedEvent { time: SystemTime::now(), event });
+    }
+
+    /// Logs the change an edit just made to the graph, after it was done or, if `reverted`, undone
+    fn log_edit(&mut self, edit: &Edit, reverted: bool) {
+        let event = match (edit, reverted) {
+            (Edit::AddLayer { layer, .. }, false) | (Edit::RemoveLayer { layer, .. }, true) => {
+                let node = match self.graph.find_by_id(*layer) {
+                    Some(node) => node,
+                    None => return,
+                };
+                let layer = self.graph.layer(node).expect("Layer exists");
+                let (kind, parameters) = (layer.kind(), layer.parameters());
+                let name = self.graph.name(node).expect("Layer exists").to_string();
+                let canvas = self.graph.canvas_mut(node).ok().cloned();
+                self.log(GraphEvent::AddLayer { layer: self.id(node), kind, name, parameters, canvas });
+                match self.layout(node).cloned() {
+                    Some(layout) => GraphEvent::SetLayout { layer: self.id(node), layout }, // Restored by undoing
+                    None => return,
+                }
+            }
+            (Edit::AddLayer { layer, .. }, true) | (Edit::RemoveLayer { layer, .. }, false) => {
+                GraphEvent::RemoveLayer { layer: *layer }
+            }
+            (&Edit::Connect { from, output, to, port }, false)
+            | (&Edit::Disconnect { from, output, to, port }, true) => GraphEvent::Connect { from, output, to, port },
+            (&Edit::Connect { to, port, .. }, true) | (&Edit::Disconnect { to, port, .. }, false) => {
+                GraphEvent::Disconnect { to, port }
+            }
+            (Edit::SetParameter { layer, name, old, new }, _) => GraphEvent::SetParameter {
+                layer: *layer,
+                name: name.clone(),
+                value: if reverted { old.clone() } else { new.clone() },
+            },
+            (Edit::Rename { layer, old, new }, _) => GraphEvent::Rename {
+                layer: *layer,
+                name: if reverted { old.clone() } else { new.clone() },
+            },
+            (Edit::Paint { layer, .. }, _) => {
+                let canvas = self.graph.find_by_id(*layer).and_then(|node| self.graph.canvas_mut(node).ok());
+                match canvas {
+                    Some(canvas) => GraphEvent::SetCanvas { layer: *layer, canvas: canvas.clone() },
+                    None => return,
+                }
+            }
+        };
+        self.log(event);
+    }
+
+    fn record(&mut self, edits: Vec<Edit>) {
+        let selected_layer = self.selected_id();
+        self.record_selecting(edits, selected_layer);
+    }
+
+    /// Like `record`, with the layer to select when the edits are undone, for edits that already removed it
+    fn record_selecting(&mut self, edits: Vec<Edit>, selected_layer: Option<LayerId>) {
+        self.end_stroke(); // It came first
+        for edit in &edits {
+            self.log_edit(edit, false);
+        }
+        self.history.push(Transaction { edits, selected_layer });
+    }
+
+    fn id(&self, layer: NodeIndex) -> LayerId {
+        self.graph.id(layer).expect("Layer exists")
+    }
+
+    fn selected_id(&self) -> Option<LayerId> {
+        self.graph.id(self.selected_layer?)
+    }
+
+    /// Runs an undo or redo. If it fails halfway, the history no longer matches the graph and is dropped.
+    fn replay(&mut self, f: impl FnOnce(&mut LayerGraph) -> Result<()>) -> Result<()> {
+        let result = f(&mut self.graph);
+        if result.is_err() {
+            self.history.clear();
+        }
+        result
+    }
+
+    /// Sets aside the layouts of layers that an undo or redo removed, and puts back those of layers it restored
+    fn restore_layout(&mut self) {
+        let graph = &self.graph;
+        let removed: Vec<_> = self.layout.keys().copied().filter(|&layer| !graph.contains(layer)).collect();
+        let restored: Vec<_> = self.removed_layout.keys().copied().filter(|&layer| graph.contains(layer)).collect();
+        for layer in removed {
+            let layout = self.layout.remove(&layer).expect("Layout exists");
+            self.removed_layout.insert(layer, layout);
+        }
+        for layer in restored {
+            let layout = self.removed_layout.remove(&layer).expect("Layout exists");
+            self.layout.insert(layer, layout);
+        }
+    }
+
+    /// Selects the layer that was selected when an edit was made, unless it doesn't exist after undoing or redoing it
+    fn restore_selection(&mut self, layer: Option<LayerId>) {
+        self.set_selection(layer.and_then(|layer| self.graph.find_by_id(layer)));
+        self.fix_selection();
+    }
+
+    fn set_selection(&mut self, layer: Option<NodeIndex>) {
+        self.selected_layer = layer;
+        self.graph.set_focus(layer);
+    }
+
+    /// Selects any layer in place of one that was removed, or none if the graph is empty
+    fn fix_selection(&mut self) {
+        if !self.selected_layer.is_some_and(|layer| self.graph.contains(layer)) {
+            let first = self.graph.node_indices().next();
+            self.set_selection(first);
+        }
+    }
+}
+
+impl From<LayerGraph> for InteractiveLayerGraph {
+    fn from(graph: LayerGraph) -> Self {
+        let mut layers = Self::new();
+        layers.graph = graph;
+        layers.fix_selection();
+        layers
+    }
 }
 
 impl Default for InteractiveLayerGraph {
@@ -0,0 +1,172 @@
+use iced::{button, scrollable, text_input, Button, Color, Column, Element, Length, Scrollable, Text, TextInput};
+use petgraph::graph::NodeIndex;
+
+use crate::{layer::LayerCategory, parameter::ParamMap, registry::LayerInfo, ui::Event};
+
+#[derive(Clone, Debug)]
+pub enum MenuMessage {
+    Toggle,
+    Close,
+    Search(String),
+    Choose(String),
+    ChooseFirst, // Enter was pressed in the search field
+}
+
+struct Entry {
+    kind: String,
+    info: LayerInfo,
+    button: button::State,
+}
+
+/// Why a layer of the given kind can't be added after a layer producing `after`, if it can't
+pub fn mismatch(info: &LayerInfo, after: Option<&str>) -> Option<String> {
+    if info.category == LayerCategory::Input {
+        return None; // Not connected to anything
+    }
+    let (expected, produced) = (*info.input_types.first()?, after?);
+    (expected != produced).then(|| format!("Expects {}, but the selected layer produces {}", expected, produced))
+}
+
+/// A searchable list of the kinds of layers that can be added, grouped by category. The chosen layer is connected to
+/// the output of the selected layer.
+pub struct LayerMenu {
+    open: bool,
+    search: String,
+    entries: Vec<Entry>, // Ordered by category and kind
+    toggle: button::State,
+    search_input: text_input::State,
+    scroll: scrollable::State,
+}
+
+impl LayerMenu {
+    pub fn new(catalog: impl IntoIterator<Item = (String, LayerInfo)>) -> Self {
+        let mut entries: Vec<_> = catalog
+            .into_iter()
+            .map(|(kind, info)| Entry {
+                kind,
+                info,
+                button: button::State::new(),
+            })
+            .collect();
+        entries.sort_by(|a, b| (a.info.category, &a.kind).cmp(&(b.info.category, &b.kind)));
+        Self {
+            open: false,
+            search: String::new(),
+            entries,
+            toggle: button::State::new(),
+            search_input: text_input::State::new(),
+            scroll: scrollable::State::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// What layers of a kind produce, if the menu knows
+    fn output_type(&self, kind: &str) -> Option<&'static str> {
+        let entry = self.entries.iter().find(|entry| entry.kind == kind)?;
+        entry.info.output_type
+    }
+
+    /// Entries matching the search, e.g. "thresh" or "convert"
+    fn matches<'a>(search: &'a str) -> impl Fn(&Entry) -> bool + 'a {
+        move |entry| {
+            let search = search.trim().to_lowercase();
+            let category = format!("{:?}", entry.info.category).to_lowercase();
+            entry.kind.to_lowercase().contains(&search) || category.contains(&search)
+        }
+    }
+
+    /// Handles a message of the menu. `selected` is the selected layer along with its kind. Returns the event to send
+    /// to the backend, if a layer was chosen.
+    pub fn update(&mut self, message: MenuMessage, selected: Option<(NodeIndex, &str)>) -> Option<Event> {
+        let kind = match message {
+            MenuMessage::Toggle => {
+                self.open = !self.open;
+                self.search.clear();
+                self.search_input = if self.open {
+                    text_input::State::focused()
+                } else {
+                    text_input::State::new()
+                };
+                return None;
+            }
+            MenuMessage::Close => {
+                self.open = false;
+                return None;
+            }
+            MenuMessage::Search(search) => {
+                self.search = search;
+                return None;
+            }
+            MenuMessage::Choose(kind) => kind,
+            MenuMessage::ChooseFirst => {
+                let after = selected.and_then(|(_, kind)| self.output_type(kind));
+                let first = self
+                    .entries
+                    .iter()
+                    .filter(|entry| Self::matches(&self.search)(entry))
+                    .find(|entry| mismatch(&entry.info, after).is_none())?;
+                first.kind.clone()
+            }
+        };
+        let after = selected.and_then(|(_, kind)| self.output_type(kind));
+        let entry = self.entries.iter().find(|entry| entry.kind == kind)?;
+        if mismatch(&entry.info, after).is_some() {
+            return None;
+        }
+        self.open = false;
+        Some(Event::InsertLayer {
+            kind,
+            parameters: ParamMap::new(),
+            connect_after: selected.map(|(node, _)| node),
+        })
+    }
+
+    /// The button opening the menu, followed by the menu itself if it is open. `selected` is the kind of the
+    /// selected layer.
+    pub fn view(&mut self, selected: Option<&str>) -> Element<'_, MenuMessage> {
+        let label = if self.open { "Cancel" } else { "Add layer" };
+        let toggle = Button::new(&mut self.toggle, Text::new(label).size(16))
+            .width(Length::Fill)
+            .on_press(MenuMessage::Toggle);
+        let column = Column::new().spacing(4).push(toggle);
+        if !self.open {
+            return column.into();
+        }
+
+        let search = TextInput::new(&mut self.search_input, "Search", &self.search, MenuMessage::Search)
+            .padding(4)
+            .size(14)
+            .on_submit(MenuMessage::ChooseFirst);
+
+        let after = selected.and_then(|kind| {
+            let entry = self.entries.iter().find(|entry| entry.kind == kind)?;
+            entry.info.output_type
+        });
+        let matches = Self::matches(&self.search);
+        let mut list = Scrollable::new(&mut self.scroll).spacing(4).max_height(400);
+        let mut category = None;
+        for entry in self.entries.iter_mut().filter(|entry| matches(entry)) {
+            if category != Some(entry.info.category) {
+                category = Some(entry.info.category);
+                list = list.push(Text::new(format!("{:?}", entry.info.category)).size(14));
+            }
+            let note = mismatch(&entry.info, after);
+            let label = Text::new(entry.kind.as_str()).size(14);
+            let mut button = Button::new(&mut entry.button, label).width(Length::Fill);
+            if note.is_none() {
+                button = button.on_press(MenuMessage::Choose(entry.kind.clone()));
+            }
+            list = list.push(button);
+            if let Some(note) = note {
+                list = list.push(Text::new(note).size(12).color(Color::from_rgb(0.5, 0.5, 0.5)));
+            }
+        }
+        if category.is_none() {
+            list = list.push(Text::new("No matching layers").size(14));
+        }
+        column.push(search).push(list).into()
+    }
+}
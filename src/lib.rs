@@ -1,4 +1,43 @@
+#[cfg(feature = "ui")]
+pub mod backend;
+pub mod cache;
+pub mod color;
+pub mod composite;
+pub mod draw;
 pub mod entity;
+pub mod error;
+pub mod event_log;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fft")]
+pub mod fft;
+#[cfg(feature = "ui")]
+pub mod graph_editor;
+#[cfg(feature = "ui")]
+pub mod histogram;
+mod history;
 pub mod layer;
 pub mod layer_graph;
+#[cfg(feature = "ui")]
+pub mod layer_menu;
+pub mod logging;
+pub mod parameter;
+#[cfg(feature = "ui")]
+pub mod parameter_panel;
 pub mod recipe;
+pub mod registry;
+pub mod roi;
+pub mod serialize;
+#[cfg(feature = "ui")]
+pub mod session;
+#[cfg(feature = "ui")]
+pub mod shortcuts;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod testing;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod util;
+#[cfg(feature = "ui")]
+pub mod viewport;
+pub mod watch;
@@ -0,0 +1,103 @@
+use std::{collections::VecDeque, fmt, time::SystemTime};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something that happened, e.g. in the backend, that might be interesting to look at
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord {
+    pub time: SystemTime,
+    pub level: Level,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self.time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        write!(f, "{:.3} {} {}", since_epoch.as_secs_f64(), self.level, self.message)
+    }
+}
+
+/// The most recent log records. Older records are dropped once the capacity is reached.
+#[derive(Clone, Debug)]
+pub struct LogBuffer {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+    unsent: usize, // Number of most recent records that weren't taken by `take_unsent` yet
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+            unsent: 0,
+        }
+    }
+
+    /// Records a message and also emits it as a `tracing` event if the `tracing` feature is enabled
+    pub fn log(&mut self, level: Level, message: String) {
+        emit(level, &message);
+        self.push(LogRecord {
+            time: SystemTime::now(),
+            level,
+            message,
+        });
+    }
+
+    pub fn push(&mut self, record: LogRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+        self.unsent = (self.unsent + 1).min(self.records.len());
+    }
+
+    /// All records, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &LogRecord> {
+        self.records.iter()
+    }
+
+    /// Records that were added since the last call. Records that were dropped in the meantime are missing.
+    pub fn take_unsent(&mut self) -> Vec<LogRecord> {
+        let unsent = self.records.iter().skip(self.records.len() - self.unsent).cloned().collect();
+        self.unsent = 0;
+        unsent
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn emit(level: Level, message: &str) {
+    match level {
+        Level::Trace => tracing::trace!("{}", message),
+        Level::Debug => tracing::debug!("{}", message),
+        Level::Info => tracing::info!("{}", message),
+        Level::Warn => tracing::warn!("{}", message),
+        Level::Error => tracing::error!("{}", message),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+fn emit(_level: Level, _message: &str) {}
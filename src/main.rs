@@ -1,31 +1,61 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
-use image::{self, GrayImage, RgbaImage};
+use iced::Application;
+use petgraph::graph::NodeIndex;
 
-use klex::{entity::{BinaryImage}, layer::{primitive::{Convert, InputFile, Threshold}}};
-use klex::layer_graph::InteractiveLayerGraph;
+use klex::{
+    backend::Supervisor,
+    parameter::{ParamMap, ParamValue},
+    registry::LayerRegistry,
+    session::Session,
+    ui::{Event, Settings, UI},
+};
 
 fn main() -> Result<()> {
-    let mut layers = InteractiveLayerGraph::new();
-    let layer = Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into()));
-    layers.add_layer(layer, vec![]);
-    let layer = Box::new(Convert::<RgbaImage, GrayImage>::new());
-    layers.add_layer(layer, vec![0.into()]);
-    let layer = Box::new(Threshold::<GrayImage, BinaryImage, u8>::new(100, std::cmp::Ordering::Greater));
-    layers.add_layer(layer, vec![1.into()]);
-    let layer = Box::new(Convert::<BinaryImage, GrayImage>::new());
-    layers.add_layer(layer, vec![2.into()]);
-    layers.compute_layer(0.into())?;
-    layers.compute_layer(1.into())?;
-    layers.compute_layer(2.into())?;
-    layers.compute_layer(3.into())?;
-
-    println!("The final layer is some: {}", layers.layer_output[3].is_some());
+    let backend = Supervisor::spawn(LayerRegistry::with_builtins);
+    let session_file = Session::default_path();
+    let session = session_file.as_deref().map(Session::load).unwrap_or_default();
 
-    if let Some(output) = &layers.layer_output[3] {
-        let output = output.downcast_ref::<GrayImage>();
-        if let Some(image) = output {
-            image.save("GrayTulips.jpg")?;
+    // An image given on the command line starts out in a thresholding pipeline. Otherwise, the graph of the previous
+    // session is restored, unless the application is started with --fresh.
+    let mut fresh = false;
+    let mut image = None;
+    for argument in std::env::args_os().skip(1) {
+        match argument.to_str() {
+            Some("--fresh") => fresh = true,
+            _ => image = Some(PathBuf::from(argument)),
         }
     }
+    let restore_recipe = !fresh && image.is_none();
+    if let Some(path) = image {
+        let path = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+        let threshold = ParamMap::from([("threshold".to_string(), ParamValue::Int(100))]);
+        let pipeline = [
+            ("InputFile", path),
+            ("Convert<RgbaImage, GrayImage>", ParamMap::new()),
+            ("Threshold", threshold),
+            ("Convert<BinaryImage, GrayImage>", ParamMap::new()),
+        ];
+        for (i, (kind, parameters)) in pipeline.into_iter().enumerate() {
+            let inputs = if i == 0 { vec![] } else { vec![NodeIndex::new(i - 1)] };
+            backend.channel().send(Event::AddLayer {
+                kind: kind.to_string(),
+                parameters,
+                inputs,
+            })?;
+        }
+        backend.channel().send(Event::SelectLayer(NodeIndex::new(3)))?;
+    }
+
+    let settings = Settings {
+        session_file,
+        restore_recipe,
+        ..Settings::default()
+    };
+    UI::run(iced::Settings {
+        exit_on_close_request: false, // The session is saved first
+        ..iced::Settings::with_flags((backend, settings, session))
+    })?;
     Ok(())
 }
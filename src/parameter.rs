@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{bail, KlexError, Result};
+
+/// Value of a layer parameter, independent of the concrete type the layer stores it as
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Path(PathBuf),
+    Choice(String), // One of a fixed set of options, e.g. the variants of an enum
+    Color([u8; 4]), // RGBA
+}
+
+impl ParamValue {
+    /// The value written out as text, e.g. for inserting it into a path
+    pub fn to_text(&self) -> String {
+        match self {
+            ParamValue::Bool(value) => value.to_string(),
+            ParamValue::Int(value) => value.to_string(),
+            ParamValue::Float(value) => value.to_string(),
+            ParamValue::Text(text) | ParamValue::Choice(text) => text.clone(),
+            ParamValue::Path(path) => path.display().to_string(),
+            ParamValue::Color([red, green, blue, alpha]) => {
+                format!("#{:02x}{:02x}{:02x}{:02x}", red, green, blue, alpha)
+            }
+        }
+    }
+}
+
+pub type ParamMap = BTreeMap<String, ParamValue>;
+
+/// Conversion between the types layers store their parameters as and `ParamValue`
+pub trait Parameter: Sized {
+    fn to_value(&self) -> ParamValue;
+    fn from_value(value: &ParamValue) -> Result<Self>;
+}
+
+impl Parameter for bool {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Bool(*self)
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Bool(value) => Ok(*value),
+            _ => bail!(KlexError::invalid_value("a boolean", value)),
+        }
+    }
+}
+
+impl Parameter for u8 {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Int(i64::from(*self))
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Int(value) => match u8::try_from(*value) {
+                Ok(value) => Ok(value),
+                Err(_) => bail!(KlexError::OutOfRange { value: *value as f64, min: 0.0, max: u8::MAX.into() }),
+            },
+            _ => bail!(KlexError::invalid_value("an integer", value)),
+        }
+    }
+}
+
+impl Parameter for u16 {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Int(i64::from(*self))
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Int(value) => match u16::try_from(*value) {
+                Ok(value) => Ok(value),
+                Err(_) => bail!(KlexError::OutOfRange { value: *value as f64, min: 0.0, max: u16::MAX.into() }),
+            },
+            _ => bail!(KlexError::invalid_value("an integer", value)),
+        }
+    }
+}
+
+impl Parameter for u32 {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Int(i64::from(*self))
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Int(value) => match u32::try_from(*value) {
+                Ok(value) => Ok(value),
+                Err(_) => bail!(KlexError::OutOfRange { value: *value as f64, min: 0.0, max: u32::MAX.into() }),
+            },
+            _ => bail!(KlexError::invalid_value("an integer", value)),
+        }
+    }
+}
+
+impl Parameter for f64 {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Float(*self)
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Float(value) => Ok(*value),
+            ParamValue::Int(value) => Ok(*value as f64),
+            _ => bail!(KlexError::invalid_value("a number", value)),
+        }
+    }
+}
+
+impl Parameter for f32 {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Float((*self).into())
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        let value = f64::from_value(value)?;
+        match value as f32 {
+            converted if converted.is_finite() || !value.is_finite() => Ok(converted),
+            _ => bail!(KlexError::OutOfRange { value, min: f32::MIN.into(), max: f32::MAX.into() }),
+        }
+    }
+}
+
+impl Parameter for String {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Text(self.clone())
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Text(text) => Ok(text.clone()),
+            _ => bail!(KlexError::invalid_value("text", value)),
+        }
+    }
+}
+
+impl Parameter for PathBuf {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Path(self.clone())
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Path(path) => Ok(path.clone()),
+            ParamValue::Text(path) => Ok(path.into()),
+            _ => bail!(KlexError::invalid_value("a path", value)),
+        }
+    }
+}
+
+impl Parameter for image::Rgba<u8> {
+    fn to_value(&self) -> ParamValue {
+        ParamValue::Color(self.0)
+    }
+
+    fn from_value(value: &ParamValue) -> Result<Self> {
+        match value {
+            ParamValue::Color(color) => Ok(image::Rgba(*color)),
+            ParamValue::Text(text) => Ok(image::Rgba(parse_color(text)?)),
+            _ => bail!(KlexError::invalid_value("a color", value)),
+        }
+    }
+}
+
+/// Reads a color written as `#rrggbb` or `#rrggbbaa`
+fn parse_color(text: &str) -> Result<[u8; 4]> {
+    let digits = text.trim().trim_start_matches('#');
+    if !matches!(digits.len(), 6 | 8) || !digits.chars().all(|digit| digit.is_ascii_hexdigit()) {
+        bail!(KlexError::invalid_value("a color like #rrggbb or #rrggbbaa", text));
+    }
+    let channel = |i: usize| digits.get(2 * i..2 * i + 2).map_or(Ok(u8::MAX), |hex| u8::from_str_radix(hex, 16));
+    Ok([channel(0)?, channel(1)?, channel(2)?, channel(3)?])
+}
+
+/// The values a parameter accepts
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParamKind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Float { min: f64, max: f64 },
+    Text,
+    Path,
+    Choice(Vec<String>),
+    Color,
+}
+
+impl ParamKind {
+    pub fn validate(&self, value: &ParamValue) -> Result<()> {
+        match (self, value) {
+            (ParamKind::Bool, ParamValue::Bool(_))
+            | (ParamKind::Text, ParamValue::Text(_))
+            | (ParamKind::Color, ParamValue::Color(_)) => Ok(()),
+            (ParamKind::Int { min, max }, ParamValue::Int(value)) => {
+                if value < min || value > max {
+                    bail!(KlexError::OutOfRange { value: *value as f64, min: *min as f64, max: *max as f64 });
+                }
+                Ok(())
+            }
+            (ParamKind::Float { min, max }, ParamValue::Float(_) | ParamValue::Int(_)) => {
+                let value = f64::from_value(value)?;
+                if value < *min || value > *max {
+                    bail!(KlexError::OutOfRange { value, min: *min, max: *max });
+                }
+                Ok(())
+            }
+            (ParamKind::Path, ParamValue::Path(path)) => {
+                if path.as_os_str().is_empty() {
+                    bail!(KlexError::EmptyPath);
+                }
+                Ok(())
+            }
+            (ParamKind::Path, ParamValue::Text(path)) => {
+                if path.is_empty() {
+                    bail!(KlexError::EmptyPath);
+                }
+                Ok(())
+            }
+            (ParamKind::Choice(options), ParamValue::Choice(choice) | ParamValue::Text(choice)) => {
+                if !options.contains(choice) {
+                    bail!("Unknown option {:?}. Expected one of {}", choice, options.join(", "));
+                }
+                Ok(())
+            }
+            (kind, value) => bail!(KlexError::invalid_value(format!("a value of kind {:?}", kind), value)),
+        }
+    }
+
+    /// Reads a value of this kind from text that was typed in. Numbers outside of the range are clamped, which is
+    /// reported by returning true along with the value.
+    pub fn parse(&self, text: &str) -> Result<(ParamValue, bool)> {
+        let text = text.trim();
+        let value = match self {
+            ParamKind::Bool => match text {
+                "true" => ParamValue::Bool(true),
+                "false" => ParamValue::Bool(false),
+                _ => bail!(KlexError::invalid_value("true or false", text)),
+            },
+            ParamKind::Int { min, max } => {
+                let value: i64 = text.parse().map_err(|_| KlexError::invalid_value("a whole number", text))?;
+                return Ok((ParamValue::Int(value.clamp(*min, *max)), value < *min || value > *max));
+            }
+            ParamKind::Float { min, max } => {
+                let value: f64 = text.parse().map_err(|_| KlexError::invalid_value("a number", text))?;
+                if !value.is_finite() {
+                    bail!(KlexError::invalid_value("a finite number", text));
+                }
+                return Ok((ParamValue::Float(value.clamp(*min, *max)), value < *min || value > *max));
+            }
+            ParamKind::Text => ParamValue::Text(text.to_string()),
+            ParamKind::Path => ParamValue::Path(text.into()),
+            ParamKind::Choice(_) => ParamValue::Choice(text.to_string()),
+            ParamKind::Color => ParamValue::Color(parse_color(text)?),
+        };
+        self.validate(&value)?;
+        Ok((value, false))
+    }
+}
+
+/// Description of a layer parameter, e.g. for building a user interface or checking a recipe before running it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamSpec {
+    pub name: String,
+    pub kind: ParamKind,
+    pub default: Option<ParamValue>, // Parameters without a default have to be provided
+}
+
+impl ParamSpec {
+    pub fn new(name: &str, kind: ParamKind, default: Option<ParamValue>) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            default,
+        }
+    }
+
+    pub fn validate(&self, value: &ParamValue) -> Result<()> {
+        self.kind.validate(value)
+    }
+}
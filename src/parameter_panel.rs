@@ -0,0 +1,385 @@
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use iced::{
+    button, container, pick_list, scrollable, slider, text_input, Background, Button, Checkbox, Color, Column,
+    Container, Element, Length, PickList, Row, Scrollable, Slider, Text, TextInput,
+};
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    parameter::{ParamKind, ParamSpec, ParamValue},
+    ui::{Event, LayerSummary},
+};
+
+#[derive(Clone, Debug)]
+pub enum PanelMessage {
+    Set { name: String, value: ParamValue }, // From controls that always produce valid values, e.g. sliders
+    Edit { name: String, text: String },     // Text was typed into the input of a parameter
+    Submit { name: String },                 // Enter was pressed in the input of a parameter
+    Browse { name: String },                 // Opens or closes the file list of a path parameter
+    Pick { name: String, path: PathBuf },    // An entry of the file list was chosen
+    Eyedropper { name: String },             // A color is to be picked from the image, which the panel leaves alone
+}
+
+/// Files and directories to pick a path parameter from, since there are no native file dialogs
+struct Browser {
+    entries: Vec<(PathBuf, String, button::State)>, // Path, label and button of each entry
+}
+
+impl Browser {
+    /// Lists the subdirectories and images in `directory`, along with its parent
+    fn open(directory: &Path) -> Self {
+        let mut listed: Vec<(PathBuf, bool)> = fs::read_dir(directory)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .map(|path| {
+                        let is_directory = path.is_dir();
+                        (path, is_directory)
+                    })
+                    .filter(|(path, is_directory)| *is_directory || image::ImageFormat::from_path(path).is_ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        listed.sort_by(|(a, a_is_directory), (b, b_is_directory)| b_is_directory.cmp(a_is_directory).then(a.cmp(b)));
+
+        let mut entries = Vec::new();
+        if let Some(parent) = directory.parent() {
+            entries.push((parent.to_path_buf(), "..".to_string(), button::State::new()));
+        }
+        for (path, is_directory) in listed {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let label = if is_directory { format!("{}/", name) } else { name };
+            entries.push((path, label, button::State::new()));
+        }
+        Self { entries }
+    }
+}
+
+/// The editing state of a single parameter
+struct Control {
+    spec: ParamSpec,
+    value: Option<ParamValue>, // As last reported by the backend or set through the panel
+    text: String,              // Contents of the text input
+    hint: Option<String>,      // Why the text can't be used as it is
+    slider: slider::State,
+    input: text_input::State,
+    choice: pick_list::State<String>,
+    browse: button::State,
+    browser: Option<Browser>,
+    eyedropper: button::State,
+}
+
+impl Control {
+    fn new(spec: ParamSpec, value: Option<ParamValue>) -> Self {
+        Self {
+            text: value.as_ref().map(ParamValue::to_text).unwrap_or_default(),
+            spec,
+            value,
+            hint: None,
+            slider: slider::State::new(),
+            input: text_input::State::new(),
+            choice: pick_list::State::default(),
+            browse: button::State::new(),
+            browser: None,
+            eyedropper: button::State::new(),
+        }
+    }
+
+    fn set(&mut self, value: ParamValue) {
+        self.text = value.to_text();
+        self.hint = None;
+        self.value = Some(value);
+    }
+
+    fn view(&mut self) -> Element<'_, PanelMessage> {
+        let name = self.spec.name.clone();
+        let mut column = Column::new().spacing(4).push(Text::new(&name).size(16));
+
+        let on_edit = {
+            let name = name.clone();
+            move |text| PanelMessage::Edit {
+                name: name.clone(),
+                text,
+            }
+        };
+        let input = TextInput::new(&mut self.input, "", &self.text, on_edit)
+            .padding(4)
+            .size(14)
+            .on_submit(PanelMessage::Submit { name: name.clone() });
+
+        match &self.spec.kind {
+            ParamKind::Bool => {
+                let checked = matches!(self.value, Some(ParamValue::Bool(true)));
+                let on_toggle = move |checked| PanelMessage::Set {
+                    name: name.clone(),
+                    value: ParamValue::Bool(checked),
+                };
+                column = column.push(Checkbox::new(checked, "", on_toggle));
+            }
+            ParamKind::Int { .. } | ParamKind::Float { .. } => {
+                if let Some(range) = slider_range(&self.spec.kind) {
+                    let is_int = matches!(self.spec.kind, ParamKind::Int { .. });
+                    let step = if is_int { 1.0 } else { (range.end() - range.start()) / 100.0 };
+                    let current = match self.value {
+                        Some(ParamValue::Int(value)) => value as f64,
+                        Some(ParamValue::Float(value)) => value,
+                        _ => *range.start(),
+                    };
+                    let name = name.clone();
+                    let on_slide = move |value: f64| PanelMessage::Set {
+                        name: name.clone(),
+                        value: if is_int {
+                            ParamValue::Int(value.round() as i64)
+                        } else {
+                            ParamValue::Float(value)
+                        },
+                    };
+                    column = column.push(Slider::new(&mut self.slider, range, current, on_slide).step(step));
+                }
+                column = column.push(input);
+            }
+            ParamKind::Choice(options) => {
+                let selected = match &self.value {
+                    Some(ParamValue::Choice(choice)) => Some(choice.clone()),
+                    _ => None,
+                };
+                let on_select = move |choice| PanelMessage::Set {
+                    name: name.clone(),
+                    value: ParamValue::Choice(choice),
+                };
+                column = column.push(PickList::new(&mut self.choice, options.clone(), selected, on_select));
+            }
+            ParamKind::Color => {
+                let color = match self.value {
+                    Some(ParamValue::Color([red, green, blue, alpha])) => {
+                        Color::from_rgba8(red, green, blue, f32::from(alpha) / 255.0)
+                    }
+                    _ => Color::TRANSPARENT,
+                };
+                let swatch = Container::new(Text::new(""))
+                    .width(Length::Units(24))
+                    .height(Length::Units(24))
+                    .style(Swatch(color));
+                let eyedropper = Button::new(&mut self.eyedropper, Text::new("Pick").size(14))
+                    .on_press(PanelMessage::Eyedropper { name: name.clone() });
+                column = column.push(Row::new().spacing(4).push(swatch).push(input).push(eyedropper));
+            }
+            ParamKind::Path => {
+                let browse = Button::new(&mut self.browse, Text::new("Browse").size(14))
+                    .on_press(PanelMessage::Browse { name: name.clone() });
+                column = column.push(Row::new().spacing(4).push(input).push(browse));
+                if let Some(browser) = &mut self.browser {
+                    for (path, label, state) in &mut browser.entries {
+                        let pick = PanelMessage::Pick {
+                            name: name.clone(),
+                            path: path.clone(),
+                        };
+                        let entry = Button::new(state, Text::new(label.as_str()).size(14))
+                            .width(Length::Fill)
+                            .on_press(pick);
+                        column = column.push(entry);
+                    }
+                }
+            }
+            ParamKind::Text => column = column.push(input),
+        }
+
+        if let Some(hint) = &self.hint {
+            column = column.push(Text::new(hint).size(12).color(Color::from_rgb(0.8, 0.2, 0.2)));
+        }
+        column.into()
+    }
+}
+
+/// The range a slider can cover for a parameter, if the parameter is numeric and its range is reasonably small
+fn slider_range(kind: &ParamKind) -> Option<RangeInclusive<f64>> {
+    const MAX_STEPS: i64 = 10_000;
+    match *kind {
+        ParamKind::Int { min, max } if max.checked_sub(min).is_some_and(|steps| steps <= MAX_STEPS) => {
+            Some(min as f64..=max as f64)
+        }
+        ParamKind::Float { min, max } if (max - min).is_finite() => Some(min..=max),
+        _ => None,
+    }
+}
+
+/// What a parameter accepts if the registry doesn't say, judging by its current value
+fn guess_kind(value: &ParamValue) -> ParamKind {
+    match value {
+        ParamValue::Bool(_) => ParamKind::Bool,
+        ParamValue::Int(_) => ParamKind::Int {
+            min: i64::MIN,
+            max: i64::MAX,
+        },
+        ParamValue::Float(_) => ParamKind::Float {
+            min: f64::MIN,
+            max: f64::MAX,
+        },
+        ParamValue::Text(_) => ParamKind::Text,
+        ParamValue::Path(_) => ParamKind::Path,
+        ParamValue::Choice(choice) => ParamKind::Choice(vec![choice.clone()]),
+        ParamValue::Color(_) => ParamKind::Color,
+    }
+}
+
+struct Swatch(Color);
+
+impl container::StyleSheet for Swatch {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(self.0)),
+            border_width: 1.0,
+            border_color: Color::from_rgb(0.5, 0.5, 0.5),
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Controls for the parameters of the selected layer. They are built from the parameter specs the backend reports
+/// and start over whenever another layer is selected.
+#[derive(Default)]
+pub struct ParameterPanel {
+    node: Option<NodeIndex>,
+    kind: String,
+    name: String,
+    controls: Vec<Control>,
+    scroll: scrollable::State,
+}
+
+impl ParameterPanel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows the selection and the parameter values the backend reported. Inputs that are being typed into keep
+    /// their text.
+    pub fn sync(&mut self, node: Option<NodeIndex>, layer: Option<&LayerSummary>) {
+        let kind = layer.map(|layer| layer.kind.clone()).unwrap_or_default();
+        if node != self.node || kind != self.kind {
+            *self = Self {
+                node,
+                kind,
+                controls: layer.map(Self::controls).unwrap_or_default(),
+                ..Self::default()
+            };
+        }
+        let layer = match layer {
+            Some(layer) => layer,
+            None => return,
+        };
+        self.name = layer.name.clone();
+        for control in &mut self.controls {
+            let value = layer.parameters.get(&control.spec.name);
+            if value.is_some() && value != control.value.as_ref() {
+                control.value = value.cloned();
+                if !control.input.is_focused() {
+                    control.text = value.map(ParamValue::to_text).unwrap_or_default();
+                    control.hint = None;
+                }
+            }
+        }
+    }
+
+    fn controls(layer: &LayerSummary) -> Vec<Control> {
+        if layer.specs.is_empty() {
+            return layer
+                .parameters
+                .iter()
+                .map(|(name, value)| Control::new(ParamSpec::new(name, guess_kind(value), None), Some(value.clone())))
+                .collect();
+        }
+        layer
+            .specs
+            .iter()
+            .map(|spec| {
+                let value = layer.parameters.get(&spec.name).or(spec.default.as_ref()).cloned();
+                Control::new(spec.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Handles a message of the panel. Returns the event to send to the backend, if a parameter was changed.
+    pub fn update(&mut self, message: PanelMessage) -> Option<Event> {
+        let node = self.node?;
+        let name = match &message {
+            PanelMessage::Set { name, .. }
+            | PanelMessage::Edit { name, .. }
+            | PanelMessage::Submit { name }
+            | PanelMessage::Browse { name }
+            | PanelMessage::Pick { name, .. }
+            | PanelMessage::Eyedropper { name } => name.clone(),
+        };
+        let control = self.controls.iter_mut().find(|control| control.spec.name == name)?;
+
+        let value = match message {
+            PanelMessage::Set { value, .. } => value,
+            PanelMessage::Edit { text, .. } => {
+                control.hint = match control.spec.kind.parse(&text) {
+                    Ok((_, false)) => None,
+                    Ok((value, true)) => Some(format!("Will be clamped to {}", value.to_text())),
+                    Err(e) => Some(e.to_string()),
+                };
+                control.text = text;
+                return None;
+            }
+            PanelMessage::Submit { .. } => match control.spec.kind.parse(&control.text) {
+                Ok((value, clamped)) => {
+                    control.set(value.clone());
+                    if clamped {
+                        control.hint = Some(format!("Clamped to {}", value.to_text()));
+                    }
+                    return Some(Event::SetParameter { node, name, value });
+                }
+                Err(e) => {
+                    control.hint = Some(e.to_string());
+                    return None;
+                }
+            },
+            PanelMessage::Browse { .. } => {
+                control.browser = match control.browser {
+                    Some(_) => None,
+                    None => {
+                        let path = PathBuf::from(&control.text);
+                        let directory = match path.parent() {
+                            Some(parent) if parent.is_dir() => parent.to_path_buf(),
+                            _ => PathBuf::from("."),
+                        };
+                        Some(Browser::open(&directory))
+                    }
+                };
+                return None;
+            }
+            PanelMessage::Pick { path, .. } if path.is_dir() => {
+                control.browser = Some(Browser::open(&path));
+                return None;
+            }
+            PanelMessage::Pick { path, .. } => {
+                control.browser = None;
+                ParamValue::Path(path)
+            }
+            PanelMessage::Eyedropper { .. } => return None,
+        };
+        control.set(value.clone());
+        Some(Event::SetParameter { node, name, value })
+    }
+
+    pub fn view(&mut self) -> Element<'_, PanelMessage> {
+        let mut content = Scrollable::new(&mut self.scroll).spacing(12).padding(8);
+        if self.node.is_none() {
+            return content.push(Text::new("No layer selected").size(16)).into();
+        }
+        content = content.push(Text::new(format!("{} ({})", self.name, self.kind)).size(18));
+        if self.controls.is_empty() {
+            content = content.push(Text::new("This layer has no parameters").size(14));
+        }
+        for control in &mut self.controls {
+            content = content.push(control.view());
+        }
+        content.into()
+    }
+}
@@ -1,3 +1,974 @@
+use std::{
+    any::Any,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicUsize},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use petgraph::{algo, graph::NodeIndex, Graph};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity,
+    error::{bail, Context, KlexError, Result},
+    layer::InputCount,
+    layer_graph::{InteractiveLayerGraph, LayerGraph, LayerId, NodeLayout},
+    parameter::{ParamKind, ParamMap, ParamValue},
+    registry::LayerRegistry,
+    util,
+    watch::{FileWatcher, PollingWatcher},
+};
+
+/// Values for the placeholders of a recipe, keyed by placeholder name
+pub type Bindings = HashMap<String, ParamValue>;
+
+/// Description of a pipeline that can be stored in a file and turned back into a graph of layers
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub version: u32,
+    #[serde(default)]
+    pub placeholders: BTreeMap<String, Placeholder>,
+    pub nodes: Vec<RecipeNode>,
+    pub edges: Vec<RecipeEdge>,
+    /// How nodes are shown in the graph editor, keyed by their position in `nodes`. Running the recipe doesn't need
+    /// any of it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layout: BTreeMap<usize, NodeLayout>,
+}
+
+/// Declaration of a value that is provided when the recipe is built. Parameters refer to it as `"${name}"`, either
+/// as the whole value or as part of a text or path, like `"out/${name}_mask.png"`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub kind: ParamKind,
+    #[serde(default)]
+    pub default: Option<ParamValue>, // Placeholders without a default have to be bound
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecipeNode {
+    pub kind: String,
+    pub name: String,
+    pub parameters: ParamMap,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<LayerId>, // Of the layer, which keeps it when the recipe is built. A new one is handed out if None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<Include>, // Only for nodes of kind `INCLUDE`
+    #[serde(default, skip_serializing)]
+    pub position: Option<(f32, f32)>, // Only read from older recipes, `Recipe::layout` has taken its place
+}
+
+/// Kind of the nodes that stand for another recipe
+pub const INCLUDE: &str = "include";
+
+/// Another recipe that is inlined in place of a node when the recipe is built. The nodes of the included recipe are
+/// named `"<include node>/<node>"`, and the parameters of the include node bind the placeholders of the included
+/// recipe.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Include {
+    pub recipe: IncludedRecipe,
+    pub inputs: Vec<(String, usize)>, // Node and input port of the included recipe fed by each input of this node
+    pub output: String,               // Node of the included recipe whose output is the output of the include node
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IncludedRecipe {
+    Path(PathBuf), // Relative paths are relative to the including recipe file
+    Embedded(Box<Recipe>),
+}
+
+/// Connection from an output of node `from` to input `port` of node `to`. Nodes are referred to by their position
+/// in `Recipe::nodes`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecipeEdge {
+    pub from: usize,
+    pub to: usize,
+    pub port: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>, // Name of the output port of `from`, only for nodes with several. The first if None.
+}
+
+impl Recipe {
+    pub const VERSION: u32 = 1; // Increased whenever the format changes in a way older versions can't read
+
+    pub fn from_graph(graph: &InteractiveLayerGraph) -> Self {
+        let mut recipe = Self::from_layer_graph(graph.graph());
+        let layers = graph.graph().node_indices().enumerate();
+        recipe.layout = layers.filter_map(|(i, layer)| Some((i, graph.layout(layer)?.clone()))).collect();
+        recipe
+    }
+
+    pub fn from_layer_graph(graph: &LayerGraph) -> Self {
+        let layers: Vec<NodeIndex> = graph.node_indices().collect();
+        let positions: HashMap<NodeIndex, usize> = layers.iter().enumerate().map(|(i, &layer)| (layer, i)).collect();
+
+        let nodes = layers
+            .iter()
+            .map(|&layer| RecipeNode {
+                kind: graph.layer(layer).expect("Layer exists").kind(),
+                name: graph.name(layer).expect("Layer exists").to_string(),
+                parameters: graph.parameters(layer).expect("Layer exists"),
+                id: graph.id(layer),
+                include: None,
+                position: None,
+            })
+            .collect();
+        let output_name = |layer: NodeIndex, output: usize| {
+            let outputs = graph.layer(layer).expect("Layer exists").outputs();
+            (outputs.len() > 1).then(|| outputs[output].name.to_string())
+        };
+        let mut edges: Vec<RecipeEdge> = graph
+            .edges()
+            .map(|(from, output, to, port)| RecipeEdge {
+                from: positions[&from],
+                to: positions[&to],
+                port,
+                output: output_name(from, output),
+            })
+            .collect();
+        edges.sort_by_key(|edge| (edge.to, edge.port));
+
+        Self {
+            version: Self::VERSION,
+            placeholders: BTreeMap::new(),
+            nodes,
+            edges,
+            layout: BTreeMap::new(),
+        }
+    }
+
+    /// Like `build_layer_graph`, along with the layout of the nodes
+    pub fn build_graph(&self, registry: &LayerRegistry, bindings: &Bindings) -> Result<InteractiveLayerGraph> {
+        let recipe = self.resolve(bindings, &mut Vec::new())?;
+        let mut layers: InteractiveLayerGraph = recipe.build_resolved(registry)?.into();
+        for (&node, layout) in &recipe.layout {
+            layers.set_layout(NodeIndex::new(node), layout.clone())?;
+        }
+        Ok(layers)
+    }
+
+    /// Constructs the layers through the registry and connects them, after substituting the placeholders with
+    /// `bindings` and inlining included recipes. Without includes, layer indices match the positions of the nodes.
+    pub fn build_layer_graph(&self, registry: &LayerRegistry, bindings: &Bindings) -> Result<LayerGraph> {
+        self.resolve(bindings, &mut Vec::new())?.build_resolved(registry)
+    }
+
+    /// Builds a recipe that `resolve` returned, whose layer indices match the positions of its nodes
+    fn build_resolved(&self, registry: &LayerRegistry) -> Result<LayerGraph> {
+        let mut graph = LayerGraph::new();
+        if let Some(last) = self.nodes.iter().filter_map(|node| node.id).max() {
+            graph.reserve_ids(last); // For the nodes that have one, so that the others don't get theirs
+        }
+        let mut layers = Vec::new();
+        for node in &self.nodes {
+            let layer = registry
+                .create(&node.kind, &node.parameters)
+                .context(format!("Failed to create layer {:?}", node.name))?;
+            let layer = graph.add_layer(layer, vec![]);
+            graph.rename(layer, node.name.clone())?;
+            if let Some(id) = node.id {
+                graph.set_id(layer, id).context(format!("Failed to create layer {:?}", node.name))?;
+            }
+            layers.push(layer);
+        }
+
+        for edge in &self.edges {
+            let from = layers[edge.from];
+            let output = match &edge.output {
+                None => 0,
+                Some(name) => {
+                    let outputs = graph.layer(from).expect("Layer exists").outputs();
+                    let output = outputs.iter().position(|output| output.name == name);
+                    output.context(format!("Node {:?} has no output {:?}", self.nodes[edge.from].name, name))?
+                }
+            };
+            graph.connect_output(from, output, layers[edge.to], edge.port)?;
+        }
+        Ok(graph)
+    }
+
+    /// A copy of the recipe with the placeholders substituted and the included recipes inlined. `includes` holds the
+    /// paths of the recipes that are currently being included, to detect recursion. Nodes of included recipes are
+    /// left without a layout and without ids, since these were made for a different graph.
+    fn resolve(&self, bindings: &Bindings, includes: &mut Vec<PathBuf>) -> Result<Recipe> {
+        if self.version > Self::VERSION {
+            bail!(
+                "Recipe has version {}, but only versions up to {} are supported",
+                self.version,
+                Self::VERSION
+            );
+        }
+        let values = self.placeholder_values(bindings)?;
+
+        // Nodes of the resolved recipe that each input port and the output of a node end up at
+        struct Placement {
+            inputs: Option<Vec<(usize, usize)>>, // Only known for included recipes, other nodes keep their ports
+            output: usize,
+        }
+
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        let mut layout = BTreeMap::new();
+        let mut placements = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let mut parameters = ParamMap::new();
+            for (name, value) in &node.parameters {
+                let value = substitute(value, &values)
+                    .context(format!("Failed to fill in parameter {:?} of layer {:?}", name, node.name))?;
+                parameters.insert(name.clone(), value);
+            }
+
+            if node.kind != INCLUDE {
+                placements.push(Placement {
+                    inputs: None,
+                    output: nodes.len(),
+                });
+                if let Some(node_layout) = self.node_layout(i) {
+                    layout.insert(nodes.len(), node_layout);
+                }
+                nodes.push(RecipeNode {
+                    parameters,
+                    include: None,
+                    position: None,
+                    ..node.clone()
+                });
+                continue;
+            }
+
+            let include = node
+                .include
+                .as_ref()
+                .context(format!("Include node {:?} doesn't say which recipe to include", node.name))?;
+            let bindings: Bindings = parameters.into_iter().collect();
+            let included = match &include.recipe {
+                IncludedRecipe::Embedded(recipe) => recipe.resolve(&bindings, includes),
+                IncludedRecipe::Path(path) => {
+                    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                    if includes.contains(&canonical) {
+                        let chain: Vec<String> = includes
+                            .iter()
+                            .chain([&canonical])
+                            .map(|path| format!("{:?}", path))
+                            .collect();
+                        bail!("Recipe includes itself: {}", chain.join(" -> "));
+                    }
+                    includes.push(canonical);
+                    let included = Recipe::load(path).and_then(|recipe| recipe.resolve(&bindings, includes));
+                    includes.pop();
+                    included
+                }
+            }
+            .context(format!("Failed to include recipe in node {:?}", node.name))?;
+
+            let offset = nodes.len();
+            let position = |name: &str| {
+                included
+                    .nodes
+                    .iter()
+                    .position(|node| node.name == name)
+                    .map(|position| offset + position)
+                    .context(format!("Included recipe of node {:?} has no node {:?}", node.name, name))
+            };
+            let inputs = include
+                .inputs
+                .iter()
+                .map(|(name, port)| Ok((position(name)?, *port)))
+                .collect::<Result<_>>()?;
+            placements.push(Placement {
+                inputs: Some(inputs),
+                output: position(&include.output)?,
+            });
+
+            edges.extend(included.edges.iter().map(|edge| RecipeEdge {
+                from: offset + edge.from,
+                to: offset + edge.to,
+                port: edge.port,
+                output: edge.output.clone(),
+            }));
+            nodes.extend(included.nodes.into_iter().map(|included| RecipeNode {
+                name: format!("{}/{}", node.name, included.name),
+                id: None,
+                ..included
+            }));
+        }
+
+        for edge in &self.edges {
+            let (from, to) = match (placements.get(edge.from), placements.get(edge.to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => bail!(
+                    "Connection from node {} to node {} refers to a node that doesn't exist",
+                    edge.from,
+                    edge.to
+                ),
+            };
+            let (to, port) = match &to.inputs {
+                None => (to.output, edge.port),
+                Some(inputs) => *inputs.get(edge.port).context(format!(
+                    "Include node {:?} has no input {}",
+                    self.nodes[edge.to].name, edge.port
+                ))?,
+            };
+            edges.push(RecipeEdge {
+                from: from.output,
+                to,
+                port,
+                output: edge.output.clone(), // Include nodes have the outputs of their output node
+            });
+        }
+
+        Ok(Recipe {
+            version: Self::VERSION,
+            placeholders: BTreeMap::new(),
+            nodes,
+            edges,
+            layout,
+        })
+    }
+
+    /// The layout of a node, which recipes written before there was `Recipe::layout` only have a position of
+    fn node_layout(&self, node: usize) -> Option<NodeLayout> {
+        match (self.layout.get(&node), self.nodes.get(node)?.position) {
+            (Some(layout), _) => Some(layout.clone()),
+            (None, position) => Some(NodeLayout {
+                position: Some(position?),
+                ..NodeLayout::default()
+            }),
+        }
+    }
+
+    /// Values of all placeholders, taken from `bindings` or from their defaults
+    fn placeholder_values(&self, bindings: &Bindings) -> Result<Bindings> {
+        if let Some(name) = bindings.keys().find(|&name| !self.placeholders.contains_key(name)) {
+            bail!(KlexError::UnknownPlaceholder(name.to_string()));
+        }
+
+        let mut values = Bindings::new();
+        for (name, placeholder) in &self.placeholders {
+            let value = match bindings.get(name).or(placeholder.default.as_ref()) {
+                Some(value) => value,
+                None => bail!(KlexError::UnboundPlaceholder(name.to_string())),
+            };
+            placeholder
+                .kind
+                .validate(value)
+                .context(format!("Invalid value for placeholder {:?}", name))?;
+            values.insert(name.clone(), value.clone());
+        }
+        Ok(values)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+            .context(format!("Failed to read recipe {:?}", path))?;
+        let mut recipe = Self::from_ron(&text).context(format!("Failed to parse recipe {:?}", path))?;
+        recipe.make_include_paths_absolute(path.parent().unwrap_or_else(|| Path::new("")));
+        Ok(recipe)
+    }
+
+    fn make_include_paths_absolute(&mut self, directory: &Path) {
+        for include in self.nodes.iter_mut().filter_map(|node| node.include.as_mut()) {
+            match &mut include.recipe {
+                IncludedRecipe::Path(path) => *path = directory.join(&*path),
+                IncludedRecipe::Embedded(recipe) => recipe.make_include_paths_absolute(directory),
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        util::write_atomically(path, self.to_ron()?.as_bytes())
+            .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+            .context(format!("Failed to write recipe {:?}", path))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning, // The recipe can be run, but probably doesn't do what was intended
+    Error,   // Building or running the recipe would fail
+}
+
+/// Problem found by `Recipe::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub node: Option<String>, // Name of the node the issue is about, if any
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match &self.node {
+            Some(node) => write!(f, "{} in node {:?}: {}", severity, node, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+impl Recipe {
+    /// Checks the recipe for problems without constructing any layers, so that recipes from untrusted sources can
+    /// be checked cheaply. Parameters containing placeholders are only checked for the placeholders being declared.
+    pub fn validate(&self, registry: &LayerRegistry) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let mut report = |severity, node: Option<&RecipeNode>, message: String| {
+            issues.push(ValidationIssue {
+                severity,
+                node: node.map(|node| node.name.clone()),
+                message,
+            })
+        };
+
+        if self.version > Self::VERSION {
+            let message = format!("Version {} is newer than the supported version {}", self.version, Self::VERSION);
+            report(Severity::Error, None, message);
+        }
+
+        // Nodes and their parameters
+        let mut used_placeholders = BTreeSet::new();
+        let mut names = HashSet::new();
+        let mut ids = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(node.name.as_str()) {
+                let message = "Another node has the same name, so parameters can't be addressed by name".to_string();
+                report(Severity::Warning, Some(node), message);
+            }
+            if let Some(id) = node.id.filter(|&id| !ids.insert(id)) {
+                report(Severity::Error, Some(node), format!("Another node has the same id {}", id));
+            }
+
+            let info = registry.info(&node.kind);
+            if node.kind == INCLUDE {
+                if node.include.is_none() {
+                    let message = "Include node doesn't say which recipe to include".to_string();
+                    report(Severity::Error, Some(node), message);
+                }
+            } else if info.is_none() {
+                report(Severity::Error, Some(node), format!("Unknown layer kind {:?}", node.kind));
+            }
+
+            for (name, value) in &node.parameters {
+                let placeholders = match placeholder_names(value) {
+                    Ok(placeholders) => placeholders,
+                    Err(e) => {
+                        report(Severity::Error, Some(node), format!("Parameter {:?}: {:#}", name, e));
+                        continue;
+                    }
+                };
+                for placeholder in &placeholders {
+                    if !self.placeholders.contains_key(placeholder) {
+                        let message = format!("Parameter {:?} uses undeclared placeholder {:?}", name, placeholder);
+                        report(Severity::Error, Some(node), message);
+                    }
+                }
+                used_placeholders.extend(placeholders.iter().cloned());
+
+                let spec = match info {
+                    Some(info) => info.parameters.iter().find(|spec| &spec.name == name),
+                    None => continue,
+                };
+                match spec {
+                    None => report(Severity::Error, Some(node), format!("Unknown parameter {:?}", name)),
+                    Some(spec) if placeholders.is_empty() => {
+                        if let Err(e) = spec.validate(value) {
+                            let message = format!("Invalid value for parameter {:?}: {:#}", name, e);
+                            report(Severity::Error, Some(node), message);
+                        }
+                    }
+                    Some(_) => (), // Checked once the placeholders are bound
+                }
+            }
+
+            for spec in info.map(|info| info.parameters.as_slice()).unwrap_or_default() {
+                if spec.default.is_none() && !node.parameters.contains_key(&spec.name) {
+                    report(Severity::Error, Some(node), format!("Missing parameter {:?}", spec.name));
+                }
+            }
+        }
+
+        for (name, placeholder) in &self.placeholders {
+            if let Some(default) = &placeholder.default {
+                if let Err(e) = placeholder.kind.validate(default) {
+                    report(Severity::Error, None, format!("Invalid default for placeholder {:?}: {:#}", name, e));
+                }
+            }
+            if !used_placeholders.contains(name) {
+                report(Severity::Warning, None, format!("Placeholder {:?} is never used", name));
+            }
+        }
+
+        if let Some(node) = self.layout.keys().find(|&&node| node >= self.nodes.len()) {
+            report(Severity::Warning, None, format!("The layout refers to node {}, which doesn't exist", node));
+        }
+
+        // Connections
+        let info = |node: usize| registry.info(&self.nodes[node].kind);
+        let mut graph = Graph::<(), ()>::new();
+        let indices: Vec<NodeIndex> = self.nodes.iter().map(|_| graph.add_node(())).collect();
+        let mut connected = HashSet::new();
+        for edge in &self.edges {
+            if edge.from >= self.nodes.len() || edge.to >= self.nodes.len() {
+                let message = format!(
+                    "Connection from node {} to node {} refers to a node that doesn't exist",
+                    edge.from, edge.to
+                );
+                report(Severity::Error, None, message);
+                continue;
+            }
+            graph.add_edge(indices[edge.from], indices[edge.to], ());
+
+            let (from, to) = (&self.nodes[edge.from], &self.nodes[edge.to]);
+            if !connected.insert((edge.to, edge.port)) {
+                report(Severity::Error, Some(to), format!("Input {} is connected more than once", edge.port));
+            }
+
+            let output = match (&edge.output, info(edge.from)) {
+                (None, _) => Some(0),
+                (Some(name), Some(info)) => info.output_port(name),
+                (Some(_), None) => None, // Unknown kind, which is reported already
+            };
+            if let (Some(name), Some(_), None) = (&edge.output, info(edge.from), output) {
+                report(Severity::Error, Some(from), format!("There is no output {:?}", name));
+            }
+            let input_types = info(edge.to).map(|info| info.input_types.as_slice()).unwrap_or_default();
+            let output_type = info(edge.from).zip(output).and_then(|(info, output)| info.output_element(output));
+            match (input_types.get(edge.port), output_type) {
+                _ if input_types.is_empty() => (), // Unknown inputs
+                (None, _) => report(Severity::Error, Some(to), format!("There is no input {}", edge.port)),
+                (Some(input_type), Some(output_type)) if *input_type != output_type => {
+                    let message = format!(
+                        "Input {} expects {}, but node {:?} produces {}",
+                        edge.port, input_type, from.name, output_type
+                    );
+                    report(Severity::Error, Some(to), message);
+                }
+                _ => (),
+            }
+        }
+
+        if let Err(cycle) = algo::toposort(&graph, None) {
+            let node = &self.nodes[cycle.node_id().index()];
+            report(Severity::Error, Some(node), "Node is part of a cycle".to_string());
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let highest = connected.iter().filter(|&&(to, _)| to == i).map(|&(_, port)| port + 1).max();
+            let input_count = match info(i).map(|info| info.inputs) {
+                Some(InputCount::AtLeast(count)) => count.max(highest.unwrap_or(0)),
+                inputs => inputs.map_or(0, InputCount::required),
+            };
+            for port in (0..input_count).filter(|&port| !connected.contains(&(i, port))) {
+                report(Severity::Error, Some(node), format!("Input {} is not connected", port));
+            }
+
+            let orphan = self.edges.iter().all(|edge| edge.from != i && edge.to != i);
+            if orphan && self.nodes.len() > 1 {
+                report(Severity::Warning, Some(node), "Node is not connected to any other node".to_string());
+            }
+        }
+
+        issues
+    }
+}
+
+/// Names of the placeholders used in a parameter
+fn placeholder_names(value: &ParamValue) -> Result<Vec<String>> {
+    let text = match value {
+        ParamValue::Text(text) => text.clone(),
+        ParamValue::Path(path) => path.to_string_lossy().into_owned(),
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut names = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .context(format!("Unterminated placeholder in {:?}", text))?;
+        names.push(rest[start + 2..start + end].to_string());
+        rest = &rest[start + end + 1..];
+    }
+    Ok(names)
+}
+
+/// Replaces placeholders in a parameter. A parameter consisting of a single placeholder takes on the bound value as
+/// it is, while placeholders within a text or path are replaced by the bound value written out as text.
+fn substitute(value: &ParamValue, values: &Bindings) -> Result<ParamValue> {
+    let text = match value {
+        ParamValue::Text(text) => text.clone(),
+        ParamValue::Path(path) => path.to_string_lossy().into_owned(),
+        _ => return Ok(value.clone()),
+    };
+
+    if let Some(name) = text.strip_prefix("${").and_then(|rest| rest.strip_suffix('}')) {
+        if !name.contains(['$', '{', '}']) {
+            return values
+                .get(name)
+                .cloned()
+                .context(format!("Placeholder {:?} is not declared in the recipe", name));
+        }
+    }
+
+    let mut substituted = String::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .context(format!("Unterminated placeholder in {:?}", text))?;
+        let name = &rest[start + 2..start + end];
+        let value = values
+            .get(name)
+            .context(format!("Placeholder {:?} is not declared in the recipe", name))?;
+        substituted.push_str(&rest[..start]);
+        substituted.push_str(&value.to_text());
+        rest = &rest[start + end + 1..];
+    }
+    substituted.push_str(rest);
+
+    Ok(match value {
+        ParamValue::Path(_) => ParamValue::Path(substituted.into()),
+        _ => ParamValue::Text(substituted),
+    })
+}
+
+/// Parameters to use instead of the ones stored in a recipe, addressed as `"<node name>.<parameter name>"`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParamOverrides {
+    values: BTreeMap<String, ParamValue>,
+    bindings: Bindings,
+}
+
+impl ParamOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, path: impl Into<String>, value: ParamValue) -> &mut Self {
+        self.values.insert(path.into(), value);
+        self
+    }
+
+    /// Binds a placeholder of the recipe
+    pub fn bind(&mut self, placeholder: impl Into<String>, value: ParamValue) -> &mut Self {
+        self.bindings.insert(placeholder.into(), value);
+        self
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    /// A copy of the recipe with the overridden parameters replaced
+    pub fn apply(&self, recipe: &Recipe) -> Result<Recipe> {
+        let mut recipe = recipe.clone();
+        for (path, value) in &self.values {
+            let (name, parameter) = path
+                .split_once('.')
+                .context(format!("Expected a parameter path like \"<node>.<parameter>\", got {:?}", path))?;
+
+            let mut nodes = recipe.nodes.iter_mut().filter(|node| node.name == name);
+            let node = match (nodes.next(), nodes.next()) {
+                (Some(node), None) => node,
+                (None, _) => bail!(KlexError::UnknownName { name: name.to_string(), scope: "the recipe" }),
+                (Some(_), Some(_)) => bail!(KlexError::AmbiguousName { name: name.to_string(), scope: "the recipe" }),
+            };
+            node.parameters.insert(parameter.to_string(), value.clone());
+        }
+        Ok(recipe)
+    }
+}
+
+/// Output of a node without children
+pub struct RunOutput {
+    pub value: Box<dyn Any + Send + Sync>,
+    pub type_name: &'static str, // Name of the element, e.g. "GrayImage"
+}
+
+/// Outputs of a recipe run, keyed by node name
+pub type RunOutputs = BTreeMap<String, RunOutput>;
+
+/// Builds the recipe with the built-in layers, computes it, and returns the outputs of all nodes without children.
+/// Nothing of the user interface is initialized, so this can be used from other programs.
+pub fn run(recipe: &Recipe, overrides: &ParamOverrides) -> Result<RunOutputs> {
+    run_with_registry(recipe, overrides, &LayerRegistry::with_builtins())
+}
+
+pub fn run_with_registry(recipe: &Recipe, overrides: &ParamOverrides, registry: &LayerRegistry) -> Result<RunOutputs> {
+    let mut graph = overrides.apply(recipe)?.build_layer_graph(registry, overrides.bindings())?;
+    graph.compute_all()?;
+
+    let sinks: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|&layer| graph.children(layer).is_empty())
+        .collect();
+    let mut outputs = RunOutputs::new();
+    for layer in sinks {
+        let name = graph.name(layer).expect("Layer exists").to_string();
+        let value = graph
+            .take_output(layer)?
+            .context(format!("Layer {:?} did not produce an output", name))?;
+        let type_name = entity::element_name(&*value).unwrap_or("unknown");
+        if outputs.insert(name.clone(), RunOutput { value, type_name }).is_some() {
+            bail!("Several output nodes are named {:?}", name);
+        }
+    }
+    Ok(outputs)
+}
+
+/// Placeholder that `run_batch` binds to each input file
+pub const BATCH_INPUT: &str = "input";
+
+pub struct BatchSuccess {
+    pub input: PathBuf,
+    pub outputs: Vec<PathBuf>,
+    pub duration: Duration,
+}
+
+pub struct BatchFailure {
+    pub input: PathBuf,
+    pub error: crate::error::KlexError,
+    pub duration: Duration,
+}
+
+/// Outcome of a batch run, ordered like the input files
+#[derive(Default)]
+pub struct BatchReport {
+    pub successes: Vec<BatchSuccess>,
+    pub failures: Vec<BatchFailure>,
+}
+
+/// State of a batch run after a file has been processed
+pub struct BatchProgress<'a> {
+    pub completed: usize,
+    pub total: usize,
+    pub input: &'a Path,
+    pub error: Option<&'a crate::error::KlexError>,
+}
+
+/// Applies the recipe to every image in a directory, or to every file matching a glob pattern. Each file is bound to
+/// the placeholder `BATCH_INPUT`. The outputs of the nodes without children are written to `output_template`, in
+/// which `{stem}` is replaced by the file name of the input without extension, `{index}` by the position of the
+/// input, and `{output}` by the node name. The paths of `ExportData` and `ExportVector` nodes are filled in the same
+/// way. Up to `parallelism` files are processed at once. A file that fails doesn't stop the others, and `progress` is
+/// called whenever a file is done.
+pub fn run_batch(
+    recipe: &Recipe,
+    input: &str,
+    output_template: &str,
+    parallelism: usize,
+    progress: impl Fn(&BatchProgress) + Sync,
+) -> Result<BatchReport> {
+    if !recipe.placeholders.contains_key(BATCH_INPUT) {
+        bail!("Recipe has no placeholder {:?} to bind the input files to", BATCH_INPUT);
+    }
+    let inputs = batch_inputs(input)?;
+    let registry = LayerRegistry::with_builtins();
+
+    let next = AtomicUsize::new(0);
+    let completed = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BatchFileResult>>> = Mutex::new(inputs.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..parallelism.clamp(1, inputs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                let input = match inputs.get(index) {
+                    Some(input) => input,
+                    None => break,
+                };
+
+                let start = Instant::now();
+                let result = run_batch_file(recipe, &registry, input, index, output_template);
+                let duration = start.elapsed();
+
+                progress(&BatchProgress {
+                    completed: completed.fetch_add(1, atomic::Ordering::Relaxed) + 1,
+                    total: inputs.len(),
+                    input,
+                    error: result.as_ref().err(),
+                });
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some((result, duration));
+            });
+        }
+    });
+
+    let mut report = BatchReport::default();
+    let results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    for (input, result) in inputs.into_iter().zip(results) {
+        match result.expect("Every input was processed") {
+            (Ok(outputs), duration) => report.successes.push(BatchSuccess {
+                input,
+                outputs,
+                duration,
+            }),
+            (Err(error), duration) => report.failures.push(BatchFailure { input, error, duration }),
+        }
+    }
+    Ok(report)
+}
+
+type BatchFileResult = (Result<Vec<PathBuf>>, Duration); // Written files or error, and processing time
+
+/// Image files in a directory, or files matching a glob pattern, sorted by path
+fn batch_inputs(input: &str) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    if Path::new(input).is_dir() {
+        let entries = fs::read_dir(input).map_err(|source| KlexError::Io { path: input.into(), source });
+        for entry in entries.context(format!("Failed to read directory {:?}", input))? {
+            let path = entry.map_err(|source| KlexError::Io { path: input.into(), source })?.path();
+            if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+                inputs.push(path);
+            }
+        }
+    } else {
+        for path in glob::glob(input).context(format!("Invalid glob pattern {:?}", input))? {
+            let path = path?;
+            if path.is_file() {
+                inputs.push(path);
+            }
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn run_batch_file(
+    recipe: &Recipe,
+    registry: &LayerRegistry,
+    input: &Path,
+    index: usize,
+    output_template: &str,
+) -> Result<Vec<PathBuf>> {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let fill_in = |template: &str, output: &str| {
+        let template = template.replace("{stem}", &stem).replace("{index}", &index.to_string());
+        PathBuf::from(template.replace("{output}", output))
+    };
+
+    let mut overrides = ParamOverrides::new();
+    overrides.bind(BATCH_INPUT, ParamValue::Path(input.to_path_buf()));
+    for node in recipe.nodes.iter().filter(|node| ["ExportData", "ExportVector"].contains(&node.kind.as_str())) {
+        if let Some(ParamValue::Path(path)) = node.parameters.get("path") {
+            let path = fill_in(&path.to_string_lossy(), &node.name);
+            overrides.set(format!("{}.path", node.name), ParamValue::Path(path));
+        }
+    }
+    let outputs = run_with_registry(recipe, &overrides, registry)?;
+    if outputs.len() > 1 && !output_template.contains("{output}") {
+        bail!("Recipe has several outputs, so the output template has to contain \"{{output}}\"");
+    }
+
+    let mut written = Vec::new();
+    for (name, output) in outputs {
+        let path = fill_in(output_template, &name);
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|source| KlexError::Io { path: directory.into(), source })?;
+        }
+        entity::save(&*output.value, &path).context(format!("Failed to write output {:?} to {:?}", name, path))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Outputs of the nodes without children, keyed by node name
+pub type SinkOutputs<'a> = BTreeMap<&'a str, &'a (dyn Any + Send + Sync)>;
+
+/// Runs the recipe and runs it again whenever one of the files read by its `InputFile` nodes changes, or another input
+/// layer reports a change of what it reads through `Layer::has_changed`, e.g. a remote image. Only the layers depending
+/// on the changes are recomputed. `callback` receives the outputs after each run, or the error if a run failed, and
+/// decides whether to keep watching.
+pub fn watch(
+    recipe: &Recipe,
+    bindings: &Bindings,
+    callback: impl FnMut(Result<SinkOutputs>) -> ControlFlow<()>,
+) -> Result<()> {
+    let mut watcher = PollingWatcher::default();
+    let graph = recipe.build_layer_graph(&LayerRegistry::with_builtins(), bindings)?;
+    watcher.track(&input_files(&graph).into_keys().collect::<Vec<_>>());
+    watch_graph(graph, &mut watcher, callback)
+}
+
+/// Like `watch`, with the layers built through `registry` and changes detected by `watcher`
+pub fn watch_with(
+    recipe: &Recipe,
+    bindings: &Bindings,
+    registry: &LayerRegistry,
+    watcher: &mut dyn FileWatcher,
+    callback: impl FnMut(Result<SinkOutputs>) -> ControlFlow<()>,
+) -> Result<()> {
+    watch_graph(recipe.build_layer_graph(registry, bindings)?, watcher, callback)
+}
+
+fn watch_graph(
+    mut graph: LayerGraph,
+    watcher: &mut dyn FileWatcher,
+    mut callback: impl FnMut(Result<SinkOutputs>) -> ControlFlow<()>,
+) -> Result<()> {
+    let inputs = input_files(&graph);
+    let paths: Vec<PathBuf> = inputs.keys().cloned().collect();
+    loop {
+        let outputs = graph.compute_all().map(|()| sink_outputs(&graph));
+        if callback(outputs).is_break() {
+            return Ok(());
+        }
+
+        let mut changed = Vec::new();
+        let changed_paths = watcher.wait_for_changes_polling(&paths, &mut || {
+            // Failing checks count as changes, so that the next run reports the error
+            changed.extend(graph.node_indices().filter(|&layer| {
+                graph.layer(layer).is_some_and(|layer| layer.has_changed().unwrap_or(true))
+            }));
+            !changed.is_empty()
+        })?;
+        for path in changed_paths {
+            changed.extend(inputs.get(&path).into_iter().flatten());
+        }
+        for layer in changed {
+            graph.mark_dirty(layer);
+        }
+    }
+}
+
+/// Layers reading each file, found through their `"path"` parameter
+fn input_files(graph: &LayerGraph) -> BTreeMap<PathBuf, Vec<NodeIndex>> {
+    let mut files: BTreeMap<PathBuf, Vec<NodeIndex>> = BTreeMap::new();
+    for layer in graph.node_indices() {
+        if graph.layer(layer).map(|layer| layer.kind()).as_deref() != Some("InputFile") {
+            continue;
+        }
+        let path = graph.parameters(layer).ok().and_then(|mut parameters| parameters.remove("path"));
+        if let Some(ParamValue::Path(path)) = path {
+            files.entry(path).or_default().push(layer);
+        }
+    }
+    files
+}
+
+fn sink_outputs(graph: &LayerGraph) -> SinkOutputs<'_> {
+    graph
+        .node_indices()
+        .filter(|&layer| graph.children(layer).is_empty())
+        .filter_map(|layer| Some((graph.name(layer)?, graph.output(layer, 0)?)))
+        .collect()
+}
+
 pub struct CannyEdge {}
 
 // impl Layer for CannyEdge {
@@ -26,7 +997,7 @@ pub struct CannyEdge {}
 // pub struct CannyEdge{}
 
 // impl Layer for CannyEdge {
-//     fn compute(&mut self, input: &Box<dyn std::any::Any>) -> anyhow::Result<()> {
+//     fn compute(&mut self, input: &Box<dyn std::any::Any>) -> crate::error::Result<()> {
 //         todo!()
 //     }
 
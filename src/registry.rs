@@ -0,0 +1,496 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use image::{GrayImage, RgbaImage};
+
+use crate::{
+    color::ConversionGraph,
+    entity::{BinaryImage, DataFormat, Gray16Image, GrayImageF32},
+    error::{bail, Context, KlexError, Result},
+    layer::{
+        primitive::{
+            Annotate, BackgroundEstimate, BackgroundMethod, BackgroundOutput, Connectivity, Contours, Convert,
+            ConvertAny, Crop, DrawGeometry, ExportData, FloatRange, FloatScale, InputFile, InputFrames, Invert,
+            Normalize, PaintedMask, RegionGrow, SplitChannels, Stack, StackReduction, Threshold, ThresholdMode, ToFloat,
+            Vignette, Watershed, WhiteBalance, WhiteBalanceMethod, Window, WindowMode,
+        },
+        InputCount, Layer, LayerCategory, OutputPort,
+    },
+    parameter::{ParamKind, ParamMap, ParamSpec, ParamValue},
+};
+
+pub type LayerFactory = Box<dyn Fn(&ParamMap) -> Result<Box<dyn Layer>> + Send + Sync>;
+
+/// What is known about a kind of layer without constructing it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerInfo {
+    pub parameters: Vec<ParamSpec>,
+    pub input_types: Vec<&'static str>, // Empty if unknown, see `Layer::input_types`
+    pub inputs: InputCount,             // See `Layer::expected_inputs`
+    pub output_type: Option<&'static str>,
+    pub outputs: Vec<OutputPort>, // Empty if unknown, in which case there is a single one
+    pub category: LayerCategory,
+}
+
+impl LayerInfo {
+    /// Name of the element produced at an output port, if known
+    pub fn output_element(&self, port: usize) -> Option<&'static str> {
+        match self.outputs.get(port) {
+            Some(output) => output.element.or(self.output_type.filter(|_| port == 0)),
+            None => self.output_type.filter(|_| port == 0 && self.outputs.is_empty()),
+        }
+    }
+
+    /// The output port with the given name
+    pub fn output_port(&self, name: &str) -> Option<usize> {
+        let outputs = if self.outputs.is_empty() { &[OutputPort::SINGLE][..] } else { &self.outputs };
+        outputs.iter().position(|output| output.name == name)
+    }
+}
+
+struct Entry {
+    info: LayerInfo,
+    factory: LayerFactory,
+    default: Option<Box<dyn Fn() -> Box<dyn Layer> + Send + Sync>>, // Constructs the layer without any parameters
+}
+
+/// Constructs layers from their kind, as returned by `Layer::kind`, and a set of parameters
+pub struct LayerRegistry {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl LayerRegistry {
+    /// A registry without any layers
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// A registry containing all layers that come with this crate
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register_default(
+            || InputFile::<RgbaImage>::new(PathBuf::new()),
+            vec![ParamSpec::new("path", ParamKind::Path, None)],
+        );
+        registry.register_default(
+            || InputFile::<Gray16Image>::new(PathBuf::new()),
+            vec![ParamSpec::new("path", ParamKind::Path, None)],
+        );
+        registry.register_default(
+            || InputFrames::new(PathBuf::new(), 0),
+            vec![
+                ParamSpec::new("path", ParamKind::Path, None),
+                ParamSpec::new(
+                    "frame",
+                    ParamKind::Int { min: 0, max: i64::from(u32::MAX) },
+                    Some(ParamValue::Int(0)),
+                ),
+            ],
+        );
+        #[cfg(feature = "http")]
+        registry.register_default(
+            || crate::layer::primitive::InputUrl::new(String::new()),
+            vec![
+                ParamSpec::new("url", ParamKind::Text, None),
+                ParamSpec::new("timeout", ParamKind::Float { min: 0.1, max: 3600.0 }, Some(ParamValue::Float(10.0))),
+                ParamSpec::new(
+                    "max_bytes",
+                    ParamKind::Int { min: 1, max: i64::from(u32::MAX) },
+                    Some(ParamValue::Int(50 << 20)),
+                ),
+                ParamSpec::new("interval", ParamKind::Float { min: 0.0, max: 86400.0 }, Some(ParamValue::Float(10.0))),
+            ],
+        );
+        #[cfg(feature = "clipboard")]
+        {
+            registry.register_default(crate::layer::primitive::ClipboardInput::new, vec![]);
+            registry.register_default(crate::layer::primitive::ClipboardOutput::new, vec![]);
+        }
+        registry.register_default(Convert::<RgbaImage, GrayImage>::new, vec![]);
+        registry.register_default(Convert::<BinaryImage, GrayImage>::new, vec![]);
+        registry.register_default(
+            || Window::new(WindowMode::MinMax, 1.0),
+            vec![
+                ParamSpec::new(
+                    "window",
+                    ParamKind::Choice(WindowMode::ALL.iter().map(|mode| format!("{:?}", mode)).collect()),
+                    Some(ParamValue::Choice("MinMax".to_string())),
+                ),
+                ParamSpec::new(
+                    "percentile",
+                    ParamKind::Float { min: 0.0, max: 50.0 },
+                    Some(ParamValue::Float(1.0)),
+                ),
+            ],
+        );
+        registry.register_default(
+            || ToFloat::new(FloatScale::Normalized),
+            vec![ParamSpec::new(
+                "scale",
+                ParamKind::Choice(FloatScale::ALL.iter().map(|scale| format!("{:?}", scale)).collect()),
+                Some(ParamValue::Choice("Normalized".to_string())),
+            )],
+        );
+        let bound = ParamKind::Float {
+            min: f64::from(f32::MIN),
+            max: f64::from(f32::MAX),
+        };
+        registry.register_default(
+            || Normalize::new(FloatRange::MinMax, 0.0, 1.0),
+            vec![
+                ParamSpec::new(
+                    "range",
+                    ParamKind::Choice(FloatRange::ALL.iter().map(|range| format!("{:?}", range)).collect()),
+                    Some(ParamValue::Choice("MinMax".to_string())),
+                ),
+                ParamSpec::new("min", bound.clone(), Some(ParamValue::Float(0.0))),
+                ParamSpec::new("max", bound.clone(), Some(ParamValue::Float(1.0))),
+            ],
+        );
+        let threshold_specs = |kind: ParamKind, default: ParamValue| {
+            vec![
+                ParamSpec::new("threshold", kind, Some(default)),
+                ParamSpec::new(
+                    "ordering",
+                    ParamKind::Choice(ThresholdMode::ALL.iter().map(|mode| format!("{:?}", mode)).collect()),
+                    Some(ParamValue::Choice("Greater".to_string())),
+                ),
+            ]
+        };
+        let int = |max: u16| ParamKind::Int {
+            min: 0,
+            max: max.into(),
+        };
+        registry.register_default(
+            || Threshold::new(128, ThresholdMode::Greater),
+            threshold_specs(int(u8::MAX.into()), ParamValue::Int(128)),
+        );
+        registry.register_default(
+            || Threshold::<Gray16Image>::with_threshold(32768, ThresholdMode::Greater),
+            threshold_specs(int(u16::MAX), ParamValue::Int(32768)),
+        );
+        registry.register_default(
+            || Threshold::<GrayImageF32>::with_threshold(0.5, ThresholdMode::Greater),
+            threshold_specs(bound, ParamValue::Float(0.5)),
+        );
+        let crop_specs = || {
+            let size = ParamKind::Int {
+                min: 0,
+                max: u32::MAX.into(),
+            };
+            ["x", "y", "width", "height"]
+                .into_iter()
+                .map(|name| ParamSpec::new(name, size.clone(), Some(ParamValue::Int(0))))
+                .collect()
+        };
+        registry.register_default(Invert::<GrayImage>::new, vec![]);
+        registry.register_default(Invert::<Gray16Image>::new, vec![]);
+        registry.register_default(|| Crop::<RgbaImage>::new(0, 0, 0, 0), crop_specs());
+        registry.register_default(|| Crop::<GrayImage>::new(0, 0, 0, 0), crop_specs());
+        registry.register_default(|| Crop::<Gray16Image>::new(0, 0, 0, 0), crop_specs());
+        registry.register_default(|| Crop::<GrayImageF32>::new(0, 0, 0, 0), crop_specs());
+        let mask_size = ParamKind::Int { min: 1, max: 16384 };
+        registry.register_default(
+            || PaintedMask::new(512, 512),
+            vec![
+                ParamSpec::new("width", mask_size.clone(), Some(ParamValue::Int(512))),
+                ParamSpec::new("height", mask_size, Some(ParamValue::Int(512))),
+            ],
+        );
+        registry.register_default(Contours::new, vec![]);
+        registry.register_default(|| SplitChannels, vec![]);
+        let vignette_specs = || {
+            let spec = |name, min, max, default| {
+                ParamSpec::new(name, ParamKind::Float { min, max }, Some(ParamValue::Float(default)))
+            };
+            vec![
+                spec("strength", -1.0, 1.0, 0.0),
+                spec("radius", 0.01, 2.0, 0.5),
+                spec("center_x", -0.5, 0.5, 0.0),
+                spec("center_y", -0.5, 0.5, 0.0),
+            ]
+        };
+        registry.register_default(|| Vignette::<GrayImage>::new(0.0, 0.5, [0.0; 2]), vignette_specs());
+        registry.register_default(|| Vignette::<RgbaImage>::new(0.0, 0.5, [0.0; 2]), vignette_specs());
+        registry.register_default(
+            || BackgroundEstimate::new(BackgroundMethod::Morphological, BackgroundOutput::Subtracted, 50, true),
+            vec![
+                ParamSpec::new(
+                    "method",
+                    ParamKind::Choice(BackgroundMethod::ALL.iter().map(|method| format!("{:?}", method)).collect()),
+                    Some(ParamValue::Choice("Morphological".to_string())),
+                ),
+                ParamSpec::new(
+                    "output",
+                    ParamKind::Choice(BackgroundOutput::ALL.iter().map(|output| format!("{:?}", output)).collect()),
+                    Some(ParamValue::Choice("Subtracted".to_string())),
+                ),
+                ParamSpec::new("radius", ParamKind::Int { min: 1, max: 16384 }, Some(ParamValue::Int(50))),
+                ParamSpec::new("light_background", ParamKind::Bool, Some(ParamValue::Bool(true))),
+            ],
+        );
+        registry.register_default(
+            || RegionGrow::new(Vec::new(), 10.0),
+            vec![
+                ParamSpec::new("seeds", ParamKind::Text, Some(ParamValue::Text(String::new()))),
+                ParamSpec::new(
+                    "tolerance",
+                    ParamKind::Float { min: 0.0, max: 255.0 },
+                    Some(ParamValue::Float(10.0)),
+                ),
+                ParamSpec::new(
+                    "connectivity",
+                    ParamKind::Choice(Connectivity::ALL.iter().map(|neighbors| format!("{:?}", neighbors)).collect()),
+                    Some(ParamValue::Choice("Four".to_string())),
+                ),
+                ParamSpec::new("binary", ParamKind::Bool, Some(ParamValue::Bool(false))),
+            ],
+        );
+        registry.register_default(
+            || Watershed::new(Connectivity::Four, true),
+            vec![
+                ParamSpec::new(
+                    "connectivity",
+                    ParamKind::Choice(Connectivity::ALL.iter().map(|neighbors| format!("{:?}", neighbors)).collect()),
+                    Some(ParamValue::Choice("Four".to_string())),
+                ),
+                ParamSpec::new("lines", ParamKind::Bool, Some(ParamValue::Bool(true))),
+            ],
+        );
+        registry.register_default(
+            || Annotate::new("{index}".to_string()),
+            vec![
+                ParamSpec::new("format", ParamKind::Text, Some(ParamValue::Text("{index}".to_string()))),
+                ParamSpec::new("size", ParamKind::Int { min: 1, max: 1000 }, Some(ParamValue::Int(14))),
+                ParamSpec::new("color", ParamKind::Color, Some(ParamValue::Color([255, 255, 0, 255]))),
+                ParamSpec::new("markers", ParamKind::Bool, Some(ParamValue::Bool(true))),
+            ],
+        );
+        let canvas_size = ParamKind::Int { min: 0, max: 16384 };
+        registry.register_default(
+            || DrawGeometry::new(0, 0),
+            vec![
+                ParamSpec::new("width", canvas_size.clone(), Some(ParamValue::Int(0))),
+                ParamSpec::new("height", canvas_size, Some(ParamValue::Int(0))),
+                ParamSpec::new("stroke", ParamKind::Int { min: 0, max: 100 }, Some(ParamValue::Int(1))),
+                ParamSpec::new("color", ParamKind::Color, Some(ParamValue::Color([255, 0, 0, 255]))),
+                ParamSpec::new("fill", ParamKind::Bool, Some(ParamValue::Bool(false))),
+            ],
+        );
+        registry.register_default(
+            || Stack::new(StackReduction::Mean),
+            vec![ParamSpec::new(
+                "reduction",
+                ParamKind::Choice(StackReduction::ALL.iter().map(|reduction| format!("{:?}", reduction)).collect()),
+                Some(ParamValue::Choice("Mean".to_string())),
+            )],
+        );
+        #[cfg(feature = "fft")]
+        registry.register_default(
+            || crate::layer::primitive::AlignTranslate::new(true),
+            vec![ParamSpec::new("subpixel", ParamKind::Bool, Some(ParamValue::Bool(true)))],
+        );
+        #[cfg(feature = "fft")]
+        {
+            use crate::layer::primitive::{FftFilter, FrequencyBand, SpectrumOutput};
+
+            let cutoff = || ParamKind::Float { min: 0.001, max: 2.0 };
+            registry.register_default(
+                || FftFilter::new(FrequencyBand::LowPass, 0.5, 1.0),
+                vec![
+                    ParamSpec::new(
+                        "band",
+                        ParamKind::Choice(FrequencyBand::ALL.iter().map(|band| format!("{:?}", band)).collect()),
+                        Some(ParamValue::Choice("LowPass".to_string())),
+                    ),
+                    ParamSpec::new(
+                        "output",
+                        ParamKind::Choice(SpectrumOutput::ALL.iter().map(|output| format!("{:?}", output)).collect()),
+                        Some(ParamValue::Choice("Filtered".to_string())),
+                    ),
+                    ParamSpec::new("cutoff", cutoff(), Some(ParamValue::Float(0.5))),
+                    ParamSpec::new("upper_cutoff", cutoff(), Some(ParamValue::Float(1.0))),
+                ],
+            );
+        }
+        let gain = || ParamKind::Float {
+            min: 0.0,
+            max: f64::from(WhiteBalance::MAX_GAIN),
+        };
+        registry.register_default(
+            || WhiteBalance::new(WhiteBalanceMethod::GrayWorld, 1.0),
+            vec![
+                ParamSpec::new(
+                    "method",
+                    ParamKind::Choice(WhiteBalanceMethod::ALL.iter().map(|method| format!("{:?}", method)).collect()),
+                    Some(ParamValue::Choice("GrayWorld".to_string())),
+                ),
+                ParamSpec::new(
+                    "percentile",
+                    ParamKind::Float { min: 0.0, max: 50.0 },
+                    Some(ParamValue::Float(1.0)),
+                ),
+                ParamSpec::new("red", gain(), Some(ParamValue::Float(1.0))),
+                ParamSpec::new("green", gain(), Some(ParamValue::Float(1.0))),
+                ParamSpec::new("blue", gain(), Some(ParamValue::Float(1.0))),
+            ],
+        );
+        registry.register_default(
+            || ExportData::new(PathBuf::new(), DataFormat::Csv),
+            vec![
+                ParamSpec::new("path", ParamKind::Path, None),
+                ParamSpec::new(
+                    "format",
+                    ParamKind::Choice(DataFormat::ALL.iter().map(|format| format!("{:?}", format)).collect()),
+                    Some(ParamValue::Choice("Csv".to_string())),
+                ),
+            ],
+        );
+        #[cfg(feature = "svg")]
+        registry.register_default(
+            || crate::layer::primitive::ExportVector::new(PathBuf::new()),
+            vec![
+                ParamSpec::new("path", ParamKind::Path, None),
+                ParamSpec::new("color", ParamKind::Color, Some(ParamValue::Color([255, 0, 0, 255]))),
+                ParamSpec::new("width", ParamKind::Float { min: 0.1, max: 100.0 }, Some(ParamValue::Float(2.0))),
+                ParamSpec::new("scale", ParamKind::Float { min: 0.01, max: 100.0 }, Some(ParamValue::Float(1.0))),
+            ],
+        );
+        // Conversions without a layer of their own go through other elements
+        let graph = ConversionGraph::builtins();
+        for from in graph.elements() {
+            for to in graph.elements() {
+                let kind = format!("Convert<{}, {}>", from, to);
+                if from != to && graph.can_convert(from, to) && !registry.contains(&kind) {
+                    registry.register_default(move || ConvertAny::between(from, to).expect("Path exists"), vec![]);
+                }
+            }
+        }
+        registry
+    }
+
+    /// Makes a kind of layer available. The factory receives parameters that were checked against `parameters` and
+    /// completed with their defaults. Registering a kind again replaces the previous factory.
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        parameters: Vec<ParamSpec>,
+        factory: impl Fn(&ParamMap) -> Result<Box<dyn Layer>> + Send + Sync + 'static,
+    ) {
+        let info = LayerInfo {
+            parameters,
+            ..LayerInfo::default()
+        };
+        self.register_with_info(kind, info, factory);
+    }
+
+    /// Like `register`, but also makes the element types of the layer known, so that recipes can be checked
+    pub fn register_with_info(
+        &mut self,
+        kind: impl Into<String>,
+        info: LayerInfo,
+        factory: impl Fn(&ParamMap) -> Result<Box<dyn Layer>> + Send + Sync + 'static,
+    ) {
+        let entry = Entry {
+            info,
+            factory: Box::new(factory),
+            default: None,
+        };
+        self.entries.insert(kind.into(), entry);
+    }
+
+    /// Registers a layer that is constructed with default parameters, which are then overwritten one by one
+    pub fn register_default<L: Layer + 'static>(
+        &mut self,
+        default: impl Fn() -> L + Send + Sync + 'static,
+        parameters: Vec<ParamSpec>,
+    ) {
+        let prototype = default();
+        let kind = prototype.kind();
+        let info = LayerInfo {
+            parameters,
+            input_types: prototype.input_types(),
+            inputs: prototype.expected_inputs(),
+            output_type: prototype.output_type(),
+            outputs: prototype.outputs().to_vec(),
+            category: prototype.category(),
+        };
+        let default = Arc::new(default);
+        let factory = default.clone();
+        self.register_with_info(kind.clone(), info, move |parameters| {
+            set_parameters(Box::new(factory()), parameters)
+        });
+        if let Some(entry) = self.entries.get_mut(&kind) {
+            entry.default = Some(Box::new(move || Box::new(default())));
+        }
+    }
+
+    pub fn create(&self, kind: &str, parameters: &ParamMap) -> Result<Box<dyn Layer>> {
+        let entry = self.entries.get(kind).ok_or_else(|| KlexError::UnknownLayerKind(kind.to_string()))?;
+        let parameters = Self::complete_parameters(&entry.info.parameters, parameters)
+            .context(format!("Invalid parameters for layer of kind {:?}", kind))?;
+        (entry.factory)(&parameters).context(format!("Failed to create layer of kind {:?}", kind))
+    }
+
+    /// Creates a layer with default parameters. Unlike `create`, this also works for kinds with parameters that
+    /// have no default, as long as they were registered with `register_default`. Those parameters are then left unset
+    /// until they are provided.
+    pub fn create_default(&self, kind: &str) -> Result<Box<dyn Layer>> {
+        match self.entries.get(kind).and_then(|entry| entry.default.as_ref()) {
+            Some(default) => Ok(default()),
+            None => self.create(kind, &ParamMap::new()),
+        }
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.entries.contains_key(kind)
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// All available kinds of layers along with the parameters they accept
+    pub fn catalog(&self) -> impl Iterator<Item = (&str, &[ParamSpec])> {
+        self.entries
+            .iter()
+            .map(|(kind, entry)| (kind.as_str(), entry.info.parameters.as_slice()))
+    }
+
+    pub fn info(&self, kind: &str) -> Option<&LayerInfo> {
+        self.entries.get(kind).map(|entry| &entry.info)
+    }
+
+    /// Checks the given parameters against the specs and adds defaults for the ones that are missing
+    fn complete_parameters(specs: &[ParamSpec], parameters: &ParamMap) -> Result<ParamMap> {
+        if let Some(name) = parameters.keys().find(|name| specs.iter().all(|spec| &spec.name != *name)) {
+            bail!(KlexError::UnknownParameter(name.to_string()));
+        }
+
+        let mut complete = ParamMap::new();
+        for spec in specs {
+            let value = match parameters.get(&spec.name).or(spec.default.as_ref()) {
+                Some(value) => value,
+                None => bail!(KlexError::MissingParameter(spec.name.to_string())),
+            };
+            spec.validate(value)
+                .context(format!("Invalid value for parameter {:?}", spec.name))?;
+            complete.insert(spec.name.clone(), value.clone());
+        }
+        Ok(complete)
+    }
+}
+
+impl Default for LayerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn set_parameters(mut layer: Box<dyn Layer>, parameters: &ParamMap) -> Result<Box<dyn Layer>> {
+    for (name, value) in parameters {
+        layer.set_parameter(name, value.clone())?;
+    }
+    Ok(layer)
+}
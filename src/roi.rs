@@ -0,0 +1,146 @@
+use crate::{
+    entity::{self, Rect},
+    error::{bail, Context, KlexError, Result},
+    layer::{CancelToken, ComputeContext, InputCount, Layer, LayerOutput},
+    parameter::{ParamMap, ParamValue, Parameter},
+};
+
+/// Restricts another layer to a region of its input, so that e.g. a filter only changes part of an image. The inner
+/// layer computes on its image inputs cropped to the region, and its output is pasted over a copy of the first input,
+/// which it therefore has to produce the same element as. Parameters of the inner layer are addressed as
+/// `"inner.<parameter name>"`.
+pub struct RoiScope {
+    inner: Box<dyn Layer>,
+    rect: Rect, // Sizes of 0 reach to the edge of the image, like with `Crop`
+}
+
+impl RoiScope {
+    const PREFIX: &'static str = "inner.";
+
+    pub fn new(inner: Box<dyn Layer>, rect: Rect) -> Self {
+        Self { inner, rect }
+    }
+
+    pub fn inner(&self) -> &dyn Layer {
+        self.inner.as_ref()
+    }
+
+    /// The region of an image of the given size that the inner layer computes on
+    pub fn region(&self, size: (u32, u32)) -> Rect {
+        let reach = |size: u32| if size == 0 { u32::MAX } else { size };
+        Rect::new(self.rect.x, self.rect.y, reach(self.rect.width), reach(self.rect.height)).clamped(size)
+    }
+}
+
+impl Layer for RoiScope {
+    fn kind(&self) -> String {
+        "RoiScope".to_string()
+    }
+
+    fn category(&self) -> crate::layer::LayerCategory {
+        self.inner.category()
+    }
+
+    fn input_types(&self) -> Vec<&'static str> {
+        self.inner.input_types()
+    }
+
+    fn expected_inputs(&self) -> InputCount {
+        self.inner.expected_inputs()
+    }
+
+    fn output_type(&self) -> Option<&'static str> {
+        self.inner.output_type()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        self.compute_cancellable(input, output, &CancelToken::new())
+    }
+
+    fn compute_cancellable(
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        self.compute_in_context(input, output, ComputeContext { cancel, pool: None })
+    }
+
+    fn compute_in_context(
+        &self,
+        input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        context: ComputeContext,
+    ) -> Result<()> {
+        let first = input.first().and_then(|input| input.as_deref()).context("Missing input")?;
+        let size = entity::dimensions(first).context("The first input of a region has to be an image")?;
+        let region = self.region(size);
+        // Inputs that aren't images, e.g. geometry, are passed on as they are
+        let cropped: Vec<LayerOutput> = input
+            .iter()
+            .map(|input| input.as_deref().and_then(|input| entity::crop(input, region)))
+            .collect();
+        let inner_input: Vec<&LayerOutput> = input
+            .iter()
+            .zip(&cropped)
+            .map(|(input, cropped)| if cropped.is_some() { cropped } else { *input })
+            .collect();
+
+        let mut patch = None;
+        self.inner.compute_in_context(&inner_input, &mut patch, context)?;
+        let patch = patch.context("Layer inside of the region produced no output")?;
+        let mut result = entity::crop(first, Rect::new(0, 0, size.0, size.1)).context("Can't copy the input")?;
+        entity::paste(result.as_mut(), patch.as_ref(), region.x, region.y)
+            .context("The layer inside of a region has to produce the same element it receives")?;
+        *output = Some(result);
+        Ok(())
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        Some(format!("{} {:?} {}", self.kind(), self.rect, self.inner.cache_key()?))
+    }
+
+    fn parameters(&self) -> ParamMap {
+        let mut parameters = ParamMap::from([
+            ("x".to_string(), self.rect.x.to_value()),
+            ("y".to_string(), self.rect.y.to_value()),
+            ("width".to_string(), self.rect.width.to_value()),
+            ("height".to_string(), self.rect.height.to_value()),
+        ]);
+        for (name, value) in self.inner.parameters() {
+            parameters.insert(format!("{}{}", Self::PREFIX, name), value);
+        }
+        parameters
+    }
+
+    fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        match name {
+            "x" => self.rect.x = Parameter::from_value(&value)?,
+            "y" => self.rect.y = Parameter::from_value(&value)?,
+            "width" => self.rect.width = Parameter::from_value(&value)?,
+            "height" => self.rect.height = Parameter::from_value(&value)?,
+            _ => match name.strip_prefix(Self::PREFIX) {
+                Some(name) => self.inner.set_parameter(name, value)?,
+                None => bail!(KlexError::UnknownParameter(name.to_string())),
+            },
+        }
+        Ok(())
+    }
+
+    fn scale_parameters(&mut self, factor: f64) {
+        let scale = |value: u32| (f64::from(value) * factor).round() as u32;
+        let rect = &mut self.rect;
+        (rect.x, rect.y) = (scale(rect.x), scale(rect.y));
+        // A region that isn't empty at full resolution isn't empty in the preview either
+        let scale_size = |size: u32| if size == 0 { 0 } else { scale(size).max(1) };
+        (rect.width, rect.height) = (scale_size(rect.width), scale_size(rect.height));
+        self.inner.scale_parameters(factor);
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        Ok(Box::new(Self::new(self.inner.clone_boxed()?, self.rect)))
+    }
+}
+
+#[cfg(feature = "ui")]
+impl crate::layer::InteractiveLayer for RoiScope {}
@@ -0,0 +1,224 @@
+//! Storing image elements, either with serde or as `.npy` files that NumPy can load
+
+use std::{fmt, fs, path::Path};
+
+use image::{GrayImage, ImageBuffer, Pixel};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    entity::{BinaryImage, Gray16Image, GrayImageF32},
+    error::{bail, ensure, Context, KlexError, Result},
+};
+
+/// Serializes an image of the `image` crate as its size and raw bytes, e.g. with
+/// `#[serde(with = "klex::serialize::image_buffer")]`. Formats that support byte strings store the pixels as one
+/// instead of as a list of numbers.
+pub mod image_buffer {
+    use super::*;
+
+    pub fn serialize<P, S>(image: &ImageBuffer<P, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        S: Serializer,
+    {
+        let pixels = Bytes(image.as_raw().clone());
+        Stored { width: image.width(), height: image.height(), pixels }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, P, D>(deserializer: D) -> Result<ImageBuffer<P, Vec<u8>>, D::Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        D: Deserializer<'de>,
+    {
+        let Stored { width, height, pixels } = Stored::deserialize(deserializer)?;
+        ImageBuffer::from_raw(width, height, pixels.0)
+            .ok_or_else(|| de::Error::custom(format!("Wrong number of bytes for a {}x{} image", width, height)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    width: u32,
+    height: u32,
+    pixels: Bytes, // Binary images pack eight pixels into a byte
+}
+
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Bytes;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Bytes, E> {
+                Ok(Bytes(bytes.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Bytes, E> {
+                Ok(Bytes(bytes))
+            }
+
+            // For formats without byte strings
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut sequence: A) -> Result<Bytes, A::Error> {
+                let mut bytes = Vec::with_capacity(sequence.size_hint().unwrap_or(0));
+                while let Some(byte) = sequence.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(Bytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(Visitor)
+    }
+}
+
+impl Serialize for BinaryImage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let pixels = self.data().chunks(8).map(|pixels| {
+            pixels.iter().enumerate().filter(|(_, &set)| set).fold(0_u8, |byte, (i, _)| byte | 1 << i)
+        });
+        Stored { width: self.width(), height: self.height(), pixels: Bytes(pixels.collect()) }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BinaryImage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Stored { width, height, pixels } = Stored::deserialize(deserializer)?;
+        let count = width as usize * height as usize;
+        if pixels.0.len() != count.div_ceil(8) {
+            return Err(de::Error::custom(format!("Wrong number of bytes for a {}x{} image", width, height)));
+        }
+        let data = (0..count).map(|i| pixels.0[i / 8] & 1 << (i % 8) != 0).collect();
+        Ok(Self::new(width, height, data))
+    }
+}
+
+/// Images that can be stored as two dimensional NumPy arrays of shape (height, width)
+pub trait Npy: Sized {
+    const DESCR: &'static str; // The NumPy data type
+
+    fn to_npy(&self, path: &Path) -> Result<()>;
+    fn from_npy(path: &Path) -> Result<Self>;
+}
+
+impl Npy for GrayImage {
+    const DESCR: &'static str = "|u1";
+
+    fn to_npy(&self, path: &Path) -> Result<()> {
+        write_npy(path, Self::DESCR, (self.width(), self.height()), self.as_raw())
+    }
+
+    fn from_npy(path: &Path) -> Result<Self> {
+        let ((width, height), data) = read_npy(path, Self::DESCR)?;
+        Ok(Self::from_raw(width, height, data).expect("npy data matches its shape"))
+    }
+}
+
+impl Npy for BinaryImage {
+    const DESCR: &'static str = "|b1";
+
+    fn to_npy(&self, path: &Path) -> Result<()> {
+        let data: Vec<_> = self.data().iter().map(|&pixel| u8::from(pixel)).collect();
+        write_npy(path, Self::DESCR, (self.width(), self.height()), &data)
+    }
+
+    fn from_npy(path: &Path) -> Result<Self> {
+        let ((width, height), data) = read_npy(path, Self::DESCR)?;
+        Ok(Self::new(width, height, data.into_iter().map(|value| value != 0).collect()))
+    }
+}
+
+impl Npy for Gray16Image {
+    const DESCR: &'static str = "<u2";
+
+    fn to_npy(&self, path: &Path) -> Result<()> {
+        let data: Vec<_> = self.as_raw().iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_npy(path, Self::DESCR, (self.width(), self.height()), &data)
+    }
+
+    fn from_npy(path: &Path) -> Result<Self> {
+        let ((width, height), data) = read_npy(path, Self::DESCR)?;
+        let data = data.chunks_exact(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).collect();
+        Ok(Self::from_raw(width, height, data).expect("npy data matches its shape"))
+    }
+}
+
+impl Npy for GrayImageF32 {
+    const DESCR: &'static str = "<f4";
+
+    fn to_npy(&self, path: &Path) -> Result<()> {
+        let data: Vec<_> = self.as_raw().iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_npy(path, Self::DESCR, (self.width(), self.height()), &data)
+    }
+
+    fn from_npy(path: &Path) -> Result<Self> {
+        let ((width, height), data) = read_npy(path, Self::DESCR)?;
+        let data = data.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().expect("4 bytes"))).collect();
+        Ok(Self::from_raw(width, height, data).expect("npy data matches its shape"))
+    }
+}
+
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00"; // Version 1.0 of the format
+
+fn write_npy(path: &Path, descr: &str, (width, height): (u32, u32), data: &[u8]) -> Result<()> {
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}", descr, height, width);
+    // The data starts at a multiple of 64 bytes, after the magic string, the length of the header and a newline
+    let length = MAGIC.len() + 2 + header.len() + 1;
+    header.extend(std::iter::repeat_n(' ', length.next_multiple_of(64) - length));
+    header.push('\n');
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(u16::try_from(header.len()).ok().context("npy header is too long")?.to_le_bytes());
+    bytes.extend(header.as_bytes());
+    bytes.extend_from_slice(data);
+    fs::write(path, bytes).map_err(|source| KlexError::Io { path: path.into(), source })
+}
+
+/// Reads a C ordered two dimensional array of values of a single type, as written by `write_npy`. The values are
+/// returned as their bytes.
+fn read_npy(path: &Path, descr: &str) -> Result<((u32, u32), Vec<u8>)> {
+    let bytes = fs::read(path).map_err(|source| KlexError::Io { path: path.into(), source })?;
+    parse_npy(&bytes, descr).context(format!("Failed to read npy file {:?}", path))
+}
+
+fn parse_npy(bytes: &[u8], descr: &str) -> Result<((u32, u32), Vec<u8>)> {
+    ensure!(bytes.starts_with(&MAGIC[..6]), "Not an npy file");
+    ensure!(bytes.get(6) == Some(&1), "Only version 1 of the npy format is supported");
+    let length = bytes.get(8..10).context("Missing header")?;
+    let length = usize::from(u16::from_le_bytes([length[0], length[1]]));
+    let header = bytes.get(10..10 + length).and_then(|header| std::str::from_utf8(header).ok()).context("Bad header")?;
+    // The header is a Python dictionary literal. Instead of parsing it, look for the values klex writes.
+    let value = |key: &str| {
+        let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+        Some(header[start..].trim_start())
+    };
+    ensure!(
+        value("descr").is_some_and(|value| value.starts_with(&format!("'{}'", descr))),
+        "Expected data type {}",
+        descr
+    );
+    ensure!(value("fortran_order").is_some_and(|value| value.starts_with("False")), "Only C order is supported");
+    let shape = value("shape").and_then(|value| value.strip_prefix('(')?.split(')').next()).context("Missing shape")?;
+    let dimensions: Vec<_> = shape.split(',').map(str::trim).filter(|size| !size.is_empty()).collect();
+    let (height, width) = match dimensions[..] {
+        [height, width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+        _ => bail!("Expected a two dimensional array, not one of shape ({})", shape),
+    };
+    let data = &bytes[10 + length..];
+    let item_size: usize = descr[2..].parse().ok().context("Bad data type")?; // E.g. "<u2" for two byte values
+    let expected = width as usize * height as usize * item_size;
+    ensure!(data.len() == expected, "Data doesn't match the shape ({})", shape);
+    Ok(((width, height), data.to_vec()))
+}
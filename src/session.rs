@@ -0,0 +1,58 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Context, KlexError, Result},
+    recipe::Bindings,
+    shortcuts::Shortcuts,
+    util,
+};
+
+/// What is remembered from one run of the application to the next
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub recent: Vec<PathBuf>,    // Images that were opened, most recent first
+    pub recipe: Option<PathBuf>, // The graph that was worked on last, which is restored on startup
+    pub bindings: Bindings,      // Values for the placeholders of the recipe
+    pub shortcuts: Shortcuts,
+}
+
+impl Session {
+    pub const RECENT_LENGTH: usize = 10;
+
+    /// The file in the configuration directory of the platform that the session is kept in, if there is one
+    pub fn default_path() -> Option<PathBuf> {
+        let directories = directories_next::ProjectDirs::from("", "", "Klex")?;
+        Some(directories.config_dir().join("session.ron"))
+    }
+
+    /// Where the graph of the session stored at `path` is saved to when the application closes
+    pub fn recipe_path(path: &Path) -> PathBuf {
+        path.with_file_name("last-recipe.ron")
+    }
+
+    /// Reads a session. Since a session is only a convenience, a missing or corrupted file results in an empty one.
+    pub fn load(path: &Path) -> Self {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        ron::from_str(&text).unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        util::write_atomically(path, text.as_bytes())
+            .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+            .context(format!("Failed to write session {:?}", path))
+    }
+
+    /// Puts an image at the front of the recently opened ones
+    pub fn add_recent(&mut self, path: PathBuf) {
+        self.recent.retain(|recent| *recent != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(Self::RECENT_LENGTH);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt, str::FromStr};
+
+use iced_native::keyboard::{KeyCode, Modifiers};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{bail, KlexError, Result};
+
+/// What a keyboard shortcut does
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    AddLayer, // Opens the menu for adding layers
+    Cancel,   // Closes menus and overlays, and stops picking colors
+    DeleteLayer,
+    DuplicateLayer,
+    Undo,
+    Redo,
+    ZoomToFit,
+    Export,
+    Copy, // The output of the selected layer, to the clipboard
+    Open,
+    ShowShortcuts,
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::AddLayer => "Add a layer",
+            Action::Cancel => "Close menus and cancel picking",
+            Action::DeleteLayer => "Delete the selected layer",
+            Action::DuplicateLayer => "Duplicate the selected layer",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::ZoomToFit => "Fit the image into the viewport",
+            Action::Export => "Export the selected layer",
+            Action::Copy => "Copy the selected layer to the clipboard",
+            Action::Open => "Open images",
+            Action::ShowShortcuts => "Show keyboard shortcuts",
+        }
+    }
+}
+
+/// A key along with the modifiers held down with it, written like "Ctrl+Shift+Z". Keys are named after `KeyCode`,
+/// except for letters and digits, which are written as they are.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyCombo {
+    key: String, // Debug name of the `KeyCode`
+    control: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl KeyCombo {
+    /// Whether a key press matches the combination. The logo key is ignored.
+    pub fn matches(&self, key_code: KeyCode, modifiers: Modifiers) -> bool {
+        (modifiers.control, modifiers.alt, modifiers.shift) == (self.control, self.alt, self.shift)
+            && format!("{:?}", key_code) == self.key
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = KlexError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut parts: Vec<_> = text.split('+').map(str::trim).collect();
+        let key = match parts.pop() {
+            Some(key) if !key.is_empty() => key,
+            _ => bail!("Shortcut {:?} has no key", text),
+        };
+        let mut combo = Self {
+            key: match key.chars().collect::<Vec<_>>().as_slice() {
+                [letter] if letter.is_ascii_alphabetic() => letter.to_ascii_uppercase().to_string(),
+                [digit] if digit.is_ascii_digit() => format!("Key{}", digit),
+                _ => key.to_string(),
+            },
+            control: false,
+            alt: false,
+            shift: false,
+        };
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => combo.control = true,
+                "alt" => combo.alt = true,
+                "shift" => combo.shift = true,
+                _ => bail!("Unknown modifier {:?} in shortcut {:?}", modifier, text),
+            }
+        }
+        Ok(combo)
+    }
+}
+
+impl TryFrom<String> for KeyCombo {
+    type Error = KlexError;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [(self.control, "Ctrl+"), (self.alt, "Alt+"), (self.shift, "Shift+")] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match self.key.strip_prefix("Key") {
+            Some(digit) if digit.len() == 1 => f.write_str(digit),
+            _ => f.write_str(&self.key),
+        }
+    }
+}
+
+impl From<KeyCombo> for String {
+    fn from(combo: KeyCombo) -> Self {
+        combo.to_string()
+    }
+}
+
+/// Which key combinations trigger which actions. The first binding of a key combination wins. Stored in the
+/// session, where it replaces the defaults as a whole.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Shortcuts {
+    bindings: Vec<(KeyCombo, Action)>,
+}
+
+impl Shortcuts {
+    pub fn new(bindings: Vec<(KeyCombo, Action)>) -> Self {
+        Self { bindings }
+    }
+
+    /// The action bound to a key press, if any
+    pub fn action(&self, key_code: KeyCode, modifiers: Modifiers) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(combo, _)| combo.matches(key_code, modifiers))
+            .map(|&(_, action)| action)
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&KeyCombo, Action)> {
+        self.bindings.iter().map(|(combo, action)| (combo, *action))
+    }
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let bindings = [
+            ("Shift+A", Action::AddLayer),
+            ("Escape", Action::Cancel),
+            ("Delete", Action::DeleteLayer),
+            ("Ctrl+D", Action::DuplicateLayer),
+            ("Ctrl+Z", Action::Undo),
+            ("Ctrl+Shift+Z", Action::Redo),
+            ("Ctrl+Y", Action::Redo),
+            ("Ctrl+0", Action::ZoomToFit),
+            ("Ctrl+E", Action::Export),
+            ("Ctrl+Shift+C", Action::Copy),
+            ("Ctrl+O", Action::Open),
+            ("F1", Action::ShowShortcuts),
+        ];
+        let bindings = bindings
+            .into_iter()
+            .map(|(combo, action)| (combo.parse().expect("Default shortcuts are valid"), action))
+            .collect();
+        Self::new(bindings)
+    }
+}
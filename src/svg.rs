@@ -0,0 +1,109 @@
+//! Writes geometry elements as SVG vector graphics, optionally over the image they were found in, e.g. for reports.
+//! Only the few elements needed for that are written, so no SVG library is involved.
+
+use std::{fmt::Write, path::Path};
+
+use image::{DynamicImage, GenericImageView};
+
+use crate::{
+    entity::{Element, Geometry},
+    error::{KlexError, Result},
+    util,
+};
+
+/// An SVG document, as put out by the `ExportVector` layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Svg {
+    pub text: String,
+}
+
+impl Element for Svg {
+    const NAME: &'static str = "Svg";
+}
+
+impl Svg {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        util::write_atomically(path, self.text.as_bytes())
+            .map_err(|source| KlexError::Io { path: path.into(), source })
+    }
+}
+
+/// How shapes are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    pub color: [u8; 4], // RGBA, of lines and outlines as well as of filled points
+    pub width: f32,     // Of lines and outlines, and the diameter of points, in pixels of the document
+    pub scale: f32,     // Pixels of the document per pixel of the image the shapes were found in
+}
+
+/// Draws the shapes over `background`, which is embedded as a PNG. Without a background, the document reaches from
+/// the origin to the shapes furthest to the right and bottom.
+pub fn render(geometry: &Geometry, background: Option<&DynamicImage>, style: &Style) -> Result<Svg> {
+    let (width, height) = match background {
+        Some(image) => (image.width() as f32, image.height() as f32),
+        None => geometry.extent(),
+    };
+    let (width, height) = (width * style.scale, height * style.scale);
+    let scaled = |(x, y): (f32, f32)| (x * style.scale, y * style.scale);
+
+    let mut text = String::new();
+    writeln!(
+        text,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    )?;
+    if let Some(image) = background {
+        let mut png = Vec::new();
+        image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+        writeln!(
+            text,
+            r#"  <image width="{}" height="{}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            width,
+            height,
+            base64(&png)
+        )?;
+    }
+
+    let [red, green, blue, alpha] = style.color;
+    let color = format!("#{:02x}{:02x}{:02x}", red, green, blue);
+    let opacity = f32::from(alpha) / 255.0;
+    writeln!(
+        text,
+        r#"  <g fill="none" stroke="{}" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round">"#,
+        color, opacity, style.width
+    )?;
+    for &(start, end) in &geometry.lines {
+        let ((x1, y1), (x2, y2)) = (scaled(start), scaled(end));
+        writeln!(text, r#"    <line x1="{}" y1="{}" x2="{}" y2="{}"/>"#, x1, y1, x2, y2)?;
+    }
+    for polygon in &geometry.polygons {
+        let points: Vec<_> = polygon.iter().map(|&point| scaled(point)).map(|(x, y)| format!("{},{}", x, y)).collect();
+        writeln!(text, r#"    <polygon points="{}"/>"#, points.join(" "))?;
+    }
+    writeln!(text, "  </g>")?;
+    writeln!(text, r#"  <g fill="{}" fill-opacity="{}">"#, color, opacity)?;
+    for &point in &geometry.points {
+        let (x, y) = scaled(point);
+        writeln!(text, r#"    <circle cx="{}" cy="{}" r="{}"/>"#, x, y, style.width / 2.0)?;
+    }
+    writeln!(text, "  </g>")?;
+    writeln!(text, "</svg>")?;
+    Ok(Svg { text })
+}
+
+/// Standard base64 with padding, as used in data URLs
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0_u32, |group, (i, &byte)| group | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
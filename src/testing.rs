@@ -0,0 +1,378 @@
+//! Helpers for testing and benchmarking layers, also for crates implementing layers of their own
+
+use image::{GrayImage, ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
+
+use crate::entity::Gray16Image;
+
+/// Synthetic images of any size, for tests and benchmarks that shouldn't depend on image files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestPattern {
+    Gradient,          // From black on the left to white on the right
+    Checkerboard(u32), // Black and white squares of the given size, black at the top left
+    Noise(u64),        // Random values, which are the same for the same seed
+}
+
+impl TestPattern {
+    pub fn gray(self, width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([(self.value(x, y, width) * 255.0).round() as u8]))
+    }
+
+    pub fn gray16(self, width: u32, height: u32) -> Gray16Image {
+        Gray16Image::from_fn(width, height, |x, y| Luma([(self.value(x, y, width) * 65535.0).round() as u16]))
+    }
+
+    /// Opaque, with the pattern in red and green and its inverse in blue, so that the channels can be told apart
+    pub fn rgba(self, width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let value = (self.value(x, y, width) * 255.0).round() as u8;
+            Rgba([value, value, 255 - value, 255])
+        })
+    }
+
+    /// The value of a pixel from 0 to 1
+    fn value(self, x: u32, y: u32, width: u32) -> f64 {
+        match self {
+            Self::Gradient => f64::from(x) / f64::from(width.saturating_sub(1).max(1)),
+            Self::Checkerboard(size) => f64::from((x / size.max(1) + y / size.max(1)) % 2),
+            Self::Noise(seed) => {
+                // SplitMix64 of the seed and the position
+                let mut hash = seed ^ (u64::from(y) << 32 | u64::from(x));
+                hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                hash ^= hash >> 31;
+                (hash >> 11) as f64 / (1_u64 << 53) as f64
+            }
+        }
+    }
+}
+
+/// Asserts that two images have the same size and that none of their channels differ by more than `tolerance`, e.g.
+/// for the results of lossy operations. Panics with the first pixel that differs too much.
+#[track_caller]
+pub fn assert_images_close<P>(a: &ImageBuffer<P, Vec<u8>>, b: &ImageBuffer<P, Vec<u8>>, tolerance: u8)
+where
+    P: Pixel<Subpixel = u8> + std::fmt::Debug + 'static,
+{
+    assert_eq!(a.dimensions(), b.dimensions(), "Images have different sizes");
+    let differing = a.enumerate_pixels().zip(b.pixels()).find(|((_, _, left), right)| {
+        left.channels().iter().zip(right.channels()).any(|(left, right)| left.abs_diff(*right) > tolerance)
+    });
+    if let Some(((x, y, left), right)) = differing {
+        panic!("Pixels at ({}, {}) differ by more than {}: {:?} and {:?}", x, y, tolerance, left, right);
+    }
+}
+
+/// Golden output tests of recipes: the outputs of a recipe are compared with those stored in a manifest, to notice
+/// when a change, e.g. an upgrade of Klex, changes what a recipe computes
+pub mod golden {
+    use std::{
+        any::Any,
+        collections::BTreeMap,
+        env, fmt, fs,
+        path::{Path, PathBuf},
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::TestPattern;
+    use crate::{
+        cache,
+        entity::{self, BinaryImage, Gray16Image, GrayImageF32},
+        error::{bail, Context, KlexError, Result},
+        parameter::ParamValue,
+        recipe::{self, ParamOverrides, Recipe},
+        registry::LayerRegistry,
+        util,
+    };
+
+    /// Environment variable that makes `Mode::from_env` regenerate manifests, if it's set to anything but "0"
+    pub const REGENERATE: &str = "KLEX_REGENERATE_GOLDEN";
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Mode {
+        Compare,    // Report outputs that differ from the manifest
+        Regenerate, // Store the outputs as the new manifest
+    }
+
+    impl Mode {
+        /// `Regenerate` if `REGENERATE` is set, so that manifests can be updated without changing any tests
+        pub fn from_env() -> Self {
+            match env::var(REGENERATE) {
+                Ok(value) if !value.is_empty() && value != "0" => Self::Regenerate,
+                _ => Self::Compare,
+            }
+        }
+    }
+
+    /// What a manifest keeps of an output
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct GoldenOutput {
+        pub element: String, // Name of the element, e.g. "GrayImage"
+        pub dimensions: Option<(u32, u32)>,
+        pub hash: Option<u64>, // See `entity::content_hash`. Outputs that aren't images have none.
+    }
+
+    impl GoldenOutput {
+        pub fn of(output: &dyn Any) -> Self {
+            Self {
+                element: entity::element_name(output).unwrap_or("unknown").to_string(),
+                dimensions: entity::dimensions(output),
+                hash: entity::content_hash(output),
+            }
+        }
+    }
+
+    impl fmt::Display for GoldenOutput {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.dimensions {
+                Some((width, height)) => write!(f, "{} of {}x{}", self.element, width, height),
+                None => write!(f, "{}", self.element),
+            }
+        }
+    }
+
+    /// The outputs of a recipe by node name, stored as RON. The pixels of images are stored next to it, in a
+    /// directory named like the manifest with the extension "pixels", so that differences can be measured.
+    #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Manifest {
+        pub outputs: BTreeMap<String, GoldenOutput>,
+    }
+
+    impl Manifest {
+        pub fn load(path: &Path) -> Result<Self> {
+            let text = fs::read_to_string(path)
+                .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+                .context(format!("Failed to read golden manifest {:?}, set {} to create it", path, REGENERATE))?;
+            ron::from_str(&text).context(format!("Failed to parse golden manifest {:?}", path))
+        }
+
+        pub fn save(&self, path: &Path) -> Result<()> {
+            let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+            util::write_atomically(path, text.as_bytes())
+                .map_err(|source| KlexError::Io { path: path.to_path_buf(), source })
+                .context(format!("Failed to write golden manifest {:?}", path))
+        }
+    }
+
+    /// How much the pixels of an output changed, over the values of all channels
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct PixelDifference {
+        pub differing: usize, // Number of values that changed
+        pub max: f64,
+        pub mean: f64, // Over all values, including those that didn't change
+    }
+
+    impl PixelDifference {
+        /// The difference between two images of the same kind and size, if they are images
+        pub fn between(expected: &dyn Any, found: &dyn Any) -> Option<Self> {
+            let (expected, found) = (samples(expected)?, samples(found)?);
+            if expected.len() != found.len() {
+                return None;
+            }
+            let differences: Vec<f64> = expected.iter().zip(&found).map(|(a, b)| (a - b).abs()).collect();
+            Some(Self {
+                differing: differences.iter().filter(|&&difference| difference > 0.0).count(),
+                max: differences.iter().copied().fold(0.0, f64::max),
+                mean: differences.iter().sum::<f64>() / differences.len().max(1) as f64,
+            })
+        }
+    }
+
+    /// An output that doesn't match the manifest
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Divergence {
+        Missing {
+            node: String,
+        }, // In the manifest, but the recipe didn't compute it
+        Unexpected {
+            node: String,
+        }, // Computed, but not in the manifest
+        Changed {
+            node: String,
+            expected: GoldenOutput,
+            found: GoldenOutput,
+            difference: Option<PixelDifference>, // If the stored pixels could be compared with the new ones
+        },
+    }
+
+    impl Divergence {
+        pub fn node(&self) -> &str {
+            match self {
+                Self::Missing { node } | Self::Unexpected { node } | Self::Changed { node, .. } => node,
+            }
+        }
+    }
+
+    impl fmt::Display for Divergence {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Missing { node } => write!(f, "Node {:?} has no output anymore", node),
+                Self::Unexpected { node } => write!(f, "Node {:?} isn't in the manifest", node),
+                Self::Changed { node, difference: Some(difference), .. } => write!(
+                    f,
+                    "Node {:?} changed: {} values differ, by at most {} and by {:.4} on average",
+                    node, difference.differing, difference.max, difference.mean
+                ),
+                Self::Changed { node, expected, found, .. } => {
+                    write!(f, "Node {:?} changed from a {} to a {}", node, expected, found)
+                }
+            }
+        }
+    }
+
+    /// A recipe together with its inputs, whose outputs are compared with a manifest. Input files that were generated
+    /// for it are removed along with it.
+    pub struct GoldenTest<'a> {
+        recipe: &'a Recipe,
+        registry: Option<&'a LayerRegistry>, // The built-in layers if there is none
+        overrides: ParamOverrides,
+        generated: Vec<PathBuf>,
+    }
+
+    impl<'a> GoldenTest<'a> {
+        pub fn new(recipe: &'a Recipe) -> Self {
+            Self {
+                recipe,
+                registry: None,
+                overrides: ParamOverrides::new(),
+                generated: Vec::new(),
+            }
+        }
+
+        pub fn with_registry(mut self, registry: &'a LayerRegistry) -> Self {
+            self.registry = Some(registry);
+            self
+        }
+
+        /// Overrides a parameter of the recipe, addressed as in `ParamOverrides`
+        pub fn set(mut self, parameter: &str, value: ParamValue) -> Self {
+            self.overrides.set(parameter, value);
+            self
+        }
+
+        pub fn bind(mut self, placeholder: &str, value: ParamValue) -> Self {
+            self.overrides.bind(placeholder, value);
+            self
+        }
+
+        /// Reads an input from a file that comes with the tests, through a path parameter like `"input.path"`
+        pub fn fixture(self, parameter: &str, path: impl Into<PathBuf>) -> Self {
+            self.set(parameter, ParamValue::Path(path.into()))
+        }
+
+        /// Reads an input from a PNG file with a test pattern, which is written to the temporary directory
+        pub fn generated(mut self, parameter: &str, pattern: TestPattern, (width, height): (u32, u32)) -> Result<Self> {
+            static GENERATED: AtomicUsize = AtomicUsize::new(0);
+
+            let number = GENERATED.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("klex-golden-{}-{}.png", process::id(), number));
+            pattern.rgba(width, height).save(&path).context(format!("Failed to write input {:?}", path))?;
+            self.generated.push(path.clone());
+            Ok(self.fixture(parameter, path))
+        }
+
+        /// Runs the recipe and compares the outputs of its nodes without children with the manifest at `path`. With
+        /// `Mode::Regenerate`, the outputs are stored there instead, and nothing diverges.
+        pub fn compare(&self, path: &Path, mode: Mode) -> Result<Vec<Divergence>> {
+            let outputs = match self.registry {
+                Some(registry) => recipe::run_with_registry(self.recipe, &self.overrides, registry)?,
+                None => recipe::run(self.recipe, &self.overrides)?,
+            };
+            let pixels = pixel_directory(path);
+            if mode == Mode::Regenerate {
+                if pixels.exists() {
+                    fs::remove_dir_all(&pixels).map_err(|source| KlexError::Io { path: pixels.clone(), source })?;
+                }
+                fs::create_dir_all(&pixels).map_err(|source| KlexError::Io { path: pixels.clone(), source })?;
+                let mut manifest = Manifest::default();
+                for (node, output) in &outputs {
+                    let golden = GoldenOutput::of(&*output.value);
+                    if let Some(extension) = cache::file_extension(&golden.element) {
+                        cache::write_element(&*output.value, &pixel_file(&pixels, node, extension))?;
+                    }
+                    manifest.outputs.insert(node.clone(), golden);
+                }
+                manifest.save(path)?;
+                return Ok(Vec::new());
+            }
+
+            let manifest = Manifest::load(path)?;
+            let mut divergences = Vec::new();
+            for (node, expected) in &manifest.outputs {
+                let output = match outputs.get(node) {
+                    Some(output) => &*output.value,
+                    None => {
+                        divergences.push(Divergence::Missing { node: node.clone() });
+                        continue;
+                    }
+                };
+                let found = GoldenOutput::of(output);
+                if found != *expected {
+                    let difference = (found.element == expected.element && found.dimensions == expected.dimensions)
+                        .then(|| load_pixels(&pixels, node, &expected.element))
+                        .flatten()
+                        .and_then(|stored| PixelDifference::between(&*stored, output));
+                    let expected = expected.clone();
+                    divergences.push(Divergence::Changed { node: node.clone(), expected, found, difference });
+                }
+            }
+            let unexpected = outputs.keys().filter(|node| !manifest.outputs.contains_key(*node));
+            divergences.extend(unexpected.map(|node| Divergence::Unexpected { node: node.clone() }));
+            Ok(divergences)
+        }
+
+        /// Like `compare`, but fails with all divergences
+        pub fn check(&self, path: &Path, mode: Mode) -> Result<()> {
+            let divergences = self.compare(path, mode)?;
+            if !divergences.is_empty() {
+                let lines: Vec<String> = divergences.iter().map(|divergence| divergence.to_string()).collect();
+                bail!("Outputs differ from golden manifest {:?}:\n{}", path, lines.join("\n"));
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for GoldenTest<'_> {
+        fn drop(&mut self) {
+            for path in &self.generated {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+
+    /// Where the pixels of the outputs of a manifest are stored
+    fn pixel_directory(manifest: &Path) -> PathBuf {
+        manifest.with_extension("pixels")
+    }
+
+    /// The file of the output of a node. Node names may contain characters that file names can't.
+    fn pixel_file(directory: &Path, node: &str, extension: &str) -> PathBuf {
+        let name: String = node.chars().map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' }).collect();
+        directory.join(format!("{}.{}", name, extension))
+    }
+
+    fn load_pixels(directory: &Path, node: &str, element: &str) -> Option<Box<dyn Any + Send + Sync>> {
+        let extension = cache::file_extension(element)?;
+        let (_, read) = cache::FILES.iter().find(|(file_extension, _)| *file_extension == extension)?;
+        read(&pixel_file(directory, node, extension)).ok()
+    }
+
+    /// The values of all channels of an image, row by row
+    fn samples(image: &dyn Any) -> Option<Vec<f64>> {
+        if let Some(image) = image.downcast_ref::<image::RgbaImage>() {
+            Some(image.as_raw().iter().map(|&value| f64::from(value)).collect())
+        } else if let Some(image) = image.downcast_ref::<image::GrayImage>() {
+            Some(image.as_raw().iter().map(|&value| f64::from(value)).collect())
+        } else if let Some(image) = image.downcast_ref::<Gray16Image>() {
+            Some(image.as_raw().iter().map(|&value| f64::from(value)).collect())
+        } else if let Some(image) = image.downcast_ref::<GrayImageF32>() {
+            Some(image.as_raw().iter().map(|&value| f64::from(value)).collect())
+        } else {
+            let image = image.downcast_ref::<BinaryImage>()?;
+            Some(image.data().iter().map(|&pixel| f64::from(u8::from(pixel))).collect())
+        }
+    }
+}
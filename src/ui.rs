@@ -1,11 +1,2052 @@
-use std::time::{Instant};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-struct UI {
-    backend: (),
-    target_refresh_rate: u64,
-    
+use iced::{
+    button, container, executor, image::Handle, scrollable, slider, text_input, Align, Application, Background, Button,
+    Checkbox, Clipboard, Color, Column, Command, Container, Element, Image, Length, Row, Scrollable, Slider,
+    Subscription, Text, TextInput,
+};
+use iced_futures::{
+    futures::{channel::mpsc, future, stream::BoxStream, StreamExt},
+    subscription::Recipe,
+};
+use iced_native::{event, keyboard, window};
+use image::{GrayAlphaImage, GrayImage, Luma, LumaA, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    backend::{Data, Supervisor},
+    entity::{self, BinaryImage, Geometry, Gray16Image, Histogram, Rect, Sample, Stroke},
+    graph_editor::{EditorMessage, EditorState, GraphEditor},
+    histogram::{ChartMessage, ChartState, HistogramChart},
+    layer_graph::{ColorTag, GraphDelta, GraphSnapshot, InteractiveLayerGraph, NodeLayout, NodeSnapshot},
+    layer_menu::{LayerMenu, MenuMessage},
+    logging::LogRecord,
+    parameter::{ParamMap, ParamSpec, ParamValue},
+    recipe::{self, Bindings},
+    registry::LayerInfo,
+    parameter_panel::{PanelMessage, ParameterPanel},
+    session::Session,
+    shortcuts::Action,
+    viewport::{BrushEvent, Hover, ImageViewport, ViewState},
+};
+
+/// Messages from the user interface to the backend
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    AddLayer {
+        kind: String,
+        parameters: ParamMap,
+        inputs: Vec<NodeIndex>,
+    },
+    InsertLayer {
+        kind: String,
+        parameters: ParamMap,             // Overriding the defaults
+        connect_after: Option<NodeIndex>, // Usually the selected layer. Ignored for input layers
+    }, // Adds a layer and selects it
+    RemoveLayer(NodeIndex),
+    DuplicateLayer(NodeIndex), // Adds an unconnected copy of a layer and selects it
+    Connect {
+        from: NodeIndex,
+        output: usize, // Output port of `from`
+        to: NodeIndex,
+        port: usize,
+    },
+    Disconnect {
+        to: NodeIndex,
+        port: usize,
+    },
+    MoveLayer {
+        node: NodeIndex,
+        position: (f32, f32),
+    }, // Only changes where the layer is drawn in the graph editor
+    SetLayout {
+        node: NodeIndex,
+        layout: NodeLayout,
+    }, // Like `MoveLayer`, for everything else about how the layer is shown
+    SetParameter {
+        node: NodeIndex,
+        name: String,
+        value: ParamValue,
+    },
+    SelectLayer(NodeIndex),
+    RequestCompute(NodeIndex),
+    RequestGraph, // Asks for a whole `Data::Graph` snapshot, after missing a delta
+    QueryPixel {
+        node: NodeIndex,
+        x: f32,
+        y: f32,
+    }, // Position in the output of the layer, as a fraction of its size
+    Paint {
+        node: NodeIndex,
+        stroke: Stroke,
+    }, // Part of a stroke on a layer that can be painted on, in pixels of its output
+    EndStroke, // The stroke being painted is done and can be undone as a whole
+    Undo,
+    Redo,
+    LoadRecipe {
+        recipe: Box<recipe::Recipe>,
+        bindings: Bindings,
+    }, // Replaces the whole graph
+    Export {
+        node: NodeIndex,
+        path: PathBuf, // Its extension picks the format
+        quality: u8,   // Of JPEGs, from 1 to 100
+    }, // Writes the full resolution output of a layer to an image file
+    CopyToClipboard(NodeIndex), // Copies the full resolution output of a layer to the system clipboard
+    Exit,
+}
+
+/// Order of the channels of the pixels in a buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra, // What `Handle::from_pixels` expects
+}
+
+impl ChannelOrder {
+    /// A buffer of pixels in this order, given pixels in RGBA order
+    fn buffer(self, pixels: impl ExactSizeIterator<Item = [u8; 4]>) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(pixels.len() * 4);
+        for [red, green, blue, alpha] in pixels {
+            buffer.extend_from_slice(&match self {
+                ChannelOrder::Rgba => [red, green, blue, alpha],
+                ChannelOrder::Bgra => [blue, green, red, alpha],
+            });
+        }
+        buffer
+    }
+}
+
+/// Conversion of images into something the user interface can display
+pub trait ImageHandle {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// The pixels as RGBA or BGRA, row by row
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8>;
+
+    fn handle(&self) -> Handle {
+        let (width, height) = self.dimensions();
+        Handle::from_pixels(width, height, self.buffer(ChannelOrder::Bgra))
+    }
+}
+
+impl ImageHandle for RgbaImage {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8> {
+        order.buffer(self.pixels().map(|pixel| pixel.0))
+    }
+}
+
+impl ImageHandle for GrayImage {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8> {
+        order.buffer(self.pixels().map(|&Luma([value])| [value, value, value, u8::MAX]))
+    }
+}
+
+impl ImageHandle for GrayAlphaImage {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8> {
+        order.buffer(self.pixels().map(|&LumaA([value, alpha])| [value, value, value, alpha]))
+    }
+}
+
+impl ImageHandle for Gray16Image {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8> {
+        order.buffer(self.pixels().map(|&Luma([value])| {
+            let value = value.to_u8();
+            [value, value, value, u8::MAX]
+        }))
+    }
+}
+
+impl ImageHandle for BinaryImage {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn buffer(&self, order: ChannelOrder) -> Vec<u8> {
+        order.buffer(self.data().iter().map(|&pixel| match pixel {
+            true => [u8::MAX; 4],
+            false => [u8::MIN, u8::MIN, u8::MIN, u8::MAX],
+        }))
+    }
+}
+
+/// Converts a known image element for displaying it, without going through an RGBA copy of it first
+pub fn image_handle(element: &dyn std::any::Any) -> Option<Handle> {
+    if let Some(image) = element.downcast_ref::<RgbaImage>() {
+        Some(image.handle())
+    } else if let Some(image) = element.downcast_ref::<GrayImage>() {
+        Some(image.handle())
+    } else if let Some(image) = element.downcast_ref::<GrayAlphaImage>() {
+        Some(image.handle())
+    } else if let Some(image) = element.downcast_ref::<Gray16Image>() {
+        Some(image.handle())
+    } else {
+        element.downcast_ref::<BinaryImage>().map(ImageHandle::handle)
+    }
+}
+
+/// What the user interface knows about a layer of the graph
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerSummary {
+    pub name: String,
+    pub kind: String,
+    pub inputs: Vec<(NodeIndex, usize, usize)>, // Parent layers with their output port and the input port it feeds
+    pub specs: Vec<ParamSpec>,                  // Empty if the registry doesn't know the kind
+    pub parameters: ParamMap,
+    pub layout: NodeLayout, // In the graph editor
+}
+
+/// A lightweight copy of the structure of the graph, which lives on the backend thread. It is kept up to date with
+/// the data the backend sends.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphMirror {
+    layers: BTreeMap<NodeIndex, LayerSummary>,
+    selected: Option<NodeIndex>,
+    sequence: Option<u64>, // Of the last graph snapshot or delta applied
+    out_of_date: bool,     // A delta was missed, so the following ones can't be applied until the next snapshot
+    requested: bool,       // The next snapshot was asked for
+}
+
+impl GraphMirror {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes in a change of the graph. Data that doesn't change the structure is ignored.
+    pub fn apply(&mut self, data: &Data) {
+        match data {
+            Data::LayerAdded {
+                node,
+                kind,
+                name,
+                inputs,
+            } => {
+                let layer = LayerSummary {
+                    name: name.clone(),
+                    kind: kind.clone(),
+                    inputs: inputs.iter().zip(0..).map(|(&input, port)| (input, 0, port)).collect(),
+                    specs: Vec::new(),
+                    parameters: ParamMap::new(),
+                    layout: NodeLayout::default(),
+                };
+                self.layers.insert(*node, layer);
+            }
+            Data::LayerRemoved(node) => {
+                self.layers.remove(node);
+                for layer in self.layers.values_mut() {
+                    layer.inputs.retain(|&(parent, _, _)| parent != *node);
+                }
+            }
+            Data::Connected { from, output, to, port } => {
+                if let Some(layer) = self.layers.get_mut(to) {
+                    layer.inputs.retain(|&(_, _, connected_port)| connected_port != *port);
+                    layer.inputs.push((*from, *output, *port));
+                    layer.inputs.sort_by_key(|&(_, _, port)| port);
+                }
+            }
+            Data::Disconnected { to, port } => {
+                if let Some(layer) = self.layers.get_mut(to) {
+                    layer.inputs.retain(|&(_, _, connected_port)| connected_port != *port);
+                }
+            }
+            Data::LayerMoved { node, position } => {
+                if let Some(layer) = self.layers.get_mut(node) {
+                    layer.layout.position = Some(*position);
+                }
+            }
+            Data::LayerSelected(node) => self.selected = Some(*node),
+            Data::Graph { sequence, snapshot } => {
+                self.reconcile(snapshot);
+                self.sequence = Some(*sequence);
+                self.out_of_date = false;
+                self.requested = false;
+            }
+            Data::GraphDelta { sequence, delta } => match self.sequence {
+                Some(current) if *sequence <= current => (), // Applied already
+                Some(current) if *sequence == current + 1 && !self.out_of_date => {
+                    self.apply_delta(delta);
+                    self.sequence = Some(*sequence);
+                }
+                _ => self.out_of_date = true,
+            },
+            Data::Parameters { node, specs, values } => {
+                if let Some(layer) = self.layers.get_mut(node) {
+                    layer.specs = specs.clone();
+                    layer.parameters = values.clone();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Catches up with a snapshot of the whole graph. Layers it doesn't know yet are added without parameters, which
+    /// arrive separately.
+    fn reconcile(&mut self, snapshot: &GraphSnapshot) {
+        let nodes: BTreeSet<_> = snapshot.nodes.iter().map(|node| node.node).collect();
+        self.layers.retain(|node, _| nodes.contains(node));
+        for node in &snapshot.nodes {
+            let layer = self.update_layer(node);
+            let inputs = snapshot.edges.iter().filter(|&&(_, _, to, _)| to == node.node);
+            layer.inputs = inputs.map(|&(from, output, _, port)| (from, output, port)).collect();
+            layer.inputs.sort_by_key(|&(_, _, port)| port);
+        }
+        self.selected = snapshot.selected;
+    }
+
+    /// Takes in the changes since the previous snapshot or delta, touching only the layers that changed
+    fn apply_delta(&mut self, delta: &GraphDelta) {
+        for node in &delta.removed {
+            self.layers.remove(node);
+        }
+        for node in delta.changed.iter().chain(&delta.added) {
+            self.update_layer(node);
+        }
+        for &(from, output, to, port) in &delta.removed_edges {
+            if let Some(layer) = self.layers.get_mut(&to) {
+                layer.inputs.retain(|&input| input != (from, output, port));
+            }
+        }
+        for &(from, output, to, port) in &delta.added_edges {
+            if let Some(layer) = self.layers.get_mut(&to) {
+                layer.inputs.retain(|&(_, _, connected_port)| connected_port != port);
+                layer.inputs.push((from, output, port));
+                layer.inputs.sort_by_key(|&(_, _, port)| port);
+            }
+        }
+        self.selected = delta.selected;
+    }
+
+    /// Updates what is known about a layer from its snapshot, adding it without parameters if it isn't known yet.
+    /// Its inputs are left alone.
+    fn update_layer(&mut self, node: &NodeSnapshot) -> &mut LayerSummary {
+        let layer = self.layers.entry(node.node).or_insert_with(|| LayerSummary {
+            name: String::new(),
+            kind: String::new(),
+            inputs: Vec::new(),
+            specs: Vec::new(),
+            parameters: ParamMap::new(),
+            layout: NodeLayout::default(),
+        });
+        layer.name.clone_from(&node.name);
+        layer.kind.clone_from(&node.kind);
+        let position = node.layout.position.or(layer.layout.position);
+        layer.layout = NodeLayout {
+            position,
+            ..node.layout.clone()
+        };
+        layer
+    }
+
+    /// Whether the next graph snapshot should be asked for with `Event::RequestGraph`, because a delta was missed.
+    /// Only says so once until the snapshot arrives.
+    pub fn request_snapshot(&mut self) -> bool {
+        let request = self.out_of_date && !self.requested;
+        self.requested |= request;
+        request
+    }
+
+    /// All layers, ordered by their index
+    pub fn layers(&self) -> impl Iterator<Item = (NodeIndex, &LayerSummary)> {
+        self.layers.iter().map(|(&node, layer)| (node, layer))
+    }
+
+    pub fn layer(&self, node: NodeIndex) -> Option<&LayerSummary> {
+        self.layers.get(&node)
+    }
+
+    pub fn selected(&self) -> Option<NodeIndex> {
+        self.selected.filter(|node| self.layers.contains_key(node))
+    }
+
+    /// Where the pipeline leading to a layer starts, found by following the first input of each layer
+    pub fn source(&self, node: NodeIndex) -> NodeIndex {
+        let mut source = node;
+        // Bounded, in case the backend ever sends a cycle
+        for _ in 0..self.layers.len() {
+            match self.layers.get(&source).and_then(|layer| layer.inputs.first()) {
+                Some(&(parent, _, _)) => source = parent,
+                None => break,
+            }
+        }
+        source
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The file that the pipeline leading to the selected layer starts with. Otherwise, that of the first input layer
+    /// with a file.
+    pub fn primary_input(&self) -> Option<&Path> {
+        let path = |node| {
+            let layer = self.layers.get(&node).filter(|layer| layer.kind == "InputFile")?;
+            match layer.parameters.get("path")? {
+                ParamValue::Path(path) => Some(path.as_path()),
+                _ => None,
+            }
+        };
+        let selected = self.selected().and_then(|node| path(self.source(node)));
+        selected.or_else(|| self.layers.keys().find_map(|&node| path(node)))
+    }
+}
+
+/// Layers whose last computation failed, kept up to date with the data the backend sends. A layer counts as failed
+/// until it is computed successfully or removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Failures {
+    errors: BTreeMap<NodeIndex, String>,
+    dismissed: BTreeSet<NodeIndex>, // Failures the user has seen. They are still highlighted, but not reported
+}
+
+impl Failures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes in the result of a computation or the removal of a layer. Other data is ignored.
+    pub fn apply(&mut self, data: &Data) {
+        match data {
+            Data::ComputeFailed { node, error } => {
+                self.errors.insert(*node, error.clone());
+                self.dismissed.remove(node);
+            }
+            Data::ComputeFinished { node, .. } | Data::LayerRemoved(node) => {
+                self.errors.remove(node);
+                self.dismissed.remove(node);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn is_failed(&self, node: NodeIndex) -> bool {
+        self.errors.contains_key(&node)
+    }
+
+    pub fn failed(&self) -> BTreeSet<NodeIndex> {
+        self.errors.keys().copied().collect()
+    }
+
+    /// Failures that weren't dismissed, ordered by layer
+    pub fn reported(&self) -> impl Iterator<Item = (NodeIndex, &str)> {
+        self.errors
+            .iter()
+            .filter(move |(node, _)| !self.dismissed.contains(node))
+            .map(|(&node, error)| (node, error.as_str()))
+    }
+
+    /// Stops reporting the current failures
+    pub fn dismiss(&mut self) {
+        self.dismissed.extend(self.errors.keys());
+    }
+}
+
+/// Highlights the selected layer in the layer list, and layers that failed to compute
+struct LayerButtonStyle {
+    selected: bool,
+    failed: bool,
+}
+
+impl button::StyleSheet for LayerButtonStyle {
+    fn active(&self) -> button::Style {
+        let background = match (self.selected, self.failed) {
+            (true, false) => Color::from_rgb(0.75, 0.85, 1.0),
+            (true, true) => Color::from_rgb(0.95, 0.7, 0.7),
+            (false, true) => Color::from_rgb(1.0, 0.88, 0.86),
+            (false, false) => Color::from_rgb(0.93, 0.93, 0.93),
+        };
+        button::Style {
+            background: Some(Background::Color(background)),
+            border_radius: 2.0,
+            border_width: if self.failed { 1.0 } else { 0.0 },
+            border_color: Color::from_rgb(0.85, 0.25, 0.2),
+            ..button::Style::default()
+        }
+    }
+}
+
+/// Frame around the thumbnail of a layer, which turns red if the layer failed to compute
+struct ThumbnailStyle {
+    failed: bool,
+}
+
+impl ThumbnailStyle {
+    const ERROR: Color = Color::from_rgb(0.85, 0.25, 0.2);
+}
+
+impl container::StyleSheet for ThumbnailStyle {
+    fn style(&self) -> container::Style {
+        container::Style {
+            border_width: if self.failed { 2.0 } else { 1.0 },
+            border_color: if self.failed { Self::ERROR } else { Color::from_rgb(0.7, 0.7, 0.7) },
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Reporting layers that failed to compute above the viewport, along with other notices like finished exports
+#[derive(Default)]
+struct Banner {
+    notices: Vec<(String, bool)>, // Along with whether they report an error
+    expanded: bool,               // Whether all lines are listed, rather than the first few and a count of the rest
+    expand: button::State,
+    dismiss: button::State,
+}
+
+struct BannerStyle {
+    error: bool,
+}
+
+impl container::StyleSheet for BannerStyle {
+    fn style(&self) -> container::Style {
+        let (background, border) = if self.error {
+            (Color::from_rgb(1.0, 0.9, 0.88), Color::from_rgb(0.85, 0.25, 0.2))
+        } else {
+            (Color::from_rgb(0.9, 0.96, 0.9), Color::from_rgb(0.3, 0.65, 0.35))
+        };
+        container::Style {
+            background: Some(Background::Color(background)),
+            border_width: 1.0,
+            border_color: border,
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Writing the output of the selected layer to an image file
+struct Exporting {
+    quality: u8, // Of JPEGs
+    export: button::State,
+    copy: button::State,
+    quality_slider: slider::State,
+}
+
+impl Default for Exporting {
+    fn default() -> Self {
+        Self {
+            quality: 90,
+            export: button::State::new(),
+            copy: button::State::new(),
+            quality_slider: slider::State::new(),
+        }
+    }
+}
+
+/// Buttons for adding input layers from image files, or changing the file of the selected one
+#[derive(Default)]
+struct Opening {
+    open: button::State,
+    replace: button::State,
+    fresh: button::State,
+    show_recent: bool,
+    recent_toggle: button::State,
+    recent: Vec<button::State>, // One for each recently opened image
+}
+
+/// Buttons for undoing edits, and for duplicating or deleting the selected layer
+#[derive(Default)]
+struct Editing {
+    undo: button::State,
+    redo: button::State,
+    duplicate: button::State,
+    delete: button::State,
+}
+
+/// Controls for how the selected layer is shown in the graph editor
+#[derive(Default)]
+struct Annotation {
+    color: button::State,
+    note_input: text_input::State,
+    note: Option<(NodeIndex, String)>, // Being typed, until Enter is pressed
+}
+
+/// The color tag that follows `tag`, going through all of them and then back to none
+fn next_color_tag(tag: Option<ColorTag>) -> Option<ColorTag> {
+    match tag {
+        None => Some(ColorTag::ALL[0]),
+        Some(tag) => ColorTag::ALL.iter().skip_while(|&&other| other != tag).nth(1).copied(),
+    }
+}
+
+/// The list of keyboard shortcuts, shown in place of the viewport
+#[derive(Default)]
+struct ShortcutList {
+    shown: bool,
+    toggle: button::State,
+}
+
+/// A dialog for picking image files to open
+fn image_dialog<'a>() -> native_dialog::FileDialog<'a> {
+    let extensions = &["png", "jpg", "jpeg", "bmp", "tif", "tiff", "gif", "webp", "tga", "pnm", "ico"];
+    native_dialog::FileDialog::new().add_filter("Image", extensions)
+}
+
+/// Whether an image file can be read, judging by its header
+fn check_image(path: &Path) -> crate::error::Result<()> {
+    image::image_dimensions(path)?;
+    Ok(())
 }
 
-enum Message {
-    Tick(Instant)
-}
\ No newline at end of file
+/// Asks where to export the output of a layer to. Without an extension, it is exported as PNG.
+fn export_path(name: &str) -> crate::error::Result<Option<PathBuf>> {
+    let filename = format!("{}.png", name);
+    let path = native_dialog::FileDialog::new()
+        .set_filename(&filename)
+        .add_filter("PNG image", &["png"])
+        .add_filter("JPEG image", &["jpg", "jpeg"])
+        .add_filter("BMP image", &["bmp"])
+        .add_filter("TIFF image", &["tif", "tiff"])
+        .show_save_single_file()?;
+    Ok(path.map(|path| match path.extension() {
+        Some(_) => path,
+        None => path.with_extension("png"),
+    }))
+}
+
+/// Showing the selected layer next to a reference layer in the viewport
+#[derive(Default)]
+struct Comparison {
+    enabled: bool,
+    pinned: Option<NodeIndex>,    // The reference the user picked. Otherwise, it's the source of the selected layer
+    requested: Option<NodeIndex>, // Reference whose output was asked for last
+    toggle: button::State,
+    swap: button::State,
+    pin: button::State,
+}
+
+/// Drawing a region on the viewport to crop the image to. While a crop layer is selected, its input is shown and the
+/// region is the one it crops to.
+#[derive(Default)]
+struct Cropping {
+    enabled: bool,
+    region: Option<Rect>, // Drawn on the output of the selected layer, to add a crop layer with
+    toggle: button::State,
+    apply: button::State,
+}
+
+/// Painting on the selected layer, if it can be painted on
+struct Brush {
+    radius: f32, // In pixels of the layer at full resolution
+    erase: bool,
+    last: Option<(f32, f32)>, // Where the stroke in progress was painted last
+    mode: button::State,
+    size: slider::State,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            radius: 10.0,
+            erase: false,
+            last: None,
+            mode: button::State::new(),
+            size: slider::State::new(),
+        }
+    }
+}
+
+/// Picking a color for a parameter from the image in the viewport
+#[derive(Default)]
+struct Eyedropper {
+    armed: Option<(NodeIndex, String)>, // Layer and color parameter to pick for, until the image is clicked
+    from_output: bool,                  // Whether to sample the output of the layer instead of its input
+    picked: Option<Pick>,
+    source: button::State,
+    cancel: button::State,
+}
+
+/// A pixel that was clicked to pick a color, which is set as soon as the backend answers. Answers from a preview are
+/// followed up on once the full resolution output is there.
+struct Pick {
+    target: (NodeIndex, String), // Layer and color parameter
+    sample: NodeIndex,
+    position: (f32, f32),   // As a fraction of the image size
+    queried: bool,          // Whether the pixel was asked for since the output it's picked from last changed
+    color: Option<[u8; 4]>, // Set so far
+}
+
+/// Drawing the shapes found by the selected layer on top of the image they were found in
+struct ShapeOverlay {
+    shown: bool,
+    color: usize, // Of `ShapeOverlay::COLORS`
+    width: f32,   // In screen pixels
+    toggle: button::State,
+    color_button: button::State,
+    width_slider: slider::State,
+}
+
+impl ShapeOverlay {
+    const COLORS: [(&'static str, Color); 4] = [
+        ("Red", Color::from_rgb(1.0, 0.2, 0.2)),
+        ("Green", Color::from_rgb(0.2, 1.0, 0.3)),
+        ("Cyan", Color::from_rgb(0.1, 0.9, 1.0)),
+        ("Yellow", Color::from_rgb(1.0, 0.9, 0.1)),
+    ];
+}
+
+impl Default for ShapeOverlay {
+    fn default() -> Self {
+        Self {
+            shown: true,
+            color: 0,
+            width: 2.0,
+            toggle: button::State::new(),
+            color_button: button::State::new(),
+            width_slider: slider::State::new(),
+        }
+    }
+}
+
+/// Looking up the pixel under the cursor in the full resolution output on the backend
+#[derive(Default)]
+struct Inspector {
+    hovered: Option<(NodeIndex, (f32, f32))>, // Layer and position under the cursor, as a fraction of the image size
+    queried: Option<(NodeIndex, (f32, f32))>, // What was last asked for
+    last_query: Option<Instant>,
+    readout: Option<(NodeIndex, String)>, // Latest answer
+}
+
+pub struct Settings {
+    pub target_refresh_rate: u64,      // Ticks per second while the backend is busy
+    pub idle_refresh_rate: u64,        // Ticks per second while the backend has nothing to do
+    pub session_file: Option<PathBuf>, // Where the session is saved to, if anywhere
+    pub restore_recipe: bool,          // Whether the graph of the previous session is loaded on start
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            target_refresh_rate: 60,
+            idle_refresh_rate: 2,
+            session_file: None,
+            restore_recipe: false,
+        }
+    }
+}
+
+/// Produces the current time at a fixed interval, starting one interval after it is subscribed to
+pub fn every(interval: Duration) -> Subscription<Instant> {
+    Subscription::from_recipe(Every(interval))
+}
+
+struct Every(Duration);
+
+impl<H: Hasher, E> Recipe<H, E> for Every {
+    type Output = Instant;
+
+    fn hash(&self, state: &mut H) {
+        std::any::TypeId::of::<Self>().hash(state);
+        self.0.hash(state);
+    }
+
+    fn stream(self: Box<Self>, _input: BoxStream<'static, E>) -> BoxStream<'static, Instant> {
+        // A thread keeps the executor free. It stops once the subscription is dropped.
+        let (sender, receiver) = mpsc::unbounded();
+        let interval = self.0;
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if sender.unbounded_send(Instant::now()).is_err() {
+                break;
+            }
+        });
+        receiver.boxed()
+    }
+}
+
+pub struct UI {
+    backend: Supervisor,
+    settings: Settings,
+    graph: GraphMirror,
+    catalog: BTreeMap<String, LayerInfo>, // Kinds of layers the backend can add
+    previews: HashMap<NodeIndex, Handle>,
+    thumbnails: HashMap<NodeIndex, Handle>, // Of the outputs of the layers, for the layer list
+    full_sizes: HashMap<NodeIndex, (u32, u32)>, // Of the outputs the previews were made from
+    geometry: HashMap<NodeIndex, (Arc<Geometry>, (u32, u32))>, // Along with the size of the image it was found in
+    view: ViewState,
+    comparison: Comparison,
+    cropping: Cropping,
+    brush: Brush,
+    eyedropper: Eyedropper,
+    shape_overlay: ShapeOverlay,
+    exporting: Exporting,
+    opening: Opening,
+    editing: Editing,
+    annotation: Annotation,
+    shortcut_list: ShortcutList,
+    session: Session,
+    exiting: bool, // Whether the window was closed
+    shown_requested: Option<NodeIndex>, // Layer other than the selected one, whose output was asked for to show it
+    inspector: Inspector,
+    histogram: Option<(NodeIndex, Histogram)>, // Of the selected layer, unless another one was selected since
+    chart: ChartState,
+    log_scale: bool, // Whether the histogram is shown on a log scale
+    editor: EditorState,
+    failures: Failures,
+    banner: Banner,
+    status: String,
+    queue: (Option<NodeIndex>, usize, usize), // Layer being computed, computations done and left to do
+    memory_usage: usize,                      // Of the outputs stored on the backend
+    durations: HashMap<NodeIndex, Duration>,  // Of the last computation of each layer
+    busy: bool,               // Whether the backend has been active since it was last found idle
+    log: VecDeque<LogRecord>, // Most recent records from the backend
+    layer_buttons: BTreeMap<NodeIndex, button::State>, // One for each layer in the graph
+    layer_list: scrollable::State,
+    menu: LayerMenu,
+    parameters: ParameterPanel,
+}
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    Tick(Instant),
+    SelectLayer(NodeIndex),
+    SetTargetRefreshRate(u64),
+    SetIdleRefreshRate(u64),
+    View(ViewState), // The viewport was zoomed or panned
+    Hover(Option<Hover>), // The cursor moved over the viewport
+    Chart(ChartMessage),
+    SetLogScale(bool),
+    ToggleComparison,
+    PinReference(Option<NodeIndex>), // None compares with the source of the selected layer
+    ToggleCropping,
+    Region(Rect), // A region was drawn on the viewport
+    CropToRegion,
+    Brush(BrushEvent),
+    SetBrushRadius(f32),
+    ToggleErase,
+    Pick(Hover), // The image was clicked to pick a color
+    ToggleSampleSource,
+    CancelPicking,
+    Escape,
+    KeyPressed(keyboard::KeyCode, keyboard::Modifiers), // Dispatched to whatever the shortcuts bind it to
+    ToggleShortcuts,
+    DeleteLayer, // The selected one
+    DuplicateLayer,
+    Undo,
+    Redo,
+    OpenImages,           // Asks for image files to add input layers for
+    ReplaceImage,         // Asks for an image file to read in the selected input layer instead
+    FileDropped(PathBuf), // Onto the window, to add an input layer for
+    ToggleRecent,
+    OpenRecent(PathBuf),
+    StartFresh, // Replaces the graph with an empty one
+    Close,      // The window is about to close
+    Export,               // Asks where to export the output of the selected layer to
+    CopyToClipboard,      // The output of the selected layer
+    SetExportQuality(u8),
+    Exported { node: NodeIndex, path: PathBuf, result: Result<(), String> }, // The file was written, or not
+    RecipeLoaded(Result<Box<recipe::Recipe>, String>), // Of the previous session
+    SessionSaved(Result<(), String>),
+    ToggleShapes,
+    NextShapeColor,
+    SetShapeWidth(f32),
+    ToggleFailureList,
+    DismissFailures,
+    SetCollapsed(bool), // Of the selected layer in the graph editor
+    NextColorTag,
+    EditNote(String),
+    SubmitNote,
+    Panel(PanelMessage),
+    Menu(MenuMessage),
+    Editor(EditorMessage),
+}
+
+/// A number of bytes in the largest binary unit that keeps it above 1, e.g. "1.5 MiB"
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+/// Key presses to look up in the shortcuts. Keys that went to a widget, e.g. a text input, are left alone.
+fn key_press(event: iced_native::Event, status: event::Status) -> Option<Message> {
+    match (event, status) {
+        (iced_native::Event::Keyboard(keyboard::Event::KeyPressed { key_code, modifiers }), event::Status::Ignored) => {
+            Some(Message::KeyPressed(key_code, modifiers))
+        }
+        _ => None,
+    }
+}
+
+fn window_event(event: iced_native::Event, _status: event::Status) -> Option<Message> {
+    match event {
+        iced_native::Event::Window(window::Event::FileDropped(path)) => Some(Message::FileDropped(path)),
+        iced_native::Event::Window(window::Event::CloseRequested) => Some(Message::Close),
+        _ => None,
+    }
+}
+
+/// Parameters of a crop layer that crops to `region`
+fn crop_parameters(region: Rect) -> ParamMap {
+    let parameters = [("x", region.x), ("y", region.y), ("width", region.width), ("height", region.height)];
+    parameters
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), ParamValue::Int(value.into())))
+        .collect()
+}
+
+impl UI {
+    const LOG_LENGTH: usize = 100;
+    const VISIBLE_LOG_LINES: usize = 5;
+    const SIDEBAR_WIDTH: u16 = 200;
+    const PANEL_WIDTH: u16 = 280;
+    const QUERY_INTERVAL: Duration = Duration::from_millis(50); // Between pixel queries while the cursor moves
+    const LISTED_LINES: usize = 3; // Shown in the banner before the others are collapsed into a count
+    const THUMBNAIL_SIZE: u16 = 48; // Of the images in the layer list, which the backend sends larger
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Ticks per second at the moment, depending on whether the backend is busy
+    pub fn refresh_rate(&self) -> u64 {
+        let rate = if self.busy {
+            self.settings.target_refresh_rate
+        } else {
+            self.settings.idle_refresh_rate
+        };
+        rate.max(1)
+    }
+
+    /// Takes in whatever the backend sent since the last tick. Returns the work that is done asynchronously because it
+    /// would hold up the user interface, which sends a message once it's done.
+    fn receive_data(&mut self) -> Command<Message> {
+        let mut commands = Vec::new();
+        for data in self.backend.channel().receive() {
+            self.graph.apply(&data);
+            self.failures.apply(&data);
+            self.busy = match data {
+                Data::QueueState { current, .. } => current.is_some(),
+                Data::LogBatch(_) => self.busy,
+                _ => true, // Whatever caused this might cause more
+            };
+            match data {
+                Data::LayerAdded { node, kind, .. } => {
+                    self.layer_buttons.insert(node, button::State::new());
+                    self.status = format!("Added layer {} ({})", node.index(), kind)
+                }
+                Data::LayerRemoved(node) => {
+                    self.layer_buttons.remove(&node);
+                    self.previews.remove(&node);
+                    self.thumbnails.remove(&node);
+                    self.full_sizes.remove(&node);
+                    self.geometry.remove(&node);
+                    self.durations.remove(&node);
+                    if self.comparison.pinned == Some(node) {
+                        self.comparison.pinned = None;
+                    }
+                    let eyedropper = &mut self.eyedropper;
+                    if eyedropper.armed.as_ref().is_some_and(|(target, _)| *target == node) {
+                        eyedropper.armed = None;
+                    }
+                    if eyedropper.picked.as_ref().is_some_and(|pick| pick.target.0 == node || pick.sample == node) {
+                        eyedropper.picked = None;
+                    }
+                    self.status = format!("Removed layer {}", node.index())
+                }
+                Data::LayerMoved { node, .. } => self.editor.settle(node),
+                Data::LayerSelected(_) => {
+                    self.cropping.region = None; // It was drawn on another image
+                    self.eyedropper.armed = None;
+                }
+                Data::Connected { .. } | Data::Disconnected { .. } | Data::Parameters { .. } => (),
+                Data::Graph { .. } | Data::GraphDelta { .. } => (),
+                Data::ComputeFinished { node, duration } => {
+                    self.durations.insert(node, duration);
+                    self.status = format!("Computed layer {} in {:.1?}", node.index(), duration)
+                }
+                Data::ComputeFailed { node, error } => {
+                    self.status = format!("Failed to compute layer {}: {}", node.index(), error)
+                }
+                Data::Preview {
+                    node,
+                    image,
+                    full_size,
+                    ..
+                } => {
+                    self.previews.insert(node, image);
+                    self.full_sizes.insert(node, full_size);
+                    if let Some(pick) = &mut self.eyedropper.picked {
+                        // The output that was picked from changed, or is available at full resolution now
+                        pick.queried = pick.queried && pick.sample != node;
+                    }
+                }
+                Data::Pixel {
+                    node,
+                    position,
+                    x,
+                    y,
+                    value,
+                    exact,
+                } => {
+                    let note = if exact { "" } else { " (preview)" };
+                    self.inspector.readout = Some((node, format!("{}, {}: {}{}", x, y, value, note)));
+                    self.receive_pick(node, position, value.to_rgba(), exact);
+                }
+                Data::Histogram { node, histogram } => self.histogram = Some((node, histogram)),
+                Data::Thumbnail { node, image } => {
+                    self.thumbnails.insert(node, image);
+                }
+                Data::Geometry { node, geometry, size } => {
+                    self.geometry.insert(node, (Arc::new(geometry), size));
+                }
+                Data::ExportReady { node, job } => {
+                    let export = async move {
+                        let result = job.write().map_err(|e| format!("{:#}", e));
+                        Message::Exported { node, path: job.path().to_path_buf(), result }
+                    };
+                    commands.push(Command::perform(export, |message| message));
+                }
+                Data::ExportFailed { node, path, error } => {
+                    let notice = format!("Failed to export layer {} to {}: {}", node.index(), path.display(), error);
+                    self.banner.notices.push((notice, true));
+                }
+                Data::Copied(node) => {
+                    let notice = format!("Copied layer {} to the clipboard", node.index());
+                    self.banner.notices.push((notice, false));
+                }
+                Data::CopyFailed { node, error } => {
+                    let notice = format!("Failed to copy layer {} to the clipboard: {}", node.index(), error);
+                    self.banner.notices.push((notice, true));
+                }
+                Data::QueueState { current, done, pending } => self.queue = (current, done, pending),
+                Data::MemoryUsage(bytes) => self.memory_usage = bytes,
+                Data::LogBatch(records) => {
+                    self.log.extend(records);
+                    let excess = self.log.len().saturating_sub(Self::LOG_LENGTH);
+                    self.log.drain(..excess);
+                }
+                Data::Error(error) => self.status = error,
+            }
+        }
+        let selected = self.graph.selected();
+        self.parameters.sync(selected, selected.and_then(|node| self.graph.layer(node)));
+        self.request_reference();
+        self.request_shown();
+        if self.graph.request_snapshot() {
+            self.send(Event::RequestGraph);
+        }
+
+        if self.backend.has_crashed() {
+            self.queue = (None, 0, 0);
+            self.status = match self.backend.restart() {
+                Ok(reason) => format!("{}, restarted it", reason),
+                Err(e) => format!("Backend stopped and couldn't be restarted: {:#}", e),
+            };
+        }
+        Command::batch(commands)
+    }
+
+    /// The layer the selected layer is compared with, if they are being compared
+    fn reference(&self) -> Option<NodeIndex> {
+        if !self.comparison.enabled {
+            return None;
+        }
+        let selected = self.graph.selected()?;
+        let pinned = self.comparison.pinned.filter(|&node| self.graph.layer(node).is_some());
+        let reference = pinned.unwrap_or_else(|| self.graph.source(selected));
+        (reference != selected).then_some(reference)
+    }
+
+    /// Asks for the output of the reference once, if there is none yet
+    fn request_reference(&mut self) {
+        let reference = match self.reference() {
+            Some(reference) if !self.previews.contains_key(&reference) => reference,
+            _ => return,
+        };
+        if self.comparison.requested != Some(reference) {
+            self.comparison.requested = Some(reference);
+            self.send(Event::RequestCompute(reference));
+        }
+    }
+
+    /// The selected layer if it crops its input, along with that input
+    fn crop_layer(&self) -> Option<(NodeIndex, NodeIndex)> {
+        let selected = self.graph.selected()?;
+        let layer = self.graph.layer(selected)?;
+        let &(input, _, _) = layer.inputs.first()?;
+        layer.kind.starts_with("Crop<").then_some((selected, input))
+    }
+
+    /// The selected layer if it finds shapes in an image, along with the layer whose output they are found in
+    fn shape_layer(&self) -> Option<(NodeIndex, NodeIndex)> {
+        let selected = self.graph.selected()?;
+        let layer = self.graph.layer(selected)?;
+        let &(input, _, _) = layer.inputs.first()?;
+        let output_type = self.catalog.get(&layer.kind)?.output_type?;
+        entity::is_geometry(output_type).then_some((selected, input))
+    }
+
+    /// The layer whose output is shown in the viewport
+    fn shown(&self) -> Option<NodeIndex> {
+        if let Some(sample) = self.sample_source() {
+            return Some(sample);
+        }
+        if let Some((_, input)) = self.shape_layer() {
+            return Some(input);
+        }
+        match self.crop_layer() {
+            Some((_, input)) if self.cropping.enabled => Some(input),
+            _ => self.graph.selected(),
+        }
+    }
+
+    /// The region being drawn, which is the one the selected crop layer crops to, if there is one. Sizes of 0 reach to
+    /// the edge of the input.
+    fn region(&self) -> Option<Rect> {
+        let (node, input) = match self.crop_layer() {
+            Some(crop) => crop,
+            None => return self.cropping.region,
+        };
+        let (width, height) = *self.full_sizes.get(&input)?;
+        let parameters = &self.graph.layer(node)?.parameters;
+        let get = |name| match parameters.get(name) {
+            Some(ParamValue::Int(value)) => u32::try_from(*value).ok(),
+            _ => None,
+        };
+        let reach = |size| if size == 0 { u32::MAX } else { size };
+        let region = Rect::new(get("x")?, get("y")?, reach(get("width")?), reach(get("height")?));
+        Some(region.clamped((width, height)))
+    }
+
+    /// The selected layer, if the brush paints on it
+    fn canvas(&self) -> Option<NodeIndex> {
+        let selected = self.graph.selected()?;
+        (self.graph.layer(selected)?.kind == "PaintedMask").then_some(selected)
+    }
+
+    /// The kind of crop layer that fits the output of the selected layer, if there is one
+    fn crop_kind(&self) -> Option<String> {
+        let layer = self.graph.layer(self.graph.selected()?)?;
+        let kind = format!("Crop<{}>", self.catalog.get(&layer.kind)?.output_type?);
+        self.catalog.contains_key(&kind).then_some(kind)
+    }
+
+    /// The layer to pick a color from, while the eyedropper is armed. That's the input of the layer the color is for,
+    /// unless the user chose to sample its output.
+    fn sample_source(&self) -> Option<NodeIndex> {
+        let (node, _) = self.eyedropper.armed.as_ref()?;
+        let input = self.graph.layer(*node)?.inputs.first().map(|&(input, _, _)| input);
+        match input {
+            Some(input) if !self.eyedropper.from_output => Some(input),
+            _ => Some(*node),
+        }
+    }
+
+    /// Asks for the output of the layer that is shown once, if it isn't the selected one and there is none yet
+    fn request_shown(&mut self) {
+        let shown = match self.shown() {
+            Some(shown) if self.graph.selected() != Some(shown) && !self.previews.contains_key(&shown) => shown,
+            _ => return,
+        };
+        if self.shown_requested != Some(shown) {
+            self.shown_requested = Some(shown);
+            self.send(Event::RequestCompute(shown));
+        }
+    }
+
+    /// Sets the color that was picked, once the backend answered with the value of the pixel
+    fn receive_pick(&mut self, node: NodeIndex, position: (f32, f32), color: [u8; 4], exact: bool) {
+        let pick = match &mut self.eyedropper.picked {
+            Some(pick) if pick.sample == node && pick.position == position => pick,
+            _ => return,
+        };
+        let changed = pick.color.replace(color) != Some(color);
+        let (node, name) = pick.target.clone();
+        if exact {
+            self.eyedropper.picked = None;
+        }
+        if changed {
+            let value = ParamValue::Color(color);
+            self.send(Event::SetParameter { node, name, value });
+            self.refresh();
+        }
+    }
+
+    /// Asks for the pixel under the cursor, unless it was asked for already or the last query was too recent. Queries
+    /// held back are sent on a later tick.
+    ///
+    /// A pixel that was picked with the eyedropper comes first, and until it's answered exactly, the cursor isn't
+    /// followed. Otherwise, the query for the picked pixel might be dropped in favor of a later one.
+    fn query_pixel(&mut self) {
+        let inspector = &mut self.inspector;
+        let (node, (x, y)) = match (&self.eyedropper.picked, inspector.hovered) {
+            (Some(pick), _) if !pick.queried => (pick.sample, pick.position),
+            (Some(_), _) => return,
+            (None, Some(hovered)) if inspector.queried != Some(hovered) => hovered,
+            _ => return,
+        };
+        if inspector.last_query.is_some_and(|last_query| last_query.elapsed() < Self::QUERY_INTERVAL) {
+            self.busy = true; // Ticks come often enough to send it soon
+            return;
+        }
+        match &mut self.eyedropper.picked {
+            Some(pick) => pick.queried = true,
+            None => inspector.queried = inspector.hovered,
+        }
+        inspector.last_query = Some(Instant::now());
+        self.send(Event::QueryPixel { node, x, y });
+    }
+
+    /// Asks for the outputs being looked at again, after something they depend on changed
+    fn refresh(&mut self) {
+        let nodes: Vec<_> = self.graph.selected().into_iter().chain(self.reference()).collect();
+        for node in nodes {
+            self.send(Event::RequestCompute(node));
+        }
+    }
+
+    /// The selected layer, if it reads an image file
+    fn input_layer(&self) -> Option<NodeIndex> {
+        let selected = self.graph.selected()?;
+        (self.graph.layer(selected)?.kind == "InputFile").then_some(selected)
+    }
+
+    /// Adds an input layer for an image file, or has `replace` read it instead. Files that can't be read as images
+    /// are reported rather than leaving a layer behind that fails to compute.
+    fn open_image(&mut self, path: PathBuf, replace: Option<NodeIndex>) -> Command<Message> {
+        if let Err(e) = check_image(&path) {
+            self.banner.notices.push((format!("Can't open {}: {:#}", path.display(), e), true));
+            return Command::none();
+        }
+        self.session.add_recent(path.clone());
+        let saving = self.save_session();
+        let (name, value) = ("path".to_string(), ParamValue::Path(path));
+        match replace {
+            Some(node) => self.send(Event::SetParameter { node, name, value }),
+            None => self.send(Event::InsertLayer {
+                kind: "InputFile".to_string(),
+                parameters: ParamMap::from([(name, value)]),
+                connect_after: None,
+            }),
+        }
+        saving
+    }
+
+    /// Writing the session along with the graph, so that both are restored on the next start. Serializing the graph
+    /// takes a while for large ones, so this is left to the caller.
+    fn session_writer(&mut self) -> Option<impl FnOnce() -> crate::error::Result<()> + Send + 'static> {
+        let path = self.settings.session_file.clone()?;
+        let recipe_path = Session::recipe_path(&path);
+        let recipe = self.backend.snapshot();
+        self.session.recipe = Some(recipe_path.clone());
+        let session = self.session.clone();
+        Some(move || {
+            recipe.save(&recipe_path)?;
+            session.save(&path)
+        })
+    }
+
+    /// Saves the session asynchronously, see `session_writer`
+    fn save_session(&mut self) -> Command<Message> {
+        match self.session_writer() {
+            Some(write) => {
+                let saving = async move { write().map_err(|e| format!("{:#}", e)) };
+                Command::perform(saving, Message::SessionSaved)
+            }
+            None => Command::none(),
+        }
+    }
+
+    /// Pixels of the preview of a layer per pixel of its full resolution output, once both sizes are known
+    fn scale(&self, node: NodeIndex) -> Option<f32> {
+        let (full_width, _) = *self.full_sizes.get(&node)?;
+        match self.previews.get(&node)?.data() {
+            iced_native::image::Data::Pixels { width, .. } if full_width > 0 => Some(*width as f32 / full_width as f32),
+            _ => None,
+        }
+    }
+
+    /// The message that the buttons for an action send
+    fn action_message(&self, action: Action) -> Message {
+        match action {
+            Action::AddLayer => Message::Menu(MenuMessage::Toggle),
+            Action::Cancel => Message::Escape,
+            Action::DeleteLayer => Message::DeleteLayer,
+            Action::DuplicateLayer => Message::DuplicateLayer,
+            Action::Undo => Message::Undo,
+            Action::Redo => Message::Redo,
+            Action::ZoomToFit => Message::View(self.view.fitted()),
+            Action::Export => Message::Export,
+            Action::Copy => Message::CopyToClipboard,
+            Action::Open => Message::OpenImages,
+            Action::ShowShortcuts => Message::ToggleShortcuts,
+        }
+    }
+
+    /// Sends a changed layout of the selected layer to the backend
+    fn change_layout(&mut self, change: impl FnOnce(&mut NodeLayout)) {
+        let node = match self.graph.selected() {
+            Some(node) => node,
+            None => return,
+        };
+        if let Some(layer) = self.graph.layer(node) {
+            let mut layout = layer.layout.clone();
+            change(&mut layout);
+            self.send(Event::SetLayout { node, layout });
+        }
+    }
+
+    /// Sends an event to the backend, which is expected to answer soon
+    fn send(&mut self, event: Event) {
+        if let Err(e) = self.backend.channel().send(event) {
+            self.status = format!("Failed to reach the backend: {}", e);
+        }
+        self.busy = true;
+    }
+}
+
+impl Application for UI {
+    type Executor = executor::Default;
+    type Message = Message;
+    type Flags = (Supervisor, Settings, Session);
+
+    fn new((backend, settings, session): Self::Flags) -> (Self, Command<Message>) {
+        let catalog: BTreeMap<_, _> = backend.catalog().into_iter().collect();
+        let menu = LayerMenu::new(catalog.clone());
+        let ui = Self {
+            backend,
+            settings,
+            graph: GraphMirror::new(),
+            catalog,
+            previews: HashMap::new(),
+            thumbnails: HashMap::new(),
+            full_sizes: HashMap::new(),
+            geometry: HashMap::new(),
+            view: ViewState::fit(),
+            comparison: Comparison::default(),
+            cropping: Cropping::default(),
+            brush: Brush::default(),
+            eyedropper: Eyedropper::default(),
+            shape_overlay: ShapeOverlay::default(),
+            exporting: Exporting::default(),
+            opening: Opening::default(),
+            editing: Editing::default(),
+            annotation: Annotation::default(),
+            shortcut_list: ShortcutList::default(),
+            session: session.clone(),
+            exiting: false,
+            shown_requested: None,
+            inspector: Inspector::default(),
+            histogram: None,
+            chart: ChartState::new(),
+            log_scale: false,
+            editor: EditorState::new(),
+            failures: Failures::new(),
+            banner: Banner::default(),
+            status: String::new(),
+            queue: (None, 0, 0),
+            memory_usage: 0,
+            durations: HashMap::new(),
+            busy: true,
+            log: VecDeque::new(),
+            layer_buttons: BTreeMap::new(),
+            layer_list: scrollable::State::new(),
+            menu,
+            parameters: ParameterPanel::new(),
+        };
+        // A recipe that went missing is ignored, just like a corrupted session
+        let restoring = match session.recipe {
+            Some(path) if ui.settings.restore_recipe => {
+                let loading = async move { recipe::Recipe::load(&path).map(Box::new).map_err(|e| format!("{:#}", e)) };
+                Command::perform(loading, Message::RecipeLoaded)
+            }
+            _ => Command::none(),
+        };
+        (ui, restoring)
+    }
+
+    fn should_exit(&self) -> bool {
+        self.exiting
+    }
+
+    fn title(&self) -> String {
+        match self.graph.primary_input().and_then(|path| path.file_name()) {
+            Some(name) => format!("{} - Klex", name.to_string_lossy()),
+            None => "Klex".to_string(),
+        }
+    }
+
+    fn update(&mut self, message: Message, _clipboard: &mut Clipboard) -> Command<Message> {
+        match message {
+            Message::Tick(_) => {
+                let received = self.receive_data();
+                self.query_pixel();
+                return received;
+            }
+            Message::SelectLayer(node) => {
+                if let Err(e) = self.backend.channel().send(Event::SelectLayer(node)) {
+                    self.status = format!("Failed to select layer {}: {}", node.index(), e);
+                }
+                self.busy = true; // Answers are expected soon
+            }
+            Message::SetTargetRefreshRate(rate) => self.settings.target_refresh_rate = rate,
+            Message::SetIdleRefreshRate(rate) => self.settings.idle_refresh_rate = rate,
+            Message::View(view) => self.view = view,
+            Message::Panel(PanelMessage::Eyedropper { name }) => {
+                self.eyedropper.armed = self.graph.selected().map(|node| (node, name));
+                self.request_shown();
+            }
+            Message::Panel(message) => {
+                let event = match (self.parameters.update(message), self.graph.selected()) {
+                    (Some(event), Some(_)) => event,
+                    _ => return Command::none(),
+                };
+                // The new value only shows once the layer has been computed again
+                self.send(event);
+                self.refresh();
+            }
+            Message::Hover(hover) => {
+                let node = match hover {
+                    Some(Hover { reference: true, .. }) => self.reference(),
+                    Some(_) => self.shown(),
+                    None => None,
+                };
+                let position = hover.map(|hover| (hover.position.x, hover.position.y));
+                self.inspector.hovered = node.zip(position);
+                self.query_pixel();
+            }
+            Message::Chart(ChartMessage::State(state)) => self.chart = state,
+            Message::Chart(ChartMessage::Threshold(threshold)) => {
+                if let Some(node) = self.graph.selected() {
+                    let value = ParamValue::Int(threshold.into());
+                    self.send(Event::SetParameter {
+                        node,
+                        name: "threshold".to_string(),
+                        value,
+                    });
+                    self.refresh();
+                }
+            }
+            Message::SetLogScale(log_scale) => self.log_scale = log_scale,
+            Message::ToggleComparison => {
+                self.comparison.enabled = !self.comparison.enabled;
+                self.request_reference();
+            }
+            Message::PinReference(node) => {
+                self.comparison.pinned = node;
+                self.request_reference();
+            }
+            Message::ToggleCropping => {
+                self.cropping.enabled = !self.cropping.enabled;
+                self.cropping.region = None;
+                self.request_shown();
+            }
+            Message::Region(region) => match self.crop_layer() {
+                Some((node, _)) => {
+                    for (name, value) in crop_parameters(region) {
+                        self.send(Event::SetParameter { node, name, value });
+                    }
+                    self.refresh();
+                }
+                None => self.cropping.region = Some(region),
+            },
+            Message::CropToRegion => {
+                let (region, kind) = match (self.cropping.region.take(), self.crop_kind()) {
+                    (Some(region), Some(kind)) => (region, kind),
+                    _ => return Command::none(),
+                };
+                self.send(Event::InsertLayer {
+                    kind,
+                    parameters: crop_parameters(region),
+                    connect_after: self.graph.selected(),
+                });
+            }
+            Message::Brush(BrushEvent::Painted(point)) => {
+                let node = match self.canvas() {
+                    Some(node) => node,
+                    None => return Command::none(),
+                };
+                let point = (point.x, point.y);
+                let points = match self.brush.last.replace(point) {
+                    Some(last) => vec![last, point], // Continues where the previous part of the stroke ended
+                    None => vec![point],
+                };
+                let stroke = Stroke {
+                    points,
+                    radius: self.brush.radius,
+                    erase: self.brush.erase,
+                };
+                self.send(Event::Paint { node, stroke });
+                self.refresh();
+            }
+            Message::Brush(BrushEvent::Lifted) => {
+                self.brush.last = None;
+                self.send(Event::EndStroke);
+            }
+            Message::SetBrushRadius(radius) => self.brush.radius = radius,
+            Message::ToggleErase => self.brush.erase = !self.brush.erase,
+            Message::Pick(hover) => {
+                let sample = if hover.reference { self.reference() } else { self.shown() };
+                let (target, sample) = match (self.eyedropper.armed.take(), sample) {
+                    (Some(target), Some(sample)) => (target, sample),
+                    _ => return Command::none(),
+                };
+                self.eyedropper.picked = Some(Pick {
+                    target,
+                    sample,
+                    position: (hover.position.x, hover.position.y),
+                    queried: false,
+                    color: None,
+                });
+                self.send(Event::RequestCompute(sample)); // An exact answer needs the full resolution output
+                self.query_pixel();
+            }
+            Message::ToggleSampleSource => {
+                self.eyedropper.from_output = !self.eyedropper.from_output;
+                self.request_shown();
+            }
+            Message::CancelPicking => self.eyedropper.armed = None,
+            Message::Escape => {
+                self.eyedropper.armed = None;
+                self.shortcut_list.shown = false;
+                self.menu.update(MenuMessage::Close, None);
+            }
+            Message::KeyPressed(key_code, modifiers) => {
+                if let Some(action) = self.session.shortcuts.action(key_code, modifiers) {
+                    let message = self.action_message(action);
+                    return Command::perform(future::ready(message), |message| message);
+                }
+            }
+            Message::ToggleShortcuts => self.shortcut_list.shown = !self.shortcut_list.shown,
+            Message::DeleteLayer => {
+                if let Some(node) = self.graph.selected() {
+                    self.send(Event::RemoveLayer(node));
+                }
+            }
+            Message::DuplicateLayer => {
+                if let Some(node) = self.graph.selected() {
+                    self.send(Event::DuplicateLayer(node));
+                }
+            }
+            Message::Undo => self.send(Event::Undo),
+            Message::Redo => self.send(Event::Redo),
+            Message::OpenImages => match image_dialog().show_open_multiple_file() {
+                Ok(paths) => {
+                    let opened: Vec<_> = paths.into_iter().map(|path| self.open_image(path, None)).collect();
+                    return Command::batch(opened);
+                }
+                Err(e) => self.banner.notices.push((format!("Couldn't ask for images to open: {:#}", e), true)),
+            },
+            Message::ReplaceImage => {
+                let input = self.input_layer();
+                match image_dialog().show_open_single_file() {
+                    Ok(Some(path)) => return self.open_image(path, input),
+                    Ok(None) => (),
+                    Err(e) => self.banner.notices.push((format!("Couldn't ask for an image to open: {:#}", e), true)),
+                }
+            }
+            Message::FileDropped(path) | Message::OpenRecent(path) => return self.open_image(path, None),
+            Message::ToggleRecent => self.opening.show_recent = !self.opening.show_recent,
+            Message::StartFresh => {
+                let recipe = Box::new(recipe::Recipe::from_graph(&InteractiveLayerGraph::new()));
+                self.send(Event::LoadRecipe { recipe, bindings: Bindings::new() });
+            }
+            Message::Close => {
+                // Closing anyway, there's nowhere to report errors. Saved right away, since nothing waits for tasks.
+                if let Some(write) = self.session_writer() {
+                    let _ = write();
+                }
+                self.exiting = true;
+            }
+            Message::Export => {
+                let selected = self.graph.selected();
+                if let Some((node, layer)) = selected.and_then(|node| Some((node, self.graph.layer(node)?))) {
+                    match export_path(&layer.name) {
+                        Ok(Some(path)) => {
+                            self.status = format!("Exporting layer {} to {:?}", node.index(), path);
+                            let quality = self.exporting.quality;
+                            self.send(Event::Export { node, path, quality });
+                        }
+                        Ok(None) => (),
+                        Err(e) => {
+                            let notice = format!("Couldn't ask where to export to: {:#}", e);
+                            self.banner.notices.push((notice, true));
+                        }
+                    }
+                }
+            }
+            Message::CopyToClipboard => {
+                if let Some(node) = self.graph.selected() {
+                    self.status = format!("Copying layer {} to the clipboard", node.index());
+                    self.send(Event::CopyToClipboard(node));
+                }
+            }
+            Message::SetExportQuality(quality) => self.exporting.quality = quality,
+            Message::Exported { node, path, result } => {
+                let notice = match result {
+                    Ok(()) => (format!("Exported layer {} to {}", node.index(), path.display()), false),
+                    Err(e) => (format!("Failed to export layer {} to {}: {}", node.index(), path.display(), e), true),
+                };
+                self.banner.notices.push(notice);
+            }
+            Message::RecipeLoaded(Ok(recipe)) => {
+                let bindings = self.session.bindings.clone();
+                self.send(Event::LoadRecipe { recipe, bindings });
+            }
+            Message::RecipeLoaded(Err(_)) => (),
+            Message::SessionSaved(result) => {
+                if let Err(e) = result {
+                    self.banner.notices.push((e, true));
+                }
+            }
+            Message::ToggleShapes => self.shape_overlay.shown = !self.shape_overlay.shown,
+            Message::NextShapeColor => {
+                self.shape_overlay.color = (self.shape_overlay.color + 1) % ShapeOverlay::COLORS.len();
+            }
+            Message::SetShapeWidth(width) => self.shape_overlay.width = width,
+            Message::ToggleFailureList => self.banner.expanded = !self.banner.expanded,
+            Message::DismissFailures => {
+                self.failures.dismiss();
+                self.banner.notices.clear();
+                self.banner.expanded = false;
+            }
+            Message::SetCollapsed(collapsed) => self.change_layout(|layout| layout.collapsed = collapsed),
+            Message::NextColorTag => self.change_layout(|layout| layout.color = next_color_tag(layout.color)),
+            Message::EditNote(note) => {
+                if let Some(node) = self.graph.selected() {
+                    self.annotation.note = Some((node, note));
+                }
+            }
+            Message::SubmitNote => {
+                if let Some((node, note)) = self.annotation.note.take() {
+                    if let Some(layer) = self.graph.layer(node) {
+                        let layout = NodeLayout { note, ..layer.layout.clone() };
+                        self.send(Event::SetLayout { node, layout });
+                    }
+                }
+            }
+            Message::Menu(message) => {
+                let selected = self.graph.selected();
+                let selected = selected.and_then(|node| Some((node, self.graph.layer(node)?.kind.as_str())));
+                if let Some(event) = self.menu.update(message, selected) {
+                    self.send(event);
+                }
+            }
+            Message::Editor(message) => {
+                let event = match message {
+                    EditorMessage::State(state) => {
+                        self.editor = state;
+                        return Command::none();
+                    }
+                    EditorMessage::Select(node) => Event::SelectLayer(node),
+                    EditorMessage::Move { node, position } => Event::MoveLayer {
+                        node,
+                        position: (position.x, position.y),
+                    },
+                    EditorMessage::Connect { from, output, to, port } => Event::Connect { from, output, to, port },
+                    EditorMessage::Layout { node, layout } => Event::SetLayout { node, layout },
+                    EditorMessage::Disconnect { to, port } => Event::Disconnect { to, port },
+                    EditorMessage::Remove(node) => Event::RemoveLayer(node),
+                };
+                let changes_output = matches!(event, Event::Connect { .. } | Event::Disconnect { .. });
+                self.send(event);
+                if changes_output {
+                    self.refresh();
+                }
+            }
+        }
+        Command::none()
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch(vec![
+            every(Duration::from_millis(1000 / self.refresh_rate())).map(Message::Tick),
+            iced_native::subscription::events_with(key_press),
+            iced_native::subscription::events_with(window_event),
+        ])
+    }
+
+    fn view(&mut self) -> Element<'_, Message> {
+        let selected = self.graph.selected();
+        let reference = self.reference();
+        let shown = self.shown();
+        let scale = shown.and_then(|node| self.scale(node));
+        let region = self.region();
+        let crop_kind = self.crop_kind();
+        let cropping_layer = self.crop_layer().is_some();
+        let canvas = self.canvas();
+        let shapes = self.shape_layer().map(|(node, _)| node);
+
+        let mut layer_list = Scrollable::new(&mut self.layer_list).spacing(4).padding(8);
+        if self.graph.is_empty() {
+            layer_list = layer_list.push(Text::new("No layers yet").size(16));
+        }
+        for (&node, state) in self.layer_buttons.iter_mut() {
+            let layer = match self.graph.layer(node) {
+                Some(layer) => layer,
+                None => continue,
+            };
+            let text = Column::new()
+                .push(Text::new(&layer.name).size(16))
+                .push(Text::new(format!("{} · {}", node.index(), layer.kind)).size(12));
+            let failed = self.failures.is_failed(node);
+            let mut label = Row::new().spacing(6).align_items(Align::Center);
+            if let Some(thumbnail) = self.thumbnails.get(&node) {
+                let size = Length::Units(Self::THUMBNAIL_SIZE);
+                let image = Image::new(thumbnail.clone()).width(size).height(size);
+                let mut thumbnail = Column::new().align_items(Align::Center).push(
+                    Container::new(image).padding(1).style(ThumbnailStyle { failed }),
+                );
+                if failed {
+                    // The output it shows is from before the failure
+                    thumbnail = thumbnail.push(Text::new("failed").size(11).color(ThumbnailStyle::ERROR));
+                }
+                label = label.push(thumbnail);
+            }
+            let label = label.push(text);
+            let button = Button::new(state, label)
+                .width(Length::Fill)
+                .style(LayerButtonStyle {
+                    selected: selected == Some(node),
+                    failed,
+                })
+                .on_press(Message::SelectLayer(node));
+            layer_list = layer_list.push(button);
+        }
+        let selected_kind = selected.and_then(|node| Some(self.graph.layer(node)?.kind.as_str()));
+        let menu = self.menu.view(selected_kind).map(Message::Menu);
+        let opening = &mut self.opening;
+        let open = Button::new(&mut opening.open, Text::new("Open image…").size(14)).on_press(Message::OpenImages);
+        let mut files = Row::new().spacing(8).push(open);
+        if selected_kind == Some("InputFile") {
+            let replace = Button::new(&mut opening.replace, Text::new("Replace…").size(14));
+            files = files.push(replace.on_press(Message::ReplaceImage));
+        }
+        let fresh = Button::new(&mut opening.fresh, Text::new("Start fresh").size(14)).on_press(Message::StartFresh);
+        let label = if opening.show_recent { "Recent ▾" } else { "Recent ▸" };
+        let mut toggle = Button::new(&mut opening.recent_toggle, Text::new(label).size(14));
+        if !self.session.recent.is_empty() {
+            toggle = toggle.on_press(Message::ToggleRecent);
+        }
+        let mut files = Column::new().spacing(4).push(files).push(Row::new().spacing(8).push(fresh).push(toggle));
+        if opening.show_recent {
+            opening.recent.resize_with(self.session.recent.len(), button::State::new);
+            for (path, state) in self.session.recent.iter().zip(&mut opening.recent) {
+                let name = path.file_name().map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy());
+                let button = Button::new(state, Text::new(name).size(14)).width(Length::Fill);
+                files = files.push(button.on_press(Message::OpenRecent(path.clone())));
+            }
+        }
+        let editing = &mut self.editing;
+        let undo = Button::new(&mut editing.undo, Text::new("Undo").size(14)).on_press(Message::Undo);
+        let redo = Button::new(&mut editing.redo, Text::new("Redo").size(14)).on_press(Message::Redo);
+        let mut duplicate = Button::new(&mut editing.duplicate, Text::new("Duplicate").size(14));
+        let mut delete = Button::new(&mut editing.delete, Text::new("Delete").size(14));
+        if selected.is_some() {
+            duplicate = duplicate.on_press(Message::DuplicateLayer);
+            delete = delete.on_press(Message::DeleteLayer);
+        }
+        let edits = Column::new()
+            .spacing(4)
+            .push(Row::new().spacing(8).push(undo).push(redo))
+            .push(Row::new().spacing(8).push(duplicate).push(delete));
+        let sidebar = Column::new()
+            .push(Container::new(files).padding(8))
+            .push(Container::new(edits).padding(8))
+            .push(Container::new(menu).padding(8))
+            .push(layer_list);
+        let sidebar = Container::new(sidebar)
+            .width(Length::Units(Self::SIDEBAR_WIDTH))
+            .height(Length::Fill);
+
+        let viewport: Element<'_, Message> = match (shown, shown.and_then(|node| self.previews.get(&node))) {
+            (_, Some(preview)) => {
+                let mut viewport =
+                    ImageViewport::new(preview.clone(), self.view, Message::View).on_hover(Message::Hover);
+                match shown.and_then(|node| self.full_sizes.get(&node)) {
+                    _ if self.eyedropper.armed.is_some() => viewport = viewport.picker(Message::Pick),
+                    Some(&full_size) if self.cropping.enabled => {
+                        viewport = viewport.region(region, full_size, Message::Region);
+                    }
+                    Some(&full_size) if canvas.is_some() => {
+                        viewport = viewport.brush(self.brush.radius, full_size, Message::Brush);
+                    }
+                    _ => (),
+                }
+                let inspector = &self.inspector;
+                match (inspector.hovered, &inspector.readout) {
+                    (Some((hovered, _)), Some((node, readout))) if hovered == *node => {
+                        viewport = viewport.readout(readout.clone());
+                    }
+                    _ => (),
+                }
+                let overlay = &self.shape_overlay;
+                match shapes.and_then(|node| self.geometry.get(&node)) {
+                    Some((geometry, size)) if overlay.shown => {
+                        let (_, color) = ShapeOverlay::COLORS[overlay.color];
+                        viewport = viewport.overlay(geometry.clone(), *size, color, overlay.width);
+                    }
+                    _ => (),
+                }
+                match reference.and_then(|node| self.previews.get(&node)) {
+                    Some(reference) => viewport.compare(reference.clone()).into(),
+                    None => viewport.into(),
+                }
+            }
+            (Some(node), None) => Text::new(format!("Layer {} hasn't been computed yet", node.index())).into(),
+            (None, None) if self.graph.is_empty() => Text::new("Add a layer to get started").into(),
+            (None, None) => Text::new("Select a layer to see its output").into(),
+        };
+        let viewport: Element<'_, Message> = if self.shortcut_list.shown {
+            let mut list = Column::new().spacing(4).push(Text::new("Keyboard shortcuts").size(18));
+            for (combo, action) in self.session.shortcuts.bindings() {
+                let combo = Text::new(combo.to_string()).size(14).width(Length::Units(120));
+                list = list.push(Row::new().spacing(16).push(combo).push(Text::new(action.description()).size(14)));
+            }
+            list.into()
+        } else {
+            viewport
+        };
+        let viewport = Container::new(viewport)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y();
+
+        let comparison = &mut self.comparison;
+        let label = if comparison.enabled { "Stop comparing" } else { "Compare" };
+        let mut toolbar = Row::new()
+            .spacing(8)
+            .padding(4)
+            .align_items(Align::Center)
+            .push(Button::new(&mut comparison.toggle, Text::new(label).size(14)).on_press(Message::ToggleComparison));
+        if comparison.enabled {
+            let swap = Button::new(&mut comparison.swap, Text::new("Swap sides").size(14));
+            toolbar = toolbar.push(swap.on_press(Message::View(self.view.swapped())));
+            let (label, pin) = match comparison.pinned {
+                Some(_) => ("Compare with source", Some(Message::PinReference(None))),
+                None => ("Use as reference", selected.map(|node| Message::PinReference(Some(node)))),
+            };
+            let mut pin_button = Button::new(&mut comparison.pin, Text::new(label).size(14));
+            if let Some(pin) = pin {
+                pin_button = pin_button.on_press(pin);
+            }
+            toolbar = toolbar.push(pin_button);
+            let note = match reference.map(|node| (node, self.graph.layer(node))) {
+                Some((node, Some(layer))) if self.previews.contains_key(&node) => {
+                    format!("Reference: {} ({})", layer.name, node.index())
+                }
+                Some((node, _)) => format!("Waiting for the output of layer {}", node.index()),
+                None => "Pick another layer to compare with".to_string(),
+            };
+            toolbar = toolbar.push(Text::new(note).size(14));
+        }
+        let cropping = &mut self.cropping;
+        let label = if cropping.enabled { "Stop selecting" } else { "Select region" };
+        let toggle = Button::new(&mut cropping.toggle, Text::new(label).size(14));
+        toolbar = toolbar.push(toggle.on_press(Message::ToggleCropping));
+        if cropping.enabled {
+            let note = match (cropping_layer, cropping.region, crop_kind) {
+                (true, _, _) => "Drag the region to change what the layer crops to",
+                (false, Some(_), Some(_)) => {
+                    let apply = Button::new(&mut cropping.apply, Text::new("Crop to selection").size(14));
+                    toolbar = toolbar.push(apply.on_press(Message::CropToRegion));
+                    ""
+                }
+                (false, _, Some(_)) => "Drag on the image to select a region",
+                (false, _, None) => "The output of this layer can't be cropped",
+            };
+            toolbar = toolbar.push(Text::new(note).size(14));
+        }
+        let eyedropper = &mut self.eyedropper;
+        if let Some((_, name)) = &eyedropper.armed {
+            let label = if eyedropper.from_output { "Sample the input" } else { "Sample the output" };
+            let source = Button::new(&mut eyedropper.source, Text::new(label).size(14));
+            let cancel = Button::new(&mut eyedropper.cancel, Text::new("Cancel").size(14));
+            toolbar = toolbar
+                .push(Text::new(format!("Click on the image to pick {}", name)).size(14))
+                .push(source.on_press(Message::ToggleSampleSource))
+                .push(cancel.on_press(Message::CancelPicking));
+        }
+        if canvas.is_some() && !cropping.enabled {
+            let brush = &mut self.brush;
+            let label = if brush.erase { "Erasing" } else { "Painting" };
+            let mode = Button::new(&mut brush.mode, Text::new(label).size(14)).on_press(Message::ToggleErase);
+            let size = Slider::new(&mut brush.size, 1.0..=100.0, brush.radius, Message::SetBrushRadius).step(1.0);
+            toolbar = toolbar
+                .push(mode)
+                .push(Text::new(format!("Brush size {}", brush.radius)).size(14))
+                .push(Container::new(size).width(Length::Units(120)));
+        }
+        if shapes.is_some() {
+            let overlay = &mut self.shape_overlay;
+            let label = if overlay.shown { "Hide shapes" } else { "Show shapes" };
+            let toggle = Button::new(&mut overlay.toggle, Text::new(label).size(14)).on_press(Message::ToggleShapes);
+            let (name, _) = ShapeOverlay::COLORS[overlay.color];
+            let color = Button::new(&mut overlay.color_button, Text::new(name).size(14));
+            let width = Slider::new(&mut overlay.width_slider, 1.0..=8.0, overlay.width, Message::SetShapeWidth);
+            toolbar = toolbar
+                .push(toggle)
+                .push(color.on_press(Message::NextShapeColor))
+                .push(Text::new(format!("Line width {}", overlay.width)).size(14))
+                .push(Container::new(width.step(0.5)).width(Length::Units(100)));
+        }
+        let exporting = &mut self.exporting;
+        let mut export = Button::new(&mut exporting.export, Text::new("Export…").size(14));
+        let mut copy = Button::new(&mut exporting.copy, Text::new("Copy").size(14));
+        if selected.is_some() {
+            export = export.on_press(Message::Export);
+            copy = copy.on_press(Message::CopyToClipboard);
+        }
+        let quality = Slider::new(&mut exporting.quality_slider, 1..=100, exporting.quality, Message::SetExportQuality);
+        toolbar = toolbar
+            .push(export)
+            .push(copy)
+            .push(Text::new(format!("JPEG quality {}", exporting.quality)).size(14))
+            .push(Container::new(quality).width(Length::Units(100)));
+        let shortcut_list = &mut self.shortcut_list;
+        let label = if shortcut_list.shown { "Hide shortcuts" } else { "Shortcuts" };
+        let toggle = Button::new(&mut shortcut_list.toggle, Text::new(label).size(14));
+        toolbar = toolbar.push(toggle.on_press(Message::ToggleShortcuts));
+        let viewport = Column::new()
+            .height(Length::FillPortion(3))
+            .push(toolbar)
+            .push(viewport);
+        let editor = GraphEditor::new(&self.graph, &self.catalog, self.editor, Message::Editor)
+            .failed(self.failures.failed())
+            .height(Length::FillPortion(2));
+
+        let banner = &mut self.banner;
+        let failures = self.failures.reported().map(|(node, error)| {
+            let name = self.graph.layer(node).map_or("Unknown layer", |layer| layer.name.as_str());
+            (format!("{} ({}) failed: {}", name, node.index(), error), true)
+        });
+        let lines: Vec<_> = failures.chain(banner.notices.iter().cloned()).collect();
+        let mut main = Column::new();
+        if !lines.is_empty() {
+            let listed = if banner.expanded { lines.len() } else { Self::LISTED_LINES };
+            let mut list = Column::new().spacing(4).width(Length::Fill);
+            for (line, _) in lines.iter().take(listed) {
+                list = list.push(Text::new(line.as_str()).size(14));
+            }
+            let mut buttons = Row::new().spacing(8);
+            if lines.len() > Self::LISTED_LINES {
+                let label = if banner.expanded {
+                    "Show fewer".to_string()
+                } else {
+                    format!("Show {} more", lines.len() - Self::LISTED_LINES)
+                };
+                let expand = Button::new(&mut banner.expand, Text::new(label).size(14));
+                buttons = buttons.push(expand.on_press(Message::ToggleFailureList));
+            }
+            let dismiss = Button::new(&mut banner.dismiss, Text::new("Dismiss").size(14));
+            buttons = buttons.push(dismiss.on_press(Message::DismissFailures));
+            let content = Row::new().spacing(8).align_items(Align::Start).push(list).push(buttons);
+            let style = BannerStyle {
+                error: lines.iter().any(|&(_, error)| error),
+            };
+            main = main.push(Container::new(content).padding(8).width(Length::Fill).style(style));
+        }
+
+        let mut status = Column::new().padding(8).push(Text::new(&self.status));
+        let dropped = self.backend.channel().dropped();
+        if dropped > 0 {
+            status = status.push(Text::new(format!("{} outdated previews skipped", dropped)).size(14));
+        }
+        let visible_log = self.log.iter().skip(self.log.len().saturating_sub(Self::VISIBLE_LOG_LINES));
+        for record in visible_log {
+            status = status.push(Text::new(record.to_string()).size(14));
+        }
+
+        let mut panel = Column::new().spacing(8);
+        if let Some((node, histogram)) = self.histogram.as_ref().filter(|(node, _)| selected == Some(*node)) {
+            // Thresholds can be picked right from the histogram
+            let layer = self.graph.layer(*node);
+            let threshold = match layer.filter(|layer| layer.kind == "Threshold").map(|layer| &layer.parameters) {
+                Some(parameters) => match parameters.get("threshold") {
+                    Some(ParamValue::Int(threshold)) => u8::try_from(*threshold).ok(),
+                    _ => None,
+                },
+                None => None,
+            };
+            let mut chart = HistogramChart::new(histogram, self.log_scale, self.chart, Message::Chart);
+            if let Some(threshold) = threshold {
+                chart = chart.threshold(threshold);
+            }
+            let log_scale = Checkbox::new(self.log_scale, "Log scale", Message::SetLogScale).size(14).text_size(14);
+            panel = panel.push(Column::new().padding(8).spacing(4).push(chart).push(log_scale));
+        }
+        if let Some(layer) = selected.and_then(|node| self.graph.layer(node)) {
+            let Annotation { color, note_input, note } = &mut self.annotation;
+            let layout = &layer.layout;
+            let collapsed = Checkbox::new(layout.collapsed, "Collapsed", Message::SetCollapsed).size(14).text_size(14);
+            let label = layout.color.map_or_else(|| "No color".to_string(), |tag| format!("{:?}", tag));
+            let color = Button::new(color, Text::new(label).size(14)).on_press(Message::NextColorTag);
+            let note = match note {
+                Some((node, note)) if Some(*node) == selected => note.as_str(),
+                _ => layout.note.as_str(),
+            };
+            let note = TextInput::new(note_input, "Note", note, Message::EditNote)
+                .padding(4)
+                .size(14)
+                .on_submit(Message::SubmitNote);
+            let row = Row::new().spacing(8).align_items(Align::Center).push(collapsed).push(color);
+            panel = panel.push(Column::new().padding(8).spacing(4).push(row).push(note));
+        }
+        let panel = Container::new(panel.push(self.parameters.view().map(Message::Panel)))
+            .width(Length::Units(Self::PANEL_WIDTH))
+            .height(Length::Fill);
+
+        let (current, done, pending) = self.queue;
+        let activity = match current {
+            Some(node) => {
+                let name = self.graph.layer(node).map_or("a removed layer", |layer| layer.name.as_str());
+                format!("Computing {} ({}/{})…", name, done + 1, done + pending)
+            }
+            None => match selected.and_then(|node| self.full_sizes.get(&node)) {
+                Some((width, height)) => format!("{} × {}", width, height),
+                None => "Idle".to_string(),
+            },
+        };
+        let mut status_bar = Row::new().spacing(24).padding(4).push(Text::new(activity).size(14));
+        if let Some(duration) = selected.and_then(|node| self.durations.get(&node)) {
+            status_bar = status_bar.push(Text::new(format!("Computed in {:.1?}", duration)).size(14));
+        }
+        let zoom = match (self.view.fixed_zoom(), scale) {
+            (None, _) => "Zoom to fit".to_string(),
+            (Some(zoom), scale) => format!("Zoom {:.0}%", zoom * scale.unwrap_or(1.0) * 100.0),
+        };
+        status_bar = status_bar
+            .push(Text::new(format!("Cached outputs {}", format_bytes(self.memory_usage))).size(14))
+            .push(Text::new(zoom).size(14));
+
+        let main = main.push(viewport).push(editor).push(status);
+        let content = Row::new()
+            .align_items(Align::Start)
+            .height(Length::Fill)
+            .push(sidebar)
+            .push(main)
+            .push(panel);
+        Column::new().push(content).push(status_bar).into()
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::Write,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+/// The error when the other end of a `ThreadChannel` was dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The other end of the channel was dropped")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+/// A coalescable message, which can be replaced by a newer one for as long as it wasn't received
+type Slot<T> = Arc<Mutex<Option<T>>>;
+
+enum Envelope<T> {
+    Message(T),
+    Coalescable(Slot<T>),
+}
+
+/// Keeps the number of coalescable messages in flight bounded, see `ThreadChannel::set_coalescing`
+struct Coalescing<T> {
+    capacity: usize,
+    key: fn(&T) -> Option<usize>,
+    pending: Mutex<VecDeque<(usize, Slot<T>)>>, // Sent, but maybe not received yet. Oldest first
+}
+
+/// One end of a two-way channel between threads. Sends messages of type `S` and receives messages of type `R`.
+pub struct ThreadChannel<S, R> {
+    sender: Sender<Envelope<S>>,
+    receiver: Receiver<Envelope<R>>,
+    disconnected: AtomicBool, // The other end was found to be dropped
+    coalescing: Option<Coalescing<S>>,
+    replaced_sent: Arc<AtomicUsize>,     // Messages sent from this end that were replaced before being received
+    replaced_received: Arc<AtomicUsize>, // The same for messages sent to this end
+}
+
+impl<S, R> ThreadChannel<S, R> {
+    /// Both ends of a new channel
+    pub fn new_pair() -> (ThreadChannel<S, R>, ThreadChannel<R, S>) {
+        let (sender_a, receiver_a) = crossbeam_channel::unbounded();
+        let (sender_b, receiver_b) = crossbeam_channel::unbounded();
+        let (replaced_a, replaced_b) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        (
+            ThreadChannel {
+                sender: sender_a,
+                receiver: receiver_b,
+                disconnected: AtomicBool::new(false),
+                coalescing: None,
+                replaced_sent: replaced_a.clone(),
+                replaced_received: replaced_b.clone(),
+            },
+            ThreadChannel {
+                sender: sender_b,
+                receiver: receiver_a,
+                disconnected: AtomicBool::new(false),
+                coalescing: None,
+                replaced_sent: replaced_b,
+                replaced_received: replaced_a,
+            },
+        )
+    }
+
+    /// Bounds the messages sent from this end for which `key` returns a key, e.g. previews that are produced faster
+    /// than they are looked at. Such a message replaces one with the same key that wasn't received yet. If there are
+    /// `capacity` of them in flight already, it replaces the oldest one. Other messages are never dropped.
+    pub fn set_coalescing(&mut self, capacity: usize, key: fn(&S) -> Option<usize>) {
+        self.coalescing = Some(Coalescing {
+            capacity: capacity.max(1),
+            key,
+            pending: Mutex::new(VecDeque::new()),
+        });
+    }
+
+    pub fn send(&self, message: S) -> Result<(), Disconnected> {
+        let coalescing = match &self.coalescing {
+            Some(coalescing) => coalescing,
+            None => return self.send_envelope(Envelope::Message(message)),
+        };
+        let key = match (coalescing.key)(&message) {
+            Some(key) => key,
+            None => return self.send_envelope(Envelope::Message(message)),
+        };
+
+        let mut pending = lock(&coalescing.pending);
+        pending.retain(|(_, slot)| lock(slot).is_some());
+        let replaced = pending
+            .iter()
+            .position(|&(pending_key, _)| pending_key == key)
+            .or_else(|| (pending.len() >= coalescing.capacity).then_some(0));
+        if let Some((_, slot)) = replaced.and_then(|index| pending.remove(index)) {
+            let mut content = lock(&slot);
+            if content.is_some() {
+                *content = Some(message);
+                drop(content);
+                self.replaced_sent.fetch_add(1, Ordering::Relaxed);
+                pending.push_back((key, slot));
+                return Ok(());
+            }
+        }
+        let slot = Arc::new(Mutex::new(Some(message)));
+        pending.push_back((key, slot.clone()));
+        self.send_envelope(Envelope::Coalescable(slot))
+    }
+
+    fn send_envelope(&self, envelope: Envelope<S>) -> Result<(), Disconnected> {
+        self.sender.send(envelope).map_err(|_| self.disconnect())
+    }
+
+    /// All messages that have arrived so far, without waiting for more
+    pub fn receive(&self) -> Vec<R> {
+        let mut messages = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(envelope) => messages.extend(open(envelope)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnect();
+                    break;
+                }
+            }
+        }
+        messages
+    }
+
+    /// Waits for the next message. Messages that were sent before the other end was dropped are still received.
+    pub fn receive_blocking(&self) -> Result<R, Disconnected> {
+        loop {
+            let envelope = self.receiver.recv().map_err(|_| self.disconnect())?;
+            if let Some(message) = open(envelope) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Waits for the next message, but at most for `timeout`. Returns None if no message arrived in time.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<Option<R>, Disconnected> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(envelope) => Ok(open(envelope)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.disconnect()),
+        }
+    }
+
+    /// Whether the other end of the channel was dropped, as far as sending and receiving have shown so far
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// How many coalescable messages sent to this end were replaced by newer ones before they could be received
+    pub fn dropped(&self) -> usize {
+        self.replaced_received.load(Ordering::Relaxed)
+    }
+
+    fn disconnect(&self) -> Disconnected {
+        self.disconnected.store(true, Ordering::Relaxed);
+        Disconnected
+    }
+}
+
+fn open<T>(envelope: Envelope<T>) -> Option<T> {
+    match envelope {
+        Envelope::Message(message) => Some(message),
+        Envelope::Coalescable(slot) => lock(&slot).take(),
+    }
+}
+
+/// Locks a mutex even if another thread panicked while holding it, since the data is replaced as a whole
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Writes a file through a temporary file next to it, which replaces it once it's complete. A crash can't leave the
+/// file half written that way. Missing directories are created.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        fs::create_dir_all(directory)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut file = fs::File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+/// Scratch buffers that computations return once they're done with them, so that the next computation needing a
+/// buffer of the same type and length doesn't have to allocate one. Recomputing a layer over and over, e.g. while a
+/// slider is dragged, then doesn't keep the allocator busy. Returned buffers are dropped instead of kept once the
+/// pool retains `max_retained_bytes`.
+pub struct BufferPool {
+    max_retained_bytes: usize,
+    buffers: Mutex<HashMap<(TypeId, usize), Vec<Retained>>>, // Keyed by element type and length
+    retained_bytes: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+type Retained = Box<dyn Any + Send>; // A `Vec` of some type
+
+/// How well a `BufferPool` is doing, e.g. for showing it in a profiler
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub hits: usize,   // Buffers that were reused
+    pub misses: usize, // Buffers that had to be allocated
+    pub retained_bytes: usize,
+}
+
+impl BufferPool {
+    pub const DEFAULT_MAX_RETAINED_BYTES: usize = 64 << 20;
+
+    pub fn new(max_retained_bytes: usize) -> Self {
+        Self {
+            max_retained_bytes,
+            buffers: Mutex::new(HashMap::new()),
+            retained_bytes: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// A buffer of `length` default values, which goes back to the pool when it's dropped
+    pub fn take<T: Clone + Default + Send + 'static>(&self, length: usize) -> Pooled<'_, T> {
+        let reused = lock(&self.buffers).get_mut(&(TypeId::of::<T>(), length)).and_then(Vec::pop);
+        let buffer = match reused.map(|buffer| buffer.downcast::<Vec<T>>()) {
+            Some(Ok(mut buffer)) => {
+                self.retained_bytes.fetch_sub(std::mem::size_of_val(buffer.as_slice()), Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buffer.fill(T::default());
+                *buffer
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                vec![T::default(); length]
+            }
+        };
+        Pooled { buffer, pool: Some(self) }
+    }
+
+    /// Like `take`, but allocates a buffer that isn't returned anywhere if there's no pool
+    pub fn take_from<T: Clone + Default + Send + 'static>(pool: Option<&Self>, length: usize) -> Pooled<'_, T> {
+        match pool {
+            Some(pool) => pool.take(length),
+            None => Pooled { buffer: vec![T::default(); length], pool: None },
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            retained_bytes: self.retained_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drops all retained buffers
+    pub fn clear(&self) {
+        lock(&self.buffers).clear();
+        self.retained_bytes.store(0, Ordering::Relaxed);
+    }
+
+    fn recycle<T: Send + 'static>(&self, buffer: Vec<T>) {
+        let bytes = std::mem::size_of_val(buffer.as_slice());
+        let mut buffers = lock(&self.buffers);
+        // Checked while holding the lock, so that concurrent returns can't exceed the limit together
+        if self.retained_bytes.load(Ordering::Relaxed) + bytes > self.max_retained_bytes {
+            return;
+        }
+        self.retained_bytes.fetch_add(bytes, Ordering::Relaxed);
+        buffers.entry((TypeId::of::<T>(), buffer.len())).or_default().push(Box::new(buffer));
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_RETAINED_BYTES)
+    }
+}
+
+/// A buffer taken from a `BufferPool`
+pub struct Pooled<'a, T: Send + 'static> {
+    buffer: Vec<T>,
+    pool: Option<&'a BufferPool>,
+}
+
+impl<T: Send + 'static> Deref for Pooled<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.buffer
+    }
+}
+
+impl<T: Send + 'static> DerefMut for Pooled<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.buffer
+    }
+}
+
+impl<T: Send + 'static> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool {
+            pool.recycle(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+/// A hasher whose results stay the same across program runs and versions of Rust, unlike those of the standard
+/// library, so that they can be stored, e.g. as the names of cached files. It's FNV-1a, applied to whole words where
+/// possible to be fast on large images, with a final mix of the bits.
+#[derive(Clone, Debug)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn mix(&mut self, word: u64) {
+        self.0 = (self.0 ^ word).wrapping_mul(Self::PRIME);
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::hash::Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        // The last bytes only reach the low bits through the multiplication, so spread them over all bits
+        let mut hash = self.0;
+        hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let words = bytes.chunks_exact(8);
+        let rest = words.remainder();
+        for word in words {
+            self.mix(u64::from_le_bytes(word.try_into().expect("8 bytes")));
+        }
+        for &byte in rest {
+            self.mix(u64::from(byte));
+        }
+        self.mix(bytes.len() as u64); // Otherwise trailing zero bytes wouldn't change the hash
+    }
+
+    fn write_u8(&mut self, value: u8) {
+        self.mix(value.into());
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.mix(value.into());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.mix(value.into());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.mix(value);
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.mix(value as u64);
+    }
+}
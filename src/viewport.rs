@@ -0,0 +1,969 @@
+use std::{hash::Hash, sync::Arc};
+
+use iced_graphics::{backend, Backend, Primitive, Renderer};
+use iced_native::{
+    event, image::Handle, keyboard, layout, mouse, Background, Clipboard, Color, Element, Event, Font, Hasher,
+    HorizontalAlignment, Layout, Length, Point, Rectangle, Size, Vector, VerticalAlignment, Widget,
+};
+
+use crate::{
+    draw,
+    entity::{Geometry, Rect},
+    graph_editor::Lines,
+};
+
+/// Regions are drawn on the viewport in pixels of the image at full resolution
+impl Rect {
+    /// The rectangle between two corners, clamped to an image of the given size
+    pub fn spanning(a: Point, b: Point, size: (u32, u32)) -> Self {
+        let clamp = |value: f32, extent: u32| value.round().clamp(0.0, extent as f32) as u32;
+        let (left, right) = (clamp(a.x.min(b.x), size.0), clamp(a.x.max(b.x), size.0));
+        let (top, bottom) = (clamp(a.y.min(b.y), size.1), clamp(a.y.max(b.y), size.1));
+        Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    /// Moved by `offset` pixels, but kept inside an image of the given size
+    pub fn moved(&self, offset: Vector, size: (u32, u32)) -> Self {
+        let shift = |start: u32, length: u32, offset: f32, extent: u32| {
+            let end = extent.saturating_sub(length) as f32;
+            (start as f32 + offset).round().clamp(0.0, end) as u32
+        };
+        Self {
+            x: shift(self.x, self.width, offset.x, size.0),
+            y: shift(self.y, self.height, offset.y, size.1),
+            ..*self
+        }
+    }
+
+    /// Clockwise, starting at the top left
+    fn corners(&self) -> [Point; 4] {
+        let (left, top) = (self.x as f32, self.y as f32);
+        let (right, bottom) = (left + self.width as f32, top + self.height as f32);
+        [
+            Point::new(left, top),
+            Point::new(right, top),
+            Point::new(right, bottom),
+            Point::new(left, bottom),
+        ]
+    }
+}
+
+/// What the brush of an `ImageViewport` did, in pixels of the image at full resolution
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrushEvent {
+    Painted(Point), // The brush was put down or moved while down
+    Lifted,         // The stroke is done
+}
+
+/// What the left mouse button did on the image of an `ImageViewport`, in pixels of the image at full resolution
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerEvent {
+    Pressed(Point), // The button went down over the image
+    Dragged(Point), // The cursor moved over the image while the button is down
+    Released,       // The button went up again
+}
+
+/// What is being done to the region of an `ImageViewport`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RegionGrab {
+    Corner(Point),                          // The opposite corner, which stays where it is
+    Move { start: Point, region: Rect }, // Where the cursor was in the image, and the region at the time
+}
+
+/// How an image is shown in an `ImageViewport`. The application keeps it between frames and replaces it with the
+/// states the viewport sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewState {
+    zoom: Option<f32>,             // Screen pixels per image pixel. None fits the image into the viewport
+    center: Vector,                // Point of the image in the middle of the viewport, as a fraction of its size
+    grab: Option<(Point, Vector)>, // Cursor position and center when dragging started
+    divider: f32,                  // Where the two sides of a comparison meet, as a fraction of the viewport width
+    divider_grabbed: bool,         // Whether the divider is being dragged instead of the image
+    swapped: bool,                 // Whether the reference of a comparison is on the right instead of the left
+    region_grab: Option<RegionGrab>,
+    painting: bool, // Whether the button is down for the brush or the pointer
+}
+
+impl ViewState {
+    const MIN_ZOOM: f32 = 0.01;
+    const MAX_ZOOM: f32 = 64.0;
+    const ZOOM_STEP: f32 = 1.2; // Per line scrolled
+    const PIXELS_PER_LINE: f32 = 40.0;
+    const DIVIDER_REACH: f32 = 6.0; // How close to the divider it can be grabbed, in screen pixels
+    const CORNER_REACH: f32 = 8.0; // How close to the corner of a region it can be grabbed, in screen pixels
+
+    /// The whole image, as large as the viewport allows
+    pub fn fit() -> Self {
+        Self {
+            zoom: None,
+            center: Vector::new(0.5, 0.5),
+            grab: None,
+            divider: 0.5,
+            divider_grabbed: false,
+            swapped: false,
+            region_grab: None,
+            painting: false,
+        }
+    }
+
+    /// Like `fit`, but a comparison keeps its divider where it is
+    pub fn fitted(&self) -> Self {
+        Self {
+            divider: self.divider,
+            swapped: self.swapped,
+            ..Self::fit()
+        }
+    }
+
+    /// One screen pixel per image pixel, centered on the same part of the image
+    pub fn actual_size(&self) -> Self {
+        Self {
+            zoom: Some(1.0),
+            grab: None,
+            ..*self
+        }
+    }
+
+    /// Screen pixels per image pixel, unless the image is fitted into the viewport
+    pub fn fixed_zoom(&self) -> Option<f32> {
+        self.zoom
+    }
+
+    /// Screen pixels per image pixel
+    pub fn zoom(&self, image: Size, viewport: Size) -> f32 {
+        self.zoom.unwrap_or_else(|| {
+            let fit = (viewport.width / image.width).min(viewport.height / image.height);
+            if fit.is_finite() && fit > 0.0 {
+                fit
+            } else {
+                1.0
+            }
+        })
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grab.is_some() || self.divider_grabbed || self.region_grab.is_some()
+    }
+
+    pub fn is_swapped(&self) -> bool {
+        self.swapped
+    }
+
+    /// Puts the reference of a comparison on the other side of the divider
+    pub fn swapped(&self) -> Self {
+        Self {
+            swapped: !self.swapped,
+            ..*self
+        }
+    }
+
+    /// Horizontal screen position of the divider of a comparison
+    pub fn divider(&self, viewport: Rectangle) -> f32 {
+        viewport.x + self.divider * viewport.width
+    }
+
+    pub fn is_on_divider(&self, cursor: Point, viewport: Rectangle) -> bool {
+        viewport.contains(cursor) && (cursor.x - self.divider(viewport)).abs() <= Self::DIVIDER_REACH
+    }
+
+    /// Starts moving the divider of a comparison instead of the image
+    pub fn grabbed_divider(&self) -> Self {
+        Self {
+            grab: None,
+            divider_grabbed: true,
+            region_grab: None,
+            ..*self
+        }
+    }
+
+    /// Where the image ends up on the screen
+    pub fn image_bounds(&self, image: Size, viewport: Rectangle) -> Rectangle {
+        let zoom = self.zoom(image, viewport.size());
+        let (width, height) = (image.width * zoom, image.height * zoom);
+        Rectangle {
+            x: viewport.center_x() - self.center.x * width,
+            y: viewport.center_y() - self.center.y * height,
+            width,
+            height,
+        }
+    }
+
+    /// The point of the image under `cursor`, as a fraction of its size, or None if the cursor isn't over the image
+    pub fn image_position(&self, cursor: Point, image: Size, viewport: Rectangle) -> Option<Point> {
+        if !viewport.contains(cursor) {
+            return None;
+        }
+        let bounds = self.image_bounds(image, viewport);
+        let position = Point::new((cursor.x - bounds.x) / bounds.width, (cursor.y - bounds.y) / bounds.height);
+        let inside = |fraction: f32| (0.0..1.0).contains(&fraction);
+        (inside(position.x) && inside(position.y)).then_some(position)
+    }
+
+    /// The point of the image under `cursor`, in pixels of the image at full resolution. `image` is the size of the
+    /// image that is shown, which might be a downscaled preview.
+    pub fn to_image(&self, cursor: Point, image: Size, viewport: Rectangle, full_size: (u32, u32)) -> Point {
+        let bounds = self.image_bounds(image, viewport);
+        Point::new(
+            (cursor.x - bounds.x) / bounds.width * full_size.0 as f32,
+            (cursor.y - bounds.y) / bounds.height * full_size.1 as f32,
+        )
+    }
+
+    /// Like `to_image`, but None if the cursor isn't over the image
+    pub fn pixel_at(&self, cursor: Point, image: Size, viewport: Rectangle, full_size: (u32, u32)) -> Option<Point> {
+        self.image_position(cursor, image, viewport)?;
+        Some(self.to_image(cursor, image, viewport, full_size))
+    }
+
+    /// Where a point of the image at full resolution is on the screen, see `to_image`
+    pub fn to_screen(&self, point: Point, image: Size, viewport: Rectangle, full_size: (u32, u32)) -> Point {
+        let bounds = self.image_bounds(image, viewport);
+        Point::new(
+            bounds.x + point.x / full_size.0 as f32 * bounds.width,
+            bounds.y + point.y / full_size.1 as f32 * bounds.height,
+        )
+    }
+
+    pub fn is_painting(&self) -> bool {
+        self.painting
+    }
+
+    /// Puts the brush or the pointer down
+    pub fn painting(&self) -> Self {
+        Self {
+            grab: None,
+            divider_grabbed: false,
+            region_grab: None,
+            painting: true,
+            ..*self
+        }
+    }
+
+    /// Starts changing `region` with the cursor: a corner near the cursor is dragged, a region under it is moved, and
+    /// otherwise a new region is drawn
+    pub fn grabbed_region(
+        &self,
+        cursor: Point,
+        region: Option<Rect>,
+        image: Size,
+        viewport: Rectangle,
+        full_size: (u32, u32),
+    ) -> Self {
+        let point = self.to_image(cursor, image, viewport, full_size);
+        let new = RegionGrab::Corner(point);
+        let grab = match region.filter(|region| !region.is_empty()) {
+            None => new,
+            Some(region) => {
+                let corners = region.corners();
+                let screen = corners.map(|corner| self.to_screen(corner, image, viewport, full_size));
+                let near = |corner: &Point| corner.distance(cursor) <= Self::CORNER_REACH;
+                let inside = (corners[0].x..corners[2].x).contains(&point.x)
+                    && (corners[0].y..corners[2].y).contains(&point.y);
+                match screen.iter().position(near) {
+                    Some(i) => RegionGrab::Corner(corners[(i + 2) % 4]),
+                    None if inside => RegionGrab::Move { start: point, region },
+                    None => new,
+                }
+            }
+        };
+        Self {
+            grab: None,
+            divider_grabbed: false,
+            region_grab: Some(grab),
+            ..*self
+        }
+    }
+
+    /// The region that follows the cursor while it is being changed, clamped to the image
+    pub fn dragged_region(
+        &self,
+        cursor: Point,
+        image: Size,
+        viewport: Rectangle,
+        full_size: (u32, u32),
+    ) -> Option<Rect> {
+        let point = self.to_image(cursor, image, viewport, full_size);
+        match self.region_grab? {
+            RegionGrab::Corner(anchor) => Some(Rect::spanning(anchor, point, full_size)),
+            RegionGrab::Move { start, region } => Some(region.moved(point - start, full_size)),
+        }
+    }
+
+    /// Zooms in (`lines > 0`) or out while the image point under `cursor` stays where it is
+    pub fn zoomed(&self, lines: f32, cursor: Point, image: Size, viewport: Rectangle) -> Self {
+        let zoom = self.zoom(image, viewport.size());
+        let new_zoom = (zoom * Self::ZOOM_STEP.powf(lines)).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        let bounds = self.image_bounds(image, viewport);
+        // Fraction of the image under the cursor, which has to stay under the cursor
+        let anchor = Vector::new((cursor.x - bounds.x) / bounds.width, (cursor.y - bounds.y) / bounds.height);
+        let offset = Vector::new(cursor.x - viewport.center_x(), cursor.y - viewport.center_y());
+        let center = Vector::new(
+            anchor.x - offset.x / (image.width * new_zoom),
+            anchor.y - offset.y / (image.height * new_zoom),
+        );
+        Self {
+            zoom: Some(new_zoom),
+            center,
+            grab: None,
+            divider_grabbed: false,
+            region_grab: None,
+            ..*self
+        }
+        .clamped()
+    }
+
+    pub fn grabbed(&self, cursor: Point) -> Self {
+        Self {
+            grab: Some((cursor, self.center)),
+            divider_grabbed: false,
+            region_grab: None,
+            ..*self
+        }
+    }
+
+    /// Follows the cursor while dragging
+    pub fn dragged(&self, cursor: Point, image: Size, viewport: Rectangle) -> Self {
+        if self.divider_grabbed {
+            let divider = (cursor.x - viewport.x) / viewport.width;
+            return Self {
+                divider: if divider.is_finite() { divider.clamp(0.0, 1.0) } else { 0.5 },
+                ..*self
+            };
+        }
+        let (start, start_center) = match self.grab {
+            Some(grab) => grab,
+            None => return *self,
+        };
+        let zoom = self.zoom(image, viewport.size());
+        let center = Vector::new(
+            start_center.x - (cursor.x - start.x) / (image.width * zoom),
+            start_center.y - (cursor.y - start.y) / (image.height * zoom),
+        );
+        Self {
+            zoom: Some(zoom),
+            center,
+            ..*self
+        }
+        .clamped()
+    }
+
+    pub fn released(&self) -> Self {
+        Self {
+            grab: None,
+            divider_grabbed: false,
+            region_grab: None,
+            painting: false,
+            ..*self
+        }
+    }
+
+    /// Keeps the middle of the viewport on the image, so that it can't get lost off-screen
+    fn clamped(self) -> Self {
+        Self {
+            center: Vector::new(self.center.x.clamp(0.0, 1.0), self.center.y.clamp(0.0, 1.0)),
+            ..self
+        }
+    }
+}
+
+impl Default for ViewState {
+    fn default() -> Self {
+        Self::fit()
+    }
+}
+
+/// Shapes drawn on top of the image of an `ImageViewport`
+struct Overlay {
+    geometry: Arc<Geometry>,
+    size: (u32, u32), // Of the image the shapes are given in pixels of
+    color: Color,
+    width: f32, // In screen pixels
+}
+
+/// What the cursor is over in an `ImageViewport`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hover {
+    pub position: Point, // As a fraction of the size of the image
+    pub reference: bool, // Whether it's the reference side of a comparison
+}
+
+/// Shows an image that can be zoomed with the mouse wheel and panned by dragging. F fits the image into the
+/// viewport, 1 shows it at 100 %. With `Length::Shrink`, the viewport takes the size of the image.
+///
+/// With a reference image to compare against, the reference is shown on one side of a divider that can be dragged,
+/// and the image on the other. The reference is stretched over the image, so that the same features line up even if
+/// it has a different size. S swaps the sides.
+///
+/// The viewport can report what the cursor is over and show a readout next to it, e.g. the value of the pixel there.
+/// Shapes found in the image can be drawn on top of it.
+///
+/// With a region to edit, dragging draws a new region, moves it, or moves one of its corners. With a brush, dragging
+/// paints, and with a picker, clicking reports the position on the image. A pointer reports presses and drags of the
+/// left button in pixels of the image. The image can still be panned with the middle mouse button. Nothing is
+/// reported for the cursor outside of the image.
+pub struct ImageViewport<Message> {
+    handle: Handle,
+    reference: Option<Handle>,
+    state: ViewState,
+    on_change: Box<dyn Fn(ViewState) -> Message>,
+    on_hover: Option<Box<dyn Fn(Option<Hover>) -> Message>>,
+    readout: Option<String>,
+    region: Option<Rect>,
+    full_size: (u32, u32), // Of the image, which might be shown downscaled
+    on_region: Option<Box<dyn Fn(Rect) -> Message>>,
+    brush: Option<f32>, // Radius in pixels of the image at full resolution
+    on_brush: Option<Box<dyn Fn(BrushEvent) -> Message>>,
+    on_pick: Option<Box<dyn Fn(Hover) -> Message>>,
+    on_pointer: Option<Box<dyn Fn(PointerEvent) -> Message>>,
+    overlay: Option<Overlay>,
+    width: Length,
+    height: Length,
+}
+
+impl<Message> ImageViewport<Message> {
+    pub fn new(handle: Handle, state: ViewState, on_change: impl Fn(ViewState) -> Message + 'static) -> Self {
+        Self {
+            handle,
+            reference: None,
+            state,
+            on_change: Box::new(on_change),
+            on_hover: None,
+            readout: None,
+            region: None,
+            full_size: (0, 0),
+            on_region: None,
+            brush: None,
+            on_brush: None,
+            on_pick: None,
+            on_pointer: None,
+            overlay: None,
+            width: Length::Fill,
+            height: Length::Fill,
+        }
+    }
+
+    pub fn width(mut self, width: Length) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: Length) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Compares the image to `reference`. Both handles should be kept between frames, so that moving the divider
+    /// doesn't upload the images again.
+    pub fn compare(mut self, reference: Handle) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Reports what the cursor is over whenever it moves, or None once it left the image
+    pub fn on_hover(mut self, on_hover: impl Fn(Option<Hover>) -> Message + 'static) -> Self {
+        self.on_hover = Some(Box::new(on_hover));
+        self
+    }
+
+    /// Text shown next to the cursor while it is over the image
+    pub fn readout(mut self, readout: String) -> Self {
+        self.readout = Some(readout);
+        self
+    }
+
+    /// Lets the user draw and change a region of the image, which is reported whenever it changes. `full_size` is the
+    /// size of the image at full resolution, which regions are given in.
+    pub fn region(
+        mut self,
+        region: Option<Rect>,
+        full_size: (u32, u32),
+        on_region: impl Fn(Rect) -> Message + 'static,
+    ) -> Self {
+        self.region = region;
+        self.full_size = full_size;
+        self.on_region = Some(Box::new(on_region));
+        self
+    }
+
+    /// Lets the user paint on the image with a round brush of the given radius. `full_size` is the size of the image at
+    /// full resolution, which the brush reports positions in.
+    pub fn brush(
+        mut self,
+        radius: f32,
+        full_size: (u32, u32),
+        on_brush: impl Fn(BrushEvent) -> Message + 'static,
+    ) -> Self {
+        self.brush = Some(radius);
+        self.full_size = full_size;
+        self.on_brush = Some(Box::new(on_brush));
+        self
+    }
+
+    /// Reports where the image is clicked, e.g. for picking a color from it
+    pub fn picker(mut self, on_pick: impl Fn(Hover) -> Message + 'static) -> Self {
+        self.on_pick = Some(Box::new(on_pick));
+        self
+    }
+
+    /// Reports presses and drags of the left mouse button on the image. `full_size` is the size of the image at full
+    /// resolution, which positions are given in.
+    pub fn pointer(mut self, full_size: (u32, u32), on_pointer: impl Fn(PointerEvent) -> Message + 'static) -> Self {
+        self.full_size = full_size;
+        self.on_pointer = Some(Box::new(on_pointer));
+        self
+    }
+
+    /// Draws shapes on top of the image: lines, crosses at points and the outlines of polygons. `size` is the size of
+    /// the image the shapes are given in pixels of, which is stretched over the image that is shown. The shapes are
+    /// drawn `width` screen pixels wide, whatever the zoom.
+    pub fn overlay(mut self, geometry: Arc<Geometry>, size: (u32, u32), color: Color, width: f32) -> Self {
+        self.overlay = Some(Overlay {
+            geometry,
+            size,
+            color,
+            width,
+        });
+        self
+    }
+
+    fn image_size<B: Backend + backend::Image>(&self, renderer: &Renderer<B>) -> Size {
+        dimensions(renderer, &self.handle)
+    }
+
+    /// An outline of the region with a handle on each corner
+    fn draw_region(&self, region: Rect, image: Size, bounds: Rectangle) -> Primitive {
+        const COLOR: Color = Color::from_rgb(1.0, 0.8, 0.0);
+        const HANDLE: f32 = 8.0;
+        let corners = region.corners().map(|corner| self.state.to_screen(corner, image, bounds, self.full_size));
+        let mut primitives = vec![Primitive::Quad {
+            bounds: Rectangle::new(corners[0], Size::new(corners[2].x - corners[0].x, corners[2].y - corners[0].y)),
+            background: Background::Color(Color::TRANSPARENT),
+            border_radius: 0.0,
+            border_width: 2.0,
+            border_color: COLOR,
+        }];
+        for corner in corners {
+            primitives.push(Primitive::Quad {
+                bounds: Rectangle::new(corner - Vector::new(HANDLE, HANDLE) * 0.5, Size::new(HANDLE, HANDLE)),
+                background: Background::Color(COLOR),
+                border_radius: 0.0,
+                border_width: 1.0,
+                border_color: Color::BLACK,
+            });
+        }
+        Primitive::Clip {
+            bounds,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Group { primitives }),
+        }
+    }
+
+    /// The shapes of the overlay, only on the side of the image in a comparison
+    fn draw_overlay(&self, overlay: &Overlay, image: Size, bounds: Rectangle) -> Primitive {
+        let image_bounds = self.state.image_bounds(image, bounds);
+        let (width, height) = (overlay.size.0.max(1) as f32, overlay.size.1.max(1) as f32);
+        // Relative to the viewport, which the lines are moved to as a whole
+        let screen = |(x, y): (f32, f32)| {
+            (
+                image_bounds.x - bounds.x + x / width * image_bounds.width,
+                image_bounds.y - bounds.y + y / height * image_bounds.height,
+            )
+        };
+        let (color, line_width) = (overlay.color, overlay.width);
+        // The same strokes as drawn into images by `DrawGeometry`, but on the screen
+        let mut lines = Lines::default();
+        for stroke in draw::strokes(&overlay.geometry.map(screen), draw::arm(line_width)) {
+            let points: Vec<_> = stroke.into_iter().map(|(x, y)| Point::new(x, y)).collect();
+            lines.push(&points, line_width, color);
+        }
+        let clip = match &self.reference {
+            Some(reference) => {
+                let sides = self.sides(reference, bounds);
+                sides.into_iter().find(|&(_, _, label)| label == "Selected").map_or(bounds, |(side, _, _)| side)
+            }
+            None => bounds,
+        };
+        Primitive::Clip {
+            bounds: clip,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Translate {
+                translation: Vector::new(bounds.x, bounds.y),
+                content: Box::new(lines.into_primitive(bounds.size())),
+            }),
+        }
+    }
+
+    /// The outline of the brush around the cursor, in black and white so that it shows on any image
+    fn draw_brush(&self, radius: f32, cursor: Point, image: Size, bounds: Rectangle) -> Primitive {
+        let radius = radius * self.state.image_bounds(image, bounds).width / self.full_size.0.max(1) as f32;
+        let circle = |radius: f32, color| Primitive::Quad {
+            bounds: Rectangle::new(cursor - Vector::new(radius, radius), Size::new(2.0 * radius, 2.0 * radius)),
+            background: Background::Color(Color::TRANSPARENT),
+            border_radius: radius,
+            border_width: 1.0,
+            border_color: color,
+        };
+        Primitive::Clip {
+            bounds,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Group {
+                primitives: vec![circle(radius + 1.0, Color::BLACK), circle(radius, Color::WHITE)],
+            }),
+        }
+    }
+
+    fn hover(&self, cursor: Point, image: Size, bounds: Rectangle) -> Option<Hover> {
+        let position = self.state.image_position(cursor, image, bounds)?;
+        let left = cursor.x < self.state.divider(bounds);
+        Some(Hover {
+            position,
+            reference: self.reference.is_some() && left != self.state.swapped,
+        })
+    }
+
+    fn draw_readout(&self, readout: &str, cursor: Point, bounds: Rectangle) -> Primitive {
+        const SIZE: f32 = 14.0;
+        const PADDING: f32 = 4.0;
+        // Wide enough for digits, which is what readouts are mostly made of
+        let size = Size::new(readout.chars().count() as f32 * SIZE * 0.6 + 2.0 * PADDING, SIZE + 2.0 * PADDING);
+        let top_left = Point::new(cursor.x + 16.0, cursor.y + 16.0);
+        let background = Primitive::Quad {
+            bounds: Rectangle::new(top_left, size),
+            background: Background::Color(Color::from_rgba(0.0, 0.0, 0.0, 0.7)),
+            border_radius: 2.0,
+            border_width: 0.0,
+            border_color: Color::TRANSPARENT,
+        };
+        let text = Primitive::Text {
+            content: readout.to_string(),
+            bounds: Rectangle::new(top_left + Vector::new(PADDING, PADDING), size),
+            color: Color::WHITE,
+            size: SIZE,
+            font: Font::Default,
+            horizontal_alignment: HorizontalAlignment::Left,
+            vertical_alignment: VerticalAlignment::Top,
+        };
+        Primitive::Clip {
+            bounds,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Group {
+                primitives: vec![background, text],
+            }),
+        }
+    }
+
+    /// The two sides of a comparison with what they show, from left to right
+    fn sides(&self, reference: &Handle, bounds: Rectangle) -> [(Rectangle, Handle, &'static str); 2] {
+        let divider = self.state.divider(bounds);
+        let left = Rectangle {
+            width: divider - bounds.x,
+            ..bounds
+        };
+        let right = Rectangle {
+            x: divider,
+            width: bounds.x + bounds.width - divider,
+            ..bounds
+        };
+        let (reference, image) = ((reference.clone(), "Reference"), (self.handle.clone(), "Selected"));
+        let [(first, first_label), (second, second_label)] = if self.state.swapped {
+            [image, reference]
+        } else {
+            [reference, image]
+        };
+        [(left, first, first_label), (right, second, second_label)]
+    }
+
+    fn draw_comparison(&self, reference: &Handle, image: Size, reference_size: Size, bounds: Rectangle) -> Primitive {
+        let image_bounds = self.state.image_bounds(image, bounds);
+        let mut primitives = Vec::new();
+        for (side, handle, label) in self.sides(reference, bounds) {
+            let mut label = label.to_string();
+            if label == "Reference" && reference_size != image {
+                label = format!(
+                    "Reference, scaled from {}×{}",
+                    reference_size.width, reference_size.height
+                );
+            }
+            let alignment = if side.x == bounds.x {
+                HorizontalAlignment::Left
+            } else {
+                HorizontalAlignment::Right
+            };
+            let content = vec![
+                Primitive::Image {
+                    handle,
+                    bounds: image_bounds,
+                },
+                Primitive::Text {
+                    content: label,
+                    bounds: Rectangle {
+                        x: if side.x == bounds.x { bounds.x + 8.0 } else { bounds.x + bounds.width - 8.0 },
+                        y: bounds.y + 8.0,
+                        ..bounds
+                    },
+                    color: Color::WHITE,
+                    size: 16.0,
+                    font: Font::Default,
+                    horizontal_alignment: alignment,
+                    vertical_alignment: VerticalAlignment::Top,
+                },
+            ];
+            primitives.push(Primitive::Clip {
+                bounds: side,
+                offset: Vector::new(0, 0),
+                content: Box::new(Primitive::Group { primitives: content }),
+            });
+        }
+        let divider = self.state.divider(bounds);
+        primitives.push(Primitive::Clip {
+            bounds,
+            offset: Vector::new(0, 0),
+            content: Box::new(Primitive::Quad {
+                bounds: Rectangle::new(Point::new(divider - 1.0, bounds.y), Size::new(2.0, bounds.height)),
+                background: Background::Color(Color::WHITE),
+                border_radius: 0.0,
+                border_width: 0.0,
+                border_color: Color::TRANSPARENT,
+            }),
+        });
+        Primitive::Group { primitives }
+    }
+}
+
+fn dimensions<B: Backend + backend::Image>(renderer: &Renderer<B>, handle: &Handle) -> Size {
+    let (width, height) = renderer.backend().dimensions(handle);
+    Size::new(width as f32, height as f32)
+}
+
+impl<Message, B> Widget<Message, Renderer<B>> for ImageViewport<Message>
+where
+    B: Backend + backend::Image,
+{
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, renderer: &Renderer<B>, limits: &layout::Limits) -> layout::Node {
+        // A viewport that shrinks takes the size of the image, scaled down to fit while keeping its aspect ratio
+        let limits = limits.width(self.width).height(self.height);
+        let (image, max) = (self.image_size(renderer), limits.max());
+        let scale = (max.width / image.width).min(max.height / image.height).min(1.0);
+        layout::Node::new(limits.resolve(Size::new(image.width * scale, image.height * scale)))
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Renderer<B>,
+        _defaults: &iced_graphics::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+    ) -> (Primitive, mouse::Interaction) {
+        let bounds = layout.bounds();
+        let image = self.image_size(renderer);
+        let primitive = match &self.reference {
+            Some(reference) => self.draw_comparison(reference, image, dimensions(renderer, reference), bounds),
+            None => Primitive::Clip {
+                bounds,
+                offset: Vector::new(0, 0),
+                content: Box::new(Primitive::Image {
+                    handle: self.handle.clone(),
+                    bounds: self.state.image_bounds(image, bounds),
+                }),
+            },
+        };
+        let primitive = match &self.overlay {
+            Some(overlay) => Primitive::Group {
+                primitives: vec![primitive, self.draw_overlay(overlay, image, bounds)],
+            },
+            None => primitive,
+        };
+        let primitive = match self.region.filter(|region| self.on_region.is_some() && !region.is_empty()) {
+            Some(region) => Primitive::Group {
+                primitives: vec![primitive, self.draw_region(region, image, bounds)],
+            },
+            None => primitive,
+        };
+        let primitive = match self.brush {
+            Some(radius) if bounds.contains(cursor_position) => Primitive::Group {
+                primitives: vec![primitive, self.draw_brush(radius, cursor_position, image, bounds)],
+            },
+            _ => primitive,
+        };
+        let primitive = match &self.readout {
+            Some(readout) if self.state.image_position(cursor_position, image, bounds).is_some() => {
+                Primitive::Group {
+                    primitives: vec![primitive, self.draw_readout(readout, cursor_position, bounds)],
+                }
+            }
+            _ => primitive,
+        };
+        let comparing = self.reference.is_some();
+        let interaction = if self.state.divider_grabbed
+            || (comparing && !self.state.is_grabbed() && self.state.is_on_divider(cursor_position, bounds))
+        {
+            mouse::Interaction::ResizingHorizontally
+        } else if self.state.is_grabbed() {
+            mouse::Interaction::Grabbing
+        } else if (self.on_region.is_some() || self.brush.is_some() || self.on_pick.is_some())
+            && bounds.contains(cursor_position)
+        {
+            mouse::Interaction::Crosshair
+        } else if bounds.contains(cursor_position) {
+            mouse::Interaction::Grab
+        } else {
+            mouse::Interaction::Idle
+        };
+        (primitive, interaction)
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+        self.width.hash(state);
+        self.height.hash(state);
+        self.handle.id().hash(state);
+        // The image bounds depend on the view, so it has to be redrawn whenever the view changes
+        self.state.zoom.map(f32::to_bits).hash(state);
+        self.state.center.x.to_bits().hash(state);
+        self.state.center.y.to_bits().hash(state);
+        if let Some(reference) = &self.reference {
+            reference.id().hash(state);
+            self.state.divider.to_bits().hash(state);
+            self.state.swapped.hash(state);
+        }
+        self.readout.hash(state);
+        self.region.hash(state);
+        self.brush.map(f32::to_bits).hash(state);
+        if let Some(overlay) = &self.overlay {
+            let geometry = &overlay.geometry;
+            (geometry.lines.len(), geometry.points.len(), geometry.polygons.len()).hash(state);
+            overlay.size.hash(state);
+            overlay.width.to_bits().hash(state);
+        }
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        renderer: &Renderer<B>,
+        _clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        let bounds = layout.bounds();
+        let image = self.image_size(renderer);
+        let is_over = bounds.contains(cursor_position);
+        let comparing = self.reference.is_some();
+        if let (Some(on_hover), Event::Mouse(mouse::Event::CursorMoved { position })) = (&self.on_hover, &event) {
+            messages.push(on_hover(self.hover(*position, image, bounds)));
+        }
+        let zooms = matches!(event, Event::Mouse(mouse::Event::WheelScrolled { .. }));
+        let state = match event {
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if is_over => {
+                let lines = match delta {
+                    mouse::ScrollDelta::Lines { y, .. } => y,
+                    mouse::ScrollDelta::Pixels { y, .. } => y / ViewState::PIXELS_PER_LINE,
+                };
+                self.state.zoomed(lines, cursor_position, image, bounds)
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) if is_over && self.on_pick.is_some() => {
+                if let (Some(on_pick), Some(hover)) = (&self.on_pick, self.hover(cursor_position, image, bounds)) {
+                    messages.push(on_pick(hover));
+                }
+                return event::Status::Captured;
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) if is_over => {
+                if comparing && self.state.is_on_divider(cursor_position, bounds) {
+                    self.state.grabbed_divider()
+                } else if self.on_region.is_some() {
+                    self.state.grabbed_region(cursor_position, self.region, image, bounds, self.full_size)
+                } else if let Some(on_brush) = &self.on_brush {
+                    if let Some(point) = self.state.pixel_at(cursor_position, image, bounds, self.full_size) {
+                        messages.push(on_brush(BrushEvent::Painted(point)));
+                    }
+                    self.state.painting()
+                } else if let Some(on_pointer) = &self.on_pointer {
+                    if let Some(point) = self.state.pixel_at(cursor_position, image, bounds, self.full_size) {
+                        messages.push(on_pointer(PointerEvent::Pressed(point)));
+                    }
+                    self.state.painting()
+                } else {
+                    self.state.grabbed(cursor_position)
+                }
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Middle)) if is_over => {
+                self.state.grabbed(cursor_position)
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if self.state.region_grab.is_some() => {
+                let region = self.state.dragged_region(position, image, bounds, self.full_size);
+                if let (Some(on_region), Some(region)) = (&self.on_region, region) {
+                    if !region.is_empty() && self.region != Some(region) {
+                        self.region = Some(region);
+                        messages.push(on_region(region));
+                    }
+                }
+                return event::Status::Captured;
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if self.state.painting => {
+                if let Some(point) = self.state.pixel_at(position, image, bounds, self.full_size) {
+                    if let Some(on_brush) = &self.on_brush {
+                        messages.push(on_brush(BrushEvent::Painted(point)));
+                    } else if let Some(on_pointer) = &self.on_pointer {
+                        messages.push(on_pointer(PointerEvent::Dragged(point)));
+                    }
+                }
+                return event::Status::Captured;
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) if self.state.painting => {
+                if let Some(on_brush) = &self.on_brush {
+                    messages.push(on_brush(BrushEvent::Lifted));
+                } else if let Some(on_pointer) = &self.on_pointer {
+                    messages.push(on_pointer(PointerEvent::Released));
+                }
+                self.state.released()
+            }
+            Event::Mouse(mouse::Event::CursorMoved { position }) if self.state.is_grabbed() => {
+                self.state.dragged(position, image, bounds)
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left | mouse::Button::Middle))
+                if self.state.is_grabbed() =>
+            {
+                self.state.released()
+            }
+            Event::Keyboard(keyboard::Event::KeyPressed { key_code, .. }) if is_over => match key_code {
+                keyboard::KeyCode::F => self.state.fitted(),
+                keyboard::KeyCode::Key1 | keyboard::KeyCode::Numpad1 => self.state.actual_size(),
+                keyboard::KeyCode::S if comparing => self.state.swapped(),
+                _ => return event::Status::Ignored,
+            },
+            _ => return event::Status::Ignored,
+        };
+        self.state = state;
+        messages.push((self.on_change)(state));
+        if let (Some(on_hover), true) = (&self.on_hover, zooms) {
+            // A different pixel is under the cursor now
+            messages.push(on_hover(self.hover(cursor_position, image, bounds)));
+        }
+        event::Status::Captured
+    }
+}
+
+impl<'a, Message: 'a, B> From<ImageViewport<Message>> for Element<'a, Message, Renderer<B>>
+where
+    B: Backend + backend::Image + 'a,
+{
+    fn from(viewport: ImageViewport<Message>) -> Self {
+        Element::new(viewport)
+    }
+}
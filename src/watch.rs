@@ -0,0 +1,99 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::error::Result;
+
+/// Notices changes to files
+pub trait FileWatcher {
+    /// Blocks until some of `paths` changed and returns those
+    fn wait_for_changes(&mut self, paths: &[PathBuf]) -> Result<Vec<PathBuf>>;
+
+    /// Like `wait_for_changes`, but also returns when `poll`, which is called regularly, reports that a source other
+    /// than the files changed, e.g. a remote image. Then the returned paths may be empty. Watchers that don't poll
+    /// never call it.
+    fn wait_for_changes_polling(&mut self, paths: &[PathBuf], _poll: &mut dyn FnMut() -> bool) -> Result<Vec<PathBuf>> {
+        self.wait_for_changes(paths)
+    }
+}
+
+/// Watches files by regularly checking their modification time and size. Changes are only reported once a file has
+/// been left alone for the debounce duration, since editors often write a file several times when saving.
+pub struct PollingWatcher {
+    interval: Duration,
+    debounce: Duration,
+    states: HashMap<PathBuf, Option<(SystemTime, u64)>>, // None if the file couldn't be read
+}
+
+impl PollingWatcher {
+    pub fn new(interval: Duration, debounce: Duration) -> Self {
+        Self {
+            interval,
+            debounce,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Remembers the current state of the files, so that only later changes are reported
+    pub fn track(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.states.insert(path.clone(), Self::state(path));
+        }
+    }
+
+    fn state(path: &PathBuf) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Paths whose state differs from the remembered one. The new state is remembered.
+    fn poll(&mut self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for path in paths {
+            let state = Self::state(path);
+            match self.states.insert(path.clone(), state) {
+                Some(previous) if previous == state => (),
+                None => (), // Newly tracked
+                Some(_) => changed.push(path.clone()),
+            }
+        }
+        changed
+    }
+}
+
+impl Default for PollingWatcher {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(250), Duration::from_millis(500))
+    }
+}
+
+impl FileWatcher for PollingWatcher {
+    fn wait_for_changes(&mut self, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        self.wait_for_changes_polling(paths, &mut || false)
+    }
+
+    fn wait_for_changes_polling(&mut self, paths: &[PathBuf], poll: &mut dyn FnMut() -> bool) -> Result<Vec<PathBuf>> {
+        self.poll(paths); // Start tracking paths that weren't tracked yet
+
+        let mut changed = BTreeSet::new();
+        let mut last_change = Instant::now();
+        let mut other_changed = false;
+        loop {
+            thread::sleep(self.interval);
+            other_changed |= poll();
+            let now_changed = self.poll(paths);
+            if !now_changed.is_empty() {
+                changed.extend(now_changed);
+                last_change = Instant::now();
+            } else if (!changed.is_empty() && last_change.elapsed() >= self.debounce)
+                || (changed.is_empty() && other_changed)
+            {
+                return Ok(changed.into_iter().collect());
+            }
+        }
+    }
+}
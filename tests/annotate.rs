@@ -0,0 +1,99 @@
+use image::{Rgba, RgbaImage};
+
+use klex::{
+    draw,
+    entity::{self, Contour, Line, Point},
+    layer::{primitive::Annotate, LayerOutput},
+    parameter::ParamValue,
+    registry::LayerRegistry,
+};
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+fn drawn(image: &RgbaImage) -> Vec<(u32, u32)> {
+    image.enumerate_pixels().filter(|(_, _, pixel)| pixel[3] > 0).map(|(x, y, _)| (x, y)).collect()
+}
+
+#[test]
+fn text_is_drawn_in_the_font_and_clipped() {
+    assert_eq!(draw::text_size("Hi", 2), (22, 14));
+    assert_eq!(draw::text_size("Hi\nthere", 1), (29, 16));
+    assert_eq!(draw::text_size("", 3), (0, 0));
+    let mut image = RgbaImage::new(30, 20);
+    draw::text(&mut image, "Hi", (1, 1), 2, WHITE);
+    let pixels = drawn(&image);
+    // 17 pixels of the font for the H and 9 for the i, each drawn as 2 by 2
+    assert_eq!(pixels.len(), 4 * (17 + 9));
+    assert!(pixels.iter().all(|&(x, y)| (1..23).contains(&x) && (1..15).contains(&y)));
+    assert!(pixels.contains(&(1, 1)) && pixels.contains(&(10, 14)) && !pixels.contains(&(3, 3)));
+
+    // Characters beyond ASCII are boxes of 20 pixels, one for each character rather than byte
+    let mut image = RgbaImage::new(30, 10);
+    draw::text(&mut image, "ü€", (0, 0), 1, WHITE);
+    assert_eq!(drawn(&image).len(), 40);
+    assert_eq!(draw::text_size("ü€", 1), draw::text_size("ab", 1));
+
+    // Whatever doesn't fit is left out
+    let mut image = RgbaImage::new(8, 8);
+    for corner in [(-4, -5), (6, 6), (i64::MAX, 0), (0, i64::MIN), (i64::MIN, i64::MAX)] {
+        draw::text(&mut image, "Overflow\nand more", corner, 3, WHITE);
+    }
+    draw::text(&mut image, "Large", (-10, -10), u32::MAX, [0, 0, 255, 128]);
+    draw::marker(&mut image, (f32::NAN, f32::INFINITY), u32::MAX / 4, WHITE);
+    assert_eq!(image.dimensions(), (8, 8));
+    assert_eq!(image.get_pixel(7, 7), &Rgba([0, 0, 255, 128]));
+    let mut opaque = RgbaImage::from_pixel(1, 1, Rgba(WHITE));
+    draw::blend(&mut opaque, 0, 0, [0, 0, 255, 128]);
+    assert_eq!(opaque.get_pixel(0, 0), &Rgba([127, 127, 255, 255]));
+}
+
+#[test]
+fn labels_show_measurements_of_shapes() {
+    let image = RgbaImage::new(40, 30);
+    let contours = vec![Contour { points: vec![(0.0, 0.0), (10.0, 0.0), (10.0, 4.0), (0.0, 4.0)] }];
+    let layer = Annotate::new("#{index}: area={area}, p={perimeter} at {x},{y}".to_string());
+    let labels = layer.labels(&image, entity::geometry(&contours).as_ref()).unwrap();
+    assert_eq!(labels, [((5.0, 2.0), "#0: area=40, p=28 at 5,2".to_string())]);
+
+    let lines = vec![Line { start: (0.0, 0.0), end: (1.0, 1.0) }, Line { start: (2.0, 2.0), end: (2.0, 5.0) }];
+    let layer = Annotate::new("{{{index}}} {length}".to_string());
+    let labels = layer.labels(&image, entity::geometry(&lines).as_ref()).unwrap();
+    assert_eq!(labels, [((0.5, 0.5), "{0} 1.41".to_string()), ((2.0, 3.5), "{1} 3".to_string())]);
+    let error = layer.labels(&image, None).unwrap_err().to_string();
+    assert!(error.contains("\"length\"") && error.contains("index, width, height"), "{}", error);
+    let size = Annotate::new("{width}x{height}".to_string()).labels(&image, None).unwrap();
+    assert_eq!(size, [((0.0, 0.0), "40x30".to_string())]);
+    for format in ["{index", "index}", "{}"] {
+        assert!(Annotate::new(format.to_string()).labels(&image, None).is_err(), "{:?}", format);
+    }
+}
+
+#[test]
+fn labels_are_drawn_next_to_markers() {
+    let image = RgbaImage::from_pixel(60, 40, Rgba([0, 0, 0, 255]));
+    let points = vec![Point { x: 10.0, y: 20.0 }, Point { x: 59.0, y: 39.0 }];
+    let mut layer = LayerRegistry::with_builtins().create_default("Annotate").unwrap();
+    layer.set_parameter("color", ParamValue::Color([255, 0, 0, 255])).unwrap();
+    let (input, shapes): (LayerOutput, LayerOutput) = (Some(Box::new(image.clone())), Some(Box::new(points)));
+    let mut output = None;
+    layer.compute(&[&shapes, &input], &mut output).unwrap();
+    let output = output.unwrap();
+    let annotated = output.downcast_ref::<RgbaImage>().unwrap();
+    assert_eq!(annotated.dimensions(), image.dimensions());
+    let red: Vec<_> = annotated.enumerate_pixels().filter(|(_, _, &pixel)| pixel == Rgba([255, 0, 0, 255])).collect();
+    assert!(red.iter().all(|(_, _, pixel)| pixel[3] == 255));
+    let red: Vec<_> = red.into_iter().map(|(x, y, _)| (x, y)).collect();
+    // The marker crosses at the point, and its label starts to the right of it
+    assert!(red.contains(&(10, 20)) && red.contains(&(8, 18)) && red.contains(&(12, 18)));
+    assert!(red.iter().any(|&(x, y)| x > 12 && (y as i32 - 20).abs() <= 4));
+    assert!(red.contains(&(59, 39)), "The second marker is clipped at the corner");
+
+    layer.set_parameter("markers", ParamValue::Bool(false)).unwrap();
+    layer.set_parameter("size", ParamValue::Int(7)).unwrap();
+    let mut output = None;
+    layer.compute(&[&input, &shapes], &mut output).unwrap();
+    let unmarked = output.unwrap();
+    assert!(unmarked.downcast_ref::<RgbaImage>().unwrap().get_pixel(10, 20)[0] == 0);
+    assert!(layer.compute(&[&shapes], &mut None).is_err(), "There has to be an image");
+    assert!(layer.compute(&[&input, &shapes, &shapes], &mut None).is_err());
+}
@@ -0,0 +1,1033 @@
+#![cfg(feature = "ui")]
+
+use std::{
+    cell::Cell,
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{imageops, GenericImageView, GrayImage, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    backend::{self, Backend, Data, JobQueue, Priority, Supervisor},
+    entity::{PixelValue, Stroke},
+    error::Result,
+    layer::{CancelToken, Layer, LayerOutput},
+    parameter::{ParamKind, ParamMap, ParamSpec, ParamValue, Parameter},
+    recipe::{Bindings, Recipe},
+    registry::LayerRegistry,
+    ui::{Event, GraphMirror, ImageHandle},
+    util::ThreadChannel,
+};
+
+/// Collects data from the backend until `done` returns true for one of the messages
+fn receive_until(channel: &ThreadChannel<Event, Data>, done: impl Fn(&Data) -> bool) -> Vec<Data> {
+    let start = Instant::now();
+    let mut received = Vec::new();
+    while start.elapsed() < Duration::from_secs(10) {
+        for data in channel.receive() {
+            let finished = done(&data);
+            received.push(data);
+            if finished {
+                return received;
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("Backend didn't answer in time, received {:?}", received);
+}
+
+#[test]
+fn backend_computes_requested_layers() {
+    let directory = std::env::temp_dir().join(format!("klex-backend-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("input.png");
+    GrayImage::from_raw(2, 1, vec![0, 200]).unwrap().save(&path).unwrap();
+
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    for (kind, parameters, inputs) in [
+        ("InputFile", parameters, vec![]),
+        ("Convert<RgbaImage, GrayImage>", ParamMap::new(), vec![NodeIndex::new(0)]),
+        ("Blur", ParamMap::new(), vec![]),
+    ] {
+        let kind = kind.to_string();
+        channel.send(Event::AddLayer { kind, parameters, inputs }).unwrap();
+    }
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    let added: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::LayerAdded { node, .. } => Some(node.index()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(added, vec![0, 1]);
+    assert!(received.iter().any(|data| matches!(data, Data::Error(error) if error.contains("\"Blur\""))));
+    let computed: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::ComputeFinished { node, .. } => Some(node.index()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(computed, vec![0, 1]);
+    assert!(matches!(received.last(), Some(Data::Preview { node, .. }) if node.index() == 1));
+
+    // Layers that are up to date aren't computed again
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    assert!(!received.iter().any(|data| matches!(data, Data::ComputeFinished { .. })));
+
+    fs::remove_file(directory.join("input.png")).unwrap();
+    channel.send(Event::RequestCompute(NodeIndex::new(5))).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::ComputeFailed { .. }));
+    assert!(matches!(received.last(), Some(Data::ComputeFailed { node, .. }) if node.index() == 5));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn backend_stops_when_the_ui_goes_away() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+
+    let parameters = ParamMap::from([("threshold".to_string(), ParamValue::Int(10))]);
+    channel
+        .send(Event::AddLayer {
+            kind: "Threshold".to_string(),
+            parameters,
+            inputs: vec![],
+        })
+        .unwrap();
+    drop(channel);
+    assert!(handle.join().unwrap().is_err());
+}
+
+/// Produces a single gray pixel. Computing takes until it is cancelled, unless the value is 0.
+struct Slow {
+    value: u8,
+    started: Arc<AtomicUsize>,
+}
+
+impl Layer for Slow {
+    fn kind(&self) -> String {
+        "Slow".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        self.compute_cancellable(input, output, &CancelToken::new())
+    }
+
+    fn compute_cancellable(
+        &self,
+        _input: &[&LayerOutput],
+        output: &mut LayerOutput,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        let start = Instant::now();
+        while self.value != 0 && start.elapsed() < Duration::from_secs(10) {
+            cancel.check()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+        *output = Some(Box::new(GrayImage::from_raw(1, 1, vec![self.value]).unwrap()));
+        Ok(())
+    }
+
+    fn parameters(&self) -> ParamMap {
+        ParamMap::from([("value".to_string(), ParamValue::Int(self.value.into()))])
+    }
+
+    fn set_parameter(&mut self, _name: &str, value: ParamValue) -> Result<()> {
+        self.value = Parameter::from_value(&value)?;
+        Ok(())
+    }
+}
+
+fn slow_registry(started: &Arc<AtomicUsize>) -> LayerRegistry {
+    let mut registry = LayerRegistry::new();
+    let spec = ParamSpec::new("value", ParamKind::Int { min: 0, max: 255 }, None);
+    let counter = started.clone();
+    registry.register("Slow", vec![spec], move |parameters| {
+        let value = match parameters["value"] {
+            ParamValue::Int(value) => value as u8,
+            _ => unreachable!("Parameters are validated by the registry"),
+        };
+        let started = counter.clone();
+        Ok(Box::new(Slow { value, started }))
+    });
+    registry
+}
+
+#[test]
+fn outdated_computations_are_cancelled() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, slow_registry(&started));
+    let handle = thread::spawn(move || backend.run());
+
+    let parameters = ParamMap::from([("value".to_string(), ParamValue::Int(1))]);
+    let kind = "Slow".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    let start = Instant::now();
+    while started.load(Ordering::SeqCst) == 0 {
+        assert!(start.elapsed() < Duration::from_secs(10), "Computation didn't start");
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let name = "value".to_string();
+    let node = NodeIndex::new(0);
+    channel.send(Event::SetParameter { node, name, value: ParamValue::Int(0) }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    assert!(start.elapsed() < Duration::from_secs(5), "Computation wasn't cancelled");
+    assert_eq!(started.load(Ordering::SeqCst), 2);
+
+    let finished = received.iter().filter(|data| matches!(data, Data::ComputeFinished { .. })).count();
+    assert_eq!(finished, 1);
+    let cancellation_logged = received.iter().any(|data| match data {
+        Data::LogBatch(records) => records.iter().any(|record| record.message == "Cancelled computing layer 0"),
+        _ => false,
+    });
+    assert!(cancellation_logged);
+    match received.last() {
+        Some(Data::Preview { image, .. }) => {
+            let expected = RgbaImage::from_raw(1, 1, vec![0, 0, 0, 255]).unwrap().handle();
+            assert_eq!(image.id(), expected.id());
+        }
+        data => panic!("Expected a preview, got {:?}", data),
+    }
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+fn set_value(node: usize, name: &str, value: i64) -> Event {
+    Event::SetParameter {
+        node: NodeIndex::new(node),
+        name: name.to_string(),
+        value: ParamValue::Int(value),
+    }
+}
+
+fn query(x: f32) -> Event {
+    Event::QueryPixel {
+        node: NodeIndex::new(0),
+        x,
+        y: 0.5,
+    }
+}
+
+#[test]
+fn parameter_changes_are_coalesced() {
+    let add = Event::AddLayer {
+        kind: "Slow".to_string(),
+        parameters: ParamMap::new(),
+        inputs: vec![],
+    };
+    let connect = Event::Connect {
+        from: NodeIndex::new(0),
+        output: 0,
+        to: NodeIndex::new(1),
+        port: 0,
+    };
+    let events = vec![
+        set_value(0, "value", 1),
+        set_value(1, "value", 1),
+        set_value(0, "other", 1),
+        set_value(0, "value", 2),
+        Event::RequestCompute(NodeIndex::new(0)),
+        query(0.1),
+        set_value(0, "value", 3),
+        query(0.2),
+        add.clone(),
+        set_value(0, "value", 4),
+        connect.clone(),
+        set_value(1, "value", 2),
+        set_value(1, "value", 3),
+        Event::Exit,
+    ];
+    let expected = vec![
+        set_value(1, "value", 1),
+        set_value(0, "other", 1),
+        Event::RequestCompute(NodeIndex::new(0)),
+        set_value(0, "value", 3),
+        query(0.2),
+        add,
+        set_value(0, "value", 4),
+        connect,
+        set_value(1, "value", 3),
+        Event::Exit,
+    ];
+    assert_eq!(backend::coalesce(events), expected);
+}
+
+#[test]
+fn bursts_of_parameter_changes_are_computed_once() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, slow_registry(&started));
+    backend.set_coalesce_interval(Duration::from_secs(1));
+    let handle = thread::spawn(move || backend.run());
+
+    let parameters = ParamMap::from([("value".to_string(), ParamValue::Int(0))]);
+    let kind = "Slow".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    for _ in 0..20 {
+        channel.send(set_value(0, "value", 0)).unwrap();
+        channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    }
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    let finished = received.iter().filter(|data| matches!(data, Data::ComputeFinished { .. })).count();
+    assert_eq!(finished, 1);
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn job_queue_keeps_the_highest_priority() {
+    let mut queue = JobQueue::new();
+    queue.push(NodeIndex::new(0), Priority::Background);
+    queue.push(NodeIndex::new(1), Priority::Normal);
+    queue.push(NodeIndex::new(0), Priority::Normal);
+    queue.push(NodeIndex::new(1), Priority::Background);
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.layers(Priority::Normal), vec![NodeIndex::new(0), NodeIndex::new(1)]);
+
+    queue.select(NodeIndex::new(2));
+    queue.select(NodeIndex::new(1));
+    assert_eq!(queue.highest_priority(), Some(Priority::Preview));
+    assert_eq!(queue.layers(Priority::Preview), vec![NodeIndex::new(1)]);
+    assert_eq!(queue.layers(Priority::Normal), vec![NodeIndex::new(0), NodeIndex::new(2)]);
+
+    for layer in queue.all_layers() {
+        queue.remove(layer);
+    }
+    assert_eq!(queue.highest_priority(), None);
+}
+
+#[test]
+fn selected_layer_is_computed_first() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, slow_registry(&started));
+    backend.set_coalesce_interval(Duration::from_millis(200));
+    let handle = thread::spawn(move || backend.run());
+
+    for _ in 0..3 {
+        let parameters = ParamMap::from([("value".to_string(), ParamValue::Int(0))]);
+        let kind = "Slow".to_string();
+        channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    }
+    channel.send(set_value(0, "value", 0)).unwrap(); // Holds back computing until everything is queued
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+    channel.send(Event::SelectLayer(NodeIndex::new(2))).unwrap();
+
+    let received = receive_until(&channel, |data| {
+        matches!(data, Data::QueueState { current: None, .. })
+    });
+    let computed: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::ComputeFinished { node, .. } => Some(node.index()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(computed, vec![2, 0, 1]);
+    let states: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::QueueState { current, done, pending } => Some((current.map(|node| node.index()), *done, *pending)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(states, vec![(Some(2), 0, 3), (Some(0), 1, 2), (Some(1), 2, 1), (None, 0, 0)]);
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+/// Produces a single gray pixel, or panics if asked to. Setting parameters always panics.
+struct Fragile {
+    panic: bool,
+    computed: Arc<AtomicUsize>,
+}
+
+impl Layer for Fragile {
+    fn kind(&self) -> String {
+        "Fragile".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        self.computed.fetch_add(1, Ordering::SeqCst);
+        if self.panic {
+            panic!("Fragile layer broke");
+        }
+        *output = Some(Box::new(GrayImage::new(1, 1)));
+        Ok(())
+    }
+
+    fn parameters(&self) -> ParamMap {
+        ParamMap::from([("panic".to_string(), ParamValue::Bool(self.panic))])
+    }
+
+    fn set_parameter(&mut self, _name: &str, _value: ParamValue) -> Result<()> {
+        panic!("Setting parameters of a fragile layer is broken");
+    }
+}
+
+fn fragile_registry(computed: &Arc<AtomicUsize>) -> LayerRegistry {
+    let mut registry = LayerRegistry::new();
+    let spec = ParamSpec::new("panic", ParamKind::Bool, Some(ParamValue::Bool(false)));
+    let computed = computed.clone();
+    registry.register("Fragile", vec![spec], move |parameters| {
+        let panic = parameters["panic"] == ParamValue::Bool(true);
+        let computed = computed.clone();
+        Ok(Box::new(Fragile { panic, computed }))
+    });
+    registry
+}
+
+fn add_fragile(channel: &ThreadChannel<Event, Data>, panic: bool) {
+    let parameters = ParamMap::from([("panic".to_string(), ParamValue::Bool(panic))]);
+    let kind = "Fragile".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+}
+
+#[test]
+fn panicking_layers_dont_take_down_the_backend() {
+    let computed = Arc::new(AtomicUsize::new(0));
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, fragile_registry(&computed));
+    let handle = thread::spawn(move || backend.run());
+
+    add_fragile(&channel, true);
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::ComputeFailed { .. }));
+    match received.last() {
+        Some(Data::ComputeFailed { node, error }) => {
+            assert_eq!(node.index(), 0);
+            assert!(error.contains("Layer 0 (Fragile) panicked: Fragile layer broke"), "{}", error);
+        }
+        data => panic!("Expected a failure, got {:?}", data),
+    }
+
+    add_fragile(&channel, false);
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+    receive_until(&channel, |data| matches!(data, Data::Preview { node, .. } if node.index() == 1));
+
+    // A layer that panicked isn't computed again until it changes
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    receive_until(&channel, |data| matches!(data, Data::ComputeFailed { .. }));
+    assert_eq!(computed.load(Ordering::SeqCst), 2);
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn supervisor_restarts_a_crashed_backend() {
+    let computed = Arc::new(AtomicUsize::new(0));
+    let registry_computed = computed.clone();
+    let mut supervisor = Supervisor::spawn(move || fragile_registry(&registry_computed));
+
+    add_fragile(supervisor.channel(), false);
+    receive_until(supervisor.channel(), |data| matches!(data, Data::LayerAdded { .. }));
+    supervisor.channel().send(set_value(0, "panic", 1)).unwrap();
+    let start = Instant::now();
+    while !supervisor.has_crashed() {
+        assert!(start.elapsed() < Duration::from_secs(10), "Backend didn't crash");
+        supervisor.channel().receive();
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let reason = supervisor.restart().unwrap();
+    assert!(reason.contains("Setting parameters of a fragile layer is broken"), "{}", reason);
+    assert!(!supervisor.has_crashed());
+    supervisor.channel().send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    receive_until(supervisor.channel(), |data| matches!(data, Data::Preview { .. }));
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+}
+
+/// Crops its input to a width in pixels. Remembers the width of each input it got and the width it cropped to.
+struct Crop {
+    width: u8,
+    computed: Arc<Mutex<Vec<(u32, u32)>>>,
+}
+
+impl Layer for Crop {
+    fn kind(&self) -> String {
+        "Crop".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        let image = input[0].as_ref().and_then(|image| image.downcast_ref::<RgbaImage>()).unwrap();
+        self.computed.lock().unwrap().push((image.width(), self.width.into()));
+        let width = u32::from(self.width).min(image.width());
+        let cropped = imageops::crop_imm(image, 0, 0, width, image.height()).to_image();
+        *output = Some(Box::new(cropped));
+        Ok(())
+    }
+
+    fn parameters(&self) -> ParamMap {
+        ParamMap::from([("width".to_string(), ParamValue::Int(self.width.into()))])
+    }
+
+    fn set_parameter(&mut self, _name: &str, value: ParamValue) -> Result<()> {
+        self.width = Parameter::from_value(&value)?;
+        Ok(())
+    }
+
+    fn scale_parameters(&mut self, factor: f64) {
+        self.width = (f64::from(self.width) * factor).round() as u8;
+    }
+}
+
+#[test]
+fn previews_are_computed_at_a_lower_resolution_first() {
+    let directory = std::env::temp_dir().join(format!("klex-backend-preview-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("input.png");
+    RgbaImage::new(8, 4).save(&path).unwrap();
+
+    let computed = Arc::new(Mutex::new(Vec::new()));
+    let mut registry = LayerRegistry::with_builtins();
+    let spec = ParamSpec::new("width", ParamKind::Int { min: 0, max: 100 }, None);
+    let crop_computed = computed.clone();
+    registry.register("Crop", vec![spec], move |parameters| {
+        let width = Parameter::from_value(&parameters["width"])?;
+        let computed = crop_computed.clone();
+        Ok(Box::new(Crop { width, computed }))
+    });
+
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, registry);
+    backend.set_preview_size(Some(4));
+    let handle = thread::spawn(move || backend.run());
+
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    let kind = "InputFile".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    let parameters = ParamMap::from([("width".to_string(), ParamValue::Int(6))]);
+    let (kind, inputs) = ("Crop".to_string(), vec![NodeIndex::new(0)]);
+    channel.send(Event::AddLayer { kind, parameters, inputs }).unwrap();
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { full_resolution: true, .. }));
+    let previews: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::Preview { node, full_resolution, .. } => Some((node.index(), *full_resolution)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(previews, vec![(1, false), (1, true)]);
+    // The preview works on half the width, so the crop is scaled along with it
+    assert_eq!(*computed.lock().unwrap(), vec![(4, 3), (8, 6)]);
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn inserted_layers_follow_the_selection_and_become_selected() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+
+    let insert = |kind: &str, connect_after: Option<usize>| Event::InsertLayer {
+        kind: kind.to_string(),
+        parameters: ParamMap::new(),
+        connect_after: connect_after.map(NodeIndex::new),
+    };
+    channel.send(insert("InputFile", None)).unwrap(); // Has no default path, which has to be set later
+    channel.send(insert("Convert<RgbaImage, GrayImage>", Some(0))).unwrap();
+    channel.send(insert("InputFile", Some(1))).unwrap(); // Input layers aren't connected
+    channel.send(insert("Blur", Some(1))).unwrap();
+
+    let received = receive_until(&channel, |data| matches!(data, Data::Error(_)));
+    let added: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::LayerAdded { node, inputs, .. } => {
+                Some((node.index(), inputs.iter().map(|input| input.index()).collect::<Vec<_>>()))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(added, vec![(0, vec![]), (1, vec![0]), (2, vec![])]);
+    let selected: Vec<_> = received
+        .iter()
+        .filter_map(|data| match data {
+            Data::LayerSelected(node) => Some(node.index()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(selected, vec![0, 1, 2]);
+
+    // Parameters given along with the kind override the defaults
+    let parameters = ParamMap::from([("x".to_string(), ParamValue::Int(3)), ("width".to_string(), ParamValue::Int(5))]);
+    let kind = "Crop<GrayImage>".to_string();
+    channel.send(Event::InsertLayer { kind, parameters, connect_after: Some(NodeIndex::new(1)) }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::LayerSelected(_)));
+    let values = received.iter().find_map(|data| match data {
+        Data::Parameters { node, values, .. } if node.index() == 3 => Some(values),
+        _ => None,
+    });
+    let values = values.expect("The parameters of the new layer are sent");
+    let (x, y, width) = (&values["x"], &values["y"], &values["width"]);
+    assert_eq!((x, y, width), (&ParamValue::Int(3), &ParamValue::Int(0), &ParamValue::Int(5)));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn layers_can_be_removed_disconnected_and_moved() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+
+    for (kind, inputs) in [
+        ("Convert<RgbaImage, GrayImage>", vec![]),
+        ("Threshold", vec![NodeIndex::new(0)]),
+        ("Convert<BinaryImage, GrayImage>", vec![NodeIndex::new(1)]),
+    ] {
+        let kind = kind.to_string();
+        channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs }).unwrap();
+    }
+    let (first, second, third) = (NodeIndex::new(0), NodeIndex::new(1), NodeIndex::new(2));
+    channel.send(Event::MoveLayer { node: first, position: (100.0, 50.0) }).unwrap();
+    channel.send(Event::Disconnect { to: third, port: 0 }).unwrap();
+    channel.send(Event::RemoveLayer(first)).unwrap();
+    channel.send(Event::Disconnect { to: second, port: 0 }).unwrap(); // Went away along with the first layer
+
+    let received = receive_until(&channel, |data| matches!(data, Data::Error(_)));
+    assert!(received.iter().any(|data| matches!(data, Data::LayerMoved { node, position: (x, y) }
+        if *node == first && (*x, *y) == (100.0, 50.0))));
+    assert!(received.iter().any(|data| matches!(data, Data::Disconnected { to, port: 0 } if *to == third)));
+    assert!(received.iter().any(|data| matches!(data, Data::LayerRemoved(node) if *node == first)));
+    assert!(matches!(received.last(), Some(Data::Error(error)) if error.contains("not connected")));
+
+    // Removed layers can't be used anymore, and new layers take their place in the preview graph as well
+    channel.send(Event::MoveLayer { node: first, position: (0.0, 0.0) }).unwrap();
+    receive_until(&channel, |data| matches!(data, Data::Error(_)));
+    let kind = "Convert<RgbaImage, GrayImage>".to_string();
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![] }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::LayerAdded { .. }));
+    assert!(matches!(received.last(), Some(Data::LayerAdded { node, .. }) if *node == first));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn duplicates_and_undone_edits_reach_the_user_interface() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+    let mut graph = GraphMirror::new();
+    let mut receive_graph = |done: fn(&Data) -> bool| {
+        for data in receive_until(&channel, done) {
+            graph.apply(&data);
+        }
+        graph.layers().map(|(node, layer)| (node.index(), layer.clone())).collect::<Vec<_>>()
+    };
+
+    let parameters = ParamMap::from([("threshold".to_string(), ParamValue::Int(42))]);
+    channel.send(Event::AddLayer { kind: "Threshold".to_string(), parameters, inputs: vec![] }).unwrap();
+    channel.send(Event::MoveLayer { node: NodeIndex::new(0), position: (10.0, 20.0) }).unwrap();
+    channel.send(Event::DuplicateLayer(NodeIndex::new(0))).unwrap();
+    let layers = receive_graph(|data| matches!(data, Data::LayerSelected(_)));
+    assert_eq!(layers.len(), 2);
+    let (original, copy) = (&layers[0].1, &layers[1].1);
+    assert_eq!((&copy.kind, &copy.parameters), (&original.kind, &original.parameters));
+    assert_eq!(copy.layout.position, Some((40.0, 50.0)), "Copies are placed next to the original");
+
+    channel.send(Event::Undo).unwrap();
+    let layers = receive_graph(|data| matches!(data, Data::LayerSelected(_)));
+    assert_eq!(layers.iter().map(|(node, _)| *node).collect::<Vec<_>>(), [0]);
+    channel.send(Event::Redo).unwrap();
+    let layers = receive_graph(|data| matches!(data, Data::LayerSelected(_)));
+    assert_eq!(layers.iter().map(|(node, _)| *node).collect::<Vec<_>>(), [0, 1]);
+
+    for _ in 0..3 {
+        channel.send(Event::Undo).unwrap();
+    }
+    let layers = receive_graph(|data| matches!(data, Data::Error(_)));
+    assert!(layers.is_empty());
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn graph_changes_are_sent_as_numbered_deltas() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+    let sequences = |received: &[Data]| {
+        let sequences = received.iter().filter_map(|data| match data {
+            Data::Graph { sequence, .. } => Some((*sequence, true)),
+            Data::GraphDelta { sequence, .. } => Some((*sequence, false)),
+            _ => None,
+        });
+        sequences.collect::<Vec<_>>()
+    };
+
+    let kind = "Threshold".to_string();
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![] }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Graph { .. }));
+    assert_eq!(sequences(&received), [(1, true)]);
+    let node = NodeIndex::new(0);
+    channel.send(Event::MoveLayer { node, position: (3.0, 4.0) }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::GraphDelta { .. }));
+    match received.last() {
+        Some(Data::GraphDelta { sequence: 2, delta }) => {
+            assert_eq!(delta.changed.iter().map(|node| node.layout.position).collect::<Vec<_>>(), [Some((3.0, 4.0))]);
+            assert!(delta.added.is_empty() && delta.removed.is_empty());
+        }
+        data => panic!("Expected the second delta, got {:?}", data),
+    }
+
+    channel.send(Event::RequestGraph).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Graph { .. }));
+    match received.last() {
+        Some(Data::Graph { sequence: 3, snapshot }) => assert_eq!(snapshot.nodes[0].layout.position, Some((3.0, 4.0))),
+        data => panic!("Expected a snapshot, got {:?}", data),
+    }
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn memory_usage_is_only_sent_when_it_changes() {
+    let directory = std::env::temp_dir().join(format!("klex-memory-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("input.png");
+    GrayImage::from_raw(2, 1, vec![0, 200]).unwrap().save(&path).unwrap();
+
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+    let usages = |received: &[Data]| -> Vec<usize> {
+        received
+            .iter()
+            .filter_map(|data| match data {
+                Data::MemoryUsage(bytes) => Some(*bytes),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    channel.send(Event::AddLayer { kind: "InputFile".to_string(), parameters, inputs: vec![] }).unwrap();
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::QueueState { current: None, .. }));
+    assert_eq!(usages(&received), [8]);
+    channel.send(Event::RequestCompute(NodeIndex::new(0))).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    assert_eq!(usages(&received), [], "Nothing changed");
+
+    channel.send(Event::RemoveLayer(NodeIndex::new(0))).unwrap();
+    receive_until(&channel, |data| matches!(data, Data::MemoryUsage(0)));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn full_resolution_outputs_can_be_inspected() {
+    let directory = std::env::temp_dir().join(format!("klex-backend-pixel-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("input.png");
+    let mut image = RgbaImage::new(8, 4);
+    image.put_pixel(6, 2, image::Rgba([10, 20, 30, 255]));
+    image.save(&path).unwrap();
+
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    let kind = "InputFile".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    let node = NodeIndex::new(0);
+    channel.send(Event::RequestCompute(node)).unwrap();
+    // The input is selected, since it's the first layer, so its preview comes with a histogram
+    let received = receive_until(&channel, |data| matches!(data, Data::Histogram { .. }));
+    assert!(matches!(received[received.len() - 2], Data::Preview { full_resolution: true, .. }));
+    let histogram = match received.last() {
+        Some(Data::Histogram { histogram, .. }) => histogram,
+        _ => unreachable!(),
+    };
+    assert_eq!(histogram.channels.len(), 3);
+    assert_eq!((histogram.channels[0][0], histogram.channels[0][10], histogram.channels[2][30]), (31, 1, 1));
+
+    // Positions outside of the image go unanswered
+    channel.send(Event::QueryPixel { node, x: 1.5, y: 0.5 }).unwrap();
+    channel.send(Event::QueryPixel { node, x: 0.8, y: 0.6 }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Pixel { .. }));
+    let pixel = received.iter().find_map(|data| match data {
+        Data::Pixel { position, x, y, value, exact, .. } => Some((*position, *x, *y, *value, *exact)),
+        _ => None,
+    });
+    assert_eq!(pixel, Some(((0.8, 0.6), 6, 2, PixelValue::Rgba([10, 20, 30, 255]), true)));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn outputs_are_only_converted_for_display_once() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    backend.set_preview_size(None);
+    let handle = thread::spawn(move || backend.run());
+    let size = |size| ParamValue::Int(size);
+    let parameters = ParamMap::from([("width".to_string(), size(4)), ("height".to_string(), size(4))]);
+    let kind = "PaintedMask".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    let kind = "Convert<BinaryImage, GrayImage>".to_string();
+    let (mask, gray) = (NodeIndex::new(0), NodeIndex::new(1));
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![mask] }).unwrap();
+
+    let preview = |node| {
+        channel.send(Event::RequestCompute(node)).unwrap();
+        let received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+        match received.last() {
+            Some(Data::Preview { node: shown, image, .. }) if *shown == node => image.clone(),
+            data => panic!("Expected a preview, got {:?}", data),
+        }
+    };
+    let first = preview(gray);
+    preview(mask);
+    let again = preview(gray);
+    assert!(std::ptr::eq(first.data(), again.data()), "The pixels are shared instead of copied again");
+
+    let stroke = Stroke { points: vec![(1.0, 1.0)], radius: 1.0, erase: false };
+    channel.send(Event::Paint { node: mask, stroke }).unwrap();
+    channel.send(Event::EndStroke).unwrap();
+    let changed = preview(gray);
+    assert_ne!(changed.id(), first.id());
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn thumbnails_are_sent_when_outputs_change() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    backend.set_preview_size(None);
+    let handle = thread::spawn(move || backend.run());
+    let size = |size| ParamValue::Int(size);
+    let parameters = ParamMap::from([("width".to_string(), size(200)), ("height".to_string(), size(100))]);
+    let kind = "PaintedMask".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    let kind = "Convert<BinaryImage, GrayImage>".to_string();
+    let (mask, gray) = (NodeIndex::new(0), NodeIndex::new(1));
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![mask] }).unwrap();
+
+    let thumbnails = |received: &[Data]| -> Vec<_> {
+        received
+            .iter()
+            .filter_map(|data| match data {
+                Data::Thumbnail { node, image } => match image.data() {
+                    iced_native::image::Data::Pixels { width, height, .. } => Some((node.index(), (*width, *height))),
+                    _ => panic!("Thumbnails are made of pixels"),
+                },
+                _ => None,
+            })
+            .collect()
+    };
+    let paint = |erase| {
+        let stroke = Stroke { points: vec![(10.0, 10.0)], radius: 3.0, erase };
+        channel.send(Event::Paint { node: mask, stroke }).unwrap();
+        channel.send(Event::EndStroke).unwrap();
+        channel.send(Event::RequestCompute(gray)).unwrap();
+    };
+    channel.send(Event::RequestCompute(gray)).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Thumbnail { node, .. } if *node == gray));
+    assert_eq!(thumbnails(&received), [(0, (96, 48)), (1, (96, 48))]);
+
+    // Erasing where nothing was painted computes the layers again, but their outputs stay the same
+    paint(true);
+    let mut received = receive_until(&channel, |data| matches!(data, Data::Preview { .. }));
+    paint(false);
+    received.extend(receive_until(&channel, |data| matches!(data, Data::Thumbnail { node, .. } if *node == gray)));
+    assert_eq!(thumbnails(&received), [(0, (96, 48)), (1, (96, 48))]);
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn painted_masks_feed_the_layers_after_them() {
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    let handle = thread::spawn(move || backend.run());
+    let size = |size| ParamValue::Int(size);
+    let parameters = ParamMap::from([("width".to_string(), size(8)), ("height".to_string(), size(4))]);
+    let kind = "PaintedMask".to_string();
+    channel.send(Event::AddLayer { kind, parameters, inputs: vec![] }).unwrap();
+    let kind = "Convert<BinaryImage, GrayImage>".to_string();
+    let (mask, gray) = (NodeIndex::new(0), NodeIndex::new(1));
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![mask] }).unwrap();
+
+    for points in [vec![(1.0, 1.0)], vec![(1.0, 1.0), (6.0, 1.0)]] {
+        let stroke = Stroke { points, radius: 0.6, erase: false };
+        channel.send(Event::Paint { node: mask, stroke }).unwrap();
+    }
+    channel.send(Event::EndStroke).unwrap();
+    channel.send(Event::RequestCompute(gray)).unwrap();
+    receive_until(&channel, |data| matches!(data, Data::ComputeFinished { node, .. } if *node == gray));
+
+    let mut pixels = Vec::new();
+    for x in [0.3, 0.9] {
+        channel.send(Event::QueryPixel { node: gray, x, y: 0.3 }).unwrap();
+        let received = receive_until(&channel, |data| matches!(data, Data::Pixel { .. }));
+        pixels.extend(received.iter().filter_map(|data| match data {
+            Data::Pixel { value, .. } => Some(*value),
+            _ => None,
+        }));
+    }
+    assert_eq!(pixels, vec![PixelValue::Gray(u8::MAX), PixelValue::Gray(0)]);
+
+    let stroke = Stroke { points: vec![(0.0, 0.0)], radius: 1.0, erase: false };
+    channel.send(Event::Paint { node: gray, stroke }).unwrap();
+    let received = receive_until(&channel, |data| matches!(data, Data::Error(_)));
+    assert!(matches!(received.last(), Some(Data::Error(error)) if error.contains("can't be painted on")));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn exports_wait_for_the_full_resolution_output() {
+    let directory = std::env::temp_dir().join(format!("klex-backend-export-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("input.png");
+    RgbaImage::from_pixel(8, 4, image::Rgba([200, 100, 50, 128])).save(&path).unwrap();
+
+    let (channel, backend_channel) = ThreadChannel::new_pair();
+    let mut backend = Backend::new(backend_channel, LayerRegistry::with_builtins());
+    backend.set_preview_size(Some(4));
+    let handle = thread::spawn(move || backend.run());
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    let (input, gray, orphan) = (NodeIndex::new(0), NodeIndex::new(1), NodeIndex::new(2));
+    for (kind, parameters, inputs) in [
+        ("InputFile", parameters, vec![]),
+        ("Convert<RgbaImage, GrayImage>", ParamMap::new(), vec![input]),
+        ("Convert<RgbaImage, GrayImage>", ParamMap::new(), vec![]),
+    ] {
+        let kind = kind.to_string();
+        channel.send(Event::AddLayer { kind, parameters, inputs }).unwrap();
+    }
+    channel.send(Event::SelectLayer(gray)).unwrap();
+    let exports = [(input, "color.jpg"), (gray, "gray.tif"), (gray, "gray.gif"), (orphan, "orphan.png")];
+    for (node, name) in exports {
+        let path = directory.join(name);
+        channel.send(Event::Export { node, path, quality: 80 }).unwrap();
+    }
+
+    let finished = Cell::new(0);
+    let received = receive_until(&channel, |data| {
+        if matches!(data, Data::ExportReady { .. } | Data::ExportFailed { .. }) {
+            finished.set(finished.get() + 1);
+        }
+        finished.get() == exports.len()
+    });
+    let mut outcomes: Vec<_> = received
+        .into_iter()
+        .filter_map(|data| match data {
+            // Written by the user interface, where it doesn't hold up the backend
+            Data::ExportReady { job, .. } => Some((job.path().to_path_buf(), job.write().err().map(|e| e.to_string()))),
+            Data::ExportFailed { path, error, .. } => Some((path, Some(error))),
+            _ => None,
+        })
+        .collect();
+    outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+    let names: Vec<_> = outcomes.iter().map(|(path, _)| path.file_name().unwrap().to_str().unwrap()).collect();
+    assert_eq!(names, ["color.jpg", "gray.gif", "gray.tif", "orphan.png"]);
+    assert!(outcomes[0].1.is_none());
+    assert!(matches!(&outcomes[1].1, Some(error) if error.contains("only to PNG, JPEG, BMP and TIFF")));
+    assert!(outcomes[2].1.is_none());
+    assert!(matches!(&outcomes[3].1, Some(error) if error.contains("couldn't be computed")));
+
+    // At full resolution, even though the gray layer was only previewed at first. JPEGs have no alpha channel.
+    let color = image::open(directory.join("color.jpg")).unwrap();
+    assert_eq!((color.width(), color.height(), color.color()), (8, 4, image::ColorType::Rgb8));
+    let gray = image::open(directory.join("gray.tif")).unwrap();
+    assert_eq!((gray.width(), gray.height(), gray.color()), (8, 4, image::ColorType::L8));
+
+    channel.send(Event::Exit).unwrap();
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn loaded_recipes_replace_the_graph() {
+    let supervisor = Supervisor::spawn(LayerRegistry::with_builtins);
+    let channel = supervisor.channel();
+    let kind = "PaintedMask".to_string();
+    channel.send(Event::AddLayer { kind, parameters: ParamMap::new(), inputs: vec![] }).unwrap();
+    receive_until(channel, |data| matches!(data, Data::Parameters { .. })); // Sent right after the layer was added
+
+    let recipe = Recipe::from_ron(
+        r#"(
+            version: 1,
+            placeholders: {"image": (kind: Path)},
+            nodes: [
+                (kind: "InputFile", name: "photo", parameters: {"path": Path("${image}")}),
+                (kind: "Convert<RgbaImage, GrayImage>", name: "gray", parameters: {}, position: Some((200.0, 40.0))),
+            ],
+            edges: [(from: 0, to: 1, port: 0)],
+        )"#,
+    )
+    .unwrap();
+    let bindings = Bindings::from([("image".to_string(), ParamValue::Path("Tulips.jpg".into()))]);
+    let event = Event::LoadRecipe { recipe: Box::new(recipe), bindings };
+    channel.send(event).unwrap();
+    let received = receive_until(channel, |data| matches!(data, Data::LayerSelected(_)));
+    let mut graph = GraphMirror::new();
+    for data in &received {
+        graph.apply(data);
+    }
+    // Snapshots of the graph before the recipe was loaded may still arrive in between
+    let first = received.iter().find(|data| matches!(data, Data::LayerRemoved(_) | Data::LayerAdded { .. }));
+    assert!(matches!(first, Some(Data::LayerRemoved(node)) if node.index() == 0));
+    let layers: Vec<_> = graph.layers().map(|(node, layer)| (node.index(), layer.name.as_str())).collect();
+    assert_eq!(layers, [(0, "photo"), (1, "gray")]);
+    let gray = graph.layer(NodeIndex::new(1)).unwrap();
+    assert_eq!(gray.inputs, [(NodeIndex::new(0), 0, 0)]);
+    assert_eq!(gray.layout.position, Some((200.0, 40.0)), "Positions of older recipes are still read");
+    let photo = graph.layer(NodeIndex::new(0)).unwrap();
+    assert_eq!(photo.parameters["path"], ParamValue::Path("Tulips.jpg".into()));
+
+    // Restarting goes by the loaded graph
+    channel.send(Event::RequestCompute(NodeIndex::new(1))).unwrap();
+    receive_until(channel, |data| matches!(data, Data::QueueState { current: None, .. }));
+    assert_eq!(supervisor.snapshot().nodes.len(), 2);
+}
@@ -0,0 +1,83 @@
+use image::{GrayImage, Luma};
+
+use klex::{
+    layer::{
+        primitive::{BackgroundEstimate, BackgroundMethod, BackgroundOutput},
+        Layer,
+    },
+    parameter::ParamValue,
+    registry::LayerRegistry,
+};
+
+/// Brightness of paper lit from the right, from 120 to 200
+fn paper(x: u32) -> u8 {
+    (120 + x - x / 3) as u8
+}
+
+fn is_mark(x: u32, y: u32) -> bool {
+    x % 10 < 3 && y % 10 < 3
+}
+
+/// Paper with dark marks of 3 by 3 pixels every 10 pixels
+fn uneven_scan() -> GrayImage {
+    GrayImage::from_fn(120, 40, |x, y| Luma([paper(x) - if is_mark(x, y) { 60 } else { 0 }]))
+}
+
+#[test]
+fn removing_the_background_makes_a_single_threshold_work() {
+    let scan = uneven_scan();
+    // No single threshold separates the marks on the right from the paper on the left
+    assert!(scan.get_pixel(111, 1)[0] > scan.get_pixel(5, 5)[0]);
+
+    for method in BackgroundMethod::ALL {
+        let layer = BackgroundEstimate::new(method, BackgroundOutput::Background, 6, true);
+        let background = layer.background(&scan);
+        assert_eq!(background.dimensions(), scan.dimensions());
+        if method == BackgroundMethod::Morphological {
+            // Only the radius at the darker edge is lifted to the paper further in
+            for (x, y, value) in background.enumerate_pixels().filter(|&(x, _, _)| x >= 6) {
+                assert_eq!(value[0], paper(x), "At {}, {}", x, y);
+            }
+        }
+
+        let flat = BackgroundEstimate::new(method, BackgroundOutput::Subtracted, 6, true).compute(&scan);
+        let (marks, rest): (Vec<_>, Vec<_>) = flat.enumerate_pixels().partition(|&(x, y, _)| is_mark(x, y));
+        let darkest_paper = rest.iter().map(|(_, _, value)| value[0]).min().unwrap();
+        let brightest_mark = marks.iter().map(|(_, _, value)| value[0]).max().unwrap();
+        assert!(brightest_mark < darkest_paper, "{:?}: {} vs {}", method, brightest_mark, darkest_paper);
+        if method == BackgroundMethod::Morphological {
+            assert!(darkest_paper >= 230 && brightest_mark <= 25, "{} vs {}", darkest_paper, brightest_mark);
+        }
+    }
+}
+
+#[test]
+fn large_radii_are_estimated_on_a_downscaled_image() {
+    let scan = uneven_scan();
+    let radius = 10 * BackgroundEstimate::FULL_RESOLUTION_RADIUS;
+    let layer = BackgroundEstimate::new(BackgroundMethod::Morphological, BackgroundOutput::Background, radius, true);
+    let background = layer.background(&scan);
+    assert!(scan.pixels().zip(background.pixels()).all(|(value, background)| background[0] >= value[0]));
+    let mut huge = LayerRegistry::with_builtins().create_default("BackgroundEstimate").unwrap();
+    huge.set_parameter("radius", ParamValue::Int(16384)).unwrap();
+    huge.set_parameter("output", ParamValue::Choice("Background".to_string())).unwrap();
+    let input = Some(Box::new(scan.clone()) as Box<dyn std::any::Any + Send + Sync>);
+    let mut output = None;
+    huge.compute(&[&input], &mut output).unwrap();
+    let output = output.unwrap();
+    let background = output.downcast_ref::<GrayImage>().unwrap();
+    assert!(background.pixels().all(|value| value[0] == paper(119)), "The brightest paper everywhere");
+
+    // Light objects on a dark background, like the scan inverted
+    let mut inverted = scan.clone();
+    image::imageops::invert(&mut inverted);
+    let layer = BackgroundEstimate::new(BackgroundMethod::Morphological, BackgroundOutput::Subtracted, 6, false);
+    let mut flat = BackgroundEstimate::new(BackgroundMethod::Morphological, BackgroundOutput::Subtracted, 6, true)
+        .compute(&scan);
+    image::imageops::invert(&mut flat);
+    assert_eq!(layer.compute(&inverted), flat);
+
+    let mut preview = BackgroundEstimate::new(BackgroundMethod::Blur, BackgroundOutput::Background, 50, true);
+    preview.scale_parameters(0.25);
+    assert_eq!(preview.parameters()["radius"], ParamValue::Int(13));
+}
@@ -0,0 +1,140 @@
+use std::{cmp::Ordering, collections::BTreeMap, fs, sync::Mutex};
+
+use image::{GrayImage, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    entity::{BinaryImage, DataFormat},
+    layer::primitive::{Contours, Convert, ExportData, InputFile, Threshold},
+    layer_graph::InteractiveLayerGraph,
+    parameter::ParamKind,
+    recipe::{self, Placeholder, Recipe, BATCH_INPUT},
+};
+
+fn threshold_graph() -> (InteractiveLayerGraph, NodeIndex) {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("${input}".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    (layers, threshold)
+}
+
+fn mask_recipe() -> Recipe {
+    let (mut layers, threshold) = threshold_graph();
+    let mask = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers.rename(mask, "mask".to_string()).unwrap();
+    batch_recipe(&layers)
+}
+
+fn batch_recipe(layers: &InteractiveLayerGraph) -> Recipe {
+    let mut recipe = Recipe::from_graph(layers);
+    recipe.placeholders = BTreeMap::from([(
+        BATCH_INPUT.to_string(),
+        Placeholder {
+            kind: ParamKind::Path,
+            default: None,
+        },
+    )]);
+    recipe
+}
+
+#[test]
+fn batch_processes_every_image_in_a_directory() {
+    let directory = std::env::temp_dir().join(format!("klex-batch-{}", std::process::id()));
+    let inputs = directory.join("inputs");
+    fs::create_dir_all(&inputs).unwrap();
+    for (i, value) in [50, 150, 250].into_iter().enumerate() {
+        let image = GrayImage::from_raw(2, 1, vec![value, 0]).unwrap();
+        image.save(inputs.join(format!("image{}.png", i))).unwrap();
+    }
+    fs::write(inputs.join("broken.png"), b"not an image").unwrap();
+    fs::write(inputs.join("notes.txt"), b"skipped").unwrap();
+
+    let template = directory.join("outputs/{stem}_{index}.png");
+    let progress = Mutex::new(Vec::new());
+    let report = recipe::run_batch(
+        &mask_recipe(),
+        inputs.to_str().unwrap(),
+        template.to_str().unwrap(),
+        2,
+        |update| {
+            progress
+                .lock()
+                .unwrap()
+                .push((update.completed, update.total, update.error.is_some()))
+        },
+    )
+    .unwrap();
+
+    assert_eq!(report.successes.len(), 3);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].input, inputs.join("broken.png"));
+
+    let outputs: Vec<_> = report.successes.iter().map(|success| success.outputs.clone()).collect();
+    assert_eq!(outputs[0], vec![directory.join("outputs/image0_1.png")]);
+    let mask = image::open(&outputs[2][0]).unwrap().into_luma8();
+    assert_eq!(mask.as_raw(), &vec![255, 0]);
+    let mask = image::open(&outputs[0][0]).unwrap().into_luma8();
+    assert_eq!(mask.as_raw(), &vec![0, 0]);
+
+    let mut progress = progress.into_inner().unwrap();
+    progress.sort();
+    assert_eq!(progress.len(), 4);
+    assert_eq!(progress.iter().filter(|&&(_, _, failed)| failed).count(), 1);
+    assert_eq!(
+        progress.last().map(|&(completed, total, _)| (completed, total)),
+        Some((4, 4))
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn batch_requires_an_input_placeholder() {
+    let mut recipe = mask_recipe();
+    recipe.placeholders.clear();
+    assert!(recipe::run_batch(&recipe, "*.png", "{stem}.png", 1, |_| ()).is_err());
+}
+
+#[test]
+fn batches_export_data_for_each_input() {
+    let directory = std::env::temp_dir().join(format!("klex-batch-data-{}", std::process::id()));
+    let inputs = directory.join("inputs");
+    fs::create_dir_all(&inputs).unwrap();
+    for (i, width) in [1, 2].into_iter().enumerate() {
+        let image = GrayImage::from_raw(width, 1, vec![255; width as usize]).unwrap();
+        image.save(inputs.join(format!("image{}.png", i))).unwrap();
+    }
+
+    let (mut layers, threshold) = threshold_graph();
+    let contours = layers.add_layer(Box::new(Contours::new()), vec![threshold]);
+    let path = directory.join("data/{stem}.csv");
+    let export = layers.add_layer(Box::new(ExportData::new(path, DataFormat::Csv)), vec![contours]);
+    layers.rename(export, "contours".to_string()).unwrap();
+    let recipe = batch_recipe(&layers);
+    let (inputs, template) = (inputs.to_str().unwrap(), directory.join("outputs/{index}.csv"));
+    let report = recipe::run_batch(&recipe, inputs, template.to_str().unwrap(), 2, |_| ()).unwrap();
+    assert!(report.failures.is_empty(), "{:#}", report.failures[0].error);
+    let first = fs::read_to_string(directory.join("data/image0.csv")).unwrap();
+    let second = fs::read_to_string(directory.join("data/image1.csv")).unwrap();
+    assert!(first.starts_with("contour,x,y\n0,"), "{}", first);
+    assert_ne!(first, second);
+    assert_eq!(
+        fs::read_to_string(directory.join("outputs/1.csv")).unwrap(),
+        second,
+        "The output is what was written"
+    );
+
+    // Data that can't be exported fails the run, naming what can be
+    let (mut layers, threshold) = threshold_graph();
+    let path = directory.join("data/{stem}.json");
+    layers.add_layer(Box::new(ExportData::new(path, DataFormat::Json)), vec![threshold]);
+    let report = recipe::run_batch(&batch_recipe(&layers), inputs, template.to_str().unwrap(), 1, |_| ()).unwrap();
+    let error = format!("{:#}", report.failures[0].error);
+    assert!(
+        error.contains("Can't export BinaryImage as data, only Lines, Points"),
+        "{}",
+        error
+    );
+    fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,189 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    cache::OutputCache,
+    entity::{BinaryImage, Gray16Image, GrayImageF32},
+    error::Result,
+    layer::{self, Layer, LayerOutput},
+    layer_graph::LayerGraph,
+    testing::TestPattern,
+};
+
+fn directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("klex-cache-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&directory).ok();
+    directory
+}
+
+/// Produces a test pattern
+struct Source(TestPattern);
+
+impl Layer for Source {
+    fn kind(&self) -> String {
+        "Source".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(self.0.gray(40, 30)));
+        Ok(())
+    }
+}
+
+/// Inverts a gray image and counts how often it did so
+struct Expensive(Arc<AtomicUsize>);
+
+impl Layer for Expensive {
+    fn kind(&self) -> String {
+        "Expensive".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        let mut image = layer::single_input::<GrayImage>(input)?.clone();
+        image.iter_mut().for_each(|value| *value = 255 - *value);
+        self.0.fetch_add(1, Ordering::SeqCst);
+        *output = Some(Box::new(image));
+        Ok(())
+    }
+}
+
+/// Runs a pipeline of a source and an expensive layer and returns the output of the latter
+fn run(cache: &Arc<OutputCache>, pattern: TestPattern, computations: &Arc<AtomicUsize>) -> GrayImage {
+    let mut graph = LayerGraph::new();
+    graph.set_output_cache(Some(cache.clone()));
+    let source = graph.add_layer(Box::new(Source(pattern)), vec![]);
+    let expensive = graph.add_layer(Box::new(Expensive(computations.clone())), vec![source]);
+    graph.compute_all().unwrap();
+    graph.output(expensive, 0).unwrap().downcast_ref::<GrayImage>().unwrap().clone()
+}
+
+#[test]
+fn outputs_are_only_computed_again_for_different_inputs() {
+    let directory = directory("reuse");
+    let cache = Arc::new(OutputCache::new(&directory, 1 << 20).unwrap());
+    let computations = Arc::new(AtomicUsize::new(0));
+
+    let first = run(&cache, TestPattern::Noise(1), &computations);
+    assert_eq!(run(&cache, TestPattern::Noise(1), &computations), first);
+    assert_eq!(computations.load(Ordering::SeqCst), 1, "The second run loads the stored output");
+
+    run(&cache, TestPattern::Noise(2), &computations);
+    assert_eq!(computations.load(Ordering::SeqCst), 2, "A different input is computed");
+
+    assert_eq!(cache.invalidate_kind("Expensive").unwrap(), 2);
+    assert_eq!(run(&cache, TestPattern::Noise(1), &computations), first);
+    assert_eq!(computations.load(Ordering::SeqCst), 3, "Invalidated outputs are computed again");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn least_recently_used_outputs_are_removed_beyond_the_limit() {
+    let directory = directory("limit");
+    // A 40x30 gray image takes up 1200 bytes plus a header of 128
+    let cache = Arc::new(OutputCache::new(&directory, 3000).unwrap());
+    let computations = Arc::new(AtomicUsize::new(0));
+    for seed in 0..2 {
+        run(&cache, TestPattern::Noise(seed), &computations);
+        std::thread::sleep(std::time::Duration::from_millis(20)); // Apart in modification time
+    }
+    run(&cache, TestPattern::Noise(0), &computations); // Used again, so it's the newest
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    run(&cache, TestPattern::Noise(2), &computations);
+    assert_eq!(computations.load(Ordering::SeqCst), 3);
+    assert!(cache.size_bytes().unwrap() <= 3000);
+
+    run(&cache, TestPattern::Noise(0), &computations);
+    assert_eq!(computations.load(Ordering::SeqCst), 3, "The recently used output was kept");
+    run(&cache, TestPattern::Noise(1), &computations);
+    assert_eq!(computations.load(Ordering::SeqCst), 4, "The oldest output was removed");
+
+    cache.clear().unwrap();
+    assert_eq!(cache.size_bytes().unwrap(), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn threads_can_store_the_same_output_at_once() {
+    let directory = directory("threads");
+    let cache = OutputCache::new(&directory, 1 << 20).unwrap();
+    let image = TestPattern::Noise(4).gray(200, 100);
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| assert!(cache.store("Noise", 1, &image).unwrap()));
+        }
+    });
+    assert_eq!(cache.load("Noise", 1).unwrap().downcast_ref::<GrayImage>(), Some(&image));
+    let files = std::fs::read_dir(&directory).unwrap().count();
+    assert_eq!(files, 1, "No temporary files are left behind");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn failed_stores_leave_no_temporary_files_behind() {
+    let directory = directory("failed");
+    let cache = OutputCache::new(&directory, 1 << 20).unwrap();
+    let image = TestPattern::Noise(5).gray(20, 10);
+    assert!(cache.store("Noise", 1, &image).unwrap());
+    // A directory in place of the stored file keeps the next store from moving its file there
+    let stored = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+    std::fs::remove_file(&stored).unwrap();
+    std::fs::create_dir(&stored).unwrap();
+    std::fs::write(stored.join("blocking"), b"").unwrap();
+
+    assert!(cache.store("Noise", 1, &image).is_err());
+    let files: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(files, [stored]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn stale_temporary_files_are_removed() {
+    let directory = directory("stale");
+    let cache = OutputCache::new(&directory, 1 << 20).unwrap();
+    let (stale, recent) = (directory.join("Noise-1.gray.1-0.tmp"), directory.join("Noise-2.gray.1-1.tmp"));
+    for path in [&stale, &recent] {
+        std::fs::write(path, b"half written").unwrap();
+    }
+    let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    std::fs::File::options().append(true).open(&stale).unwrap().set_modified(yesterday).unwrap();
+
+    assert!(cache.store("Noise", 3, &TestPattern::Noise(6).gray(20, 10)).unwrap());
+    assert!(!stale.exists(), "Left behind by a store that never finished");
+    assert!(recent.exists(), "May still be written to");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn every_image_element_survives_the_round_trip() {
+    let directory = directory("elements");
+    let cache = OutputCache::new(&directory, 1 << 20).unwrap();
+    let gray = TestPattern::Noise(3).gray(5, 4);
+    let binary = BinaryImage::new(3, 2, vec![true, false, false, true, true, false]);
+    let gray16 = TestPattern::Noise(3).gray16(5, 4);
+    let float = GrayImageF32::from_raw(2, 1, vec![0.25, -1e9]).unwrap();
+    let rgba = TestPattern::Noise(3).rgba(5, 4);
+
+    assert!(cache.store("Gray", 1, &gray).unwrap());
+    assert!(cache.store("Binary", 1, &binary).unwrap());
+    assert!(cache.store("Gray16", 1, &gray16).unwrap());
+    assert!(cache.store("Float", 1, &float).unwrap());
+    assert!(cache.store("Rgba", 1, &rgba).unwrap());
+    assert!(!cache.store("Unit", 1, &()).unwrap(), "Unknown elements aren't stored");
+
+    let load = |kind| cache.load(kind, 1).unwrap();
+    assert_eq!(load("Gray").downcast_ref::<GrayImage>(), Some(&gray));
+    assert_eq!(load("Binary").downcast_ref::<BinaryImage>(), Some(&binary));
+    assert_eq!(load("Gray16").downcast_ref::<Gray16Image>(), Some(&gray16));
+    assert_eq!(load("Float").downcast_ref::<GrayImageF32>(), Some(&float));
+    assert_eq!(load("Rgba").downcast_ref::<RgbaImage>(), Some(&rgba));
+    assert!(cache.load("Gray", 2).is_none());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
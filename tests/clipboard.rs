@@ -0,0 +1,39 @@
+#![cfg(feature = "clipboard")]
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    error::KlexError,
+    layer::{
+        primitive::{copy_to_clipboard, ClipboardInput, ClipboardOutput},
+        Layer, LayerCategory, LayerOutput,
+    },
+    parameter::ParamMap,
+    registry::LayerRegistry,
+};
+
+#[test]
+fn clipboard_layers_fail_with_a_message() {
+    // Whether there is a clipboard at all depends on where the tests run, so only failures are checked
+    let mut output: LayerOutput = None;
+    if let Err(error) = ClipboardInput::new().compute(&[], &mut output) {
+        let message = format!("{:#}", error);
+        let no_image = matches!(error.root(), KlexError::NoClipboardImage);
+        assert!(no_image || message.contains("Failed to open the clipboard"), "{}", message);
+    }
+
+    let text: LayerOutput = Some(Box::new("Not an image".to_string()));
+    let error = ClipboardOutput::new().compute(&[&text], &mut output).unwrap_err();
+    assert!(error.to_string().contains("cannot be saved as an image"), "{}", error);
+    assert!(copy_to_clipboard(&0_u8).is_err());
+    if copy_to_clipboard(&GrayImage::new(2, 2)).is_ok() {
+        let copied = ClipboardInput::new().compute(&[], &mut output);
+        assert!(copied.is_ok() && output.unwrap().is::<RgbaImage>());
+    }
+
+    let registry = LayerRegistry::with_builtins();
+    let input = registry.create("ClipboardInput", &ParamMap::new()).unwrap();
+    assert_eq!((input.category(), input.cache_key()), (LayerCategory::Input, None));
+    let output = registry.create("ClipboardOutput", &ParamMap::new()).unwrap();
+    assert_eq!(output.category(), LayerCategory::Output);
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use image::{imageops, GrayAlphaImage, GrayImage, Luma, LumaA, Rgba, RgbaImage};
+
+use klex::{
+    cache::OutputCache,
+    color::{self, linear_to_srgb, quantize, srgb_to_linear, ConversionGraph, SrgbTable},
+    entity::{from_planes, planes, BinaryImage, Gray16Image, Plane},
+    error::KlexError,
+    layer::{
+        primitive::{Convert, InputFile, Vignette, WhiteBalance, WhiteBalanceMethod},
+        Layer, LayerOutput,
+    },
+    layer_graph::LayerGraph,
+    parameter::ParamValue,
+    registry::LayerRegistry,
+    testing::assert_images_close,
+};
+
+#[test]
+fn transfer_functions_invert_each_other() {
+    assert_eq!(srgb_to_linear(0.0), 0.0);
+    assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+    assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3, "Midtones are darker in linear light");
+    assert!((srgb_to_linear(0.02) - 0.02 / 12.92).abs() < 1e-6, "Dark values are linear");
+    for value in 0..=255 {
+        assert_eq!(quantize(linear_to_srgb(srgb_to_linear(value as f32 / 255.0))), value);
+    }
+}
+
+#[test]
+fn table_lookups_match_the_transfer_functions() {
+    let table = SrgbTable::new();
+    for value in 0..=255 {
+        assert_eq!(table.to_linear(value), srgb_to_linear(value as f32 / 255.0));
+        assert_eq!(table.to_srgb(table.to_linear(value)), value);
+    }
+    for step in 0..=1000 {
+        let linear = step as f32 / 1000.0;
+        let expected = quantize(linear_to_srgb(linear));
+        assert!(table.to_srgb(linear).abs_diff(expected) <= 1, "Linear light {}", linear);
+    }
+    assert_eq!(table.to_srgb(-1.0), 0);
+    assert_eq!(table.to_srgb(2.0), 255);
+}
+
+#[test]
+fn colors_are_converted_to_gray_of_the_same_luminance() {
+    let colors = [[0, 0, 0], [128, 128, 128], [255, 255, 255], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+    let pixels = colors.iter().flat_map(|&[red, green, blue]| [red, green, blue, 255]).collect();
+    let image = RgbaImage::from_raw(colors.len() as u32, 1, pixels).unwrap();
+    let gray = Convert::<RgbaImage, GrayImage>::compute(&image).unwrap();
+    let expected = GrayImage::from_raw(6, 1, vec![0, 128, 255, 127, 220, 76]).unwrap(); // Rounded reference values
+    assert_images_close(&gray, &expected, 1);
+
+    let transparent = RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 0]));
+    let opaque = RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 255]));
+    let convert = Convert::<RgbaImage, GrayImage>::compute;
+    assert_eq!(convert(&transparent).unwrap(), convert(&opaque).unwrap(), "Alpha is dropped");
+}
+
+#[test]
+fn channels_are_borrowed_as_planes_and_put_back_together() {
+    let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 10 * x as u8 + y as u8, 255]));
+    let [red, green, blue, alpha] = planes(&image);
+    assert_eq!(red.values().collect::<Vec<_>>(), [0, 1, 2, 0, 1, 2]);
+    assert_eq!(green.values().collect::<Vec<_>>(), [0, 0, 0, 1, 1, 1]);
+    assert!(alpha.values().all(|value| value == 255));
+
+    let flipped = imageops::flip_horizontal(&blue);
+    assert_eq!(flipped.get_pixel(0, 1), &Luma([21]));
+    assert_eq!(from_planes([&red, &green, &blue, &alpha]).unwrap(), image);
+
+    let small = GrayImage::new(2, 2);
+    let owned = |plane: Plane| GrayImage::from_vec(3, 2, plane.values().collect()).unwrap();
+    let [red, green, blue] = [red, green, blue].map(owned);
+    let error = from_planes([&red, &green, &blue, &small]).unwrap_err();
+    assert!(matches!(error.root(), KlexError::ShapeMismatch { expected: (3, 2), found: (2, 2) }));
+}
+
+#[test]
+fn pixel_arithmetic_saturates_and_keeps_alpha() {
+    assert_eq!(color::saturating_add(Rgba([200, 10, 0, 128]), Rgba([100, 20, 255, 255])), Rgba([255, 30, 255, 128]));
+    assert_eq!(color::saturating_sub(Rgba([10, 200, 5, 7]), Rgba([20, 100, 5, 0])), Rgba([0, 100, 0, 7]));
+    assert_eq!(color::scale(Rgba([100, 200, 255, 77]), 1.5), Rgba([150, 255, 255, 77]));
+    assert_eq!(color::scale(Rgba([100, 200, 255, 77]), -1.0), Rgba([0, 0, 0, 77]));
+    assert_eq!(color::scale(Rgba([255, 1, 0, 77]), 0.5), Rgba([128, 1, 0, 77]), "Halves are rounded up");
+
+    let (black, white) = (Rgba([0, 0, 0, 0]), Rgba([255; 4]));
+    assert_eq!(color::lerp(black, white, 0.5), Rgba([128; 4]));
+    assert_eq!(color::lerp(black, white, 0.0), black);
+    assert_eq!(color::lerp(black, white, 2.0), white, "t is clamped");
+    assert_eq!(color::lerp(white, black, -1.0), white);
+}
+
+#[test]
+fn pixels_are_composited_with_straight_or_premultiplied_alpha() {
+    let half_red = Rgba([255, 128, 0, 128]);
+    assert_eq!(color::premultiply(half_red), Rgba([128, 64, 0, 128]));
+    assert_eq!(color::unpremultiply(color::premultiply(half_red)), half_red);
+    assert_eq!(color::unpremultiply(Rgba([10, 20, 30, 0])), Rgba([0; 4]), "Transparent pixels have no color");
+
+    let blue = Rgba([0, 0, 255, 255]);
+    assert_eq!(color::over(Rgba([255, 0, 0, 128]), blue), Rgba([128, 0, 127, 255]));
+    assert_eq!(color::over(Rgba([255, 0, 0, 255]), blue), Rgba([255, 0, 0, 255]), "Opaque pixels cover");
+    assert_eq!(color::over(Rgba([255, 0, 0, 0]), blue), blue, "Transparent pixels don't");
+    assert_eq!(color::over_premultiplied(Rgba([255; 4]), Rgba([255; 4])), Rgba([255; 4]), "Sums saturate");
+    assert_eq!(color::over_premultiplied(Rgba([0, 0, 0, 0]), Rgba([0; 4])), Rgba([0; 4]));
+}
+
+#[test]
+fn conversions_take_the_shortest_path() {
+    let graph = ConversionGraph::builtins();
+    let path = graph.path("Gray16Image", "RgbaImage").unwrap();
+    assert_eq!(path, ["Gray16Image", "GrayImage", "RgbaImage"]);
+    assert_eq!(graph.path("GrayImage", "GrayImage").unwrap(), ["GrayImage"]);
+    assert!(graph.can_convert("RgbaImage", "BinaryImage"));
+    assert!(!graph.can_convert("RgbaImage", "Contours"));
+    assert!(!ConversionGraph::new().can_convert("GrayImage", "RgbaImage"));
+
+    let image = Gray16Image::from_fn(2, 1, |x, _| Luma([[0, u16::MAX][x as usize]]));
+    let converted = graph.convert(&image, "RgbaImage").unwrap();
+    let converted = converted.downcast_ref::<RgbaImage>().unwrap();
+    assert_eq!(converted.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+    assert_eq!(converted.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+}
+
+#[test]
+fn failed_conversions_name_their_path() {
+    let image = RgbaImage::from_pixel(1, 1, Rgba([100, 100, 100, 255])); // Gray, but neither black nor white
+    let error = ConversionGraph::builtins().convert(&image, "BinaryImage").unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("GrayImage to BinaryImage"), "{}", message);
+    assert!(message.contains("RgbaImage -> GrayImage -> BinaryImage"), "{}", message);
+}
+
+#[test]
+fn registry_creates_conversions_through_other_elements() {
+    let registry = LayerRegistry::with_builtins();
+    let layer = registry.create_default("Convert<BinaryImage, RgbaImage>").unwrap();
+    assert_eq!(layer.kind(), "Convert<BinaryImage, RgbaImage>");
+    assert_eq!(layer.input_types(), ["BinaryImage"]);
+    assert_eq!(layer.output_type(), Some("RgbaImage"));
+
+    let input: LayerOutput = Some(Box::new(BinaryImage::new(2, 1, vec![false, true])));
+    let mut output = None;
+    layer.compute(&[&input], &mut output).unwrap();
+    let output = output.unwrap();
+    assert_eq!(output.downcast_ref::<RgbaImage>().unwrap().get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+
+    // Direct conversions keep their own layers
+    assert!(registry.create_default("Convert<RgbaImage, GrayImage>").is_ok());
+    assert!(registry.create_default("Convert<RgbaImage, Contours>").is_err());
+}
+
+#[test]
+fn white_balance_estimates_and_reuses_gains() {
+    // A warm cast, with a transparent pixel that would count as blue
+    let cast = [Rgba([200, 100, 50, 255]), Rgba([100, 50, 25, 255]), Rgba([0, 0, 255, 0])];
+    let image = RgbaImage::from_fn(3, 1, |x, _| cast[x as usize]);
+    let layer = WhiteBalance::new(WhiteBalanceMethod::GrayWorld, 1.0);
+    assert_eq!(layer.gains(), None);
+    assert!(layer.metadata().is_empty());
+    let balanced = layer.compute(&image);
+    let gains = layer.gains().unwrap();
+    let expected = [87.5 / 150.0, 87.5 / 75.0, 87.5 / 37.5];
+    assert!(gains.iter().zip(expected).all(|(gain, expected)| (gain - expected).abs() < 1e-5), "{:?}", gains);
+    assert_eq!(balanced.get_pixel(0, 0), &Rgba([117, 117, 117, 255]));
+    assert_eq!(balanced.get_pixel(2, 0), &Rgba([0, 0, 255, 0]), "Values are clamped and alpha is kept");
+    assert_eq!(layer.metadata()["green_gain"], ParamValue::Float(f64::from(gains[1])));
+
+    // Frozen gains balance other images the same way, also in place
+    let frozen = WhiteBalance::with_gains(gains);
+    let other = RgbaImage::from_pixel(2, 2, Rgba([60, 30, 15, 255]));
+    assert_eq!(frozen.compute(&other), RgbaImage::from_pixel(2, 2, Rgba([35, 35, 35, 255])));
+    let mut input: Box<dyn std::any::Any> = Box::new(other.clone());
+    assert!(frozen.computes_in_place());
+    frozen.compute_in_place(input.as_mut()).unwrap();
+    assert_eq!(input.downcast_ref::<RgbaImage>(), Some(&frozen.compute(&other)));
+    let mut reloaded = LayerRegistry::with_builtins().create_default("WhiteBalance").unwrap();
+    for (name, value) in frozen.parameters() {
+        reloaded.set_parameter(&name, value).unwrap();
+    }
+    let input: LayerOutput = Some(Box::new(other.clone()));
+    let mut output = None;
+    reloaded.compute(&[&input], &mut output).unwrap();
+    assert_eq!(output.unwrap().downcast_ref::<RgbaImage>(), Some(&frozen.compute(&other)));
+}
+
+#[test]
+fn white_balance_gains_are_known_after_every_run() {
+    let directory = std::env::temp_dir().join(format!("klex-white-balance-{}", std::process::id()));
+    let cache = Arc::new(OutputCache::new(&directory, 1 << 26).unwrap());
+    for _ in 0..2 {
+        let mut graph = LayerGraph::new();
+        graph.set_output_cache(Some(cache.clone()));
+        let input = graph.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![]);
+        let balance = WhiteBalance::new(WhiteBalanceMethod::GrayWorld, 1.0);
+        let balance = graph.add_layer(Box::new(balance), vec![input]);
+        graph.compute_all().unwrap();
+        let metadata = graph.layer(balance).unwrap().metadata();
+        assert!(metadata.contains_key("green_gain"), "Outputs aren't taken from the cache");
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn white_patch_ignores_outliers_and_saturation() {
+    // One of 100 pixels is a bright outlier, the others reach 200 in red and 100 in green and blue at most
+    let image = RgbaImage::from_fn(10, 10, |x, y| match (x, y) {
+        (0, 0) => Rgba([255, 255, 255, 255]),
+        _ => Rgba([(x * 20 + 20) as u8, (y * 10 + 10) as u8, 100, 255]),
+    });
+    let gains = WhiteBalance::new(WhiteBalanceMethod::WhitePatch, 0.0).estimate(&image);
+    assert_eq!(gains, [1.0; 3]);
+    let gains = WhiteBalance::new(WhiteBalanceMethod::WhitePatch, 1.0).estimate(&image);
+    assert_eq!(gains, [255.0 / 200.0, 255.0 / 100.0, 255.0 / 100.0]);
+
+    // Saturated or empty channels get finite gains
+    let red = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 1, 255]));
+    for method in [WhiteBalanceMethod::GrayWorld, WhiteBalanceMethod::WhitePatch] {
+        let gains = WhiteBalance::new(method, 1.0).estimate(&red);
+        assert!(gains.iter().all(|gain| gain.is_finite() && *gain <= WhiteBalance::MAX_GAIN), "{:?}", gains);
+        assert_eq!(gains[1], 1.0, "Nothing is known about a black channel");
+    }
+    assert_eq!(WhiteBalance::new(WhiteBalanceMethod::WhitePatch, 1.0).estimate(&red)[2], WhiteBalance::MAX_GAIN);
+    let transparent = RgbaImage::new(2, 2);
+    assert_eq!(WhiteBalance::new(WhiteBalanceMethod::GrayWorld, 1.0).estimate(&transparent), [1.0; 3]);
+}
+
+#[test]
+fn vignettes_are_applied_and_removed() {
+    let flat = GrayImage::from_pixel(21, 21, Luma([200]));
+    let mut layer = Vignette::<GrayImage>::new(0.0, 0.45, [0.0; 2]);
+    assert_eq!(layer.compute(&flat), flat, "Strength 0 leaves the image as it is");
+    layer.set_parameter("strength", ParamValue::Float(0.5)).unwrap();
+    let darkened = layer.compute(&flat);
+    assert_eq!(darkened.get_pixel(10, 10), &Luma([200]));
+    assert_eq!(darkened.get_pixel(0, 0)[0], 100, "The corners are beyond the radius");
+    assert!(darkened.get_pixel(5, 10)[0] < 200 && darkened.get_pixel(5, 10)[0] > 100);
+
+    // A negative strength undoes the same positive one
+    let distances = layer.distances(21, 21);
+    layer.set_parameter("strength", ParamValue::Float(-0.5)).unwrap();
+    assert!(std::sync::Arc::ptr_eq(&layer.distances(21, 21), &distances), "Only the size and center matter");
+    let restored = layer.compute(&darkened);
+    assert!(restored.pixels().all(|pixel| pixel[0].abs_diff(200) <= 2), "{:?}", restored);
+    assert!(!std::sync::Arc::ptr_eq(&layer.distances(20, 21), &distances));
+
+    // The center can be moved, and alpha is kept
+    let mut layer = Vignette::<RgbaImage>::new(0.8, 0.5, [0.0; 2]);
+    layer.set_parameter("center_x", ParamValue::Float(0.5)).unwrap();
+    let image = RgbaImage::from_pixel(21, 11, Rgba([100, 150, 200, 128]));
+    let mut input: Box<dyn std::any::Any> = Box::new(image.clone());
+    layer.compute_in_place(input.as_mut()).unwrap();
+    let darkened = input.downcast_ref::<RgbaImage>().unwrap();
+    assert_eq!(darkened, &layer.compute(&image));
+    let (left, right) = (darkened.get_pixel(0, 5), darkened.get_pixel(20, 5));
+    assert!(left[2] < right[2] && right[2] >= 195, "{:?} {:?}", left, right);
+    assert!(darkened.pixels().all(|pixel| pixel[3] == 128));
+    let gray_alpha = GrayAlphaImage::from_pixel(21, 11, LumaA([200, 128]));
+    let darkened = Vignette::<GrayAlphaImage>::new(0.8, 0.5, [0.0; 2]).compute(&gray_alpha);
+    assert!(darkened.get_pixel(0, 0)[0] < 200 && darkened.pixels().all(|pixel| pixel[1] == 128));
+    let registry = LayerRegistry::with_builtins();
+    assert_eq!(registry.create_default("Vignette<RgbaImage>").unwrap().parameters()["radius"], ParamValue::Float(0.5));
+}
@@ -0,0 +1,106 @@
+use std::cmp::Ordering;
+
+use image::GrayImage;
+
+use klex::{
+    entity::BinaryImage,
+    error::Result,
+    layer::{
+        primitive::{Convert, Threshold},
+        Layer, LayerOutput,
+    },
+    layer_graph::LayerGraph,
+    parameter::ParamValue,
+};
+
+/// Source layer producing a horizontal gradient
+struct Gradient;
+
+impl Layer for Gradient {
+    fn kind(&self) -> String {
+        "Gradient".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(GrayImage::from_fn(256, 4, |x, _| image::Luma([x as u8]))));
+        Ok(())
+    }
+}
+
+/// Gradient → Threshold → Convert → Convert, with the threshold renamed to "binarize"
+fn pipeline() -> LayerGraph {
+    let mut graph = LayerGraph::new();
+    let source = graph.add_layer(Box::new(Gradient), vec![]);
+    let threshold = graph.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![source]);
+    graph.rename(threshold, "binarize".to_string()).unwrap();
+    let convert = graph.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    graph.add_layer(Box::new(Threshold::new(0, Ordering::Greater)), vec![convert]);
+    graph
+}
+
+fn white_pixels(graph: &LayerGraph, layer: petgraph::graph::NodeIndex) -> usize {
+    let image = graph.output(layer, 0).unwrap().downcast_ref::<BinaryImage>().unwrap();
+    image.data().iter().filter(|&&pixel| pixel).count()
+}
+
+#[test]
+fn grouped_layers_compute_the_same_output() {
+    let mut reference = pipeline();
+    reference.compute_all().unwrap();
+
+    let mut grouped = pipeline();
+    let composite = grouped.group_nodes(&[1.into(), 2.into()]).unwrap();
+    assert_eq!(grouped.node_count(), 3);
+    assert_eq!(grouped.inputs(composite), vec![(0.into(), 0)]);
+    assert_eq!(grouped.inputs(3.into()), vec![(composite, 0)]);
+
+    grouped.compute_all().unwrap();
+    assert_eq!(white_pixels(&grouped, 3.into()), white_pixels(&reference, 3.into()));
+    assert_eq!(white_pixels(&grouped, 3.into()), 155 * 4);
+}
+
+#[test]
+fn inner_parameters_are_namespaced() {
+    let mut graph = pipeline();
+    let composite = graph.group_nodes(&[1.into(), 2.into()]).unwrap();
+    assert_eq!(
+        graph.parameters(composite).unwrap()["binarize.threshold"],
+        ParamValue::Int(100)
+    );
+
+    graph
+        .set_parameter(composite, "binarize.threshold", ParamValue::Int(200))
+        .unwrap();
+    graph.compute_all().unwrap();
+    assert_eq!(white_pixels(&graph, 3.into()), 55 * 4);
+
+    assert!(graph.set_parameter(composite, "blur.sigma", ParamValue::Float(1.0)).is_err());
+    assert!(graph.set_parameter(composite, "threshold", ParamValue::Int(1)).is_err());
+}
+
+#[test]
+fn composite_layers_can_be_duplicated() {
+    let mut graph = pipeline();
+    let composite = graph.group_nodes(&[1.into(), 2.into()]).unwrap();
+    let copy = graph.duplicate_layer(composite).unwrap();
+    graph.set_parameter(copy, "binarize.threshold", ParamValue::Int(7)).unwrap();
+    assert_eq!(
+        graph.parameters(composite).unwrap()["binarize.threshold"],
+        ParamValue::Int(100)
+    );
+
+    graph.connect(0.into(), copy, 0).unwrap();
+    graph.compute_all().unwrap();
+    assert!(graph.output(copy, 0).unwrap().downcast_ref::<GrayImage>().is_some());
+}
+
+#[test]
+fn invalid_selections_are_rejected() {
+    let mut graph = pipeline();
+    assert!(graph.group_nodes(&[]).is_err());
+
+    // Both the threshold and the convert layer feed into layers outside the selection
+    graph.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![1.into()]);
+    assert!(graph.group_nodes(&[1.into(), 2.into()]).is_err());
+    assert_eq!(graph.node_count(), 5);
+}
@@ -0,0 +1,96 @@
+use image::{Rgba, RgbaImage};
+
+use klex::{
+    draw,
+    entity::{self, Contour, Geometry, Line, Point, Rect},
+    layer::{primitive::DrawGeometry, Layer, LayerOutput},
+    parameter::ParamValue,
+    registry::LayerRegistry,
+};
+
+const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+fn covered(image: &RgbaImage) -> Vec<(u32, u32)> {
+    image.enumerate_pixels().filter(|(_, _, pixel)| pixel[3] > 0).map(|(x, y, _)| (x, y)).collect()
+}
+
+#[test]
+fn shapes_are_rasterized_like_the_overlay() {
+    // A line along a row, from the pixel its start is in to the one its end is in
+    let lines = vec![Line { start: (1.5, 2.5), end: (6.2, 2.0) }];
+    let image = DrawGeometry::new(8, 4).compute(&entity::geometry(&lines).unwrap(), None);
+    assert_eq!(covered(&image), (1..=6).map(|x| (x, 2)).collect::<Vec<_>>());
+    assert!(image.enumerate_pixels().all(|(_, _, pixel)| pixel[3] == 0 || *pixel == RED));
+
+    // Points are crosses of two diagonals, like in the viewport
+    let geometry = Geometry { points: vec![(10.0, 10.0)], ..Default::default() };
+    assert_eq!(draw::strokes(&geometry, 2.0), [vec![(8.0, 8.0), (12.0, 12.0)], vec![(12.0, 8.0), (8.0, 12.0)]]);
+    let mut image = RgbaImage::new(20, 20);
+    let style = draw::Style { color: RED.0, width: 1, fill: false };
+    draw::shapes(&mut image, &geometry, &style);
+    let arm = draw::arm(1.0) as u32;
+    assert_eq!(covered(&image).len() as u32, 4 * arm + 1);
+    assert!((0..=2 * arm).all(|offset| image.get_pixel(10 - arm + offset, 10 - arm + offset) == &RED));
+
+    // Filled polygons cover the pixels whose centers are inside, and outlines are drawn over them only once
+    let corners = vec![(1.0, 1.0), (4.0, 1.0), (4.0, 4.0), (1.0, 4.0)];
+    let square = Geometry { polygons: vec![corners], ..Default::default() };
+    let mut image = RgbaImage::new(6, 6);
+    draw::shapes(&mut image, &square, &draw::Style { color: [0, 0, 255, 128], width: 0, fill: true });
+    assert_eq!(covered(&image), (1..4).flat_map(|y| (1..4).map(move |x| (x, y))).collect::<Vec<_>>());
+    let mut outlined = RgbaImage::new(6, 6);
+    draw::shapes(&mut outlined, &square, &draw::Style { color: [0, 0, 255, 128], width: 1, fill: true });
+    assert!(outlined.pixels().all(|pixel| pixel[3] == 0 || pixel[3] == 128));
+    assert_eq!(covered(&outlined).len(), 16, "The outline reaches to the pixels the last corner is in");
+
+    // Wide strokes and shapes far outside of the image are clipped
+    let mut image = RgbaImage::new(5, 5);
+    let far = Geometry {
+        lines: vec![((-1e9, 2.0), (1e9, 2.0)), ((f32::NAN, 0.0), (3.0, 3.0))],
+        polygons: vec![vec![(-1e9, -1e9), (1e9, -1e9), (0.0, 1e9)]],
+        ..Default::default()
+    };
+    draw::shapes(&mut image, &far, &draw::Style { color: RED.0, width: 3, fill: false });
+    assert_eq!(covered(&image), (1..=3).flat_map(|y| (0..5).map(move |x| (x, y))).collect::<Vec<_>>());
+    draw::shapes(&mut image, &far, &draw::Style { color: RED.0, width: 0, fill: true });
+    assert_eq!(covered(&image).len(), 25);
+}
+
+#[test]
+fn geometry_is_drawn_onto_images_or_transparent_ones() {
+    let mut layer = LayerRegistry::with_builtins().create_default("DrawGeometry").unwrap();
+    layer.set_parameter("fill", ParamValue::Bool(true)).unwrap();
+    layer.set_parameter("stroke", ParamValue::Int(0)).unwrap();
+    layer.set_parameter("color", ParamValue::Color([0, 255, 0, 255])).unwrap();
+    let rects: LayerOutput = Some(Box::new(vec![Rect::new(1, 2, 3, 2)]));
+    let mut output = None;
+    layer.compute(&[&rects], &mut output).unwrap();
+    let output = output.unwrap();
+    let drawn = output.downcast_ref::<RgbaImage>().unwrap();
+    // Without a size, the image reaches to the bottom right corner of the rectangle
+    assert_eq!(drawn.dimensions(), (4, 4));
+    assert_eq!(covered(drawn), [(1, 2), (2, 2), (3, 2), (1, 3), (2, 3), (3, 3)]);
+
+    // The image can be connected in either order and is drawn onto as a copy
+    let image = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+    let contours = vec![Contour { points: vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)] }];
+    let (input, contours): (LayerOutput, LayerOutput) = (Some(Box::new(image.clone())), Some(Box::new(contours)));
+    let mut output = None;
+    layer.compute(&[&contours, &input], &mut output).unwrap();
+    let output = output.unwrap();
+    let drawn = output.downcast_ref::<RgbaImage>().unwrap();
+    assert_eq!(drawn.dimensions(), (10, 10));
+    assert_eq!(drawn.get_pixel(1, 1), &Rgba([0, 255, 0, 255]));
+    assert_eq!(drawn.get_pixel(8, 8), &Rgba([0, 0, 0, 255]));
+    assert!(layer.compute(&[&input], &mut None).is_err(), "There have to be shapes");
+    assert!(layer.compute(&[&contours, &contours], &mut None).is_err());
+
+    let points = vec![Point { x: 3.0, y: 3.0 }];
+    let mut layer = DrawGeometry::new(30, 20);
+    layer.scale_parameters(0.1);
+    assert_eq!(layer.parameters()["stroke"], ParamValue::Int(1), "Strokes stay visible");
+    let drawn = layer.compute(&entity::geometry(&points).unwrap(), None);
+    assert_eq!(drawn.dimensions(), (3, 2));
+    assert_eq!(covered(&drawn).len(), 2, "Only the part of the cross in the image");
+    assert_eq!(entity::table(&vec![Rect::new(1, 2, 3, 4)]).unwrap().rows, [vec![1.0, 2.0, 3.0, 4.0]]);
+}
@@ -0,0 +1,284 @@
+use image::{DynamicImage, GrayAlphaImage, GrayImage, Luma, RgbImage, RgbaImage};
+
+use klex::{
+    entity::{self, BinaryImage, DataFile, DataFormat, Histogram, Line, Point, Rect},
+    testing::assert_images_close,
+};
+
+/// A 3x2 image with only the pixel in the last column of the first row set, which tells x and y apart
+fn corner() -> BinaryImage {
+    BinaryImage::new(3, 2, vec![false, false, true, false, false, false])
+}
+
+#[test]
+fn pixels_are_addressed_by_column_then_row() {
+    let image = corner();
+    assert_eq!(image.get(2, 0), Some(&true));
+    assert_eq!(image.get(0, 2), None, "There are only two rows");
+    assert_eq!(image.get(3, 0), None);
+    assert!(image[(2, 0)]);
+    assert!(!image[(1, 1)]);
+
+    let rows = image.rows().collect::<Vec<_>>();
+    assert_eq!(rows, [&[false, false, true][..], &[false; 3][..]]);
+    for (y, row) in image.rows().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            assert_eq!(image[(x as u32, y as u32)], pixel);
+        }
+    }
+}
+
+#[test]
+fn pixels_are_edited_in_place() {
+    let mut image = corner();
+    *image.get_mut(0, 1).unwrap() = true;
+    image[(2, 0)] = false;
+    assert!(image.get_mut(1, 2).is_none());
+    assert_eq!(image.data(), &[false, false, false, true, false, false]);
+
+    for (x, y, pixel) in image.pixels_mut() {
+        *pixel = x == 2 && y == 1;
+    }
+    assert_eq!(image.data(), &[false, false, false, false, false, true]);
+
+    image.data_mut().fill(true);
+    assert!(image.rows().flatten().all(|&pixel| pixel));
+}
+
+#[test]
+#[should_panic(expected = "outside of the 3x2 image")]
+fn indexing_outside_of_the_image_panics() {
+    let _ = corner()[(0, 2)];
+}
+
+#[test]
+fn images_compare_by_their_pixels() {
+    let mut image = corner();
+    assert_eq!(image, corner());
+    assert_eq!(format!("{:?}", image), "BinaryImage { width: 3, height: 2, set: 1 }");
+    image[(0, 0)] = true;
+    assert_ne!(image, corner());
+    assert_ne!(
+        BinaryImage::new(2, 3, corner().data().clone()),
+        corner(),
+        "The size matters too"
+    );
+}
+
+#[test]
+fn images_are_close_within_the_tolerance() {
+    let image = GrayImage::from_pixel(2, 2, Luma([100]));
+    let mut brighter = image.clone();
+    brighter.put_pixel(1, 0, Luma([102]));
+    assert_images_close(&image, &brighter, 2);
+    let result = std::panic::catch_unwind(|| assert_images_close(&image, &brighter, 1));
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("(1, 0)"), "{}", message);
+}
+
+#[test]
+fn binary_images_are_saved_as_pbm() {
+    let directory = std::env::temp_dir().join(format!("klex-entity-pbm-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    // Wider than a byte, so that rows are padded
+    let image = BinaryImage::new(10, 2, (0..20).map(|i| i % 3 == 0).collect());
+    let path = directory.join("mask.PBM");
+    entity::save(&image, &path).unwrap();
+    assert_eq!(BinaryImage::open_pbm(&path).unwrap(), image);
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(bytes.len(), "P4\n10 2\n".len() + 2 * 2);
+
+    // Other programs see set pixels in white too
+    let gray = image::open(&path).unwrap().into_luma8();
+    let shown = gray.pixels().map(|pixel| pixel.0[0] != 0).collect::<Vec<_>>();
+    assert_eq!(&shown, image.data());
+
+    entity::export(&image, &directory.join("mask.png"), 90).unwrap();
+    let png = image::open(directory.join("mask.png")).unwrap().into_luma8();
+    assert_images_close(&png, &gray, 0);
+
+    std::fs::write(&path, b"P4\n# A comment\n3 1\n\x20").unwrap();
+    assert_eq!(BinaryImage::open_pbm(&path).unwrap().data(), &[true, true, false]);
+    std::fs::write(&path, b"P4\n3 2\n\x20").unwrap();
+    assert!(BinaryImage::open_pbm(&path).is_err(), "Rows are missing");
+    std::fs::write(&path, b"P1\n1 1\n1").unwrap();
+    assert!(BinaryImage::open_pbm(&path).is_err(), "Plain PBM isn't supported");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn images_of_the_image_crate_keep_their_buffers() {
+    let rgba = RgbaImage::new(4, 3);
+    let pixels = rgba.as_raw().as_ptr();
+    let element = entity::from_dynamic(DynamicImage::ImageRgba8(rgba));
+    assert!(std::ptr::eq(
+        element.downcast_ref::<RgbaImage>().unwrap().as_raw().as_ptr(),
+        pixels
+    ));
+
+    let gray = GrayImage::new(4, 3);
+    let pixels = gray.as_raw().as_ptr();
+    let element = entity::from_dynamic(DynamicImage::ImageLuma8(gray));
+    assert!(std::ptr::eq(
+        element.downcast_ref::<GrayImage>().unwrap().as_raw().as_ptr(),
+        pixels
+    ));
+    assert!(entity::from_dynamic(DynamicImage::ImageLumaA8(GrayAlphaImage::new(1, 1))).is::<GrayAlphaImage>());
+
+    let rgb = RgbImage::from_pixel(2, 1, image::Rgb([1, 2, 3]));
+    let element = entity::from_dynamic(DynamicImage::ImageRgb8(rgb));
+    assert_eq!(
+        element.downcast_ref::<RgbaImage>().unwrap().as_raw(),
+        &[1, 2, 3, 255, 1, 2, 3, 255]
+    );
+}
+
+#[test]
+fn binary_images_convert_to_black_and_white() {
+    let gray = GrayImage::try_from(&corner()).unwrap();
+    assert_eq!(gray.as_raw(), &[0, 0, 255, 0, 0, 0]);
+    assert_eq!(BinaryImage::try_from(&gray).unwrap(), corner());
+
+    let mut blurred = gray;
+    blurred.put_pixel(1, 0, Luma([128]));
+    let error = BinaryImage::try_from(&blurred).unwrap_err();
+    assert!(error.to_string().contains("128"), "{}", error);
+}
+
+/// All rectangles in a small area, including empty ones
+fn small_rects() -> Vec<Rect> {
+    let range = || 0..4;
+    let rects = range().flat_map(|x| range().flat_map(move |y| range().map(move |width| (x, y, width))));
+    rects
+        .flat_map(|(x, y, width)| range().map(move |height| Rect::new(x, y, width, height)))
+        .collect()
+}
+
+#[test]
+fn rect_operations_agree_with_the_pixels_they_contain() {
+    let pixels = |rect: Rect| {
+        let pixels = (0..8).flat_map(|y| (0..8).map(move |x| (x, y)));
+        pixels.filter(|&(x, y)| rect.contains(x, y)).collect::<Vec<_>>()
+    };
+    for a in small_rects() {
+        assert_eq!(pixels(a).len() as u64, a.area());
+        assert_eq!(a.is_empty(), a.area() == 0);
+        for b in small_rects() {
+            let both = pixels(a)
+                .into_iter()
+                .filter(|&(x, y)| b.contains(x, y))
+                .collect::<Vec<_>>();
+            match a.intersection(&b) {
+                Some(intersection) => assert_eq!(pixels(intersection), both, "{:?} {:?}", a, b),
+                None => assert!(both.is_empty(), "{:?} and {:?} share pixels", a, b),
+            }
+            assert_eq!(a.intersection(&b), b.intersection(&a));
+
+            let union = a.union(&b);
+            assert!(
+                pixels(a)
+                    .into_iter()
+                    .chain(pixels(b))
+                    .all(|(x, y)| union.contains(x, y)),
+                "{:?} {:?}",
+                a,
+                b
+            );
+            assert_eq!(union, b.union(&a));
+        }
+    }
+}
+
+#[test]
+fn rects_are_clamped_and_combined_at_their_edges() {
+    let a = Rect::new(0, 0, 2, 2);
+    assert_eq!(
+        a.intersection(&Rect::new(2, 0, 2, 2)),
+        None,
+        "Touching rectangles don't intersect"
+    );
+    assert_eq!(a.intersection(&Rect::new(5, 5, 1, 1)), None);
+    assert_eq!(a.intersection(&Rect::new(1, 1, 5, 5)), Some(Rect::new(1, 1, 1, 1)));
+    assert_eq!(
+        a.intersection(&Rect::new(0, 0, 1, 1)),
+        Some(Rect::new(0, 0, 1, 1)),
+        "Contained"
+    );
+    assert_eq!(a.union(&Rect::new(2, 0, 2, 2)), Rect::new(0, 0, 4, 2));
+    assert_eq!(a.union(&Rect::new(9, 9, 0, 3)), a, "Empty rectangles are ignored");
+    assert_eq!(Rect::new(5, 5, 0, 0).union(&a), a);
+
+    assert_eq!(Rect::new(3, 1, 10, 10).clamped((5, 4)), Rect::new(3, 1, 2, 3));
+    assert_eq!(Rect::new(7, 1, 10, 10).clamped((5, 4)), Rect::new(5, 1, 0, 3));
+    let huge = Rect::new(u32::MAX - 1, 0, u32::MAX, 1);
+    assert_eq!(huge.right(), u32::MAX, "Edges saturate");
+    assert!(huge.contains(u32::MAX - 1, 0));
+}
+
+#[test]
+fn thumbnails_picture_images_and_shapes() {
+    let float = entity::GrayImageF32::from_fn(400, 200, |x, _| Luma([x as f32 / 399.0]));
+    let thumbnail = entity::thumbnail(&float, 96).unwrap();
+    assert_eq!(thumbnail.dimensions(), (96, 48));
+    let [dark, .., alpha] = thumbnail.get_pixel(0, 0).0;
+    assert!(
+        dark < 5 && alpha == 255,
+        "Values from 0 to 1 are shown from black to white"
+    );
+    assert!(thumbnail.get_pixel(95, 0).0[0] > 250);
+    let small = entity::thumbnail(&corner(), 96).unwrap();
+    assert_eq!(
+        (small.dimensions(), small.get_pixel(2, 0).0),
+        ((3, 2), [255, 255, 255, 255]),
+        "Not scaled up"
+    );
+
+    // A diagonal line is drawn across the plot, whatever its coordinates
+    let line = entity::Line {
+        start: (1000.0, 1000.0),
+        end: (1100.0, 1100.0),
+    };
+    let plot = entity::thumbnail(&vec![line], 32).unwrap();
+    assert_eq!(plot.dimensions(), (32, 32));
+    assert_ne!(plot.get_pixel(16, 16), plot.get_pixel(28, 4));
+    assert!(entity::thumbnail(&vec![1u8], 32).is_none());
+}
+
+#[test]
+fn data_is_written_as_csv_and_json() {
+    let points = vec![Point { x: 1.5, y: 2.0 }, Point { x: f32::NAN, y: 0.0 }];
+    let csv = DataFile::of(&points, DataFormat::Csv).unwrap();
+    assert_eq!(csv.text, "x,y\n1.5,2\nNaN,0\n");
+    let json = DataFile::of(&points, DataFormat::Json).unwrap();
+    assert_eq!(
+        json.text,
+        "[\n  {\"x\": 1.5, \"y\": 2},\n  {\"x\": null, \"y\": 0}\n]\n"
+    );
+
+    let lines = vec![Line {
+        start: (0.0, 1.0),
+        end: (2.0, 3.0),
+    }];
+    assert_eq!(
+        DataFile::of(&lines, DataFormat::Csv).unwrap().text,
+        "x1,y1,x2,y2\n0,1,2,3\n"
+    );
+    let mut channel = [0; 256];
+    channel[3] = 7;
+    let histogram = Histogram {
+        channels: vec![channel],
+    };
+    let table = entity::table(&histogram).unwrap();
+    assert_eq!(
+        (table.columns, table.rows.len(), &table.rows[3]),
+        (vec!["bin", "count"], 256, &vec![3.0, 7.0])
+    );
+
+    let error = DataFile::of(&GrayImage::new(1, 1), DataFormat::Csv)
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        error,
+        "Can't export GrayImage as data, only Lines, Points, Contours, Rects, Histogram"
+    );
+}
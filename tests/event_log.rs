@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+
+use image::{GrayImage, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    entity::{BinaryImage, Stroke},
+    error::KlexError,
+    event_log::{EventLog, GraphEvent},
+    layer::primitive::{Convert, InputFile, PaintedMask, Threshold},
+    layer_graph::{InteractiveLayerGraph, NodeSnapshot},
+    parameter::{ParamKind, ParamSpec, ParamValue},
+    recipe::Recipe,
+    registry::LayerRegistry,
+};
+
+fn canvas(layers: &mut InteractiveLayerGraph, mask: NodeIndex) -> BinaryImage {
+    layers.compute_layer(mask).unwrap();
+    let output = layers.graph().output(mask, 0).unwrap();
+    output.downcast_ref::<BinaryImage>().unwrap().clone()
+}
+
+#[test]
+fn replaying_a_log_reproduces_the_graph() {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    layers.set_parameter(threshold, "threshold", ParamValue::Int(120)).unwrap();
+    layers.rename(threshold, "binarize".to_string()).unwrap();
+    layers.move_layer(threshold, (440.0, 20.0)).unwrap();
+    let mask = layers.add_layer(Box::new(PaintedMask::new(20, 10)), vec![]);
+    let stroke = |points: Vec<(f32, f32)>, erase| Stroke { points, radius: 2.0, erase };
+    layers.paint(mask, &stroke(vec![(2.0, 5.0), (18.0, 5.0)], false)).unwrap();
+    layers.end_stroke();
+    layers.paint(mask, &stroke(vec![(10.0, 5.0)], true)).unwrap();
+    layers.undo().unwrap(); // Logged as the canvas from before the stroke
+    layers.remove_layer(gray).unwrap();
+    layers.undo().unwrap(); // Logged as the layer and its connections being added back
+    layers.disconnect(threshold, 0).unwrap();
+    layers.undo().unwrap();
+    layers.redo().unwrap();
+
+    let mut log = EventLog::new();
+    layers.record_to(&mut log);
+    assert!(log.events().windows(2).all(|pair| pair[0].time <= pair[1].time));
+    assert!(log.events().iter().any(|logged| matches!(logged.event, GraphEvent::SetCanvas { .. })));
+    let loaded = EventLog::from_ron(&log.to_ron().unwrap()).unwrap();
+    assert_eq!(loaded, log);
+
+    let mut replayed = InteractiveLayerGraph::new();
+    loaded.replay_onto(&mut replayed, &LayerRegistry::with_builtins()).unwrap();
+    assert_eq!(Recipe::from_graph(&replayed), Recipe::from_graph(&layers));
+    let (old, new) = (layers.snapshot(), replayed.snapshot());
+    let ids = |nodes: &[NodeSnapshot]| nodes.iter().map(|node| (node.node, node.id)).collect::<Vec<_>>();
+    assert_eq!(ids(&new.nodes), ids(&old.nodes));
+    assert_eq!(new.edges, old.edges);
+    assert_eq!(canvas(&mut replayed, mask), canvas(&mut layers, mask));
+
+    // Later changes go to the end of the log
+    layers.rename(input, "tulips".to_string()).unwrap();
+    layers.record_to(&mut log);
+    let last = &log.events().last().unwrap().event;
+    assert!(matches!(last, GraphEvent::Rename { name, .. } if name == "tulips"));
+    let mut empty = EventLog::new();
+    layers.record_to(&mut empty);
+    assert!(empty.events().is_empty());
+}
+
+#[test]
+fn diverging_replays_name_the_event() {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![]);
+    layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let mut log = EventLog::new();
+    layers.record_to(&mut log);
+
+    let mut registry = LayerRegistry::new();
+    let path = ParamSpec::new("path", ParamKind::Path, None);
+    registry.register_default(|| InputFile::<RgbaImage>::new("".into()), vec![path]);
+    let mut replayed = InteractiveLayerGraph::new();
+    let error = log.replay_onto(&mut replayed, &registry).err().unwrap();
+    assert!(matches!(error.root(), KlexError::ReplayFailed { position: 1, .. }));
+    assert_eq!(replayed.graph().node_count(), 1, "Events before the failing one stay applied");
+
+    // Replaying onto a graph that already has the layers fails right away
+    let error = log.replay_onto(&mut layers, &LayerRegistry::with_builtins()).err().unwrap();
+    assert!(matches!(error.root(), KlexError::ReplayFailed { position: 0, .. }));
+}
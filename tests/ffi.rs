@@ -0,0 +1,69 @@
+#![cfg(feature = "ffi")]
+
+use std::{cmp::Ordering, ffi::CString, ptr};
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    entity::BinaryImage,
+    ffi::{self, KlexImage, KlexRunner, KlexStatus},
+    layer::primitive::{Convert, InputFile, Threshold},
+    layer_graph::InteractiveLayerGraph,
+    recipe::Recipe,
+};
+
+fn last_error() -> String {
+    let mut buffer = vec![0_u8; 256];
+    let size = unsafe { ffi::klex_last_error(buffer.as_mut_ptr().cast(), buffer.len()) };
+    assert!(size > 0 && size <= buffer.len(), "Needs {} bytes", size);
+    String::from_utf8(buffer[..size - 1].to_vec()).unwrap()
+}
+
+#[test]
+fn recipes_run_through_the_c_interface() {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("missing.png".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    let result = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers.rename(input, "input".to_string()).unwrap();
+    layers.rename(result, "result".to_string()).unwrap();
+    let path = std::env::temp_dir().join(format!("klex-ffi-{}.ron", std::process::id()));
+    Recipe::from_graph(&layers).save(&path).unwrap();
+
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let mut runner: *mut KlexRunner = ptr::null_mut();
+    assert_eq!(unsafe { ffi::klex_runner_new(path.as_ptr(), &mut runner) }, KlexStatus::Ok);
+    assert_eq!(unsafe { ffi::klex_last_error(ptr::null_mut(), 0) }, 0);
+
+    // Two gray pixels in rows of 3 pixels, of which the last one is padding
+    let row = |value| [value, value, value, 255];
+    let pixels: Vec<u8> = [row(50), row(200), row(0), row(220), row(10), row(0)].concat();
+    let (node, result) = (CString::new("input").unwrap(), CString::new("result").unwrap());
+    let status = unsafe { ffi::klex_runner_bind_rgba(runner, node.as_ptr(), pixels.as_ptr(), 2, 2, 12) };
+    assert_eq!(status, KlexStatus::Ok);
+    assert_eq!(unsafe { ffi::klex_runner_run(runner) }, KlexStatus::Ok);
+
+    let mut image = KlexImage { width: 0, height: 0, data: ptr::null_mut(), length: 0 };
+    assert_eq!(unsafe { ffi::klex_runner_output(runner, result.as_ptr(), &mut image) }, KlexStatus::Ok);
+    assert_eq!((image.width, image.height, image.length), (2, 2, 16));
+    let data = unsafe { std::slice::from_raw_parts(image.data, image.length) };
+    let red: Vec<u8> = data.chunks(4).map(|pixel| pixel[0]).collect();
+    assert_eq!(red, [0, 255, 255, 0]);
+    unsafe { ffi::klex_image_free(&mut image) };
+    assert!(image.data.is_null());
+
+    let missing = CString::new("missing").unwrap();
+    assert_eq!(unsafe { ffi::klex_runner_output(runner, missing.as_ptr(), &mut image) }, KlexStatus::Error);
+    assert!(last_error().contains("no node named \"missing\""), "{}", last_error());
+    let status = unsafe { ffi::klex_runner_bind_rgba(runner, node.as_ptr(), ptr::null(), 2, 2, 8) };
+    assert_eq!(status, KlexStatus::InvalidArgument);
+    let status = unsafe { ffi::klex_runner_bind_rgba(runner, node.as_ptr(), pixels.as_ptr(), 4, 1, 8) };
+    assert_eq!(status, KlexStatus::Error, "The stride is too small");
+
+    unsafe { ffi::klex_runner_free(runner) };
+    let unknown = CString::new("/nonexistent/recipe.ron").unwrap();
+    assert_eq!(unsafe { ffi::klex_runner_new(unknown.as_ptr(), &mut runner) }, KlexStatus::Error);
+    assert!(last_error().contains("recipe.ron"));
+    std::fs::remove_file(path.to_str().unwrap()).unwrap();
+}
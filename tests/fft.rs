@@ -0,0 +1,171 @@
+#![cfg(feature = "fft")]
+
+use image::{GrayImage, Luma};
+
+use klex::{
+    entity::{GrayImageF32, Point},
+    error::KlexError,
+    fft,
+    layer::{
+        port_output,
+        primitive::{AlignTranslate, FftFilter, FrequencyBand, SpectrumOutput},
+        Layer, LayerOutput,
+    },
+    parameter::ParamValue,
+    registry::LayerRegistry,
+};
+
+/// Smooth blobs of different sizes, as seen through a window at `(left, top)` of `width` by `height` pixels
+fn scene(left: f32, top: f32, width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32 + left, y as f32 + top);
+        let blob = |cx: f32, cy: f32, radius: f32| (-((x - cx).powi(2) + (y - cy).powi(2)) / radius.powi(2)).exp();
+        let value = blob(20.0, 15.0, 5.0) + blob(40.0, 30.0, 3.0) + 0.7 * blob(28.0, 40.0, 7.0) + blob(12.0, 35.0, 2.5);
+        Luma([(40.0 + 200.0 * value).min(255.0) as u8])
+    })
+}
+
+/// Deterministic noise, so that tests don't depend on a random generator
+fn noise(seed: u32, width: u32, height: u32) -> GrayImage {
+    let mut state = seed;
+    GrayImage::from_fn(width, height, |_, _| {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        Luma([(state >> 24) as u8])
+    })
+}
+
+#[test]
+fn whole_pixel_shifts_are_found_in_images_of_any_size() {
+    let first = scene(5.0, 3.0, 45, 37);
+    for (dx, dy) in [(4, -3), (-6, 2), (0, 0), (7, 5)] {
+        // Moving the window to the right moves the content to the left
+        let second = scene(5.0 - dx as f32, 3.0 - dy as f32, 45, 37);
+        let correlation = fft::phase_correlation(&first, &second, false).unwrap();
+        assert_eq!(correlation.shift, (dx as f32, dy as f32));
+        assert!(correlation.confidence > 0.3, "{:?}", correlation);
+    }
+
+    let unrelated = fft::phase_correlation(&noise(1, 45, 37), &noise(2, 45, 37), false).unwrap();
+    let shifted = fft::phase_correlation(&first, &scene(7.0, 4.0, 45, 37), false).unwrap();
+    assert!(unrelated.confidence < 0.2, "{:?}", unrelated);
+    assert!(shifted.confidence > 3.0 * unrelated.confidence);
+}
+
+#[test]
+fn subpixel_shifts_are_interpolated() {
+    let first = scene(5.0, 3.0, 48, 50);
+    let second = scene(2.5, 4.25, 48, 50);
+    let whole = fft::phase_correlation(&first, &second, false).unwrap();
+    assert!((whole.shift.0 - 2.5).abs() <= 0.5 && (whole.shift.1 + 1.25).abs() <= 0.5, "{:?}", whole);
+    let (x, y) = fft::phase_correlation(&first, &second, true).unwrap().shift;
+    assert!((x - 2.5).abs() < 0.25 && (y + 1.25).abs() < 0.25, "{}, {}", x, y);
+}
+
+#[test]
+fn the_second_image_is_moved_onto_the_first() {
+    let (first, second) = (scene(5.0, 3.0, 45, 37), scene(1.0, 5.0, 45, 37));
+    let layer = LayerRegistry::with_builtins().create_default("AlignTranslate").unwrap();
+    assert_eq!(layer.input_types(), ["GrayImage", "GrayImage"]);
+    assert_eq!(layer.outputs().iter().map(|port| port.name).collect::<Vec<_>>(), ["aligned", "shift"]);
+    let (first, second): (LayerOutput, LayerOutput) = (Some(Box::new(first)), Some(Box::new(second)));
+    let mut output = None;
+    layer.compute(&[&first, &second], &mut output).unwrap();
+
+    let aligned = port_output(&output, 0).as_deref().unwrap().downcast_ref::<GrayImage>().unwrap();
+    let shift = port_output(&output, 1).as_deref().unwrap().downcast_ref::<Vec<Point>>().unwrap();
+    assert_eq!(shift, &[Point { x: 4.0, y: -2.0 }]);
+    let first = first.as_ref().unwrap().downcast_ref::<GrayImage>().unwrap();
+    for (x, y, value) in aligned.enumerate_pixels() {
+        match x < 41 && y >= 2 {
+            true => assert!(value[0].abs_diff(first.get_pixel(x, y)[0]) <= 1, "At {}, {}", x, y),
+            false => assert_eq!(value[0], 0, "At {}, {}", x, y),
+        }
+    }
+    assert_eq!(layer.metadata()["shift_x"], ParamValue::Float(4.0));
+    assert!(matches!(layer.metadata()["confidence"], ParamValue::Float(confidence) if confidence > 0.3));
+}
+
+#[test]
+fn images_have_to_be_of_the_same_size() {
+    let layer = AlignTranslate::new(false);
+    assert!(layer.correlation().is_none() && layer.metadata().is_empty());
+    let error = layer.compute(&scene(0.0, 0.0, 20, 20), &scene(0.0, 0.0, 20, 21)).unwrap_err();
+    assert!(matches!(error.root(), KlexError::ShapeMismatch { .. }), "{}", error);
+    assert!(layer.compute(&GrayImage::new(0, 4), &GrayImage::new(0, 4)).is_err());
+}
+
+/// A slow ramp from left to right
+fn ramp(x: u32) -> f32 {
+    60.0 + 100.0 * (std::f32::consts::PI * x as f32 / 64.0).sin()
+}
+
+/// The raster of a scanner, in vertical lines every 4 pixels
+fn raster(x: u32) -> f32 {
+    if x % 4 < 2 {
+        40.0
+    } else {
+        0.0
+    }
+}
+
+fn scan() -> GrayImage {
+    GrayImage::from_fn(64, 30, |x, _| Luma([(ramp(x) + raster(x)) as u8]))
+}
+
+#[test]
+fn filters_keep_the_frequencies_of_their_band() {
+    let scan = scan();
+    let smooth = FftFilter::new(FrequencyBand::LowPass, 0.2, 1.0).compute(&scan);
+    for (x, y, value) in smooth.enumerate_pixels() {
+        let expected = (ramp(x) + 20.0) / 255.0;
+        assert!((value[0] - expected).abs() < 0.03, "At {}, {}: {} instead of {}", x, y, value[0], expected);
+    }
+    // What the low pass leaves out, the high pass with the same cutoff keeps
+    let high = FftFilter::new(FrequencyBand::HighPass, 0.2, 1.0).compute(&scan);
+    for ((smooth, high), original) in smooth.pixels().zip(high.pixels()).zip(scan.pixels()) {
+        assert!((smooth[0] + high[0] - f32::from(original[0]) / 255.0).abs() < 1e-4);
+    }
+
+    // A band around the raster keeps it alone, around zero
+    let band = FftFilter::new(FrequencyBand::BandPass, 0.3, 0.7).compute(&scan);
+    let mean = band.pixels().map(|value| value[0]).sum::<f32>() / band.len() as f32;
+    assert!(mean.abs() < 1e-4, "{}", mean);
+    for (x, y, value) in band.enumerate_pixels().filter(|&(x, _, _)| (16..48).contains(&x)) {
+        let expected = (raster(x) - 20.0).signum();
+        assert!(value[0].signum() == expected && value[0].abs() < 0.1, "At {}, {}: {}", x, y, value[0]);
+    }
+}
+
+#[test]
+fn the_spectrum_shows_periodic_noise() {
+    let scan = scan();
+    let mut layer = LayerRegistry::with_builtins().create_default("FftFilter").unwrap();
+    assert_eq!(layer.output_type(), Some("GrayImageF32"));
+    layer.set_parameter("output", ParamValue::Choice("Spectrum".to_string())).unwrap();
+    let input: LayerOutput = Some(Box::new(scan));
+    let mut output = None;
+    layer.compute(&[&input], &mut output).unwrap();
+    let output = output.unwrap();
+    let spectrum = output.downcast_ref::<GrayImageF32>().unwrap();
+    assert_eq!(spectrum.dimensions(), (64, 30));
+    assert!(spectrum.pixels().all(|value| (0.0..=1.0).contains(&value[0])));
+    assert_eq!(spectrum.get_pixel(32, 15)[0], 1.0, "The mean is strongest, in the center");
+    // The raster has a period of 4 of 64 pixels, so it is 16 positions off the center, on both sides
+    let [left, right, between] = [16, 48, 40].map(|x| spectrum.get_pixel(x, 15)[0]);
+    assert!(left > 0.7 && (left - right).abs() < 1e-4 && between < 0.75 * left, "{} {} {}", left, right, between);
+    assert!(spectrum.get_pixel(16, 10)[0] < 0.2, "Lines that are straight up only spread sideways");
+}
+
+#[test]
+fn cutoffs_follow_the_preview_scale() {
+    let layer = FftFilter::new(FrequencyBand::LowPass, 0.5, 1.0);
+    assert_eq!(layer.gain(0.0), 1.0);
+    assert!((layer.gain(0.5) - 0.5).abs() < 1e-6);
+    let mut layer = FftFilter::new(FrequencyBand::BandPass, 0.25, 0.375).with_output(SpectrumOutput::Filtered);
+    assert!(layer.gain(0.0) < 1e-6 && layer.gain(0.3) > layer.gain(0.8));
+    layer.scale_parameters(0.5);
+    assert_eq!(layer.parameters()["cutoff"], ParamValue::Float(0.5));
+    assert_eq!(layer.parameters()["upper_cutoff"], ParamValue::Float(0.75));
+    let empty = FftFilter::new(FrequencyBand::HighPass, 0.5, 1.0).compute(&GrayImage::new(0, 3));
+    assert_eq!(empty.dimensions(), (0, 3));
+}
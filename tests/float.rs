@@ -0,0 +1,44 @@
+use image::{GrayImage, Luma};
+
+use klex::{
+    entity::{self, GrayImageF32},
+    layer::{
+        primitive::{FloatRange, FloatScale, Normalize, ToFloat},
+        Layer,
+    },
+    parameter::{ParamMap, ParamValue},
+    registry::LayerRegistry,
+};
+
+#[test]
+fn gray_images_are_converted_to_floats_and_back() {
+    let gray = GrayImage::from_raw(3, 1, vec![0, 51, 255]).unwrap();
+    let normalized = ToFloat::new(FloatScale::Normalized).compute(&gray);
+    assert_eq!(normalized.as_raw(), &[0.0, 0.2, 1.0]);
+    assert_eq!(ToFloat::new(FloatScale::Raw).compute(&gray).as_raw(), &[0.0, 51.0, 255.0]);
+    assert_eq!(Normalize::new(FloatRange::Fixed, 0.0, 1.0).compute(&normalized), gray);
+    assert_eq!(entity::element_name(&normalized), Some("GrayImageF32"));
+
+    let registry = LayerRegistry::with_builtins();
+    let parameters = ParamMap::from([("scale".to_string(), ParamValue::Choice("Raw".to_string()))]);
+    let layer = registry.create("Convert<GrayImage, GrayImageF32>", &parameters).unwrap();
+    assert_eq!(layer.parameters(), parameters);
+    assert!(registry.create("Convert<GrayImageF32, GrayImage>", &ParamMap::new()).is_ok());
+}
+
+#[test]
+fn normalizing_clamps_infinities_and_blackens_nan() {
+    let image = GrayImageF32::from_raw(6, 1, vec![-1.0, 0.5, 3.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY]).unwrap();
+    let fixed = Normalize::new(FloatRange::Fixed, 0.0, 1.0);
+    assert_eq!(fixed.compute(&image).as_raw(), &[0, 128, 255, 0, 255, 0]);
+
+    // Only finite values span the range
+    let min_max = Normalize::new(FloatRange::MinMax, 0.0, 1.0);
+    assert_eq!(min_max.bounds(&image), (-1.0, 3.0));
+    assert_eq!(min_max.compute(&image).as_raw(), &[0, 96, 255, 0, 255, 0]);
+    let undefined = GrayImageF32::from_pixel(2, 1, Luma([f32::NAN]));
+    assert_eq!(min_max.bounds(&undefined), (0.0, 1.0));
+    let flat = GrayImageF32::from_pixel(2, 1, Luma([7.0]));
+    assert_eq!(min_max.compute(&flat).as_raw(), &[0, 0]);
+    assert_eq!(min_max.kind(), "Convert<GrayImageF32, GrayImage>");
+}
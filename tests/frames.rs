@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use image::{codecs::gif::GifEncoder, Delay, Frame, Rgba, RgbaImage};
+
+use klex::{
+    layer::{primitive::InputFrames, Layer, LayerOutput},
+    layer_graph::LayerGraph,
+    parameter::ParamValue,
+};
+
+fn frame(value: u8) -> RgbaImage {
+    RgbaImage::from_pixel(3, 2, Rgba([value, 0, 255 - value, 255]))
+}
+
+fn red(output: &LayerOutput) -> u8 {
+    output.as_ref().unwrap().downcast_ref::<RgbaImage>().unwrap().get_pixel(0, 0)[0]
+}
+
+#[test]
+fn frames_of_animated_gifs_are_chosen_by_their_index() {
+    let directory = std::env::temp_dir().join(format!("klex-frames-gif-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("animation.gif");
+    let mut encoder = GifEncoder::new(std::fs::File::create(&path).unwrap());
+    let delays = [100, 250, 40];
+    for (value, delay) in [0, 128, 255].into_iter().zip(delays) {
+        let delay = Delay::from_saturating_duration(Duration::from_millis(delay));
+        encoder.encode_frame(Frame::from_parts(frame(value), 0, 0, delay)).unwrap();
+    }
+    drop(encoder);
+
+    let mut layers = LayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFrames::new(path.clone(), 1)), vec![]);
+    layers.compute_all().unwrap();
+    assert_eq!(layers.output(input, 0).unwrap().downcast_ref::<RgbaImage>().unwrap().get_pixel(0, 0)[0], 128);
+
+    // Choosing another frame computes the layer again, without decoding the file again
+    layers.set_parameter(input, "frame", ParamValue::Int(2)).unwrap();
+    layers.compute_all().unwrap();
+    assert_eq!(layers.output(input, 0).unwrap().downcast_ref::<RgbaImage>().unwrap().get_pixel(0, 0)[0], 255);
+    let metadata = layers.layer(input).unwrap().metadata();
+    assert_eq!(metadata.get("frame_count"), Some(&ParamValue::Int(3)));
+    assert_eq!(metadata.get("delay"), Some(&ParamValue::Float(0.04)));
+
+    let layer = InputFrames::new(path, 3);
+    assert_eq!(layer.frame_count(), None, "Frames are counted when the layer is computed");
+    let mut output: LayerOutput = None;
+    let error = layer.compute(&[], &mut output).unwrap_err();
+    assert!(error.to_string().contains("there are 3"), "{}", error);
+    assert_eq!(layer.frame_count(), Some(3));
+    let delays: Vec<_> = delays.into_iter().map(Duration::from_millis).collect();
+    assert_eq!(layer.frame_delays(), Some(delays));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn numbered_files_are_read_as_frames() {
+    let directory = std::env::temp_dir().join(format!("klex-frames-sequence-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    for number in 1..=3 {
+        frame(number * 10).save(directory.join(format!("frame_{:04}.png", number))).unwrap();
+    }
+    frame(50).save(directory.join("frame_0005.png")).unwrap(); // After a gap, so not part of the sequence
+
+    let mut layer = InputFrames::new(directory.join("frame_%04d.png"), 0);
+    let mut output: LayerOutput = None;
+    layer.compute(&[], &mut output).unwrap();
+    assert_eq!(red(&output), 10, "The sequence starts at 1 without a frame 0");
+    assert_eq!(layer.frame_count(), Some(3));
+    assert_eq!(layer.frame_delays(), Some(vec![Duration::ZERO; 3]));
+    layer.set_parameter("frame", ParamValue::Int(2)).unwrap();
+    layer.compute(&[], &mut output).unwrap();
+    assert_eq!(red(&output), 30);
+
+    // Single images are a single frame
+    layer.set_parameter("path", ParamValue::Path(directory.join("frame_0005.png"))).unwrap();
+    assert_eq!(layer.frame_count(), None, "Frames of the previous path are forgotten");
+    layer.set_parameter("frame", ParamValue::Int(0)).unwrap();
+    layer.compute(&[], &mut output).unwrap();
+    assert_eq!((red(&output), layer.frame_count()), (50, Some(1)));
+
+    layer.set_parameter("path", ParamValue::Path(directory.join("missing_%d.png"))).unwrap();
+    assert!(layer.compute(&[], &mut output).is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,33 @@
+use klex::{
+    entity::{self, BinaryImage},
+    layer::primitive::Contours,
+};
+
+fn mask(width: u32, rows: &[&str]) -> BinaryImage {
+    let data = rows.iter().flat_map(|row| row.chars().map(|pixel| pixel == '#')).collect();
+    BinaryImage::new(width, rows.len() as u32, data)
+}
+
+#[test]
+fn contours_follow_pixel_edges() {
+    let square = mask(4, &["....", ".##.", ".##.", "...."]);
+    let contours = Contours::compute(&square);
+    assert_eq!(contours.len(), 1);
+    let mut points = contours[0].points.clone();
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(points, vec![(1.0, 1.0), (1.0, 3.0), (3.0, 1.0), (3.0, 3.0)], "Only the corners are kept");
+
+    let ring = mask(3, &["###", "#.#", "###"]);
+    assert_eq!(Contours::compute(&ring).len(), 2, "Holes have their own contour");
+    assert!(Contours::compute(&mask(2, &["..", ".."])).is_empty());
+    assert_eq!(Contours::compute(&mask(2, &["#.", ".#"])).len(), 2, "Diagonal neighbors are separate regions");
+}
+
+#[test]
+fn contours_are_drawn_as_polygons() {
+    let contours = Contours::compute(&mask(1, &["#"]));
+    let geometry = entity::geometry(&contours).unwrap();
+    assert_eq!((geometry.lines.len(), geometry.points.len(), geometry.polygons.len()), (0, 0, 1));
+    assert!(entity::is_geometry("Contours"));
+    assert!(entity::geometry(&mask(1, &["#"])).is_none());
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "ui")]
+
+use std::collections::BTreeMap;
+
+use iced_native::Point;
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    backend::Data,
+    graph_editor::{connection_problem, edges, EditorEdge, EditorNode, EditorState, Hit},
+    registry::LayerRegistry,
+    ui::GraphMirror,
+};
+
+fn added(node: usize, kind: &str, inputs: &[usize]) -> Data {
+    Data::LayerAdded {
+        node: NodeIndex::new(node),
+        kind: kind.to_string(),
+        name: kind.to_string(),
+        inputs: inputs.iter().map(|&input| NodeIndex::new(input)).collect(),
+    }
+}
+
+/// InputFile -> Convert -> Threshold -> Convert back, plus an unconnected Threshold that was moved and the channels of
+/// the input file
+fn pipeline() -> (Vec<EditorNode>, Vec<EditorEdge>) {
+    let registry = LayerRegistry::with_builtins();
+    let catalog: BTreeMap<_, _> = registry
+        .kinds()
+        .map(|kind| (kind.to_string(), registry.info(kind).unwrap().clone()))
+        .collect();
+    let mut graph = GraphMirror::new();
+    graph.apply(&added(0, "InputFile", &[]));
+    graph.apply(&added(1, "Convert<RgbaImage, GrayImage>", &[0]));
+    graph.apply(&added(2, "Threshold", &[1]));
+    graph.apply(&added(3, "Threshold", &[]));
+    graph.apply(&added(4, "Convert<BinaryImage, GrayImage>", &[2]));
+    graph.apply(&added(5, "SplitChannels", &[0]));
+    graph.apply(&Data::LayerMoved {
+        node: NodeIndex::new(3),
+        position: (500.0, 300.0),
+    });
+    (EditorNode::from_graph(&graph, &catalog), edges(&graph))
+}
+
+#[test]
+fn unplaced_layers_are_laid_out_by_depth() {
+    let (nodes, edges) = pipeline();
+    let positions: Vec<_> = nodes.iter().map(|node| (node.position.x, node.position.y)).collect();
+    assert_eq!(positions[3], (500.0, 300.0));
+    assert!(positions[0].0 < positions[1].0 && positions[1].0 < positions[2].0, "{:?}", positions);
+    assert!(nodes[0].input_types.is_empty(), "Inputs have no input ports");
+    assert_eq!(nodes[2].input_types, vec![Some("GrayImage")]);
+    assert_eq!(edges.len(), 4);
+    let channels: Vec<_> = nodes[5].outputs.iter().map(|output| output.name).collect();
+    assert_eq!(channels, vec!["red", "green", "blue", "alpha"]);
+    assert_eq!(nodes[3].outputs.len(), 1);
+}
+
+#[test]
+fn connections_are_checked_before_they_are_made() {
+    let (nodes, edges) = pipeline();
+    let node = NodeIndex::new;
+    assert_eq!(connection_problem(&nodes, &edges, (node(1), 0), node(3), 0), None);
+    assert!(connection_problem(&nodes, &edges, (node(0), 0), node(3), 0).unwrap().contains("RgbaImage"));
+    assert!(connection_problem(&nodes, &edges, (node(3), 0), node(3), 0).is_some());
+    assert_eq!(connection_problem(&nodes, &edges, (node(5), 2), node(3), 0), None, "A channel is a gray image");
+    assert_eq!(
+        connection_problem(&nodes, &edges, (node(4), 0), node(2), 0),
+        Some("This would create a cycle".to_string())
+    );
+}
+
+#[test]
+fn ports_can_be_hit_at_any_zoom() {
+    let (nodes, edges) = pipeline();
+    let threshold = &nodes[3];
+    let input = threshold.input_port(threshold.position, 0);
+    let output = threshold.output_port(threshold.position, 0);
+    let blue = nodes[5].output_port(nodes[5].position, 2);
+
+    let mut state = EditorState::new();
+    for lines in [0.0, -8.0, 8.0] {
+        state = state.zoomed(lines, Point::new(100.0, 100.0));
+        let near = |point: Point| Point::new(state.to_screen(point).x + 3.0, state.to_screen(point).y);
+        assert_eq!(state.hit(&nodes, &edges, near(input)), Some(Hit::Input(NodeIndex::new(3), 0)));
+        assert_eq!(state.hit(&nodes, &edges, near(output)), Some(Hit::Output(NodeIndex::new(3), 0)));
+        assert_eq!(state.hit(&nodes, &edges, near(blue)), Some(Hit::Output(NodeIndex::new(5), 2)));
+    }
+
+    let body = Point::new(threshold.position.x + 80.0, threshold.position.y + 30.0);
+    let state = EditorState::new();
+    assert_eq!(state.hit(&nodes, &edges, state.to_screen(body)), Some(Hit::Node(NodeIndex::new(3))));
+    assert_eq!(state.hit(&nodes, &edges, Point::new(-100.0, -100.0)), None);
+}
+
+#[test]
+fn zooming_keeps_the_point_under_the_cursor() {
+    let cursor = Point::new(120.0, 80.0);
+    let state = EditorState::new();
+    let anchor = state.to_graph(cursor);
+    let zoomed = state.zoomed(3.0, cursor);
+    assert!(zoomed.zoom() > state.zoom());
+    let moved = zoomed.to_screen(anchor);
+    assert!((moved.x - cursor.x).abs() < 1e-3 && (moved.y - cursor.y).abs() < 1e-3);
+    assert_eq!(state.zoomed(-100.0, cursor).zoom(), 0.25, "Zoom is limited");
+}
@@ -0,0 +1,83 @@
+use image::{GrayImage, Luma};
+
+use klex::{
+    entity::{self, Gray16Image, PixelValue},
+    layer::{
+        primitive::{InputFile, Threshold, ThresholdMode, Window, WindowMode},
+        Layer, LayerOutput,
+    },
+    parameter::{ParamMap, ParamValue},
+    registry::LayerRegistry,
+};
+
+/// Values that differ in their lower 8 bits only, and would be lost if the image was loaded with 8 bits
+fn scan() -> Gray16Image {
+    Gray16Image::from_raw(4, 1, vec![1000, 1100, 1200, 60000]).unwrap()
+}
+
+#[test]
+fn sixteen_bit_images_are_loaded_and_exported_without_losing_bits() {
+    let directory = std::env::temp_dir().join(format!("klex-gray16-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("scan.png");
+    entity::export(&scan(), &path, 90).unwrap();
+
+    let registry = LayerRegistry::with_builtins();
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    let input = registry.create("InputFile<Gray16Image>", &parameters).unwrap();
+    assert_eq!(input.output_type(), Some("Gray16Image"));
+    let mut output: LayerOutput = None;
+    input.compute(&[], &mut output).unwrap();
+    assert_eq!(output.unwrap().downcast_ref::<Gray16Image>(), Some(&scan()));
+    assert_eq!(InputFile::<Gray16Image>::new(Default::default()).kind(), "InputFile<Gray16Image>");
+
+    // Formats without 16 bit gray images get the upper 8 bits
+    let path = directory.join("scan.bmp");
+    entity::export(&scan(), &path, 90).unwrap();
+    let bmp = image::open(&path).unwrap().into_luma8();
+    assert_eq!(bmp.as_raw(), &[4, 4, 5, 233]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn sixteen_bit_images_are_thresholded_at_full_precision() {
+    let threshold = Threshold::<Gray16Image>::with_threshold(1100, ThresholdMode::GreaterEqual);
+    assert_eq!(threshold.kind(), "Threshold<Gray16Image>");
+    assert_eq!(threshold.compute(&scan()).data(), &[false, true, true, true]);
+
+    let registry = LayerRegistry::with_builtins();
+    let parameters = ParamMap::from([("threshold".to_string(), ParamValue::Int(70000))]);
+    assert!(registry.create("Threshold<Gray16Image>", &parameters).is_err(), "Thresholds are limited to 16 bits");
+    assert_eq!(Threshold::new(100, ThresholdMode::Greater).kind(), "Threshold", "8 bit images keep their kind");
+}
+
+#[test]
+fn windows_spread_values_over_8_bits() {
+    let image = scan();
+    let gray = |mode, percentile| Window::new(mode, percentile).compute(&image).into_raw();
+    assert_eq!(gray(WindowMode::Full, 0.0), entity::to_gray8(&image).into_raw());
+    assert_eq!(gray(WindowMode::Full, 0.0), [4, 4, 5, 233]);
+    assert_eq!(gray(WindowMode::MinMax, 0.0), [0, 0, 1, 255]);
+
+    // Leaving out the brightest pixel spreads the others over the range
+    assert_eq!(Window::new(WindowMode::Percentile, 25.0).bounds(&image), (1100, 1200));
+    assert_eq!(gray(WindowMode::Percentile, 25.0), [0, 0, 255, 255]);
+
+    let flat = Gray16Image::from_pixel(2, 2, Luma([500]));
+    assert_eq!(Window::new(WindowMode::MinMax, 0.0).compute(&flat), GrayImage::new(2, 2), "Flat images stay black");
+    assert_eq!(Window::new(WindowMode::MinMax, 0.0).kind(), "Convert<Gray16Image, GrayImage>");
+}
+
+#[test]
+fn sixteen_bit_images_are_inspected_like_gray_images() {
+    let image = scan();
+    assert_eq!(entity::element_name(&image), Some("Gray16Image"));
+    assert_eq!(entity::pixel(&image, 1, 0), Some(PixelValue::Gray16(1100)));
+    assert_eq!(PixelValue::Gray16(1100).to_rgba(), [4, 4, 4, 255]);
+    let histogram = entity::histogram(&image).unwrap();
+    assert_eq!(histogram.channels.len(), 1);
+    assert_eq!((histogram.channels[0][4], histogram.channels[0][5], histogram.channels[0][233]), (2, 1, 1));
+    assert_eq!(entity::size_bytes(&image), Some(8));
+    let preview = entity::resize(&image, 0.5).unwrap();
+    assert_eq!(entity::dimensions(preview.as_ref()), Some((2, 1)));
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "ui")]
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use klex::{
+    entity::{self, BinaryImage},
+    histogram::{bar_fraction, bin_at},
+};
+
+#[test]
+fn histograms_count_values_per_channel() {
+    let mut image = GrayImage::new(4, 2);
+    image.put_pixel(1, 1, Luma([200]));
+    let histogram = entity::histogram(&image).unwrap();
+    assert_eq!(histogram.channels.len(), 1);
+    assert_eq!((histogram.channels[0][0], histogram.channels[0][200]), (7, 1));
+    assert_eq!(histogram.max(), 7);
+
+    let image = RgbaImage::from_pixel(2, 2, Rgba([1, 2, 3, 255]));
+    let histogram = entity::histogram(&image).unwrap();
+    let counts: Vec<_> = histogram.channels.iter().map(|channel| (channel[1], channel[2], channel[3])).collect();
+    assert_eq!(counts, vec![(4, 0, 0), (0, 4, 0), (0, 0, 4)], "Alpha is left out");
+
+    assert!(entity::histogram(&BinaryImage::new(1, 1, vec![true])).is_none());
+}
+
+#[test]
+fn bars_can_be_scaled_logarithmically() {
+    assert_eq!(bar_fraction(50, 100, false), 0.5);
+    assert_eq!(bar_fraction(100, 100, true), 1.0);
+    assert!(bar_fraction(1, 10_000, true) > 0.05, "Rare values stay visible");
+    assert_eq!(bar_fraction(0, 0, true), 0.0);
+
+    assert_eq!(bin_at(0.0, 512.0), 0);
+    assert_eq!(bin_at(3.0, 512.0), 1);
+    assert_eq!(bin_at(-10.0, 512.0), 0);
+    assert_eq!(bin_at(600.0, 512.0), 255);
+}
@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    entity::{BinaryImage, Stroke},
+    error::{KlexError, Result},
+    layer::{primitive::PaintedMask, Layer, LayerOutput},
+    layer_graph::InteractiveLayerGraph,
+    parameter::{ParamMap, ParamValue, Parameter},
+};
+
+/// Layer that only carries a parameter, so that edits can be checked without computing anything
+struct Gain {
+    factor: f64,
+}
+
+impl Layer for Gain {
+    fn kind(&self) -> String {
+        "Gain".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(self.factor));
+        Ok(())
+    }
+
+    fn parameters(&self) -> ParamMap {
+        ParamMap::from([("factor".to_string(), self.factor.to_value())])
+    }
+
+    fn set_parameter(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        match name {
+            "factor" => self.factor = Parameter::from_value(&value)?,
+            _ => return Err(KlexError::UnknownParameter(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        Ok(Box::new(Gain { factor: self.factor }))
+    }
+}
+
+/// Small xorshift generator, so that the edit sequences are random but reproducible
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+
+    fn layer(&mut self, layers: &InteractiveLayerGraph) -> NodeIndex {
+        let indices: Vec<NodeIndex> = layers.graph().node_indices().collect();
+        indices[self.below(indices.len())]
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    layers: BTreeMap<usize, (String, String, ParamMap)>,
+    edges: BTreeSet<(usize, usize, usize)>,
+    selected_layer: Option<NodeIndex>,
+}
+
+fn snapshot(layers: &InteractiveLayerGraph) -> Snapshot {
+    let graph = layers.graph();
+    Snapshot {
+        layers: graph
+            .node_indices()
+            .map(|layer| {
+                let kind = graph.layer(layer).unwrap().kind();
+                let name = graph.name(layer).unwrap().to_string();
+                (layer.index(), (kind, name, graph.parameters(layer).unwrap()))
+            })
+            .collect(),
+        edges: graph
+            .edges()
+            .map(|(from, _, to, port)| (from.index(), to.index(), port))
+            .collect(),
+        selected_layer: layers.selected(),
+    }
+}
+
+/// Applies a random edit and returns whether it succeeded. Edits that are invalid for the current graph, like
+/// connections that would create cycles, fail and must leave no trace in the history.
+fn random_edit(layers: &mut InteractiveLayerGraph, random: &mut Random) -> bool {
+    if layers.graph().node_count() < 2 {
+        layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![]);
+        return true;
+    }
+
+    let result = match random.below(8) {
+        0 => {
+            let parent = random.layer(layers);
+            layers.add_layer(Box::new(Gain { factor: 2.0 }), vec![parent]);
+            Ok(())
+        }
+        1 => {
+            let (parent, child) = (random.layer(layers), random.layer(layers));
+            layers.add_layer_with_children(Box::new(Gain { factor: 3.0 }), vec![parent], vec![child]);
+            Ok(())
+        }
+        2 => layers.remove_layer(random.layer(layers)),
+        3 => {
+            let (from, to) = (random.layer(layers), random.layer(layers));
+            layers.connect(from, to, random.below(3))
+        }
+        4 => layers.disconnect(random.layer(layers), random.below(3)).map(|_| ()),
+        5 => {
+            let factor = random.below(100) as f64;
+            layers.set_parameter(random.layer(layers), "factor", ParamValue::Float(factor))
+        }
+        6 => {
+            let name = format!("layer {}", random.below(100));
+            layers.rename(random.layer(layers), name)
+        }
+        _ => layers.duplicate_layer(random.layer(layers)).map(|_| ()),
+    };
+
+    // Selecting a layer isn't an edit, but undoing the next edit has to return to this selection
+    if result.is_ok() && random.below(4) == 0 {
+        let layer = random.layer(layers);
+        layers.select(layer).unwrap();
+    }
+    result.is_ok()
+}
+
+#[test]
+fn undoing_random_edits_restores_the_graph() {
+    for seed in 1..=20 {
+        let mut random = Random(seed);
+        let mut layers = InteractiveLayerGraph::new();
+        let source = layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![]);
+        layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![source]);
+        let initial = snapshot(&layers);
+
+        let mut edits = 0;
+        while edits < 50 {
+            if random_edit(&mut layers, &mut random) {
+                edits += 1;
+            }
+        }
+        let edited = snapshot(&layers);
+
+        for _ in 0..edits {
+            layers.undo().unwrap();
+        }
+        assert_eq!(snapshot(&layers), initial, "seed {}", seed);
+
+        while layers.can_redo() {
+            layers.redo().unwrap();
+        }
+        let mut redone = snapshot(&layers);
+        redone.selected_layer = edited.selected_layer; // The selection made after the last edit isn't redone
+        assert_eq!(redone, edited, "seed {}", seed);
+    }
+}
+
+#[test]
+fn undone_layers_are_recomputed() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![]);
+    layers.compute_all().unwrap();
+
+    layers.set_parameter(source, "factor", ParamValue::Float(5.0)).unwrap();
+    layers.compute_all().unwrap();
+    layers.undo().unwrap();
+    assert!(layers.graph().is_dirty(source));
+
+    layers.compute_all().unwrap();
+    assert_eq!(layers.graph().output(source, 0).unwrap().downcast_ref::<f64>(), Some(&1.0));
+}
+
+#[test]
+fn history_depth_limits_undo() {
+    let mut layers = InteractiveLayerGraph::new();
+    layers.set_history_depth(2);
+    let source = layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![]);
+    layers.rename(source, "first".to_string()).unwrap();
+    layers.rename(source, "second".to_string()).unwrap();
+
+    layers.undo().unwrap();
+    layers.undo().unwrap();
+    assert!(!layers.can_undo());
+    assert!(layers.undo().is_err());
+    assert_eq!(layers.graph().name(source), Some("Gain"));
+
+    // A new edit discards what could have been redone
+    layers.rename(source, "third".to_string()).unwrap();
+    assert!(!layers.can_redo());
+}
+
+#[test]
+fn strokes_are_undone_as_a_whole() {
+    let mut layers = InteractiveLayerGraph::new();
+    let mask = layers.add_layer(Box::new(PaintedMask::new(20, 10)), vec![]);
+    let painted = |layers: &mut InteractiveLayerGraph| {
+        layers.compute_all().unwrap();
+        let output = layers.graph().output(mask, 0).unwrap().downcast_ref::<BinaryImage>().unwrap();
+        output.data().iter().filter(|&&pixel| pixel).count()
+    };
+    let stroke = |points: Vec<(f32, f32)>, erase| Stroke { points, radius: 2.0, erase };
+
+    // A stroke sent in parts, continuing where the previous part ended
+    layers.paint(mask, &stroke(vec![(2.0, 5.0)], false)).unwrap();
+    layers.paint(mask, &stroke(vec![(2.0, 5.0), (10.0, 5.0)], false)).unwrap();
+    layers.paint(mask, &stroke(vec![(10.0, 5.0), (18.0, 5.0)], false)).unwrap();
+    layers.end_stroke();
+    let full = painted(&mut layers);
+    assert!(full > 16 * 4, "{} pixels painted", full);
+
+    layers.paint(mask, &stroke(vec![(10.0, 5.0)], true)).unwrap();
+    assert!(painted(&mut layers) < full);
+    layers.undo().unwrap(); // Ends the stroke in progress first
+    assert_eq!(painted(&mut layers), full);
+    layers.undo().unwrap();
+    assert_eq!(painted(&mut layers), 0);
+    layers.redo().unwrap();
+    assert_eq!(painted(&mut layers), full);
+
+    let other = layers.add_layer(Box::new(Gain { factor: 1.0 }), vec![]);
+    assert!(layers.paint(other, &stroke(vec![(0.0, 0.0)], false)).is_err());
+}
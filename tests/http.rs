@@ -0,0 +1,156 @@
+#![cfg(feature = "http")]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use image::{GrayImage, Rgba, RgbaImage};
+
+use klex::{
+    error::KlexError,
+    layer::{
+        primitive::{Convert, InputUrl},
+        Layer, LayerOutput,
+    },
+    layer_graph::InteractiveLayerGraph,
+    parameter::ParamValue,
+    recipe::{self, Bindings, Recipe},
+    registry::LayerRegistry,
+    watch::PollingWatcher,
+};
+
+/// Serves `/image.png` with an ETag of its version, which changes with the image, as well as `/missing` and `/text`
+fn serve(version: Arc<Mutex<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let lines: Vec<String> = BufReader::new(&stream)
+                .lines()
+                .map(Result::unwrap)
+                .take_while(|line| !line.is_empty())
+                .collect();
+            let (method, path) = lines[0].split_once(' ').unwrap();
+            let path = path.split(' ').next().unwrap();
+            let version = *version.lock().unwrap();
+            let etag = format!("\"{}\"", version);
+            let (status, body) = match path {
+                "/image.png" if lines.contains(&format!("If-None-Match: {}", etag)) => ("304 Not Modified", vec![]),
+                "/image.png" => {
+                    let mut png = Vec::new();
+                    let image = RgbaImage::from_pixel(2, 2, Rgba([version, 0, 0, 255]));
+                    image::DynamicImage::ImageRgba8(image)
+                        .write_to(&mut png, image::ImageOutputFormat::Png)
+                        .unwrap();
+                    ("200 OK", png)
+                }
+                "/text" => ("200 OK", b"Not an image".to_vec()),
+                _ => ("404 Not Found", vec![]),
+            };
+            let header = format!(
+                "HTTP/1.1 {}\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                etag,
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            if method == "GET" {
+                stream.write_all(&body).unwrap();
+            }
+        }
+    });
+    format!("http://{}", address)
+}
+
+fn red(output: &LayerOutput) -> u8 {
+    output
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<RgbaImage>()
+        .unwrap()
+        .get_pixel(0, 0)[0]
+}
+
+#[test]
+fn remote_images_are_downloaded_again_once_they_changed() {
+    let version = Arc::new(Mutex::new(1));
+    let server = serve(version.clone());
+    let mut layer = InputUrl::new(format!("{}/image.png", server));
+    layer.set_parameter("interval", ParamValue::Float(0.0)).unwrap();
+    let mut output: LayerOutput = None;
+    layer.compute(&[], &mut output).unwrap();
+    assert_eq!(red(&output), 1);
+    assert!(
+        !layer.has_changed().unwrap(),
+        "The server answers that the image wasn't modified"
+    );
+
+    *version.lock().unwrap() = 2;
+    assert!(layer.has_changed().unwrap());
+    layer.compute(&[], &mut output).unwrap();
+    assert_eq!(red(&output), 2);
+    assert!(!layer.has_changed().unwrap());
+
+    *version.lock().unwrap() = 3;
+    layer.set_parameter("interval", ParamValue::Float(3600.0)).unwrap();
+    assert!(!layer.has_changed().unwrap(), "The server was asked too recently");
+    assert_eq!(layer.cache_key(), None);
+}
+
+#[test]
+fn failed_downloads_name_the_url() {
+    let server = serve(Arc::new(Mutex::new(1)));
+    let failure = |path: &str, max_bytes: i64| {
+        let mut layer = InputUrl::new(format!("{}{}", server, path));
+        layer.set_parameter("max_bytes", ParamValue::Int(max_bytes)).unwrap();
+        let error = layer.compute(&[], &mut None).unwrap_err();
+        assert!(matches!(error.root(), KlexError::Download { url, .. } if url.ends_with(path)));
+        format!("{:#}", error)
+    };
+    assert!(failure("/missing", 1000).contains("404"));
+    assert!(failure("/text", 1000).contains("Failed to decode the image"));
+    assert!(failure("/image.png", 10).contains("more than the maximum of 10"));
+
+    let mut layer = InputUrl::new("http://127.0.0.1:1/image.png".to_string());
+    layer.set_parameter("timeout", ParamValue::Float(1.0)).unwrap();
+    let error = layer.compute(&[], &mut None).unwrap_err();
+    assert!(error.to_string().contains("http://127.0.0.1:1/image.png"), "{}", error);
+}
+
+#[test]
+fn watching_recomputes_changed_remote_images() {
+    let version = Arc::new(Mutex::new(1));
+    let server = serve(version.clone());
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputUrl::new(format!("{}/image.png", server))), vec![]);
+    layers.set_parameter(input, "interval", ParamValue::Float(0.0)).unwrap();
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    layers.rename(gray, "gray".to_string()).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+
+    let mut watcher = PollingWatcher::new(Duration::from_millis(10), Duration::from_millis(10));
+    let mut values = Vec::new();
+    recipe::watch_with(
+        &recipe,
+        &Bindings::new(),
+        &LayerRegistry::with_builtins(),
+        &mut watcher,
+        |outputs| {
+            values.push(outputs.unwrap()["gray"].downcast_ref::<GrayImage>().unwrap().as_raw()[0]);
+            *version.lock().unwrap() += 100;
+            if values.len() < 2 {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        },
+    )
+    .unwrap();
+    assert!(values[0] < values[1], "{:?}", values);
+}
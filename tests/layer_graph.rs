@@ -0,0 +1,616 @@
+use std::{
+    any::Any,
+    cmp,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{GrayImage, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    entity::BinaryImage,
+    error::{Context, KlexError, Result},
+    layer::{
+        self,
+        primitive::{Convert, Crop, InputFile, Invert, SplitChannels, Threshold},
+        Layer, LayerOutput,
+    },
+    layer_graph::{ColorTag, GraphSnapshot, InteractiveLayerGraph, LayerGraph, LayerId, NodeLayout},
+    parameter::ParamValue,
+};
+
+/// Sleeps for a while and then produces a unit output. Fails if a connected input hasn't been computed yet.
+struct Sleep(Duration);
+
+impl Layer for Sleep {
+    fn kind(&self) -> String {
+        "Sleep".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        for input in input {
+            input.as_ref().context("Input was not computed before its dependent layer")?;
+        }
+        thread::sleep(self.0);
+        *output = Some(Box::new(()));
+        Ok(())
+    }
+}
+
+/// Waits until as many layers as share its counter are being computed, which only happens when they are computed at
+/// the same time. Fails if the others don't arrive within a generous timeout instead of waiting forever.
+struct Meet {
+    arrived: Arc<AtomicUsize>,
+    count: usize,
+}
+
+impl Layer for Meet {
+    fn kind(&self) -> String {
+        "Meet".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        self.arrived.fetch_add(1, Ordering::SeqCst);
+        let timeout = Instant::now() + Duration::from_secs(10);
+        while self.arrived.load(Ordering::SeqCst) < self.count {
+            if Instant::now() >= timeout {
+                return Err("The other branches weren't computed at the same time".into());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        *output = Some(Box::new(()));
+        Ok(())
+    }
+}
+
+/// Produces an output of a fixed size and counts how often it was computed
+struct Blob {
+    size: usize,
+    computations: Arc<AtomicUsize>,
+}
+
+impl Layer for Blob {
+    fn kind(&self) -> String {
+        "Blob".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        for input in input {
+            input.as_ref().context("Evicted input was not restored")?;
+        }
+        self.computations.fetch_add(1, Ordering::SeqCst);
+        *output = Some(Box::new(vec![0_u8; self.size]));
+        Ok(())
+    }
+
+    fn output_size_bytes(&self, output: &(dyn Any + Send + Sync)) -> Option<usize> {
+        output.downcast_ref::<Vec<u8>>().map(Vec::len)
+    }
+}
+
+/// A chain of four blob layers of 100 bytes each
+fn blob_chain() -> (InteractiveLayerGraph, Arc<AtomicUsize>) {
+    let computations = Arc::new(AtomicUsize::new(0));
+    let mut layers = InteractiveLayerGraph::new();
+    for layer in 0..4 {
+        let blob = Blob {
+            size: 100,
+            computations: computations.clone(),
+        };
+        let parents = if layer == 0 { vec![] } else { vec![(layer - 1).into()] };
+        layers.add_layer(Box::new(blob), parents);
+    }
+    (layers, computations)
+}
+
+#[test]
+fn independent_branches_are_computed_in_parallel() {
+    let mut layers = InteractiveLayerGraph::new();
+    layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![]);
+    let arrived = Arc::new(AtomicUsize::new(0));
+    for _ in 0..2 {
+        let meet = Meet {
+            arrived: arrived.clone(),
+            count: 2,
+        };
+        layers.add_layer(Box::new(meet), vec![0.into()]);
+    }
+
+    layers.compute_all().unwrap();
+    assert!(layers.graph().node_indices().all(|layer| layers.graph().output(layer, 0).is_some()));
+}
+
+#[test]
+fn dependent_layers_wait_for_their_inputs() {
+    let mut layers = InteractiveLayerGraph::new();
+    layers.add_layer(Box::new(Sleep(Duration::from_millis(50))), vec![]);
+    layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![0.into()]);
+    layers.add_layer(Box::new(Sleep(Duration::from_millis(50))), vec![0.into()]);
+    layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![1.into(), 2.into()]);
+
+    layers.compute_all().unwrap();
+    assert!(layers.graph().node_indices().all(|layer| layers.graph().output(layer, 0).is_some()));
+}
+
+#[test]
+fn clean_layers_are_not_recomputed() {
+    let (mut layers, computations) = blob_chain();
+    layers.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 4);
+
+    layers.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 4);
+
+    layers.mark_dirty(2.into());
+    layers.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 6);
+}
+
+#[test]
+fn memory_budget_evicts_outputs() {
+    let (mut layers, _) = blob_chain();
+    layers.compute_all().unwrap();
+    assert_eq!(layers.graph().memory_usage(), 400);
+
+    layers.set_memory_budget(Some(250));
+    assert_eq!(layers.graph().memory_usage(), 200);
+    assert!(layers.graph().output(0.into(), 0).is_some(), "Source layers must not be evicted");
+    assert!(layers.graph().output(1.into(), 0).is_some(), "Layers close to the selected layer are evicted last");
+    assert!(layers.graph().output(3.into(), 0).is_none());
+
+    // Evicted inputs are recomputed on demand
+    layers.compute_layer(3.into()).unwrap();
+    assert!(layers.graph().output(3.into(), 0).is_some());
+    assert!(layers.graph().memory_usage() <= 250);
+
+    layers.mark_dirty(1.into());
+    layers.compute_all().unwrap();
+    assert!(layers.graph().memory_usage() <= 250);
+}
+
+#[test]
+fn duplicated_layers_are_independent() {
+    let mut layers = InteractiveLayerGraph::new();
+    let original = layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![]);
+    let copy = layers.duplicate_layer(original).unwrap();
+    assert_ne!(original, copy);
+    assert_eq!(layers.graph().parameters(copy).unwrap(), layers.graph().parameters(original).unwrap());
+    assert_eq!(layers.graph().edges().count(), 0);
+
+    layers.set_parameter(copy, "threshold", ParamValue::Int(42)).unwrap();
+    assert_eq!(layers.graph().parameters(original).unwrap()["threshold"], ParamValue::Int(100));
+    assert_eq!(layers.graph().parameters(copy).unwrap()["threshold"], ParamValue::Int(42));
+
+    let convert = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![original]);
+    assert!(layers.duplicate_layer(convert).is_ok());
+}
+
+#[test]
+fn layers_without_clone_support_cannot_be_duplicated() {
+    let mut layers = InteractiveLayerGraph::new();
+    let layer = layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![]);
+    assert!(layers.duplicate_layer(layer).is_err());
+    assert_eq!(layers.graph().node_count(), 1);
+}
+
+#[test]
+fn layers_with_the_wrong_number_of_inputs_are_not_computed() {
+    let mut layers = InteractiveLayerGraph::new();
+    let unconnected = layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![]);
+    let error = layers.compute_layer(unconnected).unwrap_err();
+    assert!(matches!(error.root(), KlexError::MissingInput { node, port: 0 } if *node == unconnected));
+
+    let first = layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![]);
+    let second = layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![]);
+    let crowded = layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![first, second]);
+    let error = layers.compute_layer(crowded).unwrap_err();
+    assert!(matches!(error.root(), KlexError::ExtraInputs { found: 2, expected: 1, .. }));
+
+    let miswired = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![first]);
+    let error = layers.compute_layer(miswired).unwrap_err();
+    assert!(matches!(error.root(), KlexError::ExtraInputs { found: 1, expected: 0, .. }));
+
+    let (threshold, mut output) = (Threshold::new(100, cmp::Ordering::Greater), None);
+    assert!(Layer::compute(&threshold, &[], &mut output).is_err(), "Fails instead of panicking");
+}
+
+#[test]
+fn failing_layers_say_who_they_are_and_what_they_got() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Sleep(Duration::ZERO)), vec![]);
+    layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![source]);
+    let error = format!("{:#}", layers.compute_all().unwrap_err());
+    assert!(error.starts_with("Failed to compute layer 1 \"Threshold\" (Threshold)"), "{}", error);
+    assert!(error.contains("Expected input of type GrayImage, got an unknown type"), "{}", error);
+
+    let binary: LayerOutput = Some(Box::new(BinaryImage::new(1, 1, vec![true])));
+    let error = layer::single_input::<GrayImage>(&[&binary]).unwrap_err();
+    assert!(matches!(error.root(), KlexError::TypeMismatch { expected: "GrayImage", found: "BinaryImage" }));
+}
+
+fn gradient() -> GrayImage {
+    GrayImage::from_fn(16, 2, |x, y| image::Luma([(x * 16 + y) as u8]))
+}
+
+/// Produces `gradient()`
+struct Gradient;
+
+impl Layer for Gradient {
+    fn kind(&self) -> String {
+        "Gradient".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(gradient()));
+        Ok(())
+    }
+}
+
+#[test]
+fn inverting_in_place_matches_inverting_a_copy() {
+    let mut graph = LayerGraph::new();
+    let source = graph.add_layer(Box::new(Gradient), vec![]);
+    let crop = graph.add_layer(Box::new(Crop::<GrayImage>::new(0, 0, 0, 0)), vec![source]);
+    let first = graph.add_layer(Box::new(Invert::<GrayImage>::new()), vec![crop]);
+    let second = graph.add_layer(Box::new(Invert::<GrayImage>::new()), vec![first]);
+    graph.compute_all().unwrap();
+
+    let gradient = gradient();
+    let inverted = Invert::<GrayImage>::new().compute(&gradient);
+    assert_eq!(graph.output(second, 0).unwrap().downcast_ref::<GrayImage>(), Some(&gradient));
+    assert!(graph.output(crop, 0).is_none() && graph.output(first, 0).is_none(), "Outputs were handed over");
+    assert!(!graph.is_dirty(crop));
+
+    // Handed over outputs are restored like evicted ones
+    graph.compute_layer(first).unwrap();
+    assert_eq!(graph.output(first, 0).unwrap().downcast_ref::<GrayImage>(), Some(&inverted));
+    assert_eq!(graph.output(crop, 0).unwrap().downcast_ref::<GrayImage>(), Some(&gradient));
+}
+
+#[test]
+fn outputs_read_by_several_layers_are_never_modified() {
+    let mut graph = LayerGraph::new();
+    let source = graph.add_layer(Box::new(Gradient), vec![]);
+    let crop = graph.add_layer(Box::new(Crop::<GrayImage>::new(0, 0, 0, 0)), vec![source]);
+    let inverts: Vec<_> = (0..2).map(|_| graph.add_layer(Box::new(Invert::<GrayImage>::new()), vec![crop])).collect();
+    let focused = graph.add_layer(Box::new(Invert::<GrayImage>::new()), vec![inverts[0]]);
+    graph.set_focus(Some(inverts[0]));
+    graph.compute_all().unwrap();
+
+    let gradient = gradient();
+    let inverted = Invert::<GrayImage>::new().compute(&gradient);
+    assert_eq!(graph.output(crop, 0).unwrap().downcast_ref::<GrayImage>(), Some(&gradient));
+    for invert in inverts {
+        assert_eq!(graph.output(invert, 0).unwrap().downcast_ref::<GrayImage>(), Some(&inverted));
+    }
+    assert_eq!(graph.output(focused, 0).unwrap().downcast_ref::<GrayImage>(), Some(&gradient), "The focus is kept");
+}
+
+/// Averages the pixels of a gray image over a square, repeating the pixels at the edges
+struct BoxBlur(u32);
+
+impl Layer for BoxBlur {
+    fn kind(&self) -> String {
+        "BoxBlur".to_string()
+    }
+
+    fn input_types(&self) -> Vec<&'static str> {
+        vec!["GrayImage"]
+    }
+
+    fn halo(&self) -> Option<usize> {
+        Some(self.0 as usize)
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        let image = layer::single_input::<GrayImage>(input)?;
+        let radius = self.0 as i64;
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        *output = Some(Box::new(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let mut sum = 0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let x = (x as i64 + dx).clamp(0, width - 1) as u32;
+                    let y = (y as i64 + dy).clamp(0, height - 1) as u32;
+                    sum += u32::from(image.get_pixel(x, y).0[0]);
+                }
+            }
+            image::Luma([(sum / (2 * self.0 + 1).pow(2)) as u8])
+        })));
+        Ok(())
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        Ok(Box::new(BoxBlur(self.0)))
+    }
+}
+
+/// Produces an image without much structure
+struct Pattern;
+
+impl Layer for Pattern {
+    fn kind(&self) -> String {
+        "Pattern".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        let pattern = GrayImage::from_fn(23, 17, |x, y| image::Luma([((x * 37 + y * 91) ^ (x * y)) as u8]));
+        *output = Some(Box::new(pattern));
+        Ok(())
+    }
+
+    fn clone_boxed(&self) -> Result<Box<dyn Layer>> {
+        Ok(Box::new(Pattern))
+    }
+}
+
+#[test]
+fn tiled_layers_match_layers_computed_as_a_whole() {
+    let mut graph = LayerGraph::new();
+    let source = graph.add_layer(Box::new(Pattern), vec![]);
+    let crop = graph.add_layer(Box::new(Crop::<GrayImage>::new(0, 0, 0, 0)), vec![source]);
+    let blur = graph.add_layer(Box::new(BoxBlur(2)), vec![crop]);
+    let invert = graph.add_layer(Box::new(Invert::<GrayImage>::new()), vec![blur]);
+    let last = graph.add_layer(Box::new(BoxBlur(3)), vec![invert]);
+    let threshold = graph.add_layer(Box::new(Threshold::new(120, cmp::Ordering::Greater)), vec![last]);
+    let mut reference = graph.try_clone().unwrap();
+    reference.compute_all().unwrap();
+    let output =
+        |graph: &LayerGraph, layer| graph.output(layer, 0).unwrap().downcast_ref::<GrayImage>().unwrap().clone();
+
+    for tile_size in [1, 2, 5, 7, 16, 100] {
+        let mut tiled = graph.try_clone().unwrap();
+        tiled.compute_layer(source).unwrap();
+        tiled.compute_layer(crop).unwrap();
+        tiled.compute_layer_tiled(last, tile_size).unwrap();
+        assert_eq!(output(&tiled, last), output(&reference, last), "tiles of {} pixels", tile_size);
+        assert!(tiled.output(blur, 0).is_none() && tiled.output(invert, 0).is_none(), "Only the last output is kept");
+        assert!(tiled.output(crop, 0).is_some());
+        assert!(tiled.is_dirty(threshold));
+
+        tiled.compute_layer_tiled(threshold, tile_size).unwrap();
+        let binary = |graph: &LayerGraph| graph.output(threshold, 0).unwrap().downcast_ref::<BinaryImage>().cloned();
+        assert_eq!(binary(&tiled), binary(&reference));
+    }
+}
+
+#[test]
+fn unchanged_outputs_stop_recomputation_after_them() {
+    let computations = Arc::new(AtomicUsize::new(0));
+    let mut graph = LayerGraph::new();
+    graph.set_skip_unchanged(true);
+    let source = graph.add_layer(Box::new(Gradient), vec![]);
+    let threshold = graph.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![source]);
+    let blob = Blob {
+        size: 1,
+        computations: computations.clone(),
+    };
+    let blob = graph.add_layer(Box::new(blob), vec![threshold]);
+    graph.compute_all().unwrap();
+    let (generation, hash) = (graph.output_generation(threshold), graph.output_hash(threshold));
+    assert!(hash.is_some());
+
+    // The gradient has no values from 101 to 110, so the same pixels are selected
+    graph.set_parameter(threshold, "threshold", ParamValue::Int(110)).unwrap();
+    graph.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 1);
+    assert_eq!((graph.output_generation(threshold), graph.output_hash(threshold)), (generation, hash));
+    assert!(!graph.is_dirty(blob));
+
+    graph.set_parameter(threshold, "threshold", ParamValue::Int(120)).unwrap();
+    graph.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 2, "112 and 113 aren't selected anymore");
+    assert_ne!(graph.output_generation(threshold), generation);
+    assert_ne!(graph.output_hash(threshold), hash);
+
+    graph.set_skip_unchanged(false);
+    graph.set_parameter(threshold, "threshold", ParamValue::Int(121)).unwrap();
+    graph.compute_all().unwrap();
+    assert_eq!(computations.load(Ordering::SeqCst), 3, "Only skipped when asked for");
+}
+
+/// Produces a single pixel with a different value in each channel
+struct Colors;
+
+impl Layer for Colors {
+    fn kind(&self) -> String {
+        "Colors".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(RgbaImage::from_pixel(1, 1, image::Rgba([10, 20, 30, 40]))));
+        Ok(())
+    }
+}
+
+#[test]
+fn outputs_of_a_layer_feed_different_layers() {
+    let mut layers = InteractiveLayerGraph::new();
+    let colors = layers.add_layer(Box::new(Colors), vec![]);
+    let channels = layers.add_layer(Box::new(SplitChannels), vec![colors]);
+    let green = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![]);
+    let alpha = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![]);
+    layers.connect_output(channels, 1, green, 0).unwrap();
+    layers.connect_output(channels, 3, alpha, 0).unwrap();
+    assert!(layers.connect_output(channels, 4, alpha, 0).is_err(), "There are only four channels");
+
+    layers.compute_all().unwrap();
+    let value = |layers: &InteractiveLayerGraph, layer| {
+        let output = layers.graph().output(layer, 0)?.downcast_ref::<GrayImage>()?;
+        Some(output.get_pixel(0, 0).0[0])
+    };
+    assert_eq!((value(&layers, green), value(&layers, alpha)), (Some(235), Some(215)));
+    let blue = layers.graph().output(channels, 2).and_then(|output| output.downcast_ref::<GrayImage>());
+    assert_eq!(blue.map(|image| image.get_pixel(0, 0).0), Some([30]));
+    assert!(layers.graph().output(channels, 4).is_none());
+    assert!(layers.snapshot().edges.contains(&(channels, 3, alpha, 0)));
+
+    layers.undo().unwrap();
+    assert_eq!(layers.graph().source(alpha, 0), None);
+    layers.redo().unwrap();
+    assert_eq!(layers.graph().source(alpha, 0), Some((channels, 3)));
+    layers.disconnect(green, 0).unwrap();
+    layers.undo().unwrap();
+    assert_eq!(layers.graph().source(green, 0), Some((channels, 1)), "Undoing restores the output port");
+}
+
+#[test]
+fn layouts_follow_their_layers() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Gradient), vec![]);
+    let inverted = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    assert_eq!(layers.layout(inverted), None, "Layers start out without a layout");
+    let layout = NodeLayout {
+        position: Some((10.0, 20.0)),
+        collapsed: true,
+        color: Some(ColorTag::Green),
+        note: "Brightest parts are dark".to_string(),
+        ..NodeLayout::default()
+    };
+    layers.set_layout(inverted, layout.clone()).unwrap();
+    assert_eq!(layers.layout(inverted), Some(&layout));
+    assert!(layers.set_layout(NodeIndex::new(7), layout.clone()).is_err());
+
+    let copy = layers.duplicate_layer(inverted).unwrap();
+    let copied = layers.layout(copy).unwrap();
+    assert_eq!((copied.position, &copied.note), (Some((40.0, 50.0)), &layout.note), "Copies are placed next to it");
+
+    layers.remove_layer(inverted).unwrap();
+    assert_eq!(layers.layout(inverted), None);
+    layers.undo().unwrap();
+    assert_eq!(layers.layout(inverted), Some(&layout), "Undoing the removal brings the layout back");
+    layers.redo().unwrap();
+    assert_eq!(layers.layout(inverted), None);
+
+    // A new layer that takes over the index of the removed one starts out without a layout
+    let added = layers.add_layer(Box::new(Gradient), vec![]);
+    assert_eq!(added, inverted);
+    assert_eq!(layers.layout(added), None);
+    layers.set_layout(source, NodeLayout::default()).unwrap();
+    assert_eq!(layers.snapshot().nodes[0].layout, NodeLayout::default());
+}
+
+#[test]
+fn layers_are_found_by_id_and_name() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Gradient), vec![]);
+    let inverted = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    let again = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![inverted]);
+    layers.rename(source, "Invert<GrayImage>".to_string()).unwrap();
+    assert_eq!(layers.find_by_name("Invert<GrayImage>"), [source, inverted, again], "Names don't have to be unique");
+    assert_eq!(layers.find_by_name("Gradient"), []);
+
+    let ids: Vec<LayerId> = layers.nodes().map(|node| node.id).collect();
+    assert_eq!(ids.iter().map(|&id| layers.find_by_id(id)).collect::<Vec<_>>(), [source, inverted, again].map(Some));
+    layers.remove_layer(inverted).unwrap();
+    assert_eq!(layers.find_by_id(ids[1]), None);
+    let added = layers.add_layer(Box::new(Gradient), vec![]);
+    assert_eq!(added, inverted, "Indices are handed out again");
+    assert!(!ids.contains(&layers.graph().id(added).unwrap()), "Ids are not");
+
+    // Undoing brings back the layer that had the id, even while the edits refer to a reused index
+    layers.undo().unwrap();
+    layers.undo().unwrap();
+    assert_eq!(layers.find_by_id(ids[1]), Some(inverted));
+    assert_eq!(layers.graph().source(again, 0), Some((inverted, 0)));
+    let copy = layers.duplicate_layer(again).unwrap();
+    assert_eq!(layers.graph().name(copy), layers.graph().name(again));
+    assert_ne!(layers.graph().id(copy), layers.graph().id(again), "Copies get an id of their own");
+}
+
+#[test]
+fn selection_follows_removed_layers() {
+    let mut layers = InteractiveLayerGraph::new();
+    assert_eq!(layers.selected(), None);
+    assert!(layers.select(NodeIndex::new(0)).is_err(), "There is nothing to select");
+    assert!(layers.selected_output().is_none());
+
+    let source = layers.add_layer(Box::new(Gradient), vec![]);
+    let inverted = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    let other = layers.add_layer(Box::new(Gradient), vec![]);
+    assert_eq!(layers.selected(), Some(source), "The first layer is selected");
+    layers.select(inverted).unwrap();
+    layers.compute_layer(inverted).unwrap();
+    let output = layers.selected_output().and_then(|output| output.downcast_ref::<GrayImage>());
+    assert_eq!(output.map(|image| image.get_pixel(0, 0).0), Some([255]));
+
+    layers.remove_layer(inverted).unwrap();
+    assert_eq!(layers.selected(), Some(source), "Its input is selected instead");
+    layers.undo().unwrap();
+    assert_eq!(layers.selected(), Some(inverted));
+    layers.redo().unwrap();
+    assert_eq!(layers.selected(), Some(source));
+
+    layers.remove_layer(source).unwrap();
+    assert_eq!(layers.selected(), Some(other));
+    layers.remove_layer(other).unwrap();
+    assert_eq!(layers.selected(), None);
+}
+
+#[test]
+fn snapshots_show_the_state_of_every_layer() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Gradient), vec![]);
+    let inverted = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![]);
+    layers.compute_layer(inverted).unwrap();
+    assert!(layers.compute_layer(threshold).is_err());
+
+    let snapshot = layers.snapshot();
+    assert_eq!(snapshot.edges, [(source, 0, inverted, 0)]);
+    assert_eq!(snapshot.edges, layers.edges().collect::<Vec<_>>());
+    assert_eq!(snapshot.selected, Some(source));
+    let nodes: Vec<_> = snapshot.nodes.iter().map(|node| (node.node, node.dirty, node.output_dimensions)).collect();
+    assert_eq!(nodes, [(source, false, Some((16, 2))), (inverted, false, Some((16, 2))), (threshold, true, None)]);
+    assert_eq!(snapshot.nodes[1].kind, "Invert<GrayImage>");
+    assert!(snapshot.nodes[..2].iter().all(|node| node.error.is_none()));
+    let error = snapshot.nodes[2].error.as_deref().unwrap();
+    assert!(error.contains("Input 0 of layer 2 is not connected"), "{}", error);
+
+    // Until it is computed successfully
+    layers.connect(inverted, threshold, 0).unwrap();
+    layers.compute_layer(threshold).unwrap();
+    assert_eq!(layers.graph().error(threshold), None);
+    assert_ne!(layers.snapshot(), snapshot);
+}
+
+#[test]
+fn snapshot_deltas_turn_old_snapshots_into_new_ones() {
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(Gradient), vec![]);
+    let inverted = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, cmp::Ordering::Greater)), vec![inverted]);
+    let old = layers.snapshot();
+    assert!(GraphSnapshot::diff(&old, &old).is_empty());
+
+    layers.move_layer(threshold, (1.0, 2.0)).unwrap();
+    layers.remove_layer(inverted).unwrap();
+    let copy = layers.add_layer(Box::new(Invert::<GrayImage>::new()), vec![source]);
+    assert_eq!(copy, inverted, "The copy takes the index of the removed layer");
+    layers.connect(copy, threshold, 0).unwrap();
+    layers.select(copy).unwrap();
+    let new = layers.snapshot();
+    assert_eq!(new.edges, old.edges);
+
+    let delta = GraphSnapshot::diff(&old, &new);
+    assert_eq!(delta.removed, [inverted], "The copy is a different layer, even though it looks the same");
+    assert_eq!(delta.added.iter().map(|node| node.node).collect::<Vec<_>>(), [copy]);
+    assert_eq!(delta.changed.iter().map(|node| node.node).collect::<Vec<_>>(), [threshold]);
+    assert_eq!(delta.added_edges, old.edges, "Connections of the copy are new as well");
+    assert_eq!(delta.selected, Some(copy));
+    let mut applied = old.clone();
+    delta.apply(&mut applied);
+    assert_eq!(applied, new);
+
+    // And back
+    let delta = GraphSnapshot::diff(&new, &old);
+    assert_eq!(delta.removed, [copy]);
+    let mut applied = new;
+    delta.apply(&mut applied);
+    assert_eq!(applied, old);
+}
@@ -0,0 +1,23 @@
+use klex::logging::{Level, LogBuffer};
+
+#[test]
+fn log_buffer_keeps_the_most_recent_records() {
+    let mut log = LogBuffer::new(3);
+    for i in 0..2 {
+        log.log(Level::Info, format!("Message {}", i));
+    }
+    let sent: Vec<_> = log.take_unsent().into_iter().map(|record| record.message).collect();
+    assert_eq!(sent, vec!["Message 0", "Message 1"]);
+    assert!(log.take_unsent().is_empty());
+
+    for i in 2..7 {
+        log.log(Level::Warn, format!("Message {}", i));
+    }
+    let kept: Vec<_> = log.records().map(|record| record.message.as_str()).collect();
+    assert_eq!(kept, vec!["Message 4", "Message 5", "Message 6"]);
+    let sent = log.take_unsent();
+    assert_eq!(sent.len(), 3);
+    assert!(sent.iter().all(|record| record.level == Level::Warn));
+    assert!(sent.windows(2).all(|records| records[0].time <= records[1].time));
+    assert!(sent[0].to_string().ends_with("WARN Message 4"));
+}
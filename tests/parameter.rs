@@ -0,0 +1,33 @@
+use klex::parameter::{ParamKind, ParamValue};
+
+#[test]
+fn typed_values_are_parsed_and_clamped() {
+    let int = ParamKind::Int { min: 0, max: 255 };
+    assert_eq!(int.parse(" 42 ").unwrap(), (ParamValue::Int(42), false));
+    assert_eq!(int.parse("300").unwrap(), (ParamValue::Int(255), true));
+    assert_eq!(int.parse("-1").unwrap(), (ParamValue::Int(0), true));
+    assert!(int.parse("4.2").is_err());
+
+    let float = ParamKind::Float { min: 0.0, max: 1.0 };
+    assert_eq!(float.parse("0.5").unwrap(), (ParamValue::Float(0.5), false));
+    assert_eq!(float.parse("2").unwrap(), (ParamValue::Float(1.0), true));
+    assert!(float.parse("NaN").is_err());
+
+    let choice = ParamKind::Choice(vec!["Nearest".to_string(), "Linear".to_string()]);
+    assert_eq!(choice.parse("Linear").unwrap(), (ParamValue::Choice("Linear".to_string()), false));
+    assert!(choice.parse("Cubic").is_err());
+
+    assert!(ParamKind::Path.parse("").is_err());
+    assert!(ParamKind::Bool.parse("yes").is_err());
+}
+
+#[test]
+fn colors_are_written_and_read_as_hex() {
+    let color = ParamValue::Color([255, 128, 0, 255]);
+    assert_eq!(color.to_text(), "#ff8000ff");
+    assert_eq!(ParamKind::Color.parse("#ff8000ff").unwrap(), (color.clone(), false));
+    assert_eq!(ParamKind::Color.parse("#FF8000").unwrap(), (color, false));
+    assert!(ParamKind::Color.parse("#ff8000ff00").is_err());
+    assert!(ParamKind::Color.parse("#ff80").is_err());
+    assert!(ParamKind::Color.parse("#gg8000").is_err());
+}
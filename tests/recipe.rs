@@ -0,0 +1,408 @@
+use std::{cmp::Ordering, collections::BTreeMap, path::PathBuf};
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    entity::BinaryImage,
+    error::KlexError,
+    layer::primitive::{Convert, InputFile, SplitChannels, Threshold},
+    layer_graph::{ColorTag, InteractiveLayerGraph, NodeLayout},
+    parameter::{ParamKind, ParamMap, ParamValue},
+    recipe::{
+        self, Bindings, Include, IncludedRecipe, ParamOverrides, Placeholder, Recipe, RecipeEdge, RecipeNode, Severity,
+        INCLUDE,
+    },
+    registry::LayerRegistry,
+};
+
+fn tulips() -> InteractiveLayerGraph {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    layers.rename(threshold, "binarize".to_string()).unwrap();
+    layers.move_layer(threshold, (440.0, 20.0)).unwrap();
+    layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers
+}
+
+#[test]
+fn recipes_reproduce_the_graph() {
+    let recipe = Recipe::from_graph(&tulips());
+    assert_eq!(recipe.version, Recipe::VERSION);
+    assert_eq!(recipe.nodes[2].name, "binarize");
+    assert_eq!(recipe.nodes[2].parameters["threshold"], ParamValue::Int(100));
+    assert_eq!(recipe.layout[&2].position, Some((440.0, 20.0)));
+    assert!(!recipe.layout.contains_key(&0), "Layers that were never placed have no layout");
+
+    let loaded = Recipe::from_ron(&recipe.to_ron().unwrap()).unwrap();
+    assert_eq!(loaded, recipe);
+
+    let layers = loaded.build_graph(&LayerRegistry::with_builtins(), &Bindings::new()).unwrap();
+    assert_eq!(Recipe::from_graph(&layers), recipe);
+    assert_eq!(
+        layers.graph().layer(3.into()).unwrap().kind(),
+        "Convert<BinaryImage, GrayImage>"
+    );
+}
+
+#[test]
+fn layouts_are_stored_apart_from_the_layers() {
+    let mut layers = tulips();
+    let layout = NodeLayout {
+        size: Some((200.0, 120.0)),
+        collapsed: true,
+        color: Some(ColorTag::Purple),
+        note: "Too dark for the stems".to_string(),
+        ..layers.layout(2.into()).unwrap().clone()
+    };
+    layers.set_layout(2.into(), layout.clone()).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+    let text = recipe.to_ron().unwrap();
+    assert!(text.contains("layout:") && text.matches("position:").count() == 1, "Only in the layout: {}", text);
+
+    let registry = LayerRegistry::with_builtins();
+    let loaded = Recipe::from_ron(&text).unwrap().build_graph(&registry, &Bindings::new()).unwrap();
+    assert_eq!(loaded.layout(2.into()), Some(&layout));
+
+    let mut bare = recipe.clone();
+    bare.layout.clear();
+    let loaded = bare.build_graph(&registry, &Bindings::new()).unwrap();
+    assert_eq!(loaded.layout(2.into()), None, "Recipes without a layout still build");
+    assert_eq!(Recipe::from_graph(&loaded).nodes, recipe.nodes);
+}
+
+#[test]
+fn layers_keep_their_ids() {
+    let registry = LayerRegistry::with_builtins();
+    let mut layers = tulips();
+    let ids: Vec<_> = layers.nodes().map(|node| node.id).collect();
+    layers.remove_layer(1.into()).unwrap(); // Leaves a gap in the ids
+    let binarize = layers.find_by_id(ids[2]).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+    assert_eq!(recipe.nodes.iter().map(|node| node.id).collect::<Vec<_>>(), [ids[0], ids[2], ids[3]].map(Some));
+
+    let loaded = Recipe::from_ron(&recipe.to_ron().unwrap()).unwrap();
+    let mut loaded = loaded.build_graph(&registry, &Bindings::new()).unwrap();
+    let node = loaded.find_by_id(ids[2]).unwrap();
+    assert_eq!(loaded.graph().name(node), layers.graph().name(binarize));
+    let added = loaded.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![]);
+    assert!(loaded.graph().id(added) > Some(ids[3]), "New layers don't take the ids of loaded ones");
+
+    let mut unnamed = recipe.clone();
+    unnamed.nodes[2].id = None;
+    unnamed.nodes[0].id = Some(ids[3]);
+    let loaded = unnamed.build_graph(&registry, &Bindings::new()).unwrap();
+    assert_eq!(loaded.find_by_id(ids[3]), Some(0.into()));
+    assert!(loaded.graph().id(2.into()).is_some_and(|id| !ids.contains(&id)));
+}
+
+#[test]
+fn unknown_kinds_and_parameters_are_named_in_errors() {
+    let registry = LayerRegistry::with_builtins();
+    let mut recipe = Recipe::from_graph(&tulips());
+    recipe.nodes[1].kind = "Blur".to_string();
+    let error = recipe.build_graph(&registry, &Bindings::new()).err().unwrap();
+    assert!(matches!(error.root(), KlexError::UnknownLayerKind(kind) if kind == "Blur"));
+
+    let mut recipe = Recipe::from_graph(&tulips());
+    recipe.nodes[2]
+        .parameters
+        .insert("sigma".to_string(), ParamValue::Float(1.0));
+    let error = recipe.build_graph(&registry, &Bindings::new()).err().unwrap();
+    assert!(format!("{:#}", error).contains("\"sigma\""), "{:#}", error);
+    assert!(format!("{:#}", error).contains("\"binarize\""), "{:#}", error);
+}
+
+#[test]
+fn invalid_recipes_are_rejected() {
+    let registry = LayerRegistry::with_builtins();
+    let node = RecipeNode {
+        kind: "Convert<RgbaImage, GrayImage>".to_string(),
+        name: "gray".to_string(),
+        parameters: ParamMap::new(),
+        id: None,
+        include: None,
+        position: None,
+    };
+
+    let newer = Recipe {
+        version: Recipe::VERSION + 1,
+        placeholders: BTreeMap::new(),
+        nodes: vec![node.clone()],
+        edges: vec![],
+        layout: BTreeMap::new(),
+    };
+    assert!(newer.build_graph(&registry, &Bindings::new()).is_err());
+
+    let dangling = Recipe {
+        version: Recipe::VERSION,
+        placeholders: BTreeMap::new(),
+        nodes: vec![node],
+        edges: vec![RecipeEdge { from: 0, output: None, to: 1, port: 0 }],
+        layout: BTreeMap::new(),
+    };
+    assert!(dangling.build_graph(&registry, &Bindings::new()).is_err());
+
+    assert!(Recipe::from_ron("(version: 1, nodes: [(kind: 3)])").is_err());
+
+    let missing = std::env::temp_dir().join("klex-no-such-recipe.ron");
+    let error = Recipe::load(&missing).err().unwrap();
+    assert!(matches!(error.root(), KlexError::Io { path, .. } if *path == missing));
+}
+
+#[test]
+fn recipes_run_without_a_user_interface() {
+    let path = std::env::temp_dir().join(format!("klex-run-{}.png", std::process::id()));
+    let image = GrayImage::from_raw(4, 1, vec![0, 90, 110, 255]).unwrap();
+    image.save(&path).unwrap();
+
+    let mut layers = tulips();
+    layers.rename(0.into(), "input".to_string()).unwrap();
+    layers.rename(3.into(), "result".to_string()).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+
+    let mut overrides = ParamOverrides::new();
+    overrides.set("input.path", ParamValue::Path(path.clone()));
+    let outputs = recipe::run(&recipe, &overrides).unwrap();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs["result"].type_name, "GrayImage");
+    let result = outputs["result"].value.downcast_ref::<GrayImage>().unwrap();
+    assert_eq!(result.as_raw(), &vec![0, 0, 255, 255]);
+
+    overrides.set("binarize.threshold", ParamValue::Int(100));
+    overrides.set("binarize.ordering", ParamValue::Choice("Less".to_string()));
+    let outputs = recipe::run(&recipe, &overrides).unwrap();
+    let result = outputs["result"].value.downcast_ref::<GrayImage>().unwrap();
+    assert_eq!(result.as_raw(), &vec![255, 255, 0, 0]);
+
+    overrides.set("missing.path", ParamValue::Path(path.clone()));
+    assert!(recipe::run(&recipe, &overrides).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn placeholders_are_substituted() {
+    let mut recipe = Recipe::from_graph(&tulips());
+    recipe.placeholders.insert(
+        "folder".to_string(),
+        Placeholder {
+            kind: ParamKind::Path,
+            default: None,
+        },
+    );
+    recipe.placeholders.insert(
+        "level".to_string(),
+        Placeholder {
+            kind: ParamKind::Int { min: 0, max: 255 },
+            default: Some(ParamValue::Int(30)),
+        },
+    );
+    recipe.nodes[0]
+        .parameters
+        .insert("path".to_string(), ParamValue::Path("${folder}/tulips_${level}.png".into()));
+    recipe.nodes[2]
+        .parameters
+        .insert("threshold".to_string(), ParamValue::Text("${level}".to_string()));
+    let recipe = Recipe::from_ron(&recipe.to_ron().unwrap()).unwrap();
+    let registry = LayerRegistry::with_builtins();
+
+    let bindings = Bindings::from([("folder".to_string(), ParamValue::Path("images".into()))]);
+    let layers = recipe.build_graph(&registry, &bindings).unwrap();
+    assert_eq!(
+        layers.graph().parameters(0.into()).unwrap()["path"],
+        ParamValue::Path(PathBuf::from("images/tulips_30.png"))
+    );
+    assert_eq!(layers.graph().parameters(2.into()).unwrap()["threshold"], ParamValue::Int(30));
+
+    let mut bindings = bindings;
+    bindings.insert("level".to_string(), ParamValue::Int(7));
+    let layers = recipe.build_graph(&registry, &bindings).unwrap();
+    assert_eq!(layers.graph().parameters(2.into()).unwrap()["threshold"], ParamValue::Int(7));
+
+    bindings.insert("level".to_string(), ParamValue::Text("high".to_string()));
+    let error = recipe.build_graph(&registry, &bindings).err().unwrap();
+    assert!(format!("{:#}", error).contains("\"level\""), "{:#}", error);
+
+    let error = recipe.build_graph(&registry, &Bindings::new()).err().unwrap();
+    assert!(format!("{:#}", error).contains("\"folder\" is not bound"), "{:#}", error);
+}
+
+#[test]
+fn validation_reports_issues_by_node() {
+    let registry = LayerRegistry::with_builtins();
+    assert_eq!(Recipe::from_graph(&tulips()).validate(&registry), vec![]);
+
+    let mut recipe = Recipe::from_graph(&tulips());
+    recipe.nodes[0].parameters.clear();
+    recipe.nodes[1].kind = "Blur".to_string();
+    recipe.nodes[2].parameters.insert("threshold".to_string(), ParamValue::Int(256));
+    recipe.nodes[3].parameters.insert("path".to_string(), ParamValue::Text("${output}".to_string()));
+    recipe.nodes.push(RecipeNode {
+        kind: "Convert<RgbaImage, GrayImage>".to_string(),
+        name: "extra".to_string(),
+        parameters: ParamMap::new(),
+        id: None,
+        include: None,
+        position: None,
+    });
+    recipe.nodes.push(RecipeNode {
+        kind: "Convert<RgbaImage, GrayImage>".to_string(),
+        name: "mismatch".to_string(),
+        parameters: ParamMap::new(),
+        id: recipe.nodes[1].id,
+        include: None,
+        position: None,
+    });
+    recipe.edges.push(RecipeEdge { from: 2, output: None, to: 5, port: 0 });
+    recipe.edges.push(RecipeEdge { from: 3, output: None, to: 2, port: 0 });
+    recipe.edges.push(RecipeEdge { from: 0, output: None, to: 9, port: 0 });
+
+    let issues = recipe.validate(&registry);
+    let has_issue = |severity, node: Option<&str>, message: &str| {
+        issues.iter().any(|issue| {
+            issue.severity == severity && issue.node.as_deref() == node && issue.message.contains(message)
+        })
+    };
+    assert!(has_issue(Severity::Error, Some("InputFile"), "Missing parameter \"path\""), "{:#?}", issues);
+    let unknown = "Unknown layer kind \"Blur\"";
+    assert!(has_issue(Severity::Error, Some("Convert<RgbaImage, GrayImage>"), unknown), "{:#?}", issues);
+    assert!(has_issue(Severity::Error, Some("binarize"), "out of range"), "{:#?}", issues);
+    assert!(has_issue(Severity::Error, Some("binarize"), "connected more than once"), "{:#?}", issues);
+    let mismatch = "expects RgbaImage, but node \"binarize\" produces BinaryImage";
+    assert!(has_issue(Severity::Error, Some("mismatch"), mismatch), "{:#?}", issues);
+    let undeclared = "undeclared placeholder \"output\"";
+    assert!(has_issue(Severity::Error, Some("Convert<BinaryImage, GrayImage>"), undeclared), "{:#?}", issues);
+    assert!(has_issue(Severity::Error, None, "node 9"), "{:#?}", issues);
+    assert!(issues.iter().any(|issue| issue.message.contains("cycle")), "{:#?}", issues);
+    assert!(has_issue(Severity::Error, Some("extra"), "Input 0 is not connected"), "{:#?}", issues);
+    assert!(has_issue(Severity::Warning, Some("extra"), "not connected to any other node"), "{:#?}", issues);
+    assert!(has_issue(Severity::Error, Some("mismatch"), "same id"), "{:#?}", issues);
+}
+
+#[test]
+fn recipes_name_the_output_ports_they_connect() {
+    let registry = LayerRegistry::with_builtins();
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("Tulips.jpg".into())), vec![]);
+    let channels = layers.add_layer(Box::new(SplitChannels), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![]);
+    layers.connect_output(channels, 2, threshold, 0).unwrap();
+
+    let recipe = Recipe::from_graph(&layers);
+    let outputs: Vec<_> = recipe.edges.iter().map(|edge| edge.output.as_deref()).collect();
+    assert_eq!(outputs, vec![None, Some("blue")], "Layers with a single output don't name it");
+    let loaded = Recipe::from_ron(&recipe.to_ron().unwrap()).unwrap();
+    assert_eq!(loaded, recipe);
+    assert_eq!(loaded.validate(&registry), vec![]);
+    let built = loaded.build_graph(&registry, &Bindings::new()).unwrap();
+    assert_eq!(built.graph().source(threshold, 0), Some((channels, 2)));
+
+    let mut recipe = recipe;
+    recipe.edges[1].output = Some("purple".to_string());
+    let error = recipe.build_graph(&registry, &Bindings::new()).err().unwrap();
+    assert!(format!("{:#}", error).contains("\"purple\""), "{:#}", error);
+    let issues = recipe.validate(&registry);
+    assert!(issues.iter().any(|issue| issue.message.contains("no output \"purple\"")), "{:#?}", issues);
+}
+
+fn include_node(name: &str, recipe: IncludedRecipe, parameters: ParamMap) -> RecipeNode {
+    RecipeNode {
+        kind: INCLUDE.to_string(),
+        name: name.to_string(),
+        parameters,
+        id: None,
+        include: Some(Include {
+            recipe,
+            inputs: vec![("gray".to_string(), 0)],
+            output: "binarize".to_string(),
+        }),
+        position: None,
+    }
+}
+
+/// InputFile → include → Convert<BinaryImage, GrayImage>
+fn including_recipe(included: IncludedRecipe, parameters: ParamMap) -> Recipe {
+    let tulips = Recipe::from_graph(&tulips());
+    Recipe {
+        version: Recipe::VERSION,
+        placeholders: BTreeMap::new(),
+        nodes: vec![
+            tulips.nodes[0].clone(),
+            include_node("preamble", included, parameters),
+            tulips.nodes[3].clone(),
+        ],
+        edges: vec![
+            RecipeEdge { from: 0, output: None, to: 1, port: 0 },
+            RecipeEdge { from: 1, output: None, to: 2, port: 0 },
+        ],
+        layout: BTreeMap::new(),
+    }
+}
+
+#[test]
+fn included_recipes_are_inlined() {
+    // Convert<RgbaImage, GrayImage> → Threshold, with the threshold as a placeholder
+    let mut preamble = Recipe::from_graph(&tulips());
+    preamble.nodes.remove(0);
+    preamble.nodes.pop();
+    preamble.nodes[0].name = "gray".to_string();
+    preamble.nodes[1]
+        .parameters
+        .insert("threshold".to_string(), ParamValue::Text("${level}".to_string()));
+    preamble.edges = vec![RecipeEdge { from: 0, output: None, to: 1, port: 0 }];
+    preamble.placeholders.insert(
+        "level".to_string(),
+        Placeholder {
+            kind: ParamKind::Int { min: 0, max: 255 },
+            default: None,
+        },
+    );
+
+    let directory = std::env::temp_dir().join(format!("klex-include-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    preamble.save(&directory.join("preamble.ron")).unwrap();
+
+    let parameters = ParamMap::from([("level".to_string(), ParamValue::Int(42))]);
+    let recipe = including_recipe(IncludedRecipe::Path("preamble.ron".into()), parameters.clone());
+    recipe.save(&directory.join("project.ron")).unwrap();
+    let recipe = Recipe::load(&directory.join("project.ron")).unwrap();
+    let registry = LayerRegistry::with_builtins();
+    assert!(recipe.validate(&registry).iter().all(|issue| issue.severity != Severity::Error));
+
+    let layers = recipe.build_graph(&registry, &Bindings::new()).unwrap();
+    let graph = layers.graph();
+    let names: Vec<_> = graph.node_indices().map(|layer| graph.name(layer).unwrap()).collect();
+    assert_eq!(names, vec!["InputFile", "preamble/gray", "preamble/binarize", "Convert<BinaryImage, GrayImage>"]);
+    assert_eq!(graph.parameters(2.into()).unwrap()["threshold"], ParamValue::Int(42));
+    let mut edges: Vec<_> = graph.edges().map(|(from, _, to, port)| (from.index(), to.index(), port)).collect();
+    edges.sort();
+    assert_eq!(edges, vec![(0, 1, 0), (1, 2, 0), (2, 3, 0)]);
+
+    // Embedding the recipe works the same way
+    let embedded = including_recipe(IncludedRecipe::Embedded(Box::new(preamble)), parameters);
+    let layers = embedded.build_graph(&registry, &Bindings::new()).unwrap();
+    assert_eq!(Recipe::from_graph(&layers).nodes[2].name, "preamble/binarize");
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn recursive_includes_are_rejected() {
+    let directory = std::env::temp_dir().join(format!("klex-recursion-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    including_recipe(IncludedRecipe::Path("b.ron".into()), ParamMap::new())
+        .save(&directory.join("a.ron"))
+        .unwrap();
+    including_recipe(IncludedRecipe::Path("a.ron".into()), ParamMap::new())
+        .save(&directory.join("b.ron"))
+        .unwrap();
+
+    let recipe = Recipe::load(&directory.join("a.ron")).unwrap();
+    let error = recipe.build_graph(&LayerRegistry::with_builtins(), &Bindings::new()).err().unwrap();
+    let message = format!("{:#}", error);
+    assert!(message.contains("includes itself"), "{}", message);
+    assert!(message.contains("a.ron\" -> ") && message.contains("b.ron\""), "{}", message);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use image::{GrayImage, Luma};
+
+use klex::{
+    error::Result,
+    layer::{Layer, LayerCategory, LayerOutput},
+    parameter::{ParamKind, ParamMap, ParamSpec, ParamValue},
+    registry::LayerRegistry,
+};
+
+fn error_message(registry: &LayerRegistry, kind: &str, parameters: ParamMap) -> String {
+    match registry.create(kind, &parameters) {
+        Ok(_) => panic!("Creating {:?} with {:?} should fail", kind, parameters),
+        Err(error) => format!("{:#}", error),
+    }
+}
+
+#[test]
+fn builtin_layers_validate_their_parameters() {
+    let registry = LayerRegistry::with_builtins();
+
+    let message = error_message(&registry, "InputFile", ParamMap::new());
+    assert!(message.contains("Missing parameter \"path\""), "{}", message);
+
+    let parameters = ParamMap::from([("threshold".to_string(), ParamValue::Int(300))]);
+    let message = error_message(&registry, "Threshold", parameters);
+    assert!(message.contains("\"threshold\"") && message.contains("out of range"), "{}", message);
+
+    let parameters = ParamMap::from([("ordering".to_string(), ParamValue::Choice("Sideways".to_string()))]);
+    let message = error_message(&registry, "Threshold", parameters);
+    assert!(message.contains("\"Sideways\""), "{}", message);
+
+    let message = error_message(&registry, "Blur", ParamMap::new());
+    assert!(message.contains("Unknown layer kind \"Blur\""), "{}", message);
+}
+
+#[test]
+fn missing_parameters_take_their_defaults() {
+    let registry = LayerRegistry::with_builtins();
+    let parameters = ParamMap::from([("threshold".to_string(), ParamValue::Int(10))]);
+    let layer = registry.create("Threshold", &parameters).unwrap();
+    assert_eq!(layer.parameters()["threshold"], ParamValue::Int(10));
+    assert_eq!(layer.parameters()["ordering"], ParamValue::Choice("Greater".to_string()));
+}
+
+#[test]
+fn catalog_lists_registered_layers() {
+    struct Counter {
+        start: i64,
+    }
+
+    impl Layer for Counter {
+        fn kind(&self) -> String {
+            "Counter".to_string()
+        }
+
+        fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+            *output = Some(Box::new(self.start));
+            Ok(())
+        }
+    }
+
+    let mut registry = LayerRegistry::with_builtins();
+    let spec = ParamSpec::new("start", ParamKind::Int { min: 0, max: 10 }, Some(ParamValue::Int(0)));
+    registry.register("Counter", vec![spec.clone()], |parameters| {
+        let start = match parameters["start"] {
+            ParamValue::Int(start) => start,
+            _ => unreachable!("Parameters are validated by the registry"),
+        };
+        Ok(Box::new(Counter { start }))
+    });
+
+    let catalog: Vec<_> = registry.catalog().collect();
+    assert!(catalog.contains(&("Counter", &[spec][..])));
+    assert!(catalog.iter().any(|&(kind, _)| kind == "Convert<RgbaImage, GrayImage>"));
+    let (_, threshold) = catalog.iter().find(|&&(kind, _)| kind == "Threshold").unwrap();
+    assert_eq!(threshold.len(), 2);
+
+    assert!(registry.create("Counter", &ParamMap::new()).is_ok());
+    let parameters = ParamMap::from([("start".to_string(), ParamValue::Int(11))]);
+    assert!(registry.create("Counter", &parameters).is_err());
+}
+
+#[test]
+fn layers_can_be_created_with_defaults() {
+    let registry = LayerRegistry::with_builtins();
+    assert_eq!(registry.info("InputFile").unwrap().category, LayerCategory::Input);
+    assert_eq!(registry.info("Threshold").unwrap().category, LayerCategory::Filter);
+    assert_eq!(registry.info("Convert<RgbaImage, GrayImage>").unwrap().category, LayerCategory::Convert);
+
+    assert!(registry.create("InputFile", &ParamMap::new()).is_err(), "The path has no default");
+    let input = registry.create_default("InputFile").unwrap();
+    assert_eq!(input.parameters()["path"], ParamValue::Path(PathBuf::new()));
+    let threshold = registry.create_default("Threshold").unwrap();
+    assert_eq!(threshold.parameters()["threshold"], ParamValue::Int(128));
+    assert!(registry.create_default("Blur").is_err());
+}
+
+#[test]
+fn crops_are_clamped_to_the_image() {
+    let registry = LayerRegistry::with_builtins();
+    let crop = |x: i64, y: i64, width: i64, height: i64| {
+        let parameters = [("x", x), ("y", y), ("width", width), ("height", height)];
+        let parameters = parameters.map(|(name, value)| (name.to_string(), ParamValue::Int(value)));
+        registry.create("Crop<GrayImage>", &ParamMap::from(parameters)).unwrap()
+    };
+    let image = GrayImage::from_fn(10, 8, |x, y| Luma([(10 * y + x) as u8]));
+    let input: LayerOutput = Some(Box::new(image));
+    let cropped = |layer: Box<dyn Layer>| {
+        let mut output = None;
+        layer.compute(&[&input], &mut output).unwrap();
+        output.unwrap().downcast::<GrayImage>().unwrap()
+    };
+
+    let output = cropped(crop(2, 3, 4, 2));
+    assert_eq!(output.dimensions(), (4, 2));
+    assert_eq!(output.get_pixel(0, 0).0, [32]);
+    assert_eq!(cropped(crop(2, 3, 0, 0)).dimensions(), (8, 5), "Sizes of 0 reach to the edge");
+    assert_eq!(cropped(crop(6, 0, 100, 1)).dimensions(), (4, 1));
+    let output = cropped(crop(50, 50, 3, 3));
+    assert_eq!((output.dimensions(), output.get_pixel(0, 0).0), ((1, 1), [79]), "The last pixel is kept");
+}
@@ -0,0 +1,91 @@
+use std::cmp::Ordering;
+
+use image::{GrayImage, Luma};
+
+use klex::{
+    entity::Rect,
+    error::{KlexError, Result},
+    layer::{primitive::Threshold, Layer, LayerOutput},
+    parameter::Parameter,
+    roi::RoiScope,
+};
+
+/// Inverts a gray image
+struct Invert;
+
+impl Layer for Invert {
+    fn kind(&self) -> String {
+        "Invert".to_string()
+    }
+
+    fn compute(&self, input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        let image = input[0].as_ref().unwrap().downcast_ref::<GrayImage>().unwrap();
+        let mut image = image.clone();
+        image.pixels_mut().for_each(|pixel| pixel.0[0] = 255 - pixel.0[0]);
+        *output = Some(Box::new(image));
+        Ok(())
+    }
+}
+
+fn gradient() -> LayerOutput {
+    Some(Box::new(GrayImage::from_fn(8, 6, |x, y| Luma([(x * 10 + y) as u8]))))
+}
+
+fn compute(layer: &dyn Layer, input: &LayerOutput) -> Result<GrayImage> {
+    let mut output = None;
+    layer.compute(&[input], &mut output)?;
+    Ok(*output.unwrap().downcast::<GrayImage>().unwrap())
+}
+
+#[test]
+fn only_the_region_changes() {
+    let input = gradient();
+    let original = input.as_ref().unwrap().downcast_ref::<GrayImage>().unwrap();
+    let rect = Rect::new(2, 1, 3, 4);
+    let output = compute(&RoiScope::new(Box::new(Invert), rect), &input).unwrap();
+
+    assert_eq!(output.dimensions(), original.dimensions());
+    for (x, y, pixel) in output.enumerate_pixels() {
+        let expected = original.get_pixel(x, y).0[0];
+        let expected = if rect.contains(x, y) { 255 - expected } else { expected };
+        assert_eq!(pixel.0[0], expected, "pixel ({}, {})", x, y);
+    }
+}
+
+#[test]
+fn regions_are_clamped_to_the_image() {
+    let input = gradient();
+    let original = input.as_ref().unwrap().downcast_ref::<GrayImage>().unwrap();
+    let output = compute(&RoiScope::new(Box::new(Invert), Rect::new(6, 4, 100, 100)), &input).unwrap();
+    assert_eq!(output.get_pixel(7, 5).0[0], 255 - original.get_pixel(7, 5).0[0]);
+    assert_eq!(output.get_pixel(5, 5), original.get_pixel(5, 5));
+
+    // A size of 0 reaches to the edge, like with Crop
+    let roi = RoiScope::new(Box::new(Invert), Rect::new(3, 2, 0, 0));
+    assert_eq!(roi.region((8, 6)), Rect::new(3, 2, 5, 4));
+    assert!(RoiScope::new(Box::new(Invert), Rect::new(20, 20, 2, 2)).region((8, 6)).is_empty());
+}
+
+#[test]
+fn inner_parameters_are_forwarded_with_a_prefix() {
+    let mut roi = RoiScope::new(Box::new(Threshold::new(10, Ordering::Greater)), Rect::new(0, 0, 4, 4));
+    let parameters = roi.parameters();
+    assert_eq!(parameters["x"], 0_u32.to_value());
+    assert_eq!(parameters["width"], 4_u32.to_value());
+    assert_eq!(parameters["inner.threshold"], 10_u8.to_value());
+
+    roi.set_parameter("inner.threshold", 42_u8.to_value()).unwrap();
+    roi.set_parameter("height", 2_u32.to_value()).unwrap();
+    assert_eq!(roi.inner().parameters()["threshold"], 42_u8.to_value());
+    assert_eq!(roi.parameters()["height"], 2_u32.to_value());
+    assert!(roi.set_parameter("threshold", 42_u8.to_value()).is_err());
+}
+
+#[test]
+fn inner_layers_must_keep_the_element_type() {
+    let roi = RoiScope::new(Box::new(Threshold::new(10, Ordering::Greater)), Rect::new(0, 0, 4, 4));
+    let mut output = None;
+    let error = roi.compute(&[&gradient()], &mut output).unwrap_err();
+    assert!(matches!(error.root(), KlexError::TypeMismatch { .. }), "{:?}", error);
+    assert!(output.is_none());
+}
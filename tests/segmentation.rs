@@ -0,0 +1,124 @@
+use image::{GrayImage, Luma};
+
+use klex::{
+    entity::{self, BinaryImage, LabelImage, PixelValue, Point},
+    error::KlexError,
+    layer::{
+        primitive::{Connectivity, RegionGrow, Watershed},
+        Layer, LayerOutput,
+    },
+    parameter::ParamValue,
+    registry::LayerRegistry,
+};
+
+fn labels(image: &LabelImage) -> Vec<u32> {
+    image.as_raw().clone()
+}
+
+#[test]
+fn regions_grow_from_each_seed() {
+    // Two noisy halves, the right one brighter
+    let image = GrayImage::from_fn(8, 4, |x, y| Luma([if x < 4 { 50 } else { 200 } + ((x * 7 + y * 3) % 5) as u8]));
+    let seeds = [Point { x: 1.5, y: 2.0 }, Point { x: 6.0, y: 0.0 }];
+    let grown = RegionGrow::new(Vec::new(), 10.0).compute(&image, &seeds).unwrap();
+    assert!(grown.enumerate_pixels().all(|(x, _, label)| label[0] == if x < 4 { 1 } else { 2 }));
+    assert_eq!(entity::pixel(&grown, 5, 1), Some(PixelValue::Label(2)));
+    assert_ne!(entity::label_color(1), entity::label_color(2));
+    let thumbnail = entity::thumbnail(&grown, 8).unwrap();
+    assert_eq!(thumbnail.get_pixel(7, 3).0, entity::label_color(2));
+
+    // The tolerance is to the mean of the region, not to the neighbor a pixel is reached from
+    let ramp = GrayImage::from_fn(6, 1, |x, _| Luma([x as u8 * 5]));
+    let grown = RegionGrow::new(Vec::new(), 6.0).compute(&ramp, &[Point { x: 0.0, y: 0.0 }]).unwrap();
+    assert_eq!(labels(&grown), [1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn connectivity_decides_whether_corners_connect() {
+    let diagonal = GrayImage::from_fn(3, 3, |x, y| Luma([if x == y { 255 } else { 0 }]));
+    let mut layer = RegionGrow::new(vec![Point { x: 0.0, y: 0.0 }], 10.0);
+    layer.set_parameter("binary", ParamValue::Bool(true)).unwrap();
+    assert_eq!(layer.output_type(), Some("BinaryImage"));
+    let input: LayerOutput = Some(Box::new(diagonal));
+    let grow = |layer: &RegionGrow| {
+        let mut output = None;
+        Layer::compute(layer, &[&input], &mut output).unwrap();
+        output.unwrap().downcast_ref::<BinaryImage>().unwrap().data().clone()
+    };
+    assert_eq!(grow(&layer).iter().filter(|&&pixel| pixel).count(), 1);
+    layer.set_parameter("connectivity", ParamValue::Choice("Eight".to_string())).unwrap();
+    assert_eq!(grow(&layer), [true, false, false, false, true, false, false, false, true]);
+}
+
+#[test]
+fn seeds_come_from_points_or_parameters() {
+    let image = GrayImage::from_fn(4, 2, |x, _| Luma([x as u8 * 80]));
+    let mut layer = LayerRegistry::with_builtins().create_default("RegionGrow").unwrap();
+    layer.set_parameter("seeds", ParamValue::Text("0,0 3.5,1".to_string())).unwrap();
+    assert_eq!(layer.parameters()["seeds"], ParamValue::Text("0,0 3.5,1".to_string()));
+    let input: LayerOutput = Some(Box::new(image));
+    let mut output = None;
+    layer.compute(&[&input], &mut output).unwrap();
+    let grown = output.take().unwrap();
+    assert_eq!(labels(grown.downcast_ref::<LabelImage>().unwrap()), [1, 0, 0, 2, 1, 0, 0, 2]);
+
+    // Connected points take the place of the parameter, in either order
+    let points: LayerOutput = Some(Box::new(vec![Point { x: 1.0, y: 1.0 }]));
+    layer.compute(&[&points, &input], &mut output).unwrap();
+    let grown = output.take().unwrap();
+    assert_eq!(labels(grown.downcast_ref::<LabelImage>().unwrap()), [0, 1, 0, 0, 0, 1, 0, 0]);
+
+    let outside: LayerOutput = Some(Box::new(vec![Point { x: 1.0, y: 1.0 }, Point { x: 4.0, y: -0.5 }]));
+    let error = layer.compute(&[&input, &outside], &mut output).unwrap_err();
+    assert!(matches!(error.root(), KlexError::SeedOutOfBounds { x, y, size: (4, 2) } if (*x, *y) == (4.0, -0.5)));
+    assert!(error.to_string().contains("(4, -0.5)"), "{}", error);
+    assert!(layer.set_parameter("seeds", ParamValue::Text("1;2".to_string())).is_err());
+    layer.set_parameter("seeds", ParamValue::Text(String::new())).unwrap();
+    assert!(layer.compute(&[&input], &mut output).is_err(), "There has to be a seed");
+}
+
+#[test]
+fn watersheds_split_basins_between_markers() {
+    let ridge = GrayImage::from_raw(9, 1, vec![0, 1, 2, 3, 4, 3, 2, 1, 0]).unwrap();
+    let mut markers = LabelImage::new(9, 1);
+    markers.put_pixel(0, 0, Luma([7]));
+    markers.put_pixel(8, 0, Luma([3]));
+    let flooded = Watershed::new(Connectivity::Four, true).compute(&ridge, &markers).unwrap();
+    assert_eq!(labels(&flooded), [7, 7, 7, 7, 0, 3, 3, 3, 3]);
+    // Without lines, the tie at the ridge goes to the basin reaching it from the lower index
+    let flooded = Watershed::new(Connectivity::Four, false).compute(&ridge, &markers).unwrap();
+    assert_eq!(labels(&flooded), [7, 7, 7, 7, 7, 3, 3, 3, 3]);
+
+    // Two touching blobs, as the inverted distance to their centers, are split where they touch
+    let centers = [(3.0_f32, 3.0_f32), (9.0, 4.0)];
+    let blobs = GrayImage::from_fn(13, 8, |x, y| {
+        let distances = centers.map(|(cx, cy)| (x as f32 - cx).hypot(y as f32 - cy));
+        let distance = distances.into_iter().fold(f32::MAX, f32::min);
+        Luma([(distance * 20.0).min(255.0) as u8])
+    });
+    let mut markers = LabelImage::new(13, 8);
+    markers.put_pixel(3, 3, Luma([1]));
+    markers.put_pixel(9, 4, Luma([2]));
+    let layer = LayerRegistry::with_builtins().create_default("Watershed").unwrap();
+    assert_eq!(layer.input_types(), ["GrayImage", "LabelImage"]);
+    let (image, markers): (LayerOutput, LayerOutput) = (Some(Box::new(blobs)), Some(Box::new(markers)));
+    let mut output = None;
+    layer.compute(&[&image, &markers], &mut output).unwrap();
+    let output = output.unwrap();
+    let flooded = output.downcast_ref::<LabelImage>().unwrap();
+    for (x, y, label) in flooded.enumerate_pixels() {
+        let [left, right] = centers.map(|(cx, cy)| (x as f32 - cx).hypot(y as f32 - cy));
+        match label[0] {
+            0 => assert!((left - right).abs() <= 1.5, "A line at {}, {} far from the middle", x, y),
+            1 => assert!(left <= right + 1.0, "{}, {}", x, y),
+            2 => assert!(right <= left + 1.0, "{}, {}", x, y),
+            other => panic!("Unexpected label {}", other),
+        }
+    }
+    let line = flooded.pixels().filter(|label| label[0] == 0).count();
+    assert!((8..=16).contains(&line), "{} pixels of lines", line);
+
+    let small: LayerOutput = Some(Box::new(LabelImage::new(2, 2)));
+    let error = layer.compute(&[&image, &small], &mut None).unwrap_err();
+    assert!(matches!(error.root(), KlexError::ShapeMismatch { .. }), "{}", error);
+}
@@ -0,0 +1,57 @@
+use image::{GrayImage, Luma, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use klex::{
+    entity::BinaryImage,
+    serialize::{image_buffer, Npy},
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Cached {
+    #[serde(with = "image_buffer")]
+    color: RgbaImage,
+    #[serde(with = "image_buffer")]
+    gray: GrayImage,
+    mask: BinaryImage,
+}
+
+#[test]
+fn images_are_serialized_as_bytes() {
+    let cached = Cached {
+        color: RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 7, 255])),
+        gray: GrayImage::from_fn(5, 1, |x, _| Luma([x as u8 * 50])),
+        mask: BinaryImage::new(3, 3, (0..9).map(|i| i % 4 == 0).collect()),
+    };
+    let text = ron::to_string(&cached).unwrap();
+    assert!(!text.contains("255,"), "Pixels are stored as a byte string: {}", text);
+    assert_eq!(ron::from_str::<Cached>(&text).unwrap(), cached);
+
+    let wrong = text.replace("width:3", "width:4");
+    assert!(ron::from_str::<Cached>(&wrong).is_err(), "Sizes are checked");
+}
+
+#[test]
+fn images_are_stored_as_npy_files() {
+    let directory = std::env::temp_dir().join(format!("klex-serialize-npy-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let gray = GrayImage::from_fn(3, 2, |x, y| Luma([(10 * x + y) as u8]));
+    let path = directory.join("gray.npy");
+    gray.to_npy(&path).unwrap();
+    assert_eq!(GrayImage::from_npy(&path).unwrap(), gray);
+
+    // The layout NumPy expects: the data starts at a multiple of 64 bytes, in rows of the image
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(bytes.starts_with(b"\x93NUMPY\x01\x00"));
+    let header = std::str::from_utf8(&bytes[10..bytes.len() - 6]).unwrap();
+    assert!(header.starts_with("{'descr': '|u1', 'fortran_order': False, 'shape': (2, 3), }"), "{}", header);
+    assert!(header.ends_with('\n'));
+    assert_eq!((bytes.len() - 6) % 64, 0);
+    assert_eq!(&bytes[bytes.len() - 6..], &[0, 10, 20, 1, 11, 21]);
+
+    let mask = BinaryImage::new(2, 3, vec![true, false, false, true, true, true]);
+    let path = directory.join("mask.npy");
+    mask.to_npy(&path).unwrap();
+    assert_eq!(BinaryImage::from_npy(&path).unwrap(), mask);
+    assert!(GrayImage::from_npy(&path).is_err(), "The data type is checked");
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "ui")]
+
+use std::{fs, path::PathBuf};
+
+use klex::{parameter::ParamValue, recipe::Bindings, session::Session, util};
+
+#[test]
+fn sessions_survive_a_round_trip_and_ignore_broken_files() {
+    let directory = std::env::temp_dir().join(format!("klex-session-{}", std::process::id()));
+    let path = directory.join("config").join("session.ron");
+    assert_eq!(Session::load(&path), Session::default(), "Missing files are an empty session");
+
+    let mut session = Session {
+        recipe: Some(Session::recipe_path(&path)),
+        bindings: Bindings::from([("image".to_string(), ParamValue::Path("Tulips.jpg".into()))]),
+        ..Session::default()
+    };
+    session.add_recent("Tulips.jpg".into());
+    session.save(&path).unwrap();
+    assert_eq!(Session::load(&path), session);
+    assert_eq!(session.recipe, Some(directory.join("config").join("last-recipe.ron")));
+
+    fs::write(&path, "(recent: [\"Tulips.jpg\"], recipe: 3").unwrap();
+    assert_eq!(Session::load(&path), Session::default(), "Corrupted files are an empty session");
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn recent_images_are_kept_in_order_without_duplicates() {
+    let mut session = Session::default();
+    for i in 0..Session::RECENT_LENGTH + 2 {
+        session.add_recent(PathBuf::from(format!("{}.png", i)));
+    }
+    session.add_recent("5.png".into());
+    assert_eq!(session.recent.len(), Session::RECENT_LENGTH);
+    assert_eq!(session.recent[..3], [PathBuf::from("5.png"), "11.png".into(), "10.png".into()]);
+    assert_eq!(session.recent.last(), Some(&PathBuf::from("2.png")));
+}
+
+#[test]
+fn atomic_writes_replace_the_file_as_a_whole() {
+    let directory = std::env::temp_dir().join(format!("klex-atomic-{}", std::process::id()));
+    let path = directory.join("nested").join("state.txt");
+    util::write_atomically(&path, b"first version, which is longer").unwrap();
+    util::write_atomically(&path, b"second").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    let files: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(files, ["state.txt"], "The temporary file is gone");
+    fs::remove_dir_all(directory).unwrap();
+}
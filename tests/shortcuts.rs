@@ -0,0 +1,56 @@
+#![cfg(feature = "ui")]
+
+use std::fs;
+
+use iced_native::keyboard::{KeyCode, Modifiers};
+
+use klex::{
+    session::Session,
+    shortcuts::{Action, KeyCombo, Shortcuts},
+};
+
+fn modifiers(control: bool, shift: bool) -> Modifiers {
+    Modifiers {
+        control,
+        shift,
+        ..Modifiers::default()
+    }
+}
+
+#[test]
+fn default_shortcuts_need_the_exact_modifiers() {
+    let shortcuts = Shortcuts::default();
+    assert_eq!(shortcuts.action(KeyCode::Z, modifiers(true, false)), Some(Action::Undo));
+    assert_eq!(shortcuts.action(KeyCode::Z, modifiers(true, true)), Some(Action::Redo));
+    assert_eq!(shortcuts.action(KeyCode::Z, modifiers(false, false)), None);
+    assert_eq!(shortcuts.action(KeyCode::A, modifiers(false, true)), Some(Action::AddLayer));
+    assert_eq!(shortcuts.action(KeyCode::Key0, modifiers(true, false)), Some(Action::ZoomToFit));
+    assert_eq!(shortcuts.action(KeyCode::C, modifiers(true, true)), Some(Action::Copy));
+
+    let listed: Vec<_> = shortcuts.bindings().map(|(combo, _)| combo.to_string()).take(5).collect();
+    assert_eq!(listed, ["Shift+A", "Escape", "Delete", "Ctrl+D", "Ctrl+Z"]);
+}
+
+#[test]
+fn shortcuts_can_be_rebound_in_the_session_file() {
+    let directory = std::env::temp_dir().join(format!("klex-shortcuts-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("session.ron");
+    fs::write(&path, r#"(recent: [], shortcuts: [("ctrl + u", Undo), ("F5", Redo), ("Shift+Ctrl+1", Open)])"#).unwrap();
+
+    let session = Session::load(&path);
+    let shortcuts = &session.shortcuts;
+    assert_eq!(shortcuts.action(KeyCode::U, modifiers(true, false)), Some(Action::Undo));
+    assert_eq!(shortcuts.action(KeyCode::F5, modifiers(false, false)), Some(Action::Redo));
+    assert_eq!(shortcuts.action(KeyCode::Key1, modifiers(true, true)), Some(Action::Open));
+    assert_eq!(shortcuts.action(KeyCode::Z, modifiers(true, false)), None, "The defaults are replaced");
+    let listed: Vec<_> = shortcuts.bindings().map(|(combo, _)| combo.to_string()).collect();
+    assert_eq!(listed, ["Ctrl+U", "F5", "Ctrl+Shift+1"]);
+
+    session.save(&path).unwrap();
+    assert_eq!(Session::load(&path), session);
+    fs::remove_dir_all(directory).unwrap();
+
+    assert!("Super+Z".parse::<KeyCombo>().is_err());
+    assert!("Ctrl+".parse::<KeyCombo>().is_err());
+}
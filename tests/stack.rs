@@ -0,0 +1,103 @@
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use klex::{
+    error::{KlexError, Result},
+    layer::{
+        primitive::{Stack, StackReduction},
+        InputCount, Layer, LayerOutput,
+    },
+    layer_graph::InteractiveLayerGraph,
+    parameter::ParamValue,
+    recipe::Recipe,
+    registry::LayerRegistry,
+};
+
+/// Produces a copy of an image, standing in for a layer that loads an exposure
+struct Exposure(GrayImage);
+
+impl Layer for Exposure {
+    fn kind(&self) -> String {
+        "Exposure".to_string()
+    }
+
+    fn compute(&self, _input: &[&LayerOutput], output: &mut LayerOutput) -> Result<()> {
+        *output = Some(Box::new(self.0.clone()));
+        Ok(())
+    }
+}
+
+fn row(values: &[u8]) -> GrayImage {
+    GrayImage::from_raw(values.len() as u32, 1, values.to_vec()).unwrap()
+}
+
+#[test]
+fn exposures_are_combined_pixel_by_pixel() {
+    let exposures = [row(&[10, 200, 0, 7]), row(&[20, 0, 0, 9]), row(&[90, 100, 255, 8]), row(&[30, 50, 1, 8])];
+    let references: Vec<_> = exposures.iter().collect();
+    let stack = |reduction, count: usize| Stack::new(reduction).compute(&references[..count]).unwrap().into_raw();
+    assert_eq!(stack(StackReduction::Mean, 4), [38, 88, 64, 8]);
+    assert_eq!(stack(StackReduction::Median, 3), [20, 100, 0, 8]);
+    assert_eq!(stack(StackReduction::Median, 4), [25, 75, 1, 8], "Between the two values in the middle");
+    assert_eq!(stack(StackReduction::Min, 4), [10, 0, 0, 7]);
+    assert_eq!(stack(StackReduction::Max, 4), [90, 200, 255, 9]);
+    assert_eq!(stack(StackReduction::Median, 1), exposures[0].as_raw().clone());
+
+    // Color channels are combined separately
+    let colors = [Rgba([0, 100, 200, 255]), Rgba([50, 30, 0, 255]), Rgba([10, 20, 30, 0])];
+    let images: Vec<_> = colors.iter().map(|&color| RgbaImage::from_pixel(3, 2, color)).collect();
+    let median = Stack::new(StackReduction::Median).compute(&images.iter().collect::<Vec<_>>()).unwrap();
+    assert!(median.pixels().all(|&pixel| pixel == Rgba([10, 30, 30, 255])));
+}
+
+#[test]
+fn inputs_have_to_match_the_first_one() {
+    let layer = LayerRegistry::with_builtins().create_default("Stack").unwrap();
+    assert_eq!(layer.expected_inputs(), InputCount::AtLeast(1));
+    assert_eq!(LayerRegistry::with_builtins().info("Stack").unwrap().inputs, InputCount::AtLeast(1));
+    let gray = |width| -> LayerOutput { Some(Box::new(GrayImage::from_pixel(width, 2, Luma([9])))) };
+    let (small, large) = (gray(3), gray(4));
+    let color: LayerOutput = Some(Box::new(RgbaImage::new(3, 2)));
+    let mut output = None;
+    layer.compute(&[&small, &small, &small], &mut output).unwrap();
+    assert_eq!(output.unwrap().downcast_ref::<GrayImage>().unwrap(), small.as_ref().unwrap().downcast_ref().unwrap());
+
+    let mut output = None;
+    let error = layer.compute(&[&small, &small, &large, &color], &mut output).unwrap_err();
+    assert!(matches!(error.root(), KlexError::InputMismatch { index: 2, .. }), "{}", error);
+    assert!(error.to_string().contains("4x2") && error.to_string().contains("3x2"), "{}", error);
+    let error = layer.compute(&[&small, &small, &color, &large], &mut output).unwrap_err();
+    assert!(matches!(error.root(), KlexError::InputMismatch { index: 2, .. }), "{}", error);
+    assert!(error.to_string().contains("RgbaImage"), "{}", error);
+    let error = layer.compute(&[&color, &color, &small], &mut output).unwrap_err();
+    assert!(matches!(error.root(), KlexError::InputMismatch { index: 2, .. }), "{}", error);
+    let mut layer = layer;
+    layer.set_parameter("reduction", ParamValue::Choice("Max".to_string())).unwrap();
+    assert!(layer.set_parameter("reduction", ParamValue::Choice("Mode".to_string())).is_err());
+}
+
+#[test]
+fn graphs_feed_as_many_inputs_as_are_connected() {
+    let mut graph = InteractiveLayerGraph::new();
+    let sources: Vec<_> = [10, 20, 60]
+        .into_iter()
+        .map(|value| graph.add_layer(Box::new(Exposure(row(&[value, 255 - value]))), vec![]))
+        .collect();
+    let stack = graph.add_layer(Box::new(Stack::new(StackReduction::Mean)), sources[..2].to_vec());
+    graph.compute_layer(stack).unwrap();
+    assert_eq!(graph.graph().output(stack, 0).unwrap().downcast_ref::<GrayImage>().unwrap().as_raw(), &[15, 240]);
+
+    // Ports are counted from 0 without gaps
+    graph.connect(sources[2], stack, 3).unwrap();
+    let error = graph.compute_layer(stack).unwrap_err();
+    assert!(matches!(error.root(), KlexError::MissingInput { port: 2, .. }), "{}", error);
+    let issues = Recipe::from_graph(&graph).validate(&LayerRegistry::with_builtins());
+    assert!(issues.iter().any(|issue| issue.message == "Input 2 is not connected"), "{:#?}", issues);
+    graph.disconnect(stack, 3).unwrap();
+    graph.connect(sources[2], stack, 2).unwrap();
+    graph.compute_layer(stack).unwrap();
+    assert_eq!(graph.graph().output(stack, 0).unwrap().downcast_ref::<GrayImage>().unwrap().as_raw(), &[30, 225]);
+
+    let lonely = graph.add_layer(Box::new(Stack::new(StackReduction::Mean)), vec![]);
+    let error = graph.compute_layer(lonely).unwrap_err();
+    assert!(matches!(error.root(), KlexError::MissingInput { port: 0, .. }), "{}", error);
+}
@@ -0,0 +1,170 @@
+#![cfg(feature = "svg")]
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use klex::{
+    entity::{Contour, Geometry, Line, Point},
+    layer::{primitive::ExportVector, Layer, LayerCategory, LayerOutput},
+    parameter::{ParamMap, ParamValue},
+    registry::LayerRegistry,
+    svg::Svg,
+};
+
+/// The value of the attribute `name` in the first element of `element` that has it
+fn attribute<'a>(element: &'a str, name: &str) -> &'a str {
+    let start = element.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3;
+    &element[start..start + element[start..].find('"').unwrap()]
+}
+
+fn number(element: &str, name: &str) -> f32 {
+    attribute(element, name).parse().unwrap()
+}
+
+/// The shapes of an SVG written by `ExportVector`, together with the stroke width
+fn parse(svg: &str) -> (Geometry, f32) {
+    let mut geometry = Geometry::default();
+    let mut width = 0.0;
+    for element in svg.lines().map(str::trim) {
+        if element.starts_with("<g fill=\"none\"") {
+            width = number(element, "stroke-width");
+        } else if element.starts_with("<line") {
+            let (x1, y1) = (number(element, "x1"), number(element, "y1"));
+            geometry.lines.push(((x1, y1), (number(element, "x2"), number(element, "y2"))));
+        } else if element.starts_with("<polygon") {
+            let point = |point: &str| {
+                let (x, y) = point.split_once(',').unwrap();
+                (x.parse().unwrap(), y.parse().unwrap())
+            };
+            geometry.polygons.push(attribute(element, "points").split(' ').map(point).collect());
+        } else if element.starts_with("<circle") {
+            assert_eq!(number(element, "r"), width / 2.0);
+            geometry.points.push((number(element, "cx"), number(element, "cy")));
+        }
+    }
+    (geometry, width)
+}
+
+fn distance_to_segment((x, y): (f32, f32), (start, end): ((f32, f32), (f32, f32))) -> f32 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = dx * dx + dy * dy;
+    let t = match length > 0.0 {
+        true => (((x - start.0) * dx + (y - start.1) * dy) / length).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+    (x - start.0 - t * dx).hypot(y - start.1 - t * dy)
+}
+
+/// Which pixel centers of a `width` by `height` image the shapes cover, when drawn `stroke` pixels wide
+fn rasterize(geometry: &Geometry, stroke: f32, width: u32, height: u32) -> Vec<bool> {
+    let mut segments = geometry.lines.clone();
+    for polygon in &geometry.polygons {
+        segments.extend(polygon.iter().zip(polygon.iter().cycle().skip(1)).map(|(&start, &end)| (start, end)));
+    }
+    let covered = |center: (f32, f32)| {
+        segments.iter().any(|&segment| distance_to_segment(center, segment) <= stroke / 2.0)
+            || geometry.points.iter().any(|&point| distance_to_segment(center, (point, point)) <= stroke / 2.0)
+    };
+    let centers = (0..height).flat_map(|y| (0..width).map(move |x| (x as f32 + 0.5, y as f32 + 0.5)));
+    centers.map(covered).collect()
+}
+
+fn scaled(geometry: &Geometry, scale: f32) -> Geometry {
+    let point = |(x, y): (f32, f32)| (x * scale, y * scale);
+    Geometry {
+        lines: geometry.lines.iter().map(|&(start, end)| (point(start), point(end))).collect(),
+        points: geometry.points.iter().copied().map(point).collect(),
+        polygons: geometry.polygons.iter().map(|polygon| polygon.iter().copied().map(point).collect()).collect(),
+    }
+}
+
+fn base64_decode(text: &str) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let sextets: Vec<u32> = text
+        .bytes()
+        .filter(|&byte| byte != b'=')
+        .map(|byte| ALPHABET.iter().position(|&symbol| symbol == byte).unwrap() as u32)
+        .collect();
+    let mut bytes = Vec::new();
+    for chunk in sextets.chunks(4) {
+        let group = chunk.iter().enumerate().fold(0, |group, (i, &sextet)| group | sextet << (18 - 6 * i));
+        bytes.extend((0..chunk.len() - 1).map(|i| (group >> (16 - 8 * i)) as u8));
+    }
+    bytes
+}
+
+fn export(layer: &ExportVector, input: &[&LayerOutput]) -> Svg {
+    let mut output: LayerOutput = None;
+    layer.compute(input, &mut output).unwrap();
+    output.unwrap().downcast_ref::<Svg>().unwrap().clone()
+}
+
+#[test]
+fn shapes_are_drawn_over_the_image() {
+    let path = std::env::temp_dir().join(format!("klex-svg-{}.svg", std::process::id()));
+    let mut layer = ExportVector::new(path.clone());
+    layer.set_parameter("color", ParamValue::Color([0, 128, 255, 51])).unwrap();
+    layer.set_parameter("width", ParamValue::Float(1.5)).unwrap();
+    layer.set_parameter("scale", ParamValue::Float(2.0)).unwrap();
+
+    let image = RgbaImage::from_fn(8, 6, |x, y| Rgba([x as u8 * 30, y as u8 * 40, 7, 255]));
+    let lines = vec![Line { start: (1.0, 1.0), end: (7.0, 4.5) }, Line { start: (2.0, 5.0), end: (2.0, 5.0) }];
+    let input: LayerOutput = Some(Box::new(image.clone()));
+    let shapes: LayerOutput = Some(Box::new(lines.clone()));
+    let svg = export(&layer, &[&shapes, &input]);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), svg.text);
+    std::fs::remove_file(&path).unwrap();
+    assert!(svg.text.contains(r##"stroke="#0080ff" stroke-opacity="0.2""##), "{}", svg.text);
+    let root = svg.text.lines().next().unwrap();
+    assert_eq!((number(root, "width"), number(root, "height")), (16.0, 12.0));
+
+    let png = base64_decode(attribute(&svg.text, "href").strip_prefix("data:image/png;base64,").unwrap());
+    assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image);
+    let (parsed, width) = parse(&svg.text);
+    assert_eq!(width, 1.5);
+    let expected = scaled(&klex::entity::geometry(&lines).unwrap(), 2.0);
+    let raster = rasterize(&parsed, width, 16, 12);
+    assert_eq!(raster, rasterize(&expected, 1.5, 16, 12));
+    assert!(raster.iter().filter(|&&covered| covered).count() > 12);
+
+    // The image can come first as well, but there has to be geometry
+    assert_eq!(export(&layer, &[&input, &shapes]), svg);
+    let mut output: LayerOutput = None;
+    assert!(layer.compute(&[&input], &mut output).is_err());
+    assert!(layer.compute(&[&shapes, &shapes], &mut output).is_err());
+}
+
+#[test]
+fn geometry_is_exported_without_an_image() {
+    let path = std::env::temp_dir().join(format!("klex-svg-geometry-{}.svg", std::process::id()));
+    let layer = ExportVector::new(path.clone());
+    let contours = vec![Contour { points: vec![(1.0, 1.0), (6.0, 1.0), (6.0, 4.2), (1.0, 4.2)] }];
+    let shapes: LayerOutput = Some(Box::new(contours.clone()));
+    let svg = export(&layer, &[&shapes]);
+    assert!(!svg.text.contains("<image"));
+    let root = svg.text.lines().next().unwrap();
+    assert_eq!((number(root, "width"), number(root, "height")), (6.0, 5.0));
+    let (parsed, width) = parse(&svg.text);
+    let expected = klex::entity::geometry(&contours).unwrap();
+    assert_eq!(rasterize(&parsed, width, 6, 5), rasterize(&expected, 2.0, 6, 5));
+
+    let points = vec![Point { x: 2.0, y: 3.0 }, Point { x: 10.5, y: 0.0 }];
+    let shapes: LayerOutput = Some(Box::new(points.clone()));
+    let svg = export(&layer, &[&shapes]);
+    let (parsed, width) = parse(&svg.text);
+    assert_eq!(parsed.points, [(2.0, 3.0), (10.5, 0.0)]);
+    let expected = klex::entity::geometry(&points).unwrap();
+    assert_eq!(rasterize(&parsed, width, 11, 4), rasterize(&expected, 2.0, 11, 4));
+
+    // Images of other kinds are embedded as well
+    let gray: LayerOutput = Some(Box::new(GrayImage::from_pixel(3, 2, Luma([90]))));
+    let svg = export(&layer, &[&gray, &shapes]);
+    let png = base64_decode(attribute(&svg.text, "href").strip_prefix("data:image/png;base64,").unwrap());
+    assert_eq!(image::load_from_memory(&png).unwrap().to_luma8(), GrayImage::from_pixel(3, 2, Luma([90])));
+    std::fs::remove_file(&path).unwrap();
+
+    let registry = LayerRegistry::with_builtins();
+    let parameters = ParamMap::from([("path".to_string(), ParamValue::Path(path))]);
+    let layer = registry.create("ExportVector", &parameters).unwrap();
+    assert_eq!((layer.category(), layer.cache_key()), (LayerCategory::Output, None));
+    assert_eq!(layer.parameters()["width"], ParamValue::Float(2.0));
+}
@@ -0,0 +1,72 @@
+use std::cmp::Ordering;
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    entity::BinaryImage,
+    layer::primitive::{Convert, InputFile, Threshold},
+    layer_graph::InteractiveLayerGraph,
+    parameter::ParamValue,
+    recipe::Recipe,
+    testing::{
+        golden::{Divergence, GoldenTest, Manifest, Mode},
+        TestPattern,
+    },
+};
+
+#[test]
+fn patterns_are_reproducible_and_cover_the_range() {
+    let gradient = TestPattern::Gradient.gray(256, 2);
+    assert_eq!(gradient.get_pixel(0, 1).0, [0]);
+    assert_eq!(gradient.get_pixel(255, 0).0, [255]);
+    assert_eq!(TestPattern::Gradient.gray16(3, 1).as_raw(), &[0, 32768, 65535]);
+
+    let checkerboard = TestPattern::Checkerboard(2).gray(4, 4);
+    assert_eq!(checkerboard.as_raw()[..4], [0, 0, 255, 255]);
+    assert_eq!(checkerboard.get_pixel(2, 2).0, [0]);
+
+    let noise = TestPattern::Noise(7).rgba(64, 64);
+    assert_eq!(noise, TestPattern::Noise(7).rgba(64, 64));
+    assert_ne!(noise, TestPattern::Noise(8).rgba(64, 64));
+    let values: std::collections::HashSet<u8> = noise.pixels().map(|pixel| pixel.0[0]).collect();
+    assert!(values.len() > 200, "Noise spreads over the values, got {} different ones", values.len());
+    assert!(noise.pixels().all(|pixel| pixel.0[2] == 255 - pixel.0[0] && pixel.0[3] == 255));
+}
+
+#[test]
+fn golden_tests_tell_which_node_changed_and_by_how_much() {
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new("".into())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    let result = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers.rename(input, "input".to_string()).unwrap();
+    layers.rename(threshold, "binarize".to_string()).unwrap();
+    layers.rename(result, "result".to_string()).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+    let manifest = std::env::temp_dir().join(format!("klex-golden-{}.ron", std::process::id()));
+
+    let test = GoldenTest::new(&recipe).generated("input.path", TestPattern::Gradient, (256, 2)).unwrap();
+    test.check(&manifest, Mode::Regenerate).unwrap();
+    assert_eq!(Manifest::load(&manifest).unwrap().outputs["result"].dimensions, Some((256, 2)));
+    test.check(&manifest, Mode::Compare).unwrap();
+
+    let changed = test.set("binarize.threshold", ParamValue::Int(110));
+    let divergences = changed.compare(&manifest, Mode::Compare).unwrap();
+    assert_eq!(divergences.len(), 1);
+    assert_eq!(divergences[0].node(), "result");
+    match &divergences[0] {
+        Divergence::Changed { difference: Some(difference), .. } => {
+            // Gray values from 101 to 110 turned black, in both rows
+            assert!(difference.differing > 0 && difference.differing % 2 == 0, "{:?}", difference);
+            assert_eq!(difference.max, 255.0);
+            assert!((difference.mean - difference.differing as f64 * 255.0 / 512.0).abs() < 1e-9);
+        }
+        divergence => panic!("Expected a measured change, got {:?}", divergence),
+    }
+    let error = changed.check(&manifest, Mode::Compare).err().unwrap();
+    assert!(error.to_string().contains("Node \"result\" changed"), "{}", error);
+
+    std::fs::remove_dir_all(manifest.with_extension("pixels")).unwrap();
+    std::fs::remove_file(manifest).unwrap();
+}
@@ -0,0 +1,53 @@
+use std::cmp::Ordering;
+
+use image::GrayImage;
+
+use klex::{
+    entity::GrayImageF32,
+    layer::{
+        primitive::{Threshold, ThresholdMode},
+        Layer,
+    },
+};
+
+/// Which of the pixels 0, `threshold` and 255 the mode selects
+fn selected(threshold: u8, mode: ThresholdMode) -> Vec<bool> {
+    let image = GrayImage::from_raw(3, 1, vec![0, threshold, 255]).unwrap();
+    Threshold::new(threshold, mode).compute(&image).data().to_vec()
+}
+
+#[test]
+fn pixels_at_the_threshold_are_only_selected_by_inclusive_modes() {
+    let expected = [
+        (ThresholdMode::Greater, [false, false, true]),
+        (ThresholdMode::GreaterEqual, [false, true, true]),
+        (ThresholdMode::Less, [true, false, false]),
+        (ThresholdMode::LessEqual, [true, true, false]),
+        (ThresholdMode::Equal, [false, true, false]),
+        (ThresholdMode::NotEqual, [true, false, true]),
+    ];
+    for (mode, expected) in expected {
+        assert_eq!(selected(100, mode), expected, "{:?}", mode);
+    }
+    assert_eq!(ThresholdMode::from(Ordering::Greater), ThresholdMode::Greater);
+    assert_eq!(ThresholdMode::from(Ordering::Equal), ThresholdMode::Equal);
+}
+
+#[test]
+fn thresholds_at_the_ends_of_the_range_select_all_pixels_or_none() {
+    assert_eq!(selected(0, ThresholdMode::Less), [false; 3]);
+    assert_eq!(selected(0, ThresholdMode::GreaterEqual), [true; 3]);
+    assert_eq!(selected(255, ThresholdMode::Greater), [false; 3]);
+    assert_eq!(selected(255, ThresholdMode::LessEqual), [true; 3]);
+}
+
+#[test]
+fn float_images_are_thresholded_without_rounding() {
+    let image = GrayImageF32::from_raw(4, 1, vec![0.25, 0.5, 0.50001, f32::NAN]).unwrap();
+    let selected = |mode| Threshold::<GrayImageF32>::with_threshold(0.5, mode).compute(&image).data().to_vec();
+    assert_eq!(selected(ThresholdMode::Greater), [false, false, true, false]);
+    assert_eq!(selected(ThresholdMode::LessEqual), [true, true, false, false]);
+    assert_eq!(selected(ThresholdMode::NotEqual), [true, false, true, true], "NaN differs from every threshold");
+    let threshold = Threshold::<GrayImageF32>::with_threshold(0.5, ThresholdMode::Greater);
+    assert_eq!(threshold.kind(), "Threshold<GrayImageF32>");
+}
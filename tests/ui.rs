@@ -0,0 +1,307 @@
+#![cfg(feature = "ui")]
+
+use std::{path::Path, time::Duration};
+
+use image::{GrayAlphaImage, GrayImage, RgbaImage};
+use petgraph::graph::NodeIndex;
+
+use klex::{
+    backend::Data,
+    entity::BinaryImage,
+    layer::{
+        primitive::{Convert, PaintedMask},
+        LayerCategory,
+    },
+    layer_graph::{GraphSnapshot, InteractiveLayerGraph, NodeLayout},
+    layer_menu::{mismatch, LayerMenu, MenuMessage},
+    parameter::{ParamKind, ParamMap, ParamSpec, ParamValue},
+    registry::{LayerInfo, LayerRegistry},
+    parameter_panel::{PanelMessage, ParameterPanel},
+    ui::{self, ChannelOrder, Event, Failures, GraphMirror, ImageHandle, LayerSummary},
+};
+
+fn added(node: usize, kind: &str, inputs: &[usize]) -> Data {
+    Data::LayerAdded {
+        node: NodeIndex::new(node),
+        kind: kind.to_string(),
+        name: kind.to_string(),
+        inputs: inputs.iter().map(|&input| NodeIndex::new(input)).collect(),
+    }
+}
+
+#[test]
+fn comparisons_default_to_the_source_of_the_pipeline() {
+    let mut graph = GraphMirror::new();
+    graph.apply(&added(0, "InputFile", &[]));
+    graph.apply(&added(1, "Convert<RgbaImage, GrayImage>", &[0]));
+    graph.apply(&added(2, "Threshold", &[1]));
+    graph.apply(&added(3, "InputFile", &[]));
+    assert_eq!(graph.source(NodeIndex::new(2)), NodeIndex::new(0));
+    assert_eq!(graph.source(NodeIndex::new(3)), NodeIndex::new(3));
+
+    // Following the first input, even if the source is connected elsewhere
+    graph.apply(&Data::Connected {
+        from: NodeIndex::new(3),
+        output: 0,
+        to: NodeIndex::new(1),
+        port: 0,
+    });
+    assert_eq!(graph.source(NodeIndex::new(2)), NodeIndex::new(3));
+}
+
+#[test]
+fn graph_mirror_follows_the_backend() {
+    let mut graph = GraphMirror::new();
+    assert!(graph.is_empty());
+    assert_eq!(graph.selected(), None);
+
+    graph.apply(&Data::LayerSelected(NodeIndex::new(1)));
+    assert_eq!(graph.selected(), None, "Layer 1 doesn't exist yet");
+
+    graph.apply(&added(0, "InputFile", &[]));
+    graph.apply(&added(1, "Threshold", &[0]));
+    graph.apply(&added(2, "InputFile", &[]));
+    graph.apply(&Data::Connected {
+        from: NodeIndex::new(2),
+        output: 0,
+        to: NodeIndex::new(1),
+        port: 0,
+    });
+    graph.apply(&Data::Error("Ignored".to_string()));
+
+    assert_eq!(graph.selected(), Some(NodeIndex::new(1)));
+    let layers: Vec<_> = graph.layers().map(|(node, layer)| (node.index(), layer.kind.as_str())).collect();
+    assert_eq!(layers, vec![(0, "InputFile"), (1, "Threshold"), (2, "InputFile")]);
+    let expected = LayerSummary {
+        name: "Threshold".to_string(),
+        kind: "Threshold".to_string(),
+        inputs: vec![(NodeIndex::new(2), 0, 0)], // Replaced the previous connection
+        specs: Vec::new(),
+        parameters: ParamMap::new(),
+        layout: NodeLayout::default(),
+    };
+    assert_eq!(graph.layer(NodeIndex::new(1)), Some(&expected));
+
+    graph.apply(&Data::LayerMoved {
+        node: NodeIndex::new(1),
+        position: (10.0, 20.0),
+    });
+    assert_eq!(graph.layer(NodeIndex::new(1)).unwrap().layout.position, Some((10.0, 20.0)));
+    graph.apply(&Data::LayerRemoved(NodeIndex::new(2)));
+    assert_eq!(graph.layer(NodeIndex::new(1)).unwrap().inputs, vec![], "Connections of removed layers are gone");
+    graph.apply(&added(3, "Threshold", &[0]));
+    graph.apply(&Data::Disconnected {
+        to: NodeIndex::new(3),
+        port: 0,
+    });
+    assert_eq!(graph.layer(NodeIndex::new(3)).unwrap().inputs, vec![]);
+
+    // Snapshots of the whole graph catch up with anything that was missed
+    let mut layers = InteractiveLayerGraph::new();
+    let source = layers.add_layer(Box::new(PaintedMask::new(4, 4)), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![source]);
+    layers.move_layer(gray, (5.0, 6.0)).unwrap();
+    layers.select(gray).unwrap();
+    let snapshot = layers.snapshot();
+    graph.apply(&Data::Graph { sequence: 1, snapshot: snapshot.clone() });
+    let summary: Vec<_> = graph.layers().map(|(node, layer)| (node.index(), layer.kind.as_str())).collect();
+    assert_eq!(summary, [(0, "PaintedMask"), (1, "Convert<BinaryImage, GrayImage>")]);
+    let layer = graph.layer(gray).unwrap();
+    assert_eq!((layer.inputs.as_slice(), layer.layout.position), ([(source, 0, 0)].as_slice(), Some((5.0, 6.0))));
+    assert_eq!(graph.selected(), Some(gray));
+
+    // Followed by deltas, which are applied in order and only once
+    layers.disconnect(gray, 0).unwrap();
+    layers.select(source).unwrap();
+    let delta = GraphSnapshot::diff(&snapshot, &layers.snapshot());
+    for _ in 0..2 {
+        graph.apply(&Data::GraphDelta { sequence: 2, delta: delta.clone() });
+        assert_eq!(graph.layer(gray).unwrap().inputs, []);
+        assert_eq!(graph.selected(), Some(source));
+    }
+    assert!(!graph.request_snapshot());
+
+    // Missing one means waiting for the next snapshot
+    layers.remove_layer(gray).unwrap();
+    let delta = GraphSnapshot::diff(&layers.snapshot(), &layers.snapshot());
+    graph.apply(&Data::GraphDelta { sequence: 4, delta: delta.clone() });
+    graph.apply(&Data::GraphDelta { sequence: 5, delta });
+    assert!(graph.layer(gray).is_some());
+    assert!(graph.request_snapshot());
+    assert!(!graph.request_snapshot(), "Asked for once");
+    graph.apply(&Data::Graph { sequence: 6, snapshot: layers.snapshot() });
+    assert_eq!(graph.layers().count(), 1);
+    assert!(!graph.request_snapshot());
+}
+
+#[test]
+fn primary_input_follows_the_selected_pipeline() {
+    let path = |node: usize, path: &str| Data::Parameters {
+        node: NodeIndex::new(node),
+        specs: vec![ParamSpec::new("path", ParamKind::Path, None)],
+        values: ParamMap::from([("path".to_string(), ParamValue::Path(path.into()))]),
+    };
+    let mut graph = GraphMirror::new();
+    assert_eq!(graph.primary_input(), None);
+    graph.apply(&added(0, "PaintedMask", &[]));
+    graph.apply(&added(1, "InputFile", &[]));
+    graph.apply(&path(1, "images/Tulips.jpg"));
+    graph.apply(&added(2, "Convert<RgbaImage, GrayImage>", &[1]));
+    graph.apply(&added(3, "InputFile", &[]));
+    graph.apply(&path(3, "Roses.png"));
+    assert_eq!(graph.primary_input(), Some(Path::new("images/Tulips.jpg")), "Nothing is selected");
+
+    graph.apply(&Data::LayerSelected(NodeIndex::new(3)));
+    assert_eq!(graph.primary_input(), Some(Path::new("Roses.png")));
+    graph.apply(&Data::LayerSelected(NodeIndex::new(0)));
+    assert_eq!(graph.primary_input(), Some(Path::new("images/Tulips.jpg")), "The mask isn't read from a file");
+}
+
+fn threshold_parameters(node: usize, value: i64) -> Data {
+    Data::Parameters {
+        node: NodeIndex::new(node),
+        specs: vec![ParamSpec::new("value", ParamKind::Int { min: 0, max: 255 }, None)],
+        values: ParamMap::from([("value".to_string(), ParamValue::Int(value))]),
+    }
+}
+
+#[test]
+fn parameter_panel_edits_the_selected_layer() {
+    let mut graph = GraphMirror::new();
+    graph.apply(&added(0, "Threshold", &[]));
+    graph.apply(&added(1, "Threshold", &[0]));
+    graph.apply(&threshold_parameters(0, 128));
+    graph.apply(&threshold_parameters(1, 64));
+    let mut panel = ParameterPanel::new();
+    let (first, second) = (NodeIndex::new(0), NodeIndex::new(1));
+    panel.sync(Some(first), graph.layer(first));
+
+    let edit = |text: &str| PanelMessage::Edit {
+        name: "value".to_string(),
+        text: text.to_string(),
+    };
+    let submit = || PanelMessage::Submit {
+        name: "value".to_string(),
+    };
+    assert_eq!(panel.update(edit("abc")), None);
+    assert_eq!(panel.update(submit()), None, "Invalid text isn't sent");
+    assert_eq!(panel.update(edit("300")), None);
+    let clamped = Event::SetParameter {
+        node: first,
+        name: "value".to_string(),
+        value: ParamValue::Int(255),
+    };
+    assert_eq!(panel.update(submit()), Some(clamped));
+
+    let slid = PanelMessage::Set {
+        name: "value".to_string(),
+        value: ParamValue::Int(10),
+    };
+    panel.sync(Some(second), graph.layer(second));
+    let expected = Event::SetParameter {
+        node: second,
+        name: "value".to_string(),
+        value: ParamValue::Int(10),
+    };
+    assert_eq!(panel.update(slid.clone()), Some(expected));
+    let unknown = PanelMessage::Submit {
+        name: "missing".to_string(),
+    };
+    assert_eq!(panel.update(unknown), None);
+
+    panel.sync(None, None);
+    assert_eq!(panel.update(slid), None, "Nothing is selected");
+}
+
+#[test]
+fn layer_menu_only_offers_matching_layers() {
+    let registry = LayerRegistry::with_builtins();
+    let catalog = registry.kinds().map(|kind| (kind.to_string(), registry.info(kind).unwrap().clone()));
+    let mut menu = LayerMenu::new(catalog);
+    let threshold = registry.info("Threshold").unwrap();
+    assert_eq!(mismatch(threshold, Some("GrayImage")), None);
+    assert_eq!(mismatch(threshold, None), None, "Nothing to connect to");
+    assert!(mismatch(threshold, Some("RgbaImage")).unwrap().contains("GrayImage"));
+    let input = LayerInfo {
+        category: LayerCategory::Input,
+        input_types: vec!["RgbaImage"],
+        ..LayerInfo::default()
+    };
+    assert_eq!(mismatch(&input, Some("GrayImage")), None, "Inputs aren't connected");
+
+    let input_file = Some((NodeIndex::new(0), "InputFile"));
+    menu.update(MenuMessage::Toggle, input_file);
+    assert!(menu.is_open());
+    assert_eq!(menu.update(MenuMessage::Choose("Threshold".to_string()), input_file), None);
+    assert!(menu.is_open(), "Choosing a disabled layer keeps the menu open");
+
+    // The first match that fits is chosen, even though conversions from BinaryImage come first. Conversions through
+    // other elements are offered as well, so it goes to BinaryImage by way of GrayImage.
+    menu.update(MenuMessage::Search("CONVERT".to_string()), input_file);
+    let expected = Event::InsertLayer {
+        kind: "Convert<RgbaImage, BinaryImage>".to_string(),
+        parameters: ParamMap::new(),
+        connect_after: Some(NodeIndex::new(0)),
+    };
+    assert_eq!(menu.update(MenuMessage::ChooseFirst, input_file), Some(expected));
+    assert!(!menu.is_open());
+}
+
+#[test]
+fn failures_are_reported_until_dismissed_and_kept_until_fixed() {
+    let failed = |node: usize, error: &str| Data::ComputeFailed {
+        node: NodeIndex::new(node),
+        error: error.to_string(),
+    };
+    let mut failures = Failures::new();
+    failures.apply(&failed(1, "Layer has no input on port 0"));
+    failures.apply(&failed(2, "Failed to read input: No such file or directory"));
+    failures.apply(&failed(1, "Image is empty"));
+    let reported: Vec<_> = failures.reported().collect();
+    assert_eq!(
+        reported,
+        [
+            (NodeIndex::new(1), "Image is empty"),
+            (NodeIndex::new(2), "Failed to read input: No such file or directory")
+        ]
+    );
+
+    // Dismissed failures are still highlighted, until the layer computes
+    failures.dismiss();
+    assert_eq!(failures.reported().count(), 0);
+    assert!(failures.is_failed(NodeIndex::new(1)));
+    failures.apply(&Data::ComputeFinished {
+        node: NodeIndex::new(1),
+        duration: Duration::from_millis(5),
+    });
+    assert_eq!(failures.failed(), [NodeIndex::new(2)].into_iter().collect());
+
+    // Failing again is reported again
+    failures.apply(&failed(2, "Image is empty"));
+    assert_eq!(failures.reported().count(), 1);
+
+    failures.apply(&Data::LayerRemoved(NodeIndex::new(2)));
+    assert!(!failures.is_failed(NodeIndex::new(2)));
+    assert_eq!(failures, Failures::new());
+}
+
+#[test]
+fn pixel_buffers_have_the_requested_channel_order() {
+    let red = RgbaImage::from_raw(1, 1, vec![255, 0, 0, 255]).unwrap();
+    assert_eq!(red.buffer(ChannelOrder::Rgba), vec![255, 0, 0, 255]);
+    assert_eq!(red.buffer(ChannelOrder::Bgra), vec![0, 0, 255, 255]);
+    let gray = GrayAlphaImage::from_raw(1, 1, vec![7, 9]).unwrap();
+    assert_eq!(gray.buffer(ChannelOrder::Bgra), vec![7, 7, 7, 9]);
+}
+
+#[test]
+fn image_elements_are_displayed_as_rgba() {
+    let rgba = |pixels: Vec<u8>| RgbaImage::from_raw(2, 1, pixels).unwrap().handle().id();
+    let gray = GrayImage::from_raw(2, 1, vec![10, 200]).unwrap();
+    assert_eq!(gray.handle().id(), rgba(vec![10, 10, 10, 255, 200, 200, 200, 255]));
+    let gray_alpha = GrayAlphaImage::from_raw(2, 1, vec![10, 0, 200, 128]).unwrap();
+    assert_eq!(gray_alpha.handle().id(), rgba(vec![10, 10, 10, 0, 200, 200, 200, 128]));
+    let binary = BinaryImage::new(2, 1, vec![true, false]);
+    assert_eq!(ui::image_handle(&binary).map(|handle| handle.id()), Some(rgba(vec![255, 255, 255, 255, 0, 0, 0, 255])));
+    assert!(ui::image_handle(&vec![1u8]).is_none());
+}
@@ -0,0 +1,122 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use klex::util::{BufferPool, Disconnected, PoolStats, ThreadChannel};
+
+#[test]
+fn messages_arrive_in_order() {
+    let (a, b) = ThreadChannel::<u32, &str>::new_pair();
+    for message in 0..5 {
+        a.send(message).unwrap();
+    }
+    b.send("first").unwrap();
+    b.send("second").unwrap();
+
+    assert_eq!(b.receive_blocking(), Ok(0));
+    assert_eq!(b.receive_timeout(Duration::from_secs(1)), Ok(Some(1)));
+    assert_eq!(b.receive(), vec![2, 3, 4]);
+    assert_eq!(a.receive(), vec!["first", "second"]);
+    assert!(a.receive().is_empty());
+}
+
+#[test]
+fn receiving_times_out_without_messages() {
+    let (a, _b) = ThreadChannel::<(), ()>::new_pair();
+    let start = Instant::now();
+    assert_eq!(a.receive_timeout(Duration::from_millis(20)), Ok(None));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(!a.is_disconnected());
+}
+
+#[test]
+fn blocking_receive_wakes_up_for_messages_from_another_thread() {
+    let (a, b) = ThreadChannel::<u32, ()>::new_pair();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        a.send(7).unwrap();
+        a
+    });
+    assert_eq!(b.receive_blocking(), Ok(7));
+    drop(sender.join().unwrap());
+    assert_eq!(b.receive_blocking(), Err(Disconnected));
+}
+
+#[test]
+fn dropping_either_end_disconnects_the_other() {
+    let (a, b) = ThreadChannel::<u32, u32>::new_pair();
+    a.send(1).unwrap();
+    drop(a);
+    assert!(!b.is_disconnected(), "Nothing showed the disconnection yet");
+    assert_eq!(b.receive(), vec![1]); // Messages sent before dropping still arrive
+    assert!(b.is_disconnected());
+    assert_eq!(b.send(2), Err(Disconnected));
+    assert_eq!(b.receive_timeout(Duration::from_secs(1)), Err(Disconnected));
+
+    let (a, b) = ThreadChannel::<u32, u32>::new_pair();
+    drop(b);
+    assert_eq!(a.send(1), Err(Disconnected));
+    assert!(a.is_disconnected());
+    assert_eq!(a.receive_blocking(), Err(Disconnected));
+}
+
+/// Coalesces messages by their tens, e.g. 12 replaces an unreceived 15. Negative messages are never coalesced.
+fn tens(message: &i32) -> Option<usize> {
+    usize::try_from(message / 10).ok().filter(|_| *message >= 0)
+}
+
+#[test]
+fn coalescable_messages_replace_unreceived_ones() {
+    let (mut a, b) = ThreadChannel::<i32, ()>::new_pair();
+    a.set_coalescing(10, tens);
+    for message in [10, -1, 20, 11, -2, 12] {
+        a.send(message).unwrap();
+    }
+    // The replacement takes the place of the message it replaced
+    assert_eq!(b.receive(), vec![12, -1, 20, -2]);
+    assert_eq!(b.dropped(), 2);
+
+    a.send(13).unwrap();
+    assert_eq!(b.receive(), vec![13]);
+    assert_eq!(b.dropped(), 2);
+}
+
+#[test]
+fn full_channels_replace_the_oldest_coalescable_message() {
+    let (mut a, b) = ThreadChannel::<i32, ()>::new_pair();
+    a.set_coalescing(2, tens);
+    for message in [10, 20, -1, 30, -2, -3, 40] {
+        a.send(message).unwrap();
+    }
+    assert_eq!(b.receive(), vec![30, 40, -1, -2, -3]);
+    assert_eq!(b.dropped(), 2);
+}
+
+#[test]
+fn returned_buffers_are_reused_up_to_a_limit() {
+    let pool = BufferPool::new(1000);
+    {
+        let mut buffer = pool.take::<u16>(100);
+        assert!(buffer.iter().all(|&value| value == 0));
+        buffer[3] = 7;
+    }
+    assert_eq!(pool.stats(), PoolStats { hits: 0, misses: 1, retained_bytes: 200 });
+
+    let buffer = pool.take::<u16>(100);
+    assert!(buffer.iter().all(|&value| value == 0), "Reused buffers are cleared");
+    assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 1, retained_bytes: 0 });
+    let other_type = pool.take::<i16>(100);
+    let other_length = pool.take::<u16>(99);
+    assert_eq!(pool.stats().misses, 3, "Buffers are only reused for the same type and length");
+
+    drop((buffer, other_type, other_length));
+    assert_eq!(pool.stats().retained_bytes, 598);
+    drop(pool.take::<u8>(500));
+    assert_eq!(pool.stats().retained_bytes, 598, "Buffers beyond the limit are dropped");
+    pool.clear();
+    assert_eq!(pool.stats().retained_bytes, 0);
+
+    let unpooled = BufferPool::take_from::<u8>(None, 10);
+    assert_eq!(unpooled.len(), 10);
+}
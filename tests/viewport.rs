@@ -0,0 +1,161 @@
+#![cfg(feature = "ui")]
+
+use iced_native::{Point, Rectangle, Size, Vector};
+
+use klex::{entity::Rect, viewport::ViewState};
+
+const IMAGE: Size = Size::new(400.0, 200.0);
+
+fn viewport() -> Rectangle {
+    Rectangle::new(Point::new(10.0, 20.0), Size::new(200.0, 200.0))
+}
+
+#[test]
+fn fitted_images_are_centered_in_the_viewport() {
+    let view = ViewState::fit();
+    assert_eq!(view.zoom(IMAGE, viewport().size()), 0.5);
+    let expected = Rectangle::new(Point::new(10.0, 70.0), Size::new(200.0, 100.0));
+    assert_eq!(view.image_bounds(IMAGE, viewport()), expected);
+
+    let actual_size = view.actual_size().image_bounds(IMAGE, viewport());
+    assert_eq!(actual_size, Rectangle::new(Point::new(-90.0, 20.0), IMAGE));
+}
+
+#[test]
+fn zooming_keeps_the_point_under_the_cursor() {
+    let cursor = Point::new(60.0, 100.0);
+    let before = ViewState::fit().image_bounds(IMAGE, viewport());
+    let view = ViewState::fit().zoomed(2.0, cursor, IMAGE, viewport());
+    let after = view.image_bounds(IMAGE, viewport());
+
+    assert!((view.zoom(IMAGE, viewport().size()) - 0.5 * 1.2 * 1.2).abs() < 1e-6);
+    let fraction = |bounds: Rectangle| ((cursor.x - bounds.x) / bounds.width, (cursor.y - bounds.y) / bounds.height);
+    let (before, after) = (fraction(before), fraction(after));
+    assert!((before.0 - after.0).abs() < 1e-6 && (before.1 - after.1).abs() < 1e-6);
+}
+
+#[test]
+fn panning_follows_the_cursor_but_keeps_the_image_in_view() {
+    let view = ViewState::fit().actual_size().grabbed(Point::new(100.0, 100.0));
+    assert!(view.is_grabbed());
+    let moved = view.dragged(Point::new(150.0, 100.0), IMAGE, viewport());
+    let bounds = moved.image_bounds(IMAGE, viewport());
+    assert_eq!(bounds.x, -40.0);
+
+    // Dragging far away stops once the edge of the image reaches the middle of the viewport
+    let moved = view.dragged(Point::new(10_000.0, -10_000.0), IMAGE, viewport());
+    let bounds = moved.image_bounds(IMAGE, viewport());
+    assert_eq!((bounds.x, bounds.y + bounds.height), (viewport().center_x(), viewport().center_y()));
+
+    let released = moved.released();
+    assert!(!released.is_grabbed());
+    assert_eq!(released.dragged(Point::ORIGIN, IMAGE, viewport()), released);
+}
+
+#[test]
+fn the_divider_of_a_comparison_can_be_dragged_across_the_viewport() {
+    let view = ViewState::fit();
+    assert_eq!(view.divider(viewport()), 110.0);
+    assert!(view.is_on_divider(Point::new(114.0, 50.0), viewport()));
+    assert!(!view.is_on_divider(Point::new(120.0, 50.0), viewport()));
+
+    let grabbed = view.grabbed_divider();
+    assert!(grabbed.is_grabbed());
+    let moved = grabbed.dragged(Point::new(60.0, 0.0), IMAGE, viewport());
+    assert_eq!(moved.divider(viewport()), 60.0);
+    assert_eq!(moved.image_bounds(IMAGE, viewport()), view.image_bounds(IMAGE, viewport()), "The image stays put");
+    let moved = grabbed.dragged(Point::new(-500.0, 0.0), IMAGE, viewport());
+    assert_eq!(moved.divider(viewport()), 10.0);
+
+    // Zooming and fitting leave the comparison alone
+    let released = moved.released().swapped();
+    assert!(!released.is_grabbed() && released.is_swapped());
+    let refitted = released.zoomed(1.0, Point::new(50.0, 50.0), IMAGE, viewport()).fitted();
+    assert_eq!((refitted.divider(viewport()), refitted.is_swapped()), (10.0, true));
+}
+
+#[test]
+fn the_image_position_under_the_cursor_is_known_while_it_is_over_the_image() {
+    let view = ViewState::fit();
+    // The fitted image covers y from 70 to 170
+    assert_eq!(view.image_position(Point::new(60.0, 120.0), IMAGE, viewport()), Some(Point::new(0.25, 0.5)));
+    assert_eq!(view.image_position(Point::new(60.0, 60.0), IMAGE, viewport()), None);
+
+    // Zoomed in so far that the image covers more than the viewport
+    let zoomed = view.actual_size();
+    assert_eq!(zoomed.image_position(Point::new(110.0, 120.0), IMAGE, viewport()), Some(Point::new(0.5, 0.5)));
+    assert_eq!(zoomed.image_position(Point::new(250.0, 120.0), IMAGE, viewport()), None, "Outside of the viewport");
+}
+
+#[test]
+fn regions_are_drawn_in_full_resolution_pixels_and_kept_inside_the_image() {
+    let full_size = (800, 400); // The image shown is a preview at half the resolution
+    let view = ViewState::fit();
+    // The fitted image covers x from 10 to 210 and y from 70 to 170
+    assert_eq!(view.to_image(Point::new(60.0, 95.0), IMAGE, viewport(), full_size), Point::new(200.0, 100.0));
+    let zoomed = view.actual_size();
+    assert_eq!(zoomed.to_image(Point::new(110.0, 120.0), IMAGE, viewport(), full_size), Point::new(400.0, 200.0));
+    let point = Point::new(123.0, 45.0);
+    let screen = zoomed.to_screen(point, IMAGE, viewport(), full_size);
+    assert_eq!(zoomed.to_image(screen, IMAGE, viewport(), full_size), point);
+
+    // Drawing a new region, which is clamped to the image
+    let drawing = view.grabbed_region(Point::new(60.0, 95.0), None, IMAGE, viewport(), full_size);
+    assert!(drawing.is_grabbed());
+    let region = drawing.dragged_region(Point::new(0.0, 500.0), IMAGE, viewport(), full_size).unwrap();
+    assert_eq!(region, Rect { x: 0, y: 100, width: 200, height: 300 });
+
+    // Grabbing a corner moves it while the opposite one stays put
+    let corner = view.to_screen(Point::new(200.0, 400.0), IMAGE, viewport(), full_size);
+    let resizing = view.grabbed_region(corner + Vector::new(3.0, -3.0), Some(region), IMAGE, viewport(), full_size);
+    let resized = resizing.dragged_region(Point::new(160.0, 120.0), IMAGE, viewport(), full_size).unwrap();
+    assert_eq!(resized, Rect { x: 0, y: 100, width: 600, height: 100 });
+
+    // Grabbing the inside moves the whole region, but not past the edge of the image
+    let moving = view.grabbed_region(Point::new(30.0, 140.0), Some(region), IMAGE, viewport(), full_size);
+    let moved = moving.dragged_region(Point::new(50.0, 100.0), IMAGE, viewport(), full_size).unwrap();
+    assert_eq!(moved, Rect { x: 80, y: 0, ..region });
+    let moved = moving.dragged_region(Point::new(1000.0, 1000.0), IMAGE, viewport(), full_size).unwrap();
+    assert_eq!(moved, Rect { x: 600, y: 100, ..region });
+    assert!(!moving.released().is_grabbed());
+}
+
+#[test]
+fn pointers_only_hit_pixels_inside_the_image() {
+    let full_size = (800, 400);
+    let view = ViewState::fit();
+    // The fitted image covers x from 10 to 210 and y from 70 to 170
+    let pixel = |cursor| view.pixel_at(cursor, IMAGE, viewport(), full_size);
+    assert_eq!(pixel(Point::new(60.0, 95.0)), Some(Point::new(200.0, 100.0)));
+    assert_eq!(pixel(Point::new(10.0, 70.0)), Some(Point::new(0.0, 0.0)));
+    assert_eq!(pixel(Point::new(60.0, 60.0)), None, "Above the image, but in the viewport");
+    assert_eq!(pixel(Point::new(60.0, 170.0)), None, "Just past the last row");
+    assert_eq!(pixel(Point::new(250.0, 95.0)), None, "Outside of the viewport");
+}
+
+#[test]
+fn clicks_hit_the_pixel_under_the_cursor_through_zoom_and_pan() {
+    let pixel = |view: ViewState, cursor: Point| {
+        let position = view.image_position(cursor, IMAGE, viewport()).unwrap();
+        ((position.x * IMAGE.width) as u32, (position.y * IMAGE.height) as u32)
+    };
+    let zoomed = ViewState::fit()
+        .zoomed(10.0, Point::new(60.0, 100.0), IMAGE, viewport())
+        .grabbed(Point::new(100.0, 100.0));
+    let view = zoomed.dragged(Point::new(137.0, 81.0), IMAGE, viewport()).released();
+    let bounds = view.image_bounds(IMAGE, viewport());
+    let scale = bounds.width / IMAGE.width;
+    assert!(scale > 2.0, "Pixels cover several screen pixels at {}", scale);
+    let mut hits = 0;
+    for (x, y) in [(95, 40), (96, 41), (120, 60)] {
+        // Anywhere within the pixel on screen, not just its center
+        for offset in [0.05, 0.5, 0.95] {
+            let cursor = Point::new(bounds.x + (x as f32 + offset) * scale, bounds.y + (y as f32 + offset) * scale);
+            if viewport().contains(cursor) {
+                assert_eq!(pixel(view, cursor), (x, y), "At {:?}", cursor);
+                hits += 1;
+            }
+        }
+    }
+    assert!(hits >= 6, "Only {} clicks were in the viewport", hits);
+}
@@ -0,0 +1,97 @@
+use std::{
+    cmp::Ordering,
+    fs,
+    ops::ControlFlow,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{GrayImage, RgbaImage};
+
+use klex::{
+    entity::BinaryImage,
+    error::Result,
+    layer::primitive::{Convert, InputFile, Threshold},
+    layer_graph::InteractiveLayerGraph,
+    recipe::{self, Bindings, Recipe},
+    registry::LayerRegistry,
+    watch::{FileWatcher, PollingWatcher},
+};
+
+/// Rewrites the watched image with the next pixel value instead of waiting for someone to edit it
+struct ScriptedWatcher {
+    values: Vec<u8>,
+}
+
+impl FileWatcher for ScriptedWatcher {
+    fn wait_for_changes(&mut self, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let value = self.values.remove(0);
+        GrayImage::from_raw(1, 1, vec![value]).unwrap().save(&paths[0])?;
+        Ok(paths.to_vec())
+    }
+}
+
+fn temp_image(name: &str, value: u8) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("klex-{}-{}.png", name, std::process::id()));
+    GrayImage::from_raw(1, 1, vec![value]).unwrap().save(&path).unwrap();
+    path
+}
+
+#[test]
+fn outputs_are_recomputed_when_inputs_change() {
+    let path = temp_image("watch", 0);
+    let mut layers = InteractiveLayerGraph::new();
+    let input = layers.add_layer(Box::new(InputFile::<RgbaImage>::new(path.clone())), vec![]);
+    let gray = layers.add_layer(Box::new(Convert::<RgbaImage, GrayImage>::new()), vec![input]);
+    let threshold = layers.add_layer(Box::new(Threshold::new(100, Ordering::Greater)), vec![gray]);
+    let mask = layers.add_layer(Box::new(Convert::<BinaryImage, GrayImage>::new()), vec![threshold]);
+    layers.rename(mask, "mask".to_string()).unwrap();
+    let recipe = Recipe::from_graph(&layers);
+
+    let mut watcher = ScriptedWatcher { values: vec![200, 50] };
+    let mut masks = Vec::new();
+    recipe::watch_with(
+        &recipe,
+        &Bindings::new(),
+        &LayerRegistry::with_builtins(),
+        &mut watcher,
+        |outputs| {
+            let mask = outputs.unwrap()["mask"].downcast_ref::<GrayImage>().unwrap().as_raw()[0];
+            masks.push(mask);
+            if masks.len() < 3 {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        },
+    )
+    .unwrap();
+
+    assert_eq!(masks, vec![0, 255, 0]);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn polling_waits_for_writes_to_settle() {
+    let path = temp_image("poll", 0);
+    let paths = vec![path.clone()];
+    let mut watcher = PollingWatcher::new(Duration::from_millis(10), Duration::from_millis(200));
+    watcher.track(&paths);
+
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            for value in [1, 2] {
+                thread::sleep(Duration::from_millis(50));
+                GrayImage::from_raw(2, 1, vec![value, value]).unwrap().save(&path).unwrap();
+            }
+        })
+    };
+
+    let start = Instant::now();
+    assert_eq!(watcher.wait_for_changes(&paths).unwrap(), paths);
+    assert!(start.elapsed() >= Duration::from_millis(300)); // Both writes, then the debounce duration
+    writer.join().unwrap();
+    fs::remove_file(path).unwrap();
+}